@@ -1 +1,3 @@
-export const SERVER_ENDPOINT = "http://localhost:8080";
+// Empty when the dashboard is served by the server itself, see `npm run export`.
+export const SERVER_ENDPOINT =
+  process.env.NEXT_PUBLIC_SERVER_ENDPOINT ?? "http://localhost:8080";
@@ -2,4 +2,22 @@
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
+
+    // The commit the binary was built from, for `GET /api/version`. Builds outside of a git
+    // checkout, like from a published crate, can set `NERVEMQ_GIT_SHA` instead.
+    println!("cargo:rerun-if-env-changed=NERVEMQ_GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let sha = std::env::var("NERVEMQ_GIT_SHA").ok().or_else(|| {
+        std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_owned())
+    });
+    if let Some(sha) = sha.filter(|sha| !sha.is_empty()) {
+        println!("cargo:rustc-env=NERVEMQ_GIT_SHA={sha}");
+    }
 }
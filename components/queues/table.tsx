@@ -35,6 +35,8 @@ export type QueueStatistics = Queue & {
   pending: number;
   delivered: number;
   failed: number;
+  in_flight: number;
+  dead_letter_queue: boolean;
 };
 
 export const columns: ColumnDef<QueueStatistics>[] = [
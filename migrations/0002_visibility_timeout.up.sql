@@ -0,0 +1,2 @@
+-- Time after which a delivered but not yet deleted message becomes receivable again.
+alter table messages add column visible_at integer;
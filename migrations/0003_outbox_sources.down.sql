@@ -0,0 +1,2 @@
+drop index outbox_sources_name_idx;
+drop table outbox_sources;
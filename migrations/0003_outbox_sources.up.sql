@@ -0,0 +1,17 @@
+create table if not exists outbox_sources (
+  id integer not null,
+  name text not null,
+  queue integer not null,
+  source_url text not null,
+  table_name text not null,
+  id_column text not null,
+  body_column text not null,
+  batch_size integer not null,
+  high_water_mark integer not null default 0,
+  created_by integer,
+
+  primary key (id),
+  foreign key (queue) references queues(id) on delete cascade,
+  foreign key (created_by) references users(id) on delete set null
+);
+create unique index if not exists outbox_sources_name_idx on outbox_sources(name);
@@ -0,0 +1,2 @@
+drop index user_invitations_token_hash_idx;
+drop table user_invitations;
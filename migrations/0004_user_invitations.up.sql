@@ -0,0 +1,12 @@
+-- Pending invitations for users created without a password. Invited users have an empty
+-- hashed_pass, which never verifies, until they accept.
+create table if not exists user_invitations (
+  id integer not null,
+  user integer not null,
+  token_hash text not null,
+  expires_at integer not null,
+
+  primary key (id),
+  foreign key (user) references users(id) on delete cascade
+);
+create unique index if not exists user_invitations_token_hash_idx on user_invitations(token_hash);
@@ -0,0 +1,3 @@
+drop index password_resets_user_idx;
+drop index password_resets_token_hash_idx;
+drop table password_resets;
@@ -0,0 +1,12 @@
+create table if not exists password_resets (
+  id integer not null,
+  user integer not null,
+  token_hash text not null,
+  created_at integer not null,
+  expires_at integer not null,
+
+  primary key (id),
+  foreign key (user) references users(id) on delete cascade
+);
+create unique index if not exists password_resets_token_hash_idx on password_resets(token_hash);
+create index if not exists password_resets_user_idx on password_resets(user, created_at);
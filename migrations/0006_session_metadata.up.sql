@@ -0,0 +1,6 @@
+alter table sessions add column email text;
+alter table sessions add column created_at integer not null default 0;
+alter table sessions add column last_seen integer not null default 0;
+alter table sessions add column user_agent text;
+alter table sessions add column ip text;
+create index if not exists sessions_email_idx on sessions(email);
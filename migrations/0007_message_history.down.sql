@@ -0,0 +1,4 @@
+drop index messages_history_queue_idx;
+drop table messages_history;
+alter table queue_configurations drop column history_retention;
+alter table queue_configurations drop column history;
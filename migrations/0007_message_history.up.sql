@@ -0,0 +1,19 @@
+-- Per-queue retention of deleted messages: 'off', 'metadata' (no body) or 'full'.
+alter table queue_configurations add column history text not null default 'off' check (history in ('off', 'metadata', 'full'));
+alter table queue_configurations add column history_retention integer not null default 604800;
+
+create table if not exists messages_history (
+  id integer not null,
+  message integer not null,
+  queue integer not null,
+  body blob,
+  sent_by integer,
+  tries integer not null,
+  delivered_at integer,
+  deleted_at integer not null default (unixepoch('now')),
+
+  primary key (id),
+  foreign key (queue) references queues(id) on delete cascade,
+  foreign key (sent_by) references users(id) on delete set null
+);
+create index if not exists messages_history_queue_idx on messages_history(queue, deleted_at);
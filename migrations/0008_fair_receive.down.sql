@@ -0,0 +1,2 @@
+drop table fair_receive_groups;
+alter table messages drop column group_id;
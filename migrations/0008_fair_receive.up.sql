@@ -0,0 +1,11 @@
+alter table messages add column group_id text;
+
+-- Rotation state for fair receives: the group served most recently has the largest last_served.
+create table if not exists fair_receive_groups (
+  queue integer not null,
+  group_key text not null,
+  last_served integer not null,
+
+  primary key (queue, group_key),
+  foreign key (queue) references queues(id) on delete cascade
+);
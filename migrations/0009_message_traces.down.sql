@@ -0,0 +1,5 @@
+drop index message_traces_at_idx;
+drop index message_traces_trace_idx;
+drop table message_traces;
+alter table messages_history drop column trace_id;
+alter table messages drop column trace_id;
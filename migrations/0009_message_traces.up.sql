@@ -0,0 +1,20 @@
+-- Durable trace ids, shared by every copy of a message as it moves between queues.
+alter table messages add column trace_id text;
+update messages set trace_id = lower(hex(randomblob(16)));
+alter table messages_history add column trace_id text;
+
+-- Lifecycle events of traced messages. Queues are recorded by name so that traces outlive them.
+create table if not exists message_traces (
+  id integer not null,
+  trace_id text not null,
+  message integer not null,
+  namespace text not null,
+  queue text not null,
+  event text not null check (event in ('sent', 'received', 'dead_lettered', 'deleted', 'purged', 'queue_deleted')),
+  receive_count integer,
+  at integer not null default (unixepoch('now')),
+
+  primary key (id)
+);
+create index if not exists message_traces_trace_idx on message_traces(trace_id, id);
+create index if not exists message_traces_at_idx on message_traces(at);
@@ -0,0 +1,5 @@
+alter table message_traces drop column consumer_group;
+drop index consumer_group_deliveries_message_idx;
+drop table consumer_group_deliveries;
+drop index consumer_groups_name_idx;
+drop table consumer_groups;
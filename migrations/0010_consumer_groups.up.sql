@@ -0,0 +1,29 @@
+-- Named consumer groups, each of which receives every message of the queue independently.
+create table if not exists consumer_groups (
+  id integer not null,
+  queue integer not null,
+  name text not null,
+  created_at integer not null default (unixepoch('now')),
+
+  primary key (id),
+  foreign key (queue) references queues(id) on delete cascade
+);
+create unique index if not exists consumer_groups_name_idx on consumer_groups(queue, name);
+
+-- Per-group delivery state. Messages without a row have not been delivered to the group yet, and
+-- messages with deleted_at set are finished with.
+create table if not exists consumer_group_deliveries (
+  grp integer not null,
+  message integer not null,
+  tries integer not null default 0,
+  delivered_at integer,
+  visible_at integer,
+  deleted_at integer,
+
+  primary key (grp, message),
+  foreign key (grp) references consumer_groups(id) on delete cascade,
+  foreign key (message) references messages(id) on delete cascade
+);
+create index if not exists consumer_group_deliveries_message_idx on consumer_group_deliveries(message);
+
+alter table message_traces add column consumer_group text;
@@ -0,0 +1,3 @@
+-- Bodies that are stored compressed stay compressed, and can't be read after reverting this.
+alter table messages_history drop column compressed;
+alter table messages drop column compressed;
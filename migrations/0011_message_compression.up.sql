@@ -0,0 +1,3 @@
+-- Bodies of messages with compressed set are stored zstd-compressed.
+alter table messages add column compressed boolean not null default false;
+alter table messages_history add column compressed boolean not null default false;
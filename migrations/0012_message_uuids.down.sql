@@ -0,0 +1,2 @@
+drop index messages_message_id_idx;
+alter table messages drop column message_id;
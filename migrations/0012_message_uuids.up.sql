@@ -0,0 +1,11 @@
+-- Globally unique message IDs, as returned by the SQS API. The integer id is only used internally.
+alter table messages add column message_id text;
+
+-- Existing messages get random (version 4) UUIDs, new messages get UUIDv7s.
+update messages set message_id = lower(
+  hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-'
+  || substr('89ab', 1 + abs(random() % 4), 1) || substr(hex(randomblob(2)), 2) || '-'
+  || hex(randomblob(6))
+);
+
+create unique index if not exists messages_message_id_idx on messages(message_id);
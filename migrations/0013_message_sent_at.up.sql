@@ -0,0 +1,7 @@
+-- Time each message was sent, for reports on message age.
+alter table messages add column sent_at integer;
+
+-- Existing messages are dated by their trace, if it is still around.
+update messages set sent_at = (
+  select min(at) from message_traces t where t.message = messages.id and t.event = 'sent'
+);
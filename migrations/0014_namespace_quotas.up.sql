@@ -0,0 +1,3 @@
+-- Number of namespaces a user may create themselves. Users without a quota can't create
+-- namespaces; admins can create any number regardless.
+alter table users add column namespace_quota integer;
@@ -0,0 +1,3 @@
+alter table api_keys drop column request_count;
+alter table api_keys drop column last_used_at;
+alter table api_keys drop column created_at;
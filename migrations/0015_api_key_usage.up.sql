@@ -0,0 +1,5 @@
+-- When API keys were created and last used, and how many requests they authenticated, so users
+-- can find stale keys. Usage is written in batches, so it can lag behind by a few seconds.
+alter table api_keys add column created_at integer;
+alter table api_keys add column last_used_at integer;
+alter table api_keys add column request_count integer not null default 0;
@@ -0,0 +1,3 @@
+-- Networks (a JSON array of CIDRs) that requests authenticated by an API key must come from.
+-- Keys without a list can be used from anywhere.
+alter table api_keys add column allowed_networks text;
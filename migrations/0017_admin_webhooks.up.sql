@@ -0,0 +1,11 @@
+-- Subscriptions to administrative events. The secret is kept as is, since deliveries are signed
+-- with it. `events` is a JSON array of event types, or null for every administrative event.
+create table if not exists admin_webhooks (
+  id integer not null,
+  url text not null,
+  secret text not null,
+  events text,
+  created_at integer not null,
+
+  primary key (id)
+);
@@ -0,0 +1,2 @@
+drop index message_failures_message_idx;
+drop table message_failures;
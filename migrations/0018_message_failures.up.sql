@@ -0,0 +1,14 @@
+-- Failure reasons consumers report when they give up on a delivery. Only the most recent few are
+-- kept per message, and they follow the message into its dead-letter queue.
+create table if not exists message_failures (
+  id integer not null,
+  message integer not null,
+  consumer_group text,
+  reason text not null,
+  tries integer not null,
+  reported_at integer not null default (unixepoch('now')),
+
+  primary key (id),
+  foreign key (message) references messages(id) on delete cascade
+);
+create index if not exists message_failures_message_idx on message_failures(message, id);
@@ -0,0 +1 @@
+alter table messages_history drop column result;
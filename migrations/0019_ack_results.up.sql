@@ -0,0 +1,2 @@
+-- Status payload a consumer reported with a successful acknowledgement, as JSON text.
+alter table messages_history add column result text;
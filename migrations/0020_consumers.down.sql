@@ -0,0 +1,2 @@
+drop index consumers_name_idx;
+drop table consumers;
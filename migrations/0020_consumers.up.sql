@@ -0,0 +1,13 @@
+-- Consumers that registered with a queue. A consumer is alive while it keeps sending heartbeats;
+-- queues without any registered consumer aren't watched.
+create table if not exists consumers (
+  id integer not null,
+  queue integer not null,
+  name text not null,
+  registered_at integer not null default (unixepoch('now')),
+  last_heartbeat_at integer not null default (unixepoch('now')),
+
+  primary key (id),
+  foreign key (queue) references queues(id) on delete cascade
+);
+create unique index if not exists consumers_name_idx on consumers(queue, name);
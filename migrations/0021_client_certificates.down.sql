@@ -0,0 +1,4 @@
+drop index client_certificates_fingerprint_idx;
+drop index client_certificates_subject_name_idx;
+drop index client_certificates_user_name_idx;
+drop table client_certificates;
@@ -0,0 +1,19 @@
+-- Client certificates users mapped to one of their namespaces, recognized either by a subject
+-- alternative name the certificate is valid for or by the fingerprint of one certificate.
+create table if not exists client_certificates (
+  id integer not null,
+  user integer not null,
+  ns integer not null,
+  name text not null,
+  subject_name text,
+  fingerprint text,
+  created_at integer not null default (unixepoch('now')),
+
+  primary key (id),
+  foreign key (user) references users(id) on delete cascade,
+  foreign key (ns) references namespaces(id) on delete cascade,
+  check ((subject_name is null) != (fingerprint is null))
+);
+create unique index if not exists client_certificates_user_name_idx on client_certificates(user, name);
+create unique index if not exists client_certificates_subject_name_idx on client_certificates(subject_name);
+create unique index if not exists client_certificates_fingerprint_idx on client_certificates(fingerprint);
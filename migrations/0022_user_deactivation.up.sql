@@ -0,0 +1,3 @@
+-- Time a user was deactivated, for example by their identity provider through SCIM.
+-- Deactivated users keep their data, but can't log in or use the API until reactivated.
+alter table users add column deactivated_at integer;
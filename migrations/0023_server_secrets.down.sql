@@ -0,0 +1 @@
+drop table server_secrets;
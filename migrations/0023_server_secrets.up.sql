@@ -0,0 +1,10 @@
+-- Secrets the server generates for itself, like the key session cookies are signed with, encrypted
+-- with the key manager.
+create table if not exists server_secrets (
+  name text not null,
+  kms_key_id text not null,
+  secret blob not null,
+  created_at integer not null default (unixepoch('now')),
+
+  primary key (name)
+);
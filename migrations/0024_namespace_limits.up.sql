@@ -0,0 +1,14 @@
+-- Limits on the resources of a namespace, set by admins. A missing row or a null limit means
+-- unlimited.
+create table if not exists namespace_limits (
+  namespace integer not null,
+  -- Number of queues in the namespace
+  max_queues integer,
+  -- Total size of the bodies of the messages in the namespace, in bytes
+  max_storage_bytes integer,
+  -- Number of messages in each queue of the namespace
+  max_messages_per_queue integer,
+
+  primary key (namespace),
+  foreign key (namespace) references namespaces(id) on delete cascade
+);
@@ -0,0 +1,2 @@
+drop table rate_limit_buckets;
+drop table rate_limits;
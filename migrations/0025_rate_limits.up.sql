@@ -0,0 +1,26 @@
+-- Rate limits of a namespace's API keys and users, or of a single API key, overriding the
+-- configured default. A key's own limit takes precedence over its namespace's.
+create table if not exists rate_limits (
+  id integer not null,
+  namespace integer,
+  api_key integer,
+  requests_per_second real not null,
+  burst integer not null,
+
+  primary key (id),
+  foreign key (namespace) references namespaces(id) on delete cascade,
+  foreign key (api_key) references api_keys(id) on delete cascade,
+  check ((namespace is null) != (api_key is null))
+);
+create unique index if not exists rate_limits_namespace_idx on rate_limits(namespace);
+create unique index if not exists rate_limits_api_key_idx on rate_limits(api_key);
+
+-- Token buckets of rate limits, when they are kept in the database so that instances sharing it
+-- share their limits.
+create table if not exists rate_limit_buckets (
+  key text not null,
+  tokens real not null,
+  updated_at real not null,
+
+  primary key (key)
+);
@@ -0,0 +1,2 @@
+drop table message_move_tasks;
+alter table messages drop column dead_lettered_from;
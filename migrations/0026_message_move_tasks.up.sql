@@ -0,0 +1,29 @@
+-- The queue a message was in before it was moved to a dead-letter queue, so that a message move
+-- task can return it there.
+alter table messages add column dead_lettered_from integer references queues(id) on delete set null;
+
+-- Tasks moving the messages of a dead-letter queue to another queue, or back to the queues they
+-- came from if there is no destination. Only the messages that were in the queue when the task
+-- started, up to `last_message`, are moved.
+create table if not exists message_move_tasks (
+  id integer not null,
+  handle text not null,
+  source integer not null,
+  destination integer,
+  max_per_second integer,
+  status text not null default 'running' check (status in ('running', 'completed', 'cancelled', 'failed')),
+  moved integer not null default 0,
+  to_move integer not null,
+  last_message integer not null,
+  failure_reason text,
+  started_at integer not null,
+
+  primary key (id),
+  foreign key (source) references queues(id) on delete cascade,
+  foreign key (destination) references queues(id) on delete cascade
+);
+create unique index if not exists message_move_tasks_handle_idx on message_move_tasks(handle);
+create index if not exists message_move_tasks_source_idx on message_move_tasks(source, id);
+-- A queue has at most one running task.
+create unique index if not exists message_move_tasks_running_idx
+  on message_move_tasks(source) where status = 'running';
@@ -0,0 +1,14 @@
+-- Messages sent with a MessageDeduplicationId, so that sending the same ID to the same queue again
+-- within the deduplication interval returns the first message instead of sending another one.
+create table if not exists message_deduplication (
+  queue integer not null,
+  deduplication_id text not null,
+  message_id text not null,
+  message integer not null,
+  expires_at integer not null,
+
+  primary key (queue, deduplication_id),
+  foreign key (queue) references queues(id) on delete cascade
+);
+create index if not exists message_deduplication_expires_idx
+  on message_deduplication(queue, expires_at);
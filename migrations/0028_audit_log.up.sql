@@ -0,0 +1,16 @@
+-- Mutating requests, with who made them and what came of them, for compliance. Entries are kept
+-- for the audit retention period.
+create table if not exists audit_log (
+  id integer not null,
+  at integer not null,
+  actor text,
+  api_key text,
+  namespace text,
+  action text not null,
+  resource text not null,
+  status integer not null,
+
+  primary key (id)
+);
+create index if not exists audit_log_at_idx on audit_log(at);
+create index if not exists audit_log_actor_idx on audit_log(actor, id);
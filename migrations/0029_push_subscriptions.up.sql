@@ -0,0 +1,17 @@
+-- Webhooks the messages of a queue are pushed to, see `crate::push`. A queue has at most one.
+-- Failed deliveries are retried after `initial_backoff` seconds, and twice as long after every
+-- further failure, up to `max_backoff` seconds.
+create table if not exists push_subscriptions (
+  id integer not null,
+  queue integer not null,
+  url text not null,
+  auth_header text,
+  concurrency integer not null,
+  initial_backoff integer not null,
+  max_backoff integer not null,
+  created_at integer not null,
+
+  primary key (id),
+  foreign key (queue) references queues(id) on delete cascade
+);
+create unique index if not exists push_subscriptions_queue_idx on push_subscriptions(queue);
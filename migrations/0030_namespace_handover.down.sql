@@ -0,0 +1 @@
+drop trigger if exists users_namespace_handover;
@@ -0,0 +1,13 @@
+-- Namespaces outlive the user who created them, like queues do, and are handed to the oldest
+-- remaining admin. Deleting such a user used to fail on the foreign key instead.
+--
+-- A trigger rather than `on delete set null`, since SQLite can only change a foreign key by
+-- rebuilding the table, and rebuilding it within the migration's transaction would cascade to
+-- every queue, key and permission of the namespaces.
+create trigger if not exists users_namespace_handover
+before delete on users
+begin
+  update namespaces
+  set created_by = (select min(id) from users where role = 'admin' and id != old.id)
+  where created_by = old.id;
+end;
@@ -0,0 +1 @@
+alter table queues drop column purged_at;
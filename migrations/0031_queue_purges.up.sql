@@ -0,0 +1,2 @@
+-- Time a queue was last purged. As in SQS, a queue can only be purged once a minute.
+alter table queues add column purged_at integer;
@@ -0,0 +1,8 @@
+drop trigger if exists users_namespace_handover;
+create trigger if not exists users_namespace_handover
+before delete on users
+begin
+  update namespaces
+  set created_by = (select min(id) from users where role = 'admin' and id != old.id)
+  where created_by = old.id;
+end;
@@ -0,0 +1,15 @@
+-- Deleting the last admin used to hand their namespaces to nobody, which `created_by` doesn't
+-- allow, and failed on the NOT NULL constraint. It now fails with a message that says why, which
+-- `UserService::delete_user` reports to the caller.
+drop trigger if exists users_namespace_handover;
+create trigger if not exists users_namespace_handover
+before delete on users
+begin
+  select raise(abort, 'no admin to hand namespaces over to')
+  where exists (select 1 from namespaces where created_by = old.id)
+  and not exists (select 1 from users where role = 'admin' and id != old.id);
+
+  update namespaces
+  set created_by = (select min(id) from users where role = 'admin' and id != old.id)
+  where created_by = old.id;
+end;
@@ -0,0 +1,3 @@
+-- History records name the message by the ID clients know it by, like receipt handles do, rather
+-- than by its row id. Records made before this have none.
+alter table messages_history add column message_id text;
@@ -0,0 +1,4 @@
+drop trigger if exists messages_usage_update;
+drop trigger if exists messages_usage_delete;
+drop trigger if exists messages_usage_insert;
+drop table if exists queue_usage;
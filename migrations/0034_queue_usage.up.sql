@@ -0,0 +1,48 @@
+-- Number and size of the messages in each queue, kept up to date by triggers in the transaction
+-- that changes them, so that checking a namespace's limits on every send doesn't count its
+-- messages. They live apart from `queues`, which nearly every request reads, so that concurrent
+-- sends don't hold each other's reads of it up.
+create table if not exists queue_usage (
+  queue integer primary key not null references queues (id) on delete cascade,
+  message_count integer not null default 0,
+  message_bytes integer not null default 0
+);
+
+insert into queue_usage (queue, message_count, message_bytes)
+select q.id, count(m.id), coalesce(sum(length(m.body)), 0)
+from queues q
+left join messages m on m.queue = q.id
+group by q.id;
+
+create trigger if not exists messages_usage_insert
+after insert on messages
+begin
+  insert or ignore into queue_usage (queue) values (new.queue);
+  update queue_usage
+  set message_count = message_count + 1,
+    message_bytes = message_bytes + coalesce(length(new.body), 0)
+  where queue = new.queue;
+end;
+
+create trigger if not exists messages_usage_delete
+after delete on messages
+begin
+  update queue_usage
+  set message_count = message_count - 1,
+    message_bytes = message_bytes - coalesce(length(old.body), 0)
+  where queue = old.queue;
+end;
+
+create trigger if not exists messages_usage_update
+after update of queue, body on messages
+begin
+  update queue_usage
+  set message_count = message_count - 1,
+    message_bytes = message_bytes - coalesce(length(old.body), 0)
+  where queue = old.queue;
+  insert or ignore into queue_usage (queue) values (new.queue);
+  update queue_usage
+  set message_count = message_count + 1,
+    message_bytes = message_bytes + coalesce(length(new.body), 0)
+  where queue = new.queue;
+end;
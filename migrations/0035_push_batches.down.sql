@@ -0,0 +1,2 @@
+alter table push_subscriptions drop column batch_window;
+alter table push_subscriptions drop column batch_size;
@@ -0,0 +1,5 @@
+-- Push subscriptions can deliver up to `batch_size` messages in one request, waiting up to
+-- `batch_window` milliseconds for a batch to fill, see `crate::push`. One message per request,
+-- without waiting, is how subscriptions delivered before.
+alter table push_subscriptions add column batch_size integer not null default 1;
+alter table push_subscriptions add column batch_window integer not null default 0;
@@ -1,7 +1,8 @@
 import type { NextConfig } from "next";
 
 const nextConfig: NextConfig = {
-  /* config options here */
+  // `npm run export` builds a static export into `out/`, for the `dashboard` feature of the server.
+  output: process.env.NEXT_OUTPUT === "export" ? "export" : undefined,
 };
 
 export default nextConfig;
@@ -0,0 +1,73 @@
+//! Consumer-reported processing results.
+//!
+//! DeleteMessage only tells NerveMQ that a consumer is done with a message. With `POST /api/ack`,
+//! consumers report for each receipt handle whether processing succeeded, along with a status
+//! payload of their choosing:
+//!
+//! - Successfully processed messages are deleted, and the payload is kept as the `result` of the
+//!   message's history record, if the queue keeps a history (see [`crate::history`]).
+//! - Failed messages become receivable again right away, subject to the queue's
+//!   `RedeliveryBackoff` (see [`crate::redelivery`]), and move to the dead-letter queue once they
+//!   have used up their retries. The payload is recorded as the failure reason of the delivery.
+//!
+//! Each result is handled independently, like in a batch delete: results that can't be applied
+//! (e.g. because the message is no longer in flight) are returned as failed entries.
+
+use serde::{Deserialize, Serialize};
+
+use crate::message::MAX_FAILURE_REASON_LENGTH;
+
+/// Maximum number of results in an acknowledgement.
+pub const MAX_ACK_RESULTS: usize = 1000;
+
+/// Maximum length, in bytes, of a status payload once serialized as JSON. Failure payloads become
+/// failure reasons, so they share their limit.
+pub const MAX_ACK_PAYLOAD_LENGTH: usize = MAX_FAILURE_REASON_LENGTH;
+
+/// Results of processing messages of a queue.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckRequest {
+    pub namespace: String,
+    pub queue: String,
+    /// Consumer group the messages were received by, if any
+    #[serde(default)]
+    pub consumer_group: Option<String>,
+    pub results: Vec<AckResult>,
+}
+
+/// Result of processing a message.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckResult {
+    pub receipt_handle: String,
+    pub success: bool,
+    /// Status payload, any JSON value
+    #[serde(default)]
+    pub payload: Option<serde_json::Value>,
+}
+
+/// Result of processing a message, with the message ID from its receipt handle.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub message_id: u64,
+    pub success: bool,
+    pub payload: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckResponse {
+    /// Receipt handles whose results were applied
+    pub successful: Vec<String>,
+    pub failed: Vec<AckError>,
+}
+
+/// A result that couldn't be applied.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckError {
+    pub receipt_handle: String,
+    pub code: String,
+    pub message: String,
+}
@@ -0,0 +1,85 @@
+use actix_identity::Identity;
+use actix_web::{post, web};
+
+use crate::{
+    ack::{AckError, AckRequest, AckResponse, Outcome},
+    auth::credential::AuthorizedNamespace,
+    error::Error,
+    service::Service,
+};
+
+#[post("/ack")]
+pub(super) async fn ack_messages(
+    service: web::Data<Service>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+    request: web::Json<AckRequest>,
+) -> Result<web::Json<AckResponse>, Error> {
+    let request = request.into_inner();
+
+    AuthorizedNamespace::check(authorized.as_ref(), &request.namespace, &service)?;
+
+    let mut response = AckResponse::default();
+
+    // Several results may carry the same receipt handle, so keep the handle of each.
+    let mut outcomes = Vec::new();
+    let mut handles = Vec::new();
+    let message_ids = service
+        .resolve_receipt_handles(
+            &request.namespace,
+            &request.queue,
+            &request
+                .results
+                .iter()
+                .map(|result| result.receipt_handle.clone())
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+    for (result, message_id) in request.results.into_iter().zip(message_ids) {
+        match message_id {
+            Some(message_id) => {
+                outcomes.push(Outcome {
+                    message_id,
+                    success: result.success,
+                    payload: result.payload,
+                });
+                handles.push((message_id, result.receipt_handle));
+            }
+            None => response.failed.push(AckError {
+                code: "ReceiptHandleIsInvalid".to_owned(),
+                message: format!("receipt handle {} is invalid", result.receipt_handle),
+                receipt_handle: result.receipt_handle,
+            }),
+        }
+    }
+
+    if !outcomes.is_empty() {
+        let (applied, errors) = service
+            .ack_messages(
+                &request.namespace,
+                &request.queue,
+                outcomes,
+                request.consumer_group.as_deref(),
+                identity,
+            )
+            .await?;
+
+        let handle = |message_id: u64| {
+            handles
+                .iter()
+                .find(|(id, _)| *id == message_id)
+                .map(|(_, handle)| handle.clone())
+                .unwrap_or_default()
+        };
+        response.successful = applied.into_iter().map(handle).collect();
+        response
+            .failed
+            .extend(errors.into_iter().map(|(message_id, error)| AckError {
+                receipt_handle: handle(message_id),
+                code: error.code().to_owned(),
+                message: error.to_string(),
+            }));
+    }
+
+    Ok(web::Json(response))
+}
@@ -1,15 +1,26 @@
 use actix_web::{
-    delete,
-    error::{ErrorBadRequest, ErrorInternalServerError},
-    get, post, put,
+    delete, get, guard, post, put,
     web::{self, Json},
-    HttpResponse, Responder, Scope,
+    HttpMessage, HttpRequest, HttpResponse, Responder, Scope,
 };
+use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 use serde_email::Email;
 use sqlx::FromRow;
 
-use crate::{error::Error, service::Service};
+use crate::{
+    audit::{AuditEntry, AuditQuery},
+    error::Error,
+    key_export::{self, ExportRequest, ImportRequest, ImportResponse, KeyExport},
+    logging,
+    namespace::NamespaceLimits,
+    rate_limit::{RateLimit, RateLimitTarget},
+    report::{ReportKind, ReportRow, DEFAULT_REPORT_ROWS},
+    service::Service,
+    storage::{self, StorageReport, VacuumQuery},
+    user_import::{self, ImportUsersResponse},
+    webhooks::{CreateWebhookRequest, CreateWebhookResponse, Webhook},
+};
 
 use super::auth::Role;
 
@@ -28,17 +39,60 @@ pub async fn create_user(
 ) -> Result<impl Responder, Error> {
     let data = data.into_inner();
 
-    let email = Email::from_str(&data.email).map_err(|e| ErrorBadRequest(e))?;
+    let email = Email::from_str(&data.email)
+        .map_err(|e| Error::invalid_parameter(format!("email: {e}")))?;
 
     service
         .create_user(email, data.password, Some(data.role), data.namespaces)
-        .await
-        .map_err(|e| ErrorInternalServerError(e))?;
+        .await?;
 
     // Return the plain API key (should be securely sent/stored by the user).
     Ok(HttpResponse::Ok())
 }
 
+#[post("/users:import")]
+async fn import_users(
+    service: web::Data<Service>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> Result<Json<ImportUsersResponse>, Error> {
+    let users = user_import::parse(req.content_type(), &body)?;
+
+    Ok(Json(service.import_users(users).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteUserRequest {
+    email: String,
+    role: Role,
+    #[serde(default)]
+    namespaces: Vec<String>,
+    #[serde(default)]
+    namespace_quota: Option<u64>,
+}
+
+#[post("/invitations")]
+pub async fn invite_user(
+    data: web::Json<InviteUserRequest>,
+    service: web::Data<Service>,
+) -> Result<impl Responder, Error> {
+    let data = data.into_inner();
+
+    let email = Email::from_str(&data.email)
+        .map_err(|e| Error::invalid_parameter(format!("email: {e}")))?;
+
+    service
+        .invite_user(
+            email,
+            Some(data.role),
+            data.namespaces,
+            data.namespace_quota,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct UserInfo {
     email: String,
@@ -46,11 +100,10 @@ pub struct UserInfo {
 }
 
 #[get("/users")]
-pub async fn list_users(service: web::Data<Service>) -> actix_web::Result<impl Responder> {
+pub async fn list_users(service: web::Data<Service>) -> Result<impl Responder, Error> {
     let users: Vec<UserInfo> = sqlx::query_as("SELECT * FROM users")
         .fetch_all(service.db())
-        .await
-        .map_err(ErrorInternalServerError)?;
+        .await?;
 
     Ok(Json(users))
 }
@@ -64,19 +117,32 @@ pub struct DeleteUserRequest {
 pub async fn delete_user(
     data: web::Json<DeleteUserRequest>,
     service: web::Data<Service>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, Error> {
     service
-        .delete_user(Email::from_str(&data.email).map_err(|e| ErrorBadRequest(e))?)
+        .delete_user(
+            Email::from_str(&data.email)
+                .map_err(|e| Error::invalid_parameter(format!("email: {e}")))?,
+        )
         .await?;
 
     Ok(HttpResponse::Ok())
 }
 
-#[get("/users/{email}/permissions")]
+#[delete("/users/{email}/sessions")]
+pub async fn delete_user_sessions(
+    service: web::Data<Service>,
+    email: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    service.delete_user_sessions(&email).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[get("/permissions")]
 pub async fn list_user_permissions(
     service: web::Data<Service>,
     email: web::Path<String>,
-) -> actix_web::Result<web::Json<Vec<String>>> {
+) -> Result<web::Json<Vec<String>>, Error> {
     let email = email.into_inner();
 
     let permissions: Vec<String> = sqlx::query_scalar(
@@ -89,8 +155,7 @@ pub async fn list_user_permissions(
     )
     .bind(&email)
     .fetch_all(service.db())
-    .await
-    .map_err(ErrorInternalServerError)?;
+    .await?;
 
     Ok(Json(permissions))
 }
@@ -100,28 +165,23 @@ pub async fn grant_user_permissions(
     service: web::Data<Service>,
     email: web::Path<String>,
     data: Json<Vec<String>>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, Error> {
     let email = email.into_inner();
-    let mut tx = service
-        .db()
-        .begin()
-        .await
-        .map_err(ErrorInternalServerError)?;
+    let mut tx = service.db().begin().await?;
     for namespace in data.iter() {
         sqlx::query(
             "
             INSERT INTO user_permissions (user, namespace)
             VALUES ((SELECT id FROM users WHERE email = $1), (SELECT id FROM namespaces WHERE name = $2))
-            ON CONFLICT DO UPDATE
+            ON CONFLICT DO NOTHING
             ",
         )
         .bind(&email)
         .bind(namespace)
         .execute(&mut *tx)
-        .await
-        .map_err(ErrorInternalServerError)?;
+        .await?;
     }
-    tx.commit().await.map_err(ErrorInternalServerError)?;
+    tx.commit().await?;
     Ok(HttpResponse::Ok())
 }
 
@@ -130,13 +190,9 @@ pub async fn revoke_user_permissions(
     service: web::Data<Service>,
     email: web::Path<String>,
     data: Json<Vec<String>>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, Error> {
     let email = email.into_inner();
-    let mut tx = service
-        .db()
-        .begin()
-        .await
-        .map_err(ErrorInternalServerError)?;
+    let mut tx = service.db().begin().await?;
     for namespace in data.iter() {
         sqlx::query(
             "
@@ -148,10 +204,10 @@ pub async fn revoke_user_permissions(
         .bind(&email)
         .bind(namespace)
         .execute(&mut *tx)
-        .await
-        .map_err(ErrorInternalServerError)?;
+        .await?;
     }
-    tx.commit().await.map_err(ErrorInternalServerError)?;
+    service.revoke_stale_tokens(&email, &mut *tx).await?;
+    tx.commit().await?;
     Ok(HttpResponse::Ok())
 }
 
@@ -160,14 +216,10 @@ pub async fn update_user_permissions(
     service: web::Data<Service>,
     email: web::Path<String>,
     data: Json<Vec<String>>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, Error> {
     let email = email.into_inner();
 
-    let mut tx = service
-        .db()
-        .begin()
-        .await
-        .map_err(ErrorInternalServerError)?;
+    let mut tx = service.db().begin().await?;
 
     // Revoke all existing permissions.
     sqlx::query(
@@ -178,8 +230,7 @@ pub async fn update_user_permissions(
     )
     .bind(&email)
     .execute(&mut *tx)
-    .await
-    .map_err(ErrorInternalServerError)?;
+    .await?;
 
     for namespace in data.iter() {
         sqlx::query(
@@ -192,19 +243,19 @@ pub async fn update_user_permissions(
         .bind(&email)
         .bind(namespace)
         .execute(&mut *tx)
-        .await
-        .map_err(ErrorInternalServerError)?;
+        .await?;
     }
 
-    tx.commit().await.map_err(ErrorInternalServerError)?;
+    service.revoke_stale_tokens(&email, &mut *tx).await?;
+    tx.commit().await?;
     Ok(HttpResponse::Ok())
 }
 
-#[get("/users/{email}/role")]
+#[get("/role")]
 async fn get_user_role(
     service: web::Data<Service>,
     email: web::Path<String>,
-) -> actix_web::Result<web::Json<Role>> {
+) -> Result<web::Json<Role>, Error> {
     let email = email.into_inner();
     let role: Role = sqlx::query_scalar(
         "
@@ -214,8 +265,7 @@ async fn get_user_role(
     )
     .bind(&email)
     .fetch_one(service.db())
-    .await
-    .map_err(ErrorInternalServerError)?;
+    .await?;
     Ok(Json(role))
 }
 
@@ -229,32 +279,306 @@ async fn set_user_role(
     service: web::Data<Service>,
     email: web::Path<String>,
     data: web::Json<UpdateUserRoleRequest>,
-) -> actix_web::Result<impl Responder> {
+) -> Result<impl Responder, Error> {
+    service
+        .set_user_role(&email, data.into_inner().role)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceQuota {
+    quota: Option<u64>,
+}
+
+#[get("/namespace-quota")]
+async fn get_namespace_quota(
+    service: web::Data<Service>,
+    email: web::Path<String>,
+) -> Result<Json<NamespaceQuota>, Error> {
     let email = email.into_inner();
-    sqlx::query(
+    let quota: Option<u64> = sqlx::query_scalar(
         "
-            UPDATE users
-            SET role = $2
+            SELECT namespace_quota FROM users
             WHERE email = $1
         ",
     )
     .bind(&email)
-    .bind(&data.role)
-    .execute(service.db())
-    .await
-    .map_err(ErrorInternalServerError)?;
+    .fetch_optional(service.db())
+    .await?
+    .ok_or_else(|| Error::not_found(format!("user {email}")))?;
+
+    Ok(Json(NamespaceQuota { quota }))
+}
+
+#[put("/users/{email}/namespace-quota")]
+async fn set_namespace_quota(
+    service: web::Data<Service>,
+    email: web::Path<String>,
+    data: web::Json<NamespaceQuota>,
+) -> Result<impl Responder, Error> {
+    service.set_namespace_quota(&email, data.quota).await?;
+
     Ok(HttpResponse::Ok())
 }
 
+#[get("/namespaces/{namespace}/limits")]
+async fn get_namespace_limits(
+    service: web::Data<Service>,
+    namespace: web::Path<String>,
+) -> Result<Json<NamespaceLimits>, Error> {
+    Ok(Json(service.namespace_limits(&namespace).await?))
+}
+
+#[put("/namespaces/{namespace}/limits")]
+async fn set_namespace_limits(
+    service: web::Data<Service>,
+    namespace: web::Path<String>,
+    data: web::Json<NamespaceLimits>,
+) -> Result<impl Responder, Error> {
+    service
+        .set_namespace_limits(&namespace, data.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitSetting {
+    rate_limit: Option<RateLimit>,
+}
+
+#[get("/namespaces/{namespace}/rate-limit")]
+async fn get_namespace_rate_limit(
+    service: web::Data<Service>,
+    namespace: web::Path<String>,
+) -> Result<Json<RateLimitSetting>, Error> {
+    let rate_limit = service
+        .rate_limit(RateLimitTarget::Namespace(&namespace))
+        .await?;
+
+    Ok(Json(RateLimitSetting { rate_limit }))
+}
+
+#[put("/namespaces/{namespace}/rate-limit")]
+async fn set_namespace_rate_limit(
+    service: web::Data<Service>,
+    namespace: web::Path<String>,
+    data: web::Json<RateLimitSetting>,
+) -> Result<impl Responder, Error> {
+    service
+        .set_rate_limit(RateLimitTarget::Namespace(&namespace), data.rate_limit)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[get("/api-keys/{key_id}/rate-limit")]
+async fn get_api_key_rate_limit(
+    service: web::Data<Service>,
+    key_id: web::Path<String>,
+) -> Result<Json<RateLimitSetting>, Error> {
+    let rate_limit = service.rate_limit(RateLimitTarget::ApiKey(&key_id)).await?;
+
+    Ok(Json(RateLimitSetting { rate_limit }))
+}
+
+#[put("/api-keys/{key_id}/rate-limit")]
+async fn set_api_key_rate_limit(
+    service: web::Data<Service>,
+    key_id: web::Path<String>,
+    data: web::Json<RateLimitSetting>,
+) -> Result<impl Responder, Error> {
+    service
+        .set_rate_limit(RateLimitTarget::ApiKey(&key_id), data.rate_limit)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceMode {
+    enabled: bool,
+}
+
+#[get("/maintenance")]
+async fn get_maintenance_mode(service: web::Data<Service>) -> Result<impl Responder, Error> {
+    Ok(Json(MaintenanceMode {
+        enabled: service.maintenance_mode(),
+    }))
+}
+
+#[put("/maintenance")]
+async fn set_maintenance_mode(
+    service: web::Data<Service>,
+    data: web::Json<MaintenanceMode>,
+) -> Result<impl Responder, Error> {
+    service.set_maintenance_mode(data.enabled);
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogLevel {
+    filter: String,
+}
+
+#[get("/log-level")]
+async fn get_log_level() -> Result<impl Responder, Error> {
+    let filter = logging::filter().ok_or_else(|| Error::not_found("log filter"))?;
+
+    Ok(Json(LogLevel { filter }))
+}
+
+#[put("/log-level")]
+async fn set_log_level(data: web::Json<LogLevel>) -> Result<impl Responder, Error> {
+    logging::set_filter(&data.filter)?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportQuery {
+    attribute: Option<String>,
+    limit: Option<u64>,
+}
+
+#[get("/reports/{namespace}/{queue}/{report}")]
+async fn queue_report(
+    service: web::Data<Service>,
+    path: web::Path<(String, String, ReportKind)>,
+    query: web::Query<ReportQuery>,
+) -> Result<Json<Vec<ReportRow>>, Error> {
+    let (namespace, queue, kind) = path.into_inner();
+
+    let rows = service
+        .queue_report(
+            &namespace,
+            &queue,
+            kind,
+            query.attribute.as_deref(),
+            query.limit.unwrap_or(DEFAULT_REPORT_ROWS),
+        )
+        .await?;
+
+    Ok(Json(rows))
+}
+
+#[get("/storage")]
+async fn get_storage_report(service: web::Data<Service>) -> Result<Json<StorageReport>, Error> {
+    Ok(Json(storage::report(&service).await?))
+}
+
+#[post("/storage/vacuum")]
+async fn vacuum_storage(
+    service: web::Data<Service>,
+    query: web::Query<VacuumQuery>,
+) -> Result<Json<StorageReport>, Error> {
+    storage::vacuum(&service, query.full).await?;
+
+    Ok(Json(storage::report(&service).await?))
+}
+
+#[post("/webhooks")]
+async fn create_webhook(
+    service: web::Data<Service>,
+    data: web::Json<CreateWebhookRequest>,
+) -> Result<Json<CreateWebhookResponse>, Error> {
+    Ok(Json(service.create_webhook(data.into_inner()).await?))
+}
+
+#[get("/webhooks")]
+async fn list_webhooks(service: web::Data<Service>) -> Result<Json<Vec<Webhook>>, Error> {
+    Ok(Json(service.list_webhooks().await?))
+}
+
+#[delete("/webhooks/{id}")]
+async fn delete_webhook(
+    service: web::Data<Service>,
+    id: web::Path<u64>,
+) -> Result<impl Responder, Error> {
+    service.delete_webhook(id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[post("/api-keys/export")]
+async fn export_api_keys(
+    service: web::Data<Service>,
+    data: web::Json<ExportRequest>,
+) -> Result<Json<KeyExport>, Error> {
+    let public_key = key_export::parse_public_key(&data.public_key)?;
+
+    Ok(Json(service.export_api_keys(&public_key).await?))
+}
+
+#[post("/api-keys/import")]
+async fn import_api_keys(
+    service: web::Data<Service>,
+    data: web::Json<ImportRequest>,
+) -> Result<Json<ImportResponse>, Error> {
+    let ImportRequest {
+        private_key,
+        export,
+    } = data.into_inner();
+    let private_key = key_export::parse_private_key(private_key.expose_secret())?;
+
+    Ok(Json(service.import_api_keys(export, private_key).await?))
+}
+
+#[get("/audit")]
+async fn list_audit_log(
+    service: web::Data<Service>,
+    query: web::Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, Error> {
+    Ok(Json(service.list_audit_log(&query).await?))
+}
+
+/// Read-only endpoints about a single user, which users can also use for themselves, see
+/// [`crate::auth::middleware::protected_route::Protected::admin_or_self`].
+pub fn user_service() -> Scope {
+    web::scope("/admin/users/{email}")
+        .guard(guard::Get())
+        .service(list_user_permissions)
+        .service(get_user_role)
+        .service(get_namespace_quota)
+}
+
 pub fn service() -> Scope {
     web::scope("/admin")
         .service(create_user)
+        .service(import_users)
+        .service(invite_user)
         .service(delete_user)
         .service(list_users)
-        .service(list_user_permissions)
+        .service(delete_user_sessions)
         .service(grant_user_permissions)
         .service(revoke_user_permissions)
         .service(update_user_permissions)
-        .service(get_user_role)
         .service(set_user_role)
+        .service(set_namespace_quota)
+        .service(get_namespace_limits)
+        .service(set_namespace_limits)
+        .service(get_namespace_rate_limit)
+        .service(set_namespace_rate_limit)
+        .service(get_api_key_rate_limit)
+        .service(set_api_key_rate_limit)
+        .service(get_maintenance_mode)
+        .service(set_maintenance_mode)
+        .service(get_log_level)
+        .service(set_log_level)
+        .service(queue_report)
+        .service(get_storage_report)
+        .service(vacuum_storage)
+        .service(create_webhook)
+        .service(list_webhooks)
+        .service(delete_webhook)
+        .service(export_api_keys)
+        .service(import_api_keys)
+        .service(list_audit_log)
 }
@@ -1,11 +1,17 @@
 use actix_identity::Identity;
 use actix_session::SessionExt;
-use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse, Responder, Scope};
+use actix_web::{delete, get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder, Scope};
 use argon2::{password_hash::PasswordHashString, Argon2, PasswordVerifier};
 use serde::{Deserialize, Serialize};
+use serde_email::Email;
 use sqlx::prelude::FromRow;
 
-use crate::{error::Error, service::Service};
+use crate::{
+    auth::session::{IDENTITY_KEY, IP_KEY, USER_AGENT_KEY},
+    error::Error,
+    proxy::ClientInfo,
+    service::Service,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
@@ -53,16 +59,18 @@ struct LoginData {
 #[post("/login")]
 pub async fn login(
     request: HttpRequest,
+    client: ClientInfo,
     form: web::Json<LoginRequest>,
     service: web::Data<Service>,
 ) -> Result<web::Json<SessionResponse>, Error> {
     let form = form.into_inner();
 
-    let Ok(Some(user_data)) =
-        sqlx::query_as::<_, LoginData>("SELECT hashed_pass, role FROM users WHERE email = $1")
-            .bind(&form.email)
-            .fetch_optional(service.db())
-            .await
+    let Ok(Some(user_data)) = sqlx::query_as::<_, LoginData>(
+        "SELECT hashed_pass, role FROM users WHERE email = $1 AND deactivated_at IS NULL",
+    )
+    .bind(&form.email)
+    .fetch_optional(service.db())
+    .await
     else {
         return Err(Error::UserNotFound { email: form.email });
     };
@@ -92,7 +100,7 @@ pub async fn login(
     match Identity::login(&request.extensions(), form.email.clone()) {
         Ok(id) => {
             session
-                .insert::<String>("nervemq_id", id.id().expect("identifier").to_string())
+                .insert::<String>(IDENTITY_KEY, id.id().expect("identifier").to_string())
                 .ok();
         }
         Err(e) => {
@@ -103,6 +111,18 @@ pub async fn login(
         }
     }
 
+    if let Some(user_agent) = request
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+    {
+        session.insert(USER_AGENT_KEY, user_agent).ok();
+    }
+
+    if let Some(ip) = client.ip {
+        session.insert(IP_KEY, ip.to_string()).ok();
+    }
+
     Ok(web::Json(SessionResponse {
         email: form.email,
         role: user_data.role,
@@ -110,7 +130,7 @@ pub async fn login(
 }
 
 #[post("/logout")]
-pub async fn logout(user: Identity) -> actix_web::Result<impl Responder> {
+pub async fn logout(user: Identity) -> Result<impl Responder, Error> {
     user.logout();
 
     Ok(HttpResponse::Ok())
@@ -146,9 +166,93 @@ pub async fn verify(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationRequest {
+    token: String,
+    password: String,
+}
+
+#[post("/invitation")]
+pub async fn accept_invitation(
+    data: web::Json<AcceptInvitationRequest>,
+    service: web::Data<Service>,
+) -> Result<impl Responder, Error> {
+    let data = data.into_inner();
+
+    service
+        .accept_invitation(&data.token, data.password)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    email: String,
+}
+
+#[post("/forgot")]
+pub async fn forgot_password(
+    data: web::Json<ForgotPasswordRequest>,
+    service: web::Data<Service>,
+) -> Result<impl Responder, Error> {
+    let email = Email::from_str(&data.email)
+        .map_err(|e| Error::invalid_parameter(format!("email: {e}")))?;
+
+    service.request_password_reset(email).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+#[post("/reset")]
+pub async fn reset_password(
+    data: web::Json<ResetPasswordRequest>,
+    service: web::Data<Service>,
+) -> Result<impl Responder, Error> {
+    let data = data.into_inner();
+
+    service.reset_password(&data.token, data.password).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[get("/sessions")]
+pub async fn list_sessions(
+    identity: Identity,
+    service: web::Data<Service>,
+) -> Result<impl Responder, Error> {
+    let sessions = service.list_sessions(&identity.id()?).await?;
+
+    Ok(web::Json(sessions))
+}
+
+#[delete("/sessions/{id}")]
+pub async fn delete_session(
+    identity: Identity,
+    service: web::Data<Service>,
+    id: web::Path<u64>,
+) -> Result<impl Responder, Error> {
+    service
+        .delete_session(&identity.id()?, id.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
 pub fn service() -> Scope {
     web::scope("/auth")
         .service(login)
         .service(logout)
         .service(verify)
+        .service(accept_invitation)
+        .service(forgot_password)
+        .service(reset_password)
+        .service(list_sessions)
+        .service(delete_session)
 }
@@ -0,0 +1,96 @@
+use actix_identity::Identity;
+use actix_web::{
+    delete, get, post,
+    web::{self, Json},
+    HttpResponse, Responder, Scope,
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::credential::AuthorizedNamespace,
+    error::Error,
+    service::Service,
+    tls::{CertificateMatch, ClientCertificateInfo},
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateCertificateRequest {
+    pub name: String,
+    pub namespace: String,
+    /// DNS name or IP address the client certificates must be valid for
+    #[serde(default)]
+    pub subject_name: Option<String>,
+    /// SHA-256 fingerprint of the client certificate
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+}
+
+#[post("")]
+pub async fn create_certificate(
+    data: web::Json<CreateCertificateRequest>,
+    service: web::Data<Service>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<Json<ClientCertificateInfo>, Error> {
+    let CreateCertificateRequest {
+        name,
+        namespace,
+        subject_name,
+        fingerprint,
+    } = data.into_inner();
+
+    AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+
+    let certificate = CertificateMatch::new(subject_name, fingerprint)?;
+
+    Ok(Json(
+        service
+            .create_client_certificate(&name, &namespace, certificate, identity)
+            .await?,
+    ))
+}
+
+#[get("")]
+pub async fn list_certificates(
+    service: web::Data<Service>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<Json<Vec<ClientCertificateInfo>>, Error> {
+    let certificates = service
+        .list_client_certificates(identity)
+        .await?
+        .into_iter()
+        .filter(|certificate| {
+            AuthorizedNamespace::allows(authorized.as_ref(), &certificate.namespace)
+        })
+        .collect();
+
+    Ok(Json(certificates))
+}
+
+#[delete("/{name}")]
+pub async fn delete_certificate(
+    service: web::Data<Service>,
+    name: web::Path<String>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    if let Some(namespace) = service
+        .client_certificate_namespace(&identity.id()?, &name)
+        .await?
+    {
+        AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+    }
+
+    service.delete_client_certificate(&name, identity).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+pub fn service() -> Scope {
+    web::scope("/certificates")
+        .service(create_certificate)
+        .service(list_certificates)
+        .service(delete_certificate)
+}
@@ -0,0 +1,52 @@
+use actix_identity::Identity;
+use actix_web::{delete, get, put, web, HttpResponse, Responder};
+
+use crate::{
+    auth::credential::AuthorizedNamespace, consumers::Consumer, error::Error, service::Service,
+};
+
+#[get("/consumers")]
+pub(super) async fn list_consumers(
+    service: web::Data<Service>,
+    identity: Identity,
+) -> Result<web::Json<Vec<Consumer>>, Error> {
+    let consumers = service.list_consumers(identity).await?;
+
+    Ok(web::Json(consumers))
+}
+
+#[put("/consumers/{ns_name}/{queue_name}/{consumer}")]
+pub(super) async fn heartbeat_consumer(
+    service: web::Data<Service>,
+    path: web::Path<(String, String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Consumer>, Error> {
+    let (namespace, queue, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let consumer = service
+        .heartbeat_consumer(namespace, queue, name, identity)
+        .await?;
+
+    Ok(web::Json(consumer))
+}
+
+#[delete("/consumers/{ns_name}/{queue_name}/{consumer}")]
+pub(super) async fn deregister_consumer(
+    service: web::Data<Service>,
+    path: web::Path<(String, String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let (namespace, queue, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    service
+        .deregister_consumer(namespace, queue, name, identity)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
@@ -1,30 +1,29 @@
 use std::collections::HashMap;
 
 use actix_identity::Identity;
-use actix_web::{get, web, Scope};
+use actix_web::{get, middleware::from_fn, web, Scope};
 
-use crate::{namespace::NamespaceStatistics, queue::QueueStatistics, service::Service};
+use crate::{
+    caching::conditional_get, error::Error, namespace::NamespaceStatistics, queue::QueueStatistics,
+    service::Service,
+};
 
-#[get("/queue")]
+#[get("/queue", wrap = "from_fn(conditional_get)")]
 async fn queue_stats(
     service: web::Data<Service>,
     identity: Identity,
-) -> actix_web::Result<web::Json<HashMap<String, QueueStatistics>>> {
-    match service.global_queue_statistics(identity).await {
-        Ok(val) => Ok(web::Json(val)),
-        Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
-    }
+) -> Result<web::Json<HashMap<String, QueueStatistics>>, Error> {
+    Ok(web::Json(service.global_queue_statistics(identity).await?))
 }
 
-#[get("/ns")]
+#[get("/ns", wrap = "from_fn(conditional_get)")]
 async fn namespace_stats(
     service: web::Data<Service>,
     identity: Identity,
-) -> actix_web::Result<web::Json<Vec<NamespaceStatistics>>> {
-    match service.list_namespace_statistics(identity).await {
-        Ok(val) => Ok(web::Json(val)),
-        Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
-    }
+) -> Result<web::Json<Vec<NamespaceStatistics>>, Error> {
+    Ok(web::Json(
+        service.list_namespace_statistics(identity).await?,
+    ))
 }
 
 pub fn service() -> Scope {
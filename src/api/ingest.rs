@@ -0,0 +1,52 @@
+use actix_identity::Identity;
+use actix_web::{post, web, HttpMessage, HttpRequest, Scope};
+
+use crate::{
+    auth::credential::AuthorizedNamespace,
+    error::Error,
+    ingest::{self, IngestResponse, MAX_INGEST_BYTES},
+    service::Service,
+};
+
+#[post("/{ns_name}/{queue_name}")]
+async fn ingest_messages(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    payload: web::Payload,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<IngestResponse>, Error> {
+    let (namespace, name) = &*path;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let queue_id = service
+        .get_queue_id(namespace, name, service.db())
+        .await?
+        .ok_or_else(|| Error::queue_not_found(name, namespace))?;
+
+    let data = payload
+        .to_bytes_limited(MAX_INGEST_BYTES)
+        .await
+        .map_err(|_| Error::PayloadTooLarge)?
+        .map_err(|e| Error::invalid_parameter(format!("request body: {e}")))?;
+
+    let messages = ingest::parse(req.content_type(), &data)?;
+    let message_ids = service.ingest_messages(queue_id, messages).await?;
+
+    Ok(web::Json(IngestResponse { message_ids }))
+}
+
+pub fn service() -> Scope {
+    web::scope("/ingest").service(ingest_messages)
+}
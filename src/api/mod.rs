@@ -1,6 +1,38 @@
+pub mod ack;
 pub mod admin;
 pub mod auth;
+pub mod certificates;
+pub mod consumers;
 pub mod data;
+pub mod ingest;
 pub mod namespace;
+pub mod outbox;
+pub mod overview;
 pub mod queue;
+pub mod scim;
 pub mod tokens;
+pub mod trace;
+pub mod version;
+
+use actix_web::{web, Scope};
+
+use crate::Components;
+
+/// NerveMQ's native API, for what doesn't belong to a single resource.
+///
+/// # Arguments
+/// * `components` - Components to serve endpoints of
+pub fn service(components: &Components) -> Scope {
+    let mut scope = web::scope("/api").service(version::get_version);
+    if components.metrics {
+        scope = scope.service(overview::get_overview);
+    }
+    if components.sqs {
+        scope = scope
+            .service(ack::ack_messages)
+            .service(consumers::list_consumers)
+            .service(consumers::heartbeat_consumer)
+            .service(consumers::deregister_consumer);
+    }
+    scope
+}
@@ -1,17 +1,22 @@
 use actix_identity::Identity;
-use actix_web::{web, Responder, Scope};
+use actix_web::{middleware::from_fn, web, Responder, Scope};
 use serde::{Deserialize, Serialize};
 
-use crate::service::Service;
+use crate::{
+    auth::credential::AuthorizedNamespace, caching::conditional_get, error::Error, service::Service,
+};
 
 async fn list_namespaces(
     service: web::Data<Service>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
-    let data = match service.list_namespaces(identity).await {
-        Ok(data) => data,
-        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
-    };
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let data = service
+        .list_namespaces(identity)
+        .await?
+        .into_iter()
+        .filter(|namespace| AuthorizedNamespace::allows(authorized.as_ref(), &namespace.name))
+        .collect::<Vec<_>>();
 
     Ok(web::Json(data))
 }
@@ -25,11 +30,8 @@ async fn create_namespace(
     service: web::Data<Service>,
     path: web::Path<String>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
-    let id = match service.create_namespace(&*path, identity).await {
-        Ok(id) => id,
-        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
-    };
+) -> Result<impl Responder, Error> {
+    let id = service.create_namespace(&path, identity).await?;
 
     Ok(web::Json(CreateNamespaceResponse { id }))
 }
@@ -38,17 +40,19 @@ async fn delete_namespace(
     service: web::Data<Service>,
     path: web::Path<String>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
-    if let Err(e) = service.delete_namespace(&*path, identity).await {
-        return Err(actix_web::error::ErrorInternalServerError(e));
-    }
+) -> Result<impl Responder, Error> {
+    service.delete_namespace(&path, identity).await?;
 
     Ok("OK")
 }
 
 pub fn service() -> Scope {
     web::scope("/ns")
-        .route("", web::get().to(list_namespaces))
+        .service(
+            web::resource("")
+                .wrap(from_fn(conditional_get))
+                .get(list_namespaces),
+        )
         .service(
             web::resource("/{ns_name}")
                 .post(create_namespace)
@@ -0,0 +1,41 @@
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder, Scope};
+
+use crate::{error::Error, outbox::CreateOutboxSourceRequest, service::Service};
+
+async fn list_outbox_sources(service: web::Data<Service>) -> Result<impl Responder, Error> {
+    let sources = service.list_outbox_sources().await?;
+
+    Ok(web::Json(sources))
+}
+
+async fn create_outbox_source(
+    service: web::Data<Service>,
+    data: web::Json<CreateOutboxSourceRequest>,
+    identity: Identity,
+) -> Result<impl Responder, Error> {
+    service
+        .create_outbox_source(data.into_inner(), identity)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+async fn delete_outbox_source(
+    service: web::Data<Service>,
+    path: web::Path<String>,
+) -> Result<impl Responder, Error> {
+    service.delete_outbox_source(&path).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+pub fn service() -> Scope {
+    web::scope("/outbox")
+        .service(
+            web::resource("")
+                .get(list_outbox_sources)
+                .post(create_outbox_source),
+        )
+        .service(web::resource("/{name}").delete(delete_outbox_source))
+}
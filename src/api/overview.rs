@@ -0,0 +1,14 @@
+use actix_identity::Identity;
+use actix_web::{get, web};
+
+use crate::{error::Error, overview::Overview, service::Service};
+
+#[get("/overview")]
+pub(super) async fn get_overview(
+    service: web::Data<Service>,
+    identity: Identity,
+) -> Result<web::Json<Overview>, Error> {
+    let overview = service.overview(identity).await?;
+
+    Ok(web::Json(overview))
+}
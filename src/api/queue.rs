@@ -1,18 +1,23 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use actix_identity::Identity;
-use actix_web::{
-    delete,
-    error::{ErrorInternalServerError, ErrorUnauthorized},
-    get, post, web, HttpResponse, Responder, Scope,
-};
+use actix_web::{delete, get, middleware::from_fn, post, web, HttpResponse, Responder, Scope};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
 use crate::{
+    api::auth::Role,
+    auth::credential::AuthorizedNamespace,
+    caching::conditional_get,
+    consumer_group::ConsumerGroup,
     error::Error,
+    history::{HistoryEntry, HistoryMode},
+    push::{PushSubscription, PushSubscriptionRequest},
     queue::Queue,
+    sample::{MessageSample, DEFAULT_SAMPLE_SIZE},
     service::{MessageDetails, QueueConfig, Service},
+    stream::{self, StreamMode, StreamQuery},
+    websocket::{self, ConsumeQuery},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -20,6 +25,7 @@ pub struct ListQueuesResponse {
     queues: Vec<Queue>,
 }
 
+#[allow(unused)]
 #[derive(Debug, Clone, Serialize, FromRow)]
 pub struct QueueStats {
     pub pending: u64,
@@ -27,28 +33,41 @@ pub struct QueueStats {
     pub failed: u64,
 }
 
-#[get("")]
+#[get("", wrap = "from_fn(conditional_get)")]
 async fn list_all_queues(
     service: web::Data<Service>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
-    let queues = match service.list_all_queues(identity).await {
-        Ok(q) => q,
-        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
-    };
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let queues = service
+        .list_all_queues(identity)
+        .await?
+        .into_iter()
+        .filter(|queue| AuthorizedNamespace::allows(authorized.as_ref(), &queue.ns))
+        .collect();
 
     Ok(web::Json(ListQueuesResponse { queues }))
 }
 
-#[get("/{ns_name}")]
+#[get("/{ns_name}", wrap = "from_fn(conditional_get)")]
 async fn list_ns_queues(
     service: web::Data<Service>,
     path: web::Path<String>,
-) -> actix_web::Result<impl Responder> {
-    let queues = match service.list_queues_for_namespace(&*path).await {
-        Ok(q) => q,
-        Err(e) => return Err(actix_web::error::ErrorInternalServerError(e)),
-    };
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    AuthorizedNamespace::check(authorized.as_ref(), &path, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(&path, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(path.as_str()))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let queues = service.list_queues_for_namespace(&path).await?;
 
     Ok(web::Json(ListQueuesResponse { queues }))
 }
@@ -58,11 +77,13 @@ async fn delete_queue(
     service: web::Data<Service>,
     path: web::Path<(String, String)>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
     let (namespace, name) = &*path;
-    if let Err(e) = service.delete_queue(namespace, name, identity).await {
-        return Err(actix_web::error::ErrorInternalServerError(e));
-    }
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    service.delete_queue(namespace, name, identity).await?;
 
     Ok("OK")
 }
@@ -79,35 +100,44 @@ async fn create_queue(
     path: web::Path<(String, String)>,
     data: web::Json<CreateQueueRequest>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
     let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
     let data = data.into_inner();
 
-    match service
+    service
         .create_queue(namespace, name, data.attributes, data.tags, identity)
-        .await
-    {
-        Ok(_) => {}
-        Err(Error::Unauthorized) => return Err(ErrorUnauthorized("Unauthorized")),
-        Err(e) => return Err(ErrorInternalServerError(e)),
-    }
+        .await?;
 
     Ok(actix_web::HttpResponse::Ok())
 }
 
-#[get("/{ns_name}/{queue_name}")]
+#[get("/{ns_name}/{queue_name}", wrap = "from_fn(conditional_get)")]
 async fn queue_stats(
     service: web::Data<Service>,
     path: web::Path<(String, String)>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
     let (namespace, name) = &*path;
 
-    match service.queue_statistics(identity, namespace, name).await {
-        Ok(stats) => Ok(web::Json(stats)),
-        Err(Error::Unauthorized) => Err(ErrorUnauthorized("Unauthorized")),
-        Err(e) => Err(ErrorInternalServerError(e)),
-    }
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let stats = service.queue_statistics(identity, namespace, name).await?;
+
+    Ok(web::Json(stats))
 }
 
 #[get("/{ns_name}/{queue_name}/messages")]
@@ -115,27 +145,24 @@ async fn list_messages(
     service: web::Data<Service>,
     path: web::Path<(String, String)>,
     identity: Identity,
-) -> actix_web::Result<web::Json<Vec<MessageDetails>>> {
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Vec<MessageDetails>>, Error> {
     let (namespace, name) = &*path;
 
-    let ns_id = match service.get_namespace_id(namespace, service.db()).await {
-        Ok(Some(id)) => id,
-        Ok(None) => return Err(ErrorInternalServerError("Namespace not found")),
-        Err(e) => return Err(ErrorInternalServerError(e)),
-    };
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
 
-    match service
+    service
         .check_user_access(&identity, ns_id, service.db())
-        .await
-    {
-        Ok(_) => {}
-        Err(e) => return Err(ErrorUnauthorized(e)),
-    }
+        .await?;
+
+    let messages = service.list_messages(namespace, name).await?;
 
-    match service.list_messages(namespace, name).await {
-        Ok(messages) => Ok(web::Json(messages)),
-        Err(e) => Err(ErrorInternalServerError(e)),
-    }
+    Ok(web::Json(messages))
 }
 
 #[get("/{ns_name}/{queue_name}/config")]
@@ -143,9 +170,12 @@ async fn get_queue_config(
     service: web::Data<Service>,
     path: web::Path<(String, String)>,
     identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
 ) -> Result<web::Json<QueueConfig>, Error> {
     let (namespace, name) = &*path;
 
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
     let ns_id = match service.get_namespace_id(namespace, service.db()).await {
         Ok(Some(id)) => id,
         Ok(None) => return Err(Error::namespace_not_found(namespace)),
@@ -170,6 +200,10 @@ async fn get_queue_config(
 struct UpdateQueueConfigRequest {
     max_retries: u64,
     dead_letter_queue: Option<String>,
+    /// Left unchanged if not provided
+    history: Option<HistoryMode>,
+    /// Left unchanged if not provided
+    history_retention: Option<u64>,
 }
 
 #[post("/{ns_name}/{queue_name}/config")]
@@ -178,9 +212,12 @@ async fn update_queue_config(
     path: web::Path<(String, String)>,
     updates: web::Json<UpdateQueueConfigRequest>,
     identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
 ) -> Result<impl Responder, Error> {
     let (namespace, name) = &*path;
 
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
     let ns_id = match service.get_namespace_id(namespace, service.db()).await {
         Ok(Some(id)) => id,
         Ok(None) => return Err(Error::namespace_not_found(namespace)),
@@ -204,10 +241,16 @@ async fn update_queue_config(
         None => None,
     };
 
+    let current = service.get_queue_configuration(queue_id).await?;
+
     let new_config = QueueConfig {
         queue: queue_id,
         max_retries: updates.max_retries,
         dead_letter_queue,
+        history: updates.history.unwrap_or(current.history),
+        history_retention: updates
+            .history_retention
+            .unwrap_or(current.history_retention),
     };
 
     service
@@ -217,6 +260,373 @@ async fn update_queue_config(
     Ok(HttpResponse::Ok())
 }
 
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    limit: Option<u64>,
+    before: Option<u64>,
+}
+
+#[get("/{ns_name}/{queue_name}/history")]
+async fn list_message_history(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HistoryQuery>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Vec<HistoryEntry>>, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let history = service
+        .list_message_history(namespace, name, query.limit.unwrap_or(100), query.before)
+        .await?;
+
+    Ok(web::Json(history))
+}
+
+#[derive(Debug, Deserialize)]
+struct SampleQuery {
+    count: Option<u64>,
+}
+
+#[get("/{ns_name}/{queue_name}/sample")]
+async fn sample_messages(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    query: web::Query<SampleQuery>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Vec<MessageSample>>, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let samples = service
+        .sample_messages(namespace, name, query.count.unwrap_or(DEFAULT_SAMPLE_SIZE))
+        .await?;
+
+    Ok(web::Json(samples))
+}
+
+#[get("/{ns_name}/{queue_name}/groups")]
+async fn list_consumer_groups(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Vec<ConsumerGroup>>, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let groups = service.list_consumer_groups(namespace, name).await?;
+
+    Ok(web::Json(groups))
+}
+
+#[post("/{ns_name}/{queue_name}/groups/{group}")]
+async fn create_consumer_group(
+    service: web::Data<Service>,
+    path: web::Path<(String, String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let (namespace, name, group) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    service
+        .create_consumer_group(namespace, name, group)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[delete("/{ns_name}/{queue_name}/groups/{group}")]
+async fn delete_consumer_group(
+    service: web::Data<Service>,
+    path: web::Path<(String, String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let (namespace, name, group) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    service
+        .delete_consumer_group(namespace, name, group)
+        .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Request to extend the visibility timeout of received messages.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendVisibilityRequest {
+    pub receipt_handles: Vec<String>,
+    /// Seconds from now the messages stay hidden
+    pub visibility_timeout: u64,
+    /// Consumer group the messages were received by, if any
+    #[serde(default)]
+    pub consumer_group: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendVisibilityResponse {
+    /// Unix timestamp at which the messages become visible again
+    pub visible_at: u64,
+}
+
+/// Extends the visibility timeout of many in-flight messages at once, as a heartbeat for workers
+/// that process messages concurrently. Unlike ChangeMessageVisibilityBatch, either every message
+/// is extended or none is.
+#[post("/{ns_name}/{queue_name}/visibility")]
+async fn extend_visibility(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+    request: web::Json<ExtendVisibilityRequest>,
+) -> Result<web::Json<ExtendVisibilityResponse>, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let message_ids = service
+        .resolve_receipt_handles(namespace, name, &request.receipt_handles)
+        .await?
+        .into_iter()
+        .zip(&request.receipt_handles)
+        .map(|(message_id, handle)| {
+            message_id.ok_or_else(|| Error::ReceiptHandleIsInvalid {
+                handle: handle.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let visible_at = service
+        .extend_visibility(
+            namespace,
+            name,
+            &message_ids,
+            Duration::from_secs(request.visibility_timeout),
+            request.consumer_group.as_deref(),
+            identity,
+        )
+        .await?;
+
+    Ok(web::Json(ExtendVisibilityResponse { visible_at }))
+}
+
+#[get("/{ns_name}/{queue_name}/push")]
+async fn get_push_subscription(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<PushSubscription>, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let subscription = service.get_push_subscription(namespace, name).await?;
+
+    Ok(web::Json(subscription))
+}
+
+#[post("/{ns_name}/{queue_name}/push")]
+async fn set_push_subscription(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    data: web::Json<PushSubscriptionRequest>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<PushSubscription>, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+    // The server calls the URL and reports how that went, so it must not be pointed at the
+    // internal network by anyone but an admin.
+    service.check_user_role(identity, Role::Admin).await?;
+
+    let subscription = service
+        .set_push_subscription(namespace, name, data.into_inner())
+        .await?;
+
+    Ok(web::Json(subscription))
+}
+
+#[delete("/{ns_name}/{queue_name}/push")]
+async fn delete_push_subscription(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let (namespace, name) = &*path;
+
+    AuthorizedNamespace::check(authorized.as_ref(), namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    service.delete_push_subscription(namespace, name).await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+#[get("/{ns_name}/{queue_name}/stream")]
+async fn stream_messages(
+    service: web::Data<Service>,
+    path: web::Path<(String, String)>,
+    query: web::Query<StreamQuery>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<HttpResponse, Error> {
+    let (namespace, name) = path.into_inner();
+
+    AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(&namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(&namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    let queue_id = service
+        .get_queue_id(&namespace, &name, service.db())
+        .await?
+        .ok_or_else(|| Error::queue_not_found(&name, &namespace))?;
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keeps proxies like nginx from buffering the events.
+        .insert_header(("X-Accel-Buffering", "no"));
+
+    let service = service.into_inner().as_ref().clone();
+    Ok(match query.mode {
+        StreamMode::Receive => response.streaming(stream::receive(
+            service,
+            namespace,
+            name,
+            query.into_inner(),
+        )),
+        StreamMode::Tail => response.streaming(stream::tail(service, queue_id)),
+    })
+}
+
+#[get("/{ns_name}/{queue_name}/ws")]
+async fn consume_messages(
+    service: web::Data<Service>,
+    req: actix_web::HttpRequest,
+    payload: web::Payload,
+    path: web::Path<(String, String)>,
+    query: web::Query<ConsumeQuery>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<HttpResponse, Error> {
+    let (namespace, name) = path.into_inner();
+
+    AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+
+    let ns_id = service
+        .get_namespace_id(&namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(&namespace))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    websocket::consume(
+        service.into_inner().as_ref().clone(),
+        &req,
+        payload,
+        namespace,
+        name,
+        query.into_inner(),
+        &identity,
+    )
+    .await
+}
+
 pub fn service() -> Scope {
     web::scope("/queue")
         .service(list_all_queues)
@@ -227,4 +637,15 @@ pub fn service() -> Scope {
         .service(list_messages)
         .service(get_queue_config)
         .service(update_queue_config)
+        .service(list_message_history)
+        .service(sample_messages)
+        .service(list_consumer_groups)
+        .service(create_consumer_group)
+        .service(delete_consumer_group)
+        .service(extend_visibility)
+        .service(get_push_subscription)
+        .service(set_push_subscription)
+        .service(delete_push_subscription)
+        .service(stream_messages)
+        .service(consume_messages)
 }
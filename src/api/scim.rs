@@ -0,0 +1,201 @@
+use actix_web::{
+    body::MessageBody,
+    delete,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::{header, StatusCode},
+    middleware::Next,
+    patch, post, put,
+    web::{self, Data, JsonConfig, QueryConfig},
+    HttpResponse, Scope,
+};
+use serde::Serialize;
+
+use crate::{
+    scim::{
+        self, ListQuery, PatchRequest, ScimError, ScimGroup, ScimUser, SCIM_CONTENT_TYPE, SCIM_PATH,
+    },
+    service::Service,
+};
+
+fn scim_response(status: StatusCode, body: impl Serialize) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(SCIM_CONTENT_TYPE)
+        .json(body)
+}
+
+fn ok(body: impl Serialize) -> HttpResponse {
+    scim_response(StatusCode::OK, body)
+}
+
+#[get("/ServiceProviderConfig")]
+pub async fn service_provider_config() -> HttpResponse {
+    ok(scim::service_provider_config())
+}
+
+#[get("/Users")]
+pub async fn list_users(
+    service: web::Data<Service>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(scim::list_users(&service, &query).await?))
+}
+
+#[post("/Users")]
+pub async fn create_user(
+    service: web::Data<Service>,
+    user: web::Json<ScimUser>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(scim_response(
+        StatusCode::CREATED,
+        scim::create_user(&service, user.into_inner()).await?,
+    ))
+}
+
+#[get("/Users/{id}")]
+pub async fn get_user(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(scim::get_user(&service, &id).await?))
+}
+
+#[put("/Users/{id}")]
+pub async fn replace_user(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+    user: web::Json<ScimUser>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(
+        scim::replace_user(&service, &id, user.into_inner()).await?
+    ))
+}
+
+#[patch("/Users/{id}")]
+pub async fn patch_user(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+    patch: web::Json<PatchRequest>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(
+        scim::patch_user(&service, &id, patch.into_inner()).await?
+    ))
+}
+
+#[delete("/Users/{id}")]
+pub async fn delete_user(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ScimError> {
+    scim::delete_user(&service, &id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/Groups")]
+pub async fn list_groups(
+    service: web::Data<Service>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(scim::list_groups(&service, &query).await?))
+}
+
+#[post("/Groups")]
+pub async fn create_group(
+    service: web::Data<Service>,
+    group: web::Json<ScimGroup>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(scim_response(
+        StatusCode::CREATED,
+        scim::create_group(&service, group.into_inner()).await?,
+    ))
+}
+
+#[get("/Groups/{id}")]
+pub async fn get_group(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(scim::get_group(&service, &id).await?))
+}
+
+#[put("/Groups/{id}")]
+pub async fn replace_group(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+    group: web::Json<ScimGroup>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(
+        scim::replace_group(&service, &id, group.into_inner()).await?
+    ))
+}
+
+#[patch("/Groups/{id}")]
+pub async fn patch_group(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+    patch: web::Json<PatchRequest>,
+) -> Result<HttpResponse, ScimError> {
+    Ok(ok(
+        scim::patch_group(&service, &id, patch.into_inner()).await?
+    ))
+}
+
+#[delete("/Groups/{id}")]
+pub async fn delete_group(
+    service: web::Data<Service>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ScimError> {
+    scim::delete_group(&service, &id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Middleware that only lets requests with the SCIM bearer token through. SCIM requests skip the
+/// regular authentication, see [`crate::auth::middleware::authentication`].
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let service = req
+        .app_data::<Data<Service>>()
+        .expect("Service not found. This is a bug.");
+
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| service.check_scim_token(token.trim()));
+    if !authorized {
+        return Err(ScimError::unauthorized().into());
+    }
+
+    next.call(req).await
+}
+
+pub fn service() -> Scope {
+    // Malformed request data gets a SCIM error like every other error of the API.
+    let json_cfg = JsonConfig::default()
+        .content_type_required(false)
+        .error_handler(|e, _| ScimError::invalid_syntax(e.to_string()).into());
+    let query_cfg = QueryConfig::default()
+        .error_handler(|e, _| ScimError::invalid_syntax(e.to_string()).into());
+
+    web::scope(SCIM_PATH)
+        .app_data(json_cfg)
+        .app_data(query_cfg)
+        .service(service_provider_config)
+        .service(list_users)
+        .service(create_user)
+        .service(get_user)
+        .service(replace_user)
+        .service(patch_user)
+        .service(delete_user)
+        .service(list_groups)
+        .service(create_group)
+        .service(get_group)
+        .service(replace_group)
+        .service(patch_group)
+        .service(delete_group)
+}
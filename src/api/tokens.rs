@@ -1,20 +1,22 @@
 use actix_identity::Identity;
 use actix_web::{
-    delete,
-    error::{ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized},
-    get, post,
+    delete, get, post, put,
     web::{self, Json},
     HttpResponse, Responder, Scope,
 };
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
 
-use crate::{error::Error, service::Service};
+use crate::{
+    auth::credential::AuthorizedNamespace, error::Error, service::Service, token_usage::TokenInfo,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateTokenRequest {
     pub name: String,
     pub namespace: String,
+    #[serde(default, rename = "allowedNetworks")]
+    pub allowed_networks: Option<Vec<IpNet>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,13 +37,28 @@ pub async fn create_token(
     data: web::Json<CreateTokenRequest>,
     service: web::Data<Service>,
     identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
 ) -> Result<Json<CreateTokenResponse>, Error> {
-    let CreateTokenRequest { name, namespace } = data.into_inner();
+    let CreateTokenRequest {
+        name,
+        namespace,
+        allowed_networks,
+    } = data.into_inner();
 
-    service
-        .create_token(name, namespace, identity)
-        .await
-        .map(Json)
+    // A token can't be used to get a token for another namespace.
+    AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+
+    let owner = identity.id()?;
+
+    let token = service.create_token(name, namespace, identity).await?;
+
+    if allowed_networks.is_some() {
+        service
+            .set_token_allowed_networks(&owner, &token.name, allowed_networks)
+            .await?;
+    }
+
+    Ok(Json(token))
 }
 
 #[delete("")]
@@ -49,7 +66,12 @@ pub async fn delete_token(
     service: web::Data<Service>,
     data: web::Json<DeleteTokenRequest>,
     identity: Identity,
-) -> actix_web::Result<impl Responder> {
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    if let Some(namespace) = service.token_namespace(&identity.id()?, &data.name).await? {
+        AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+    }
+
     let res = sqlx::query(
         "
         DELETE FROM api_keys
@@ -60,48 +82,55 @@ pub async fn delete_token(
     ",
     )
     .bind(&data.name)
-    .bind(&identity.id().map_err(ErrorUnauthorized)?)
+    .bind(&identity.id()?)
     .execute(service.db())
-    .await
-    .map_err(|e| ErrorInternalServerError(e))?;
+    .await?;
 
     if res.rows_affected() == 0 {
-        return Err(ErrorNotFound(format!("No such api key {}", data.name)));
+        return Err(Error::not_found(format!("api key {}", data.name)));
     }
 
     Ok(HttpResponse::Ok())
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
-struct ApiKey {
-    name: String,
-    namespace: String,
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowedNetworks {
+    allowed_networks: Option<Vec<IpNet>>,
+}
+
+#[put("/{name}/allowed-networks")]
+pub async fn set_allowed_networks(
+    service: web::Data<Service>,
+    name: web::Path<String>,
+    data: web::Json<AllowedNetworks>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<impl Responder, Error> {
+    let owner = identity.id()?;
+    if let Some(namespace) = service.token_namespace(&owner, &name).await? {
+        AuthorizedNamespace::check(authorized.as_ref(), &namespace, &service)?;
+    }
+
+    service
+        .set_token_allowed_networks(&owner, &name, data.into_inner().allowed_networks)
+        .await?;
+
+    Ok(HttpResponse::Ok())
 }
 
 #[get("")]
 pub async fn list_tokens(
     service: web::Data<Service>,
     identity: Identity,
-) -> actix_web::Result<web::Json<Vec<ApiKey>>> {
-    let email = match identity.id() {
-        Ok(email) => email,
-        Err(err) => {
-            return Err(ErrorUnauthorized(err));
-        }
-    };
-
-    let tokens = sqlx::query_as(
-        "
-        SELECT k.*, ns.name as namespace FROM users u
-        INNER JOIN api_keys k ON u.id = k.user
-        JOIN namespaces ns ON k.ns = ns.id
-        WHERE u.email = $1
-    ",
-    )
-    .bind(&email)
-    .fetch_all(service.db())
-    .await
-    .map_err(ErrorInternalServerError)?;
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Vec<TokenInfo>>, Error> {
+    let tokens = service
+        .list_tokens(identity)
+        .await?
+        .into_iter()
+        .filter(|token| AuthorizedNamespace::allows(authorized.as_ref(), &token.namespace))
+        .collect();
 
     Ok(Json(tokens))
 }
@@ -111,4 +140,5 @@ pub fn service() -> Scope {
         .service(create_token)
         .service(delete_token)
         .service(list_tokens)
+        .service(set_allowed_networks)
 }
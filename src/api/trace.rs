@@ -0,0 +1,28 @@
+use actix_identity::Identity;
+use actix_web::{get, web, Scope};
+
+use crate::{auth::credential::AuthorizedNamespace, error::Error, service::Service, trace::Trace};
+
+#[get("/{trace_id}")]
+async fn get_trace(
+    service: web::Data<Service>,
+    path: web::Path<String>,
+    identity: Identity,
+    authorized: Option<AuthorizedNamespace>,
+) -> Result<web::Json<Trace>, Error> {
+    let mut trace = service.get_message_trace(&path, identity).await?;
+
+    // A trace may cross namespaces, of which a token only sees its own.
+    trace
+        .events
+        .retain(|event| AuthorizedNamespace::allows(authorized.as_ref(), &event.namespace));
+    if trace.events.is_empty() {
+        return Err(Error::not_found(format!("trace {}", path.as_str())));
+    }
+
+    Ok(web::Json(trace))
+}
+
+pub fn service() -> Scope {
+    web::scope("/trace").service(get_trace)
+}
@@ -0,0 +1,8 @@
+use actix_web::{get, web};
+
+use crate::version::VersionInfo;
+
+#[get("/version")]
+pub(super) async fn get_version() -> web::Json<VersionInfo> {
+    web::Json(VersionInfo::current())
+}
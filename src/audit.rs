@@ -0,0 +1,236 @@
+//! Audit log of mutating operations.
+//!
+//! Every request that changes something, such as creating or purging a queue, deleting messages or
+//! granting permissions, is recorded in the `audit_log` table once it has been handled: who made
+//! it, the user and the API key it was authenticated with, what it did, what it did it to and the
+//! status it was answered with. Admins can search the log with `GET /admin/audit`.
+//!
+//! Requests are recorded by [`record_requests`], a middleware that sees every request, so new
+//! endpoints are covered without having to remember to log them. Reads are not recorded, and
+//! neither is message traffic (sending, receiving and changing the visibility of messages), which
+//! would flood the log. Requests that aren't authenticated are not recorded either, as they are
+//! turned away before doing anything.
+//!
+//! Entries are kept for the configured
+//! [`audit_retention`](crate::config::Config::audit_retention) period.
+
+use actix_identity::IdentityExt as _;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{Method as HttpMethod, StatusCode},
+    middleware::Next,
+    web::Data,
+    HttpMessage as _, HttpRequest,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{
+    auth::credential::{AuthenticatedKey, AuthorizedNamespace},
+    service::Service,
+    sqs::method::Method,
+    tasks,
+};
+
+/// Number of entries returned by a search if no limit is given.
+pub const DEFAULT_AUDIT_PAGE_SIZE: u64 = 100;
+
+/// Maximum number of entries returned by a single search.
+pub const MAX_AUDIT_PAGE_SIZE: u64 = 1000;
+
+/// Actor of the requests made with the SCIM token, which belongs to no user.
+pub const SCIM_ACTOR: &str = "scim";
+
+/// What a request acted on, when that isn't in its path, such as the queue of an SQS request.
+///
+/// Included in request-local extension data by handlers.
+#[derive(Debug, Clone)]
+pub struct AuditResource(pub String);
+
+/// A recorded request.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: u64,
+    /// Unix timestamp of when the request was handled
+    pub at: u64,
+    /// Email of the user who made the request, or [`SCIM_ACTOR`]
+    pub actor: String,
+    /// Access key ID of the API key the request was authenticated with, if any
+    pub api_key: Option<String>,
+    /// Namespace the request was made in, if known
+    pub namespace: Option<String>,
+    /// SQS method, or HTTP method and route of the management API, e.g.
+    /// `DELETE /admin/users/{email}`
+    pub action: String,
+    /// Queue URL path, queue name or request path the action was applied to
+    pub resource: String,
+    /// HTTP status the request was answered with
+    pub status: u16,
+}
+
+/// A request to record, see [`Service::record_audit_entry`].
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub api_key: Option<String>,
+    pub namespace: Option<String>,
+    pub action: String,
+    pub resource: String,
+    pub status: u16,
+}
+
+/// Filters of an audit log search. Entries are returned newest first.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub api_key: Option<String>,
+    pub namespace: Option<String>,
+    pub action: Option<String>,
+    /// Only entries at or after this Unix timestamp
+    pub since: Option<u64>,
+    /// Only entries before this Unix timestamp
+    pub until: Option<u64>,
+    /// Only entries with an ID below this one, to continue from the last entry of a page
+    pub before: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+/// Whether an SQS method is recorded: every method that changes a queue or deletes messages.
+fn is_audited(method: Method) -> bool {
+    match method {
+        Method::CancelMessageMoveTask
+        | Method::CreateQueue
+        | Method::DeleteMessage
+        | Method::DeleteMessageBatch
+        | Method::DeleteQueue
+        | Method::PurgeQueue
+        | Method::SetQueueAttributes
+        | Method::StartMessageMoveTask
+        | Method::TagQueue
+        | Method::UntagQueue => true,
+        Method::ChangeMessageVisibility
+        | Method::GetQueueAttributes
+        | Method::GetQueueUrl
+        | Method::ListDeadLetterSourceQueues
+        | Method::ListMessageMoveTasks
+        | Method::ListQueues
+        | Method::ListQueueTags
+        | Method::ReceiveMessage
+        | Method::SendMessage
+        | Method::SendMessageBatch => false,
+    }
+}
+
+/// Describes a handled request as an entry, if it is to be recorded.
+///
+/// # Arguments
+/// * `req` - The request, with the extensions its handling added
+/// * `actor` - Email of the user the request was made by, before or after handling it, as
+///   logging in and out changes it
+/// * `status` - Status of the response
+fn describe(req: &HttpRequest, actor: Option<String>, status: StatusCode) -> Option<NewAuditEntry> {
+    let extensions = req.extensions();
+
+    let action = match extensions.get::<Method>() {
+        Some(method) if !is_audited(*method) => return None,
+        Some(method) => method.as_ref().to_owned(),
+        None if matches!(
+            *req.method(),
+            HttpMethod::GET | HttpMethod::HEAD | HttpMethod::OPTIONS
+        ) =>
+        {
+            return None
+        }
+        None => format!(
+            "{} {}",
+            req.method(),
+            req.match_pattern().as_deref().unwrap_or(req.path())
+        ),
+    };
+
+    let actor = match actor {
+        Some(actor) => actor,
+        None if req.path().starts_with(crate::scim::SCIM_PATH)
+            && status != StatusCode::UNAUTHORIZED =>
+        {
+            SCIM_ACTOR.to_owned()
+        }
+        None => return None,
+    };
+
+    let namespace = extensions
+        .get::<AuthorizedNamespace>()
+        .map(|namespace| namespace.0.clone())
+        .or_else(|| {
+            ["namespace", "ns_name"]
+                .into_iter()
+                .find_map(|name| req.match_info().get(name))
+                .map(str::to_owned)
+        });
+
+    Some(NewAuditEntry {
+        actor,
+        api_key: extensions
+            .get::<AuthenticatedKey>()
+            .map(|key| key.0.clone()),
+        namespace,
+        action,
+        resource: extensions
+            .get::<AuditResource>()
+            .map(|resource| resource.0.clone())
+            .unwrap_or_else(|| req.path().to_owned()),
+        status: status.as_u16(),
+    })
+}
+
+/// Records mutating requests in the audit log once they have been handled.
+///
+/// Has to be wrapped inside the authentication and identity middleware, to know who made the
+/// request. Requests turned away by middleware, such as rate-limited ones, come back without
+/// their request and aren't recorded.
+pub async fn record_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let service = req.app_data::<Data<Service>>().cloned();
+    let actor = req
+        .get_identity()
+        .ok()
+        .and_then(|identity| identity.id().ok());
+
+    let res = next.call(req).await?;
+
+    let Some(service) = service else {
+        return Ok(res);
+    };
+
+    let actor = res
+        .request()
+        .get_identity()
+        .ok()
+        .and_then(|identity| identity.id().ok())
+        .or(actor);
+
+    if let Some(entry) = describe(res.request(), actor, res.status()) {
+        if let Err(e) = service.record_audit_entry(entry).await {
+            tracing::error!("Failed to record audit log entry: {e}");
+        }
+    }
+
+    Ok(res)
+}
+
+/// Prunes expired audit log entries every prune interval until the process exits.
+pub fn spawn(service: &Service) {
+    let period = service.config().tasks().prune;
+    tasks::spawn_periodic(service, "audit", period, |service| async move {
+        let count = service.prune_audit_log().await?;
+        if count > 0 {
+            tracing::debug!(count, "Pruned audit log");
+        }
+        Ok(())
+    });
+}
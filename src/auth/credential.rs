@@ -2,7 +2,7 @@ use actix_web::{FromRequest, HttpMessage};
 use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::{error::Error, service::Service};
 
 /// Namespace authorized for the request.
 ///
@@ -25,7 +25,36 @@ impl FromRequest for AuthorizedNamespace {
     }
 }
 
+impl AuthorizedNamespace {
+    /// Checks that a request authenticated for a single namespace, as with an API token, only
+    /// works on that namespace. Requests authenticated by session aren't limited to one.
+    pub fn check(
+        authorized: Option<&Self>,
+        namespace: &str,
+        service: &Service,
+    ) -> Result<(), Error> {
+        match authorized {
+            Some(authorized) if authorized.0 != namespace => {
+                Err(service.namespace_access_denied(namespace))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a request authenticated for `authorized` may see resources of `namespace`.
+    pub fn allows(authorized: Option<&Self>, namespace: &str) -> bool {
+        authorized.is_none_or(|authorized| authorized.0 == namespace)
+    }
+}
+
+/// ID of the API key the request was authenticated with.
+///
+/// Included in request-local extension data once authenticated.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedKey(pub String);
+
 /// Request to create a new API key.
+#[allow(unused)]
 #[derive(Serialize, Deserialize)]
 pub struct ApiKeyRequest {
     api_key: String,
@@ -51,4 +80,45 @@ impl ApiKey {
             long_token,
         }
     }
+
+    /// Parses an API key in its `nervemq_<short>_<long>` form.
+    ///
+    /// # Errors
+    /// Returns an error if the key is malformed or has the wrong prefix
+    pub fn parse(key: &str) -> Result<Self, Error> {
+        let (prefix, short, long) = (super::header::prefixed_token() - pom::utf8::end())
+            .parse_str(key)
+            .map_err(|_| Error::invalid_parameter("API key is malformed"))?;
+
+        if prefix != API_KEY_PREFIX {
+            return Err(Error::invalid_parameter(format!(
+                "API key must start with {API_KEY_PREFIX}_"
+            )));
+        }
+
+        Ok(Self::new(short.to_owned(), SecretString::from(long)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let key = ApiKey::parse("nervemq_abc123_XYZ789").unwrap();
+        assert_eq!(key.short_token, "abc123");
+        assert_eq!(key.long_token.expose_secret(), "XYZ789");
+
+        for invalid in [
+            "",
+            "nervemq_abc123",
+            "other_abc123_XYZ789",
+            "nervemq_abc_X-Z",
+        ] {
+            assert!(ApiKey::parse(invalid).is_err(), "{invalid}");
+        }
+    }
 }
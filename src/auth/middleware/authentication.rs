@@ -1,7 +1,8 @@
 //! API Key authentication middleware for Actix-web.
 //!
-//! Provides middleware that authenticates requests using either NerveMQ API keys
-//! or AWS SigV4 signatures. Successful authentication creates an Identity session
+//! Provides middleware that authenticates requests using NerveMQ API keys, requests signed
+//! with them, AWS SigV4 signatures or JWT bearer tokens, or without an Authorization header, the client certificate
+//! of the connection. Successful authentication creates an Identity session
 //! and injects the authorized namespace into request extensions.
 
 use std::future::{Future, Ready};
@@ -11,15 +12,20 @@ use std::task::{Context, Poll};
 
 use actix_identity::Identity;
 use actix_web::dev::{Service, Transform};
-use actix_web::error::{ErrorInternalServerError, ErrorUnauthorized};
 use actix_web::http::header::{self};
 use actix_web::web::Data;
 use actix_web::HttpMessage;
 use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error};
 
+use crate::auth::credential::AuthenticatedKey;
 use crate::auth::header::AuthHeader;
-use crate::auth::protocols::nervemq::authenticate_api_key;
+use crate::auth::protocols::certificate::authenticate_certificate;
+#[cfg(feature = "oidc")]
+use crate::auth::protocols::jwt::authenticate_jwt;
+use crate::auth::protocols::nervemq::{authenticate_api_key, authenticate_api_key_v2};
 use crate::auth::protocols::sigv4::authenticate_sigv4;
+use crate::proxy::ClientInfo;
+use crate::tls::ClientCertificate;
 
 /// Transform factory for API key authentication middleware.
 ///
@@ -50,7 +56,7 @@ where
 ///
 /// Intercepts requests to:
 /// 1. Check for Authorization header
-/// 2. Parse and validate API keys or AWS SigV4 signatures
+/// 2. Parse and validate API keys, AWS SigV4 signatures or bearer tokens
 /// 3. Create user session on successful authentication
 /// 4. Inject authorized namespace into request extensions
 pub struct AuthMiddleware<S> {
@@ -88,8 +94,36 @@ where
                 .expect("SQLite pool not found. This is a bug.")
                 .clone();
 
+            // The SCIM API authenticates its own bearer token, see `crate::api::scim`.
+            if req.path().starts_with(crate::scim::SCIM_PATH) {
+                return svc.call(req).await;
+            }
+
             let auth_req = {
                 let Some(auth_header) = req.headers().get(header::AUTHORIZATION) else {
+                    // Clients can authenticate with a mapped certificate instead of a header.
+                    if let Some(cert) = req.conn_data::<ClientCertificate>().cloned() {
+                        match authenticate_certificate(api.db(), &cert).await {
+                            Ok((user, authed_namespace)) => {
+                                tracing::debug!(
+                                    email = user.email,
+                                    "Authenticated user by client certificate"
+                                );
+                                if Identity::login(&req.extensions(), user.email.clone()).is_err() {
+                                    return Err(crate::error::Error::Unauthorized.into());
+                                }
+                                req.extensions_mut().insert(authed_namespace);
+                            }
+                            Err(crate::error::Error::IdentityNotFound { key_id }) => {
+                                tracing::debug!(
+                                    fingerprint = key_id,
+                                    "Client certificate is not mapped to a namespace"
+                                );
+                            }
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+
                     // If there's no auth header, allow the request to pass through.
                     // Authorization will be enforced past this point by the identity system.
                     //
@@ -100,41 +134,71 @@ where
 
                 match auth_header.to_str() {
                     Ok(str) => str.to_owned(),
-                    Err(e) => return Err(ErrorInternalServerError(e)),
+                    Err(_) => return Err(invalid_authorization().into()),
                 }
             };
 
             let auth_header = crate::auth::header::auth_header()
                 .parse_str(&auth_req)
-                .map_err(|e| ErrorInternalServerError(e))?;
+                .map_err(|_| invalid_authorization())?;
 
-            let (user, authed_namespace) = match auth_header {
+            // Bearer tokens aren't API keys, so they have no key ID.
+            let (key_id, (user, authed_namespace)) = match auth_header {
                 AuthHeader::NerveMqApiV1(token) => {
+                    let key_id = token.short_token.clone();
                     match authenticate_api_key(api.db(), token).await {
-                        Ok(user) => user,
-                        Err(e) => return Err(ErrorUnauthorized(e)),
+                        Ok(user) => (Some(key_id), user),
+                        Err(_) => return Err(crate::error::Error::Unauthorized.into()),
+                    }
+                }
+                AuthHeader::NerveMqApiV2(header) => {
+                    let key_id = header.key_id.to_owned();
+                    match authenticate_api_key_v2(&api, &mut req, header).await {
+                        Ok(user) => (Some(key_id), user),
+                        // Not an authentication failure, the body was too large to check.
+                        Err(e @ crate::error::Error::PayloadTooLarge) => return Err(e.into()),
+                        Err(e) => {
+                            tracing::debug!("Error authenticating NerveMqApiV2: {:?}", e);
+                            return Err(crate::error::Error::Unauthorized.into());
+                        }
                     }
                 }
                 AuthHeader::AWSv4(header) => {
-                    match authenticate_sigv4(api, &mut req, header).await {
-                        Ok(user) => user,
+                    let key_id = header.key_id.to_owned();
+                    match authenticate_sigv4(api.clone(), &mut req, header).await {
+                        Ok(user) => (Some(key_id), user),
+                        // Not an authentication failure, the body was too large to check.
+                        Err(e @ crate::error::Error::PayloadTooLarge) => return Err(e.into()),
                         Err(e) => {
                             tracing::error!("Error authenticating AWSv4: {:?}", e);
-                            return Err(ErrorUnauthorized(e));
+                            return Err(crate::error::Error::Unauthorized.into());
                         }
                     }
                 }
+                #[cfg(feature = "oidc")]
+                AuthHeader::Bearer(token) => match authenticate_jwt(&api, token).await {
+                    Ok(user) => (None, user),
+                    Err(_) => return Err(crate::error::Error::Unauthorized.into()),
+                },
                 #[allow(unreachable_patterns)]
-                _ => return Err(ErrorUnauthorized("unimplemented")),
+                _ => return Err(crate::error::Error::Unauthorized.into()),
             };
 
             tracing::debug!(email = user.email, "Authenticated user");
 
+            if let Some(key_id) = key_id {
+                let client = ClientInfo::from_http_request(req.request());
+                api.check_token_network(&key_id, client.ip).await?;
+
+                api.record_token_use(&key_id);
+                req.extensions_mut().insert(AuthenticatedKey(key_id));
+            }
+
             match Identity::login(&req.extensions(), user.email.clone()) {
                 Ok(_) => {
                     tracing::debug!("User session established");
                 }
-                Err(e) => return Err(ErrorUnauthorized(e)),
+                Err(_) => return Err(crate::error::Error::Unauthorized.into()),
             }
 
             req.extensions_mut().insert(authed_namespace);
@@ -143,3 +207,9 @@ where
         })
     }
 }
+
+fn invalid_authorization() -> crate::error::Error {
+    crate::error::Error::InvalidHeader {
+        header: header::AUTHORIZATION.to_string(),
+    }
+}
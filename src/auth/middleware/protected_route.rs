@@ -1,7 +1,8 @@
 //! Protected route middleware for role-based access control.
 //!
 //! Provides middleware to restrict route access based on user authentication
-//! and role requirements (admin or regular user).
+//! and role requirements (admin or regular user), optionally letting users access
+//! routes about themselves.
 
 use std::future::{Future, Ready};
 use std::pin::Pin;
@@ -10,7 +11,6 @@ use std::task::{Context, Poll};
 
 use actix_identity::IdentityExt;
 use actix_web::dev::{Service, Transform};
-use actix_web::error::ErrorUnauthorized;
 use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error};
 
 use crate::api::auth::Role;
@@ -21,12 +21,18 @@ use crate::api::auth::Role;
 #[derive(Clone)]
 pub struct Protected {
     admin_only: bool,
+    /// Path parameter holding the email of a user who may access the route without being an
+    /// admin
+    self_param: Option<&'static str>,
 }
 
 impl Protected {
     /// Creates new protection config with specified admin requirement.
     pub fn new(admin_only: bool) -> Self {
-        Self { admin_only }
+        Self {
+            admin_only,
+            self_param: None,
+        }
     }
 
     /// Shorthand to create admin-only route protection.
@@ -38,6 +44,15 @@ impl Protected {
     pub fn authenticated() -> Self {
         Self::new(false)
     }
+
+    /// Creates protection that admits admins, and users whose email is in the path parameter
+    /// `email_param`. The parameter must be matched by the scope the middleware wraps.
+    pub fn admin_or_self(email_param: &'static str) -> Self {
+        Self {
+            admin_only: true,
+            self_param: Some(email_param),
+        }
+    }
 }
 
 impl Default for Protected {
@@ -99,18 +114,30 @@ where
             .expect("service should be available - this is a bug")
             .clone();
 
-        let required_role = if self.config.admin_only {
+        let self_email = self
+            .config
+            .self_param
+            .and_then(|param| req.match_info().get(param))
+            .map(str::to_owned);
+
+        let mut required_role = if self.config.admin_only {
             Role::Admin
         } else {
             Role::User
         };
 
         Box::pin(async move {
-            let identity = req.get_identity().map_err(ErrorUnauthorized)?;
+            let identity = req
+                .get_identity()
+                .map_err(|_| crate::error::Error::Unauthorized)?;
+
+            if self_email.is_some_and(|email| identity.id().is_ok_and(|id| id == email)) {
+                required_role = Role::User;
+            }
 
             match api.check_user_role(identity, required_role).await {
                 Ok(_) => svc.call(req).await,
-                Err(e) => Err(ErrorUnauthorized(e)),
+                Err(_) => Err(crate::error::Error::Unauthorized.into()),
             }
         })
     }
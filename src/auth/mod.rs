@@ -3,4 +3,6 @@ pub mod crypto;
 pub mod header;
 pub mod middleware;
 pub mod protocols;
+pub mod replay;
 pub mod session;
+pub mod signing_key;
@@ -0,0 +1,68 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    api::auth::User, auth::credential::AuthorizedNamespace, error::Error, tls::ClientCertificate,
+};
+
+/// Finds the user and namespace a client certificate is mapped to, see [`crate::tls`].
+///
+/// Mappings of the certificate's fingerprint take precedence over mappings of a subject name.
+pub async fn authenticate_certificate(
+    pool: &SqlitePool,
+    cert: &ClientCertificate,
+) -> Result<(User, AuthorizedNamespace), Error> {
+    let fingerprint = cert.fingerprint();
+
+    let mapping: Option<(String, String)> = sqlx::query_as(
+        "
+        SELECT u.email, ns.name FROM client_certificates c
+        JOIN users u ON u.id = c.user
+        JOIN namespaces ns ON ns.id = c.ns
+        WHERE c.fingerprint = $1
+        ",
+    )
+    .bind(&fingerprint)
+    .fetch_optional(pool)
+    .await?;
+
+    let mapping = match mapping {
+        Some(mapping) => Some(mapping),
+        None => {
+            let by_name: Vec<(String, String, String)> = sqlx::query_as(
+                "
+                SELECT c.subject_name, u.email, ns.name FROM client_certificates c
+                JOIN users u ON u.id = c.user
+                JOIN namespaces ns ON ns.id = c.ns
+                WHERE c.subject_name IS NOT NULL
+                ORDER BY c.id
+                ",
+            )
+            .fetch_all(pool)
+            .await?;
+
+            by_name
+                .into_iter()
+                .find(|(subject_name, _, _)| cert.is_valid_for(subject_name))
+                .map(|(_, email, namespace)| (email, namespace))
+        }
+    };
+
+    let Some((email, namespace)) = mapping else {
+        return Err(Error::IdentityNotFound {
+            key_id: fingerprint,
+        });
+    };
+
+    let user = sqlx::query_as::<_, User>(
+        "
+        SELECT * FROM users
+        WHERE email = $1 AND deactivated_at IS NULL
+        ",
+    )
+    .bind(&email)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    Ok((user, AuthorizedNamespace(namespace)))
+}
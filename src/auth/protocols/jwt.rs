@@ -0,0 +1,42 @@
+use crate::{
+    api::auth::User, auth::credential::AuthorizedNamespace, error::Error, service::Service,
+};
+
+/// Authenticates a bearer token, see [`crate::jwt`].
+///
+/// # Errors
+/// * `Error::Unauthorized` - If bearer tokens are disabled, the token isn't valid, or the user it
+///   maps to doesn't exist or has no access to its namespace
+pub async fn authenticate_jwt(
+    service: &Service,
+    token: &str,
+) -> Result<(User, AuthorizedNamespace), Error> {
+    let Some(verifier) = service.jwt() else {
+        return Err(Error::Unauthorized);
+    };
+
+    let subject = verifier.verify(token).await?;
+
+    let user = sqlx::query_as::<_, User>(
+        "
+        SELECT u.* FROM users u
+        JOIN user_permissions p ON p.user = u.id
+        JOIN namespaces ns ON ns.id = p.namespace
+        WHERE u.email = $1 AND ns.name = $2 AND u.deactivated_at IS NULL
+        ",
+    )
+    .bind(&subject.email)
+    .bind(&subject.namespace)
+    .fetch_optional(service.db())
+    .await?
+    .ok_or_else(|| {
+        tracing::warn!(
+            email = subject.email,
+            namespace = subject.namespace,
+            "Bearer token names a user without access to its namespace"
+        );
+        Error::Unauthorized
+    })?;
+
+    Ok((user, AuthorizedNamespace(subject.namespace)))
+}
@@ -1,2 +1,34 @@
+use std::pin::Pin;
+
+use actix_web::{dev::ServiceRequest, HttpMessage};
+use bytes::Bytes;
+
+use crate::{error::Error, sqs::body};
+
+pub mod certificate;
+#[cfg(feature = "oidc")]
+pub mod jwt;
 pub mod nervemq;
 pub mod sigv4;
+
+/// Reads the body of a request to verify its signature.
+///
+/// The body is put back into the request, since route handlers and other middleware still need
+/// it. Since this happens before the request is authenticated, bodies are limited to
+/// [`Config::max_request_body`](crate::config::Config::max_request_body), as with SQS requests.
+///
+/// # Errors
+/// * `Error::PayloadTooLarge` - If the body is, or is announced to be, larger than the limit
+pub(crate) async fn read_payload(req: &mut ServiceRequest, limit: usize) -> Result<Bytes, Error> {
+    let payload = req.take_payload();
+    let payload = body::read(req.headers(), payload, limit).await?;
+
+    req.set_payload(actix_web::dev::Payload::Stream {
+        payload: Box::pin(futures_util::stream::once(std::future::ready(Ok(
+            payload.clone()
+        ))))
+            as Pin<Box<dyn futures_util::Stream<Item = Result<_, actix_web::error::PayloadError>>>>,
+    });
+
+    Ok(payload)
+}
@@ -1,16 +1,66 @@
-use actix_web::web;
+//! Authentication with NerveMQ API keys.
+//!
+//! The `NerveMqApiV1` scheme sends the key itself, as `NerveMqApiV1 nervemq_<short>_<long>`,
+//! which is verified against its hash.
+//!
+//! The `NerveMqApiV2` scheme signs requests with the key instead, like SigV4 but simpler, so that
+//! the key never leaves the client and a captured request can't be sent again:
+//!
+//! ```text
+//! Authorization: NerveMqApiV2 Credential=<short>, Signature=<signature>
+//! X-NerveMq-Date: <Unix time of the signature>
+//! X-NerveMq-Nonce: <16 to 128 letters, digits, '-' or '_', unique per request>
+//! ```
+//!
+//! The signature is the hex-encoded HMAC-SHA256, keyed by the long token, of the
+//! [string to sign](string_to_sign_v2). Requests signed more than [`REPLAY_WINDOW`] away from the
+//! server's time are rejected, and so are requests whose nonce was already used with the same
+//! key within the window, see [`crate::auth::replay`].
+
+use std::{ops::RangeInclusive, time::Duration};
+
+use actix_web::dev::ServiceRequest;
 use argon2::password_hash::PasswordHashString;
+use hmac::Mac;
+use sha2::Sha256;
 use sqlx::SqlitePool;
+use tracing::instrument;
 
 use crate::{
     api::auth::User,
     auth::{
         credential::{ApiKey, AuthorizedNamespace},
-        crypto::verify_secret,
+        crypto::{sha256_hex, verify_secret},
+        protocols::read_payload,
     },
     error::Error,
+    service::Service,
 };
 
+/// Tag of the `NerveMqApiV2` scheme, which starts its string to sign.
+pub const API_V2_SCHEME: &str = "NerveMqApiV2";
+
+/// Header with the Unix time a `NerveMqApiV2` request was signed at.
+pub const DATE_HEADER: &str = "x-nervemq-date";
+
+/// Header with the nonce of a `NerveMqApiV2` request.
+pub const NONCE_HEADER: &str = "x-nervemq-nonce";
+
+/// How far from the server's time a `NerveMqApiV2` request may have been signed.
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(300);
+
+/// Allowed lengths of a nonce.
+const NONCE_LENGTH: RangeInclusive<usize> = 16..=128;
+
+/// Parsed `NerveMqApiV2` authorization header.
+#[derive(Debug)]
+pub struct ApiV2Header<'a> {
+    /// Short token of the key the request was signed with
+    pub key_id: &'a str,
+    /// Hex-encoded signature of the request
+    pub signature: &'a str,
+}
+
 pub async fn authenticate_api_key(
     pool: &SqlitePool,
     token: ApiKey,
@@ -38,9 +88,9 @@ pub async fn authenticate_api_key(
         return Err(Error::InternalServerError { source: None });
     };
 
-    match web::block(move || verify_secret(token.long_token, hashed_key))
+    match tokio::task::spawn_blocking(move || verify_secret(token.long_token, hashed_key))
         .await
-        .map_err(|e| e.into())
+        .map_err(eyre::Report::from)
         .and_then(|res| res)
     {
         Ok(_) => {}
@@ -50,15 +100,260 @@ pub async fn authenticate_api_key(
         }
     }
 
+    // Deactivating a user revokes their keys, but a key must never outlive that.
     let user = sqlx::query_as::<_, User>(
         "
         SELECT * FROM users
-        WHERE email = $1
+        WHERE email = $1 AND deactivated_at IS NULL
         ",
     )
     .bind(&email)
-    .fetch_one(pool)
-    .await?;
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
 
-    return Ok((user, AuthorizedNamespace(namespace)));
+    Ok((user, AuthorizedNamespace(namespace)))
+}
+
+/// Builds the string a `NerveMqApiV2` request signs, one line each for the scheme, the method,
+/// the path, the query string as sent, the date and nonce headers, and the hex-encoded SHA-256
+/// of the body.
+pub fn string_to_sign_v2(
+    method: &str,
+    path: &str,
+    query: &str,
+    date: &str,
+    nonce: &str,
+    payload: &[u8],
+) -> String {
+    [
+        API_V2_SCHEME,
+        method,
+        path,
+        query,
+        date,
+        nonce,
+        &sha256_hex(payload),
+    ]
+    .join("\n")
+}
+
+fn mac_v2(secret: &[u8], string_to_sign: &str) -> Result<hmac::Hmac<Sha256>, Error> {
+    let mut mac = hmac::Hmac::<Sha256>::new_from_slice(secret).map_err(Error::internal)?;
+    mac.update(string_to_sign.as_bytes());
+    Ok(mac)
+}
+
+/// Signs a string to sign with the long token of a key, see [`string_to_sign_v2`].
+#[cfg_attr(not(feature = "testing"), allow(dead_code))]
+pub fn sign_v2(secret: &[u8], string_to_sign: &str) -> Result<String, Error> {
+    Ok(hex::encode(
+        mac_v2(secret, string_to_sign)?.finalize().into_bytes(),
+    ))
+}
+
+fn header_value<'a>(req: &'a ServiceRequest, name: &str) -> Result<&'a str, Error> {
+    req.headers()
+        .get(name)
+        .ok_or_else(|| Error::MissingHeader {
+            header: name.to_owned(),
+        })?
+        .to_str()
+        .map_err(|_| Error::InvalidHeader {
+            header: name.to_owned(),
+        })
+}
+
+/// Authenticates a request signed with the `NerveMqApiV2` scheme.
+///
+/// # Arguments
+/// * `service` - Service holding the keys and the nonces of recent requests
+/// * `req` - The incoming service request to authenticate
+/// * `header` - Parsed authorization header
+///
+/// # Errors
+/// * `Error::MissingHeader` - If the date or nonce header is missing
+/// * `Error::InvalidHeader` - If the date or nonce header is malformed
+/// * `Error::IdentityNotFound` - If the key doesn't exist
+/// * `Error::Unauthorized` - If the request was signed outside of the [`REPLAY_WINDOW`], its
+///   signature is wrong or its nonce was already used
+#[instrument(skip(service, req))]
+pub async fn authenticate_api_key_v2(
+    service: &Service,
+    req: &mut ServiceRequest,
+    header: ApiV2Header<'_>,
+) -> Result<(User, AuthorizedNamespace), Error> {
+    let date = header_value(req, DATE_HEADER)?.to_owned();
+    let signed_at = date.parse::<i64>().map_err(|_| Error::InvalidHeader {
+        header: DATE_HEADER.to_owned(),
+    })?;
+
+    let now = service.now();
+    if now.abs_diff(signed_at) > REPLAY_WINDOW.as_secs() {
+        tracing::debug!(
+            key_id = header.key_id,
+            signed_at,
+            now,
+            "Request was signed outside of the replay window"
+        );
+        return Err(Error::Unauthorized);
+    }
+
+    let nonce = header_value(req, NONCE_HEADER)?.to_owned();
+    if !NONCE_LENGTH.contains(&nonce.len())
+        || !nonce
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::InvalidHeader {
+            header: NONCE_HEADER.to_owned(),
+        });
+    }
+
+    let payload = read_payload(req, service.config().max_request_body()).await?;
+
+    let Some((encrypted_key, namespace, email)) = sqlx::query_as::<_, (Vec<u8>, String, String)>(
+        "
+        SELECT k.encrypted_key, ns.name, u.email FROM api_keys k
+        JOIN namespaces ns ON ns.id = k.ns
+        JOIN users u ON u.id = k.user
+        WHERE key_id = $1
+        ",
+    )
+    .bind(header.key_id)
+    .fetch_optional(service.db())
+    .await?
+    else {
+        return Err(Error::IdentityNotFound {
+            key_id: header.key_id.to_owned(),
+        });
+    };
+
+    let kms_key_id = service.get_key_id(&email).await?;
+    let secret = service.kms().decrypt(&kms_key_id, encrypted_key).await?;
+
+    let string_to_sign = string_to_sign_v2(
+        req.method().as_str(),
+        req.uri().path(),
+        req.query_string(),
+        &date,
+        &nonce,
+        &payload,
+    );
+
+    let signature = hex::decode(header.signature).map_err(|_| Error::Unauthorized)?;
+    if mac_v2(&secret, &string_to_sign)?
+        .verify_slice(&signature)
+        .is_err()
+    {
+        tracing::debug!(key_id = header.key_id, "Invalid signature for request");
+        return Err(Error::Unauthorized);
+    }
+
+    // Only requests with a valid signature use up their nonce, so that others can't burn them.
+    let expires_at = signed_at + REPLAY_WINDOW.as_secs() as i64;
+    if !service
+        .nonces()
+        .insert(header.key_id, &nonce, expires_at, now)
+    {
+        tracing::warn!(key_id = header.key_id, nonce, "Rejected a replayed request");
+        return Err(Error::Unauthorized);
+    }
+
+    // Deactivating a user revokes their keys, but a key must never outlive that.
+    let user = sqlx::query_as::<_, User>(
+        "
+        SELECT * FROM users
+        WHERE email = $1 AND deactivated_at IS NULL
+        ",
+    )
+    .bind(&email)
+    .fetch_optional(service.db())
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    Ok((user, AuthorizedNamespace(namespace)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_identity::Identity;
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::{config::Config, kms::memory::InMemoryKeyManager};
+
+    #[actix_web::test]
+    async fn test_deactivated_user_is_unauthorized() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = service.config().root_email().to_owned();
+        service
+            .create_namespace("default", Identity::mock(root.clone()))
+            .await
+            .unwrap();
+        let token = service
+            .create_token(
+                "test".to_owned(),
+                "default".to_owned(),
+                Identity::mock(root.clone()),
+            )
+            .await
+            .unwrap();
+
+        let (service, token) = (&service, &token);
+        let authenticate = move |nonce: &'static str| async move {
+            let date = service.now().to_string();
+            let string_to_sign = string_to_sign_v2("GET", "/queue", "", &date, nonce, b"");
+            let signature = sign_v2(token.secret_key.as_bytes(), &string_to_sign).unwrap();
+            let mut req = TestRequest::get()
+                .uri("/queue")
+                .insert_header((DATE_HEADER, date))
+                .insert_header((NONCE_HEADER, nonce))
+                .to_srv_request();
+            let header = ApiV2Header {
+                key_id: &token.access_key,
+                signature: &signature,
+            };
+            authenticate_api_key_v2(service, &mut req, header).await
+        };
+
+        authenticate("first-request-nonce").await.unwrap();
+
+        // Set directly, since deactivating through the service also revokes the key.
+        sqlx::query("UPDATE users SET deactivated_at = 1 WHERE email = $1")
+            .bind(&root)
+            .execute(service.db())
+            .await
+            .unwrap();
+        let result = authenticate("second-request-nonce").await;
+        assert!(matches!(result, Err(Error::Unauthorized)), "{result:?}");
+    }
+
+    #[test]
+    fn test_sign_v2() {
+        let string_to_sign = string_to_sign_v2(
+            "POST",
+            "/sqs",
+            "a=1",
+            "1700000000",
+            "0123456789abcdef",
+            b"{}",
+        );
+        assert_eq!(
+            string_to_sign,
+            format!(
+                "NerveMqApiV2\nPOST\n/sqs\na=1\n1700000000\n0123456789abcdef\n{}",
+                sha256_hex(b"{}")
+            )
+        );
+
+        let signature = sign_v2(b"secret", &string_to_sign).unwrap();
+        assert_eq!(signature.len(), 64);
+        assert_ne!(signature, sign_v2(b"other", &string_to_sign).unwrap());
+    }
 }
@@ -13,7 +13,7 @@
 //!
 //! For more details, see [AWS Signature Version 4 signing process](https://docs.aws.amazon.com/general/latest/gr/signature-version-4.html)
 
-use std::{pin::Pin, time::SystemTime};
+use std::time::SystemTime;
 
 use actix_web::{
     dev::ServiceRequest,
@@ -21,8 +21,6 @@ use actix_web::{
     HttpMessage,
 };
 use aws_sigv4::sign::v4::generate_signing_key;
-use bytes::BytesMut;
-use futures_util::TryStreamExt;
 use hmac::{digest::FixedOutput, Mac};
 use itertools::Itertools;
 use sha2::Sha256;
@@ -30,7 +28,7 @@ use tracing::instrument;
 
 use crate::{
     api::auth::User,
-    auth::{credential::AuthorizedNamespace, crypto::sha256_hex},
+    auth::{credential::AuthorizedNamespace, crypto::sha256_hex, protocols::read_payload},
     error::Error,
 };
 
@@ -91,23 +89,7 @@ pub async fn authenticate_sigv4(
     req: &mut ServiceRequest,
     header: SigV4Header<'_>,
 ) -> Result<(User, AuthorizedNamespace), Error> {
-    let payload = {
-        let payload = req.take_payload();
-
-        let bytes = payload
-            .try_fold(BytesMut::new(), |mut acc, chunk| async move {
-                acc.extend_from_slice(&chunk);
-                Ok(acc)
-            })
-            .await
-            .map_err(|e| {
-                tracing::error!("Error reading request payload: {}", e);
-                Error::internal(e)
-            })?
-            .freeze();
-
-        bytes
-    };
+    let payload = read_payload(req, service.config().max_request_body()).await?;
 
     let pool = req
         .app_data::<web::Data<crate::service::Service>>()
@@ -124,14 +106,13 @@ pub async fn authenticate_sigv4(
             WHERE key_id = $1
             ",
         )
-        .bind(&header.key_id)
+        .bind(header.key_id)
         .fetch_optional(&pool)
         .await?
     else {
         return Err(Error::IdentityNotFound {
             key_id: header.key_id.to_string(),
-        }
-        .into());
+        });
     };
 
     let kms_key_id = service.get_key_id(&user_email).await?;
@@ -204,7 +185,7 @@ pub async fn authenticate_sigv4(
                     }
                 })?;
 
-            let canonical_value = value.trim().split_whitespace().join(" ");
+            let canonical_value = value.split_whitespace().join(" ");
 
             Ok(format!("{}:{}\n", header, canonical_value))
         })
@@ -215,8 +196,8 @@ pub async fn authenticate_sigv4(
     let signed_headers = sorted_signed_headers.join(";");
 
     let canonical_request = [
-        &req.method().to_string(),
-        &*canonical_uri,
+        req.method().as_ref(),
+        canonical_uri,
         &canonical_query,
         &canonical_headers,
         &signed_headers,
@@ -238,24 +219,14 @@ pub async fn authenticate_sigv4(
     .join("\n");
 
     let generated_signature = {
-        let mut mac = hmac::Hmac::<Sha256>::new_from_slice(signing_key.as_ref())
-            .map_err(|e| Error::internal(e))?;
+        let mut mac =
+            hmac::Hmac::<Sha256>::new_from_slice(signing_key.as_ref()).map_err(Error::internal)?;
 
         mac.update(string_to_sign.as_bytes());
 
         hex::encode(mac.finalize_fixed())
     };
 
-    // IMPORTANT: We must duplicate the payload and return it to the request,
-    // since it may be needed by route handlers or other middleware.
-    //
-    // We probably don't need this if authorization fails, but return it to the request before
-    // validating the hash just for consistency/sanity.
-    req.set_payload(actix_web::dev::Payload::Stream {
-        payload: Box::pin(futures_util::stream::once(std::future::ready(Ok(payload))))
-            as Pin<Box<dyn futures_util::Stream<Item = Result<_, actix_web::error::PayloadError>>>>,
-    });
-
     if header.signature != generated_signature {
         tracing::debug!(
             provided = header.signature,
@@ -277,12 +248,13 @@ pub async fn authenticate_sigv4(
         "
         SELECT u.* FROM api_keys k
         JOIN users u ON u.id = k.user
-        WHERE k.key_id = $1
+        WHERE k.key_id = $1 AND u.deactivated_at IS NULL
         ",
     )
-    .bind(&header.key_id)
-    .fetch_one(&pool)
-    .await?;
+    .bind(header.key_id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(Error::Unauthorized)?;
 
     Ok((user, AuthorizedNamespace(namespace)))
 }
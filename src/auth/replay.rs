@@ -0,0 +1,76 @@
+//! Replay protection of signed requests.
+//!
+//! Requests signed with the `NerveMqApiV2` scheme carry the time they were signed at and a nonce
+//! chosen by the client, see [`crate::auth::protocols::nervemq`]. Requests signed too long ago
+//! are rejected, and the [`NonceCache`] remembers the nonces of the others until they would be,
+//! so that a captured request can't be sent again in the meantime.
+//!
+//! Nonces are kept in memory, so instances of a cluster don't know about each other's nonces:
+//! a request replayed to another instance within the window is only stopped by the signing time.
+
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+
+use crate::registry::Registry;
+
+/// Nonces of recently signed requests, with the Unix time until which they are remembered.
+#[derive(Debug, Clone, Default)]
+pub struct NonceCache {
+    seen: Registry<String, i64>,
+    /// Unix time expired nonces were last removed at
+    pruned_at: Arc<AtomicI64>,
+}
+
+impl NonceCache {
+    /// Records the nonce of a request signed with a key.
+    ///
+    /// # Arguments
+    /// * `key_id` - ID of the key the request was signed with
+    /// * `nonce` - Nonce of the request
+    /// * `expires_at` - Unix time until which the nonce is remembered
+    /// * `now` - Current Unix time
+    ///
+    /// # Returns
+    /// `false` if the nonce was already recorded for the key and hasn't expired, that is if the
+    /// request is a replay
+    pub fn insert(&self, key_id: &str, nonce: &str, expires_at: i64, now: i64) -> bool {
+        // Expired nonces are removed at most once per second, rather than on every request.
+        if self.pruned_at.swap(now, Ordering::Relaxed) < now {
+            self.seen.retain(|_, expiry| *expiry > now);
+        }
+
+        let mut fresh = false;
+        self.seen.update(&format!("{key_id}:{nonce}"), |expiry| {
+            if *expiry <= now {
+                *expiry = expires_at;
+                fresh = true;
+            }
+        });
+
+        fresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_cache() {
+        let cache = NonceCache::default();
+
+        assert!(cache.insert("key", "a", 400, 100));
+        assert!(!cache.insert("key", "a", 400, 100));
+        assert!(!cache.insert("key", "a", 500, 399));
+        assert!(cache.insert("other", "a", 400, 100));
+        assert!(cache.insert("key", "b", 150, 100));
+        assert_eq!(cache.seen.len(), 3);
+
+        // Expired nonces can be used again, and are forgotten.
+        assert!(cache.insert("key", "a", 700, 400));
+        assert_eq!(cache.seen.len(), 1);
+        assert!(!cache.insert("key", "a", 700, 401));
+    }
+}
@@ -2,9 +2,13 @@
 //!
 //! This module provides a persistent session storage backend using SQLite. It implements
 //! the `SessionStore` trait from actix-session and stores session data in two tables:
-//! - sessions: Stores session metadata (id, key, TTL)
+//! - sessions: Stores session metadata (id, key, TTL, owner, timestamps, client info)
 //! - session_state: Stores key-value pairs for each session
 //!
+//! The owner and client info columns are copied from well-known session state keys
+//! ([`IDENTITY_KEY`], [`USER_AGENT_KEY`], [`IP_KEY`]) whenever a session is saved, so that
+//! sessions can be listed and revoked per user.
+//!
 //! The implementation supports all standard session operations including:
 //! - Creating new sessions
 //! - Loading existing sessions
@@ -22,6 +26,20 @@ pub use actix_session::storage::SessionStore;
 
 pub type SessionState = serde_json::Map<String, serde_json::Value>;
 
+/// Session state key holding the logged-in user's email.
+pub const IDENTITY_KEY: &str = "nervemq_id";
+
+/// Session state key holding the user agent the session was created from.
+pub const USER_AGENT_KEY: &str = "nervemq_user_agent";
+
+/// Session state key holding the client IP address the session was created from.
+pub const IP_KEY: &str = "nervemq_ip";
+
+/// Reads a string value from session state.
+fn state_str<'a>(state: &'a SessionState, key: &str) -> Option<&'a str> {
+    state.get(key).and_then(|v| v.as_str())
+}
+
 /// SQLite-based implementation of the session store.
 ///
 /// Provides persistent storage of session data using SQLite as the backend.
@@ -51,6 +69,21 @@ pub struct Session {
     state: SessionState,
 }
 
+/// An active session, as shown to its owner.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: u64,
+    /// Unix timestamp of when the session was created
+    pub created_at: u64,
+    /// Unix timestamp of the last request made with the session
+    pub last_seen: u64,
+    /// Unix timestamp after which the session expires unless it is used again
+    pub expires_at: u64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
 /// Represents a single key-value entry in a session's state.
 ///
 /// Maps to the session_state table in SQLite, where each row
@@ -143,13 +176,18 @@ impl SessionStore for SqliteSessionStore {
 
             let id: u64 = sqlx::query_scalar(
                 "
-                INSERT INTO sessions (session_key, ttl)
-                VALUES ($1, $2)
+                INSERT INTO sessions (
+                    session_key, ttl, email, user_agent, ip, created_at, last_seen
+                )
+                VALUES ($1, $2, $3, $4, $5, unixepoch('now'), unixepoch('now'))
                 RETURNING id
                 ",
             )
             .bind(key.as_ref())
             .bind(ttl.whole_seconds())
+            .bind(state_str(&session_state, IDENTITY_KEY))
+            .bind(state_str(&session_state, USER_AGENT_KEY))
+            .bind(state_str(&session_state, IP_KEY))
             .fetch_one(tx.as_mut())
             .await
             .map_err(|e| SaveError::Other(anyhow::Error::new(e)))?;
@@ -197,7 +235,12 @@ impl SessionStore for SqliteSessionStore {
 
             let ttl_query = "
                 UPDATE sessions
-                SET ttl = $1
+                SET
+                    ttl = $1,
+                    email = COALESCE($3, email),
+                    user_agent = COALESCE($4, user_agent),
+                    ip = COALESCE($5, ip),
+                    last_seen = unixepoch('now')
                 WHERE session_key = $2
                 RETURNING id
             ";
@@ -205,6 +248,9 @@ impl SessionStore for SqliteSessionStore {
             let session_id: u64 = sqlx::query_scalar(ttl_query)
                 .bind(ttl.whole_seconds())
                 .bind(session_key.as_ref())
+                .bind(state_str(&session_state, IDENTITY_KEY))
+                .bind(state_str(&session_state, USER_AGENT_KEY))
+                .bind(state_str(&session_state, IP_KEY))
                 .fetch_one(tx.as_mut())
                 .await
                 .map_err(|e| UpdateError::Other(anyhow::Error::new(e)))?;
@@ -214,7 +260,7 @@ impl SessionStore for SqliteSessionStore {
                     .keys()
                     .map(|k| format!("'{k}'"))
                     .fold(String::new(), |s, k| {
-                        if s.len() == 0 {
+                        if s.is_empty() {
                             return k;
                         }
                         format!("{s}, {k}")
@@ -268,17 +314,17 @@ impl SessionStore for SqliteSessionStore {
         Box::pin(async move {
             let query = "
                 UPDATE sessions
-                SET ttl = $1
+                SET ttl = $1, last_seen = unixepoch('now')
                 WHERE session_key = $2
             ";
-            let mut db = db.acquire().await.map_err(|e| anyhow::Error::new(e))?;
+            let mut db = db.acquire().await.map_err(anyhow::Error::new)?;
 
             sqlx::query(query)
                 .bind(ttl.whole_seconds())
                 .bind(session_key.as_ref())
                 .execute(db.as_mut())
                 .await
-                .map_err(|e| anyhow::Error::new(e))?;
+                .map_err(anyhow::Error::new)?;
 
             Ok(())
         })
@@ -327,7 +373,12 @@ mod tests {
             CREATE TABLE IF NOT EXISTS sessions (
                 id INTEGER PRIMARY KEY,
                 session_key TEXT NOT NULL UNIQUE,
-                ttl INTEGER NOT NULL
+                ttl INTEGER NOT NULL,
+                email TEXT,
+                created_at INTEGER NOT NULL DEFAULT 0,
+                last_seen INTEGER NOT NULL DEFAULT 0,
+                user_agent TEXT,
+                ip TEXT
             )
             "#,
         )
@@ -460,4 +511,33 @@ mod tests {
 
         assert_eq!(updated_ttl, new_ttl.whole_seconds());
     }
+
+    #[tokio::test]
+    async fn test_session_metadata() {
+        let db = setup_db().await;
+        let store = SqliteSessionStore::new(db.clone());
+        let ttl = Duration::minutes(30);
+
+        let session_key = store.save(SessionState::new(), &ttl).await.unwrap();
+
+        let mut state = SessionState::new();
+        state.insert(IDENTITY_KEY.to_string(), "user@example.com".into());
+        state.insert(USER_AGENT_KEY.to_string(), "test-agent".into());
+        state.insert(IP_KEY.to_string(), "127.0.0.1".into());
+        store
+            .update(session_key.clone(), state, &ttl)
+            .await
+            .unwrap();
+
+        let (email, user_agent, ip): (Option<String>, Option<String>, Option<String>) =
+            sqlx::query_as("SELECT email, user_agent, ip FROM sessions WHERE session_key = ?")
+                .bind(session_key.as_ref())
+                .fetch_one(&db)
+                .await
+                .unwrap();
+
+        assert_eq!(email.as_deref(), Some("user@example.com"));
+        assert_eq!(user_agent.as_deref(), Some("test-agent"));
+        assert_eq!(ip.as_deref(), Some("127.0.0.1"));
+    }
 }
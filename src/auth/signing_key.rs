@@ -0,0 +1,190 @@
+//! The key session cookies are signed and encrypted with.
+//!
+//! The key is generated the first time the server starts, and stored encrypted with the service's
+//! [`KeyManager`](crate::kms::KeyManager), so that sessions survive restarts and every instance
+//! sharing a database accepts the same cookies. It is stored in the database, or in
+//! [`Config::session_key_file`](crate::config::Config::session_key_file) if one is set.
+
+use actix_web::cookie::Key;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{
+    error::Error,
+    service::{write_private_file, Service},
+};
+
+/// Name of the session key in the `server_secrets` table.
+const SESSION_KEY_NAME: &str = "session";
+
+/// The session key, encrypted.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+struct StoredKey {
+    kms_key_id: String,
+    #[serde(with = "base64_bytes")]
+    secret: Vec<u8>,
+}
+
+mod base64_bytes {
+    use super::*;
+
+    pub fn serialize<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Loads the session key, generating and storing it if there is none yet.
+///
+/// # Errors
+/// Returns an error if the stored key can't be decrypted, for example because its key manager
+/// key was deleted
+pub async fn load(service: &Service) -> Result<Key, Error> {
+    if let Some(stored) = read(service).await? {
+        return decrypt(service, stored).await;
+    }
+
+    let key = Key::generate();
+    let kms_key_id = service.kms().create_key().await?;
+    let secret = service
+        .kms()
+        .encrypt(&kms_key_id, key.master().to_vec())
+        .await?;
+    let stored = StoredKey { kms_key_id, secret };
+
+    match service.config().session_key_file() {
+        Some(path) => {
+            let contents = serde_json::to_string(&stored).map_err(Error::internal)?;
+            write_private_file(path, &contents)?;
+        }
+        None => {
+            let inserted = sqlx::query(
+                "
+                INSERT INTO server_secrets (name, kms_key_id, secret) VALUES ($1, $2, $3)
+                ON CONFLICT (name) DO NOTHING
+                ",
+            )
+            .bind(SESSION_KEY_NAME)
+            .bind(&stored.kms_key_id)
+            .bind(&stored.secret)
+            .execute(service.db())
+            .await?
+            .rows_affected();
+
+            // Another instance sharing the database stored its key first, which is the one to use.
+            if inserted == 0 {
+                service.kms().delete_key(&stored.kms_key_id).await?;
+                let stored = read(service).await?.ok_or_else(|| {
+                    Error::internal(eyre::eyre!(
+                        "session key disappeared while it was being stored"
+                    ))
+                })?;
+                return decrypt(service, stored).await;
+            }
+        }
+    }
+
+    tracing::info!(target: "nervemq::audit", "Generated a new session key");
+
+    Ok(key)
+}
+
+/// Gets the ID of the key manager key the session key is encrypted with.
+///
+/// # Returns
+/// The key ID, or `None` if no session key has been stored yet
+pub async fn kms_key_id(service: &Service) -> Result<Option<String>, Error> {
+    Ok(read(service).await?.map(|stored| stored.kms_key_id))
+}
+
+/// Reads the stored session key.
+async fn read(service: &Service) -> Result<Option<StoredKey>, Error> {
+    match service.config().session_key_file() {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(Error::internal),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::internal(e)),
+        },
+        None => Ok(
+            sqlx::query_as("SELECT kms_key_id, secret FROM server_secrets WHERE name = $1")
+                .bind(SESSION_KEY_NAME)
+                .fetch_optional(service.db())
+                .await?,
+        ),
+    }
+}
+
+async fn decrypt(service: &Service, stored: StoredKey) -> Result<Key, Error> {
+    let master = service
+        .kms()
+        .decrypt(&stored.kms_key_id, stored.secret)
+        .await?;
+    Key::try_from(master.as_slice()).map_err(Error::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        config::{Config, MEMORY_DB_PATH},
+        kms::memory::InMemoryKeyManager,
+    };
+
+    async fn connect(config: Config) -> Service {
+        Service::connect_with()
+            .config(config)
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let service = connect(Config::in_memory()).await;
+
+        let key = load(&service).await.unwrap();
+        assert_eq!(load(&service).await.unwrap().master(), key.master());
+
+        let (secret,): (Vec<u8>,) = sqlx::query_as("SELECT secret FROM server_secrets")
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        assert_ne!(secret, key.master());
+    }
+
+    #[tokio::test]
+    async fn test_load_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.key");
+        let config: Config = serde_json::from_value(json!({
+            "db_path": MEMORY_DB_PATH,
+            "integrity_check": "off",
+            "session_key_file": path,
+        }))
+        .unwrap();
+        let service = connect(config).await;
+
+        let key = load(&service).await.unwrap();
+        assert!(path.exists());
+        assert_eq!(load(&service).await.unwrap().master(), key.master());
+
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM server_secrets")
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,159 @@
+//! Conditional requests for read-only management endpoints.
+//!
+//! Dashboards poll queue and namespace listings and statistics every few seconds, though most of
+//! the time nothing has changed. Responses of these endpoints carry an `ETag` (a hash of the body)
+//! and a `Last-Modified` time, and requests with a matching `If-None-Match` or an
+//! `If-Modified-Since` that isn't older than the last change get an empty `304 Not Modified`.
+//!
+//! The response is still built on every request, so this saves bandwidth, not work. The time a
+//! response last changed is tracked per user and URL in a [`Registry`], shared by every worker.
+
+use std::time::{Duration, SystemTime};
+
+use actix_identity::IdentityExt;
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{
+            self, CacheControl, CacheDirective, EntityTag, Header, HttpDate, IfModifiedSince,
+            IfNoneMatch,
+        },
+        Method, StatusCode,
+    },
+    middleware::Next,
+    web::Data,
+    HttpResponse,
+};
+
+use crate::{auth::crypto::sha256_hex, registry::Registry, service::Service};
+
+/// Number of responses whose last change is tracked. When there are more, every response counts
+/// as changed just now, which is never wrong, only less effective.
+pub const MAX_TRACKED_RESPONSES: usize = 10_000;
+
+/// The validators of the last response to a user's request for a URL.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+/// Validators of recent responses, by user and URL.
+pub type ValidatorRegistry = Registry<String, Validators>;
+
+/// Middleware that answers conditional `GET` requests with `304 Not Modified` if the response
+/// hasn't changed.
+pub async fn conditional_get(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if req.method() != Method::GET {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    let service = req.app_data::<Data<Service>>().cloned();
+    let user = req
+        .get_identity()
+        .ok()
+        .and_then(|identity| identity.id().ok())
+        .unwrap_or_default();
+    let key = format!("{user}\n{}", req.uri());
+    // Parsing a missing If-None-Match gives an empty list rather than an error.
+    let if_none_match = IfNoneMatch::parse(&req)
+        .ok()
+        .filter(|header| !matches!(header, IfNoneMatch::Items(tags) if tags.is_empty()));
+    let if_modified_since = IfModifiedSince::parse(&req).ok();
+
+    let res = next.call(req).await?;
+    let Some(service) = service.filter(|_| res.status() == StatusCode::OK) else {
+        return Ok(res.map_into_boxed_body());
+    };
+
+    let (req, res) = res.into_parts();
+    let (head, body) = res.into_parts();
+    let body = body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+
+    let hash = sha256_hex(&body);
+    let etag = EntityTag::new_strong(hash[..32].to_owned());
+    let last_modified = last_modified(service.cache_validators(), &key, etag.tag());
+
+    let not_modified = match (if_none_match, if_modified_since) {
+        // If-Modified-Since is ignored when If-None-Match is present.
+        (Some(IfNoneMatch::Any), _) => true,
+        (Some(IfNoneMatch::Items(tags)), _) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        (None, Some(IfModifiedSince(since))) => last_modified <= SystemTime::from(since),
+        (None, None) => false,
+    };
+
+    let mut res = if not_modified {
+        HttpResponse::NotModified().finish()
+    } else {
+        head.set_body(body).map_into_boxed_body()
+    };
+    let headers = res.headers_mut();
+    headers.insert(header::ETAG, etag.to_string().try_into()?);
+    headers.insert(
+        header::LAST_MODIFIED,
+        HttpDate::from(last_modified).to_string().try_into()?,
+    );
+    headers.insert(
+        header::CACHE_CONTROL,
+        CacheControl(vec![CacheDirective::NoCache, CacheDirective::Private])
+            .to_string()
+            .try_into()?,
+    );
+
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// Records the ETag of a response and returns when it last changed.
+///
+/// `Last-Modified` has a precision of one second, so the time is rounded down. A response that
+/// changes twice within a second keeps its `Last-Modified`, which is why clients should prefer the
+/// ETag.
+fn last_modified(validators: &ValidatorRegistry, key: &str, etag: &str) -> SystemTime {
+    if validators.len() >= MAX_TRACKED_RESPONSES {
+        validators.take();
+    }
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut last_modified = SystemTime::UNIX_EPOCH + Duration::from_secs(now.as_secs());
+
+    validators.update(key, |validators| match validators.last_modified {
+        Some(time) if validators.etag == etag => last_modified = time,
+        _ => {
+            validators.etag = etag.to_owned();
+            validators.last_modified = Some(last_modified);
+        }
+    });
+
+    last_modified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_modified() {
+        let validators = ValidatorRegistry::new();
+
+        let first = last_modified(&validators, "a", "1");
+        assert!(first <= SystemTime::now());
+        assert_eq!(last_modified(&validators, "a", "1"), first);
+
+        // Another user's or URL's response doesn't change it.
+        last_modified(&validators, "b", "2");
+        assert_eq!(last_modified(&validators, "a", "1"), first);
+
+        std::thread::sleep(Duration::from_secs(1));
+        let second = last_modified(&validators, "a", "2");
+        assert!(second > first);
+        assert_eq!(last_modified(&validators, "a", "2"), second);
+    }
+}
@@ -0,0 +1,252 @@
+//! A minimal client for the `nervemq send`, `nervemq tail` and `nervemq top` commands.
+//!
+//! It speaks the SQS JSON protocol to a NerveMQ server, and reads queue statistics from the
+//! management API. It authenticates with an API token, sent as
+//! `Authorization: NerveMqApiV1 <token>`, so that queues can be poked at from a shell without
+//! setting up an AWS SDK and SigV4 credentials.
+
+use std::{collections::HashMap, time::Duration};
+
+use hyper::{client::HttpConnector, Body, Request};
+use hyper_rustls::HttpsConnector;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::{
+    queue::QueueStatistics,
+    sqs::{
+        method::{Method, SQS_METHOD_PREFIX},
+        types::SqsMessageAttribute,
+    },
+};
+
+/// How long a request may take, on top of its long-polling wait.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A message received by [`Client::receive_messages`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReceivedMessage {
+    pub message_id: String,
+    pub receipt_handle: String,
+    pub body: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    #[serde(default)]
+    pub message_attributes: HashMap<String, SqsMessageAttribute>,
+}
+
+/// Options of [`Client::receive_messages`].
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReceiveOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_number_of_messages: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait_time_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility_timeout: Option<u64>,
+    /// Only receive the messages matching this message selector
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_selector: Option<String>,
+    pub attribute_names: Vec<String>,
+    pub message_attribute_names: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReceiveMessageResponse {
+    messages: Vec<ReceivedMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    code: String,
+    message: String,
+}
+
+/// A client of the SQS and statistics APIs of a NerveMQ server.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: hyper::Client<HttpsConnector<HttpConnector>>,
+    host: Url,
+    endpoint: Url,
+    authorization: String,
+}
+
+impl Client {
+    /// Creates a client of the server at `host`.
+    ///
+    /// # Arguments
+    /// * `host` - Base URL of the server, as in its `host` setting
+    /// * `token` - API token, `nervemq_<access key>_<secret key>`
+    pub fn new(host: &Url, token: &str) -> eyre::Result<Self> {
+        let mut endpoint = host.clone();
+        endpoint
+            .path_segments_mut()
+            .map_err(|_| eyre::eyre!("{host} can't be a base URL"))?
+            .pop_if_empty()
+            .push(crate::sqs::queue_url::SQS_PATH_SEGMENT);
+
+        let http = hyper::Client::builder().build(
+            hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .build(),
+        );
+
+        Ok(Self {
+            http,
+            host: host.clone(),
+            endpoint,
+            authorization: format!("NerveMqApiV1 {}", token.trim()),
+        })
+    }
+
+    /// Returns the base URL of the server.
+    pub fn host(&self) -> &Url {
+        &self.host
+    }
+
+    /// Resolves a queue given by name, in the token's namespace, or by URL, to its URL.
+    pub async fn queue_url(&self, queue: &str) -> eyre::Result<Url> {
+        if let Ok(url) = Url::parse(queue) {
+            return Ok(url);
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct GetQueueUrlResponse {
+            queue_url: Url,
+        }
+
+        let response: GetQueueUrlResponse = self
+            .call(
+                Method::GetQueueUrl,
+                json!({ "QueueName": queue }),
+                Duration::ZERO,
+            )
+            .await?;
+
+        Ok(response.queue_url)
+    }
+
+    /// Sends a message.
+    ///
+    /// # Returns
+    /// The ID of the message
+    pub async fn send_message(
+        &self,
+        queue_url: &Url,
+        body: &str,
+        attributes: &HashMap<String, SqsMessageAttribute>,
+        delay_seconds: Option<u64>,
+    ) -> eyre::Result<String> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct SendMessageResponse {
+            message_id: String,
+        }
+
+        let response: SendMessageResponse = self
+            .call(
+                Method::SendMessage,
+                json!({
+                    "QueueUrl": queue_url,
+                    "MessageBody": body,
+                    "MessageAttributes": attributes,
+                    "DelaySeconds": delay_seconds,
+                }),
+                Duration::ZERO,
+            )
+            .await?;
+
+        Ok(response.message_id)
+    }
+
+    /// Receives messages, waiting up to `options.wait_time_seconds` for some to arrive.
+    pub async fn receive_messages(
+        &self,
+        queue_url: &Url,
+        options: &ReceiveOptions,
+    ) -> eyre::Result<Vec<ReceivedMessage>> {
+        let mut request = serde_json::to_value(options)?;
+        request["QueueUrl"] = json!(queue_url);
+        let wait = Duration::from_secs(options.wait_time_seconds.unwrap_or_default());
+
+        let response: ReceiveMessageResponse =
+            self.call(Method::ReceiveMessage, request, wait).await?;
+
+        Ok(response.messages)
+    }
+
+    /// Deletes, that is acknowledges, a received message.
+    pub async fn delete_message(&self, queue_url: &Url, receipt_handle: &str) -> eyre::Result<()> {
+        let _: Value = self
+            .call(
+                Method::DeleteMessage,
+                json!({ "QueueUrl": queue_url, "ReceiptHandle": receipt_handle }),
+                Duration::ZERO,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets the statistics of every queue the token's user has access to.
+    pub async fn queue_statistics(&self) -> eyre::Result<Vec<QueueStatistics>> {
+        let mut url = self.host.clone();
+        url.path_segments_mut()
+            .map_err(|_| eyre::eyre!("{} can't be a base URL", self.host))?
+            .pop_if_empty()
+            .extend(["stats", "queue"]);
+
+        let request = Request::get(url.as_str())
+            .header(hyper::header::AUTHORIZATION, &self.authorization)
+            .body(Body::empty())?;
+
+        let response = tokio::time::timeout(CLIENT_TIMEOUT, self.http.request(request)).await??;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+
+        if !status.is_success() {
+            eyre::bail!("queue statistics: server responded with {status}");
+        }
+
+        let statistics: HashMap<String, QueueStatistics> = serde_json::from_slice(&body)?;
+
+        Ok(statistics.into_values().collect())
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        request: Value,
+        wait: Duration,
+    ) -> eyre::Result<T> {
+        let request = Request::post(self.endpoint.as_str())
+            .header(hyper::header::CONTENT_TYPE, "application/x-amz-json-1.0")
+            .header(
+                "x-amz-target",
+                format!("{SQS_METHOD_PREFIX}.{}", method.as_ref()),
+            )
+            .header(hyper::header::AUTHORIZATION, &self.authorization)
+            .body(Body::from(serde_json::to_vec(&request)?))?;
+
+        let response =
+            tokio::time::timeout(CLIENT_TIMEOUT + wait, self.http.request(request)).await??;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+
+        if !status.is_success() {
+            match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(ErrorResponse { code, message }) => eyre::bail!("{method:?}: {code}: {message}"),
+                Err(_) => eyre::bail!("{method:?}: server responded with {status}"),
+            }
+        }
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
@@ -0,0 +1,133 @@
+//! The clock the service tells time with.
+//!
+//! Visibility timeouts, redelivery delays, heartbeats and expiries are stored as absolute Unix
+//! times, which the service computes from its [`Clock`] and binds to its queries instead of
+//! reading the time in SQL with `unixepoch('now')`. Every deadline is then set and checked
+//! against the same clock, and tests can move it with a [`ManualClock`].
+//!
+//! The [`SystemClock`] reads the wall clock once, when it is created, and counts on from there
+//! with the monotonic clock, so the host's wall clock jumping while the server runs neither
+//! makes in-flight messages visible early nor hides them for longer.
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A source of the current time.
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+
+    /// Returns the current time as a Unix timestamp, in seconds.
+    fn unix_now(&self) -> i64 {
+        self.now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+}
+
+/// The wall clock as of its creation, advanced by the monotonic clock.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    anchor: SystemTime,
+    started: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            anchor: SystemTime::now(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        self.anchor + self.started.elapsed()
+    }
+}
+
+/// A clock that only moves when told to, for tests. Clones share the same time.
+#[derive(Debug, Clone)]
+#[allow(unused)]
+pub struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+#[allow(unused)]
+impl ManualClock {
+    /// Creates a clock stopped at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Sets the time, which may go backwards like a wall clock being corrected.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    /// Moves the time forward.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += by;
+    }
+
+    /// Moves the time backward.
+    pub fn rewind(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now -= by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_clock() {
+        let clock = SystemClock::new();
+        let first = clock.now();
+        let second = clock.now();
+        assert!(second >= first);
+        assert!(
+            SystemTime::now()
+                .duration_since(first)
+                .unwrap_or_default()
+                .as_secs()
+                < 5
+        );
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let clock = ManualClock::new(start);
+        let shared = clock.clone();
+
+        assert_eq!(clock.unix_now(), 1_000);
+        shared.advance(Duration::from_secs(30));
+        assert_eq!(clock.unix_now(), 1_030);
+        shared.rewind(Duration::from_secs(60));
+        assert_eq!(clock.unix_now(), 970);
+        shared.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
@@ -0,0 +1,134 @@
+//! Compression of message bodies at rest.
+//!
+//! Queues can set the `CompressionThreshold` attribute to have message bodies of at least that
+//! many bytes stored zstd-compressed, which keeps verbose payloads like JSON from growing the
+//! database file. Compressed bodies are stored as blobs with the message's `compressed` flag set,
+//! and are decompressed transparently when messages are received, listed or read from the history.
+//!
+//! Bodies that don't get smaller when compressed are stored as they are.
+
+use std::borrow::Cow;
+
+use sqlx::{
+    encode::IsNull,
+    error::BoxDynError,
+    sqlite::{SqliteArgumentValue, SqliteTypeInfo},
+    Encode, Sqlite, Type,
+};
+
+use crate::error::Error;
+
+/// zstd compression level used for message bodies. Low levels are fast enough to not slow down
+/// sends noticeably, and already do well on repetitive payloads.
+pub const COMPRESSION_LEVEL: i32 = 3;
+
+/// A message body in the form it is stored in the database.
+#[derive(Debug)]
+pub enum StoredBody<'a> {
+    /// The body as sent, stored as text
+    Plain(&'a str),
+    /// The zstd-compressed body, stored as a blob
+    Compressed(Vec<u8>),
+}
+
+impl<'a> StoredBody<'a> {
+    /// Prepares a body for storage, compressing it if it is at least `threshold` bytes long and
+    /// compression makes it smaller.
+    ///
+    /// # Arguments
+    /// * `body` - Message body
+    /// * `threshold` - The queue's compression threshold, or `None` if it doesn't compress bodies
+    pub fn new(body: &'a str, threshold: Option<u64>) -> Result<Self, Error> {
+        match threshold {
+            Some(threshold) if body.len() as u64 >= threshold => {
+                let compressed = zstd::bulk::compress(body.as_bytes(), COMPRESSION_LEVEL)
+                    .map_err(Error::internal)?;
+
+                if compressed.len() < body.len() {
+                    Ok(Self::Compressed(compressed))
+                } else {
+                    Ok(Self::Plain(body))
+                }
+            }
+            _ => Ok(Self::Plain(body)),
+        }
+    }
+
+    /// Whether the body is stored compressed.
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Self::Compressed(_))
+    }
+
+    /// Size of the body as stored, in bytes.
+    pub fn stored_len(&self) -> usize {
+        match self {
+            Self::Plain(body) => body.len(),
+            Self::Compressed(body) => body.len(),
+        }
+    }
+}
+
+impl Type<Sqlite> for StoredBody<'_> {
+    fn type_info() -> SqliteTypeInfo {
+        <Vec<u8> as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <Vec<u8> as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for StoredBody<'q> {
+    fn encode_by_ref(
+        &self,
+        args: &mut Vec<SqliteArgumentValue<'q>>,
+    ) -> Result<IsNull, BoxDynError> {
+        args.push(match self {
+            Self::Plain(body) => SqliteArgumentValue::Text(Cow::Borrowed(*body)),
+            Self::Compressed(body) => SqliteArgumentValue::Blob(Cow::Owned(body.clone())),
+        });
+
+        Ok(IsNull::No)
+    }
+}
+
+/// Restores a message body read from the database.
+///
+/// # Arguments
+/// * `body` - Stored body
+/// * `compressed` - Whether the body is stored compressed
+pub fn decode_body(body: Vec<u8>, compressed: bool) -> Result<String, Error> {
+    let body = if compressed {
+        zstd::stream::decode_all(body.as_slice()).map_err(Error::internal)?
+    } else {
+        body
+    };
+
+    String::from_utf8(body).map_err(Error::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_body_round_trip() {
+        let body = "{\"type\": \"invoice\", \"lines\": []}".repeat(100);
+
+        let stored = StoredBody::new(&body, Some(1024)).unwrap();
+        let StoredBody::Compressed(compressed) = stored else {
+            panic!("expected the body to be compressed");
+        };
+        assert!(compressed.len() < body.len());
+        assert_eq!(decode_body(compressed, true).unwrap(), body);
+
+        assert!(!StoredBody::new(&body, None).unwrap().is_compressed());
+        assert!(!StoredBody::new("short", Some(1024))
+            .unwrap()
+            .is_compressed());
+        // Incompressible bodies are stored as they are.
+        assert!(!StoredBody::new("x", Some(0)).unwrap().is_compressed());
+
+        assert_eq!(decode_body(b"plain".to_vec(), false).unwrap(), "plain");
+    }
+}
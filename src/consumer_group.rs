@@ -0,0 +1,38 @@
+//! Consumer groups.
+//!
+//! By default, the consumers of a queue compete for its messages: each message is delivered to
+//! one consumer at a time and is gone once it is deleted. Queues can opt into fan-out
+//! consumption instead by creating named consumer groups. Every group receives every message of
+//! the queue and keeps its own delivery state (receive count, visibility timeout and deletion),
+//! so groups consume the queue independently of each other. Consumers within a group still
+//! compete for that group's deliveries. The first group of a queue takes over the messages
+//! already in it, while groups created later start with the messages sent after them.
+//!
+//! Consumers select a group with the `ConsumerGroup` query parameter of the queue URL, e.g.
+//! `http://localhost:8080/sqs/ns/queue?ConsumerGroup=analytics`, so that existing SQS clients
+//! only need a different queue URL. Receipt handles are only valid within the group that
+//! received the message.
+//!
+//! A message is removed from the queue once every group has deleted it or used up the queue's
+//! retries on it. Messages are not moved to the dead-letter queue on behalf of a single group.
+//!
+//! While a queue has consumer groups, receiving from it without a group is rejected, since it
+//! would take messages away from the groups. Deleting the last group returns the queue to
+//! competing consumption, starting from the messages no group had finished with.
+
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// Query parameter of a queue URL that selects the consumer group.
+pub const CONSUMER_GROUP_PARAMETER: &str = "ConsumerGroup";
+
+/// A consumer group of a queue.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerGroup {
+    pub name: String,
+    /// Unix timestamp of the group's creation
+    pub created_at: u64,
+    /// Number of messages in the queue the group has not finished with yet
+    pub pending: u64,
+}
@@ -0,0 +1,129 @@
+//! Consumer liveness.
+//!
+//! Consumers can optionally register with the queues they consume by sending heartbeats with
+//! `PUT /api/consumers/{namespace}/{queue}/{name}`, where the name identifies the consumer
+//! process (e.g. a host name). A consumer is alive while its last heartbeat is more recent than
+//! [`CONSUMER_TTL`], so consumers should send one at least every half of it. Consumers that shut
+//! down cleanly deregister with `DELETE` on the same path.
+//!
+//! Queues that have registered consumers, none of which is alive, while messages are waiting in
+//! them are unattended. The dashboard overview lists them, and every
+//! [consumer check interval](crate::config::TaskIntervals::consumer_check) a background task
+//! publishes an administrative `queueUnattended` event for each queue that has become unattended,
+//! which webhooks can deliver to alerting systems. Queues that no consumer ever registered with are not watched,
+//! since registering is optional.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::{error::Error, events::Event, service::Service, tasks};
+
+/// How long after its last heartbeat a consumer is still considered alive.
+pub const CONSUMER_TTL: Duration = Duration::from_secs(60);
+
+/// Maximum length of a consumer name.
+pub const MAX_CONSUMER_NAME_LENGTH: usize = 128;
+
+/// A consumer registered with a queue.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Consumer {
+    pub name: String,
+    pub namespace: String,
+    pub queue: String,
+    /// Unix timestamp of the first heartbeat
+    pub registered_at: u64,
+    /// Unix timestamp of the last heartbeat
+    pub last_heartbeat_at: u64,
+    /// Whether the last heartbeat is more recent than [`CONSUMER_TTL`]
+    pub alive: bool,
+}
+
+/// A queue with waiting messages whose registered consumers are all gone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct UnattendedQueue {
+    #[serde(skip)]
+    pub id: u64,
+    pub namespace: String,
+    pub queue: String,
+    /// Number of messages that can be received
+    pub depth: u64,
+}
+
+/// Checks that a consumer name is 1 to [`MAX_CONSUMER_NAME_LENGTH`] characters of
+/// `A-Z a-z 0-9 - _ . :`.
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_CONSUMER_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+
+    if !valid {
+        return Err(Error::invalid_parameter(format!(
+            "consumer name: {name} must be 1 to {MAX_CONSUMER_NAME_LENGTH} characters of \
+             A-Z a-z 0-9 - _ . :"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Publishes a `queueUnattended` event for every queue that has become unattended, every
+/// consumer check interval until the process exits.
+pub fn spawn(service: &Service) {
+    // Queues that were unattended at the last check, so that each is only reported once until
+    // it is attended again.
+    let unattended = Arc::new(Mutex::new(HashSet::new()));
+
+    let period = service.config().tasks().consumer_check;
+    tasks::spawn_periodic(service, "consumers", period, move |service| {
+        let unattended = unattended.clone();
+        async move {
+            let queues = service.unattended_queues(None).await?;
+            let mut unattended = unattended.lock().unwrap_or_else(|e| e.into_inner());
+            for queue in &queues {
+                if !unattended.contains(&queue.id) {
+                    tracing::warn!(
+                        namespace = queue.namespace,
+                        queue = queue.queue,
+                        depth = queue.depth,
+                        "Queue has waiting messages but no live consumers"
+                    );
+                    service.events().publish(Event::QueueUnattended {
+                        queue: queue.id,
+                        namespace: queue.namespace.clone(),
+                        name: queue.queue.clone(),
+                        depth: queue.depth,
+                    });
+                }
+            }
+            *unattended = queues.into_iter().map(|queue| queue.id).collect();
+            Ok(())
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("worker-1.example.com:8080").is_ok());
+
+        for invalid in ["", "has space", "slash/", &"a".repeat(129)] {
+            assert!(
+                matches!(validate_name(invalid), Err(Error::InvalidParameter { .. })),
+                "{invalid:?}"
+            );
+        }
+    }
+}
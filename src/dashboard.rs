@@ -0,0 +1,150 @@
+//! The dashboard, served from the binary.
+//!
+//! With the `dashboard` feature, the static export of the dashboard (`npm run export`, which
+//! writes it to `out/`) is embedded in the binary and served at `/`, so that a single binary is
+//! all a deployment needs for a UI. Paths the API doesn't serve are looked up in the export as
+//! files, as pages (`/queues` is `queues.html`) or as directories with an index, and queue pages
+//! are served the page that renders any queue. Everything else gets the export's `404.html`.
+//!
+//! It can be turned off with [`dashboard`](crate::config::Config::dashboard), for deployments that
+//! host the dashboard elsewhere.
+
+use actix_web::{
+    http::{
+        header::{self, CacheControl, CacheDirective, EntityTag, Header, IfNoneMatch},
+        Method, StatusCode,
+    },
+    web, HttpRequest, HttpResponse, Route,
+};
+use rust_embed::{EmbeddedFile, RustEmbed};
+
+/// Page the dashboard renders every queue with, see `app/(dashboard)/queues/[...queueId]`.
+const QUEUE_PAGE: &str = "queues/_/_.html";
+
+/// Page served for paths that are neither in the API nor in the dashboard.
+const NOT_FOUND_PAGE: &str = "404.html";
+
+/// Directory of the export whose files are named by their content, and never change.
+const IMMUTABLE_PREFIX: &str = "_next/static/";
+
+#[derive(RustEmbed)]
+#[folder = "out/"]
+#[allow_missing = true]
+struct Assets;
+
+/// Finds the file of the export to serve for a request path.
+///
+/// # Arguments
+/// * `path` - Path of the request
+/// * `exists` - Whether the export has a file
+///
+/// # Returns
+/// The name of the file, or `None` if the dashboard has no such page
+fn resolve(path: &str, exists: impl Fn(&str) -> bool) -> Option<String> {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return Some("index.html".to_owned());
+    }
+
+    let candidates = [
+        path.to_owned(),
+        format!("{path}.html"),
+        format!("{path}/index.html"),
+    ];
+    if let Some(found) = candidates.into_iter().find(|candidate| exists(candidate)) {
+        return Some(found);
+    }
+
+    match path.split('/').collect::<Vec<_>>()[..] {
+        ["queues", _, _] => Some(QUEUE_PAGE.to_owned()),
+        _ => None,
+    }
+}
+
+/// Builds the response with a file of the export, or `304 Not Modified` if the client has it.
+fn respond(req: &HttpRequest, name: &str, file: EmbeddedFile, status: StatusCode) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(file.metadata.sha256_hash()));
+    let cache_control = if name.starts_with(IMMUTABLE_PREFIX) {
+        CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(365 * 24 * 60 * 60),
+            CacheDirective::Extension("immutable".to_owned(), None),
+        ])
+    } else {
+        CacheControl(vec![CacheDirective::NoCache])
+    };
+
+    let fresh = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        Err(_) => false,
+    };
+    if fresh && status.is_success() {
+        return HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header(cache_control)
+            .finish();
+    }
+
+    let mut res = HttpResponse::build(status);
+    res.insert_header((header::CONTENT_TYPE, file.metadata.mimetype()))
+        .insert_header(header::ETag(etag))
+        .insert_header(cache_control);
+
+    if req.method() == Method::HEAD {
+        res.finish()
+    } else {
+        res.body(file.data.into_owned())
+    }
+}
+
+async fn serve(req: HttpRequest) -> HttpResponse {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    if let Some((name, file)) = resolve(req.path(), |name| Assets::get(name).is_some())
+        .and_then(|name| Assets::get(&name).map(|file| (name, file)))
+    {
+        return respond(&req, &name, file, StatusCode::OK);
+    }
+
+    match Assets::get(NOT_FOUND_PAGE) {
+        Some(file) => respond(&req, NOT_FOUND_PAGE, file, StatusCode::NOT_FOUND),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Serves the dashboard, as the default service of the application.
+pub fn service() -> Route {
+    web::route().to(serve)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let files = [
+            "index.html",
+            "queues.html",
+            "_next/static/app.js",
+            QUEUE_PAGE,
+        ];
+        let resolve = |path| resolve(path, |name| files.contains(&name));
+
+        assert_eq!(resolve("/").as_deref(), Some("index.html"));
+        assert_eq!(resolve("/queues").as_deref(), Some("queues.html"));
+        assert_eq!(
+            resolve("/_next/static/app.js").as_deref(),
+            Some("_next/static/app.js")
+        );
+        assert_eq!(
+            resolve("/queues/default/orders").as_deref(),
+            Some(QUEUE_PAGE)
+        );
+        assert_eq!(resolve("/queues/default"), None);
+        assert_eq!(resolve("/missing"), None);
+    }
+}
@@ -4,8 +4,19 @@
 //! error cases in the application, from API validation to database operations.
 //! It uses the `snafu` crate for error handling patterns.
 
+use serde::Serialize;
 use snafu::Snafu;
 
+use crate::request_id::RequestId;
+
+/// How long clients are told to wait before retrying a request that the database was too busy to
+/// serve.
+pub const OVERLOADED_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Primary SQLite result codes of a database that is locked by other connections.
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
 /// The main error enum that represents all possible errors in the application.
 /// Each variant includes context-specific information and appropriate error messages.
 #[derive(Debug, Snafu)]
@@ -13,9 +24,16 @@ pub enum Error {
     #[snafu(display("Unauthorized"))]
     Unauthorized,
 
+    /// The caller may make the request, but not with these parameters.
+    #[snafu(display("Forbidden: {message}"))]
+    Forbidden { message: String },
+
     #[snafu(display("Resource not found: {resource}"))]
     NotFound { resource: String },
 
+    #[snafu(display("Resource not found: queue {queue} in namespace {namespace}"))]
+    QueueNotFound { queue: String, namespace: String },
+
     #[snafu(display("Internal server error"))]
     InternalServerError {
         #[snafu(source(false))]
@@ -34,6 +52,31 @@ pub enum Error {
         source: sqlx::migrate::MigrateError,
     },
 
+    #[snafu(display("Database {path} is corrupt: {details}"))]
+    DatabaseCorrupt { path: String, details: String },
+
+    #[snafu(display(
+        "A database key is configured, but NerveMQ was built without the `sqlcipher` feature"
+    ))]
+    EncryptionUnsupported,
+
+    #[snafu(display(
+        "{setting} is configured, but NerveMQ was built without the `{feature}` feature"
+    ))]
+    FeatureUnsupported {
+        setting: &'static str,
+        feature: &'static str,
+    },
+
+    #[snafu(display("Another process is migrating the database"))]
+    MigrationInProgress,
+
+    #[snafu(display("Migration pre-flight check failed: {message}"))]
+    PreflightFailed { message: String },
+
+    #[snafu(display("Invalid provisioning file {path}: {message}"))]
+    InvalidProvisionFile { path: String, message: String },
+
     #[snafu(display("Identity {key_id} not found"))]
     IdentityNotFound { key_id: String },
 
@@ -59,19 +102,108 @@ pub enum Error {
     #[snafu(display("Invalid parameter: {message}"))]
     InvalidParameter { message: String },
 
+    #[snafu(display("Invalid request: {message}"))]
+    InvalidRequest { message: String },
+
     #[snafu(display("Invalid request method: {message}"))]
     InvalidMethod { message: String },
 
     #[snafu(display("Missing parameter: {message}"))]
     MissingParameter { message: String },
+
+    #[snafu(display("Receipt handle {handle:?} is not valid"))]
+    ReceiptHandleIsInvalid { handle: String },
+
+    #[snafu(display("Too many entries in batch request: {count} (maximum is {max})"))]
+    TooManyEntriesInBatchRequest { count: usize, max: usize },
+
+    #[snafu(display("Batch entry ids must be distinct: {id} appears more than once"))]
+    BatchEntryIdsNotDistinct { id: String },
+
+    #[snafu(display("Batch request must contain at least one entry"))]
+    EmptyBatchRequest,
+
+    #[snafu(display("Invalid attribute name {name:?}: {reason}"))]
+    InvalidAttributeName { name: String, reason: String },
+
+    #[snafu(display("Too many message attributes: {count} (maximum is {max})"))]
+    TooManyMessageAttributes { count: usize, max: usize },
+
+    #[snafu(display(
+        "Message must be shorter than {max} bytes, including attributes ({size} bytes)"
+    ))]
+    MessageTooLong { size: u64, max: u64 },
+
+    #[snafu(display(
+        "Batch requests must be shorter than {max} bytes, adding up all messages ({size} bytes)"
+    ))]
+    BatchRequestTooLong { size: u64, max: u64 },
+
+    #[snafu(display("{field} does not match the data it was sent with"))]
+    ChecksumMismatch { field: String },
+
+    #[snafu(display("Query took longer than its limit of {limit:?} and was interrupted"))]
+    QueryTimedOut { limit: std::time::Duration },
+
+    #[snafu(display("Quota exceeded: at most {limit} {resource}"))]
+    QuotaExceeded { resource: String, limit: u64 },
+
+    #[snafu(display("Over limit: at most {limit} {resource}"))]
+    OverLimit { resource: String, limit: u64 },
+
+    #[snafu(display("Rate exceeded, retry in {retry_after:?}"))]
+    Throttled { retry_after: std::time::Duration },
+
+    #[snafu(display(
+        "Only one PurgeQueue operation on {queue} is allowed every {} seconds",
+        crate::service::PURGE_COOLDOWN.as_secs()
+    ))]
+    PurgeQueueInProgress {
+        queue: String,
+        retry_after: std::time::Duration,
+    },
+
+    #[snafu(display("Service unavailable: {reason}"))]
+    Unavailable {
+        reason: String,
+        retry_after: std::time::Duration,
+    },
+
+    #[snafu(display("The database is overloaded, retry in {retry_after:?}"))]
+    Overloaded {
+        #[snafu(source)]
+        source: sqlx::Error,
+        retry_after: std::time::Duration,
+    },
 }
 
+/// Database errors that only say that the database was too busy, because no connection became
+/// free or a lock wasn't released in time, become [`Error::Overloaded`] so that clients back off
+/// instead of getting an opaque internal error.
 impl From<sqlx::Error> for Error {
     fn from(source: sqlx::Error) -> Self {
+        if is_overloaded(&source) {
+            return Self::Overloaded {
+                source,
+                retry_after: OVERLOADED_RETRY_AFTER,
+            };
+        }
         Self::Sqlx { source }
     }
 }
 
+fn is_overloaded(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::PoolTimedOut => true,
+        // Extended result codes, like SQLITE_BUSY_SNAPSHOT, keep the primary code in the low byte.
+        sqlx::Error::Database(e) => e
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)),
+        _ => false,
+    }
+}
+
 impl From<eyre::Report> for Error {
     fn from(e: eyre::Report) -> Self {
         Self::InternalServerError { source: Some(e) }
@@ -127,12 +259,24 @@ impl Error {
         }
     }
 
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::Forbidden {
+            message: message.into(),
+        }
+    }
+
     pub fn invalid_parameter(message: impl Into<String>) -> Self {
         Self::InvalidParameter {
             message: message.into(),
         }
     }
 
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::InvalidRequest {
+            message: message.into(),
+        }
+    }
+
     pub fn missing_parameter(message: impl Into<String>) -> Self {
         Self::MissingParameter {
             message: message.into(),
@@ -141,8 +285,17 @@ impl Error {
 
     /// Creates a not found error specifically for queues within a namespace
     pub fn queue_not_found(queue: impl Into<String>, namespace: impl Into<String>) -> Self {
-        Self::NotFound {
-            resource: format!("queue {} in namespace {}", queue.into(), namespace.into()),
+        Self::QueueNotFound {
+            queue: queue.into(),
+            namespace: namespace.into(),
+        }
+    }
+
+    /// Creates an error for a request that can't be served right now but may be retried later
+    pub fn unavailable(reason: impl Into<String>, retry_after: std::time::Duration) -> Self {
+        Self::Unavailable {
+            reason: reason.into(),
+            retry_after,
         }
     }
 
@@ -162,19 +315,382 @@ impl actix_web::ResponseError for Error {
             Self::Unauthorized | Self::UserNotFound { .. } | Self::IdentityNotFound { .. } => {
                 actix_web::http::StatusCode::UNAUTHORIZED
             }
-            Self::NotFound { .. } => actix_web::http::StatusCode::NOT_FOUND,
+            Self::NotFound { .. } | Self::QueueNotFound { .. } => {
+                actix_web::http::StatusCode::NOT_FOUND
+            }
 
             Self::MissingHeader { .. }
             | Self::MissingParameter { .. }
             | Self::InvalidHeader { .. }
             | Self::InvalidMethod { .. }
-            | Self::InvalidParameter { .. } => actix_web::http::StatusCode::BAD_REQUEST,
+            | Self::InvalidParameter { .. }
+            | Self::InvalidRequest { .. }
+            | Self::InvalidProvisionFile { .. }
+            | Self::ReceiptHandleIsInvalid { .. }
+            | Self::TooManyEntriesInBatchRequest { .. }
+            | Self::BatchEntryIdsNotDistinct { .. }
+            | Self::EmptyBatchRequest
+            | Self::InvalidAttributeName { .. }
+            | Self::TooManyMessageAttributes { .. }
+            | Self::MessageTooLong { .. }
+            | Self::BatchRequestTooLong { .. }
+            | Self::ChecksumMismatch { .. } => actix_web::http::StatusCode::BAD_REQUEST,
             Self::PayloadTooLarge => actix_web::http::StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Throttled { .. } => actix_web::http::StatusCode::TOO_MANY_REQUESTS,
+            // SQS answers purges that come too soon with 403 as well.
+            Self::QuotaExceeded { .. }
+            | Self::OverLimit { .. }
+            | Self::PurgeQueueInProgress { .. }
+            | Self::Forbidden { .. } => actix_web::http::StatusCode::FORBIDDEN,
+            Self::Unavailable { .. }
+            | Self::Overloaded { .. }
+            | Self::MigrationInProgress
+            | Self::QueryTimedOut { .. } => actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
 
             Self::MigrationError { .. }
+            | Self::DatabaseCorrupt { .. }
+            | Self::EncryptionUnsupported
+            | Self::FeatureUnsupported { .. }
+            | Self::PreflightFailed { .. }
             | Self::InternalServerError { .. }
             | Self::Sqlx { .. }
             | Self::Whatever { .. } => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        let mut res = actix_web::HttpResponse::build(self.status_code());
+
+        if let Self::Unavailable { retry_after, .. }
+        | Self::Overloaded { retry_after, .. }
+        | Self::Throttled { retry_after }
+        | Self::PurgeQueueInProgress { retry_after, .. } = self
+        {
+            res.insert_header((
+                actix_web::http::header::RETRY_AFTER,
+                retry_after_secs(*retry_after).to_string(),
+            ));
+        }
+
+        res.json(self.body())
+    }
+}
+
+/// Rounds a delay up to whole seconds, as `Retry-After` expects, and at least one.
+fn retry_after_secs(retry_after: std::time::Duration) -> u64 {
+    (retry_after.as_secs_f64().ceil() as u64).max(1)
+}
+
+/// Code shared by all internal errors.
+const INTERNAL_ERROR_CODE: &str = "InternalServerError";
+
+/// The JSON body of error responses.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Machine-readable error code
+    pub code: &'static str,
+    /// Human-readable description of the error
+    pub message: String,
+    /// Structured information about the error, depending on the code
+    pub details: Option<serde_json::Value>,
+    /// ID of the request that failed, see [`crate::request_id`]
+    pub request_id: Option<String>,
+}
+
+impl Error {
+    /// Returns the machine-readable code of the error. Internal errors all have the same code, so
+    /// that they don't reveal details about the server.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized => "Unauthorized",
+            // Queues are resources like any other to the native API, only SQS tells them apart.
+            Self::NotFound { .. } | Self::QueueNotFound { .. } => "NotFound",
+            Self::UserNotFound { .. } => "UserNotFound",
+            Self::IdentityNotFound { .. } => "IdentityNotFound",
+            Self::PayloadTooLarge => "PayloadTooLarge",
+            Self::MissingHeader { .. } => "MissingHeader",
+            Self::InvalidHeader { .. } => "InvalidHeader",
+            Self::InvalidParameter { .. } => "InvalidParameter",
+            Self::InvalidRequest { .. } => "InvalidRequest",
+            Self::InvalidMethod { .. } => "InvalidMethod",
+            Self::MissingParameter { .. } => "MissingParameter",
+            Self::InvalidProvisionFile { .. } => "InvalidProvisionFile",
+            Self::ReceiptHandleIsInvalid { .. } => "ReceiptHandleIsInvalid",
+            Self::TooManyEntriesInBatchRequest { .. } => "TooManyEntriesInBatchRequest",
+            Self::BatchEntryIdsNotDistinct { .. } => "BatchEntryIdsNotDistinct",
+            Self::EmptyBatchRequest => "EmptyBatchRequest",
+            Self::InvalidAttributeName { .. } => "InvalidAttributeName",
+            // SQS reports both of these as invalid parameter values.
+            Self::TooManyMessageAttributes { .. } | Self::MessageTooLong { .. } => {
+                "InvalidParameterValue"
+            }
+            Self::BatchRequestTooLong { .. } => "BatchRequestTooLong",
+            Self::ChecksumMismatch { .. } => "ChecksumMismatch",
+            Self::QueryTimedOut { .. } => "QueryTimedOut",
+            Self::QuotaExceeded { .. } => "QuotaExceeded",
+            Self::OverLimit { .. } => "OverLimit",
+            Self::Throttled { .. } => "ThrottlingException",
+            Self::PurgeQueueInProgress { .. } => "PurgeQueueInProgress",
+            Self::Forbidden { .. } => "Forbidden",
+            Self::Unavailable { .. } => "Unavailable",
+            Self::Overloaded { .. } => "ServiceUnavailable",
+            Self::MigrationInProgress => "MigrationInProgress",
+            Self::MigrationError { .. }
+            | Self::DatabaseCorrupt { .. }
+            | Self::EncryptionUnsupported
+            | Self::FeatureUnsupported { .. }
+            | Self::PreflightFailed { .. }
+            | Self::InternalServerError { .. }
+            | Self::Sqlx { .. }
+            | Self::Whatever { .. } => INTERNAL_ERROR_CODE,
+        }
+    }
+
+    /// Returns the code SQS uses for the error, which SDKs read to decide whether to retry it.
+    /// Errors without an SQS counterpart get the closest one.
+    pub fn sqs_code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized | Self::Forbidden { .. } => "AccessDeniedException",
+            Self::UserNotFound { .. } | Self::IdentityNotFound { .. } => "InvalidClientTokenId",
+            Self::QueueNotFound { .. } => "QueueDoesNotExist",
+            Self::NotFound { .. } => "ResourceNotFoundException",
+            Self::ReceiptHandleIsInvalid { .. } => "ReceiptHandleIsInvalid",
+            Self::MissingHeader { .. } | Self::MissingParameter { .. } => "MissingParameter",
+            Self::InvalidMethod { .. } => "UnsupportedOperation",
+            Self::TooManyEntriesInBatchRequest { .. } => "TooManyEntriesInBatchRequest",
+            Self::BatchEntryIdsNotDistinct { .. } => "BatchEntryIdsNotDistinct",
+            Self::EmptyBatchRequest => "EmptyBatchRequest",
+            Self::BatchRequestTooLong { .. } => "BatchRequestTooLong",
+            Self::InvalidAttributeName { .. } => "InvalidAttributeName",
+            Self::InvalidHeader { .. }
+            | Self::InvalidParameter { .. }
+            | Self::InvalidRequest { .. }
+            | Self::InvalidProvisionFile { .. }
+            | Self::TooManyMessageAttributes { .. }
+            | Self::MessageTooLong { .. }
+            | Self::ChecksumMismatch { .. }
+            | Self::PayloadTooLarge => "InvalidParameterValue",
+            Self::QuotaExceeded { .. } | Self::OverLimit { .. } => "OverLimit",
+            Self::Throttled { .. } => "RequestThrottled",
+            Self::PurgeQueueInProgress { .. } => "PurgeQueueInProgress",
+            Self::Unavailable { .. }
+            | Self::Overloaded { .. }
+            | Self::MigrationInProgress
+            | Self::QueryTimedOut { .. } => "ServiceUnavailable",
+            Self::MigrationError { .. }
+            | Self::DatabaseCorrupt { .. }
+            | Self::EncryptionUnsupported
+            | Self::FeatureUnsupported { .. }
+            | Self::PreflightFailed { .. }
+            | Self::InternalServerError { .. }
+            | Self::Sqlx { .. }
+            | Self::Whatever { .. } => "InternalFailure",
+        }
+    }
+
+    /// Whether the error is the fault of whoever made the request, rather than of the server.
+    /// SQS reports this as `SenderFault` for the failed entries of a batch, and the HTTP status of
+    /// an error is a 4xx exactly when it is.
+    pub fn is_sender_fault(&self) -> bool {
+        match self {
+            Self::Unavailable { .. }
+            | Self::Overloaded { .. }
+            | Self::MigrationInProgress
+            | Self::QueryTimedOut { .. }
+            | Self::MigrationError { .. }
+            | Self::DatabaseCorrupt { .. }
+            | Self::EncryptionUnsupported
+            | Self::FeatureUnsupported { .. }
+            | Self::PreflightFailed { .. }
+            | Self::InternalServerError { .. }
+            | Self::Sqlx { .. }
+            | Self::Whatever { .. } => false,
+            Self::Unauthorized
+            | Self::UserNotFound { .. }
+            | Self::IdentityNotFound { .. }
+            | Self::NotFound { .. }
+            | Self::QueueNotFound { .. }
+            | Self::MissingHeader { .. }
+            | Self::MissingParameter { .. }
+            | Self::InvalidHeader { .. }
+            | Self::InvalidMethod { .. }
+            | Self::InvalidParameter { .. }
+            | Self::InvalidRequest { .. }
+            | Self::InvalidProvisionFile { .. }
+            | Self::ReceiptHandleIsInvalid { .. }
+            | Self::TooManyEntriesInBatchRequest { .. }
+            | Self::BatchEntryIdsNotDistinct { .. }
+            | Self::EmptyBatchRequest
+            | Self::InvalidAttributeName { .. }
+            | Self::TooManyMessageAttributes { .. }
+            | Self::MessageTooLong { .. }
+            | Self::BatchRequestTooLong { .. }
+            | Self::ChecksumMismatch { .. }
+            | Self::PayloadTooLarge
+            | Self::Throttled { .. }
+            | Self::QuotaExceeded { .. }
+            | Self::OverLimit { .. }
+            | Self::PurgeQueueInProgress { .. }
+            | Self::Forbidden { .. } => true,
+        }
+    }
+
+    /// Returns structured details about the error, for the errors that have any.
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::NotFound { resource } => Some(serde_json::json!({ "resource": resource })),
+            Self::QueueNotFound { queue, namespace } => Some(serde_json::json!({
+                "resource": format!("queue {queue} in namespace {namespace}"),
+            })),
+            Self::ReceiptHandleIsInvalid { handle } => {
+                Some(serde_json::json!({ "receiptHandle": handle }))
+            }
+            Self::MissingHeader { header } | Self::InvalidHeader { header } => {
+                Some(serde_json::json!({ "header": header }))
+            }
+            Self::TooManyEntriesInBatchRequest { count, max } => {
+                Some(serde_json::json!({ "count": count, "max": max }))
+            }
+            Self::BatchEntryIdsNotDistinct { id } => Some(serde_json::json!({ "id": id })),
+            Self::InvalidAttributeName { name, reason } => {
+                Some(serde_json::json!({ "name": name, "reason": reason }))
+            }
+            Self::TooManyMessageAttributes { count, max } => {
+                Some(serde_json::json!({ "count": count, "max": max }))
+            }
+            Self::MessageTooLong { size, max } | Self::BatchRequestTooLong { size, max } => {
+                Some(serde_json::json!({ "size": size, "max": max }))
+            }
+            Self::ChecksumMismatch { field } => Some(serde_json::json!({ "field": field })),
+            Self::QueryTimedOut { limit } => {
+                Some(serde_json::json!({ "limitSeconds": limit.as_secs_f64() }))
+            }
+            Self::QuotaExceeded { resource, limit } | Self::OverLimit { resource, limit } => {
+                Some(serde_json::json!({ "resource": resource, "limit": limit }))
+            }
+            Self::Unavailable { retry_after, .. }
+            | Self::Overloaded { retry_after, .. }
+            | Self::Throttled { retry_after } => {
+                Some(serde_json::json!({ "retryAfterSeconds": retry_after_secs(*retry_after) }))
+            }
+            Self::PurgeQueueInProgress { queue, retry_after } => Some(serde_json::json!({
+                "queue": queue,
+                "retryAfterSeconds": retry_after_secs(*retry_after),
+            })),
+            _ => None,
+        }
+    }
+
+    /// Returns the body of the error's response.
+    pub fn body(&self) -> ErrorBody {
+        let message = match self.code() {
+            INTERNAL_ERROR_CODE => "Internal server error".to_owned(),
+            _ => self.to_string(),
+        };
+
+        ErrorBody {
+            code: self.code(),
+            message,
+            details: self.details(),
+            request_id: RequestId::current().map(|id| id.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{http::header::RETRY_AFTER, ResponseError};
+
+    use super::*;
+
+    #[test]
+    fn test_unavailable_sets_retry_after() {
+        let res = Error::unavailable("testing", Duration::from_secs(30)).error_response();
+
+        assert_eq!(
+            res.status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "30");
+    }
+
+    #[tokio::test]
+    async fn test_busy_database_is_overloaded() {
+        use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("nervemq.db"))
+            .create_if_missing(true)
+            .busy_timeout(Duration::ZERO);
+        let mut writer = options.connect().await.unwrap();
+        let mut other = options.connect().await.unwrap();
+
+        sqlx::query("CREATE TABLE t (x)")
+            .execute(&mut writer)
+            .await
+            .unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut writer)
+            .await
+            .unwrap();
+        let error = sqlx::query("INSERT INTO t VALUES (1)")
+            .execute(&mut other)
+            .await
+            .unwrap_err();
+
+        let res = Error::from(error).error_response();
+        assert_eq!(
+            res.status(),
+            actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "1");
+
+        let error = Error::from(sqlx::Error::PoolTimedOut);
+        assert_eq!(error.code(), "ServiceUnavailable");
+        assert!(matches!(
+            Error::from(sqlx::Error::RowNotFound),
+            Error::Sqlx { .. }
+        ));
+    }
+
+    #[test]
+    fn test_sender_fault_matches_status() {
+        for error in [
+            Error::Unauthorized,
+            Error::not_found("thing"),
+            Error::invalid_parameter("bad"),
+            Error::MessageTooLong { size: 2, max: 1 },
+            Error::PayloadTooLarge,
+            Error::forbidden("no"),
+            Error::unavailable("testing", Duration::from_secs(1)),
+            Error::MigrationInProgress,
+            Error::internal(eyre::eyre!("boom")),
+            Error::from(sqlx::Error::RowNotFound),
+        ] {
+            assert_eq!(
+                error.is_sender_fault(),
+                error.status_code().is_client_error(),
+                "{error:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_body() {
+        let body =
+            serde_json::to_value(Error::TooManyEntriesInBatchRequest { count: 11, max: 10 }.body())
+                .unwrap();
+        assert_eq!(body["code"], "TooManyEntriesInBatchRequest");
+        assert_eq!(body["details"]["max"], 10);
+        assert!(body["request_id"].is_null());
+
+        // Internal errors don't reveal what went wrong.
+        let body = Error::from(sqlx::Error::RowNotFound).body();
+        assert_eq!(body.code, "InternalServerError");
+        assert_eq!(body.message, "Internal server error");
+        assert!(body.details.is_none());
+    }
 }
@@ -0,0 +1,230 @@
+//! Internal service events.
+//!
+//! The [`Service`](crate::service::Service) publishes an [`Event`] on its [`EventBus`] after
+//! each state change that other subsystems may want to react to, once the change has been
+//! committed. Subsystems such as metrics, webhooks, server-sent events and audit logging
+//! subscribe to the bus instead of being called directly from the service or handlers.
+//!
+//! The bus is a bounded broadcast channel. Subscribers that fall more than [`EVENT_BUS_CAPACITY`]
+//! events behind miss the oldest events, and are told how many they missed.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::api::auth::Role;
+
+/// Number of events buffered for each subscriber.
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// A state change in the service.
+///
+/// Queues are identified by id, since that is what most write paths have at hand. Subscribers
+/// that need names can resolve them with the service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    /// A message was added to a queue.
+    MessageSent { queue: u64, message: u64 },
+    /// A message was delivered to a consumer.
+    MessageReceived {
+        queue: u64,
+        message: u64,
+        receive_count: u64,
+    },
+    /// A message was deleted by a consumer.
+    MessageDeleted { queue: u64, message: u64 },
+    /// A queue was created.
+    QueueCreated {
+        queue: u64,
+        namespace: String,
+        name: String,
+    },
+    /// A message used up its retries and was moved to its queue's dead-letter queue.
+    DlqMove {
+        queue: u64,
+        dead_letter_queue: u64,
+        message: u64,
+    },
+    /// A queue was deleted.
+    QueueDeleted {
+        queue: u64,
+        namespace: String,
+        name: String,
+    },
+    /// A user was created or invited.
+    UserCreated { email: String, role: Role },
+    /// A user was deleted.
+    UserDeleted { email: String },
+    /// The role of a user was changed.
+    UserRoleChanged { email: String, role: Role },
+    /// Messages are waiting in a queue, but none of its registered consumers is alive anymore.
+    QueueUnattended {
+        queue: u64,
+        namespace: String,
+        name: String,
+        depth: u64,
+    },
+}
+
+/// Types of the administrative events, which [`crate::webhooks`] deliver.
+pub const ADMIN_EVENTS: [&str; 6] = [
+    "queueCreated",
+    "queueDeleted",
+    "userCreated",
+    "userDeleted",
+    "userRoleChanged",
+    "queueUnattended",
+];
+
+impl Event {
+    /// Returns the type of the event, as it appears in the `type` field of its JSON form.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::MessageSent { .. } => "messageSent",
+            Event::MessageReceived { .. } => "messageReceived",
+            Event::MessageDeleted { .. } => "messageDeleted",
+            Event::QueueCreated { .. } => "queueCreated",
+            Event::DlqMove { .. } => "dlqMove",
+            Event::QueueDeleted { .. } => "queueDeleted",
+            Event::UserCreated { .. } => "userCreated",
+            Event::UserDeleted { .. } => "userDeleted",
+            Event::UserRoleChanged { .. } => "userRoleChanged",
+            Event::QueueUnattended { .. } => "queueUnattended",
+        }
+    }
+
+    /// Returns whether the event is an administrative change, rather than message traffic.
+    pub fn is_admin(&self) -> bool {
+        ADMIN_EVENTS.contains(&self.kind())
+    }
+}
+
+/// Broadcasts [`Event`]s to every subscriber.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// Creates a bus with no subscribers.
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { tx }
+    }
+
+    /// Publishes an event. Events published while there are no subscribers are dropped.
+    pub fn publish(&self, event: Event) {
+        self.tx.send(event).ok();
+    }
+
+    /// Subscribes to events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+/// Records queue lifecycle and dead-letter events in the audit log until the bus is closed.
+pub async fn audit(mut events: broadcast::Receiver<Event>) {
+    loop {
+        match events.recv().await {
+            Ok(Event::QueueCreated {
+                queue,
+                namespace,
+                name,
+            }) => {
+                tracing::info!(target: "nervemq::audit", queue, namespace, name, "Queue created");
+            }
+            Ok(Event::DlqMove {
+                queue,
+                dead_letter_queue,
+                message,
+            }) => {
+                tracing::info!(
+                    target: "nervemq::audit",
+                    queue,
+                    dead_letter_queue,
+                    message,
+                    "Message moved to dead-letter queue"
+                );
+            }
+            Ok(Event::QueueDeleted {
+                queue,
+                namespace,
+                name,
+            }) => {
+                tracing::info!(target: "nervemq::audit", queue, namespace, name, "Queue deleted");
+            }
+            Ok(Event::UserCreated { email, role }) => {
+                tracing::info!(target: "nervemq::audit", email, ?role, "User created");
+            }
+            Ok(Event::UserDeleted { email }) => {
+                tracing::info!(target: "nervemq::audit", email, "User deleted");
+            }
+            Ok(Event::UserRoleChanged { email, role }) => {
+                tracing::info!(target: "nervemq::audit", email, ?role, "User role changed");
+            }
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!(missed, "Audit log fell behind the event bus");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_subscribe() {
+        let bus = EventBus::new();
+
+        // Nobody is listening yet, so this is dropped.
+        bus.publish(Event::MessageSent {
+            queue: 1,
+            message: 1,
+        });
+
+        let mut a = bus.subscribe();
+        let mut b = bus.subscribe();
+
+        let event = Event::MessageDeleted {
+            queue: 1,
+            message: 2,
+        };
+        bus.publish(event.clone());
+
+        assert_eq!(a.recv().await.unwrap(), event);
+        assert_eq!(b.recv().await.unwrap(), event);
+        assert!(a.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_kind_matches_json_type() {
+        for event in [
+            Event::MessageSent {
+                queue: 1,
+                message: 1,
+            },
+            Event::QueueDeleted {
+                queue: 1,
+                namespace: "ns".to_owned(),
+                name: "q".to_owned(),
+            },
+            Event::UserRoleChanged {
+                email: "a@example.com".to_owned(),
+                role: Role::Admin,
+            },
+        ] {
+            let json = serde_json::to_value(&event).unwrap();
+            assert_eq!(json["type"], event.kind());
+        }
+    }
+}
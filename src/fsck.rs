@@ -0,0 +1,270 @@
+//! Database consistency checks.
+//!
+//! Message pairs, queue attributes, tags, configurations and API keys are removed along with their
+//! message, queue, user or namespace through cascading foreign keys, but databases written without
+//! foreign key enforcement (or by older versions) can still contain rows whose parent is gone. The
+//! server sweeps them away periodically, and `nervemq fsck` reports and removes them on demand.
+//!
+//! The server also removes key manager keys that no user or server secret refers to anymore, such
+//! as keys of users deleted while the key manager was unreachable, or created for users that then
+//! failed to be created. Keys are created before what refers to them is stored, so a key is only
+//! removed once it has been unreferenced for a whole sweep interval.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{auth::signing_key, error::Error, service::Service, tasks};
+
+/// Tables rows can be orphaned in, with the condition that makes a row orphaned.
+const CHECKS: [(&str, &str); 5] = [
+    (
+        "kv_pairs",
+        "NOT EXISTS (SELECT 1 FROM messages m WHERE m.id = kv_pairs.message)",
+    ),
+    (
+        "queue_attributes",
+        "NOT EXISTS (SELECT 1 FROM queues q WHERE q.id = queue_attributes.queue)",
+    ),
+    (
+        "queue_tags",
+        "NOT EXISTS (SELECT 1 FROM queues q WHERE q.id = queue_tags.queue)",
+    ),
+    (
+        "queue_configurations",
+        "NOT EXISTS (SELECT 1 FROM queues q WHERE q.id = queue_configurations.queue)",
+    ),
+    (
+        "api_keys",
+        "NOT EXISTS (SELECT 1 FROM users u WHERE u.id = api_keys.user)
+        OR NOT EXISTS (SELECT 1 FROM namespaces ns WHERE ns.id = api_keys.ns)",
+    ),
+];
+
+/// Orphaned rows found in a table.
+#[derive(Debug, Clone, Serialize)]
+pub struct Orphans {
+    pub table: &'static str,
+    pub count: u64,
+}
+
+/// Finds orphaned rows and, unless `dry_run` is set, deletes them.
+///
+/// # Arguments
+/// * `pool` - Database to check
+/// * `dry_run` - Only count the orphaned rows
+///
+/// # Returns
+/// The orphaned rows of every table that has any
+pub async fn sweep(pool: &SqlitePool, dry_run: bool) -> Result<Vec<Orphans>, Error> {
+    let mut tx = pool.begin().await?;
+
+    let mut found = Vec::new();
+    for (table, orphaned) in CHECKS {
+        let count = if dry_run {
+            sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table} WHERE {orphaned}"))
+                .fetch_one(&mut *tx)
+                .await?
+        } else {
+            sqlx::query(&format!("DELETE FROM {table} WHERE {orphaned}"))
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+        };
+
+        if count > 0 {
+            found.push(Orphans { table, count });
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(found)
+}
+
+/// Finds key manager keys that nothing refers to.
+///
+/// # Returns
+/// The IDs of the keys, or `None` if the key manager can't list its keys
+pub async fn orphaned_keys(service: &Service) -> Result<Option<HashSet<String>>, Error> {
+    let Some(keys) = service.kms().list_keys().await? else {
+        return Ok(None);
+    };
+
+    let mut referenced: HashSet<String> = sqlx::query_scalar(
+        "
+        SELECT kms_key_id FROM users
+        UNION SELECT kms_key_id FROM server_secrets
+        ",
+    )
+    .fetch_all(service.db())
+    .await?
+    .into_iter()
+    .collect();
+    referenced.extend(signing_key::kms_key_id(service).await?);
+
+    Ok(Some(
+        keys.into_iter()
+            .filter(|key_id| !referenced.contains(key_id))
+            .collect(),
+    ))
+}
+
+/// Deletes the keys that are still orphaned out of those that were at the previous sweep.
+///
+/// # Arguments
+/// * `service` - Service whose keys to sweep
+/// * `suspects` - Keys that were orphaned at the previous sweep, replaced by those that are now
+///   but haven't been for long enough to be deleted
+///
+/// # Returns
+/// The number of deleted keys
+pub async fn sweep_keys(service: &Service, suspects: &mut HashSet<String>) -> Result<u64, Error> {
+    let Some(orphaned) = orphaned_keys(service).await? else {
+        return Ok(0);
+    };
+
+    let mut deleted = 0;
+    for key_id in orphaned.intersection(suspects) {
+        service.kms().delete_key(key_id).await?;
+        tracing::warn!(target: "nervemq::audit", key_id, "Deleted orphaned key");
+        deleted += 1;
+    }
+
+    *suspects = orphaned.difference(suspects).cloned().collect();
+
+    Ok(deleted)
+}
+
+/// Sweeps orphaned rows and keys every fsck interval until the process exits.
+pub fn spawn(service: &Service) {
+    let suspects = Arc::new(Mutex::new(HashSet::new()));
+
+    let period = service.config().tasks().fsck;
+    tasks::spawn_periodic(service, "fsck", period, move |service| {
+        let suspects = suspects.clone();
+        async move {
+            let rows = sweep(service.db(), false).await;
+            if let Ok(found) = &rows {
+                for Orphans { table, count } in found {
+                    tracing::warn!(table, count, "Removed orphaned rows");
+                }
+            }
+
+            // Key manager futures aren't `Send`, so the keys are swept on a thread of their own.
+            let handle = tokio::runtime::Handle::current();
+            let keys = tokio::task::spawn_blocking(move || {
+                let mut suspects = suspects.lock().unwrap_or_else(|e| e.into_inner());
+                handle.block_on(sweep_keys(&service, &mut suspects))
+            })
+            .await
+            .map_err(Error::internal)
+            .and_then(|result| result);
+
+            rows.and(keys).map(|_| ())
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, kms::memory::InMemoryKeyManager};
+
+    #[tokio::test]
+    async fn test_sweep() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+
+        let mut conn = service.db().acquire().await.unwrap();
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        for statement in [
+            "INSERT INTO kv_pairs (message, k, v) VALUES (42, 'a', 'b'), (42, 'c', 'd')",
+            "INSERT INTO queue_attributes (queue, k, v) VALUES (42, 'a', 'b')",
+            "INSERT INTO queue_tags (queue, k, v) VALUES (42, 'a', 'b')",
+            "
+            INSERT INTO api_keys (user, ns, name, key_id, hashed_key, encrypted_key)
+            VALUES (42, 42, 'a', 'b', 'c', x'00')
+            ",
+        ] {
+            sqlx::query(statement).execute(&mut *conn).await.unwrap();
+        }
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let counts = |found: Vec<Orphans>| {
+            found
+                .into_iter()
+                .map(|orphans| (orphans.table, orphans.count))
+                .collect::<Vec<_>>()
+        };
+        let expected = [
+            ("kv_pairs", 2),
+            ("queue_attributes", 1),
+            ("queue_tags", 1),
+            ("api_keys", 1),
+        ];
+
+        let found = sweep(service.db(), true).await.unwrap();
+        assert_eq!(counts(found), expected);
+
+        let found = sweep(service.db(), false).await.unwrap();
+        assert_eq!(counts(found), expected);
+
+        assert!(sweep(service.db(), true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_keys() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        signing_key::load(&service).await.unwrap();
+        let keys = service.kms().list_keys().await.unwrap().unwrap();
+
+        let orphan = service.kms().create_key().await.unwrap();
+
+        let mut suspects = HashSet::new();
+        assert_eq!(sweep_keys(&service, &mut suspects).await.unwrap(), 0);
+        assert_eq!(suspects, HashSet::from([orphan.clone()]));
+
+        // A key that becomes referenced in the meantime is kept.
+        let adopted = service.kms().create_key().await.unwrap();
+        let mut next = suspects.clone();
+        next.insert(adopted.clone());
+        sqlx::query(
+            "INSERT INTO server_secrets (name, kms_key_id, secret) VALUES ('test', $1, x'00')",
+        )
+        .bind(&adopted)
+        .execute(service.db())
+        .await
+        .unwrap();
+
+        assert_eq!(sweep_keys(&service, &mut next).await.unwrap(), 1);
+        assert!(next.is_empty());
+
+        let mut remaining = service.kms().list_keys().await.unwrap().unwrap();
+        remaining.sort();
+        let mut expected = keys;
+        expected.push(adopted);
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+}
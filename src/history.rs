@@ -0,0 +1,100 @@
+//! Message history.
+//!
+//! By default, deleting (acknowledging) a message removes every trace of it. Queues can opt into
+//! keeping a record of deleted messages in the `messages_history` table for observability, either
+//! with metadata only or with the message body as well. Records are pruned once they are older
+//! than the queue's history retention period, which defaults to 7 days.
+//!
+//! Messages removed without being acknowledged (by purging or deleting the queue) are not
+//! recorded.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+
+use crate::{compression, service::Service, tasks};
+
+/// Maximum number of history records returned by a single query.
+pub const MAX_HISTORY_PAGE_SIZE: u64 = 1000;
+
+/// What is recorded when a message in a queue is deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryMode {
+    /// Deleted messages are not recorded
+    #[default]
+    Off,
+    /// Message metadata is recorded, but not the body
+    Metadata,
+    /// Message metadata and body are recorded
+    Full,
+}
+
+/// A record of a deleted message.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// ID of the message, as returned when it was sent. Records made before message IDs were
+    /// recorded have none.
+    pub message_id: Option<String>,
+    /// Message body, if the queue records it
+    pub body: Option<String>,
+    /// ID of the user who sent the message
+    pub sent_by: Option<u64>,
+    /// Number of times the message was received
+    pub tries: u64,
+    /// Unix timestamp of the last delivery
+    pub delivered_at: Option<u64>,
+    /// Unix timestamp of the deletion
+    pub deleted_at: u64,
+    /// Trace id of the message, see [`crate::trace`]
+    pub trace_id: Option<String>,
+    /// Status payload the consumer reported when it acknowledged the message, see [`crate::ack`]
+    pub result: Option<serde_json::Value>,
+}
+
+/// History records are read from `messages_history` rows, whose body is decompressed if it is
+/// stored compressed.
+impl FromRow<'_, SqliteRow> for HistoryEntry {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let compressed = row.try_get("compressed")?;
+        let body = row
+            .try_get::<Option<Vec<u8>>, _>("body")?
+            .map(|body| compression::decode_body(body, compressed))
+            .transpose()
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "body".to_owned(),
+                source: Box::new(e),
+            })?;
+
+        Ok(Self {
+            message_id: row.try_get("message_id")?,
+            body,
+            sent_by: row.try_get("sent_by")?,
+            tries: row.try_get("tries")?,
+            delivered_at: row.try_get("delivered_at")?,
+            deleted_at: row.try_get("deleted_at")?,
+            trace_id: row.try_get("trace_id")?,
+            result: row
+                .try_get::<Option<String>, _>("result")?
+                .map(|result| serde_json::from_str(&result))
+                .transpose()
+                .map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "result".to_owned(),
+                    source: Box::new(e),
+                })?,
+        })
+    }
+}
+
+/// Prunes expired history records every prune interval until the process exits.
+pub fn spawn(service: &Service) {
+    let period = service.config().tasks().prune;
+    tasks::spawn_periodic(service, "history", period, |service| async move {
+        let count = service.prune_message_history().await?;
+        if count > 0 {
+            tracing::debug!(count, "Pruned message history");
+        }
+        Ok(())
+    });
+}
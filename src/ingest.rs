@@ -0,0 +1,154 @@
+//! Native bulk ingestion.
+//!
+//! Producers that don't need SQS compatibility can send many messages to a queue in a single
+//! `POST /ingest/{namespace}/{queue}` request, which are inserted in one transaction with
+//! multi-row inserts. The request body is either:
+//!
+//! - NDJSON (`application/x-ndjson`): one [`IngestMessage`] JSON object per line, for example
+//!   `{"body": "hello", "attributes": {"k": {"DataType": "String", "StringValue": "v"}}}`
+//! - A length-prefixed binary stream (`application/octet-stream`): each message body is
+//!   preceded by its length in bytes as a big-endian `u32`. Bodies must be valid UTF-8 and
+//!   cannot carry attributes.
+//!
+//! Either every message in a request is sent, or none is.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, sqs::types::SqsMessageAttribute};
+
+/// Maximum size of an ingest request body.
+pub const MAX_INGEST_BYTES: usize = 16 * 1024 * 1024;
+
+/// Number of messages inserted per statement, which keeps statements well below SQLite's limit
+/// on bound parameters.
+pub const INGEST_CHUNK_SIZE: usize = 500;
+
+/// Content type of NDJSON ingest requests.
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Content type of length-prefixed binary ingest requests.
+pub const BINARY_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// A message in an ingest request.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct IngestMessage {
+    pub body: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, SqsMessageAttribute>,
+    /// Message group, as used by fair receives
+    pub group_id: Option<String>,
+}
+
+impl From<String> for IngestMessage {
+    fn from(body: String) -> Self {
+        Self {
+            body,
+            attributes: HashMap::new(),
+            group_id: None,
+        }
+    }
+}
+
+/// Response to an ingest request.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestResponse {
+    /// Message IDs of the sent messages, in request order
+    pub message_ids: Vec<String>,
+}
+
+/// Parses an ingest request body according to its content type.
+///
+/// # Errors
+/// Returns [`Error::InvalidParameter`] if the content type is not supported, or the body is
+/// malformed
+pub fn parse(content_type: &str, data: &[u8]) -> Result<Vec<IngestMessage>, Error> {
+    match content_type {
+        NDJSON_CONTENT_TYPE => parse_ndjson(data),
+        BINARY_CONTENT_TYPE => parse_frames(data),
+        other => Err(Error::invalid_parameter(format!(
+            "Content-Type: expected {NDJSON_CONTENT_TYPE} or {BINARY_CONTENT_TYPE}, got {other:?}"
+        ))),
+    }
+}
+
+/// Parses newline-delimited JSON messages. Blank lines are skipped.
+fn parse_ndjson(data: &[u8]) -> Result<Vec<IngestMessage>, Error> {
+    data.split(|byte| *byte == b'\n')
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.strip_suffix(b"\r").unwrap_or(line)))
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(number, line)| {
+            serde_json::from_slice(line)
+                .map_err(|e| Error::invalid_parameter(format!("line {number}: {e}")))
+        })
+        .collect()
+}
+
+/// Parses a stream of message bodies, each prefixed by its length as a big-endian `u32`.
+fn parse_frames(mut data: &[u8]) -> Result<Vec<IngestMessage>, Error> {
+    let mut messages = Vec::new();
+
+    while !data.is_empty() {
+        let number = messages.len() + 1;
+        let truncated = || Error::invalid_parameter(format!("frame {number}: truncated"));
+
+        let (len, rest) = data.split_first_chunk::<4>().ok_or_else(truncated)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(truncated());
+        }
+
+        let (body, rest) = rest.split_at(len);
+        let body = std::str::from_utf8(body).map_err(|e| {
+            Error::invalid_parameter(format!("frame {number}: body is not valid UTF-8: {e}"))
+        })?;
+
+        messages.push(IngestMessage::from(body.to_owned()));
+        data = rest;
+    }
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ndjson() {
+        let data = b"{\"body\": \"a\"}\r\n\n{\"body\": \"b\", \"groupId\": \"g\", \"attributes\": {\"k\": {\"DataType\": \"Number\", \"StringValue\": \"1\"}}}\n";
+
+        let messages = parse(NDJSON_CONTENT_TYPE, data).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].body, "a");
+        assert_eq!(messages[1].group_id.as_deref(), Some("g"));
+        assert!(matches!(
+            messages[1].attributes["k"],
+            SqsMessageAttribute::Number { .. }
+        ));
+
+        let err = parse(NDJSON_CONTENT_TYPE, b"{\"body\": \"a\"}\n{\"bdy\": \"b\"}").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_frames() {
+        let mut data = Vec::new();
+        for body in ["hello", "", "wörld"] {
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            data.extend_from_slice(body.as_bytes());
+        }
+
+        let messages = parse(BINARY_CONTENT_TYPE, &data).unwrap();
+        let bodies = messages.iter().map(|m| m.body.as_str()).collect::<Vec<_>>();
+        assert_eq!(bodies, ["hello", "", "wörld"]);
+
+        assert!(parse(BINARY_CONTENT_TYPE, &data[..data.len() - 1]).is_err());
+        assert!(parse(BINARY_CONTENT_TYPE, &[0, 0, 0, 1, 0xff]).is_err());
+        assert!(parse("text/plain", &data).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Startup database integrity checks.
+//!
+//! A corrupt database otherwise surfaces as confusing errors in the middle of requests, so the
+//! server checks the database before opening it, according to
+//! [`Config::integrity_check`](crate::config::Config::integrity_check), and refuses to start if
+//! it is corrupt.
+//!
+//! If automatic restore is enabled, a corrupt database is instead moved aside (to
+//! `<db_path>.corrupt-<timestamp>`, along with its write-ahead log) and replaced by the newest
+//! file in the backup directory that passes the same check.
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use secrecy::SecretString;
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions};
+
+use crate::{
+    config::{Config, IntegrityCheck},
+    error::Error,
+    service::with_db_key,
+};
+
+/// Files SQLite keeps next to a database in WAL mode.
+const SIDECAR_SUFFIXES: [&str; 2] = ["-wal", "-shm"];
+
+/// Checks a database file for corruption.
+///
+/// # Arguments
+/// * `path` - Database file to check
+/// * `mode` - How thoroughly to check it
+/// * `db_key` - Passphrase the database is encrypted with, if any
+///
+/// # Returns
+/// `Ok(())` if the database is intact or doesn't exist yet
+///
+/// # Errors
+/// Returns [`Error::DatabaseCorrupt`] with SQLite's findings if the check fails, including when
+/// the file is not a database at all (which is also what an encrypted database opened with the
+/// wrong key looks like)
+pub async fn check(
+    path: &Path,
+    mode: IntegrityCheck,
+    db_key: Option<&SecretString>,
+) -> Result<(), Error> {
+    let pragma = match mode {
+        IntegrityCheck::Off => return Ok(()),
+        IntegrityCheck::Quick => "PRAGMA quick_check",
+        IntegrityCheck::Full => "PRAGMA integrity_check",
+    };
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let corrupt = |details: String| Error::DatabaseCorrupt {
+        path: path.display().to_string(),
+        details,
+    };
+
+    let options = with_db_key(SqliteConnectOptions::new().filename(path), db_key)?;
+    let mut conn = match options.connect().await {
+        Ok(conn) => conn,
+        Err(sqlx::Error::Database(e)) => return Err(corrupt(e.message().to_owned())),
+        Err(e) => return Err(e.into()),
+    };
+
+    let findings = match sqlx::query_scalar::<_, String>(pragma)
+        .fetch_all(&mut conn)
+        .await
+    {
+        Ok(findings) => findings,
+        Err(sqlx::Error::Database(e)) => return Err(corrupt(e.message().to_owned())),
+        Err(e) => return Err(e.into()),
+    };
+
+    match findings.as_slice() {
+        [ok] if ok == "ok" => Ok(()),
+        _ => Err(corrupt(findings.join("; "))),
+    }
+}
+
+/// Checks the configured database on startup, restoring it from a backup if it is corrupt and
+/// automatic restore is enabled.
+pub async fn verify(config: &Config, db_key: Option<&SecretString>) -> Result<(), Error> {
+    let path = Path::new(config.db_path());
+    let mode = config.integrity_check();
+
+    let err = match check(path, mode, db_key).await {
+        Ok(()) => return Ok(()),
+        Err(err @ Error::DatabaseCorrupt { .. }) => err,
+        Err(err) => return Err(err),
+    };
+
+    tracing::error!("{err}");
+
+    let backup_dir = match config.backup_dir() {
+        Some(dir) if config.auto_restore() => Path::new(dir),
+        _ => {
+            tracing::error!(
+                "Restore the database from a backup, or set NERVEMQ_BACKUP_DIR and \
+                 NERVEMQ_AUTO_RESTORE=true to restore the latest backup automatically"
+            );
+            return Err(err);
+        }
+    };
+
+    // Backups are expected to be encrypted with the same key. This also means that a wrong key
+    // never causes a restore, since no backup would pass the check either.
+    let Some(backup) = latest_intact_backup(backup_dir, mode, db_key).await? else {
+        tracing::error!(
+            backup_dir = %backup_dir.display(),
+            "No intact backup found to restore the database from"
+        );
+        return Err(err);
+    };
+
+    restore(path, &backup)?;
+    tracing::warn!(
+        target: "nervemq::audit",
+        backup = %backup.display(),
+        "Restored corrupt database from backup"
+    );
+
+    Ok(())
+}
+
+/// Finds the most recently modified file in `dir` that passes the integrity check.
+async fn latest_intact_backup(
+    dir: &Path,
+    mode: IntegrityCheck,
+    db_key: Option<&SecretString>,
+) -> Result<Option<PathBuf>, Error> {
+    let mut backups = std::fs::read_dir(dir)
+        .map_err(Error::internal)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            let path = entry.path();
+            let is_sidecar = SIDECAR_SUFFIXES
+                .iter()
+                .any(|suffix| path.to_string_lossy().ends_with(suffix));
+            (metadata.is_file() && !is_sidecar).then_some((modified, path))
+        })
+        .collect::<Vec<_>>();
+
+    backups.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    for (_, backup) in backups {
+        match check(&backup, mode, db_key).await {
+            Ok(()) => return Ok(Some(backup)),
+            Err(e) => tracing::warn!(backup = %backup.display(), "Skipping backup: {e}"),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Moves the database at `path` and its sidecar files aside, and copies `backup` in its place.
+fn restore(path: &Path, backup: &Path) -> Result<(), Error> {
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let sidecar = |path: &Path, suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+
+    let aside = sidecar(path, &format!(".corrupt-{timestamp}"));
+    std::fs::rename(path, &aside).map_err(Error::internal)?;
+    tracing::warn!(path = %aside.display(), "Moved corrupt database aside");
+
+    for suffix in SIDECAR_SUFFIXES {
+        let file = sidecar(path, suffix);
+        if file.exists() {
+            std::fs::rename(&file, sidecar(&aside, suffix)).map_err(Error::internal)?;
+        }
+    }
+
+    std::fs::copy(backup, path).map_err(Error::internal)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn create_db(path: &Path) {
+        let mut conn = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE t (x integer)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let missing = dir.path().join("missing.db");
+        assert!(check(&missing, IntegrityCheck::Full, None).await.is_ok());
+
+        let intact = dir.path().join("intact.db");
+        create_db(&intact).await;
+        assert!(check(&intact, IntegrityCheck::Quick, None).await.is_ok());
+        assert!(check(&intact, IntegrityCheck::Full, None).await.is_ok());
+
+        let garbage = dir.path().join("garbage.db");
+        std::fs::write(&garbage, vec![0xa5; 8192]).unwrap();
+        assert!(matches!(
+            check(&garbage, IntegrityCheck::Quick, None).await,
+            Err(Error::DatabaseCorrupt { .. })
+        ));
+        assert!(check(&garbage, IntegrityCheck::Off, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_restore_latest_intact_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = dir.path().join("backups");
+        std::fs::create_dir(&backups).unwrap();
+
+        create_db(&backups.join("old.db")).await;
+        std::fs::write(backups.join("new.db"), vec![0xa5; 8192]).unwrap();
+
+        let backup = latest_intact_backup(&backups, IntegrityCheck::Quick, None)
+            .await
+            .unwrap();
+        assert_eq!(backup, Some(backups.join("old.db")));
+
+        let db = dir.path().join("nervemq.db");
+        std::fs::write(&db, vec![0xa5; 8192]).unwrap();
+        restore(&db, &backup.unwrap()).unwrap();
+
+        assert!(check(&db, IntegrityCheck::Full, None).await.is_ok());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[tokio::test]
+    async fn test_check_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("encrypted.db");
+        let key = SecretString::new("correct horse".into());
+
+        let options = with_db_key(SqliteConnectOptions::new(), Some(&key)).unwrap();
+        let mut conn = options
+            .filename(&path)
+            .create_if_missing(true)
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE t (x integer)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        assert!(check(&path, IntegrityCheck::Full, Some(&key)).await.is_ok());
+
+        let wrong = SecretString::new("battery staple".into());
+        for key in [None, Some(&wrong)] {
+            assert!(matches!(
+                check(&path, IntegrityCheck::Quick, key).await,
+                Err(Error::DatabaseCorrupt { .. })
+            ));
+        }
+    }
+}
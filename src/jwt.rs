@@ -0,0 +1,609 @@
+//! JWT bearer token authentication.
+//!
+//! Clients that don't use an AWS SDK can authenticate with `Authorization: Bearer <jwt>`, using
+//! tokens issued by an identity provider, when `NERVEMQ_JWT_JWKS_URL`, `NERVEMQ_JWT_ISSUER` and
+//! `NERVEMQ_JWT_AUDIENCE` are set. A token is accepted if:
+//!
+//! - it is signed with RS256, RS384, RS512, PS256, PS384, PS512, ES256 or ES384 by one of the keys
+//!   published at the JWKS URL,
+//! - its `iss` claim is the configured issuer and its `aud` claim is or contains the configured
+//!   audience,
+//! - it has an `exp` claim that hasn't passed, and its `nbf` claim, if any, has, allowing for
+//!   [`CLOCK_SKEW`].
+//!
+//! The request is then authenticated as the user whose email is in the claim named by
+//! `NERVEMQ_JWT_USER_CLAIM` (default: `email`), scoped to the namespace in the claim named by
+//! `NERVEMQ_JWT_NAMESPACE_CLAIM` (default: `nervemq_namespace`), like with an API key of theirs.
+//! The user must exist and have access to the namespace.
+//!
+//! Keys are fetched when the first token is verified, and again when they are older than
+//! [`JWKS_REFRESH_INTERVAL`] or a token names a key that isn't known yet, at most once every
+//! [`JWKS_MIN_REFRESH_INTERVAL`].
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hyper::{client::HttpConnector, Body, Client, Request};
+use hyper_rustls::HttpsConnector;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use url::Url;
+
+use crate::error::Error;
+
+/// How long fetched keys are used before they are fetched again.
+pub const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Minimum time between two fetches of the keys, so that tokens naming unknown keys can't make
+/// the server hammer the identity provider.
+pub const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long fetching the keys may take.
+pub const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How far the clocks of the identity provider and the server may be apart.
+pub const CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// How tokens are verified and mapped to users.
+#[derive(Debug, Clone)]
+pub struct JwtSettings {
+    pub jwks_url: Url,
+    pub issuer: String,
+    pub audience: String,
+    /// Claim holding the email of the user
+    pub user_claim: String,
+    /// Claim holding the namespace the token is scoped to
+    pub namespace_claim: String,
+}
+
+/// The user and namespace a verified token maps to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subject {
+    pub email: String,
+    pub namespace: String,
+}
+
+/// A public key from a JWKS document.
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default, rename = "use")]
+    usage: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PublicKey {
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    /// Uncompressed point on the curve
+    Ec {
+        crv: String,
+        point: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Key {
+    kid: Option<String>,
+    alg: Option<String>,
+    key: PublicKey,
+}
+
+impl Key {
+    /// Converts a JWK, skipping keys that aren't for signatures or that use unsupported
+    /// algorithms.
+    fn from_jwk(jwk: Jwk) -> Option<Self> {
+        if jwk.usage.as_deref().is_some_and(|usage| usage != "sig") {
+            return None;
+        }
+
+        let decode = |value: Option<String>| URL_SAFE_NO_PAD.decode(value?).ok();
+        let key = match jwk.kty.as_str() {
+            "RSA" => PublicKey::Rsa {
+                n: decode(jwk.n)?,
+                e: decode(jwk.e)?,
+            },
+            "EC" => {
+                let crv = jwk.crv?;
+                if !matches!(crv.as_str(), "P-256" | "P-384") {
+                    return None;
+                }
+                let mut point = vec![0x04];
+                point.extend(decode(jwk.x)?);
+                point.extend(decode(jwk.y)?);
+                PublicKey::Ec { crv, point }
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            kid: jwk.kid,
+            alg: jwk.alg,
+            key,
+        })
+    }
+
+    /// Checks a signature made with an algorithm, if the key can be used with it.
+    fn verify(&self, alg: &str, message: &[u8], sig: &[u8]) -> bool {
+        if self.alg.as_deref().is_some_and(|key_alg| key_alg != alg) {
+            return false;
+        }
+
+        match &self.key {
+            PublicKey::Rsa { n, e } => {
+                let params = match alg {
+                    "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    "RS512" => &signature::RSA_PKCS1_2048_8192_SHA512,
+                    "PS256" => &signature::RSA_PSS_2048_8192_SHA256,
+                    "PS384" => &signature::RSA_PSS_2048_8192_SHA384,
+                    "PS512" => &signature::RSA_PSS_2048_8192_SHA512,
+                    _ => return false,
+                };
+                RsaPublicKeyComponents { n, e }
+                    .verify(params, message, sig)
+                    .is_ok()
+            }
+            PublicKey::Ec { crv, point } => {
+                let params = match (alg, crv.as_str()) {
+                    ("ES256", "P-256") => &signature::ECDSA_P256_SHA256_FIXED,
+                    ("ES384", "P-384") => &signature::ECDSA_P384_SHA384_FIXED,
+                    _ => return false,
+                };
+                UnparsedPublicKey::new(params, point)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// Why a token was rejected.
+#[derive(Debug, PartialEq, Eq)]
+enum Rejection {
+    Malformed,
+    UnknownKey,
+    BadSignature,
+    Claims(String),
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Malformed => write!(f, "malformed token"),
+            Rejection::UnknownKey => write!(f, "token is signed with an unknown key"),
+            Rejection::BadSignature => write!(f, "invalid signature"),
+            Rejection::Claims(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct KeySet {
+    keys: Vec<Key>,
+    fetched_at: Option<Instant>,
+}
+
+type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Verifies bearer tokens against the keys of the identity provider.
+#[derive(Clone)]
+pub struct JwtVerifier {
+    settings: Arc<JwtSettings>,
+    keys: Arc<RwLock<KeySet>>,
+    client: HttpClient,
+}
+
+impl JwtVerifier {
+    pub fn new(settings: JwtSettings) -> Self {
+        Self {
+            settings: Arc::new(settings),
+            keys: Arc::default(),
+            client: Client::builder().build(
+                hyper_rustls::HttpsConnectorBuilder::new()
+                    .with_native_roots()
+                    .https_or_http()
+                    .enable_http1()
+                    .build(),
+            ),
+        }
+    }
+
+    /// Verifies a token and finds the user and namespace it maps to.
+    ///
+    /// # Errors
+    /// * `Error::Unauthorized` - If the token isn't valid
+    pub async fn verify(&self, token: &str) -> Result<Subject, Error> {
+        let stale = {
+            let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+            keys.fetched_at
+                .is_none_or(|fetched_at| fetched_at.elapsed() >= JWKS_REFRESH_INTERVAL)
+        };
+        if stale {
+            self.refresh().await;
+        }
+
+        let mut result = self.verify_with_current_keys(token);
+        if result == Err(Rejection::UnknownKey) && self.refresh().await {
+            result = self.verify_with_current_keys(token);
+        }
+
+        result.map_err(|rejection| {
+            tracing::debug!("Rejected bearer token: {rejection}");
+            Error::Unauthorized
+        })
+    }
+
+    fn verify_with_current_keys(&self, token: &str) -> Result<Subject, Rejection> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        verify_token(&self.settings, &keys.keys, token, now())
+    }
+
+    /// Fetches the keys, unless they were fetched less than [`JWKS_MIN_REFRESH_INTERVAL`] ago.
+    ///
+    /// # Returns
+    /// Whether the keys were fetched
+    async fn refresh(&self) -> bool {
+        {
+            let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+            if keys
+                .fetched_at
+                .is_some_and(|fetched_at| fetched_at.elapsed() < JWKS_MIN_REFRESH_INTERVAL)
+            {
+                return false;
+            }
+            // Claim the refresh, so that concurrent requests don't fetch the keys too.
+            keys.fetched_at = Some(Instant::now());
+        }
+
+        match self.fetch().await {
+            Ok(fetched) => {
+                tracing::debug!(keys = fetched.len(), "Fetched JWT signing keys");
+                self.keys.write().unwrap_or_else(|e| e.into_inner()).keys = fetched;
+                true
+            }
+            Err(e) => {
+                // Keep the previous keys until the next attempt.
+                tracing::error!(url = %self.settings.jwks_url, "Failed to fetch JWT signing keys: {e}");
+                false
+            }
+        }
+    }
+
+    async fn fetch(&self) -> eyre::Result<Vec<Key>> {
+        let request = Request::get(self.settings.jwks_url.as_str()).body(Body::empty())?;
+        let response = tokio::time::timeout(JWKS_TIMEOUT, self.client.request(request)).await??;
+        if !response.status().is_success() {
+            eyre::bail!("JWKS URL responded with {}", response.status());
+        }
+
+        let body = tokio::time::timeout(JWKS_TIMEOUT, hyper::body::to_bytes(response.into_body()))
+            .await??;
+        let set: JwkSet = serde_json::from_slice(&body)?;
+
+        Ok(set.keys.into_iter().filter_map(Key::from_jwk).collect())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Verifies a token against a set of keys, at a point in time.
+fn verify_token(
+    settings: &JwtSettings,
+    keys: &[Key],
+    token: &str,
+    now: u64,
+) -> Result<Subject, Rejection> {
+    let Some((message, sig)) = token.rsplit_once('.') else {
+        return Err(Rejection::Malformed);
+    };
+    let Some((header, payload)) = message.split_once('.') else {
+        return Err(Rejection::Malformed);
+    };
+    if payload.contains('.') {
+        return Err(Rejection::Malformed);
+    }
+
+    let decode = |part: &str| {
+        URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| Rejection::Malformed)
+    };
+    let header: Header =
+        serde_json::from_slice(&decode(header)?).map_err(|_| Rejection::Malformed)?;
+    let sig = decode(sig)?;
+
+    let candidates = keys
+        .iter()
+        .filter(|key| header.kid.is_none() || key.kid == header.kid)
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Err(Rejection::UnknownKey);
+    }
+    if !candidates
+        .iter()
+        .any(|key| key.verify(&header.alg, message.as_bytes(), &sig))
+    {
+        return Err(Rejection::BadSignature);
+    }
+
+    let claims: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(&decode(payload)?).map_err(|_| Rejection::Malformed)?;
+    let invalid = |reason: &str| Rejection::Claims(reason.to_owned());
+    let skew = CLOCK_SKEW.as_secs();
+
+    let exp = claims
+        .get("exp")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_else(|| invalid("token has no expiry"))?;
+    if now > exp + skew {
+        return Err(invalid("token has expired"));
+    }
+    if let Some(nbf) = claims.get("nbf").and_then(serde_json::Value::as_u64) {
+        if now + skew < nbf {
+            return Err(invalid("token is not valid yet"));
+        }
+    }
+
+    if claims.get("iss").and_then(serde_json::Value::as_str) != Some(&settings.issuer) {
+        return Err(invalid("token has the wrong issuer"));
+    }
+    let audience_matches = match claims.get("aud") {
+        Some(serde_json::Value::String(aud)) => *aud == settings.audience,
+        Some(serde_json::Value::Array(auds)) => auds
+            .iter()
+            .any(|aud| aud.as_str() == Some(&settings.audience)),
+        _ => false,
+    };
+    if !audience_matches {
+        return Err(invalid("token has the wrong audience"));
+    }
+
+    let claim = |name: &str| {
+        claims
+            .get(name)
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_owned)
+            .ok_or_else(|| Rejection::Claims(format!("token has no {name} claim")))
+    };
+
+    Ok(Subject {
+        email: claim(&settings.user_claim)?,
+        namespace: claim(&settings.namespace_claim)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn settings() -> JwtSettings {
+        JwtSettings {
+            jwks_url: "https://idp.example.com/jwks.json".parse().unwrap(),
+            issuer: "https://idp.example.com".to_owned(),
+            audience: "nervemq".to_owned(),
+            user_claim: "email".to_owned(),
+            namespace_claim: "nervemq_namespace".to_owned(),
+        }
+    }
+
+    /// Generates a P-256 key pair, and the key as it would be published.
+    fn key_pair(kid: &str) -> (EcdsaKeyPair, Key) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+
+        let point = pair.public_key().as_ref();
+        let jwk = Jwk {
+            kty: "EC".to_owned(),
+            kid: Some(kid.to_owned()),
+            usage: Some("sig".to_owned()),
+            alg: None,
+            crv: Some("P-256".to_owned()),
+            n: None,
+            e: None,
+            x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+            y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+        };
+
+        (pair, Key::from_jwk(jwk).unwrap())
+    }
+
+    fn sign(pair: &EcdsaKeyPair, kid: &str, claims: serde_json::Value) -> String {
+        let header = serde_json::json!({ "alg": "ES256", "typ": "JWT", "kid": kid });
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let sig = pair.sign(&SystemRandom::new(), message.as_bytes()).unwrap();
+
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(sig.as_ref()))
+    }
+
+    fn claims() -> serde_json::Value {
+        serde_json::json!({
+            "iss": "https://idp.example.com",
+            "aud": ["other", "nervemq"],
+            "exp": NOW + 300,
+            "nbf": NOW - 10,
+            "email": "svc@example.com",
+            "nervemq_namespace": "orders",
+        })
+    }
+
+    #[test]
+    fn test_verify_token() {
+        let (pair, key) = key_pair("a");
+        let keys = [key];
+
+        let token = sign(&pair, "a", claims());
+        assert_eq!(
+            verify_token(&settings(), &keys, &token, NOW),
+            Ok(Subject {
+                email: "svc@example.com".to_owned(),
+                namespace: "orders".to_owned(),
+            })
+        );
+
+        // Within the allowed clock skew of the expiry.
+        assert!(verify_token(&settings(), &keys, &token, NOW + 330).is_ok());
+        assert!(matches!(
+            verify_token(&settings(), &keys, &token, NOW + 400),
+            Err(Rejection::Claims(_))
+        ));
+
+        assert_eq!(
+            verify_token(&settings(), &keys, &sign(&pair, "b", claims()), NOW),
+            Err(Rejection::UnknownKey)
+        );
+
+        let (other, _) = key_pair("a");
+        assert_eq!(
+            verify_token(&settings(), &keys, &sign(&other, "a", claims()), NOW),
+            Err(Rejection::BadSignature)
+        );
+
+        // Tampering with the claims invalidates the signature.
+        let parts = token.split('.').collect::<Vec<_>>();
+        let mut tampered = claims();
+        tampered["nervemq_namespace"] = "billing".into();
+        let tampered = format!(
+            "{}.{}.{}",
+            parts[0],
+            URL_SAFE_NO_PAD.encode(tampered.to_string()),
+            parts[2]
+        );
+        assert_eq!(
+            verify_token(&settings(), &keys, &tampered, NOW),
+            Err(Rejection::BadSignature)
+        );
+
+        for (claim, value) in [
+            ("iss", serde_json::json!("https://evil.example.com")),
+            ("aud", serde_json::json!("other")),
+            ("exp", serde_json::Value::Null),
+            ("nbf", serde_json::json!(NOW + 600)),
+            ("nervemq_namespace", serde_json::Value::Null),
+        ] {
+            let mut claims = claims();
+            claims[claim] = value;
+            assert!(
+                matches!(
+                    verify_token(&settings(), &keys, &sign(&pair, "a", claims), NOW),
+                    Err(Rejection::Claims(_))
+                ),
+                "{claim}"
+            );
+        }
+
+        for malformed in ["", "a.b", "a.b.c.d", "!.!.!"] {
+            assert_eq!(
+                verify_token(&settings(), &keys, malformed, NOW),
+                Err(Rejection::Malformed)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_keys() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (pair, key) = key_pair("a");
+        let PublicKey::Ec { point, .. } = &key.key else {
+            unreachable!()
+        };
+        let jwks = serde_json::json!({
+            "keys": [
+                { "kty": "oct", "k": "c2VjcmV0" },
+                {
+                    "kty": "EC",
+                    "kid": "a",
+                    "crv": "P-256",
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                },
+            ],
+        })
+        .to_string();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let verifier = JwtVerifier::new(JwtSettings {
+            jwks_url: format!("http://{}/jwks.json", listener.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+            ..settings()
+        });
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\
+                 connection: close\r\n\r\n{jwks}",
+                jwks.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let mut claims = claims();
+        claims["exp"] = (now() + 300).into();
+        claims["nbf"] = now().into();
+        let subject = verifier.verify(&sign(&pair, "a", claims)).await.unwrap();
+        assert_eq!(subject.namespace, "orders");
+        server.await.unwrap();
+
+        // The server is gone, but the keys are cached.
+        let mut claims = self::claims();
+        claims["exp"] = (now() + 300).into();
+        claims["nbf"] = now().into();
+        claims["email"] = "other@example.com".into();
+        let subject = verifier.verify(&sign(&pair, "a", claims)).await.unwrap();
+        assert_eq!(subject.email, "other@example.com");
+    }
+}
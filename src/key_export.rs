@@ -0,0 +1,167 @@
+//! Encrypted export of API keys.
+//!
+//! API keys can be moved to another instance, e.g. when recovering from a disaster, without
+//! reissuing every client's key. `POST /admin/api-keys/export` takes an RSA public key of at least
+//! [`MIN_RSA_BITS`] bits (PEM, SPKI or PKCS#1) and returns every API key with its secret encrypted
+//! to that key with RSA-OAEP (SHA-256). `POST /admin/api-keys/import` on the other instance takes
+//! the export and the matching private key (PEM, PKCS#8 or PKCS#1), and installs the keys.
+//!
+//! Secrets never leave the instance in plain text, but the rest of each key (owner, namespace,
+//! name, access key ID and allowed networks) does. Imported keys replace the owner's keys of the
+//! same name; keys whose owner or namespace doesn't exist on the importing instance are reported
+//! as failed, so they can be created and the export imported again.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ipnet::IpNet;
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    traits::PublicKeyParts,
+    Oaep, RsaPrivateKey, RsaPublicKey,
+};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::Error;
+
+/// Version of the export format.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Algorithm secrets are encrypted with, as a JWA identifier.
+pub const EXPORT_ALGORITHM: &str = "RSA-OAEP-256";
+
+/// Minimum size of the key secrets are encrypted to.
+pub const MIN_RSA_BITS: usize = 2048;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest {
+    /// PEM-encoded RSA public key to encrypt the secrets to
+    pub public_key: String,
+}
+
+/// Every API key of an instance, with encrypted secrets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyExport {
+    pub version: u32,
+    pub algorithm: String,
+    /// Unix timestamp of the export
+    pub exported_at: u64,
+    pub keys: Vec<ExportedKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedKey {
+    /// Email of the user the key belongs to
+    pub owner: String,
+    pub namespace: String,
+    pub name: String,
+    pub access_key: String,
+    /// Base64 of the secret, encrypted with [`EXPORT_ALGORITHM`]
+    pub encrypted_secret: String,
+    #[serde(default)]
+    pub allowed_networks: Option<Vec<IpNet>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRequest {
+    /// PEM-encoded RSA private key the secrets were encrypted to
+    pub private_key: SecretString,
+    pub export: KeyExport,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResponse {
+    /// Number of keys that were installed
+    pub imported: usize,
+    /// Number of keys that already existed, with the same access key and namespace
+    pub unchanged: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// A key that couldn't be imported.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportFailure {
+    pub owner: String,
+    pub name: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Parses a PEM-encoded RSA public key, in SPKI or PKCS#1 form.
+pub fn parse_public_key(pem: &str) -> Result<RsaPublicKey, Error> {
+    let key = RsaPublicKey::from_public_key_pem(pem.trim())
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem.trim()))
+        .map_err(|_| Error::invalid_parameter("publicKey: not a PEM-encoded RSA public key"))?;
+
+    if key.size() * 8 < MIN_RSA_BITS {
+        return Err(Error::invalid_parameter(format!(
+            "publicKey: must have at least {MIN_RSA_BITS} bits"
+        )));
+    }
+
+    Ok(key)
+}
+
+/// Parses a PEM-encoded RSA private key, in PKCS#8 or PKCS#1 form.
+pub fn parse_private_key(pem: &str) -> Result<RsaPrivateKey, Error> {
+    RsaPrivateKey::from_pkcs8_pem(pem.trim())
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem.trim()))
+        .map_err(|_| Error::invalid_parameter("privateKey: not a PEM-encoded RSA private key"))
+}
+
+/// Encrypts a secret to a public key.
+pub fn wrap(key: &RsaPublicKey, secret: &[u8]) -> Result<String, Error> {
+    let encrypted = key
+        .encrypt(&mut rand::thread_rng(), Oaep::new::<Sha256>(), secret)
+        .map_err(Error::internal)?;
+
+    Ok(STANDARD.encode(encrypted))
+}
+
+/// Decrypts a secret encrypted with [`wrap`].
+pub fn unwrap(key: &RsaPrivateKey, encrypted: &str) -> Result<String, Error> {
+    let invalid = || Error::invalid_parameter("encryptedSecret: can't be decrypted with the key");
+
+    let encrypted = STANDARD.decode(encrypted).map_err(|_| invalid())?;
+    let secret = key
+        .decrypt(Oaep::new::<Sha256>(), &encrypted)
+        .map_err(|_| invalid())?;
+
+    String::from_utf8(secret).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY: &str = include_str!("../tests/fixtures/export.pub.pem");
+    const PRIVATE_KEY: &str = include_str!("../tests/fixtures/export.pem");
+
+    #[test]
+    fn test_wrap() {
+        let public_key = parse_public_key(PUBLIC_KEY).unwrap();
+        let private_key = parse_private_key(PRIVATE_KEY).unwrap();
+
+        let wrapped = wrap(&public_key, b"secret").unwrap();
+        assert_eq!(unwrap(&private_key, &wrapped).unwrap(), "secret");
+
+        // Encryption is randomized.
+        assert_ne!(wrap(&public_key, b"secret").unwrap(), wrapped);
+
+        assert!(matches!(
+            unwrap(&private_key, "bm90IGVuY3J5cHRlZA=="),
+            Err(Error::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            parse_public_key(PRIVATE_KEY),
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+}
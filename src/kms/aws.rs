@@ -29,11 +29,11 @@ impl super::KeyManager for AwsKeyManager {
     /// Uses symmetric encryption with the default algorithm.
     fn encrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<u8>>>>> {
         let client = self.client.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
 
         Box::pin(async move {
             let EncryptOutput {
@@ -60,11 +60,11 @@ impl super::KeyManager for AwsKeyManager {
     /// Decrypts KMS-encrypted data using the specified key.
     fn decrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<u8>>>>> {
         let client = self.client.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             let decrypted = client
                 .decrypt()
@@ -101,9 +101,9 @@ impl super::KeyManager for AwsKeyManager {
     ///
     /// Note: This initiates key deletion with AWS KMS's standard
     /// waiting period before actual deletion.
-    fn delete_key(&self, key_id: &String) -> Pin<Box<dyn Future<Output = eyre::Result<()>>>> {
+    fn delete_key(&self, key_id: &str) -> Pin<Box<dyn Future<Output = eyre::Result<()>>>> {
         let client = self.client.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             client.schedule_key_deletion().key_id(key_id).send().await?;
             Ok(())
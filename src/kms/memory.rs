@@ -28,15 +28,21 @@ impl InMemoryKeyManager {
     }
 }
 
+impl Default for InMemoryKeyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KeyManager for InMemoryKeyManager {
     /// Encrypts data using AES-GCM-SIV with the specified key.
     fn encrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<u8>>>>> {
         let self_clone = self.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             let key = {
                 let guard = self_clone.keys.guard();
@@ -62,18 +68,18 @@ impl KeyManager for InMemoryKeyManager {
             })
             .await??;
 
-            Ok(encrypted.into())
+            Ok(encrypted)
         })
     }
 
     /// Decrypts AES-GCM-SIV encrypted data using the specified key.
     fn decrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn std::future::Future<Output = eyre::Result<Vec<u8>>>>> {
         let self_clone = self.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             let key = {
                 let guard = self_clone.keys.guard();
@@ -96,7 +102,7 @@ impl KeyManager for InMemoryKeyManager {
             })
             .await??;
 
-            Ok(decrypted.into())
+            Ok(decrypted)
         })
     }
 
@@ -131,13 +137,22 @@ impl KeyManager for InMemoryKeyManager {
     /// Removes a key from the in-memory store.
     fn delete_key(
         &self,
-        key_id: &String,
+        key_id: &str,
     ) -> Pin<Box<dyn std::future::Future<Output = eyre::Result<()>>>> {
         let self_clone = self.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             self_clone.keys.pin().remove(&key_id);
             Ok(())
         })
     }
+
+    /// Lists the IDs of every key in memory.
+    #[allow(clippy::type_complexity)]
+    fn list_keys(
+        &self,
+    ) -> Pin<Box<dyn std::future::Future<Output = eyre::Result<Option<Vec<String>>>>>> {
+        let key_ids = self.keys.pin().keys().cloned().collect();
+        Box::pin(async move { Ok(Some(key_ids)) })
+    }
 }
@@ -3,8 +3,13 @@
 //! This module provides traits and types for managing cryptographic keys and performing
 //! encryption/decryption operations in a generic way.
 
-use std::{future::Future, pin::Pin};
+use std::{future::Future, path::Path, pin::Pin};
 
+use secrecy::SecretString;
+
+use crate::error::Error;
+
+#[cfg(feature = "aws-kms")]
 pub mod aws;
 pub mod memory;
 pub mod sqlite;
@@ -31,6 +36,38 @@ impl Rotation {
     }
 }
 
+/// Gets the database passphrase stored at `path`, encrypted with the key `key_id`.
+///
+/// If the file doesn't exist yet, a random passphrase is generated and stored there encrypted, so
+/// that the passphrase itself is never stored in plain text. Pass the result to
+/// [`Service::connect_with`](crate::service::Service::connect_with) with a key manager that
+/// doesn't depend on the database, such as `aws::AwsKeyManager`.
+pub async fn database_key(
+    kms: &dyn KeyManager,
+    key_id: &str,
+    path: &Path,
+) -> Result<SecretString, Error> {
+    let passphrase = match std::fs::read(path) {
+        Ok(encrypted) => kms.decrypt(key_id, encrypted).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut passphrase = [0u8; 32];
+            rand::RngCore::try_fill_bytes(&mut rand::thread_rng(), &mut passphrase)
+                .map_err(Error::internal)?;
+            let passphrase = hex::encode(passphrase).into_bytes();
+
+            let encrypted = kms.encrypt(key_id, passphrase.clone()).await?;
+            std::fs::write(path, encrypted).map_err(Error::internal)?;
+
+            passphrase
+        }
+        Err(e) => return Err(Error::internal(e)),
+    };
+
+    String::from_utf8(passphrase)
+        .map(|passphrase| SecretString::new(passphrase.into()))
+        .map_err(Error::internal)
+}
+
 /// A trait for types that can be used as key identifiers.
 ///
 /// This trait is automatically implemented for any type that implements
@@ -67,7 +104,7 @@ pub trait KeyManager: Send + Sync + 'static {
     /// An [`Encrypted`] instance containing the encrypted data and the ID of the key used
     fn encrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<u8>>>>>;
 
@@ -81,7 +118,7 @@ pub trait KeyManager: Send + Sync + 'static {
     /// The decrypted data as [`Bytes`]
     fn decrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<u8>>>>>;
 
@@ -95,7 +132,20 @@ pub trait KeyManager: Send + Sync + 'static {
     ///
     /// # Warning
     /// Deleting a key will make it impossible to decrypt any data that was encrypted with it.
-    fn delete_key(&self, key_id: &String) -> Pin<Box<dyn Future<Output = eyre::Result<()>>>>;
+    fn delete_key(&self, key_id: &str) -> Pin<Box<dyn Future<Output = eyre::Result<()>>>>;
+
+    /// Lists the keys created by this key manager.
+    ///
+    /// Used to find keys that nothing refers to anymore, see [`crate::fsck`], so key managers
+    /// that can't tell their own keys apart from others, like one backed by a shared cloud
+    /// account, shouldn't implement it.
+    ///
+    /// # Returns
+    /// The IDs of the keys, or `None` if they can't be listed
+    #[allow(clippy::type_complexity)]
+    fn list_keys(&self) -> Pin<Box<dyn Future<Output = eyre::Result<Option<Vec<String>>>>>> {
+        Box::pin(async { Ok(None) })
+    }
 
     /// Begin a key rotation operation.
     ///
@@ -112,9 +162,9 @@ pub trait KeyManager: Send + Sync + 'static {
     /// attempting to decrypt data requiring the new key before it is activated.
     fn begin_rotation<'a>(
         &'a self,
-        key_id: &String,
+        key_id: &str,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Rotation>> + 'a>> {
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             let new_key = self.create_key().await?;
 
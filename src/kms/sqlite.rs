@@ -122,11 +122,11 @@ impl KeyManager for SqliteKeyManager {
     /// - Uses AES-256-GCM-SIV which provides both confidentiality and authenticity
     fn encrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn Future<Output = eyre::Result<Vec<u8>>>>> {
         let self_clone = self.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             let key = self_clone.get_key(&key_id).await?;
 
@@ -146,7 +146,7 @@ impl KeyManager for SqliteKeyManager {
             })
             .await??;
 
-            Ok(encrypted.into())
+            Ok(encrypted)
         })
     }
 
@@ -162,11 +162,11 @@ impl KeyManager for SqliteKeyManager {
     /// - Verifies data authenticity during decryption
     fn decrypt(
         &self,
-        key_id: &String,
+        key_id: &str,
         data: Vec<u8>,
     ) -> Pin<Box<dyn std::future::Future<Output = eyre::Result<Vec<u8>>>>> {
         let self_clone = self.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             let key = self_clone.get_key(&key_id).await?;
 
@@ -184,7 +184,7 @@ impl KeyManager for SqliteKeyManager {
             })
             .await??;
 
-            Ok(decrypted.into())
+            Ok(decrypted)
         })
     }
 
@@ -222,7 +222,7 @@ impl KeyManager for SqliteKeyManager {
                 ",
             )
             .bind(&key_id)
-            .bind(&key.as_slice())
+            .bind(key.as_slice())
             .execute(&self_clone.pool)
             .await?;
 
@@ -240,10 +240,10 @@ impl KeyManager for SqliteKeyManager {
     /// will no longer be decryptable after the key is deleted.
     fn delete_key(
         &self,
-        key_id: &String,
+        key_id: &str,
     ) -> Pin<Box<dyn std::future::Future<Output = eyre::Result<()>>>> {
         let self_clone = self.clone();
-        let key_id = key_id.clone();
+        let key_id = key_id.to_owned();
         Box::pin(async move {
             sqlx::query(
                 "
@@ -258,4 +258,19 @@ impl KeyManager for SqliteKeyManager {
             Ok(())
         })
     }
+
+    /// Lists the IDs of every key in the database.
+    #[allow(clippy::type_complexity)]
+    fn list_keys(
+        &self,
+    ) -> Pin<Box<dyn std::future::Future<Output = eyre::Result<Option<Vec<String>>>>>> {
+        let self_clone = self.clone();
+        Box::pin(async move {
+            let key_ids = sqlx::query_scalar("SELECT key_id FROM nervemq_sqlite_kms_keys")
+                .fetch_all(&self_clone.pool)
+                .await?;
+
+            Ok(Some(key_ids))
+        })
+    }
 }
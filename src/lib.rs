@@ -1,4 +1,4 @@
-use std::future::Future;
+use std::{future::Future, sync::Arc};
 
 use actix_cors::Cors;
 use actix_identity::IdentityMiddleware;
@@ -7,8 +7,11 @@ use actix_session::{
     SessionMiddleware,
 };
 use actix_web::{
-    middleware::{NormalizePath, TrailingSlash},
-    web::{Data, FormConfig, JsonConfig},
+    body::MessageBody,
+    cookie::Key,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    middleware::{from_fn, NormalizePath, TrailingSlash},
+    web::{Data, FormConfig, JsonConfig, PathConfig, QueryConfig},
     App, HttpServer,
 };
 use auth::{
@@ -19,136 +22,350 @@ use chrono::TimeDelta;
 use config::ConfigBuilder;
 use error::Error;
 use kms::KeyManager;
+use secrecy::SecretString;
 use sqlx::SqlitePool;
 use sqs::service::SqsApi;
-use tracing::level_filters::LevelFilter;
+use telemetry::TelemetrySink;
 use tracing_actix_web::TracingLogger;
-use tracing_subscriber::{util::SubscriberInitExt, EnvFilter, FmtSubscriber};
 
+mod ack;
 mod api;
+mod audit;
 mod auth;
+mod caching;
+pub mod client;
+mod clock;
+mod compression;
 pub mod config;
+mod consumer_group;
+mod consumers;
+#[cfg(feature = "dashboard")]
+mod dashboard;
 pub mod error;
+mod events;
+pub mod fsck;
+mod history;
+mod ingest;
+mod integrity;
+#[cfg(feature = "oidc")]
+mod jwt;
+mod key_export;
 pub mod kms;
+mod logging;
 mod message;
+mod message_move;
+pub mod migrate;
 mod namespace;
+mod notify;
+mod outbox;
+mod overview;
+pub mod provision;
+mod proxy;
+mod push;
 mod queue;
+mod rate_limit;
+mod redelivery;
+pub mod registry;
+mod report;
+mod request_id;
+mod sample;
+pub mod scim;
+mod selector;
 mod service;
 mod sqs;
+mod status;
+mod storage;
+mod stream;
+mod systemd;
+mod tasks;
+pub mod telemetry;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod tls;
+mod token_usage;
+pub mod top;
+mod trace;
+pub mod user_import;
 mod utils;
+pub mod version;
+mod webhooks;
+mod websocket;
 
 pub use sqs::method::*;
 pub use sqs::types;
 
+/// The parts of the HTTP API a server serves, so that NerveMQ can be embedded as only a data plane
+/// or only an admin plane. Everything is served by default.
+///
+/// Components only choose the endpoints. The background tasks, such as push delivery, outbox
+/// tailing and retention, always run, since they work on the queues whichever API is served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bon::Builder)]
+pub struct Components {
+    /// The SQS-compatible API, and the native endpoints producers and consumers use besides it:
+    /// ingestion, acknowledgements and consumer heartbeats
+    #[builder(default = true)]
+    pub sqs: bool,
+    /// Management of namespaces, queues, tokens, certificates and users, including the admin
+    /// and SCIM APIs
+    #[builder(default = true)]
+    pub management: bool,
+    /// Login and sessions for the dashboard, under `/auth`
+    #[builder(default = true)]
+    pub dashboard_auth: bool,
+    /// Queue and namespace statistics, under `/stats`, and the overview
+    #[builder(default = true)]
+    pub metrics: bool,
+}
+
+impl Default for Components {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Starts the background tasks of a service. They run whichever [`Components`] are served.
+pub(crate) fn spawn_tasks(service: &service::Service) {
+    tokio::spawn(events::audit(service.events().subscribe()));
+    outbox::spawn(service);
+    history::spawn(service);
+    trace::spawn(service);
+    token_usage::spawn(service);
+    audit::spawn(service);
+    consumers::spawn(service);
+    message_move::spawn(service);
+    push::spawn(service);
+    fsck::spawn(service);
+    storage::spawn(service);
+    tokio::spawn(webhooks::run(service.clone(), service.events().subscribe()));
+}
+
+/// Builds the application serving a service on every worker.
+pub(crate) fn app(
+    data: Data<service::Service>,
+    session_store: SqliteSessionStore,
+    secret_key: Key,
+    components: Components,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    const SESSION_EXPIRATION: TimeDelta = chrono::Duration::hours(1);
+
+    let deadline = SESSION_EXPIRATION.to_std().expect("valid duration");
+    let session_ttl = actix_web::cookie::time::Duration::new(SESSION_EXPIRATION.num_seconds(), 0);
+
+    let config = data.config();
+    #[cfg(feature = "dashboard")]
+    let dashboard = config.dashboard();
+
+    let session_middleware = SessionMiddleware::builder(session_store, secret_key)
+        .cookie_secure(config.cookie_secure())
+        .cookie_same_site(config.cookie_same_site().into())
+        .cookie_domain(config.cookie_domain().map(str::to_owned))
+        .cookie_content_security(CookieContentSecurity::Signed)
+        .session_lifecycle(PersistentSession::default().session_ttl(session_ttl))
+        .cookie_http_only(true)
+        .cookie_name(config.cookie_name().to_owned())
+        .build();
+
+    let identity_middleware = IdentityMiddleware::builder()
+        .visit_deadline(Some(deadline))
+        .logout_behaviour(actix_identity::config::LogoutBehaviour::PurgeSession)
+        .id_key(auth::session::IDENTITY_KEY)
+        .build();
+
+    let cors = Cors::default()
+        .supports_credentials()
+        .allow_any_origin()
+        .allow_any_header()
+        .allow_any_method();
+
+    // Malformed request data gets the same error body as every other error.
+    let json_cfg = JsonConfig::default()
+        .content_type_required(false)
+        .error_handler(|e, _| Error::invalid_parameter(e.to_string()).into());
+    let query_cfg =
+        QueryConfig::default().error_handler(|e, _| Error::invalid_parameter(e.to_string()).into());
+    let path_cfg =
+        PathConfig::default().error_handler(|e, _| Error::invalid_parameter(e.to_string()).into());
+    let form_cfg = FormConfig::default();
+
+    let mut app = App::new()
+        .wrap(
+            // IMPORTANT: This must be first in the middleware stack (executed last) because
+            // it mutated the request path, which breaks AWS SigV4 authentication because the
+            // request path is used in the hash/signature. We do need this however, since the
+            // Actix router doesn't seem to work without it.
+            NormalizePath::new(TrailingSlash::Trim),
+        )
+        .wrap(from_fn(audit::record_requests))
+        .wrap(from_fn(logging::scope_request_span))
+        .wrap(TracingLogger::<proxy::ClientRootSpanBuilder>::new())
+        .wrap(Authentication)
+        .wrap(identity_middleware)
+        .wrap(session_middleware)
+        .wrap(cors)
+        .wrap(from_fn(overview::record_responses))
+        .wrap(from_fn(telemetry::record_requests))
+        .wrap(request_id::AssignRequestId)
+        .service(api::service(&components).wrap(Protected::authenticated()))
+        .service(status::get_status)
+        .app_data(data)
+        .app_data(json_cfg)
+        .app_data(query_cfg)
+        .app_data(path_cfg)
+        .app_data(form_cfg);
+
+    if components.sqs {
+        app = app
+            .service(
+                api::ingest::service()
+                    .wrap(from_fn(rate_limit::limit_requests))
+                    .wrap(Protected::authenticated()),
+            )
+            .service(
+                sqs::service()
+                    .wrap(from_fn(rate_limit::limit_requests))
+                    .wrap(Protected::authenticated())
+                    .wrap(SqsApi),
+            );
+    }
+    if components.metrics {
+        app = app.service(api::data::service().wrap(Protected::authenticated()));
+    }
+    if components.management {
+        app = app
+            .service(api::queue::service().wrap(Protected::authenticated()))
+            .service(api::tokens::service().wrap(Protected::authenticated()))
+            .service(api::certificates::service().wrap(Protected::authenticated()))
+            .service(api::trace::service().wrap(Protected::authenticated()))
+            .service(api::namespace::service().wrap(Protected::authenticated()))
+            .service(api::admin::user_service().wrap(Protected::admin_or_self("email")))
+            .service(api::admin::service().wrap(Protected::admin_only()))
+            .service(api::outbox::service().wrap(Protected::admin_only()))
+            .service(api::scim::service().wrap(from_fn(api::scim::authenticate)));
+    }
+    if components.dashboard_auth {
+        app = app.service(api::auth::service());
+
+        #[cfg(feature = "dashboard")]
+        if dashboard {
+            app = app.default_service(dashboard::service());
+        }
+    }
+
+    app
+}
+
 /// Returns a builder for the main application.
+///
+/// # Arguments
+/// * `kms_factory` - Creates the key manager from the database
+/// * `db_key` - Key to decrypt the database with
+/// * `components` - Parts of the API to serve. Defaults to all of them.
+/// * `overrides` - Configuration taking precedence over the environment, like command line flags
+/// * `telemetry` - Sink to report requests, messages and errors to, see [`telemetry`]
 #[bon::builder(finish_fn = start)]
-pub async fn run<K, F, R>(kms_factory: K) -> eyre::Result<()>
+pub async fn run<K, F, R>(
+    kms_factory: K,
+    db_key: Option<SecretString>,
+    #[builder(default)] components: Components,
+    overrides: Option<config::Config>,
+    telemetry: Option<Arc<dyn TelemetrySink>>,
+) -> eyre::Result<()>
 where
     K: FnOnce(SqlitePool) -> F,
     F: Future<Output = Result<R, Error>>,
     R: KeyManager,
 {
-    #[cfg(debug_assertions)]
-    FmtSubscriber::builder()
-        .pretty()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_env_var("NERVEMQ_LOG")
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env()?,
-        )
-        .finish()
-        .try_init()?;
-
-    #[cfg(not(debug_assertions))]
-    FmtSubscriber::builder()
-        .json()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_env_var("NERVEMQ_LOG")
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env()?,
-        )
-        .finish()
-        .try_init()?;
+    logging::init()?;
 
-    let config = ConfigBuilder::new()
+    let activated = systemd::listeners()?;
+
+    let mut config = ConfigBuilder::new()
         .with_layer(config::DefaultsLayer)
-        .with_layer(config::EnvironmentLayer)
-        .load()
-        .await?;
+        .with_layer(config::EnvironmentLayer);
+    if let Some(overrides) = overrides {
+        config = config.with_layer(config::ValueLayer::new(overrides));
+    }
+    let config = config.load().await?;
 
     let service = service::Service::connect_with()
         .config(config)
         .kms_factory(kms_factory)
+        .maybe_db_key(db_key)
+        .maybe_telemetry(telemetry)
         .call()
         .await?;
 
     let session_store = SqliteSessionStore::new(service.db().clone());
 
-    // FIXME: This should be generated on first run and stored in a file, or pulled from config
-    let secret_key = actix_web::cookie::Key::generate();
+    let secret_key = auth::signing_key::load(&service).await?;
+
+    let tls_config = tls::server_config(service.config())?;
+    let bind = service.config().bind().to_owned();
+    let tls_bind = service.config().tls_bind().parse()?;
 
+    spawn_tasks(&service);
+
+    let workers = service.config().workers();
     let data = Data::new(service);
 
-    const SESSION_EXPIRATION: TimeDelta = chrono::Duration::hours(1);
+    let tls_server = match tls_config {
+        Some(tls_config) => Some(tls::server(
+            tls_bind,
+            activated.https,
+            tls_config,
+            data.clone(),
+            session_store.clone(),
+            secret_key.clone(),
+            components,
+        )?),
+        None => {
+            if !activated.https.is_empty() {
+                tracing::warn!("HTTPS is not configured, ignoring the https sockets from systemd");
+            }
+            None
+        }
+    };
 
-    let deadline = SESSION_EXPIRATION.to_std().expect("valid duration");
-    let session_ttl = actix_web::cookie::time::Duration::new(SESSION_EXPIRATION.num_seconds(), 0);
+    let mut server = HttpServer::new(move || {
+        app(
+            data.clone(),
+            session_store.clone(),
+            secret_key.clone(),
+            components,
+        )
+    });
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+    if activated.http.is_empty() {
+        server = server.bind(bind)?;
+    }
+    for listener in activated.http {
+        server = server.listen(listener)?;
+    }
+    let server = server.run();
 
-    HttpServer::new(move || {
-        let session_middleware =
-            SessionMiddleware::builder(session_store.clone(), secret_key.clone())
-                .cookie_secure(true)
-                .cookie_content_security(CookieContentSecurity::Signed)
-                .session_lifecycle(PersistentSession::default().session_ttl(session_ttl))
-                .cookie_http_only(true)
-                .cookie_name("nervemq_session".to_owned())
-                .build();
-
-        let identity_middleware = IdentityMiddleware::builder()
-            .visit_deadline(Some(deadline))
-            .logout_behaviour(actix_identity::config::LogoutBehaviour::PurgeSession)
-            .id_key("nervemq_id")
-            .build();
-
-        let cors = Cors::default()
-            .supports_credentials()
-            .allow_any_origin()
-            .allow_any_header()
-            .allow_any_method();
-
-        let json_cfg = JsonConfig::default().content_type_required(false);
-        let form_cfg = FormConfig::default();
-
-        App::new()
-            .wrap(
-                // IMPORTANT: This must be first in the middleware stack (executed last) because
-                // it mutated the request path, which breaks AWS SigV4 authentication because the
-                // request path is used in the hash/signature. We do need this however, since the
-                // Actix router doesn't seem to work without it.
-                NormalizePath::new(TrailingSlash::Trim),
-            )
-            .wrap(TracingLogger::default())
-            .wrap(Authentication)
-            .wrap(identity_middleware)
-            .wrap(session_middleware)
-            .wrap(cors)
-            .service(api::queue::service().wrap(Protected::authenticated()))
-            .service(api::data::service().wrap(Protected::authenticated()))
-            .service(api::tokens::service().wrap(Protected::authenticated()))
-            .service(sqs::service().wrap(Protected::authenticated()).wrap(SqsApi))
-            .service(api::namespace::service().wrap(Protected::admin_only()))
-            .service(api::admin::service().wrap(Protected::admin_only()))
-            .service(api::auth::service())
-            .app_data(data.clone())
-            .app_data(json_cfg)
-            .app_data(form_cfg)
-    })
-    // .bind_openssl(("127.0.0.1", 8080), ssl_acceptor)?
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await?;
-
-    Ok(())
+    if let Err(e) = systemd::notify("READY=1") {
+        tracing::warn!("Failed to notify systemd: {e}");
+    }
+    tokio::spawn(systemd::watchdog());
+
+    let result = match tls_server {
+        Some(tls_server) => tokio::try_join!(server, tls_server).map(|_| ()),
+        None => server.await,
+    };
+
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        tracing::warn!("Failed to notify systemd: {e}");
+    }
+
+    Ok(result?)
 }
@@ -0,0 +1,220 @@
+//! Logging setup.
+//!
+//! Log output is filtered by `NERVEMQ_LOG` (in [`EnvFilter`] syntax, e.g.
+//! `info,nervemq::sqs=debug`) when the server starts. Admins can replace the filter at runtime
+//! with `PUT /admin/log-level`, for example to turn on debug logging for one module during an
+//! incident. Changes last until the filter is changed again or the server restarts.
+//!
+//! Every request's root span carries correlation fields, so that log queries can slice by tenant:
+//! `http.request_id`, `enduser.id` (the email of the authenticated user), `nervemq.api_key` (the
+//! ID of the API key the request was authenticated with) and `nervemq.namespace` and
+//! `nervemq.queue`, the namespace and queue the request is about. The namespace and queue are
+//! only known once the handler has parsed the request, so the service records them with
+//! [`record_namespace`] and [`record_queue`] when it looks them up.
+
+use std::{cell::Cell, sync::OnceLock};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    HttpMessage,
+};
+use tracing::level_filters::LevelFilter;
+use tracing_actix_web::RootSpan;
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+use crate::{auth::credential::AuthorizedNamespace, error::Error};
+
+/// Environment variable holding the initial log filter.
+pub const LOG_FILTER_ENV: &str = "NERVEMQ_LOG";
+
+tokio::task_local! {
+    /// Root span of the request being handled, for code that doesn't have access to the request.
+    static REQUEST_SPAN: RequestSpan;
+}
+
+/// Root span of a request, and which of its correlation fields were recorded already.
+struct RequestSpan {
+    span: tracing::Span,
+    namespace: Cell<bool>,
+    queue: Cell<bool>,
+}
+
+/// Handle to change the filter of the subscriber installed by [`init`].
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global tracing subscriber, filtered by [`LOG_FILTER_ENV`]. Debug builds log in a
+/// human-readable format, release builds log JSON.
+pub fn init() -> eyre::Result<()> {
+    let filter = EnvFilter::builder()
+        .with_env_var(LOG_FILTER_ENV)
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env()?;
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let registry = tracing_subscriber::registry().with(filter);
+
+    #[cfg(debug_assertions)]
+    registry.with(fmt::layer().pretty()).try_init()?;
+
+    #[cfg(not(debug_assertions))]
+    registry.with(fmt::layer().json()).try_init()?;
+
+    // The subscriber can only be installed once, so neither can the handle.
+    let _ = FILTER.set(handle);
+
+    Ok(())
+}
+
+/// Returns the current log filter, or `None` if logging wasn't set up by [`init`].
+pub fn filter() -> Option<String> {
+    FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replaces the log filter.
+///
+/// # Arguments
+/// * `directives` - New filter, in [`EnvFilter`] syntax
+///
+/// # Errors
+/// Returns [`Error::InvalidParameter`] if the directives are invalid, and [`Error::NotFound`] if
+/// logging wasn't set up by [`init`] (e.g. when NerveMQ is embedded in another application)
+pub fn set_filter(directives: &str) -> Result<(), Error> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(directives)
+        .map_err(|e| Error::invalid_parameter(format!("filter: {e}")))?;
+
+    FILTER
+        .get()
+        .ok_or_else(|| Error::not_found("log filter"))?
+        .reload(filter)
+        .map_err(Error::internal)?;
+
+    tracing::info!(filter = directives, "Log filter changed");
+
+    Ok(())
+}
+
+/// Middleware that makes the root span of each request available to [`record_namespace`] and
+/// [`record_queue`]. It must be wrapped by the `TracingLogger` middleware that creates the span.
+pub async fn scope_request_span(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(span) = req
+        .extensions()
+        .get::<RootSpan>()
+        .map(|span| (**span).clone())
+    else {
+        return next.call(req).await;
+    };
+    // API keys are scoped to a namespace, which the root span starts out with.
+    let scoped = req.extensions().get::<AuthorizedNamespace>().is_some();
+
+    let span = RequestSpan {
+        span,
+        namespace: Cell::new(scoped),
+        queue: Cell::new(false),
+    };
+    REQUEST_SPAN.scope(span, next.call(req)).await
+}
+
+/// Records the namespace the request being handled is about on its root span, unless one was
+/// recorded already. Does nothing outside of requests.
+pub fn record_namespace(namespace: &str) {
+    let _ = REQUEST_SPAN.try_with(|request| {
+        if !request.namespace.replace(true) {
+            request.span.record("nervemq.namespace", namespace);
+        }
+    });
+}
+
+/// Records the queue the request being handled is about, and its namespace, on its root span,
+/// unless a queue was recorded already. Does nothing outside of requests.
+pub fn record_queue(namespace: &str, queue: &str) {
+    let _ = REQUEST_SPAN.try_with(|request| {
+        if !request.queue.replace(true) {
+            request.namespace.set(true);
+            request.span.record("nervemq.namespace", namespace);
+            request.span.record("nervemq.queue", queue);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_filter_validates_directives() {
+        assert!(matches!(
+            set_filter("nervemq::sqs=loud"),
+            Err(Error::InvalidParameter { .. })
+        ));
+
+        // Tests don't install the subscriber, so there is no filter to change.
+        assert!(matches!(
+            set_filter("info,nervemq::sqs=debug"),
+            Err(Error::NotFound { .. })
+        ));
+        assert_eq!(filter(), None);
+    }
+
+    #[test]
+    fn test_record_queue() {
+        let output = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            fmt::layer()
+                .json()
+                .with_writer(move || WriterGuard(writer.clone())),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "request",
+                nervemq.namespace = tracing::field::Empty,
+                nervemq.queue = tracing::field::Empty
+            );
+            let request = RequestSpan {
+                span: span.clone(),
+                namespace: Cell::new(false),
+                queue: Cell::new(false),
+            };
+            REQUEST_SPAN.sync_scope(request, || {
+                record_queue("orders", "jobs");
+                // Later lookups, like of a dead-letter queue, don't replace the first.
+                record_queue("orders", "jobs-dlq");
+                record_namespace("other");
+            });
+            span.in_scope(|| tracing::info!("handled"));
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.contains(r#""nervemq.namespace":"orders""#),
+            "{output}"
+        );
+        assert!(output.contains(r#""nervemq.queue":"jobs""#), "{output}");
+
+        // Outside of requests there is nothing to record on.
+        record_queue("orders", "jobs");
+    }
+
+    struct WriterGuard(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for WriterGuard {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,9 +1,563 @@
-use nervemq::kms::sqlite::SqliteKeyManager;
+use std::{
+    collections::HashMap,
+    io::{IsTerminal as _, Read as _},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::{Args, Parser, Subcommand};
+use nervemq::{
+    client::{Client, ReceiveOptions},
+    config::{self, Config, ConfigBuilder},
+    fsck,
+    kms::sqlite::SqliteKeyManager,
+    migrate::{self, MigrationLock, MigrationStatus},
+    provision, top,
+    types::SqsMessageAttribute,
+    user_import,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Serialize;
+
+/// Portable, SQS-compatible message queue backed by SQLite.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Configuration given as flags, which takes precedence over the `NERVEMQ_` environment
+/// variables. Fields are named like the configuration fields they set.
+#[derive(Args, Serialize)]
+struct ConfigArgs {
+    /// Path to the SQLite database
+    #[arg(long, global = true)]
+    db_path: Option<String>,
+    /// Address the HTTP listener binds to (e.g. `0.0.0.0:8080`)
+    #[arg(long, global = true)]
+    bind: Option<String>,
+    /// Address the HTTPS listener binds to (e.g. `0.0.0.0:8443`)
+    #[arg(long, global = true)]
+    tls_bind: Option<String>,
+    /// Base URL the server is reachable at, used in queue URLs
+    #[arg(long, global = true)]
+    host: Option<String>,
+    /// Number of worker threads per listener
+    #[arg(long, global = true)]
+    workers: Option<usize>,
+    /// Path to a TOML or YAML provisioning file applied on startup
+    #[arg(long, global = true)]
+    provision_file: Option<String>,
+    /// Start in maintenance mode
+    #[arg(long, global = true)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    maintenance_mode: bool,
+}
+
+impl ConfigArgs {
+    /// Converts the flags into a configuration in which only the given ones are set.
+    fn overrides(&self) -> eyre::Result<Config> {
+        Ok(serde_json::from_value(serde_json::to_value(self)?)?)
+    }
+
+    /// Loads the configuration from the defaults, the environment and the flags.
+    async fn load(&self) -> eyre::Result<Config> {
+        Ok(ConfigBuilder::new()
+            .with_layer(config::DefaultsLayer)
+            .with_layer(config::EnvironmentLayer)
+            .with_layer(config::ValueLayer::new(self.overrides()?))
+            .load()
+            .await?)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the server (the default)
+    Serve,
+    /// Manage the database schema
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Create the namespaces, queues, users and tokens described in a provisioning file
+    Apply {
+        /// TOML or YAML provisioning file
+        file: PathBuf,
+    },
+    /// Manage users
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Find and remove rows whose message or queue no longer exists
+    Fsck {
+        /// Only report the orphaned rows
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Create an administrator, or make an existing user one
+    CreateAdmin {
+        /// Email address of the administrator
+        email: String,
+        /// File containing the password. A random one is generated and printed if not given.
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+    },
+    /// Send a message to a queue of the server at `--host`
+    Send {
+        #[command(flatten)]
+        client: ClientArgs,
+        /// Name of the queue, in the token's namespace, or its URL
+        queue: String,
+        /// File containing the message body. The body is read from stdin if not given.
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// String attribute of the message. May be repeated.
+        #[arg(long = "attribute", value_name = "NAME=VALUE", value_parser = parse_attribute)]
+        attributes: Vec<(String, String)>,
+        /// Number attribute of the message. May be repeated.
+        #[arg(long = "number-attribute", value_name = "NAME=VALUE", value_parser = parse_attribute)]
+        number_attributes: Vec<(String, String)>,
+        /// Number of seconds before the message can be received
+        #[arg(long)]
+        delay_seconds: Option<u64>,
+    },
+    /// Print the messages of a queue of the server at `--host` as they arrive, and delete them
+    Tail {
+        #[command(flatten)]
+        client: ClientArgs,
+        /// Name of the queue, in the token's namespace, or its URL
+        queue: String,
+        /// Only receive the messages matching this message selector, like `priority > 5`
+        #[arg(long)]
+        selector: Option<String>,
+        /// Don't delete printed messages, which are received again after their visibility timeout
+        #[arg(long)]
+        no_ack: bool,
+        /// Stop after this many messages
+        #[arg(long)]
+        count: Option<u64>,
+        /// Print each message as a line of JSON with its ID and attributes, rather than its body
+        #[arg(long)]
+        json: bool,
+        /// Message attribute to print with `--json`, or `prefix.*` for those whose names start
+        /// with `prefix.`. May be repeated. Every attribute is printed if not given.
+        #[arg(long = "attribute", value_name = "NAME")]
+        attributes: Vec<String>,
+    },
+    /// Show live queue depths, rates, in-flight and failed messages of the server at `--host`
+    Top {
+        #[command(flatten)]
+        client: ClientArgs,
+        /// Number of seconds between refreshes
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+    },
+}
+
+/// Maximum number of messages `tail` receives at once, as allowed by ReceiveMessage.
+const MAX_RECEIVED: u64 = 10;
+
+/// How the `send`, `tail` and `top` commands reach the server.
+#[derive(Args)]
+struct ClientArgs {
+    /// API token, as `nervemq_<access key>_<secret key>`
+    #[arg(long, env = "NERVEMQ_TOKEN", hide_env_values = true)]
+    token: String,
+}
+
+impl ClientArgs {
+    async fn connect(&self, args: &ConfigArgs) -> eyre::Result<Client> {
+        let config = args.load().await?;
+        Client::new(&config.host(), &self.token)
+    }
+}
+
+fn parse_attribute(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{value}`"))
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// Create the users in a CSV or JSON file, either all of them or none
+    Import {
+        /// CSV file with `email`, `role` and `namespaces` columns, or JSON file ending in `.json`
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateCommand {
+    /// List migrations and whether they have been applied
+    Status,
+    /// Apply all pending migrations
+    Up {
+        /// Only print the migrations that would be applied
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip the disk space and write-ahead log checks
+        #[arg(long)]
+        force: bool,
+    },
+    /// Revert applied migrations
+    Down {
+        /// Revert every migration after this version, instead of only the latest one
+        #[arg(long)]
+        target: Option<i64>,
+        /// Only print the migrations that would be reverted
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip the disk space and write-ahead log checks
+        #[arg(long)]
+        force: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    nervemq::run()
-        .kms_factory(|db| SqliteKeyManager::new(db))
+    let cli = Cli::parse();
+    let args = &cli.config;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            nervemq::run()
+                .kms_factory(SqliteKeyManager::new)
+                .overrides(args.overrides()?)
+                .start()
+                .await
+        }
+        Command::Migrate(command) => self::migrate(args, command).await,
+        Command::Apply { file } => apply(args, file).await,
+        Command::Users(UsersCommand::Import { file }) => import_users(args, file).await,
+        Command::Fsck { dry_run } => fsck(args, dry_run).await,
+        Command::CreateAdmin {
+            email,
+            password_file,
+        } => create_admin(args, &email, password_file).await,
+        Command::Send {
+            client,
+            queue,
+            file,
+            attributes,
+            number_attributes,
+            delay_seconds,
+        } => {
+            let attributes = attributes
+                .into_iter()
+                .map(|(name, string_value)| (name, SqsMessageAttribute::String { string_value }))
+                .chain(number_attributes.into_iter().map(|(name, string_value)| {
+                    (name, SqsMessageAttribute::Number { string_value })
+                }))
+                .collect();
+            send(args, client, &queue, file, attributes, delay_seconds).await
+        }
+        Command::Tail {
+            client,
+            queue,
+            selector,
+            no_ack,
+            count,
+            json,
+            attributes,
+        } => {
+            let options = ReceiveOptions {
+                wait_time_seconds: Some(20),
+                message_selector: selector,
+                attribute_names: vec!["All".to_owned()],
+                message_attribute_names: match attributes.is_empty() {
+                    true => vec!["All".to_owned()],
+                    false => attributes,
+                },
+                ..Default::default()
+            };
+            tail(args, client, &queue, options, !no_ack, count, json).await
+        }
+        Command::Top { client, interval } => {
+            top(args, client, Duration::from_secs(interval.max(1))).await
+        }
+    }
+}
+
+async fn send(
+    args: &ConfigArgs,
+    client: ClientArgs,
+    queue: &str,
+    file: Option<PathBuf>,
+    attributes: HashMap<String, SqsMessageAttribute>,
+    delay_seconds: Option<u64>,
+) -> eyre::Result<()> {
+    let client = client.connect(args).await?;
+    let queue_url = client.queue_url(queue).await?;
+
+    let body = match file {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => {
+            let mut body = String::new();
+            std::io::stdin().read_to_string(&mut body)?;
+            body
+        }
+    };
+
+    let message_id = client
+        .send_message(&queue_url, &body, &attributes, delay_seconds)
+        .await?;
+    println!("{message_id}");
+
+    Ok(())
+}
+
+async fn tail(
+    args: &ConfigArgs,
+    client: ClientArgs,
+    queue: &str,
+    mut options: ReceiveOptions,
+    ack: bool,
+    count: Option<u64>,
+    json: bool,
+) -> eyre::Result<()> {
+    let client = client.connect(args).await?;
+    let queue_url = client.queue_url(queue).await?;
+
+    let mut printed = 0;
+    while count.is_none_or(|count| printed < count) {
+        let remaining = count.map_or(MAX_RECEIVED, |count| count - printed);
+        options.max_number_of_messages = Some(remaining.min(MAX_RECEIVED));
+
+        for message in client.receive_messages(&queue_url, &options).await? {
+            if json {
+                let attributes = message
+                    .message_attributes
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), value.to_json()?)))
+                    .collect::<eyre::Result<serde_json::Map<_, _>>>()?;
+                let line = serde_json::json!({
+                    "messageId": message.message_id,
+                    "body": message.body,
+                    "attributes": message.attributes,
+                    "messageAttributes": attributes,
+                });
+                println!("{line}");
+            } else {
+                println!("{}", message.body);
+            }
+            printed += 1;
+
+            if ack {
+                client
+                    .delete_message(&queue_url, &message.receipt_handle)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn top(args: &ConfigArgs, client: ClientArgs, interval: Duration) -> eyre::Result<()> {
+    let client = client.connect(args).await?;
+    // Redrawing only makes sense on a terminal, otherwise each refresh is appended.
+    let terminal = std::io::stdout().is_terminal();
+
+    let mut dashboard = top::Dashboard::new();
+    let mut ticks = tokio::time::interval(interval);
+    let mut last = Instant::now();
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+
+        let statistics = client.queue_statistics().await?;
+        let rows = dashboard.refresh(statistics, last.elapsed());
+        last = Instant::now();
+
+        let title = format!(
+            "{} - {} queues - every {}s, Ctrl-C to quit",
+            client.host(),
+            rows.len(),
+            interval.as_secs()
+        );
+        let table = top::render(&rows, &title);
+        match terminal {
+            true => print!("{}{table}", top::CLEAR_SCREEN),
+            false => println!("{table}"),
+        }
+    }
+}
+
+async fn apply(args: &ConfigArgs, file: PathBuf) -> eyre::Result<()> {
+    let config = args.load().await?;
+
+    let changes = provision::apply_file()
+        .config(config)
+        .kms_factory(SqliteKeyManager::new)
+        .path(&file)
         .start()
-        .await
+        .await?;
+
+    if changes.is_empty() {
+        println!("Nothing to do");
+    }
+
+    for change in changes {
+        println!("{change}");
+    }
+
+    Ok(())
+}
+
+async fn import_users(args: &ConfigArgs, file: PathBuf) -> eyre::Result<()> {
+    let config = args.load().await?;
+
+    let response = user_import::import_file()
+        .config(config)
+        .kms_factory(SqliteKeyManager::new)
+        .path(&file)
+        .start()
+        .await?;
+
+    for row in &response.rows {
+        match &row.message {
+            Some(message) => println!("{:>4}  {:<8} {}: {message}", row.row, "failed", row.email),
+            None => println!(
+                "{:>4}  {:<8} {}",
+                row.row,
+                format!("{:?}", row.status).to_lowercase(),
+                row.email
+            ),
+        }
+    }
+
+    if response.created == 0 {
+        eyre::bail!("No users were imported");
+    }
+
+    println!("Imported {} users", response.created);
+
+    Ok(())
+}
+
+async fn fsck(args: &ConfigArgs, dry_run: bool) -> eyre::Result<()> {
+    let config = args.load().await?;
+
+    let pool = migrate::connect(config.db_path(), config.db_key()?.as_ref()).await?;
+
+    let found = fsck::sweep(&pool, dry_run).await?;
+    if found.is_empty() {
+        println!("No orphaned rows");
+    }
+
+    let verb = if dry_run { "found" } else { "removed" };
+    for fsck::Orphans { table, count } in found {
+        println!("{table:<20} {count} orphaned rows {verb}");
+    }
+
+    Ok(())
+}
+
+async fn create_admin(
+    args: &ConfigArgs,
+    email: &str,
+    password_file: Option<PathBuf>,
+) -> eyre::Result<()> {
+    let config = args.load().await?;
+
+    let (password, generated) = match password_file {
+        Some(path) => (std::fs::read_to_string(path)?.trim().to_owned(), false),
+        None => (
+            rand::thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(24)
+                .map(char::from)
+                .collect(),
+            true,
+        ),
+    };
+    let password = SecretString::new(password.into());
+
+    let changes = provision::create_admin()
+        .config(config)
+        .kms_factory(SqliteKeyManager::new)
+        .email(email)
+        .password(password.clone())
+        .start()
+        .await?;
+
+    if changes.is_empty() {
+        println!("{email} is already an administrator");
+    }
+
+    for change in &changes {
+        println!("{change}");
+    }
+
+    if generated
+        && changes
+            .iter()
+            .any(|change| change.starts_with("created user"))
+    {
+        println!("Password: {}", password.expose_secret());
+    }
+
+    Ok(())
+}
+
+async fn migrate(args: &ConfigArgs, command: MigrateCommand) -> eyre::Result<()> {
+    let config = args.load().await?;
+    let db_path = config.db_path();
+
+    let pool = migrate::connect(db_path, config.db_key()?.as_ref()).await?;
+
+    let (migrations, verb) = match command {
+        MigrateCommand::Status => {
+            for migration in migrate::status(&pool).await? {
+                println!(
+                    "{:>4}  {:<9} {}",
+                    migration.version,
+                    format!("{:?}", migration.state).to_lowercase(),
+                    migration.description
+                );
+            }
+            return Ok(());
+        }
+        MigrateCommand::Up { dry_run, force } => {
+            let _lock = MigrationLock::try_acquire(db_path)?;
+            if !force {
+                migrate::preflight(db_path)?;
+            }
+            let applied = migrate::up(&pool, dry_run).await?;
+            (applied, if dry_run { "Would apply" } else { "Applied" })
+        }
+        MigrateCommand::Down {
+            target,
+            dry_run,
+            force,
+        } => {
+            let _lock = MigrationLock::try_acquire(db_path)?;
+            if !force {
+                migrate::preflight(db_path)?;
+            }
+            let reverted = migrate::down(&pool, target, dry_run).await?;
+            (reverted, if dry_run { "Would revert" } else { "Reverted" })
+        }
+    };
+
+    if migrations.is_empty() {
+        println!("Nothing to do");
+    }
+
+    for MigrationStatus {
+        version,
+        description,
+        ..
+    } in migrations
+    {
+        println!("{verb} {version}: {description}");
+    }
+
+    Ok(())
 }
@@ -16,7 +16,9 @@
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
-use sqlx::prelude::FromRow;
+use sqlx::{prelude::FromRow, sqlite::SqliteRow, Row};
+
+use crate::compression;
 
 /// Represents the current status of a message in the queue system.
 ///
@@ -51,10 +53,12 @@ pub enum MessageStatus {
 ///
 /// Messages are stored in the database and can be tracked through their
 /// lifecycle using the `status` field.
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Serialize, Deserialize)]
 pub struct Message {
     /// Unique identifier for the message
     pub id: u64,
+    /// Globally unique ID the message is known by in the SQS API
+    pub message_id: String,
     /// Name of the queue this message belongs to
     pub queue: String,
 
@@ -66,11 +70,69 @@ pub struct Message {
     pub body: String,
     /// Number of delivery attempts made
     pub tries: u64,
+    /// Trace id shared with the message's copies in other queues
+    pub trace_id: Option<String>,
 
     /// Current status of the message
     pub status: MessageStatus,
 
-    #[sqlx(skip)]
     /// Arbitrary key-value pairs associated with the message
     pub kv: HashMap<String, String>,
 }
+
+/// Maximum number of failure reasons kept per message. Older reasons are dropped.
+pub const MAX_RECORDED_FAILURES: u64 = 10;
+
+/// Maximum length, in bytes, of a failure reason reported by a consumer.
+pub const MAX_FAILURE_REASON_LENGTH: usize = 1024;
+
+/// A failure a consumer reported when it gave up on a delivery of a message, with
+/// ChangeMessageVisibility's `FailureReason` extension.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageFailure {
+    pub reason: String,
+    /// Consumer group the message was received by, if any
+    pub consumer_group: Option<String>,
+    /// Number of times the message had been received when the failure was reported
+    pub tries: u64,
+    /// Unix timestamp of the report
+    pub reported_at: u64,
+}
+
+/// Generates a new message ID. UUIDv7s are ordered by creation time, which keeps the index on
+/// message IDs compact.
+pub fn new_message_id() -> String {
+    uuid::Uuid::now_v7().to_string()
+}
+
+/// Returns the sequence number of a message sent to a group: its UUIDv7 message ID read as a
+/// number, which increases with every send without revealing the message's row.
+pub fn sequence_number(message_id: &str) -> Option<String> {
+    let id = uuid::Uuid::parse_str(message_id).ok()?;
+    Some(id.as_u128().to_string())
+}
+
+/// Messages are read from `messages` rows, whose body is decompressed if it is stored compressed.
+impl FromRow<'_, SqliteRow> for Message {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let body = compression::decode_body(row.try_get("body")?, row.try_get("compressed")?)
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "body".to_owned(),
+                source: Box::new(e),
+            })?;
+
+        Ok(Self {
+            id: row.try_get("id")?,
+            message_id: row.try_get("message_id")?,
+            queue: row.try_get("queue")?,
+            delivered_at: row.try_get("delivered_at")?,
+            sent_by: row.try_get("sent_by")?,
+            body,
+            tries: row.try_get("tries")?,
+            trace_id: row.try_get("trace_id")?,
+            status: row.try_get("status")?,
+            kv: HashMap::new(),
+        })
+    }
+}
@@ -0,0 +1,86 @@
+//! Message move tasks, which redrive the messages of a dead-letter queue.
+//!
+//! `StartMessageMoveTask` starts moving the messages that are in a dead-letter queue to another
+//! queue, or, without a destination, each back to the queue it was dead-lettered from. Messages are
+//! moved as fresh messages, at most `MaxNumberOfMessagesPerSecond` of them per second, by a
+//! background task that picks up every running task every [job poll
+//! interval](crate::config::TaskIntervals::job_poll). Progress is kept in the `message_move_tasks`
+//! table, so tasks carry on after a restart, and can be followed with `ListMessageMoveTasks` and
+//! stopped with `CancelMessageMoveTask`.
+//!
+//! Only the messages that were in the queue when the task started are moved. A queue has at most
+//! one running task. A task fails if, without a destination, it finds messages whose source queue
+//! is unknown, because they were dead-lettered before sources were recorded or their source queue
+//! has been deleted.
+
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::{service::Service, tasks};
+
+/// The highest `MaxNumberOfMessagesPerSecond`, which is also the rate of tasks that don't set one.
+pub const MAX_MESSAGES_PER_SECOND: u64 = 500;
+
+/// Maximum number of tasks returned by a single ListMessageMoveTasks call.
+pub const MAX_LISTED_TASKS: u64 = 10;
+
+/// State of a message move task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum MoveTaskStatus {
+    /// Messages are being moved
+    Running,
+    /// Every message was moved
+    Completed,
+    /// The task was cancelled before every message was moved
+    Cancelled,
+    /// The task stopped because some messages couldn't be moved
+    Failed,
+}
+
+/// A message move task.
+#[derive(Debug, Clone, FromRow)]
+pub struct MessageMoveTask {
+    pub handle: String,
+    pub source_namespace: String,
+    pub source: String,
+    /// Queue the messages are moved to, or `None` if each is moved back to its source queue
+    pub destination_namespace: Option<String>,
+    pub destination: Option<String>,
+    pub max_per_second: Option<u64>,
+    pub status: MoveTaskStatus,
+    /// Number of messages moved so far
+    pub moved: u64,
+    /// Number of messages that were in the queue when the task started
+    pub to_move: u64,
+    pub failure_reason: Option<String>,
+    /// Unix timestamp of the start of the task
+    pub started_at: u64,
+}
+
+/// What the background task needs to know about a running task.
+#[derive(Debug, Clone, FromRow)]
+pub struct RunningMoveTask {
+    pub id: u64,
+    /// ID of the dead-letter queue
+    pub source: u64,
+    /// ID of the destination queue, if there is one
+    pub destination: Option<u64>,
+    pub max_per_second: Option<u64>,
+    /// ID of the last message that was in the queue when the task started
+    pub last_message: u64,
+}
+
+/// Moves the next batch of messages of every running task, every job poll interval until the
+/// process exits.
+pub fn spawn(service: &Service) {
+    let period = service.config().tasks().job_poll;
+    tasks::spawn_periodic(service, "message_move", period, |service| async move {
+        let count = service.run_message_move_tasks().await?;
+        if count > 0 {
+            tracing::debug!(count, "Moved messages out of dead-letter queues");
+        }
+        Ok(())
+    });
+}
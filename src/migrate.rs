@@ -0,0 +1,310 @@
+//! Database schema migrations.
+//!
+//! The server applies pending migrations when it starts. The same migrations can be inspected,
+//! applied and reverted ahead of time with `nervemq migrate`, for example to upgrade the schema
+//! of a running deployment before rolling out a new version, or to roll it back.
+//!
+//! Migrations are additive wherever possible, and SQLite in WAL mode keeps serving readers while
+//! a migration runs, so the schema can be upgraded while the old version keeps running.
+//!
+//! Only one process may migrate a database at a time. Migrators hold an exclusive lock on a
+//! `<db_path>-migrate.lock` file next to the database while they run. The lock is released by
+//! the operating system if the process dies, so it can't go stale.
+//!
+//! Before applying or reverting migrations, [`preflight`] checks that the volume has room for a
+//! copy of the database (migrations that rebuild a table temporarily need one), and that the
+//! write-ahead log hasn't grown unusually large, which usually means a long-running reader is
+//! preventing checkpoints.
+
+use std::{
+    collections::HashMap,
+    fs::{File, TryLockError},
+    path::{Path, PathBuf},
+};
+
+use secrecy::SecretString;
+use serde::Serialize;
+use sqlx::{
+    migrate::{Migrate, Migration, Migrator},
+    SqlitePool,
+};
+
+use crate::{error::Error, service::Service};
+
+/// Migrations embedded from the `migrations` directory.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Largest write-ahead log [`preflight`] accepts, in bytes.
+pub const MAX_WAL_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The state of a migration in a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MigrationState {
+    /// Not yet applied
+    Pending,
+    /// Applied, and unchanged since
+    Applied,
+    /// Applied, but its script has changed since
+    Modified,
+    /// Failed part-way through, and must be fixed by hand
+    Dirty,
+}
+
+/// A migration and its state in a database.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// An exclusive lock on migrating a database, released when dropped.
+#[derive(Debug)]
+pub struct MigrationLock {
+    _file: File,
+}
+
+impl MigrationLock {
+    fn path(db_path: &str) -> PathBuf {
+        PathBuf::from(format!("{db_path}-migrate.lock"))
+    }
+
+    fn open(db_path: &str) -> Result<File, Error> {
+        File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(Self::path(db_path))
+            .map_err(Error::internal)
+    }
+
+    /// Takes the lock for a database, failing if another process holds it.
+    pub fn try_acquire(db_path: &str) -> Result<Self, Error> {
+        let file = Self::open(db_path)?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(Error::MigrationInProgress),
+            Err(TryLockError::Error(e)) => Err(Error::internal(e)),
+        }
+    }
+
+    /// Takes the lock for a database, waiting for any other process holding it to finish.
+    pub async fn acquire(db_path: &str) -> Result<Self, Error> {
+        let file = Self::open(db_path)?;
+
+        tokio::task::spawn_blocking(move || file.lock().map(|()| Self { _file: file }))
+            .await
+            .map_err(Error::internal)?
+            .map_err(Error::internal)
+    }
+}
+
+/// Opens the database at `db_path` without migrating it.
+///
+/// # Arguments
+/// * `db_path` - Path to the database file
+/// * `db_key` - Passphrase the database is encrypted with, if any
+pub async fn connect(db_path: &str, db_key: Option<&SecretString>) -> Result<SqlitePool, Error> {
+    Ok(SqlitePool::connect_with(Service::connect_options(db_path, db_key)?).await?)
+}
+
+/// Lists every known migration and whether it has been applied, oldest first.
+pub async fn status(pool: &SqlitePool) -> Result<Vec<MigrationStatus>, Error> {
+    let mut conn = pool.acquire().await?;
+
+    conn.ensure_migrations_table().await?;
+    let dirty = conn.dirty_version().await?;
+    let applied = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect::<HashMap<_, _>>();
+
+    Ok(up_migrations()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            state: match applied.get(&migration.version) {
+                _ if dirty == Some(migration.version) => MigrationState::Dirty,
+                Some(checksum) if *checksum == migration.checksum => MigrationState::Applied,
+                Some(_) => MigrationState::Modified,
+                None => MigrationState::Pending,
+            },
+        })
+        .collect())
+}
+
+/// Applies every pending migration.
+///
+/// # Arguments
+/// * `pool` - Database to migrate
+/// * `dry_run` - Only report the migrations that would be applied
+///
+/// # Returns
+/// The migrations that were (or would be) applied, oldest first
+pub async fn up(pool: &SqlitePool, dry_run: bool) -> Result<Vec<MigrationStatus>, Error> {
+    let pending = status(pool)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.state == MigrationState::Pending)
+        .collect::<Vec<_>>();
+
+    if !dry_run && !pending.is_empty() {
+        MIGRATOR.run(pool).await?;
+    }
+
+    Ok(pending)
+}
+
+/// Reverts applied migrations.
+///
+/// # Arguments
+/// * `pool` - Database to migrate
+/// * `target` - Version to revert to. Every migration after it is reverted. Defaults to the
+///   version before the latest applied one, reverting a single migration.
+/// * `dry_run` - Only report the migrations that would be reverted
+///
+/// # Returns
+/// The migrations that were (or would be) reverted, newest first
+pub async fn down(
+    pool: &SqlitePool,
+    target: Option<i64>,
+    dry_run: bool,
+) -> Result<Vec<MigrationStatus>, Error> {
+    let applied = status(pool)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.state != MigrationState::Pending)
+        .collect::<Vec<_>>();
+
+    let target = match target {
+        Some(target) => target,
+        None => applied
+            .iter()
+            .rev()
+            .nth(1)
+            .map(|migration| migration.version)
+            .unwrap_or(0),
+    };
+
+    let reverted = applied
+        .into_iter()
+        .rev()
+        .filter(|migration| migration.version > target)
+        .collect::<Vec<_>>();
+
+    if !dry_run && !reverted.is_empty() {
+        MIGRATOR.undo(pool, target).await?;
+    }
+
+    Ok(reverted)
+}
+
+/// Checks that it is safe to migrate the database at `db_path`.
+///
+/// # Errors
+/// Returns an error if the volume doesn't have room for a copy of the database and its
+/// write-ahead log, or if the write-ahead log is larger than [`MAX_WAL_SIZE`]
+pub fn preflight(db_path: &str) -> Result<(), Error> {
+    let size = |path: &Path| match std::fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(Error::internal(e)),
+    };
+
+    let db = Path::new(db_path);
+    let wal = PathBuf::from(format!("{db_path}-wal"));
+    let db_size = size(db)?;
+    let wal_size = size(&wal)?;
+
+    if wal_size > MAX_WAL_SIZE {
+        return Err(Error::PreflightFailed {
+            message: format!(
+                "write-ahead log is {wal_size} bytes (maximum is {MAX_WAL_SIZE}); \
+             check for long-running readers and checkpoint the database first"
+            ),
+        });
+    }
+
+    let dir = match db.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let available = fs4::available_space(dir).map_err(Error::internal)?;
+    let required = db_size + wal_size;
+
+    if available < required {
+        return Err(Error::PreflightFailed {
+            message: format!(
+                "{available} bytes free on the database volume, but migrating may need {required}"
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+fn up_migrations() -> impl Iterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqliteConnectOptions;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_up_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(&path)
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+
+        let total = up_migrations().count();
+
+        let planned = up(&pool, true).await.unwrap();
+        assert_eq!(planned.len(), total);
+        assert!(status(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .all(|migration| migration.state == MigrationState::Pending));
+
+        assert_eq!(up(&pool, false).await.unwrap().len(), total);
+        assert!(up(&pool, false).await.unwrap().is_empty());
+
+        let latest = planned.last().unwrap().version;
+        let reverted = down(&pool, None, false).await.unwrap();
+        assert_eq!(reverted.len(), 1);
+        assert_eq!(reverted[0].version, latest);
+
+        let status = status(&pool).await.unwrap();
+        assert_eq!(status.last().unwrap().state, MigrationState::Pending);
+        assert_eq!(status[0].state, MigrationState::Applied);
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let path = path.to_str().unwrap();
+
+        let lock = MigrationLock::try_acquire(path).unwrap();
+        assert!(MigrationLock::try_acquire(path).is_err());
+
+        drop(lock);
+        assert!(MigrationLock::try_acquire(path).is_ok());
+    }
+}
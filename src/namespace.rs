@@ -37,3 +37,16 @@ pub struct NamespaceStatistics {
     /// Total number of queues in this namespace
     pub queue_count: u64,
 }
+
+/// Limits on the resources of a namespace, set by admins. `None` means unlimited.
+#[derive(Serialize, Deserialize, FromRow, PartialEq, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceLimits {
+    /// Number of queues in the namespace
+    pub max_queues: Option<u64>,
+    /// Total size of the bodies of the messages in the namespace, in bytes, as stored (after
+    /// compression)
+    pub max_storage_bytes: Option<u64>,
+    /// Number of messages in each queue of the namespace, including in-flight ones
+    pub max_messages_per_queue: Option<u64>,
+}
@@ -0,0 +1,30 @@
+//! Notifier that writes notifications to the log instead of delivering them.
+
+use std::{future::Future, pin::Pin};
+
+use crate::error::Error;
+
+use super::{Notification, Notifier};
+
+/// Logs every notification at `INFO` level, including its body.
+///
+/// Bodies may contain secrets such as invitation tokens, so this should only be used when no
+/// real delivery channel is available.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send(
+        &self,
+        notification: Notification,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + '_>> {
+        Box::pin(async move {
+            tracing::info!(
+                to = notification.to,
+                subject = notification.subject,
+                "Notification not delivered (no notifier configured):\n{}",
+                notification.body
+            );
+            Ok(())
+        })
+    }
+}
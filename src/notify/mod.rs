@@ -0,0 +1,63 @@
+//! Notification delivery for NerveMQ.
+//!
+//! Subsystems that need to reach users outside of the API (invitations, password resets,
+//! alerts) send a [`Notification`] through a [`Notifier`]. The notifier is chosen from the
+//! configuration when the service starts:
+//!
+//! - `smtp::SmtpNotifier` delivers email through an SMTP relay when `NERVEMQ_SMTP_URL` is set,
+//!   which requires the `smtp` feature
+//! - [`log::LogNotifier`] writes notifications to the log otherwise, which is useful during
+//!   development but means invitations and resets can only be completed by reading the logs
+
+use std::{future::Future, pin::Pin};
+
+use crate::{config::Config, error::Error};
+
+pub mod log;
+#[cfg(feature = "smtp")]
+pub mod smtp;
+
+/// A message to deliver to a single recipient.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// Email address of the recipient
+    pub to: String,
+    pub subject: String,
+    /// Plain-text body of the message
+    pub body: String,
+}
+
+/// Core trait for notification channels.
+pub trait Notifier: Send + Sync + 'static {
+    /// Delivers a notification to its recipient.
+    ///
+    /// Returns once the notification has been accepted by the underlying channel, which does not
+    /// guarantee that it has reached the recipient.
+    fn send(
+        &self,
+        notification: Notification,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + '_>>;
+}
+
+/// Creates the notifier selected by the configuration.
+pub fn from_config(config: &Config) -> Result<Box<dyn Notifier>, Error> {
+    match config.smtp_url() {
+        #[cfg(feature = "smtp")]
+        Some(url) => Ok(Box::new(smtp::SmtpNotifier::new(
+            url,
+            config.smtp_password()?,
+            config.smtp_from(),
+        )?)),
+        #[cfg(not(feature = "smtp"))]
+        Some(_) => Err(Error::FeatureUnsupported {
+            setting: "An SMTP server",
+            feature: "smtp",
+        }),
+        None => {
+            tracing::warn!(
+                "No SMTP server configured, notifications will only be logged - don't do this in production!"
+            );
+            Ok(Box::new(log::LogNotifier))
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Dashboard overview.
+//!
+//! The dashboard shows the state of the server at a glance: how many namespaces, queues and
+//! messages a user can see, which queues have been left without live consumers, and, for admins,
+//! how the server itself is doing. All of it is returned
+//! by a single call, instead of one statistics call per namespace and queue.
+//!
+//! Server health is tracked in memory: responses are counted by status over the last
+//! [`RECENT_WINDOW`], and every periodic background task reports each run. A task is healthy if
+//! its last run succeeded and it ran within [`STALE_TASK_INTERVALS`] of its interval.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    middleware::Next,
+    web::Data,
+};
+use serde::Serialize;
+use sqlx::FromRow;
+
+use crate::{consumers::UnattendedQueue, error::Error, service::Service};
+
+/// How far back responses are counted for error rates.
+pub const RECENT_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Width of the buckets responses are counted in.
+const BUCKET_WIDTH: Duration = Duration::from_secs(60);
+
+/// Number of intervals after which a background task that hasn't run is considered unhealthy.
+pub const STALE_TASK_INTERVALS: u32 = 3;
+
+/// Summary of what a user can see, and of the server's health for admins.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Overview {
+    #[serde(flatten)]
+    pub totals: Totals,
+    /// Number of registered consumers that are alive, see [`crate::consumers`]
+    pub live_consumers: u64,
+    /// Queues with waiting messages whose registered consumers are all gone
+    pub unattended_queues: Vec<UnattendedQueue>,
+    /// Whether the server is in maintenance mode
+    pub maintenance: bool,
+    /// Server health, only for admins
+    pub health: Option<Health>,
+}
+
+/// Counts over the namespaces a user has access to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Totals {
+    pub namespaces: u64,
+    pub queues: u64,
+    /// Number of messages in the queues
+    pub messages: u64,
+    /// Number of messages waiting to be delivered
+    pub pending: u64,
+    /// Number of messages that used up their retries
+    pub failed: u64,
+    /// Number of queues that are the dead-letter queue of another queue
+    pub dead_letter_queues: u64,
+    /// Number of messages in dead-letter queues
+    pub dead_lettered: u64,
+}
+
+/// Health of the server.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    pub requests: RequestRates,
+    pub tasks: Vec<TaskHealth>,
+}
+
+/// Responses over the last [`RECENT_WINDOW`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestRates {
+    pub window_seconds: u64,
+    pub requests: u64,
+    /// Number of responses with a 4xx status
+    pub client_errors: u64,
+    /// Number of responses with a 5xx status
+    pub server_errors: u64,
+    /// Share of responses with a 5xx status, between 0 and 1
+    pub server_error_rate: f64,
+    /// Number of requests turned away because the database was overloaded, which are also
+    /// counted as server errors
+    pub overloaded: u64,
+}
+
+/// Health of a background task.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskHealth {
+    pub name: &'static str,
+    /// Unix timestamp of the last run
+    pub last_run_at: u64,
+    /// Error of the last run, if it failed
+    pub last_error: Option<String>,
+    pub healthy: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    start: u64,
+    requests: u64,
+    client_errors: u64,
+    server_errors: u64,
+    overloaded: u64,
+}
+
+#[derive(Debug, Clone)]
+struct TaskState {
+    interval: Duration,
+    last_run_at: u64,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    buckets: VecDeque<Bucket>,
+    tasks: BTreeMap<&'static str, TaskState>,
+}
+
+/// Tracks the health of the server.
+#[derive(Debug, Clone)]
+pub struct HealthTracker {
+    state: Arc<Mutex<State>>,
+    started: Instant,
+}
+
+impl Default for HealthTracker {
+    fn default() -> Self {
+        Self {
+            state: Default::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl HealthTracker {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts a response.
+    ///
+    /// # Arguments
+    /// * `status` - Status of the response
+    /// * `overloaded` - Whether the request failed because the database was overloaded
+    pub fn record_response(&self, status: StatusCode, overloaded: bool) {
+        self.record_response_at(status, overloaded, now());
+    }
+
+    fn record_response_at(&self, status: StatusCode, overloaded: bool, at: u64) {
+        let start = at - at % BUCKET_WIDTH.as_secs();
+        let mut state = self.state();
+
+        if state
+            .buckets
+            .back()
+            .is_none_or(|bucket| bucket.start != start)
+        {
+            state.buckets.push_back(Bucket {
+                start,
+                ..Default::default()
+            });
+        }
+        while state
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.start + RECENT_WINDOW.as_secs() <= start)
+        {
+            state.buckets.pop_front();
+        }
+
+        let bucket = state.buckets.back_mut().expect("bucket was just pushed");
+        bucket.requests += 1;
+        if status.is_client_error() {
+            bucket.client_errors += 1;
+        } else if status.is_server_error() {
+            bucket.server_errors += 1;
+        }
+        if overloaded {
+            bucket.overloaded += 1;
+        }
+    }
+
+    /// Records a run of a periodic background task.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the task
+    /// * `interval` - How often the task runs
+    /// * `error` - Error the run failed with, if any
+    pub fn record_task(&self, name: &'static str, interval: Duration, error: Option<String>) {
+        self.state().tasks.insert(
+            name,
+            TaskState {
+                interval,
+                last_run_at: now(),
+                last_error: error,
+            },
+        );
+    }
+
+    /// How long the server has been running.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns the current health of the server.
+    pub fn health(&self) -> Health {
+        self.health_at(now())
+    }
+
+    fn health_at(&self, at: u64) -> Health {
+        let state = self.state();
+
+        let mut requests = state
+            .buckets
+            .iter()
+            .filter(|bucket| bucket.start + RECENT_WINDOW.as_secs() > at)
+            .fold(RequestRates::default(), |mut rates, bucket| {
+                rates.requests += bucket.requests;
+                rates.client_errors += bucket.client_errors;
+                rates.server_errors += bucket.server_errors;
+                rates.overloaded += bucket.overloaded;
+                rates
+            });
+        requests.window_seconds = RECENT_WINDOW.as_secs();
+        if requests.requests > 0 {
+            requests.server_error_rate = requests.server_errors as f64 / requests.requests as f64;
+        }
+
+        let tasks = state
+            .tasks
+            .iter()
+            .map(|(name, task)| {
+                let stale_after = (task.interval * STALE_TASK_INTERVALS).as_secs();
+                TaskHealth {
+                    name,
+                    last_run_at: task.last_run_at,
+                    last_error: task.last_error.clone(),
+                    healthy: task.last_error.is_none()
+                        && at.saturating_sub(task.last_run_at) <= stale_after,
+                }
+            })
+            .collect();
+
+        Health { requests, tasks }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Middleware that counts every response, including errors, for [`RequestRates`].
+pub async fn record_responses(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let service = req.app_data::<Data<Service>>().cloned();
+
+    let res = next.call(req).await;
+
+    if let Some(service) = service {
+        let (status, error) = match &res {
+            Ok(res) => (res.status(), res.response().error()),
+            Err(e) => (e.as_response_error().status_code(), Some(e)),
+        };
+        let overloaded = error
+            .and_then(|e| e.as_error::<Error>())
+            .is_some_and(|e| matches!(e, Error::Overloaded { .. }));
+        service.health().record_response(status, overloaded);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_rates() {
+        let health = HealthTracker::default();
+        let start = 1_700_000_040;
+
+        health.record_response_at(StatusCode::OK, false, start);
+        health.record_response_at(StatusCode::NOT_FOUND, false, start + 1);
+        health.record_response_at(StatusCode::INTERNAL_SERVER_ERROR, false, start + 61);
+        health.record_response_at(StatusCode::OK, false, start + 62);
+
+        let rates = health.health_at(start + 62).requests;
+        assert_eq!(rates.requests, 4);
+        assert_eq!(rates.client_errors, 1);
+        assert_eq!(rates.server_errors, 1);
+        assert_eq!(rates.server_error_rate, 0.25);
+        assert_eq!(rates.overloaded, 0);
+
+        // The first minute has left the window.
+        let rates = health
+            .health_at(start + RECENT_WINDOW.as_secs() + 30)
+            .requests;
+        assert_eq!(rates.requests, 2);
+    }
+
+    #[test]
+    fn test_task_health() {
+        let health = HealthTracker::default();
+        health.record_task("ok", Duration::from_secs(60), None);
+        health.record_task("failing", Duration::from_secs(60), Some("boom".to_owned()));
+
+        let tasks = health.health().tasks;
+        assert_eq!(tasks.len(), 2);
+        assert!(!tasks[0].healthy);
+        assert_eq!(tasks[0].last_error.as_deref(), Some("boom"));
+        assert!(tasks[1].healthy);
+
+        let later = now() + 4 * 60;
+        assert!(!health.health_at(later).tasks[1].healthy);
+    }
+}
@@ -0,0 +1,513 @@
+//! Declarative resource provisioning.
+//!
+//! A provisioning file describes namespaces, queues, users and API tokens, so an environment can
+//! be reproduced from a file checked in next to the rest of its infrastructure. Files are TOML,
+//! or YAML if their extension is `.yaml` or `.yml`:
+//!
+//! ```toml
+//! namespaces = ["orders"]
+//!
+//! [[queues]]
+//! namespace = "orders"
+//! name = "incoming"
+//! attributes = { VisibilityTimeout = 60 }
+//! tags = { team = "checkout" }
+//!
+//! [[users]]
+//! email = "checkout@example.com"
+//! role = "user"
+//! namespaces = ["orders"]
+//!
+//! [[tokens]]
+//! name = "checkout"
+//! user = "checkout@example.com"
+//! namespace = "orders"
+//! token_file = "/run/secrets/checkout-token"
+//! ```
+//!
+//! Applying a file is idempotent: missing resources are created, and existing ones are brought
+//! in line with the file. Nothing is ever deleted, and permissions are only granted, never
+//! revoked. Existing users keep their password, so it can be changed after the first login.
+//!
+//! A file is applied on startup if [`Config::provision_file`] is set, or with `nervemq apply`.
+
+use std::{collections::HashMap, path::Path};
+
+use actix_identity::Identity;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_email::Email;
+
+use crate::{
+    api::auth::Role,
+    auth::{credential::ApiKey, crypto::generate_token},
+    config::Config,
+    error::Error,
+    service::{QueueAttributesSer, Service},
+};
+
+/// The resources described by a provisioning file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Provision {
+    pub namespaces: Vec<String>,
+    pub queues: Vec<QueueSpec>,
+    pub users: Vec<UserSpec>,
+    pub tokens: Vec<TokenSpec>,
+}
+
+/// A queue and its attributes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueSpec {
+    pub namespace: String,
+    pub name: String,
+    /// Attributes, named as in SQS `SetQueueAttributes`
+    pub attributes: Option<QueueAttributesSer>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+/// A user and the namespaces they can access.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserSpec {
+    pub email: String,
+    #[serde(default)]
+    pub role: Role,
+    /// Initial password. Users created without one can't log in until they reset it.
+    pub password: Option<SecretString>,
+    /// File containing the initial password, overriding `password`
+    pub password_file: Option<String>,
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+}
+
+/// An API token with a known secret.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenSpec {
+    pub name: String,
+    pub user: String,
+    pub namespace: String,
+    /// The token, as `nervemq_<id>_<secret>`
+    pub token: Option<SecretString>,
+    /// File containing the token, overriding `token`
+    pub token_file: Option<String>,
+}
+
+impl Provision {
+    /// Reads a provisioning file.
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidProvisionFile`] if the file can't be read or parsed
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let invalid = |message: String| Error::InvalidProvisionFile {
+            path: path.display().to_string(),
+            message,
+        };
+
+        let contents = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&contents).map_err(|e| invalid(e.to_string()))
+            }
+            _ => toml::from_str(&contents).map_err(|e| invalid(e.to_string())),
+        }
+    }
+}
+
+/// Reads a secret from a file if one is given, and falls back to an inline value otherwise.
+fn read_secret(
+    file: Option<&str>,
+    inline: Option<&SecretString>,
+) -> Result<Option<SecretString>, Error> {
+    match file {
+        Some(path) => std::fs::read_to_string(path)
+            .map(|secret| Some(SecretString::new(secret.trim().into())))
+            .map_err(Error::internal),
+        None => Ok(inline.cloned()),
+    }
+}
+
+/// Applies a provisioning file, acting as the root admin.
+///
+/// # Returns
+/// A description of every change that was made, in the order they were made
+pub async fn apply(service: &Service, provision: &Provision) -> Result<Vec<String>, Error> {
+    let root = service.config().root_email().to_owned();
+    let mut changes = Vec::new();
+
+    let namespaces = provision
+        .namespaces
+        .iter()
+        .chain(provision.queues.iter().map(|queue| &queue.namespace));
+
+    for namespace in namespaces {
+        if service
+            .get_namespace_id(namespace, service.db())
+            .await?
+            .is_none()
+        {
+            service
+                .create_namespace(namespace, Identity::mock(root.clone()))
+                .await?;
+            changes.push(format!("created namespace {namespace}"));
+        }
+    }
+
+    for user in &provision.users {
+        apply_user(service, user, &mut changes).await?;
+    }
+
+    for queue in &provision.queues {
+        // The root admin may not have created the namespace, but needs access to manage its queues.
+        grant_access(service, &root, &queue.namespace).await?;
+
+        let created = service
+            .get_queue_id(&queue.namespace, &queue.name, service.db())
+            .await?
+            .is_none();
+        if created {
+            service
+                .create_queue(
+                    &queue.namespace,
+                    &queue.name,
+                    HashMap::new(),
+                    HashMap::new(),
+                    Identity::mock(root.clone()),
+                )
+                .await?;
+            changes.push(format!("created queue {}/{}", queue.namespace, queue.name));
+        }
+
+        if let Some(attributes) = &queue.attributes {
+            // Round-trip through JSON, since attributes are consumed when they are set.
+            let attributes = serde_json::from_value(serde_json::to_value(attributes)?)?;
+            service
+                .set_queue_attributes(
+                    &queue.namespace,
+                    &queue.name,
+                    attributes,
+                    Identity::mock(root.clone()),
+                )
+                .await?;
+        }
+
+        if !queue.tags.is_empty() {
+            let current = service
+                .get_queue_tags(&queue.namespace, &queue.name, Identity::mock(root.clone()))
+                .await?;
+            if queue.tags.iter().any(|(k, v)| current.get(k) != Some(v)) {
+                service
+                    .tag_queue(
+                        &queue.namespace,
+                        &queue.name,
+                        queue.tags.clone(),
+                        Identity::mock(root.clone()),
+                    )
+                    .await?;
+                if !created {
+                    changes.push(format!("tagged queue {}/{}", queue.namespace, queue.name));
+                }
+            }
+        }
+    }
+
+    for token in &provision.tokens {
+        let key =
+            read_secret(token.token_file.as_deref(), token.token.as_ref())?.ok_or_else(|| {
+                Error::invalid_parameter(format!(
+                    "token {}: token or token_file is required",
+                    token.name
+                ))
+            })?;
+        let key = ApiKey::parse(key.expose_secret())?;
+
+        if service
+            .install_token(&token.name, &token.namespace, &token.user, key)
+            .await?
+        {
+            changes.push(format!("installed token {} for {}", token.name, token.user));
+        }
+    }
+
+    for change in &changes {
+        tracing::info!(target: "nervemq::audit", "Provisioning {change}");
+    }
+
+    Ok(changes)
+}
+
+async fn apply_user(
+    service: &Service,
+    user: &UserSpec,
+    changes: &mut Vec<String>,
+) -> Result<(), Error> {
+    let role: Option<Role> = sqlx::query_scalar("SELECT role FROM users WHERE email = $1")
+        .bind(&user.email)
+        .fetch_optional(service.db())
+        .await?;
+
+    match role {
+        None => {
+            let password = match read_secret(user.password_file.as_deref(), user.password.as_ref())?
+            {
+                Some(password) => password.expose_secret().to_owned(),
+                None => generate_token::<24>(rand::thread_rng()).map_err(Error::internal)?,
+            };
+
+            service
+                .create_user(
+                    Email::from_str(&user.email)
+                        .map_err(|e| Error::invalid_parameter(e.to_string()))?,
+                    password,
+                    Some(user.role.clone()),
+                    vec![],
+                )
+                .await?;
+            changes.push(format!("created user {}", user.email));
+        }
+        Some(role) if role != user.role => {
+            sqlx::query("UPDATE users SET role = $1 WHERE email = $2")
+                .bind(&user.role)
+                .bind(&user.email)
+                .execute(service.db())
+                .await?;
+            changes.push(format!("changed role of {}", user.email));
+        }
+        Some(_) => {}
+    }
+
+    for namespace in &user.namespaces {
+        if grant_access(service, &user.email, namespace).await? {
+            changes.push(format!("granted {} access to {namespace}", user.email));
+        }
+    }
+
+    Ok(())
+}
+
+/// Grants a user access to a namespace.
+///
+/// # Returns
+/// `false` if the user already had access
+async fn grant_access(service: &Service, email: &str, namespace: &str) -> Result<bool, Error> {
+    let namespace_id = service
+        .get_namespace_id(namespace, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+    let granted = sqlx::query(
+        "
+        INSERT INTO user_permissions (user, namespace)
+        VALUES ((SELECT id FROM users WHERE email = $1), $2)
+        ON CONFLICT DO NOTHING
+        ",
+    )
+    .bind(email)
+    .bind(namespace_id as i64)
+    .execute(service.db())
+    .await?
+    .rows_affected();
+
+    Ok(granted > 0)
+}
+
+/// Applies a provisioning file to the configured database.
+///
+/// # Arguments
+/// * `config` - Service configuration
+/// * `kms_factory` - Factory function to create a key management service
+/// * `path` - Provisioning file to apply
+/// * `db_key` - Passphrase the database is encrypted with, overriding the configured one
+///
+/// # Returns
+/// A description of every change that was made
+#[bon::builder(finish_fn = start)]
+pub async fn apply_file<K, F, R>(
+    config: Config,
+    kms_factory: K,
+    path: &Path,
+    db_key: Option<SecretString>,
+) -> Result<Vec<String>, Error>
+where
+    K: FnOnce(sqlx::SqlitePool) -> F,
+    F: std::future::Future<Output = Result<R, Error>>,
+    R: crate::kms::KeyManager,
+{
+    let provision = Provision::load(path)?;
+
+    let service = Service::connect_with()
+        .config(config)
+        .kms_factory(kms_factory)
+        .maybe_db_key(db_key)
+        .call()
+        .await?;
+
+    apply(&service, &provision).await
+}
+
+/// Creates an administrator in the configured database, or makes an existing user one. Existing
+/// users keep their password.
+///
+/// # Arguments
+/// * `config` - Service configuration
+/// * `kms_factory` - Factory function to create a key management service
+/// * `email` - Email address of the administrator
+/// * `password` - Password of the administrator, if they are created
+/// * `db_key` - Passphrase the database is encrypted with, overriding the configured one
+///
+/// # Returns
+/// A description of every change that was made
+#[bon::builder(finish_fn = start)]
+pub async fn create_admin<K, F, R>(
+    config: Config,
+    kms_factory: K,
+    email: &str,
+    password: SecretString,
+    db_key: Option<SecretString>,
+) -> Result<Vec<String>, Error>
+where
+    K: FnOnce(sqlx::SqlitePool) -> F,
+    F: std::future::Future<Output = Result<R, Error>>,
+    R: crate::kms::KeyManager,
+{
+    let provision = Provision {
+        users: vec![UserSpec {
+            email: email.to_owned(),
+            role: Role::Admin,
+            password: Some(password),
+            password_file: None,
+            namespaces: vec![],
+        }],
+        ..Default::default()
+    };
+
+    let service = Service::connect_with()
+        .config(config)
+        .kms_factory(kms_factory)
+        .maybe_db_key(db_key)
+        .call()
+        .await?;
+
+    apply(&service, &provision).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let toml = dir.path().join("provision.toml");
+        std::fs::write(
+            &toml,
+            r#"
+            namespaces = ["orders"]
+
+            [[queues]]
+            namespace = "orders"
+            name = "incoming"
+            attributes = { VisibilityTimeout = 60 }
+            "#,
+        )
+        .unwrap();
+        let provision = Provision::load(&toml).unwrap();
+        assert_eq!(provision.queues[0].name, "incoming");
+        assert_eq!(
+            provision.queues[0]
+                .attributes
+                .as_ref()
+                .unwrap()
+                .visibility_timeout,
+            Some(60)
+        );
+
+        let yaml = dir.path().join("provision.yaml");
+        std::fs::write(
+            &yaml,
+            "users:\n  - email: app@example.com\n    role: admin\n    namespaces: [orders]\n",
+        )
+        .unwrap();
+        let provision = Provision::load(&yaml).unwrap();
+        assert_eq!(provision.users[0].role, Role::Admin);
+
+        std::fs::write(&toml, "queus = []").unwrap();
+        assert!(matches!(
+            Provision::load(&toml),
+            Err(Error::InvalidProvisionFile { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_apply_is_idempotent() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(crate::kms::memory::InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+
+        let provision: Provision = toml::from_str(
+            r#"
+            [[queues]]
+            namespace = "orders"
+            name = "incoming"
+            attributes = { VisibilityTimeout = 60 }
+            tags = { team = "checkout" }
+
+            [[users]]
+            email = "app@example.com"
+            namespaces = ["orders"]
+
+            [[tokens]]
+            name = "app"
+            user = "app@example.com"
+            namespace = "orders"
+            token = "nervemq_abc123_SECRETsecret"
+            "#,
+        )
+        .unwrap();
+
+        let changes = apply(&service, &provision).await.unwrap();
+        assert_eq!(
+            changes,
+            [
+                "created namespace orders",
+                "created user app@example.com",
+                "granted app@example.com access to orders",
+                "created queue orders/incoming",
+                "installed token app for app@example.com",
+            ]
+        );
+        assert!(apply(&service, &provision).await.unwrap().is_empty());
+
+        let attributes = service
+            .get_queue_attributes(
+                "orders",
+                "incoming",
+                &["All".to_owned()],
+                Identity::mock("app@example.com".to_owned()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(attributes.visibility_timeout, Some(60));
+    }
+
+    #[tokio::test]
+    async fn test_create_admin() {
+        let changes = create_admin()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(crate::kms::memory::InMemoryKeyManager::new()) })
+            .email("ops@example.com")
+            .password(SecretString::new("password".into()))
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(changes, ["created user ops@example.com"]);
+    }
+}
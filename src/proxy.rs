@@ -0,0 +1,384 @@
+//! Reverse-proxy awareness.
+//!
+//! When NerveMQ runs behind a reverse proxy, the TCP peer of every request is the proxy rather
+//! than the client. Proxies report the original client address and scheme in the standard
+//! `Forwarded` header (RFC 7239), or in the de-facto `X-Forwarded-For` and `X-Forwarded-Proto`
+//! headers.
+//!
+//! The host the client asked for is likewise taken from `Forwarded: host=` or `X-Forwarded-Host`
+//! when the peer is trusted, and from the `Host` header (or HTTP/2 authority) otherwise.
+//!
+//! These headers are trivially spoofable, so they are only honored when the peer is one of the
+//! configured trusted proxies. The client address is found by walking the chain of forwarded
+//! addresses from the right (closest hop) and skipping every trusted proxy; the first untrusted
+//! address is the client.
+
+use std::net::{IpAddr, SocketAddr};
+
+use actix_identity::IdentityExt as _;
+use actix_web::{
+    dev::ServiceRequest,
+    http::header::{HeaderMap, FORWARDED, HOST},
+    web::Data,
+    FromRequest, HttpMessage, HttpRequest,
+};
+use ipnet::IpNet;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+
+use crate::{
+    auth::credential::{AuthenticatedKey, AuthorizedNamespace},
+    error::Error,
+    request_id::RequestId,
+    service::Service,
+};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// The originating client of a request, as reported by any trusted proxies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The client's IP address, if it could be determined
+    pub ip: Option<IpAddr>,
+    /// The scheme the client used to reach the outermost trusted proxy (`http` or `https`)
+    pub proto: Option<String>,
+    /// The host (with optional port) the client sent the request to
+    pub host: Option<String>,
+}
+
+impl ClientInfo {
+    /// Resolves the client of a request from its peer address and forwarding headers.
+    ///
+    /// # Arguments
+    /// * `peer` - Address of the TCP peer
+    /// * `headers` - Request headers
+    /// * `trusted` - Networks of proxies whose forwarding headers are honored
+    pub fn resolve(peer: Option<SocketAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> Self {
+        let peer = peer.map(|addr| addr.ip());
+        let host_header = headers
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let Some(peer) = peer.filter(|ip| in_networks(ip, trusted)) else {
+            return Self {
+                ip: peer,
+                proto: None,
+                host: host_header,
+            };
+        };
+
+        let Forwarded { chain, proto, host } = match forwarded(headers) {
+            Some(forwarded) => forwarded,
+            None => Forwarded {
+                chain: x_forwarded_for(headers),
+                proto: first_value(headers, X_FORWARDED_PROTO)
+                    .map(|proto| proto.to_ascii_lowercase()),
+                host: first_value(headers, X_FORWARDED_HOST),
+            },
+        };
+
+        // Walk back from the closest hop. An entry that can't be parsed (e.g. `unknown` or an
+        // obfuscated identifier) ends the walk, since nothing to its left can be attributed.
+        let mut ip = peer;
+        for hop in chain.into_iter().rev() {
+            match hop {
+                Some(hop) => {
+                    ip = hop;
+                    if !in_networks(&hop, trusted) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        Self {
+            ip: Some(ip),
+            proto: proto.filter(|proto| proto == "http" || proto == "https"),
+            host: host.or(host_header),
+        }
+    }
+
+    /// Resolves the client of a request using the configured trusted proxies.
+    pub fn from_http_request(req: &HttpRequest) -> Self {
+        let trusted = req
+            .app_data::<Data<Service>>()
+            .map(|service| service.config().trusted_proxies())
+            .unwrap_or_default();
+
+        let mut client = Self::resolve(req.peer_addr(), req.headers(), trusted);
+
+        // HTTP/2 requests carry the host in the `:authority` pseudo-header instead of `Host`.
+        if client.host.is_none() {
+            client.host = req.uri().authority().map(|authority| authority.to_string());
+        }
+
+        client
+    }
+}
+
+impl FromRequest for ClientInfo {
+    type Error = Error;
+
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        std::future::ready(Ok(Self::from_http_request(req)))
+    }
+}
+
+/// Returns whether an address is in one of the networks. IPv4-mapped IPv6 addresses are treated
+/// as the IPv4 addresses they map.
+pub(crate) fn in_networks(ip: &IpAddr, networks: &[IpNet]) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+        IpAddr::V4(_) => *ip,
+    };
+
+    networks.iter().any(|net| net.contains(&ip))
+}
+
+/// Parses a node identifier from a `Forwarded` or `X-Forwarded-For` header: an IPv4 address or
+/// bracketed IPv6 address, each with an optional port, or a bare IPv6 address.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
+/// Values reported by the proxies a request passed through.
+struct Forwarded {
+    /// Forwarded client addresses, from the original client to the closest hop
+    chain: Vec<Option<IpAddr>>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn forwarded(headers: &HeaderMap) -> Option<Forwarded> {
+    let mut chain = Vec::new();
+    let mut proto = None;
+    let mut host = None;
+    let mut present = false;
+
+    for value in headers.get_all(FORWARDED) {
+        present = true;
+
+        let Ok(value) = value.to_str() else {
+            chain.push(None);
+            continue;
+        };
+
+        for element in value.split(',') {
+            let mut node = None;
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => node = Some(parse_node(value)),
+                    "proto" if proto.is_none() => {
+                        proto = Some(value.trim().trim_matches('"').to_ascii_lowercase())
+                    }
+                    "host" if host.is_none() => {
+                        host = Some(value.trim().trim_matches('"').to_owned())
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(node) = node {
+                chain.push(node);
+            }
+        }
+    }
+
+    present.then_some(Forwarded { chain, proto, host })
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .flat_map(|value| match value.to_str() {
+            Ok(value) => value.split(',').map(parse_node).collect(),
+            Err(_) => vec![None],
+        })
+        .collect()
+}
+
+/// Gets the left-most (client-facing) value of a comma-separated forwarding header.
+fn first_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+/// Root span builder that records the client address resolved through trusted proxies, the
+/// [`RequestId`] and who made the request, so that every event logged while handling a request
+/// (including audit events) carries them. See [`crate::logging`] for the other correlation
+/// fields, which are recorded later.
+pub struct ClientRootSpanBuilder;
+
+impl RootSpanBuilder for ClientRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> tracing::Span {
+        let client = ClientInfo::from_http_request(request.request());
+        let client_ip = client.ip.map(|ip| ip.to_string());
+        // Reading the identity borrows the extensions mutably, and so may the span macro.
+        let user = request
+            .request()
+            .get_identity()
+            .ok()
+            .and_then(|identity| identity.id().ok());
+        let (request_id, api_key, namespace) = {
+            let extensions = request.extensions();
+            (
+                extensions.get::<RequestId>().map(ToString::to_string),
+                extensions
+                    .get::<AuthenticatedKey>()
+                    .map(|key| key.0.clone()),
+                extensions
+                    .get::<AuthorizedNamespace>()
+                    .map(|namespace| namespace.0.clone()),
+            )
+        };
+
+        tracing_actix_web::root_span!(
+            request,
+            client.address = client_ip.as_deref(),
+            http.request_id = request_id.as_deref(),
+            enduser.id = user.as_deref(),
+            nervemq.api_key = api_key.as_deref(),
+            nervemq.namespace = namespace.as_deref(),
+            nervemq.queue = tracing::field::Empty
+        )
+    }
+
+    fn on_request_end<B: actix_web::body::MessageBody>(
+        span: tracing::Span,
+        outcome: &Result<actix_web::dev::ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn headers(values: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in values {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        map
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "127.0.0.1/32".parse().unwrap(),
+        ]
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let peer = "203.0.113.7:4000".parse().ok();
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("x-forwarded-proto", "https"),
+        ]);
+
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.ip, ip("203.0.113.7"));
+        assert_eq!(client.proto, None);
+    }
+
+    #[test]
+    fn test_x_forwarded_for_chain() {
+        let peer = "127.0.0.1:4000".parse().ok();
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.9, 198.51.100.1, 10.1.2.3"),
+            ("x-forwarded-proto", "HTTPS"),
+        ]);
+
+        // The left-most entry was supplied by the client and must not be trusted.
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.ip, ip("198.51.100.1"));
+        assert_eq!(client.proto.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let peer = "10.0.0.2:4000".parse().ok();
+        let headers = headers(&[
+            (
+                "forwarded",
+                "for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.1",
+            ),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.ip, ip("2001:db8::1"));
+        assert_eq!(client.proto.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_forwarded_host() {
+        let headers = headers(&[
+            ("host", "10.0.0.5:8080"),
+            ("x-forwarded-host", "mq.example.com"),
+        ]);
+
+        let peer = "127.0.0.1:4000".parse().ok();
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.host.as_deref(), Some("mq.example.com"));
+
+        let peer = "203.0.113.7:4000".parse().ok();
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.host.as_deref(), Some("10.0.0.5:8080"));
+    }
+
+    #[test]
+    fn test_unparseable_hop_stops_walk() {
+        let peer = "127.0.0.1:4000".parse().ok();
+        let headers = headers(&[("forwarded", "for=198.51.100.1, for=unknown, for=10.0.0.1")]);
+
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.ip, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_unsupported_proto_is_ignored() {
+        let peer = "127.0.0.1:4000".parse().ok();
+        let headers = headers(&[("x-forwarded-proto", "gopher")]);
+
+        let client = ClientInfo::resolve(peer, &headers, &trusted());
+        assert_eq!(client.ip, ip("127.0.0.1"));
+        assert_eq!(client.proto, None);
+    }
+}
@@ -0,0 +1,803 @@
+//! Push subscriptions, which deliver the messages of a queue to a webhook.
+//!
+//! Instead of polling with ReceiveMessage, a queue can have its messages POSTed to a URL. A
+//! background task picks up every subscription every [job poll
+//! interval](crate::config::TaskIntervals::job_poll) and receives up to the subscription's
+//! `concurrency` batches of `batchSize` messages at a time, which are delivered in parallel. The
+//! configured authorization header, if any, is sent as `Authorization`.
+//!
+//! With the default `batchSize` of 1, the body of each delivery is the message as ReceiveMessage
+//! returns it, with every attribute, and its ID is sent as [`MESSAGE_HEADER`]. A `2xx` response
+//! deletes the message. Anything else (including no response within [`PUSH_TIMEOUT`]) makes the
+//! message receivable again after a backoff, which starts at the subscription's `initialBackoff`
+//! and doubles with every further failure up to `maxBackoff`. The failure is recorded as the
+//! message's failure reason, and the queue's retries and dead-letter queue apply as they do for
+//! polling consumers.
+//!
+//! With a larger `batchSize`, the body is `{"Messages": [...]}` instead, and when the queue has
+//! fewer visible messages than that, the task waits up to `batchWindow` milliseconds for more
+//! before pushing what it has. A `2xx` response acknowledges each message on its own: like AWS
+//! Lambda's partial batch responses, it can list the IDs of the messages that failed as
+//! `{"batchItemFailures": [{"itemIdentifier": "<MessageId>"}]}`, which back off as above while the
+//! others are deleted. An empty or different response body deletes them all, and a response that
+//! isn't `2xx` fails them all.
+//!
+//! Pushing and polling compete for the same messages. Queues with consumer groups can't have a
+//! push subscription.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use hyper::{body::HttpBody, Body, Request};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tokio::{
+    task::{JoinHandle, JoinSet},
+    time::Instant,
+};
+use url::{Host, Url};
+
+use crate::{
+    error::Error,
+    service::{ReceiveOptions, Service},
+    sqs::types::SqsMessage,
+    tasks,
+    webhooks::{self, HttpClient},
+};
+
+/// How long a webhook may take to respond before the delivery fails.
+pub const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long messages stay hidden while they are being pushed. Longer than the longest batch window
+/// and [`PUSH_TIMEOUT`] together, so that messages aren't received again while they wait for their
+/// batch to fill.
+const PUSH_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The highest number of messages pushed to a subscription at the same time.
+pub const MAX_PUSH_CONCURRENCY: u64 = 100;
+
+/// Number of messages pushed at the same time by subscriptions that don't set one.
+pub const DEFAULT_PUSH_CONCURRENCY: u64 = 10;
+
+/// Backoff in seconds after the first failed delivery, for subscriptions that don't set one.
+pub const DEFAULT_INITIAL_BACKOFF: u64 = 1;
+
+/// Longest backoff in seconds, for subscriptions that don't set one.
+pub const DEFAULT_MAX_BACKOFF: u64 = 300;
+
+/// The largest number of messages pushed in one request.
+pub const MAX_PUSH_BATCH_SIZE: u64 = 100;
+
+/// Longest a subscription can wait for a batch to fill.
+pub const MAX_PUSH_BATCH_WINDOW: Duration = Duration::from_secs(10);
+
+/// Longest response body read from a batch delivery.
+const MAX_RESPONSE_LENGTH: usize = 64 * 1024;
+
+/// Header with the ID of the pushed message, sent when messages are pushed one at a time.
+pub const MESSAGE_HEADER: &str = "x-nervemq-message";
+
+/// Host names of cloud metadata services, which hand out credentials to whoever asks.
+const METADATA_HOSTS: &[&str] = &["metadata", "metadata.google.internal"];
+
+/// Checks that a push URL doesn't point at a link-local or cloud metadata address, which the
+/// server could otherwise be made to call and report the answer of.
+///
+/// # Errors
+/// * `Error::InvalidParameter` - If the URL isn't an HTTP or HTTPS URL
+/// * `Error::Forbidden` - If the URL points at a link-local or metadata address
+pub fn validate_url(url: &Url) -> Result<(), Error> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::invalid_parameter(
+            "url: must be an HTTP or HTTPS URL",
+        ));
+    }
+
+    let blocked = match url.host() {
+        Some(Host::Ipv4(ip)) => is_blocked(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_blocked(IpAddr::V6(ip)),
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            METADATA_HOSTS.contains(&domain.as_str())
+        }
+        None => false,
+    };
+    if blocked {
+        return Err(Error::forbidden(format!(
+            "url: {} is a link-local or metadata address",
+            url.host_str().unwrap_or_default()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Whether an address is link-local or one of the metadata services on other ranges.
+fn is_blocked(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_link_local() || ip == Ipv4Addr::new(100, 100, 100, 200),
+        IpAddr::V6(ip) => {
+            // fe80::/10, and the IPv6 address of the EC2 metadata service
+            (ip.segments()[0] & 0xffc0) == 0xfe80
+                || ip == Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)
+        }
+    }
+}
+
+/// The push subscription of a queue.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PushSubscription {
+    pub url: String,
+    /// Value of the `Authorization` header sent with deliveries. Never returned.
+    #[serde(skip)]
+    pub auth_header: Option<String>,
+    /// Maximum number of batches pushed at the same time
+    pub concurrency: u64,
+    /// Maximum number of messages pushed in one request
+    pub batch_size: u64,
+    /// Milliseconds to wait for a batch to fill before it is pushed
+    pub batch_window: u64,
+    /// Seconds before the first retry of a failed delivery
+    pub initial_backoff: u64,
+    /// Longest wait in seconds before a retry
+    pub max_backoff: u64,
+    /// Unix timestamp of the creation
+    pub created_at: u64,
+}
+
+impl PushSubscription {
+    /// How long a message waits before it is pushed again, after its `tries`th failed delivery.
+    pub fn backoff(&self, tries: u64) -> Duration {
+        let doublings = tries.saturating_sub(1).min(u32::MAX as u64) as u32;
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u64.saturating_pow(doublings));
+
+        Duration::from_secs(backoff.min(self.max_backoff))
+    }
+}
+
+/// Request to set the push subscription of a queue.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushSubscriptionRequest {
+    pub url: url::Url,
+    #[serde(default)]
+    pub auth_header: Option<String>,
+    #[serde(default)]
+    pub concurrency: Option<u64>,
+    #[serde(default)]
+    pub batch_size: Option<u64>,
+    #[serde(default)]
+    pub batch_window: Option<u64>,
+    #[serde(default)]
+    pub initial_backoff: Option<u64>,
+    #[serde(default)]
+    pub max_backoff: Option<u64>,
+}
+
+/// What the background task needs to know about a subscription.
+#[derive(Debug, Clone, FromRow)]
+pub struct PushTarget {
+    pub id: u64,
+    pub queue_id: u64,
+    pub namespace: String,
+    pub queue: String,
+    #[sqlx(flatten)]
+    pub subscription: PushSubscription,
+}
+
+/// Pushes the messages of every subscribed queue, every job poll interval until the process
+/// exits.
+pub fn spawn(service: &Service) {
+    let client = webhooks::http_client();
+    let running: Arc<Mutex<HashMap<u64, JoinHandle<()>>>> = Arc::default();
+
+    let period = service.config().tasks().job_poll;
+    tasks::spawn_periodic(service, "push", period, move |service| {
+        let (client, running) = (client.clone(), running.clone());
+        async move {
+            let targets = service.list_push_targets().await?;
+
+            let mut running = running.lock().unwrap_or_else(|e| e.into_inner());
+            running.retain(|_, task| !task.is_finished());
+            // A subscription whose previous messages are still being pushed is left alone, so
+            // that it never has more than its concurrency in flight.
+            for target in targets {
+                if !running.contains_key(&target.id) {
+                    let id = target.id;
+                    let task = tokio::spawn(push(service.clone(), client.clone(), target));
+                    running.insert(id, task);
+                }
+            }
+            Ok(())
+        }
+    });
+}
+
+/// Pushes the visible messages of a queue until there are none left.
+async fn push(service: Service, client: HttpClient, target: PushTarget) {
+    let subscription = &target.subscription;
+    let batch_window = Duration::from_millis(subscription.batch_window);
+
+    loop {
+        let max_messages = subscription.concurrency * subscription.batch_size;
+        let mut messages = match receive(&service, &target, max_messages, Duration::ZERO).await {
+            Some(messages) if !messages.is_empty() => messages,
+            _ => return,
+        };
+
+        // Waits for a partial batch to fill, for as long as the batch window allows.
+        let deadline = Instant::now() + batch_window;
+        while (messages.len() as u64) < subscription.batch_size {
+            let wait_time = deadline.saturating_duration_since(Instant::now());
+            if wait_time.is_zero() {
+                break;
+            }
+            let max_messages = subscription.batch_size - messages.len() as u64;
+            match receive(&service, &target, max_messages, wait_time).await {
+                Some(more) if !more.is_empty() => messages.extend(more),
+                _ => break,
+            }
+        }
+
+        let mut deliveries = JoinSet::new();
+        while !messages.is_empty() {
+            let rest = messages.split_off(messages.len().min(subscription.batch_size as usize));
+            deliveries.spawn(deliver(
+                service.clone(),
+                client.clone(),
+                target.clone(),
+                messages,
+            ));
+            messages = rest;
+        }
+        deliveries.join_all().await;
+    }
+}
+
+/// Receives up to `max_messages` messages to push, waiting up to `wait_time` for the first one.
+/// Logs and returns `None` if receiving fails.
+async fn receive(
+    service: &Service,
+    target: &PushTarget,
+    max_messages: u64,
+    wait_time: Duration,
+) -> Option<Vec<SqsMessage>> {
+    let all = HashSet::from(["All".to_owned()]);
+    let options = ReceiveOptions::builder()
+        .max_messages(max_messages)
+        .visibility_timeout(PUSH_VISIBILITY_TIMEOUT)
+        .wait_time(wait_time)
+        .attribute_names(all.clone())
+        .message_attribute_names(all)
+        .build();
+
+    match service
+        .sqs_recv_batch(&target.namespace, &target.queue, options)
+        .await
+    {
+        Ok(messages) => Some(messages),
+        Err(e) => {
+            tracing::error!(
+                namespace = target.namespace,
+                queue = target.queue,
+                "Failed to receive messages to push: {e}"
+            );
+            None
+        }
+    }
+}
+
+/// Pushes a batch of messages, then deletes each of them or schedules its retry.
+async fn deliver(
+    service: Service,
+    client: HttpClient,
+    target: PushTarget,
+    messages: Vec<SqsMessage>,
+) {
+    let handles = messages
+        .iter()
+        .map(|message| message.receipt_handle.clone())
+        .collect::<Vec<_>>();
+    let ids = match service
+        .resolve_receipt_handles(&target.namespace, &target.queue, &handles)
+        .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!(
+                namespace = target.namespace,
+                queue = target.queue,
+                "Failed to resolve the receipt handles of messages to push: {e}"
+            );
+            return;
+        }
+    };
+
+    let mut batch = Vec::with_capacity(messages.len());
+    for (message, id) in messages.into_iter().zip(ids) {
+        match id {
+            Some(id) => batch.push((message, id)),
+            None => tracing::error!(
+                receipt_handle = message.receipt_handle,
+                "Received a message with an invalid receipt handle"
+            ),
+        }
+    }
+    if batch.is_empty() {
+        return;
+    }
+
+    let outcome = attempt_delivery(
+        &client,
+        &target.subscription,
+        batch.iter().map(|(message, _)| message),
+    )
+    .await;
+
+    for (message, message_id) in &batch {
+        let tries = message
+            .attributes
+            .get("ApproximateReceiveCount")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(1);
+
+        let failure = match &outcome {
+            Ok(failed) if failed.contains(&message.message_id) => {
+                Some("webhook reported the message as failed".to_owned())
+            }
+            Ok(_) => None,
+            Err(e) => Some(e.to_string()),
+        };
+
+        let result = match failure {
+            None => service.complete_push(target.queue_id, *message_id).await,
+            Some(reason) => {
+                tracing::warn!(
+                    namespace = target.namespace,
+                    queue = target.queue,
+                    message = message_id,
+                    tries,
+                    "Push delivery failed: {reason}"
+                );
+                service
+                    .fail_push(
+                        target.queue_id,
+                        *message_id,
+                        target.subscription.backoff(tries),
+                        &reason,
+                    )
+                    .await
+            }
+        };
+
+        if let Err(e) = result {
+            tracing::error!(
+                namespace = target.namespace,
+                queue = target.queue,
+                message = message_id,
+                "Failed to record the outcome of a push delivery: {e}"
+            );
+        }
+    }
+}
+
+/// Body of a batch delivery.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PushBatch<'a> {
+    messages: Vec<&'a SqsMessage>,
+}
+
+/// Response to a batch delivery, listing the messages that failed.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PushBatchResponse {
+    batch_item_failures: Vec<PushBatchItemFailure>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PushBatchItemFailure {
+    item_identifier: String,
+}
+
+/// Pushes messages to a subscription's webhook, and returns the IDs of the messages the webhook
+/// reported as failed.
+async fn attempt_delivery<'a>(
+    client: &HttpClient,
+    subscription: &PushSubscription,
+    messages: impl Iterator<Item = &'a SqsMessage>,
+) -> eyre::Result<HashSet<String>> {
+    let messages = messages.collect::<Vec<_>>();
+    let batched = subscription.batch_size > 1;
+
+    let mut request =
+        Request::post(&subscription.url).header(hyper::header::CONTENT_TYPE, "application/json");
+    if let Some(auth_header) = &subscription.auth_header {
+        request = request.header(hyper::header::AUTHORIZATION, auth_header);
+    }
+    let body = match messages.as_slice() {
+        [message] if !batched => {
+            request = request.header(MESSAGE_HEADER, &message.message_id);
+            serde_json::to_vec(message)?
+        }
+        _ => serde_json::to_vec(&PushBatch { messages })?,
+    };
+    let request = request.body(Body::from(body))?;
+
+    tokio::time::timeout(PUSH_TIMEOUT, async {
+        let response = client.request(request).await?;
+        if !response.status().is_success() {
+            eyre::bail!("webhook responded with {}", response.status());
+        }
+        if !batched {
+            return Ok(HashSet::new());
+        }
+
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk?);
+            if data.len() > MAX_RESPONSE_LENGTH {
+                eyre::bail!("webhook response is longer than {MAX_RESPONSE_LENGTH} bytes");
+            }
+        }
+
+        Ok(serde_json::from_slice::<PushBatchResponse>(&data)
+            .map(|response| {
+                response
+                    .batch_item_failures
+                    .into_iter()
+                    .map(|failure| failure.item_identifier)
+                    .collect()
+            })
+            .unwrap_or_default())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::UNIX_EPOCH};
+
+    use actix_identity::Identity;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        clock::ManualClock, config::Config, error::Error, kms::memory::InMemoryKeyManager,
+        sqs::types::send_message::SendMessageRequest,
+    };
+
+    /// Accepts one HTTP request, answers it with `status`, and returns its head and body.
+    async fn receive(listener: &tokio::net::TcpListener, status: &str) -> (String, Vec<u8>) {
+        respond(listener, status, |_| String::new()).await
+    }
+
+    /// Accepts one HTTP request, answers it with `status` and the body `response` makes of the
+    /// request's body, and returns the request's head and body.
+    async fn respond(
+        listener: &tokio::net::TcpListener,
+        status: &str,
+        response: impl FnOnce(&[u8]) -> String,
+    ) -> (String, Vec<u8>) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+
+        loop {
+            let read = socket.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..read]);
+
+            let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&data[..end]).to_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            if data.len() >= end + 4 + length {
+                let body = data[end + 4..end + 4 + length].to_vec();
+                let response = response(&body);
+                socket
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\ncontent-length: {}\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+                return (head, body);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_push() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "q", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let request = |concurrency| PushSubscriptionRequest {
+            url: format!("http://{}/push", listener.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+            auth_header: Some("Bearer secret".to_owned()),
+            concurrency,
+            batch_size: None,
+            batch_window: None,
+            initial_backoff: Some(60),
+            max_backoff: None,
+        };
+        assert!(matches!(
+            service
+                .set_push_subscription("t", "q", request(Some(0)))
+                .await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        let subscription = service
+            .set_push_subscription("t", "q", request(None))
+            .await
+            .unwrap();
+        assert_eq!(subscription.concurrency, DEFAULT_PUSH_CONCURRENCY);
+        assert_eq!(subscription.max_backoff, DEFAULT_MAX_BACKOFF);
+        assert!(matches!(
+            service.create_consumer_group("t", "q", "g").await,
+            Err(Error::InvalidParameter { .. })
+        ));
+
+        service
+            .sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    message_body: "hello".to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let [target] =
+            <[PushTarget; 1]>::try_from(service.list_push_targets().await.unwrap()).unwrap();
+        let client = webhooks::http_client();
+
+        // A failed delivery hides the message for the backoff and records why it failed.
+        let task = tokio::spawn(push(service.clone(), client.clone(), target.clone()));
+        let (head, body) = receive(&listener, "500 Internal Server Error").await;
+        task.await.unwrap();
+
+        assert!(head.starts_with("post /push "));
+        assert!(head.contains("authorization: bearer secret"));
+        assert!(head.contains(MESSAGE_HEADER));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["Body"], "hello");
+        assert_eq!(body["Attributes"]["ApproximateReceiveCount"], "1");
+
+        let messages = service.list_messages("t", "q").await.unwrap();
+        assert_eq!(messages[0].tries, 1);
+        assert!(messages[0].failures[0].reason.contains("500"));
+
+        clock.advance(Duration::from_secs(59));
+        push(service.clone(), client.clone(), target.clone()).await;
+
+        // Once the backoff has passed, a successful delivery deletes the message.
+        clock.advance(Duration::from_secs(1));
+        let task = tokio::spawn(push(service.clone(), client.clone(), target.clone()));
+        let (_, body) = receive(&listener, "204 No Content").await;
+        task.await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["Attributes"]["ApproximateReceiveCount"], "2");
+        assert!(service.list_messages("t", "q").await.unwrap().is_empty());
+
+        service.delete_push_subscription("t", "q").await.unwrap();
+        assert!(service.list_push_targets().await.unwrap().is_empty());
+        assert!(matches!(
+            service.get_push_subscription("t", "q").await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_push_batches() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "q", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let request = |batch_size, batch_window| PushSubscriptionRequest {
+            url: format!("http://{}/push", listener.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+            auth_header: None,
+            concurrency: Some(1),
+            batch_size,
+            batch_window,
+            initial_backoff: Some(60),
+            max_backoff: None,
+        };
+        for (batch_size, batch_window) in [(Some(0), None), (Some(101), None), (None, Some(10_001))]
+        {
+            assert!(matches!(
+                service
+                    .set_push_subscription("t", "q", request(batch_size, batch_window))
+                    .await,
+                Err(Error::InvalidParameter { .. })
+            ));
+        }
+        let subscription = service
+            .set_push_subscription("t", "q", request(Some(3), Some(100)))
+            .await
+            .unwrap();
+        assert_eq!(subscription.batch_size, 3);
+        assert_eq!(subscription.batch_window, 100);
+
+        let send = |body: &str| {
+            service.sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    message_body: body.to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+        };
+        for body in ["a", "b"] {
+            send(body).await.unwrap();
+        }
+
+        let [target] =
+            <[PushTarget; 1]>::try_from(service.list_push_targets().await.unwrap()).unwrap();
+        let client = webhooks::http_client();
+
+        // A partial batch waits for the batch window before it is pushed.
+        let task = tokio::spawn(push(service.clone(), client.clone(), target.clone()));
+        let (head, body) = respond(&listener, "200 OK", |_| "not json".to_owned()).await;
+        task.await.unwrap();
+
+        assert!(!head.contains(MESSAGE_HEADER));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let bodies = body["Messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["Body"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bodies, ["a", "b"]);
+        assert!(service.list_messages("t", "q").await.unwrap().is_empty());
+
+        // Messages the response lists as failed back off, the others are deleted.
+        for body in ["c", "d", "e"] {
+            send(body).await.unwrap();
+        }
+        let task = tokio::spawn(push(service.clone(), client.clone(), target.clone()));
+        let (_, body) = respond(&listener, "200 OK", |body| {
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            serde_json::json!({
+                "batchItemFailures": [{ "itemIdentifier": body["Messages"][1]["MessageId"] }]
+            })
+            .to_string()
+        })
+        .await;
+        task.await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["Messages"].as_array().unwrap().len(), 3);
+        let messages = service.list_messages("t", "q").await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body, "d");
+        assert_eq!(messages[0].tries, 1);
+        assert!(!messages[0].failures.is_empty());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let subscription = PushSubscription {
+            url: "http://localhost".to_owned(),
+            auth_header: None,
+            concurrency: 1,
+            batch_size: 1,
+            batch_window: 0,
+            initial_backoff: 2,
+            max_backoff: 30,
+            created_at: 0,
+        };
+
+        let backoffs = (1..=6)
+            .map(|tries| subscription.backoff(tries).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(backoffs, [2, 4, 8, 16, 30, 30]);
+        assert_eq!(subscription.backoff(u64::MAX).as_secs(), 30);
+    }
+
+    #[test]
+    fn test_validate_url() {
+        for url in [
+            "http://127.0.0.1:8080/push",
+            "https://example.com/push",
+            "http://10.0.0.5/push",
+        ] {
+            assert!(validate_url(&url.parse().unwrap()).is_ok(), "{url}");
+        }
+
+        for url in [
+            "http://169.254.169.254/latest/meta-data/",
+            "http://169.254.1.1/",
+            "http://[fe80::1]/",
+            "http://[fd00:ec2::254]/",
+            "http://[::ffff:169.254.169.254]/",
+            "http://100.100.100.200/",
+            "http://metadata.google.internal./computeMetadata/v1/",
+        ] {
+            assert!(
+                matches!(
+                    validate_url(&url.parse().unwrap()),
+                    Err(Error::Forbidden { .. })
+                ),
+                "{url}"
+            );
+        }
+
+        assert!(matches!(
+            validate_url(&"ftp://example.com/".parse().unwrap()),
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+}
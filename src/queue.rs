@@ -62,4 +62,8 @@ pub struct QueueStatistics {
     pub delivered: u64,
     /// Number of messages that failed processing
     pub failed: u64,
+    /// Number of delivered messages whose visibility timeout hasn't run out yet
+    pub in_flight: u64,
+    /// Whether the queue is the dead-letter queue of another queue
+    pub dead_letter_queue: bool,
 }
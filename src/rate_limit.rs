@@ -0,0 +1,350 @@
+//! Rate limits of the SQS and ingestion APIs.
+//!
+//! Every API key, and every user calling the APIs without one, has a token bucket that holds up
+//! to `burst` tokens and refills at `requests_per_second`. Each request takes a token, and
+//! requests finding the bucket empty fail with `429 Too Many Requests` and the SQS error code
+//! `ThrottlingException`, with a `Retry-After` of when the next token is due.
+//!
+//! The limit is the API key's own, set by an admin, or else its namespace's, or else
+//! [`Config::rate_limit`](crate::config::Config::rate_limit). Buckets are kept in a [`Registry`]
+//! or, with [`RateLimitStore::Sqlite`], in the database, so that instances sharing it also share
+//! their buckets.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_identity::IdentityExt;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web::Data,
+    HttpMessage,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{
+    auth::credential::{AuthenticatedKey, AuthorizedNamespace},
+    config::RateLimitStore,
+    error::Error,
+    registry::Registry,
+    service::Service,
+};
+
+/// A rate limit, as a token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    /// Rate the bucket refills at
+    pub requests_per_second: f64,
+    /// Number of tokens the bucket holds, which is how many requests can be made at once
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// Checks that the limit lets any requests through.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidParameter` if the rate isn't positive or the burst is zero
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(self.requests_per_second > 0.0 && self.requests_per_second.is_finite()) {
+            return Err(Error::invalid_parameter(
+                "requestsPerSecond must be a positive number",
+            ));
+        }
+        if self.burst == 0 {
+            return Err(Error::invalid_parameter("burst must be at least 1"));
+        }
+
+        Ok(())
+    }
+
+    /// Time until a bucket holding `tokens` has a whole token.
+    fn wait(&self, tokens: f64) -> Duration {
+        Duration::from_secs_f64(((1.0 - tokens) / self.requests_per_second).max(0.0))
+    }
+}
+
+/// What a rate limit set by an admin applies to.
+#[derive(Debug, Clone, Copy)]
+pub enum RateLimitTarget<'a> {
+    /// The API keys and users of a namespace, by name
+    Namespace(&'a str),
+    /// A single API key, by ID
+    ApiKey(&'a str),
+}
+
+/// A token bucket kept in memory.
+#[derive(Debug, Clone, Default)]
+pub struct Bucket {
+    tokens: f64,
+    /// When `tokens` was last updated, or `None` for a new, full bucket
+    updated_at: Option<Instant>,
+}
+
+impl Bucket {
+    /// Takes a token from the bucket.
+    ///
+    /// # Returns
+    /// `Ok` if there was one, or the time until there is one
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let burst = limit.burst as f64;
+        let tokens = match self.updated_at {
+            Some(updated_at) => {
+                let elapsed = now.saturating_duration_since(updated_at).as_secs_f64();
+                (self.tokens + elapsed * limit.requests_per_second).min(burst)
+            }
+            None => burst,
+        };
+        self.updated_at = Some(now);
+
+        if tokens >= 1.0 {
+            self.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            self.tokens = tokens;
+            Err(limit.wait(tokens))
+        }
+    }
+}
+
+/// Token buckets kept in memory, by API key or user.
+pub type BucketRegistry = Registry<String, Bucket>;
+
+/// Middleware that rejects requests of API keys and users that are over their rate limit.
+///
+/// It must run after authentication, and lets unauthenticated requests through to be rejected
+/// there.
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let service = req.app_data::<Data<Service>>().cloned();
+    let user = req
+        .get_identity()
+        .ok()
+        .and_then(|identity| identity.id().ok());
+
+    if let (Some(service), Some(user)) = (service, user) {
+        let key_id = req
+            .extensions()
+            .get::<AuthenticatedKey>()
+            .map(|key| key.0.clone());
+        let namespace = req
+            .extensions()
+            .get::<AuthorizedNamespace>()
+            .map(|namespace| namespace.0.clone());
+
+        let limit = service
+            .effective_rate_limit(key_id.as_deref(), namespace.as_deref())
+            .await?;
+        if let Some(limit) = limit {
+            let bucket = match key_id {
+                Some(key_id) => format!("key:{key_id}"),
+                None => format!("user:{user}"),
+            };
+            take(&service, &bucket, &limit).await?;
+        }
+    }
+
+    next.call(req).await
+}
+
+/// Takes a token from a bucket, in the configured store.
+///
+/// # Errors
+/// * `Error::Throttled` - If the bucket is empty
+async fn take(service: &Service, bucket: &str, limit: &RateLimit) -> Result<(), Error> {
+    let res = match service.config().rate_limit_store() {
+        RateLimitStore::Memory => {
+            let mut res = Ok(());
+            let now = Instant::now();
+            service
+                .rate_limit_buckets()
+                .update(bucket, |bucket| res = bucket.take(limit, now));
+            res
+        }
+        RateLimitStore::Sqlite => take_stored(service, bucket, limit).await?,
+    };
+
+    res.map_err(|retry_after| Error::Throttled { retry_after })
+}
+
+/// Takes a token from a bucket kept in the database.
+///
+/// The bucket is refilled and a token taken in a single statement, so concurrent requests, also
+/// of other instances, can't take the same token.
+async fn take_stored(
+    service: &Service,
+    bucket: &str,
+    limit: &RateLimit,
+) -> Result<Result<(), Duration>, Error> {
+    let now = service
+        .clock()
+        .now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    let taken = sqlx::query(
+        "
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at) VALUES ($1, $2 - 1, $3)
+        ON CONFLICT (key) DO UPDATE SET
+            tokens = MIN($2, tokens + MAX(0, $3 - updated_at) * $4) - 1,
+            updated_at = $3
+        WHERE MIN($2, tokens + MAX(0, $3 - updated_at) * $4) >= 1
+        ",
+    )
+    .bind(bucket)
+    .bind(limit.burst as f64)
+    .bind(now)
+    .bind(limit.requests_per_second)
+    .execute(service.db())
+    .await?
+    .rows_affected();
+
+    if taken > 0 {
+        return Ok(Ok(()));
+    }
+
+    let tokens: f64 = sqlx::query_scalar(
+        "
+        SELECT MIN($2, tokens + MAX(0, $3 - updated_at) * $4)
+        FROM rate_limit_buckets WHERE key = $1
+        ",
+    )
+    .bind(bucket)
+    .bind(limit.burst as f64)
+    .bind(now)
+    .bind(limit.requests_per_second)
+    .fetch_one(service.db())
+    .await?;
+
+    Ok(Err(limit.wait(tokens)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let limit = RateLimit {
+            requests_per_second: 2.0,
+            burst: 3,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::default();
+
+        for _ in 0..3 {
+            assert_eq!(bucket.take(&limit, start), Ok(()));
+        }
+        assert_eq!(bucket.take(&limit, start), Err(Duration::from_millis(500)));
+
+        // Half a second refills one token.
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(&limit, later), Ok(()));
+        assert!(bucket.take(&limit, later).is_err());
+
+        // A long pause refills no more than the burst.
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(&limit, much_later), Ok(()));
+        }
+        assert!(bucket.take(&limit, much_later).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stored_bucket_and_effective_limit() {
+        use actix_identity::Identity;
+        use serde_json::json;
+
+        use crate::{config::Config, kms::memory::InMemoryKeyManager};
+
+        let config: Config = serde_json::from_value(json!({
+            "db_path": crate::config::MEMORY_DB_PATH,
+            "integrity_check": "off",
+            "rate_limit": 100.0,
+            "rate_limit_store": "sqlite",
+        }))
+        .unwrap();
+        let service = Service::connect_with()
+            .config(config)
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        service.create_namespace("t", root()).await.unwrap();
+        let token = service
+            .create_token("ci".to_owned(), "t".to_owned(), root())
+            .await
+            .unwrap();
+
+        let default = service.config().rate_limit();
+        assert_eq!(default.unwrap().burst, 100);
+        let effective = |key_id| service.effective_rate_limit(key_id, Some("t"));
+        assert_eq!(effective(Some(&token.access_key)).await.unwrap(), default);
+
+        let namespace_limit = RateLimit {
+            requests_per_second: 10.0,
+            burst: 10,
+        };
+        let key_limit = RateLimit {
+            requests_per_second: 0.01,
+            burst: 2,
+        };
+        service
+            .set_rate_limit(RateLimitTarget::Namespace("t"), Some(namespace_limit))
+            .await
+            .unwrap();
+        assert_eq!(effective(None).await.unwrap(), Some(namespace_limit));
+        service
+            .set_rate_limit(RateLimitTarget::ApiKey(&token.access_key), Some(key_limit))
+            .await
+            .unwrap();
+        assert_eq!(
+            effective(Some(&token.access_key)).await.unwrap(),
+            Some(key_limit)
+        );
+        assert_eq!(effective(None).await.unwrap(), Some(namespace_limit));
+
+        assert!(take(&service, "key", &key_limit).await.is_ok());
+        assert!(take(&service, "key", &key_limit).await.is_ok());
+        assert!(matches!(
+            take(&service, "key", &key_limit).await,
+            Err(Error::Throttled { retry_after }) if retry_after > Duration::from_secs(99)
+        ));
+        // Buckets are independent.
+        assert!(take(&service, "other", &key_limit).await.is_ok());
+
+        service
+            .set_rate_limit(RateLimitTarget::ApiKey(&token.access_key), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            effective(Some(&token.access_key)).await.unwrap(),
+            Some(namespace_limit)
+        );
+        assert!(matches!(
+            service
+                .set_rate_limit(RateLimitTarget::Namespace("nope"), None)
+                .await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let limit = |requests_per_second, burst| RateLimit {
+            requests_per_second,
+            burst,
+        };
+
+        assert!(limit(0.5, 1).validate().is_ok());
+        assert!(limit(0.0, 1).validate().is_err());
+        assert!(limit(-1.0, 1).validate().is_err());
+        assert!(limit(f64::NAN, 1).validate().is_err());
+        assert!(limit(1.0, 0).validate().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Progressive redelivery delays.
+//!
+//! A message whose consumer keeps failing on it is normally received again as soon as its
+//! visibility timeout expires, so a poison message that hasn't used up its retries yet can keep
+//! consumers busy in a hot loop. Queues can set the `RedeliveryBackoff` attribute to a
+//! comma-separated list of delays in seconds (e.g. `60,300,1800`) to hold such messages back for
+//! longer after each failed delivery: after the first delivery times out, the message becomes
+//! receivable again only after the first delay, after the second after the second delay, and so on,
+//! with the last delay repeating. An empty value disables the delays.
+//!
+//! Delays don't hold back moving a message to the dead-letter queue once it has used up its
+//! retries.
+
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Maximum number of delays in a `RedeliveryBackoff` attribute.
+pub const MAX_REDELIVERY_DELAYS: usize = 16;
+
+/// Maximum delay before a message is redelivered.
+pub const MAX_REDELIVERY_DELAY: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Parses the `RedeliveryBackoff` queue attribute into the delays, in seconds, after each
+/// delivery. An empty attribute has no delays.
+pub fn parse_delays(attribute: &str) -> Result<Vec<u64>, Error> {
+    let invalid = |reason: String| Error::invalid_parameter(format!("RedeliveryBackoff: {reason}"));
+
+    let delays = attribute
+        .split(',')
+        .map(str::trim)
+        .filter(|delay| !delay.is_empty())
+        .map(|delay| {
+            delay
+                .parse::<u64>()
+                .map_err(|e| invalid(format!("{delay}: {e}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if delays.len() > MAX_REDELIVERY_DELAYS {
+        return Err(invalid(format!("at most {MAX_REDELIVERY_DELAYS} delays")));
+    }
+    if let Some(delay) = delays
+        .iter()
+        .find(|delay| **delay > MAX_REDELIVERY_DELAY.as_secs())
+    {
+        return Err(invalid(format!(
+            "{delay}: must be at most {} seconds",
+            MAX_REDELIVERY_DELAY.as_secs()
+        )));
+    }
+
+    Ok(delays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_delays() {
+        assert_eq!(parse_delays("60, 300,1800").unwrap(), [60, 300, 1800]);
+        assert!(parse_delays("").unwrap().is_empty());
+        assert!(matches!(
+            parse_delays("60,5m"),
+            Err(Error::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            parse_delays("86400"),
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+//! State shared between threads.
+//!
+//! The HTTP server runs a copy of the application on each of its
+//! [`Config::workers`](crate::config::Config::workers), and background tasks run on the Tokio
+//! runtime's threads, so state kept in memory instead of the database has to be shared by all of
+//! them. Such state lives in a [`Registry`] held by the [`Service`](crate::service::Service),
+//! whose clones all refer to the same entries, rather than in worker-local state like
+//! `thread_local!`, `Rc` or app data created in the application factory.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A map shared by every clone of it, and by every thread holding one.
+///
+/// Every operation holds the lock only for as long as it takes, so a registry must not be used
+/// for state that is held across an `.await`.
+#[derive(Debug)]
+pub struct Registry<K, V> {
+    entries: Arc<Mutex<HashMap<K, V>>>,
+}
+
+impl<K, V> Clone for Registry<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for Registry<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Registry<K, V> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<K, V>> {
+        // A panic while the lock was held can't leave a map half-updated, so the entries are
+        // still usable.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Updates the entry of a key, inserting the default value first if there is none.
+    pub fn update<Q>(&self, key: &Q, f: impl FnOnce(&mut V))
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ToOwned<Owned = K> + ?Sized,
+        V: Default,
+    {
+        let mut entries = self.entries();
+        match entries.get_mut(key) {
+            Some(value) => f(value),
+            None => f(entries.entry(key.to_owned()).or_default()),
+        }
+    }
+
+    /// Gets a copy of the entry of a key.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+        V: Clone,
+    {
+        self.entries().get(key).cloned()
+    }
+
+    /// Removes the entry of a key.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries().remove(key)
+    }
+
+    /// Removes every entry for which `keep` returns `false`.
+    pub fn retain(&self, keep: impl FnMut(&K, &mut V) -> bool) {
+        self.entries().retain(keep);
+    }
+
+    /// Takes every entry, leaving the registry empty.
+    pub fn take(&self) -> HashMap<K, V> {
+        std::mem::take(&mut *self.entries())
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Checks whether the registry has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_updates() {
+        let registry = Registry::<String, u64>::new();
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                let registry = registry.clone();
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        registry.update(["even", "odd"][i % 2], |count| *count += 1);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(registry.get("even"), Some(4000));
+        assert_eq!(registry.get("odd"), Some(4000));
+        assert_eq!(registry.remove("odd"), Some(4000));
+
+        let taken = registry.take();
+        assert_eq!(taken.len(), 1);
+        assert!(registry.is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+//! Canned reports over the messages in a queue.
+//!
+//! Operators can get an overview of what is in a queue without opening the database: the most
+//! common values of a message attribute, and how message sizes and ages are distributed. Reports
+//! are fixed, read-only queries. Their results are limited to [`MAX_REPORT_ROWS`] rows, and they
+//! are interrupted if they run for longer than [`REPORT_TIME_LIMIT`].
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::error::Error;
+
+/// Maximum number of rows in a report.
+pub const MAX_REPORT_ROWS: u64 = 100;
+
+/// Number of rows in a top values report if the request doesn't say.
+pub const DEFAULT_REPORT_ROWS: u64 = 10;
+
+/// How long a report may run before it is interrupted.
+pub const REPORT_TIME_LIMIT: Duration = Duration::from_secs(5);
+
+/// Approximate number of SQLite instructions between checks of the time limit.
+pub const REPORT_PROGRESS_INTERVAL: i32 = 10_000;
+
+/// Upper bounds (exclusive) and labels of the buckets of the size histogram. Bodies are counted
+/// with their stored size, so compressed bodies count with their compressed size.
+const SIZE_BUCKETS: [(u64, &str); 4] = [
+    (1024, "< 1 KiB"),
+    (16 * 1024, "1 KiB - 16 KiB"),
+    (64 * 1024, "16 KiB - 64 KiB"),
+    (256 * 1024, "64 KiB - 256 KiB"),
+];
+const SIZE_OVERFLOW: &str = ">= 256 KiB";
+
+/// Upper bounds (exclusive, in seconds) and labels of the buckets of the age histogram.
+const AGE_BUCKETS: [(u64, &str); 4] = [
+    (60, "< 1 minute"),
+    (60 * 60, "1 minute - 1 hour"),
+    (24 * 60 * 60, "1 hour - 1 day"),
+    (7 * 24 * 60 * 60, "1 day - 7 days"),
+];
+const AGE_OVERFLOW: &str = ">= 7 days";
+
+/// Label of the age histogram bucket for messages sent before send times were recorded.
+const AGE_UNKNOWN: &str = "unknown";
+
+/// The available reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportKind {
+    /// The most common values of a message attribute
+    TopAttributeValues,
+    /// Number of messages by body size
+    SizeHistogram,
+    /// Number of messages by time since they were sent
+    AgeHistogram,
+}
+
+/// A row of a report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportRow {
+    /// The attribute value or histogram bucket
+    pub label: String,
+    /// Number of messages with that value or in that bucket
+    pub count: u64,
+}
+
+/// Runs a report over the messages in a queue.
+///
+/// # Arguments
+/// * `conn` - Connection to run the report on
+/// * `queue_id` - Queue to report on
+/// * `kind` - Report to run
+/// * `attribute` - Attribute to report on, for [`ReportKind::TopAttributeValues`]
+/// * `limit` - Maximum number of rows, for [`ReportKind::TopAttributeValues`]
+/// * `now` - Current time as a Unix timestamp, for [`ReportKind::AgeHistogram`]
+///
+/// # Errors
+/// Returns [`Error::MissingParameter`] if the report needs an attribute and none is given
+pub async fn run(
+    conn: &mut SqliteConnection,
+    queue_id: u64,
+    kind: ReportKind,
+    attribute: Option<&str>,
+    limit: u64,
+    now: i64,
+) -> Result<Vec<ReportRow>, Error> {
+    match kind {
+        ReportKind::TopAttributeValues => {
+            let attribute = attribute.ok_or_else(|| Error::missing_parameter("attribute"))?;
+
+            let rows = sqlx::query_as::<_, (String, u64)>(
+                "
+                SELECT json_extract(CAST(kv.v AS TEXT), '$.StringValue') AS label, COUNT(*)
+                FROM kv_pairs kv
+                JOIN messages m ON m.id = kv.message
+                WHERE m.queue = $1 AND kv.k = $2
+                GROUP BY label
+                HAVING label IS NOT NULL
+                ORDER BY COUNT(*) DESC, label
+                LIMIT $3
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(attribute)
+            .bind(limit.min(MAX_REPORT_ROWS) as i64)
+            .fetch_all(conn)
+            .await?;
+
+            Ok(rows
+                .into_iter()
+                .map(|(label, count)| ReportRow { label, count })
+                .collect())
+        }
+        ReportKind::SizeHistogram => {
+            histogram(
+                conn,
+                queue_id,
+                "LENGTH(m.body)",
+                &SIZE_BUCKETS,
+                SIZE_OVERFLOW,
+            )
+            .await
+        }
+        ReportKind::AgeHistogram => {
+            let mut rows = histogram(
+                conn,
+                queue_id,
+                &format!("{now} - m.sent_at"),
+                &AGE_BUCKETS,
+                AGE_OVERFLOW,
+            )
+            .await?;
+
+            let unknown = sqlx::query_scalar::<_, u64>(
+                "SELECT COUNT(*) FROM messages m WHERE m.queue = $1 AND m.sent_at IS NULL",
+            )
+            .bind(queue_id as i64)
+            .fetch_one(conn)
+            .await?;
+
+            if unknown > 0 {
+                rows.push(ReportRow {
+                    label: AGE_UNKNOWN.to_owned(),
+                    count: unknown,
+                });
+            }
+
+            Ok(rows)
+        }
+    }
+}
+
+/// Counts the messages in a queue by buckets of `value`, returning every bucket in order, even
+/// empty ones. Messages for which `value` is `NULL` aren't counted.
+async fn histogram(
+    conn: &mut SqliteConnection,
+    queue_id: u64,
+    value: &str,
+    buckets: &[(u64, &str)],
+    overflow: &str,
+) -> Result<Vec<ReportRow>, Error> {
+    let cases = buckets
+        .iter()
+        .enumerate()
+        .map(|(i, (bound, _))| format!("WHEN {value} < {bound} THEN {i}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let counts = sqlx::query_as::<_, (u64, u64)>(&format!(
+        "
+        SELECT CASE {cases} ELSE {overflow} END AS bucket, COUNT(*)
+        FROM messages m
+        WHERE m.queue = $1 AND {value} IS NOT NULL
+        GROUP BY bucket
+        ",
+        overflow = buckets.len(),
+    ))
+    .bind(queue_id as i64)
+    .fetch_all(conn)
+    .await?;
+
+    let mut rows = buckets
+        .iter()
+        .map(|(_, label)| *label)
+        .chain([overflow])
+        .map(|label| ReportRow {
+            label: label.to_owned(),
+            count: 0,
+        })
+        .collect::<Vec<_>>();
+
+    for (bucket, count) in counts {
+        rows[bucket as usize].count = count;
+    }
+
+    Ok(rows)
+}
@@ -0,0 +1,197 @@
+//! Request IDs.
+//!
+//! Every request gets an ID, which is returned in the `X-Request-Id` response header (and, for SQS
+//! requests, in `x-amzn-RequestId`, where AWS SDKs look for it) and recorded as `http.request_id`
+//! on the request's tracing span, so that a request a user reports can be found in the logs.
+//!
+//! Clients and proxies can choose the ID by sending an `X-Request-Id` header, which is used if it
+//! is at most [`MAX_REQUEST_ID_LENGTH`] characters of `A-Z a-z 0-9 - _ . : / + =`. Otherwise a
+//! UUIDv7 is generated.
+
+use std::{
+    future::{ready, Future, Ready},
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    FromRequest, HttpMessage, HttpRequest,
+};
+
+use crate::error::Error;
+
+/// The request ID header, in requests and responses.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// The response header AWS SDKs read request IDs of SQS requests from.
+pub const X_AMZN_REQUEST_ID: HeaderName = HeaderName::from_static("x-amzn-requestid");
+
+/// Maximum length of a request ID sent by a client.
+pub const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    /// ID of the request being handled, for code that doesn't have access to the request, like
+    /// error responses.
+    static CURRENT: RequestId;
+}
+
+/// The ID of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Takes the request ID from the `X-Request-Id` header of a request, if it is valid, or
+    /// generates one.
+    pub fn resolve(req: &HttpRequest) -> Self {
+        req.headers()
+            .get(X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid(id))
+            .map(|id| Self(id.to_owned()))
+            .unwrap_or_else(|| Self(uuid::Uuid::now_v7().to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the ID of the request being handled, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Error;
+
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<RequestId>()
+                .cloned()
+                .ok_or_else(Error::opaque),
+        )
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:/+=".contains(c))
+}
+
+/// Middleware that assigns every request its [`RequestId`] and returns it in the response headers,
+/// including on errors. It must wrap every other middleware so that no response goes without an
+/// ID.
+pub struct AssignRequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for AssignRequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+
+    type Error = actix_web::Error;
+
+    type Transform = AssignRequestIdMiddleware<S>;
+
+    type InitError = ();
+
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AssignRequestIdMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AssignRequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AssignRequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let request_id = RequestId::resolve(req.request());
+
+        Box::pin(CURRENT.scope(request_id.clone(), async move {
+            req.extensions_mut().insert(request_id.clone());
+
+            let is_sqs = req.headers().contains_key("x-amz-target");
+            let value = HeaderValue::from_str(request_id.as_str())?;
+            let set_headers = |headers: &mut HeaderMap| {
+                if is_sqs {
+                    headers.insert(X_AMZN_REQUEST_ID, value.clone());
+                }
+                headers.insert(X_REQUEST_ID, value.clone());
+            };
+
+            match service.call(req).await {
+                Ok(mut res) => {
+                    set_headers(res.headers_mut());
+                    Ok(res)
+                }
+                // Errors are rendered here, rather than further out, so that they get the headers
+                // too.
+                Err(e) => {
+                    let mut res = e.error_response();
+                    set_headers(res.headers_mut());
+                    Err(InternalError::from_response(e.to_string(), res).into())
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let req = TestRequest::default()
+            .insert_header((X_REQUEST_ID, "client-1234:abc"))
+            .to_http_request();
+        assert_eq!(RequestId::resolve(&req).as_str(), "client-1234:abc");
+
+        for invalid in ["", "has space", "quote\"", &"a".repeat(129)] {
+            let req = TestRequest::default()
+                .insert_header((X_REQUEST_ID, invalid))
+                .to_http_request();
+            let id = RequestId::resolve(&req);
+            assert!(uuid::Uuid::parse_str(id.as_str()).is_ok(), "{invalid:?}");
+        }
+
+        let id = RequestId::resolve(&TestRequest::default().to_http_request());
+        assert!(uuid::Uuid::parse_str(id.as_str()).is_ok());
+    }
+}
@@ -0,0 +1,99 @@
+//! Message sampling.
+//!
+//! Data teams can look at a random sample of the messages in a queue to check their payloads,
+//! without receiving them: sampling doesn't change the delivery state of any message.
+//!
+//! Queues can set the `SampleMaskedAttributes` attribute to a comma-separated list of names whose
+//! values are masked in samples, both in message attributes and in fields of JSON bodies (at any
+//! depth). Bodies that aren't JSON are returned as they are.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+/// Maximum number of messages in a sample.
+pub const MAX_SAMPLE_SIZE: u64 = 100;
+
+/// Number of messages in a sample if the request doesn't say.
+pub const DEFAULT_SAMPLE_SIZE: u64 = 10;
+
+/// Value that masked attributes and fields are replaced with.
+pub const MASKED_VALUE: &str = "***";
+
+/// A sampled message.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageSample {
+    pub message_id: String,
+    pub body: String,
+    pub message_attributes: HashMap<String, serde_json::Value>,
+}
+
+/// Parses the `SampleMaskedAttributes` queue attribute into the set of masked names.
+pub fn masked_names(attribute: Option<&str>) -> HashSet<String> {
+    attribute
+        .into_iter()
+        .flat_map(|names| names.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Masks the fields of a JSON body with one of the given names. Bodies that aren't JSON are
+/// returned unchanged.
+pub fn mask_body(body: String, masked: &HashSet<String>) -> String {
+    if masked.is_empty() {
+        return body;
+    }
+
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(mut value) => {
+            mask_value(&mut value, masked);
+            value.to_string()
+        }
+        Err(_) => body,
+    }
+}
+
+fn mask_value(value: &mut serde_json::Value, masked: &HashSet<String>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields {
+                if masked.contains(name) {
+                    *field = serde_json::Value::String(MASKED_VALUE.to_owned());
+                } else {
+                    mask_value(field, masked);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                mask_value(item, masked);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_body() {
+        let masked = masked_names(Some(" email, ssn ,"));
+        assert_eq!(masked.len(), 2);
+
+        let body = r#"{"id":1,"email":"a@example.com","items":[{"ssn":"123","sku":"x"}]}"#;
+        let body: serde_json::Value =
+            serde_json::from_str(&mask_body(body.to_owned(), &masked)).unwrap();
+        assert_eq!(body["id"], 1);
+        assert_eq!(body["email"], MASKED_VALUE);
+        assert_eq!(body["items"][0]["ssn"], MASKED_VALUE);
+        assert_eq!(body["items"][0]["sku"], "x");
+
+        assert_eq!(mask_body("email=a".to_owned(), &masked), "email=a");
+        assert!(masked_names(None).is_empty());
+    }
+}
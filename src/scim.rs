@@ -0,0 +1,1060 @@
+//! SCIM 2.0 provisioning.
+//!
+//! Identity providers like Okta or Microsoft Entra ID can manage users through the SCIM API at
+//! `/scim/v2`, authenticating with `Authorization: Bearer <token>` and the token in
+//! [`Config::scim_token`](crate::config::Config::scim_token). The API is disabled unless a token
+//! is configured.
+//!
+//! - `/Users` are NerveMQ users: `userName` is the user's email, the `roles` `admin` and `user`
+//!   are their role, and `active` whether they can log in and use the API. Deactivating a user
+//!   revokes their API keys. Deleting a user only deactivates them, so they get their permissions
+//!   back when they are reactivated.
+//!   Users created through SCIM can't log in until they reset their password.
+//! - `/Groups` are namespaces: `displayName` is the namespace's name, and the members are the
+//!   users who can access it. Removing a member revokes their API keys for the namespace. Deleting
+//!   a group revokes the access of its members, but keeps the namespace and its queues.
+//!
+//! Lists can be filtered with `userName eq "..."` and `displayName eq "..."` respectively, and
+//! paged with `startIndex` and `count`. Attributes NerveMQ doesn't store, like names, are
+//! accepted and ignored.
+
+use std::{collections::BTreeSet, fmt};
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_email::Email;
+use serde_json::Value;
+use sqlx::{FromRow, Sqlite, Transaction};
+
+use crate::{api::auth::Role, auth::crypto::generate_token, error::Error, service::Service};
+
+/// Path the SCIM API is served under.
+pub const SCIM_PATH: &str = "/scim/v2";
+
+/// Content type of SCIM responses.
+pub const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+
+/// Maximum number of resources per page of a list.
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+/// A user, as SCIM represents them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    /// ID of the user, assigned by NerveMQ
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Email of the user
+    pub user_name: String,
+    #[serde(default = "active_default")]
+    pub active: bool,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    #[serde(default)]
+    pub roles: Vec<ScimValue>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+fn active_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+/// A multi-valued attribute of which only the value matters, like a role.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimValue {
+    pub value: String,
+}
+
+/// A group, which SCIM uses to represent a namespace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    /// ID of the namespace, assigned by NerveMQ
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Name of the namespace
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<ScimMember>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+/// A member of a group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimMember {
+    /// ID of the user
+    pub value: String,
+    /// Email of the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: &'static str,
+    pub location: String,
+}
+
+/// A page of resources.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: [&'static str; 1],
+    /// Number of resources matching the filter, across all pages
+    pub total_results: u64,
+    pub start_index: u64,
+    pub items_per_page: u64,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+/// Query parameters of lists.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    /// Position of the first resource, starting at 1
+    pub start_index: Option<u64>,
+    /// Maximum number of resources, at most [`MAX_PAGE_SIZE`]
+    pub count: Option<u64>,
+}
+
+impl ListQuery {
+    /// Returns the start index and page size the query asks for.
+    fn page(&self) -> (u64, u64) {
+        (
+            self.start_index.unwrap_or(1).max(1),
+            self.count.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE),
+        )
+    }
+}
+
+/// A `PatchOp` request, changing some attributes of a resource.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchOperation {
+    /// `add`, `remove` or `replace`, in any case
+    pub op: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatchOp {
+    Add,
+    Remove,
+    Replace,
+}
+
+impl PatchOperation {
+    fn kind(&self) -> Result<PatchOp, ScimError> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(PatchOp::Add),
+            "remove" => Ok(PatchOp::Remove),
+            "replace" => Ok(PatchOp::Replace),
+            _ => Err(ScimError::invalid_syntax(format!(
+                "op must be add, remove or replace, got {:?}",
+                self.op
+            ))),
+        }
+    }
+
+    /// Returns the path of the operation in lowercase, since attribute names aren't case
+    /// sensitive.
+    fn path(&self) -> Option<String> {
+        self.path
+            .as_deref()
+            .map(|path| path.trim().to_ascii_lowercase())
+    }
+}
+
+/// An error, in the form SCIM clients expect.
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    /// Detail error type, for the errors SCIM defines one for
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+impl ScimError {
+    fn new(status: StatusCode, scim_type: Option<&'static str>, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, None, "Unauthorized")
+    }
+
+    pub fn not_found(resource: impl fmt::Display) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            None,
+            format!("Resource not found: {resource}"),
+        )
+    }
+
+    pub fn invalid_syntax(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidSyntax"), detail)
+    }
+
+    pub fn invalid_filter(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidFilter"), detail)
+    }
+
+    pub fn invalid_value(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidValue"), detail)
+    }
+
+    pub fn mutability(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("mutability"), detail)
+    }
+
+    pub fn uniqueness(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, Some("uniqueness"), detail)
+    }
+}
+
+impl fmt::Display for ScimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl From<Error> for ScimError {
+    fn from(e: Error) -> Self {
+        Self::new(e.status_code(), None, e.to_string())
+    }
+}
+
+impl From<sqlx::Error> for ScimError {
+    fn from(e: sqlx::Error) -> Self {
+        Error::from(e).into()
+    }
+}
+
+impl ResponseError for ScimError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .content_type(SCIM_CONTENT_TYPE)
+            .json(serde_json::json!({
+                "schemas": [ERROR_SCHEMA],
+                "status": self.status.as_u16().to_string(),
+                "scimType": self.scim_type,
+                "detail": self.detail,
+            }))
+    }
+}
+
+/// Returns the features of the SCIM API, for `GET /scim/v2/ServiceProviderConfig`.
+pub fn service_provider_config() -> Value {
+    serde_json::json!({
+        "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+        "patch": { "supported": true },
+        "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+        "filter": { "supported": true, "maxResults": MAX_PAGE_SIZE },
+        "changePassword": { "supported": false },
+        "sort": { "supported": false },
+        "etag": { "supported": false },
+        "authenticationSchemes": [{
+            "type": "oauthbearertoken",
+            "name": "Bearer token",
+            "description": "The token configured as scim_token",
+        }],
+    })
+}
+
+/// Parses a filter of the form `attribute eq "value"`, the only kind of filter supported.
+///
+/// # Returns
+/// The attribute in lowercase, and the value
+fn parse_filter(filter: &str) -> Result<(String, String), ScimError> {
+    let invalid = || {
+        ScimError::invalid_filter(format!(
+            "unsupported filter {filter:?}, expected attribute eq \"value\""
+        ))
+    };
+
+    let (attribute, rest) = filter
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    let (op, value) = rest
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    if !op.eq_ignore_ascii_case("eq") {
+        return Err(invalid());
+    }
+    let value: String = serde_json::from_str(value.trim()).map_err(|_| invalid())?;
+
+    Ok((attribute.to_ascii_lowercase(), value))
+}
+
+/// Parses the value of a patch operation as a list, accepting a single value as well.
+fn patch_values<T: DeserializeOwned>(value: Option<Value>) -> Result<Vec<T>, ScimError> {
+    let value = match value {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::Array(values)) => Value::Array(values),
+        Some(value) => Value::Array(vec![value]),
+    };
+    serde_json::from_value(value).map_err(|e| ScimError::invalid_value(e.to_string()))
+}
+
+/// Parses a boolean patch value. Some identity providers send booleans as strings.
+fn patch_bool(value: Option<&Value>) -> Result<bool, ScimError> {
+    match value {
+        Some(Value::Bool(value)) => Ok(*value),
+        Some(Value::String(value)) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Some(Value::String(value)) if value.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::invalid_value(format!(
+            "active must be true or false, got {value:?}"
+        ))),
+    }
+}
+
+/// Returns the role a list of SCIM roles grants, the highest of them.
+fn role_of(roles: &[ScimValue]) -> Result<Role, ScimError> {
+    roles.iter().try_fold(Role::User, |role, value| {
+        let other = match value.value.to_ascii_lowercase().as_str() {
+            "admin" => Role::Admin,
+            "user" => Role::User,
+            _ => {
+                return Err(ScimError::invalid_value(format!(
+                    "roles must be user or admin, got {:?}",
+                    value.value
+                )))
+            }
+        };
+        Ok(role.max(other))
+    })
+}
+
+/// Parses the ID of a resource. Invalid IDs can't belong to any resource.
+fn parse_id(resource: &str, id: &str) -> Result<i64, ScimError> {
+    id.parse()
+        .map_err(|_| ScimError::not_found(format!("{resource} {id}")))
+}
+
+/// Returns the URL of a resource.
+fn location(service: &Service, resource: &str, id: i64) -> String {
+    let mut url = service.config().host();
+    url.set_path(&format!("{SCIM_PATH}/{resource}/{id}"));
+    url.to_string()
+}
+
+#[derive(Debug, FromRow)]
+struct UserRow {
+    id: i64,
+    email: String,
+    role: Role,
+    active: bool,
+}
+
+impl UserRow {
+    fn into_scim(self, service: &Service) -> ScimUser {
+        ScimUser {
+            schemas: vec![USER_SCHEMA.to_owned()],
+            id: Some(self.id.to_string()),
+            meta: Some(Meta {
+                resource_type: "User",
+                location: location(service, "Users", self.id),
+            }),
+            user_name: self.email.clone(),
+            active: self.active,
+            emails: vec![ScimEmail {
+                value: self.email,
+                primary: true,
+            }],
+            roles: vec![ScimValue {
+                value: match self.role {
+                    Role::Admin => "admin",
+                    Role::User => "user",
+                }
+                .to_owned(),
+            }],
+        }
+    }
+}
+
+const USER_COLUMNS: &str = "id, email, role, deactivated_at IS NULL AS active";
+
+async fn fetch_user(service: &Service, id: &str) -> Result<UserRow, ScimError> {
+    sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = $1"))
+        .bind(parse_id("User", id)?)
+        .fetch_optional(service.db())
+        .await?
+        .ok_or_else(|| ScimError::not_found(format!("User {id}")))
+}
+
+/// Lists users, optionally filtered by `userName`.
+pub async fn list_users(
+    service: &Service,
+    query: &ListQuery,
+) -> Result<ListResponse<ScimUser>, ScimError> {
+    let email = match query.filter.as_deref().map(parse_filter).transpose()? {
+        Some((attribute, value)) if attribute == "username" => Some(value),
+        Some((attribute, _)) => {
+            return Err(ScimError::invalid_filter(format!(
+                "users can only be filtered by userName, not {attribute}"
+            )))
+        }
+        None => None,
+    };
+    let (start_index, count) = query.page();
+
+    let total: u64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE $1 IS NULL OR lower(email) = lower($1)",
+    )
+    .bind(&email)
+    .fetch_one(service.db())
+    .await?;
+
+    let users: Vec<UserRow> = sqlx::query_as(&format!(
+        "
+        SELECT {USER_COLUMNS} FROM users
+        WHERE $1 IS NULL OR lower(email) = lower($1)
+        ORDER BY id
+        LIMIT $2 OFFSET $3
+        "
+    ))
+    .bind(&email)
+    .bind(count as i64)
+    .bind((start_index - 1) as i64)
+    .fetch_all(service.db())
+    .await?;
+
+    Ok(ListResponse {
+        schemas: [LIST_RESPONSE_SCHEMA],
+        total_results: total,
+        start_index,
+        items_per_page: users.len() as u64,
+        resources: users
+            .into_iter()
+            .map(|user| user.into_scim(service))
+            .collect(),
+    })
+}
+
+pub async fn get_user(service: &Service, id: &str) -> Result<ScimUser, ScimError> {
+    Ok(fetch_user(service, id).await?.into_scim(service))
+}
+
+/// Creates a user. Their password is random, so they have to reset it before they can log in.
+pub async fn create_user(service: &Service, user: ScimUser) -> Result<ScimUser, ScimError> {
+    let email = Email::from_str(&user.user_name)
+        .map_err(|e| ScimError::invalid_value(format!("userName must be an email: {e}")))?;
+    let role = role_of(&user.roles)?;
+
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = lower($1))")
+            .bind(email.as_str())
+            .fetch_one(service.db())
+            .await?;
+    if exists {
+        return Err(ScimError::uniqueness(format!(
+            "User {email} already exists"
+        )));
+    }
+
+    let password = generate_token::<24>(rand::thread_rng()).map_err(Error::internal)?;
+    service
+        .create_user(email.clone(), password, Some(role), vec![])
+        .await?;
+
+    if !user.active {
+        service.set_user_active(email.as_str(), false).await?;
+    }
+
+    let user: UserRow = sqlx::query_as(&format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE email = $1"
+    ))
+    .bind(email.as_str())
+    .fetch_one(service.db())
+    .await?;
+
+    Ok(user.into_scim(service))
+}
+
+/// Replaces the role and status of a user. Their `userName` can't be changed.
+pub async fn replace_user(
+    service: &Service,
+    id: &str,
+    user: ScimUser,
+) -> Result<ScimUser, ScimError> {
+    let row = fetch_user(service, id).await?;
+    check_user_name(&row, &user.user_name)?;
+
+    update_user(
+        service,
+        &row,
+        Some(role_of(&user.roles)?),
+        Some(user.active),
+    )
+    .await?;
+
+    get_user(service, id).await
+}
+
+/// Applies a `PatchOp` to a user. Only `active` and `roles` can be changed.
+pub async fn patch_user(
+    service: &Service,
+    id: &str,
+    patch: PatchRequest,
+) -> Result<ScimUser, ScimError> {
+    let row = fetch_user(service, id).await?;
+
+    let mut role = None;
+    let mut active = None;
+    for operation in patch.operations {
+        let op = operation.kind()?;
+        let path = operation.path();
+
+        // Without a path, the value holds the attributes to change.
+        let changes = match (path, operation.value) {
+            (Some(path), value) => vec![(path, value)],
+            (None, Some(Value::Object(attributes))) if op != PatchOp::Remove => attributes
+                .into_iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), Some(value)))
+                .collect(),
+            (None, _) => {
+                return Err(ScimError::invalid_syntax(
+                    "operations without a path must have an object value",
+                ))
+            }
+        };
+
+        for (path, value) in changes {
+            match (op, path.as_str()) {
+                (PatchOp::Remove, "active") => {
+                    return Err(ScimError::mutability("active can't be removed"))
+                }
+                (_, "active") => active = Some(patch_bool(value.as_ref())?),
+                (PatchOp::Add, "roles") => {
+                    // Adding the user role to an admin doesn't demote them.
+                    let added = role_of(&patch_values(value)?)?;
+                    if added == Role::Admin {
+                        role = Some(added);
+                    }
+                }
+                (PatchOp::Replace, "roles") => role = Some(role_of(&patch_values(value)?)?),
+                (PatchOp::Remove, "roles") => role = Some(Role::User),
+                (PatchOp::Remove, "username") => {
+                    return Err(ScimError::mutability("userName can't be removed"))
+                }
+                (_, "username") => match value {
+                    Some(Value::String(user_name)) => check_user_name(&row, &user_name)?,
+                    _ => return Err(ScimError::invalid_value("userName must be a string")),
+                },
+                _ => {}
+            }
+        }
+    }
+
+    update_user(service, &row, role, active).await?;
+
+    get_user(service, id).await
+}
+
+/// Deactivates a user, see [`Service::set_user_active`].
+pub async fn delete_user(service: &Service, id: &str) -> Result<(), ScimError> {
+    let row = fetch_user(service, id).await?;
+
+    update_user(service, &row, None, Some(false)).await
+}
+
+fn check_user_name(row: &UserRow, user_name: &str) -> Result<(), ScimError> {
+    if !user_name.eq_ignore_ascii_case(&row.email) {
+        return Err(ScimError::mutability(format!(
+            "userName can't be changed from {} to {user_name}",
+            row.email
+        )));
+    }
+
+    Ok(())
+}
+
+/// Changes the role and status of a user, where they differ from the current ones. The root
+/// user can't be demoted or deactivated, so that NerveMQ can't be locked out of.
+async fn update_user(
+    service: &Service,
+    row: &UserRow,
+    role: Option<Role>,
+    active: Option<bool>,
+) -> Result<(), ScimError> {
+    let role = role.filter(|role| *role != row.role);
+    let active = active.filter(|active| *active != row.active);
+
+    if row.email == service.config().root_email() && (role.is_some() || active.is_some()) {
+        return Err(ScimError::mutability(
+            "the root user can't be demoted or deactivated",
+        ));
+    }
+
+    if let Some(role) = role {
+        service.set_user_role(&row.email, role).await?;
+    }
+    if let Some(active) = active {
+        service.set_user_active(&row.email, active).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, FromRow)]
+struct GroupRow {
+    id: i64,
+    name: String,
+}
+
+#[derive(Debug, FromRow)]
+struct MemberRow {
+    id: i64,
+    email: String,
+}
+
+async fn fetch_group(service: &Service, id: &str) -> Result<GroupRow, ScimError> {
+    sqlx::query_as("SELECT id, name FROM namespaces WHERE id = $1")
+        .bind(parse_id("Group", id)?)
+        .fetch_optional(service.db())
+        .await?
+        .ok_or_else(|| ScimError::not_found(format!("Group {id}")))
+}
+
+async fn group_into_scim(service: &Service, group: GroupRow) -> Result<ScimGroup, ScimError> {
+    let members: Vec<MemberRow> = sqlx::query_as(
+        "
+        SELECT u.id, u.email FROM user_permissions p
+        JOIN users u ON p.user = u.id
+        WHERE p.namespace = $1
+        ORDER BY u.id
+        ",
+    )
+    .bind(group.id)
+    .fetch_all(service.db())
+    .await?;
+
+    Ok(ScimGroup {
+        schemas: vec![GROUP_SCHEMA.to_owned()],
+        id: Some(group.id.to_string()),
+        meta: Some(Meta {
+            resource_type: "Group",
+            location: location(service, "Groups", group.id),
+        }),
+        display_name: group.name,
+        members: members
+            .into_iter()
+            .map(|member| ScimMember {
+                value: member.id.to_string(),
+                display: Some(member.email),
+            })
+            .collect(),
+    })
+}
+
+/// Lists groups, optionally filtered by `displayName`.
+pub async fn list_groups(
+    service: &Service,
+    query: &ListQuery,
+) -> Result<ListResponse<ScimGroup>, ScimError> {
+    let name = match query.filter.as_deref().map(parse_filter).transpose()? {
+        Some((attribute, value)) if attribute == "displayname" => Some(value),
+        Some((attribute, _)) => {
+            return Err(ScimError::invalid_filter(format!(
+                "groups can only be filtered by displayName, not {attribute}"
+            )))
+        }
+        None => None,
+    };
+    let (start_index, count) = query.page();
+
+    let total: u64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM namespaces WHERE $1 IS NULL OR name = $1")
+            .bind(&name)
+            .fetch_one(service.db())
+            .await?;
+
+    let groups: Vec<GroupRow> = sqlx::query_as(
+        "
+        SELECT id, name FROM namespaces
+        WHERE $1 IS NULL OR name = $1
+        ORDER BY id
+        LIMIT $2 OFFSET $3
+        ",
+    )
+    .bind(&name)
+    .bind(count as i64)
+    .bind((start_index - 1) as i64)
+    .fetch_all(service.db())
+    .await?;
+
+    let mut resources = Vec::with_capacity(groups.len());
+    for group in groups {
+        resources.push(group_into_scim(service, group).await?);
+    }
+
+    Ok(ListResponse {
+        schemas: [LIST_RESPONSE_SCHEMA],
+        total_results: total,
+        start_index,
+        items_per_page: resources.len() as u64,
+        resources,
+    })
+}
+
+pub async fn get_group(service: &Service, id: &str) -> Result<ScimGroup, ScimError> {
+    let group = fetch_group(service, id).await?;
+
+    group_into_scim(service, group).await
+}
+
+/// Creates a namespace, owned by the root user, and grants its members access to it.
+pub async fn create_group(service: &Service, group: ScimGroup) -> Result<ScimGroup, ScimError> {
+    let name = group.display_name.trim();
+    if name.is_empty() {
+        return Err(ScimError::invalid_value("displayName must not be empty"));
+    }
+
+    let mut tx = service.db().begin().await?;
+
+    if service.get_namespace_id(name, &mut tx).await?.is_some() {
+        return Err(ScimError::uniqueness(format!(
+            "Group {name} already exists"
+        )));
+    }
+
+    // A group created concurrently, after the check above, is caught by the insert.
+    let id: Option<i64> = sqlx::query_scalar(
+        "
+        INSERT INTO namespaces (name, created_by)
+        VALUES ($1, (SELECT id FROM users WHERE email = $2))
+        ON CONFLICT (name) DO NOTHING
+        RETURNING id
+        ",
+    )
+    .bind(name)
+    .bind(service.config().root_email())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(id) = id else {
+        return Err(ScimError::uniqueness(format!(
+            "Group {name} already exists"
+        )));
+    };
+
+    let group_row = GroupRow {
+        id,
+        name: name.to_owned(),
+    };
+    set_members(service, &group_row, &member_ids(&group.members)?, &mut tx).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(target: "nervemq::audit", namespace = name, "Namespace created through SCIM");
+
+    group_into_scim(service, group_row).await
+}
+
+/// Replaces the members of a group. Its `displayName` can't be changed.
+pub async fn replace_group(
+    service: &Service,
+    id: &str,
+    group: ScimGroup,
+) -> Result<ScimGroup, ScimError> {
+    let row = fetch_group(service, id).await?;
+    check_display_name(&row, &group.display_name)?;
+
+    let mut tx = service.db().begin().await?;
+    set_members(service, &row, &member_ids(&group.members)?, &mut tx).await?;
+    tx.commit().await?;
+
+    group_into_scim(service, row).await
+}
+
+/// Applies a `PatchOp` to a group. Only the members can be changed.
+pub async fn patch_group(
+    service: &Service,
+    id: &str,
+    patch: PatchRequest,
+) -> Result<ScimGroup, ScimError> {
+    let row = fetch_group(service, id).await?;
+
+    let mut tx = service.db().begin().await?;
+    for operation in patch.operations {
+        let op = operation.kind()?;
+        let path = operation.path();
+
+        // Without a path, the value holds the attributes to change.
+        let changes = match (path, operation.value) {
+            (Some(path), value) => vec![(path, value)],
+            (None, Some(Value::Object(attributes))) if op != PatchOp::Remove => attributes
+                .into_iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), Some(value)))
+                .collect(),
+            (None, _) => {
+                return Err(ScimError::invalid_syntax(
+                    "operations without a path must have an object value",
+                ))
+            }
+        };
+
+        for (path, value) in changes {
+            match (op, path.as_str()) {
+                (PatchOp::Add, "members") => {
+                    for member in member_ids(&patch_values(value)?)? {
+                        add_member(&row, member, &mut tx).await?;
+                    }
+                }
+                (PatchOp::Replace, "members") => {
+                    set_members(service, &row, &member_ids(&patch_values(value)?)?, &mut tx)
+                        .await?;
+                }
+                (PatchOp::Remove, "members") if value.is_none() => {
+                    set_members(service, &row, &BTreeSet::new(), &mut tx).await?;
+                }
+                (PatchOp::Remove, "members") => {
+                    for member in member_ids(&patch_values(value)?)? {
+                        remove_member(service, &row, member, &mut tx).await?;
+                    }
+                }
+                (PatchOp::Remove, path) if path.starts_with("members[") => {
+                    remove_member(service, &row, member_filter(path)?, &mut tx).await?;
+                }
+                (PatchOp::Remove, "displayname") => {
+                    return Err(ScimError::mutability("displayName can't be removed"))
+                }
+                (_, "displayname") => match value {
+                    Some(Value::String(name)) => check_display_name(&row, &name)?,
+                    _ => return Err(ScimError::invalid_value("displayName must be a string")),
+                },
+                _ => {}
+            }
+        }
+    }
+    tx.commit().await?;
+
+    group_into_scim(service, row).await
+}
+
+/// Revokes the access of all members of a group. The namespace itself is kept.
+pub async fn delete_group(service: &Service, id: &str) -> Result<(), ScimError> {
+    let row = fetch_group(service, id).await?;
+
+    let mut tx = service.db().begin().await?;
+    set_members(service, &row, &BTreeSet::new(), &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+fn check_display_name(row: &GroupRow, name: &str) -> Result<(), ScimError> {
+    if name.trim() != row.name {
+        return Err(ScimError::mutability(format!(
+            "displayName can't be changed from {} to {name}",
+            row.name
+        )));
+    }
+
+    Ok(())
+}
+
+fn member_ids(members: &[ScimMember]) -> Result<BTreeSet<i64>, ScimError> {
+    members
+        .iter()
+        .map(|member| {
+            member.value.parse().map_err(|_| {
+                ScimError::invalid_value(format!("member {:?} is not a user", member.value))
+            })
+        })
+        .collect()
+}
+
+/// Parses the member of a path like `members[value eq "42"]`.
+fn member_filter(path: &str) -> Result<i64, ScimError> {
+    let filter = path
+        .strip_prefix("members[")
+        .and_then(|path| path.strip_suffix(']'))
+        .ok_or_else(|| ScimError::invalid_filter(format!("invalid path {path:?}")))?;
+
+    match parse_filter(filter)? {
+        (attribute, value) if attribute == "value" => value
+            .parse()
+            .map_err(|_| ScimError::invalid_value(format!("member {value:?} is not a user"))),
+        (attribute, _) => Err(ScimError::invalid_filter(format!(
+            "members can only be filtered by value, not {attribute}"
+        ))),
+    }
+}
+
+/// Makes exactly the given users members of a group.
+async fn set_members(
+    service: &Service,
+    group: &GroupRow,
+    members: &BTreeSet<i64>,
+    tx: &mut Transaction<'_, Sqlite>,
+) -> Result<(), ScimError> {
+    let current: Vec<i64> =
+        sqlx::query_scalar("SELECT user FROM user_permissions WHERE namespace = $1")
+            .bind(group.id)
+            .fetch_all(&mut **tx)
+            .await?;
+
+    for member in &current {
+        if !members.contains(member) {
+            remove_member(service, group, *member, tx).await?;
+        }
+    }
+    for member in members {
+        if !current.contains(member) {
+            add_member(group, *member, tx).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn add_member(
+    group: &GroupRow,
+    member: i64,
+    tx: &mut Transaction<'_, Sqlite>,
+) -> Result<(), ScimError> {
+    let email: String = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+        .bind(member)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| ScimError::invalid_value(format!("member \"{member}\" is not a user")))?;
+
+    let granted = sqlx::query(
+        "
+        INSERT INTO user_permissions (user, namespace)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        ",
+    )
+    .bind(member)
+    .bind(group.id)
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+
+    if granted > 0 {
+        tracing::info!(
+            target: "nervemq::audit",
+            email,
+            namespace = group.name,
+            "Namespace access granted through SCIM"
+        );
+    }
+
+    Ok(())
+}
+
+async fn remove_member(
+    service: &Service,
+    group: &GroupRow,
+    member: i64,
+    tx: &mut Transaction<'_, Sqlite>,
+) -> Result<(), ScimError> {
+    let email: Option<String> = sqlx::query_scalar(
+        "
+        DELETE FROM user_permissions
+        WHERE user = $1 AND namespace = $2
+        RETURNING (SELECT email FROM users WHERE users.id = user_permissions.user)
+        ",
+    )
+    .bind(member)
+    .bind(group.id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if let Some(email) = email {
+        tracing::info!(
+            target: "nervemq::audit",
+            email,
+            namespace = group.name,
+            "Namespace access revoked through SCIM"
+        );
+        service.revoke_stale_tokens(&email, &mut **tx).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(
+            parse_filter(r#"userName eq "alice@example.com""#).unwrap(),
+            ("username".to_owned(), "alice@example.com".to_owned())
+        );
+        assert_eq!(
+            parse_filter(r#" displayName  EQ "say \"hi\"" "#).unwrap(),
+            ("displayname".to_owned(), "say \"hi\"".to_owned())
+        );
+        assert_eq!(member_filter(r#"members[value eq "42"]"#).unwrap(), 42);
+
+        for filter in [
+            "userName",
+            r#"userName co "alice""#,
+            "userName eq alice",
+            r#"userName eq "alice" and active eq true"#,
+        ] {
+            assert!(parse_filter(filter).is_err(), "{filter}");
+        }
+    }
+
+    #[test]
+    fn test_role_of() {
+        let roles = |values: &[&str]| -> Vec<ScimValue> {
+            values
+                .iter()
+                .map(|value| ScimValue {
+                    value: value.to_string(),
+                })
+                .collect()
+        };
+
+        assert_eq!(role_of(&[]).unwrap(), Role::User);
+        assert_eq!(role_of(&roles(&["user", "Admin"])).unwrap(), Role::Admin);
+        assert!(role_of(&roles(&["owner"])).is_err());
+    }
+}
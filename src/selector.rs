@@ -0,0 +1,372 @@
+//! Message selectors.
+//!
+//! `ReceiveMessage` requests can include a `MessageSelector` (a NerveMQ extension) to only receive
+//! the messages whose attributes match it, for example `type = 'invoice' AND region != 'eu'`.
+//! Messages that don't match stay in the queue for other consumers.
+//!
+//! Selectors compare message attributes with literals using `=`, `!=` (or `<>`), `<`, `<=`, `>`
+//! and `>=`, test whether attributes are set with `IS NULL` and `IS NOT NULL`, and combine
+//! conditions with `AND`, `OR`, `NOT` and parentheses. String literals are single-quoted (`''`
+//! escapes a quote), and comparisons with numbers only match `Number` attributes, compared
+//! numerically.
+//!
+//! A comparison involving an attribute that a message doesn't have is never true, and neither is
+//! its negation, so `NOT type = 'invoice'` doesn't match messages without a `type`.
+//!
+//! Selectors are evaluated by the database, with every attribute name and literal passed as a
+//! query parameter.
+
+use pom::utf8::{call, end, none_of, one_of, seq, sym, Parser};
+
+use crate::error::Error;
+
+/// Maximum length of a selector, in bytes.
+pub const MAX_SELECTOR_LENGTH: usize = 1024;
+
+/// Maximum nesting depth of parentheses in a selector.
+pub const MAX_SELECTOR_DEPTH: usize = 16;
+
+/// A parsed message selector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    /// Compares an attribute with a literal
+    Compare {
+        attribute: String,
+        op: Op,
+        value: Literal,
+    },
+    /// Tests whether an attribute is set (`IS NOT NULL`) or not (`IS NULL`)
+    IsNull {
+        attribute: String,
+        negated: bool,
+    },
+    And(Box<Selector>, Box<Selector>),
+    Or(Box<Selector>, Box<Selector>),
+    Not(Box<Selector>),
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+/// A literal that attributes are compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Number(f64),
+}
+
+impl Selector {
+    /// Parses a selector.
+    ///
+    /// # Errors
+    /// Returns [`Error::InvalidParameter`] if the selector is malformed, too long or too deeply
+    /// nested
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        if input.len() > MAX_SELECTOR_LENGTH {
+            return Err(Error::invalid_parameter(format!(
+                "MessageSelector: must be at most {MAX_SELECTOR_LENGTH} bytes long"
+            )));
+        }
+
+        if nesting_depth(input) > MAX_SELECTOR_DEPTH {
+            return Err(Error::invalid_parameter(format!(
+                "MessageSelector: parentheses can be nested at most {MAX_SELECTOR_DEPTH} deep"
+            )));
+        }
+
+        (space() * expression() - end())
+            .parse_str(input)
+            .map_err(|e| Error::invalid_parameter(format!("MessageSelector: {e}")))
+    }
+
+    /// Builds the SQL condition that selects the messages matching this selector. The condition
+    /// refers to the message as `m`, and reads attribute names and literals from `param`, which
+    /// must be bound to the returned JSON array.
+    pub fn to_sql(&self, param: &str) -> (String, String) {
+        let mut values = Vec::new();
+        let condition = self.write_sql(param, &mut values);
+
+        (condition, serde_json::Value::Array(values).to_string())
+    }
+
+    fn write_sql(&self, param: &str, values: &mut Vec<serde_json::Value>) -> String {
+        let mut push = |value: serde_json::Value| {
+            values.push(value);
+            format!("json_extract({param}, '$[{}]')", values.len() - 1)
+        };
+
+        match self {
+            Selector::Compare {
+                attribute,
+                op,
+                value,
+            } => {
+                let name = push(attribute.as_str().into());
+                let (attribute, value) = match value {
+                    Literal::String(value) => (
+                        format!(
+                            "(SELECT json_extract(CAST(v AS TEXT), '$.StringValue') \
+                             FROM kv_pairs WHERE message = m.id AND k = {name})"
+                        ),
+                        push(value.as_str().into()),
+                    ),
+                    Literal::Number(value) => (
+                        format!(
+                            "(SELECT CAST(json_extract(CAST(v AS TEXT), '$.StringValue') AS REAL) \
+                             FROM kv_pairs WHERE message = m.id AND k = {name} \
+                             AND json_extract(CAST(v AS TEXT), '$.DataType') = 'Number')"
+                        ),
+                        push((*value).into()),
+                    ),
+                };
+
+                format!("{attribute} {} {value}", op.as_sql())
+            }
+            Selector::IsNull { attribute, negated } => {
+                let name = push(attribute.as_str().into());
+                format!(
+                    "{}EXISTS (SELECT 1 FROM kv_pairs WHERE message = m.id AND k = {name})",
+                    if *negated { "" } else { "NOT " }
+                )
+            }
+            Selector::And(lhs, rhs) => format!(
+                "({} AND {})",
+                lhs.write_sql(param, values),
+                rhs.write_sql(param, values)
+            ),
+            Selector::Or(lhs, rhs) => format!(
+                "({} OR {})",
+                lhs.write_sql(param, values),
+                rhs.write_sql(param, values)
+            ),
+            Selector::Not(inner) => format!("(NOT {})", inner.write_sql(param, values)),
+        }
+    }
+}
+
+/// Returns how deeply parentheses are nested in a selector, ignoring string literals.
+fn nesting_depth(input: &str) -> usize {
+    let mut depth = 0usize;
+    let mut max_depth = 0;
+    let mut in_string = false;
+
+    for c in input.chars() {
+        match c {
+            '\'' => in_string = !in_string,
+            '(' if !in_string => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            ')' if !in_string => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    max_depth
+}
+
+fn space<'a>() -> Parser<'a, ()> {
+    one_of(" \r\n\t").repeat(0..).discard()
+}
+
+/// Parser for a case-insensitive keyword, followed by optional whitespace.
+fn keyword<'a>(keyword: &'static str) -> Parser<'a, ()> {
+    (one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ")
+        .repeat(1..)
+        .collect()
+        .convert(move |word: &str| {
+            if word.eq_ignore_ascii_case(keyword) {
+                Ok(())
+            } else {
+                Err(format!("expected {keyword}"))
+            }
+        })
+        - space())
+    .name(keyword)
+}
+
+/// Parser for attribute names, which start with a letter or an underscore.
+fn attribute<'a>() -> Parser<'a, String> {
+    let first = one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_");
+    let rest = one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_.-");
+
+    ((first + rest.repeat(0..)).collect().map(ToOwned::to_owned) - space()).name("attribute")
+}
+
+fn string<'a>() -> Parser<'a, String> {
+    let quote = seq("''").map(|_| '\'');
+    let chars = (quote | none_of("'")).repeat(0..);
+
+    (sym('\'') * chars.map(String::from_iter) - sym('\'')).name("string")
+}
+
+fn number<'a>() -> Parser<'a, f64> {
+    let digits = || one_of("0123456789").repeat(1..);
+
+    (sym('-').opt() + digits() + (sym('.') + digits()).opt())
+        .collect()
+        .convert(str::parse::<f64>)
+        .name("number")
+}
+
+fn literal<'a>() -> Parser<'a, Literal> {
+    ((string().map(Literal::String) | number().map(Literal::Number)) - space()).name("literal")
+}
+
+fn op<'a>() -> Parser<'a, Op> {
+    let op = seq("<=").map(|_| Op::Le)
+        | seq(">=").map(|_| Op::Ge)
+        | seq("<>").map(|_| Op::Ne)
+        | seq("!=").map(|_| Op::Ne)
+        | sym('=').map(|_| Op::Eq)
+        | sym('<').map(|_| Op::Lt)
+        | sym('>').map(|_| Op::Gt);
+
+    (op - space()).name("operator")
+}
+
+fn comparison<'a>() -> Parser<'a, Selector> {
+    let is_null = (attribute() - keyword("IS") + keyword("NOT").opt() - keyword("NULL")).map(
+        |(attribute, not)| Selector::IsNull {
+            attribute,
+            negated: not.is_some(),
+        },
+    );
+
+    let compare =
+        (attribute() + op() + literal()).map(|((attribute, op), value)| Selector::Compare {
+            attribute,
+            op,
+            value,
+        });
+
+    (is_null | compare).name("comparison")
+}
+
+fn primary<'a>() -> Parser<'a, Selector> {
+    let parenthesized = sym('(') * space() * call(expression) - sym(')') - space();
+
+    parenthesized | comparison()
+}
+
+fn unary<'a>() -> Parser<'a, Selector> {
+    (keyword("NOT") * call(unary)).map(|inner| Selector::Not(Box::new(inner))) | primary()
+}
+
+fn conjunction<'a>() -> Parser<'a, Selector> {
+    (unary() + (keyword("AND") * unary()).repeat(0..)).map(|(first, rest)| {
+        rest.into_iter().fold(first, |lhs, rhs| {
+            Selector::And(Box::new(lhs), Box::new(rhs))
+        })
+    })
+}
+
+fn expression<'a>() -> Parser<'a, Selector> {
+    (conjunction() + (keyword("OR") * conjunction()).repeat(0..)).map(|(first, rest)| {
+        rest.into_iter()
+            .fold(first, |lhs, rhs| Selector::Or(Box::new(lhs), Box::new(rhs)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(attribute: &str, op: Op, value: Literal) -> Selector {
+        Selector::Compare {
+            attribute: attribute.to_owned(),
+            op,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Selector::parse("type = 'invoice' and region <> 'eu'").unwrap(),
+            Selector::And(
+                Box::new(compare(
+                    "type",
+                    Op::Eq,
+                    Literal::String("invoice".to_owned())
+                )),
+                Box::new(compare("region", Op::Ne, Literal::String("eu".to_owned()))),
+            )
+        );
+
+        // AND binds tighter than OR.
+        assert_eq!(
+            Selector::parse(" a = 1 OR b >= -2.5 AND NOT c IS NULL ").unwrap(),
+            Selector::Or(
+                Box::new(compare("a", Op::Eq, Literal::Number(1.0))),
+                Box::new(Selector::And(
+                    Box::new(compare("b", Op::Ge, Literal::Number(-2.5))),
+                    Box::new(Selector::Not(Box::new(Selector::IsNull {
+                        attribute: "c".to_owned(),
+                        negated: false,
+                    }))),
+                )),
+            )
+        );
+
+        assert_eq!(
+            Selector::parse("(order.id IS NOT NULL) AND note = 'it''s'").unwrap(),
+            Selector::And(
+                Box::new(Selector::IsNull {
+                    attribute: "order.id".to_owned(),
+                    negated: true,
+                }),
+                Box::new(compare("note", Op::Eq, Literal::String("it's".to_owned()))),
+            )
+        );
+
+        for invalid in [
+            "",
+            "type",
+            "type = invoice",
+            "type = 'invoice",
+            "1 = type",
+            "type = 'a' AND",
+            "(type = 'a'",
+            "type = 'a' OR OR b = 'c'",
+        ] {
+            assert!(Selector::parse(invalid).is_err(), "{invalid}");
+        }
+
+        assert!(Selector::parse(&format!("{}a = 1{}", "(".repeat(17), ")".repeat(17))).is_err());
+        assert!(Selector::parse(&format!("{}a = 1{}", "(".repeat(16), ")".repeat(16))).is_ok());
+    }
+
+    #[test]
+    fn test_to_sql_binds_values() {
+        let selector = Selector::parse("type = 'x''); DROP TABLE messages; --' OR n < 3").unwrap();
+        let (condition, params) = selector.to_sql("$6");
+
+        assert!(!condition.contains("DROP"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&params).unwrap(),
+            serde_json::json!(["type", "x'); DROP TABLE messages; --", "n", 3.0])
+        );
+    }
+}
@@ -0,0 +1,4779 @@
+//! Messages: sending, receiving, acknowledging and deleting them, and what follows them around,
+//! like their history, traces, failure reasons and moves between queues.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use actix_identity::Identity;
+use itertools::Itertools;
+use sqlx::{Acquire, QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use tokio::task::JoinSet;
+use tokio_stream::StreamExt as _;
+
+use crate::{
+    ack::{Outcome, MAX_ACK_PAYLOAD_LENGTH, MAX_ACK_RESULTS},
+    auth::crypto::generate_token,
+    compression::{self, StoredBody},
+    consumer_group::CONSUMER_GROUP_PARAMETER,
+    error::Error,
+    events::Event,
+    history::{HistoryEntry, MAX_HISTORY_PAGE_SIZE},
+    ingest::{IngestMessage, INGEST_CHUNK_SIZE},
+    message::{self, Message, MessageFailure, MAX_FAILURE_REASON_LENGTH, MAX_RECORDED_FAILURES},
+    message_move::{self, MessageMoveTask, MoveTaskStatus, RunningMoveTask},
+    outbox::{self, CreateOutboxSourceRequest, OutboxRow, OutboxSource},
+    push::PushTarget,
+    redelivery,
+    report::{self, ReportKind, ReportRow},
+    sample::{self, MessageSample, MASKED_VALUE, MAX_SAMPLE_SIZE},
+    sqs::{
+        checksum, limits,
+        types::{SqsMessage, SqsMessageAttribute},
+    },
+    telemetry::TelemetryEvent,
+    trace::{self, Trace, TraceEvent, MAX_TRACE_EVENTS, TRACE_ID_ATTRIBUTE},
+    types::{
+        send_message::{SendMessageRequest, SendMessageResponse},
+        send_message_batch::{
+            SendMessageBatchRequest, SendMessageBatchResponse, SendMessageBatchResultEntry,
+            SendMessageBatchResultErrorEntry,
+        },
+    },
+};
+
+use super::{
+    fair_group_key, json_string, queue_attributes, redelivery_delay, selector_condition,
+    MessageDetails, ReceiveOptions, Service, DEDUPLICATION_INTERVAL, DEFAULT_VISIBILITY_TIMEOUT,
+    MAX_VISIBILITY_EXTENSION_BATCH, MAX_VISIBILITY_TIMEOUT, PURGE_COOLDOWN, RECEIVE_POLL_INTERVAL,
+};
+
+/// Operations on the messages of queues, see [`Service::messages`].
+#[derive(Clone)]
+pub struct MessageService<'a> {
+    service: &'a Service,
+}
+
+impl MessageService<'_> {
+    pub(super) fn new(service: &Service) -> MessageService<'_> {
+        MessageService { service }
+    }
+
+    /// Announces a committed message on the event bus and to the telemetry sink.
+    fn message_sent(&self, queue: u64, message: u64) {
+        self.service
+            .events
+            .publish(Event::MessageSent { queue, message });
+        self.service
+            .telemetry
+            .record(&TelemetryEvent::MessageSent { queue, message });
+    }
+
+    /// Starts moving the messages in a dead-letter queue, see [`crate::message_move`].
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the dead-letter queue
+    /// * `queue` - Name of the dead-letter queue
+    /// * `destination` - Namespace and name of the queue to move the messages to, or `None` to
+    ///   move each message back to the queue it was dead-lettered from
+    /// * `max_per_second` - Maximum number of messages moved per second
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Returns
+    /// The handle of the task
+    ///
+    /// # Errors
+    /// * `Error::InvalidParameter` - If the queue isn't a dead-letter queue, is also the
+    ///   destination, or already has a running task, or the rate is out of range
+    pub async fn start_message_move_task(
+        &self,
+        ns: &str,
+        queue: &str,
+        destination: Option<(&str, &str)>,
+        max_per_second: Option<u64>,
+        identity: Identity,
+    ) -> Result<String, Error> {
+        self.service.ensure_available()?;
+
+        if let Some(max_per_second) = max_per_second {
+            if !(1..=message_move::MAX_MESSAGES_PER_SECOND).contains(&max_per_second) {
+                return Err(Error::invalid_parameter(format!(
+                    "MaxNumberOfMessagesPerSecond: must be between 1 and {}",
+                    message_move::MAX_MESSAGES_PER_SECOND
+                )));
+            }
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        let ns_id = self
+            .service
+            .get_namespace_id(ns, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(ns))?;
+        self.service
+            .check_user_access(&identity, ns_id, &mut *tx)
+            .await?;
+
+        let source_id = self
+            .service
+            .get_queue_id(ns, queue, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, ns))?;
+
+        let is_dead_letter_queue: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM queue_configurations WHERE dead_letter_queue = $1)",
+        )
+        .bind(source_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !is_dead_letter_queue {
+            return Err(Error::invalid_parameter(format!(
+                "SourceArn: {ns}:{queue} is not a dead-letter queue"
+            )));
+        }
+
+        let destination_id = match destination {
+            Some((dest_ns, dest_queue)) => {
+                let dest_ns_id = self
+                    .service
+                    .get_namespace_id(dest_ns, &mut *tx)
+                    .await?
+                    .ok_or_else(|| Error::namespace_not_found(dest_ns))?;
+                self.service
+                    .check_user_access(&identity, dest_ns_id, &mut *tx)
+                    .await?;
+
+                let dest_id = self
+                    .service
+                    .get_queue_id(dest_ns, dest_queue, &mut *tx)
+                    .await?
+                    .ok_or_else(|| Error::queue_not_found(dest_queue, dest_ns))?;
+                if dest_id == source_id {
+                    return Err(Error::invalid_parameter(
+                        "DestinationArn: must not be the source queue",
+                    ));
+                }
+                Some(dest_id)
+            }
+            None => None,
+        };
+
+        let running: bool = sqlx::query_scalar(
+            "
+            SELECT EXISTS (
+                SELECT 1 FROM message_move_tasks WHERE source = $1 AND status = 'running'
+            )
+            ",
+        )
+        .bind(source_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if running {
+            return Err(Error::invalid_parameter(format!(
+                "SourceArn: {ns}:{queue} already has a running message move task"
+            )));
+        }
+
+        let handle = generate_token::<16>(rand::thread_rng())?;
+
+        sqlx::query(
+            "
+            INSERT INTO message_move_tasks
+                (handle, source, destination, max_per_second, to_move, last_message, started_at)
+            SELECT $1, $2, $3, $4, COUNT(*), IFNULL(MAX(id), 0), $5
+            FROM messages WHERE queue = $2
+            ",
+        )
+        .bind(&handle)
+        .bind(source_id as i64)
+        .bind(destination_id.map(|id| id as i64))
+        .bind(max_per_second.map(|max| max as i64))
+        .bind(self.service.now())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace = ns,
+            queue,
+            destination = ?destination,
+            handle,
+            "Message move task started"
+        );
+
+        Ok(handle)
+    }
+
+    /// Cancels a running message move task. Messages that were already moved stay where they
+    /// are.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the task's dead-letter queue
+    /// * `handle` - Handle of the task
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Returns
+    /// The number of messages the task moved
+    ///
+    /// # Errors
+    /// * `Error::NotFound` - If the namespace has no such task
+    /// * `Error::InvalidParameter` - If the task isn't running
+    pub async fn cancel_message_move_task(
+        &self,
+        ns: &str,
+        handle: &str,
+        identity: Identity,
+    ) -> Result<u64, Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let ns_id = self
+            .service
+            .get_namespace_id(ns, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(ns))?;
+        self.service
+            .check_user_access(&identity, ns_id, &mut *tx)
+            .await?;
+
+        let task: Option<(MoveTaskStatus, u64)> = sqlx::query_as(
+            "
+            SELECT t.status, t.moved FROM message_move_tasks t
+            JOIN queues q ON q.id = t.source
+            WHERE t.handle = $1 AND q.ns = $2
+            ",
+        )
+        .bind(handle)
+        .bind(ns_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let moved = match task {
+            Some((MoveTaskStatus::Running, moved)) => moved,
+            Some(_) => {
+                return Err(Error::invalid_parameter(format!(
+                    "TaskHandle: task {handle} is not running"
+                )));
+            }
+            None => return Err(Error::not_found(format!("message move task {handle}"))),
+        };
+
+        sqlx::query("UPDATE message_move_tasks SET status = 'cancelled' WHERE handle = $1")
+            .bind(handle)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace = ns,
+            handle,
+            moved,
+            "Message move task cancelled"
+        );
+
+        Ok(moved)
+    }
+
+    /// Lists the message move tasks of a dead-letter queue, most recent first.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the dead-letter queue
+    /// * `queue` - Name of the dead-letter queue
+    /// * `limit` - Maximum number of tasks to list
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_message_move_tasks(
+        &self,
+        ns: &str,
+        queue: &str,
+        limit: u64,
+        identity: Identity,
+    ) -> Result<Vec<MessageMoveTask>, Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        let ns_id = self
+            .service
+            .get_namespace_id(ns, &mut *db)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(ns))?;
+        self.service
+            .check_user_access(&identity, ns_id, &mut *db)
+            .await?;
+
+        let source_id = self
+            .service
+            .get_queue_id(ns, queue, &mut *db)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, ns))?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                t.handle,
+                sn.name AS source_namespace,
+                sq.name AS source,
+                dn.name AS destination_namespace,
+                dq.name AS destination,
+                t.max_per_second,
+                t.status,
+                t.moved,
+                t.to_move,
+                t.failure_reason,
+                t.started_at
+            FROM message_move_tasks t
+            JOIN queues sq ON sq.id = t.source
+            JOIN namespaces sn ON sn.id = sq.ns
+            LEFT JOIN queues dq ON dq.id = t.destination
+            LEFT JOIN namespaces dn ON dn.id = dq.ns
+            WHERE t.source = $1
+            ORDER BY t.id DESC
+            LIMIT $2
+            ",
+        )
+        .bind(source_id as i64)
+        .bind(limit as i64)
+        .fetch_all(&mut *db)
+        .await?)
+    }
+
+    /// Moves the next batch of messages of every running message move task, and finishes the
+    /// tasks that have none left.
+    ///
+    /// # Returns
+    /// The number of messages moved
+    pub async fn run_message_move_tasks(&self) -> Result<u64, Error> {
+        let tasks: Vec<RunningMoveTask> = sqlx::query_as(
+            "
+            SELECT id, source, destination, max_per_second, last_message
+            FROM message_move_tasks WHERE status = 'running'
+            ",
+        )
+        .fetch_all(self.service.db())
+        .await?;
+
+        let mut moved = 0;
+        for task in tasks {
+            moved += self.move_messages(&task).await?;
+        }
+
+        Ok(moved)
+    }
+
+    /// Moves the next of the messages a message move task has left to move, up to its rate.
+    ///
+    /// # Returns
+    /// The number of messages moved
+    pub(super) async fn move_messages(&self, task: &RunningMoveTask) -> Result<u64, Error> {
+        let limit = task
+            .max_per_second
+            .unwrap_or(message_move::MAX_MESSAGES_PER_SECOND);
+        let mut tx = self.service.db().begin().await?;
+
+        let ids: Vec<u64> = sqlx::query_scalar(
+            "
+            SELECT id FROM messages
+            WHERE queue = $1 AND id <= $2
+            AND ($3 IS NOT NULL OR dead_lettered_from IS NOT NULL)
+            ORDER BY id
+            LIMIT $4
+            ",
+        )
+        .bind(task.source as i64)
+        .bind(task.last_message as i64)
+        .bind(task.destination.map(|id| id as i64))
+        .bind(limit as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if ids.is_empty() {
+            let stranded: u64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE queue = $1 AND id <= $2")
+                    .bind(task.source as i64)
+                    .bind(task.last_message as i64)
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+            let (status, reason) = match stranded {
+                0 => (MoveTaskStatus::Completed, None),
+                n => (
+                    MoveTaskStatus::Failed,
+                    Some(format!(
+                        "{n} messages have no known source queue, move them with a DestinationArn"
+                    )),
+                ),
+            };
+
+            sqlx::query(
+                "
+                UPDATE message_move_tasks SET status = $2, failure_reason = $3
+                WHERE id = $1 AND status = 'running'
+                ",
+            )
+            .bind(task.id as i64)
+            .bind(status)
+            .bind(reason)
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+            return Ok(0);
+        }
+
+        // A task cancelled since it was picked up moves nothing more.
+        let running = sqlx::query(
+            "
+            UPDATE message_move_tasks SET moved = moved + $2
+            WHERE id = $1 AND status = 'running'
+            ",
+        )
+        .bind(task.id as i64)
+        .bind(ids.len() as i64)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if running == 0 {
+            return Ok(0);
+        }
+
+        sqlx::query(
+            "
+            UPDATE messages
+            SET
+                queue = COALESCE($3, dead_lettered_from),
+                tries = 0,
+                delivered_at = NULL,
+                visible_at = NULL,
+                dead_lettered_from = NULL
+            WHERE queue = $1 AND id IN (SELECT value FROM json_each($2))
+            ",
+        )
+        .bind(task.source as i64)
+        .bind(serde_json::to_string(&ids)?)
+        .bind(task.destination.map(|id| id as i64))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(ids.len() as u64)
+    }
+
+    /// Lists every push subscription, with the queue it belongs to.
+    pub async fn list_push_targets(&self) -> Result<Vec<PushTarget>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT p.*, q.id AS queue_id, ns.name AS namespace, q.name AS queue
+            FROM push_subscriptions p
+            JOIN queues q ON q.id = p.queue
+            JOIN namespaces ns ON ns.id = q.ns
+            ORDER BY p.id
+            ",
+        )
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Deletes a message its push subscription's webhook accepted.
+    ///
+    /// # Arguments
+    /// * `queue_id` - ID of the queue
+    /// * `message_id` - ID of the message
+    pub async fn complete_push(&self, queue_id: u64, message_id: u64) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+        let removed = self.remove_message(queue_id, message_id, &mut tx).await?;
+        tx.commit().await?;
+
+        if removed {
+            self.service.events.publish(Event::MessageDeleted {
+                queue: queue_id,
+                message: message_id,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Makes a message its push subscription's webhook failed receivable again after `backoff`,
+    /// recording why it failed.
+    ///
+    /// # Arguments
+    /// * `queue_id` - ID of the queue
+    /// * `message_id` - ID of the message
+    /// * `backoff` - How long before the message is pushed again
+    /// * `reason` - Why the delivery failed
+    pub async fn fail_push(
+        &self,
+        queue_id: u64,
+        message_id: u64,
+        backoff: Duration,
+        reason: &str,
+    ) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let changed = self
+            .hide_in_flight(
+                queue_id,
+                None,
+                &BTreeSet::from([message_id]),
+                backoff,
+                &mut tx,
+            )
+            .await?;
+        for (message_id, _, tries) in changed {
+            self.record_failure(message_id, reason, None, tries, &mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Checks that `count` more messages, of `bytes` bytes in total as stored, fit within the
+    /// limits of a queue's namespace.
+    ///
+    /// # Errors
+    /// * `Error::OverLimit` - If the queue would have too many messages, or the namespace would
+    ///   store too many bytes
+    pub(super) async fn check_message_limits(
+        &self,
+        queue: u64,
+        count: u64,
+        bytes: u64,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), Error> {
+        let ns_id: u64 = sqlx::query_scalar("SELECT ns FROM queues WHERE id = $1")
+            .bind(queue as i64)
+            .fetch_one(&mut *conn)
+            .await?;
+        let limits = self.service.get_namespace_limits(ns_id, &mut *conn).await?;
+
+        // Both counts are kept in `queue_usage` by triggers on `messages`, so neither has to count
+        // messages.
+        if let Some(max_messages) = limits.max_messages_per_queue {
+            let messages: u64 = sqlx::query_scalar(
+                "SELECT COALESCE(MAX(message_count), 0) FROM queue_usage WHERE queue = $1",
+            )
+            .bind(queue as i64)
+            .fetch_one(&mut *conn)
+            .await?;
+            if messages + count > max_messages {
+                return Err(Error::OverLimit {
+                    resource: "messages in the queue".to_owned(),
+                    limit: max_messages,
+                });
+            }
+        }
+
+        if let Some(max_bytes) = limits.max_storage_bytes {
+            let stored: u64 = sqlx::query_scalar(
+                "
+                SELECT COALESCE(SUM(u.message_bytes), 0) FROM queue_usage u
+                JOIN queues q ON q.id = u.queue
+                WHERE q.ns = $1
+                ",
+            )
+            .bind(ns_id as i64)
+            .fetch_one(&mut *conn)
+            .await?;
+            if stored + bytes > max_bytes {
+                return Err(Error::OverLimit {
+                    resource: "bytes of messages in the namespace".to_owned(),
+                    limit: max_bytes,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a single message to a queue.
+    pub async fn sqs_send(
+        &self,
+        queue: u64,
+        req: SendMessageRequest,
+    ) -> Result<SendMessageResponse, Error> {
+        self.service.ensure_available()?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        let (message, res) = self.sqs_send_internal(queue, req, &mut tx).await?;
+
+        tx.commit().await?;
+
+        if let Some(message) = message {
+            self.message_sent(queue, message);
+        }
+
+        Ok(res)
+    }
+
+    pub(super) async fn sqs_send_internal(
+        &self,
+        queue: u64,
+        req: SendMessageRequest,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<(Option<u64>, SendMessageResponse), Error> {
+        checksum::verify_message_body(req.md5_of_message_body.as_deref(), &req.message_body)?;
+
+        let max_size = self
+            .service
+            .get_queue_attribute(queue, queue_attributes::MaxMessageSize)
+            .await?
+            .unwrap_or(limits::MAX_MESSAGE_SIZE);
+        limits::validate_message(&req.message_body, &req.message_attributes, max_size)?;
+
+        if let Some(deduplication_id) = &req.message_deduplication_id {
+            limits::validate_deduplication_id(deduplication_id)?;
+        }
+
+        // Attributes are digested in order of their names, as SQS clients expect.
+        let attributes = req
+            .message_attributes
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .collect::<Vec<_>>();
+        let mut attr_bytes_to_digest = Vec::new();
+        for (k, v) in &attributes {
+            v.serialize_into(k, &mut attr_bytes_to_digest);
+        }
+        let response = |message_id: String| SendMessageResponse {
+            // Message IDs increase with every send, so they order the messages of a group.
+            sequence_number: req
+                .message_group_id
+                .as_ref()
+                .and_then(|_| message::sequence_number(&message_id)),
+            message_id,
+            md5_of_message_body: checksum::md5_hex(&req.message_body),
+            md5_of_message_attributes: checksum::md5_hex(&attr_bytes_to_digest),
+            // md5_of_message_system_attributes: hex::encode(md5::compute(b"").as_ref()),
+        };
+
+        let now = self.service.now();
+        // A savepoint when sending a batch, so that an entry that fails leaves nothing behind.
+        let mut tx = exec.begin().await?;
+
+        // A message sent again with the same deduplication ID, like a batch retried after its
+        // response was lost, isn't sent twice.
+        if let Some(deduplication_id) = &req.message_deduplication_id {
+            sqlx::query("DELETE FROM message_deduplication WHERE queue = $1 AND expires_at <= $2")
+                .bind(queue as i64)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+
+            let sent: Option<String> = sqlx::query_scalar(
+                "
+                SELECT message_id FROM message_deduplication
+                WHERE queue = $1 AND deduplication_id = $2
+                ",
+            )
+            .bind(queue as i64)
+            .bind(deduplication_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if let Some(message_id) = sent {
+                tx.commit().await?;
+                return Ok((None, response(message_id)));
+            }
+        }
+
+        let compression_threshold = self
+            .service
+            .get_queue_attribute(queue, queue_attributes::CompressionThreshold)
+            .await?;
+        let body = StoredBody::new(&req.message_body, compression_threshold)?;
+        let compressed = body.is_compressed();
+        let message_id = message::new_message_id();
+
+        self.check_message_limits(queue, 1, body.stored_len() as u64, &mut tx)
+            .await?;
+
+        let msg_id: u64 = sqlx::query_scalar(
+            "
+            INSERT INTO messages (queue, message_id, body, compressed, group_id, trace_id, sent_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id
+            ",
+        )
+        .bind(queue as i64)
+        .bind(&message_id)
+        .bind(body)
+        .bind(compressed)
+        .bind(&req.message_group_id)
+        .bind(trace::new_trace_id())
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.record_trace_event(TraceEvent::Sent, queue, Some(&[msg_id]), &mut tx)
+            .await?;
+
+        for (k, v) in &attributes {
+            sqlx::query("INSERT INTO kv_pairs (message, k, v) VALUES ($1, $2, $3)")
+                .bind(msg_id as i64)
+                .bind(k)
+                .bind(serde_json::to_vec(v).map_err(Error::internal)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        if let Some(deduplication_id) = &req.message_deduplication_id {
+            let inserted = sqlx::query(
+                "
+                INSERT INTO message_deduplication
+                    (queue, deduplication_id, message_id, message, expires_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (queue, deduplication_id) DO NOTHING
+                ",
+            )
+            .bind(queue as i64)
+            .bind(deduplication_id)
+            .bind(&message_id)
+            .bind(msg_id as i64)
+            .bind(now + DEDUPLICATION_INTERVAL.as_secs() as i64)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+
+            // Another send with the same ID got there first, since the check above. Its message
+            // is the one that counts, and this one is rolled back.
+            if !inserted {
+                let message_id: String = sqlx::query_scalar(
+                    "
+                    SELECT message_id FROM message_deduplication
+                    WHERE queue = $1 AND deduplication_id = $2
+                    ",
+                )
+                .bind(queue as i64)
+                .bind(deduplication_id)
+                .fetch_one(&mut *tx)
+                .await?;
+                tx.rollback().await?;
+                return Ok((None, response(message_id)));
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok((Some(msg_id), response(message_id)))
+    }
+
+    /// Sends multiple messages to a queue in one operation.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `messages` - Vector of (message body, attributes) pairs
+    ///
+    /// # Errors
+    /// * `Error::BatchRequestTooLong` - If the messages together exceed
+    ///   [`limits::MAX_BATCH_SIZE`]. Messages that exceed the queue's `MaximumMessageSize` fail
+    ///   on their own, as failed entries.
+    #[allow(unused)]
+    pub async fn sqs_send_batch(
+        &self,
+        namespace_name: &str,
+        queue_name: &str,
+        req: SendMessageBatchRequest,
+    ) -> Result<SendMessageBatchResponse, Error> {
+        self.service.ensure_available()?;
+
+        limits::validate_batch_size(
+            req.entries
+                .iter()
+                .map(|entry| limits::message_size(&entry.message_body, &entry.message_attributes)),
+        )?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        let queue_id = self
+            .service
+            .get_queue_id(namespace_name, queue_name, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue_name, namespace_name))?;
+
+        let mut successful = Vec::new();
+        let mut failed = Vec::new();
+        let mut sent = Vec::new();
+
+        for entry in req.entries {
+            let message_attributes = entry.message_attributes;
+            let message_body = entry.message_body;
+
+            match self
+                .sqs_send_internal(
+                    queue_id,
+                    SendMessageRequest {
+                        queue_url: req.queue_url.clone(),
+                        message_body,
+                        delay_seconds: entry.delay_seconds,
+                        message_attributes,
+                        message_deduplication_id: entry.message_deduplication_id,
+                        message_group_id: entry.message_group_id,
+                        md5_of_message_body: entry.md5_of_message_body,
+                    },
+                    &mut *tx,
+                )
+                .await
+            {
+                Ok((message, res)) => {
+                    sent.extend(message);
+                    successful.push(SendMessageBatchResultEntry {
+                        id: entry.id,
+                        message_id: res.message_id,
+                        md5_of_message_body: res.md5_of_message_body,
+                        md5_of_message_attributes: res.md5_of_message_attributes,
+                        // md5_of_message_system_attributes: res.md5_of_message_system_attributes,
+                        sequence_number: res.sequence_number,
+                    });
+                }
+                Err(e) => {
+                    failed.push(SendMessageBatchResultErrorEntry {
+                        id: entry.id,
+                        sender_fault: e.is_sender_fault(),
+                        code: e.sqs_code().to_owned(),
+                        message: Some(e.to_string()),
+                    });
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        for message in sent {
+            self.message_sent(queue_id, message);
+        }
+
+        Ok(SendMessageBatchResponse { successful, failed })
+    }
+
+    /// Sends many messages to a queue in one transaction, using multi-row inserts.
+    ///
+    /// # Arguments
+    /// * `queue` - Queue ID
+    /// * `messages` - Messages to send
+    ///
+    /// # Returns
+    /// The message IDs of the sent messages, in the order they were given
+    pub async fn ingest_messages(
+        &self,
+        queue: u64,
+        messages: Vec<IngestMessage>,
+    ) -> Result<Vec<String>, Error> {
+        self.service.ensure_available()?;
+
+        let compression_threshold = self
+            .service
+            .get_queue_attribute(queue, queue_attributes::CompressionThreshold)
+            .await?;
+
+        let now = self.service.now();
+        let max_size = self
+            .service
+            .get_queue_attribute(queue, queue_attributes::MaxMessageSize)
+            .await?
+            .unwrap_or(limits::MAX_MESSAGE_SIZE);
+        for message in &messages {
+            limits::validate_message(&message.body, &message.attributes, max_size)?;
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        let bodies = messages
+            .iter()
+            .map(|message| StoredBody::new(&message.body, compression_threshold))
+            .collect::<Result<Vec<_>, _>>()?;
+        let bytes = bodies.iter().map(|body| body.stored_len() as u64).sum();
+        self.check_message_limits(queue, messages.len() as u64, bytes, &mut tx)
+            .await?;
+
+        let mut bodies = bodies.into_iter();
+        let mut ids = Vec::with_capacity(messages.len());
+        let mut message_ids = Vec::with_capacity(messages.len());
+        for chunk in messages.chunks(INGEST_CHUNK_SIZE) {
+            let bodies = bodies.by_ref().take(chunk.len()).collect::<Vec<_>>();
+            let chunk_message_ids = chunk
+                .iter()
+                .map(|_| message::new_message_id())
+                .collect::<Vec<_>>();
+
+            let mut insert = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO messages (queue, message_id, body, compressed, group_id, trace_id, sent_at) ",
+            );
+            let rows = chunk.iter().zip(&chunk_message_ids).zip(bodies);
+            insert.push_values(rows, |mut row, ((message, message_id), body)| {
+                let compressed = body.is_compressed();
+                row.push_bind(queue as i64)
+                    .push_bind(message_id)
+                    .push_bind(body)
+                    .push_bind(compressed)
+                    .push_bind(&message.group_id)
+                    .push_bind(trace::new_trace_id())
+                    .push_bind(now);
+            });
+            insert.push(" RETURNING id");
+
+            // RETURNING yields rows in no particular order, but rows inserted by a single
+            // statement get ascending ids, so sorting recovers the order of the chunk.
+            let mut chunk_ids = insert
+                .build_query_scalar::<u64>()
+                .fetch_all(&mut *tx)
+                .await?;
+            chunk_ids.sort_unstable();
+
+            let attributes = chunk_ids
+                .iter()
+                .zip(chunk)
+                .flat_map(|(id, message)| message.attributes.iter().map(move |kv| (*id, kv)))
+                .map(|(id, (k, v))| Ok((id, k, serde_json::to_vec(v).map_err(Error::internal)?)))
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            for attributes in attributes.chunks(INGEST_CHUNK_SIZE) {
+                let mut insert =
+                    QueryBuilder::<Sqlite>::new("INSERT INTO kv_pairs (message, k, v) ");
+                insert.push_values(attributes, |mut row, (id, k, v)| {
+                    row.push_bind(*id as i64).push_bind(*k).push_bind(v);
+                });
+                insert.build().execute(&mut *tx).await?;
+            }
+
+            ids.extend(chunk_ids);
+            message_ids.extend(chunk_message_ids);
+        }
+
+        for chunk in ids.chunks(INGEST_CHUNK_SIZE) {
+            self.record_trace_event(TraceEvent::Sent, queue, Some(chunk), &mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        for message in &ids {
+            self.message_sent(queue, *message);
+        }
+
+        Ok(message_ids)
+    }
+
+    /// Registers an outbox table to be tailed into a queue.
+    ///
+    /// The table is read starting after `high_water_mark`, so existing rows can be skipped by
+    /// passing the current largest id.
+    ///
+    /// # Arguments
+    /// * `request` - Definition of the outbox table and the queue to publish into
+    /// * `identity` - Identity of the authenticated admin user
+    pub async fn create_outbox_source(
+        &self,
+        request: CreateOutboxSourceRequest,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        outbox::validate_source_url(&request.source_url)?;
+        outbox::validate_identifier("table_name", &request.table_name)?;
+        outbox::validate_identifier("id_column", &request.id_column)?;
+        outbox::validate_identifier("body_column", &request.body_column)?;
+
+        let batch_size = request.batch_size.unwrap_or(outbox::DEFAULT_BATCH_SIZE);
+        if batch_size == 0 {
+            return Err(Error::invalid_parameter("batch_size: must be at least 1"));
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        let queue_id = self
+            .service
+            .get_queue_id(&request.namespace, &request.queue, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(&request.queue, &request.namespace))?;
+
+        sqlx::query(
+            "
+            INSERT INTO outbox_sources (
+                name, queue, source_url, table_name, id_column, body_column, batch_size,
+                high_water_mark, created_by
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, (SELECT id FROM users WHERE email = $9))
+            ",
+        )
+        .bind(&request.name)
+        .bind(queue_id as i64)
+        .bind(&request.source_url)
+        .bind(&request.table_name)
+        .bind(&request.id_column)
+        .bind(&request.body_column)
+        .bind(batch_size as i64)
+        .bind(request.high_water_mark.unwrap_or(0) as i64)
+        .bind(identity.id()?)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Lists all outbox sources.
+    pub async fn list_outbox_sources(&self) -> Result<Vec<OutboxSource>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                o.id,
+                o.name,
+                n.name AS namespace,
+                q.name AS queue,
+                q.id AS queue_id,
+                o.source_url,
+                o.table_name,
+                o.id_column,
+                o.body_column,
+                o.batch_size,
+                o.high_water_mark
+            FROM outbox_sources o
+            JOIN queues q ON q.id = o.queue
+            JOIN namespaces n ON n.id = q.ns
+            ORDER BY o.name
+            ",
+        )
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Removes an outbox source. Messages that were already published are kept.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the outbox source
+    pub async fn delete_outbox_source(&self, name: &str) -> Result<(), Error> {
+        let res = sqlx::query("DELETE FROM outbox_sources WHERE name = $1")
+            .bind(name)
+            .execute(self.service.db())
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::not_found(format!("outbox source {name}")));
+        }
+
+        Ok(())
+    }
+
+    /// Publishes rows read from an outbox table and advances the source's high-water mark.
+    ///
+    /// The mark is only advanced if it has not moved since `source` was read, and the messages
+    /// are inserted in the same transaction, so each row is published exactly once.
+    ///
+    /// # Returns
+    /// `false` if the rows were discarded because the mark had already moved.
+    pub async fn ingest_outbox_rows(
+        &self,
+        source: &OutboxSource,
+        rows: Vec<OutboxRow>,
+    ) -> Result<bool, Error> {
+        let Some(high_water_mark) = rows.iter().map(|row| row.id).max() else {
+            return Ok(true);
+        };
+
+        let mut tx = self.service.db().begin().await?;
+
+        let res = sqlx::query(
+            "
+            UPDATE outbox_sources
+            SET high_water_mark = $1
+            WHERE id = $2 AND high_water_mark = $3
+            ",
+        )
+        .bind(high_water_mark)
+        .bind(source.id as i64)
+        .bind(source.high_water_mark as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let compression_threshold = self
+            .service
+            .get_queue_attribute(source.queue_id, queue_attributes::CompressionThreshold)
+            .await?;
+
+        let mut messages = Vec::with_capacity(rows.len());
+        for row in rows {
+            let body = StoredBody::new(&row.body, compression_threshold)?;
+            let compressed = body.is_compressed();
+
+            let message: u64 = sqlx::query_scalar(
+                "
+                INSERT INTO messages (queue, message_id, body, compressed, trace_id, sent_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING id
+                ",
+            )
+            .bind(source.queue_id as i64)
+            .bind(message::new_message_id())
+            .bind(body)
+            .bind(compressed)
+            .bind(trace::new_trace_id())
+            .bind(self.service.now())
+            .fetch_one(&mut *tx)
+            .await?;
+
+            messages.push(message);
+        }
+
+        self.record_trace_event(TraceEvent::Sent, source.queue_id, Some(&messages), &mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        for message in messages {
+            self.message_sent(source.queue_id, message);
+        }
+
+        Ok(true)
+    }
+
+    /// Receives a single message from a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `options` - Receive options; `max_messages` is ignored
+    #[allow(unused)]
+    pub async fn sqs_recv(
+        &self,
+        namespace: &str,
+        queue: &str,
+        options: ReceiveOptions,
+    ) -> Result<Option<SqsMessage>, Error> {
+        let options = ReceiveOptions {
+            max_messages: 1,
+            ..options
+        };
+
+        Ok(self
+            .sqs_recv_batch(namespace, queue, options)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Receives multiple messages from a queue in one operation.
+    ///
+    /// Received messages are hidden from other consumers until their visibility timeout expires,
+    /// after which they are delivered again unless they have been deleted or have used up the
+    /// queue's retries. If the queue is empty, waits up to the configured wait time for messages.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `options` - Receive options
+    pub async fn sqs_recv_batch(
+        &self,
+        namespace: &str,
+        queue: &str,
+        options: ReceiveOptions,
+    ) -> Result<Vec<SqsMessage>, Error> {
+        self.service.ensure_available()?;
+
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let consumer_group = match &options.consumer_group {
+            Some(name) => Some(
+                self.service
+                    .get_consumer_group_id(queue_id, queue, name)
+                    .await?,
+            ),
+            None if self.service.has_consumer_groups(queue_id).await? => {
+                return Err(Error::invalid_parameter(format!(
+                    "QueueUrl: {queue} has consumer groups, select one with the \
+                     {CONSUMER_GROUP_PARAMETER} parameter"
+                )));
+            }
+            None => None,
+        };
+
+        let visibility_timeout = match options.visibility_timeout {
+            Some(timeout) => timeout,
+            None => self.visibility_timeout(queue_id).await?,
+        };
+
+        let wait_time = match options.wait_time {
+            Some(wait_time) => wait_time,
+            None => self
+                .service
+                .get_queue_attribute(queue_id, queue_attributes::ReceiveMessageWaitTimeSeconds)
+                .await?
+                .map(Duration::from_secs)
+                .unwrap_or_default(),
+        };
+
+        let max_in_flight = self
+            .service
+            .get_queue_attribute(queue_id, queue_attributes::MaxConcurrentReceives)
+            .await?
+            .filter(|max| *max > 0);
+
+        let fair_receive_key = self
+            .service
+            .get_queue_text_attribute(queue_id, queue_attributes::FairReceiveKey)
+            .await?
+            .filter(|key| !key.is_empty());
+
+        let redelivery_delays = self
+            .service
+            .get_queue_text_attribute(queue_id, queue_attributes::RedeliveryBackoff)
+            .await?
+            .map(|delays| redelivery::parse_delays(&delays))
+            .transpose()?
+            .filter(|delays| !delays.is_empty())
+            .map(|delays| serde_json::to_string(&delays))
+            .transpose()?;
+
+        let deadline = tokio::time::Instant::now() + wait_time;
+
+        loop {
+            let messages = match consumer_group {
+                Some(group) => {
+                    self.sqs_recv_group(
+                        queue_id,
+                        group,
+                        &options,
+                        visibility_timeout,
+                        max_in_flight,
+                        redelivery_delays.as_deref(),
+                    )
+                    .await?
+                }
+                None => {
+                    self.sqs_recv_available(
+                        queue_id,
+                        &options,
+                        visibility_timeout,
+                        max_in_flight,
+                        fair_receive_key.as_deref(),
+                        redelivery_delays.as_deref(),
+                    )
+                    .await?
+                }
+            };
+
+            let now = tokio::time::Instant::now();
+            if !messages.is_empty() || now >= deadline {
+                return Ok(messages);
+            }
+
+            tokio::time::sleep(RECEIVE_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+
+    /// Receives the messages that are currently visible in a queue, without waiting.
+    ///
+    /// If `max_in_flight` is set, fewer messages (possibly none) are received so that no more
+    /// than that many messages are in flight afterwards.
+    ///
+    /// If `fair_receive_key` is set, messages are grouped by it and taken round-robin from each
+    /// group, starting with the group that was served least recently, instead of oldest first.
+    ///
+    /// If `redelivery_delays` (a JSON array of seconds) is set, messages whose visibility timeout
+    /// expired are only received again once the delay for their number of tries has passed too.
+    pub(super) async fn sqs_recv_available(
+        &self,
+        queue_id: u64,
+        options: &ReceiveOptions,
+        visibility_timeout: Duration,
+        max_in_flight: Option<u64>,
+        fair_receive_key: Option<&str>,
+        redelivery_delays: Option<&str>,
+    ) -> Result<Vec<SqsMessage>, Error> {
+        let now = self.service.now();
+        let mut tx = self.service.db().begin().await?;
+
+        // Messages that have used up their retries and whose last delivery has timed out are
+        // moved to the dead-letter queue, if there is one, as fresh messages.
+        let dead_lettered = sqlx::query_as::<_, (u64, u64)>(
+            "
+            UPDATE messages
+            SET
+                queue = conf.dead_letter_queue,
+                dead_lettered_from = messages.queue,
+                tries = 0,
+                delivered_at = NULL,
+                visible_at = NULL
+            FROM queue_configurations conf
+            WHERE conf.queue = messages.queue
+            AND messages.queue = $1
+            AND conf.dead_letter_queue IS NOT NULL
+            AND messages.tries >= conf.max_retries
+            AND messages.visible_at <= $2
+            RETURNING messages.id, messages.queue
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // A queue has a single dead-letter queue, so every moved message ended up in the same one.
+        if let Some((_, dead_letter_queue)) = dead_lettered.first() {
+            let moved = dead_lettered.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+            self.record_trace_event(
+                TraceEvent::DeadLettered,
+                *dead_letter_queue,
+                Some(&moved),
+                &mut tx,
+            )
+            .await?;
+        }
+
+        // Messages are ranked within their group, so that every group's oldest message comes
+        // before any group's second oldest, and ties go to the least recently served group.
+        let (group_join, order) = match fair_receive_key {
+            Some(_) => {
+                let group_key = fair_group_key("$5");
+                (
+                    format!(
+                        "LEFT JOIN fair_receive_groups g ON g.queue = m.queue AND g.group_key = {group_key}"
+                    ),
+                    format!(
+                        "ROW_NUMBER() OVER (PARTITION BY {group_key} ORDER BY m.id), IFNULL(g.last_served, 0), m.id"
+                    ),
+                )
+            }
+            None => (String::new(), "m.id ASC".to_owned()),
+        };
+
+        let (selector, selector_params) = selector_condition(options, "$6");
+        let delay = redelivery_delay("m.tries", "$7");
+
+        // Claim the next visible messages and hide them for the visibility timeout in one
+        // atomic operation.
+        let query = format!(
+            "
+            WITH next_messages AS (
+                SELECT
+                    m.id,
+                    q.name as queue_name
+                FROM messages m
+                JOIN queues q ON m.queue = q.id
+                JOIN queue_configurations conf ON q.id = conf.queue
+                {group_join}
+                WHERE m.queue = $1
+                AND m.tries < conf.max_retries
+                AND (m.delivered_at IS NULL OR m.visible_at + {delay} <= $8)
+                {selector}
+                ORDER BY {order}
+                LIMIT CASE
+                    WHEN $4 IS NULL THEN $2
+                    ELSE MAX(0, MIN($2, $4 - (
+                        SELECT COUNT(*) FROM messages
+                        WHERE queue = $1
+                        AND delivered_at IS NOT NULL
+                        AND visible_at > $8
+                    )))
+                END
+            )
+            UPDATE messages
+            SET
+                delivered_at = $8,
+                visible_at = $8 + $3,
+                tries = tries + 1
+            WHERE id IN (SELECT id FROM next_messages)
+            RETURNING
+                *,
+                (SELECT queue_name FROM next_messages WHERE next_messages.id = messages.id) as queue,
+                (CASE
+                    WHEN messages.delivered_at IS NULL AND messages.tries < (SELECT max_retries FROM queue_configurations WHERE queue = messages.queue) THEN 'pending'
+                    WHEN messages.delivered_at IS NULL AND messages.tries >= (SELECT max_retries FROM queue_configurations WHERE queue = messages.queue) THEN 'failed'
+                    ELSE 'delivered'
+                END) as status
+            "
+        );
+
+        let messages = sqlx::query_as::<_, Message>(&query)
+            .bind(queue_id as i64)
+            .bind(options.max_messages as i64)
+            .bind(visibility_timeout.as_secs() as i64)
+            .bind(max_in_flight.map(|max| max as i64))
+            .bind(fair_receive_key)
+            .bind(selector_params)
+            .bind(redelivery_delays)
+            .bind(now)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .sorted_by_key(|message| message.id)
+            .collect::<Vec<_>>();
+
+        if let (Some(key), false) = (fair_receive_key, messages.is_empty()) {
+            let ids = serde_json::to_string(&messages.iter().map(|m| m.id).collect::<Vec<_>>())?;
+
+            // Mark the groups that were just served as the most recently served, in the order
+            // they were served.
+            sqlx::query(&format!(
+                "
+                INSERT INTO fair_receive_groups (queue, group_key, last_served)
+                SELECT
+                    $1,
+                    group_key,
+                    (SELECT IFNULL(MAX(last_served), 0) FROM fair_receive_groups WHERE queue = $1)
+                        + ROW_NUMBER() OVER (ORDER BY MIN(id))
+                FROM (
+                    SELECT m.id, {group_key} AS group_key
+                    FROM messages m
+                    WHERE m.id IN (SELECT value FROM json_each($2))
+                )
+                WHERE true
+                GROUP BY group_key
+                ON CONFLICT (queue, group_key) DO UPDATE SET last_served = excluded.last_served
+                ",
+                group_key = fair_group_key("$3"),
+            ))
+            .bind(queue_id as i64)
+            .bind(ids)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let received = messages
+            .iter()
+            .map(|message| (message.id, message.tries))
+            .collect::<Vec<_>>();
+
+        if !received.is_empty() {
+            let ids = received.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+            self.record_trace_event(TraceEvent::Received, queue_id, Some(&ids), &mut tx)
+                .await?;
+        }
+
+        let sqs_messages = self.to_sqs_messages(messages, options, &mut tx).await?;
+
+        tx.commit().await?;
+
+        for (message, dead_letter_queue) in dead_lettered {
+            self.service.events.publish(Event::DlqMove {
+                queue: queue_id,
+                dead_letter_queue,
+                message,
+            });
+        }
+
+        for (message, receive_count) in received {
+            self.service.events.publish(Event::MessageReceived {
+                queue: queue_id,
+                message,
+                receive_count,
+            });
+        }
+
+        Ok(sqs_messages)
+    }
+
+    /// Converts claimed messages into the form they are returned to SQS consumers in, with the
+    /// requested attributes.
+    pub(super) async fn to_sqs_messages(
+        &self,
+        messages: Vec<Message>,
+        options: &ReceiveOptions,
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<SqsMessage>, Error> {
+        let mut sqs_messages = Vec::with_capacity(messages.len());
+        for message in messages {
+            let kv = sqlx::query_as::<_, (String, Vec<u8>)>(
+                "
+                SELECT k, v FROM kv_pairs WHERE message = $1
+                ",
+            )
+            .bind(message.id as i64)
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+            // BTreeMap iteration is ordered by name, as required for the attribute digest.
+            let mut message_attributes = HashMap::new();
+            let mut attr_bytes_to_digest = Vec::new();
+            for (k, v) in kv
+                .into_iter()
+                .filter(|(k, _)| options.includes_message_attribute(k))
+            {
+                let v: SqsMessageAttribute = serde_json::from_slice(&v).map_err(Error::internal)?;
+
+                v.serialize_into(&k, &mut attr_bytes_to_digest);
+
+                message_attributes.insert(k, v);
+            }
+
+            let mut attributes = HashMap::new();
+            if options.includes_attribute("ApproximateReceiveCount") {
+                attributes.insert(
+                    "ApproximateReceiveCount".to_owned(),
+                    message.tries.to_string(),
+                );
+            }
+            if let Some(trace_id) = message
+                .trace_id
+                .filter(|_| options.includes_attribute(TRACE_ID_ATTRIBUTE))
+            {
+                attributes.insert(TRACE_ID_ATTRIBUTE.to_owned(), trace_id);
+            }
+
+            sqs_messages.push(SqsMessage {
+                receipt_handle: message.message_id.clone(),
+                message_id: message.message_id,
+
+                md5_of_body: hex::encode(md5::compute(message.body.as_bytes()).as_slice()),
+                body: message.body,
+
+                md5_of_message_attributes: hex::encode(
+                    md5::compute(&attr_bytes_to_digest).as_ref(),
+                ),
+                message_attributes,
+                // md5_of_system_attributes: hex::encode(md5::compute([]).as_ref()), // TODO
+                attributes,
+            });
+        }
+
+        Ok(sqs_messages)
+    }
+
+    /// Receives the messages that are currently visible to a consumer group, without waiting.
+    ///
+    /// Messages that every group has finished with are removed from the queue first.
+    ///
+    /// If `max_in_flight` is set, fewer messages (possibly none) are received so that no more
+    /// than that many messages are in flight to the group afterwards.
+    pub(super) async fn sqs_recv_group(
+        &self,
+        queue_id: u64,
+        group: u64,
+        options: &ReceiveOptions,
+        visibility_timeout: Duration,
+        max_in_flight: Option<u64>,
+        redelivery_delays: Option<&str>,
+    ) -> Result<Vec<SqsMessage>, Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        self.sweep_consumed_messages(queue_id, None, &mut tx)
+            .await?;
+
+        let (selector, selector_params) = selector_condition(options, "$5");
+        let delay = redelivery_delay("d.tries", "$6");
+
+        // Claim the next messages the group hasn't received, or whose visibility timeout has
+        // expired, in one atomic operation.
+        let received = sqlx::query_as::<_, (u64, u64)>(&format!(
+            "
+            INSERT INTO consumer_group_deliveries (grp, message, tries, delivered_at, visible_at)
+            SELECT $2, m.id, 1, $7, $7 + $3
+            FROM messages m
+            JOIN queue_configurations conf ON conf.queue = m.queue
+            LEFT JOIN consumer_group_deliveries d ON d.grp = $2 AND d.message = m.id
+            WHERE m.queue = $1
+            AND (d.message IS NULL OR (
+                d.deleted_at IS NULL
+                AND d.tries < conf.max_retries
+                AND d.visible_at + {delay} <= $7
+            ))
+            {selector}
+            ORDER BY m.id
+            LIMIT CASE
+                WHEN $8 IS NULL THEN $4
+                ELSE MAX(0, MIN($4, $8 - (
+                    SELECT COUNT(*) FROM consumer_group_deliveries
+                    WHERE grp = $2
+                    AND deleted_at IS NULL
+                    AND visible_at > $7
+                )))
+            END
+            ON CONFLICT (grp, message) DO UPDATE SET
+                tries = tries + 1,
+                delivered_at = excluded.delivered_at,
+                visible_at = excluded.visible_at
+            RETURNING message, tries
+            ",
+        ))
+        .bind(queue_id as i64)
+        .bind(group as i64)
+        .bind(visibility_timeout.as_secs() as i64)
+        .bind(options.max_messages as i64)
+        .bind(selector_params)
+        .bind(redelivery_delays)
+        .bind(self.service.now())
+        .bind(max_in_flight.map(|max| max as i64))
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        let ids = serde_json::to_string(&received.keys().collect::<Vec<_>>())?;
+        let mut messages = sqlx::query_as::<_, Message>(
+            "
+            SELECT m.*, q.name AS queue, 'delivered' AS status
+            FROM messages m
+            JOIN queues q ON q.id = m.queue
+            WHERE m.id IN (SELECT value FROM json_each($1))
+            ORDER BY m.id
+            ",
+        )
+        .bind(ids)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // The receive count is the group's, not the message's.
+        for message in &mut messages {
+            message.tries = received[&message.id];
+        }
+
+        let ids = messages
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        self.record_group_trace_event(group, &ids, &mut tx).await?;
+
+        let sqs_messages = self.to_sqs_messages(messages, options, &mut tx).await?;
+
+        tx.commit().await?;
+
+        for (message, receive_count) in received {
+            self.service.events.publish(Event::MessageReceived {
+                queue: queue_id,
+                message,
+                receive_count,
+            });
+        }
+
+        Ok(sqs_messages)
+    }
+
+    /// Gets how long messages received from a queue stay hidden, unless the receive sets its own
+    /// visibility timeout.
+    ///
+    /// # Arguments
+    /// * `queue_id` - ID of the queue
+    pub async fn visibility_timeout(&self, queue_id: u64) -> Result<Duration, Error> {
+        Ok(self
+            .service
+            .get_queue_attribute(queue_id, queue_attributes::VisibilityTimeout)
+            .await?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_VISIBILITY_TIMEOUT))
+    }
+
+    /// Lists all messages in a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    pub async fn list_messages(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<Vec<MessageDetails>, Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        let mut messages = sqlx::query_as::<_, Message>(
+            "
+            SELECT
+                m.*,
+                q.name as queue,
+                (CASE
+                    WHEN m.delivered_at IS NULL AND m.tries < conf.max_retries THEN 'pending'
+                    WHEN m.delivered_at IS NULL AND m.tries >= conf.max_retries THEN 'failed'
+                    ELSE 'delivered'
+                END) as status
+            FROM messages m
+            JOIN queues q ON m.queue = q.id
+            JOIN queue_configurations conf ON q.id = conf.queue
+            WHERE q.ns = (SELECT id FROM namespaces WHERE name = $1) AND q.name = $2
+        ",
+        )
+        .bind(namespace)
+        .bind(queue)
+        .fetch(&mut *db);
+
+        let mut join_set = JoinSet::new();
+        while let Some(message) = messages.next().await.transpose()? {
+            join_set.spawn_local(message_details(self.service.db().clone(), message));
+        }
+
+        let mut messages = Vec::new();
+
+        while let Some(result) = join_set
+            .join_next()
+            .await
+            .transpose()
+            .map_err(Error::internal)?
+            .transpose()?
+        {
+            messages.push(result);
+        }
+
+        Ok(messages)
+    }
+
+    /// Returns a message of a queue as [`Service::list_messages`] lists it, or `None` if it isn't
+    /// in the queue.
+    ///
+    /// # Arguments
+    /// * `queue_id` - ID of the queue
+    /// * `message_id` - ID of the message
+    pub async fn get_message_details(
+        &self,
+        queue_id: u64,
+        message_id: u64,
+    ) -> Result<Option<MessageDetails>, Error> {
+        let message = sqlx::query_as::<_, Message>(
+            "
+            SELECT
+                m.*,
+                q.name as queue,
+                (CASE
+                    WHEN m.delivered_at IS NULL AND m.tries < conf.max_retries THEN 'pending'
+                    WHEN m.delivered_at IS NULL AND m.tries >= conf.max_retries THEN 'failed'
+                    ELSE 'delivered'
+                END) as status
+            FROM messages m
+            JOIN queues q ON m.queue = q.id
+            JOIN queue_configurations conf ON q.id = conf.queue
+            WHERE m.queue = $1 AND m.id = $2
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(self.service.db())
+        .await?;
+
+        match message {
+            Some(message) => Ok(Some(
+                message_details(self.service.db().clone(), message).await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes multiple messages from a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `message_ids` - IDs of messages to delete
+    /// * `consumer_group` - Consumer group the messages were received by, if any
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Returns
+    /// Tuple of (successfully deleted IDs, failed deletions with errors)
+    pub async fn delete_message_batch(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_ids: Vec<u64>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<
+        (
+            Vec<u64>,          // Successfully deleted message IDs
+            Vec<(u64, Error)>, // Failed message IDs
+        ),
+        Error,
+    > {
+        self.service.ensure_available()?;
+
+        let mut tx = self.service.db().begin().await?;
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .service
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+        // Verify queue exists
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let group = match consumer_group {
+            Some(name) => Some(
+                self.service
+                    .get_consumer_group_id(queue_id, queue, name)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let mut success = Vec::new();
+        let mut failure = Vec::new();
+
+        for message_id in message_ids {
+            let removed = match group {
+                Some(group) => {
+                    self.remove_group_message(queue_id, group, message_id, &mut tx)
+                        .await
+                }
+                None => self.remove_message(queue_id, message_id, &mut tx).await,
+            };
+            match removed {
+                Ok(removed) => {
+                    if !removed {
+                        failure.push((
+                            message_id,
+                            Error::not_found(format!("{message_id} in queue {queue}")),
+                        ));
+                    } else {
+                        success.push(message_id);
+                    }
+                }
+                Err(err) => failure.push((message_id, err)),
+            };
+        }
+
+        tx.commit().await?;
+
+        for message in &success {
+            self.service.events.publish(Event::MessageDeleted {
+                queue: queue_id,
+                message: *message,
+            });
+        }
+
+        Ok((success, failure))
+    }
+
+    /// Extends the visibility timeout of messages a consumer is still working on, all at once.
+    ///
+    /// Either every message is extended, or none is: if any of them is not in flight (it was
+    /// never received, was deleted, or its visibility timeout has already expired), the call
+    /// fails and no deadline changes.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `message_ids` - IDs of the messages to extend, from their receipt handles
+    /// * `visibility_timeout` - How long from now the messages stay hidden
+    /// * `consumer_group` - Consumer group the messages were received by, if any
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Returns
+    /// Unix timestamp at which the messages become visible again
+    pub async fn extend_visibility(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_ids: &[u64],
+        visibility_timeout: Duration,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<u64, Error> {
+        let message_ids = message_ids.iter().copied().collect::<BTreeSet<_>>();
+        if message_ids.is_empty() || message_ids.len() > MAX_VISIBILITY_EXTENSION_BATCH {
+            return Err(Error::invalid_parameter(format!(
+                "receipt handles: must be between 1 and {MAX_VISIBILITY_EXTENSION_BATCH}"
+            )));
+        }
+
+        self.change_visibility(
+            namespace,
+            queue,
+            &message_ids,
+            visibility_timeout,
+            None,
+            consumer_group,
+            identity,
+        )
+        .await
+    }
+
+    /// Changes the visibility timeout of a message that is in flight.
+    ///
+    /// A consumer that gives up on a message can make it receivable again right away with a
+    /// timeout of 0, and report why with `failure_reason`. The last [`MAX_RECORDED_FAILURES`]
+    /// reasons are kept with the message, including when it moves to a dead-letter queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `message_id` - ID of the message, from its receipt handle
+    /// * `visibility_timeout` - How long from now the message stays hidden
+    /// * `failure_reason` - Why the consumer failed to process the message, if it did
+    /// * `consumer_group` - Consumer group the message was received by, if any
+    /// * `identity` - Identity of the authenticated user
+    #[allow(clippy::too_many_arguments)]
+    pub async fn change_message_visibility(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_id: u64,
+        visibility_timeout: Duration,
+        failure_reason: Option<&str>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        if failure_reason.is_some_and(|reason| reason.len() > MAX_FAILURE_REASON_LENGTH) {
+            return Err(Error::invalid_parameter(format!(
+                "FailureReason: must be at most {MAX_FAILURE_REASON_LENGTH} bytes"
+            )));
+        }
+
+        self.change_visibility(
+            namespace,
+            queue,
+            &BTreeSet::from([message_id]),
+            visibility_timeout,
+            failure_reason,
+            consumer_group,
+            identity,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Hides in-flight messages until `visibility_timeout` from now, recording `failure_reason`
+    /// for each of them if set, or fails without changing any of them if one is not in flight.
+    ///
+    /// # Returns
+    /// Unix timestamp at which the messages become visible again
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn change_visibility(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_ids: &BTreeSet<u64>,
+        visibility_timeout: Duration,
+        failure_reason: Option<&str>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<u64, Error> {
+        self.service.ensure_available()?;
+
+        if visibility_timeout > MAX_VISIBILITY_TIMEOUT {
+            return Err(Error::invalid_parameter(format!(
+                "VisibilityTimeout: must be at most {} seconds",
+                MAX_VISIBILITY_TIMEOUT.as_secs()
+            )));
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .service
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        // Verify queue exists
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let group = match consumer_group {
+            Some(name) => Some(
+                self.service
+                    .get_consumer_group_id(queue_id, queue, name)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let changed = self
+            .hide_in_flight(queue_id, group, message_ids, visibility_timeout, &mut tx)
+            .await?;
+
+        if changed.len() < message_ids.len() {
+            let changed = changed.iter().map(|(id, _, _)| *id).collect::<HashSet<_>>();
+            let missing = message_ids
+                .iter()
+                .filter(|id| !changed.contains(id))
+                .join(", ");
+            return Err(Error::not_found(format!(
+                "in-flight messages {missing} in queue {queue}"
+            )));
+        }
+
+        if let Some(reason) = failure_reason {
+            for (message_id, _, tries) in &changed {
+                self.record_failure(*message_id, reason, consumer_group, *tries, &mut tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(changed
+            .first()
+            .map(|(_, visible_at, _)| *visible_at)
+            .unwrap_or_default())
+    }
+
+    /// Applies the processing results consumers report for messages, see [`crate::ack`].
+    ///
+    /// Successfully processed messages are deleted, keeping their payload in the message
+    /// history. Failed messages become receivable again, and their payload is recorded as the
+    /// failure reason.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `outcomes` - Processing results
+    /// * `consumer_group` - Consumer group the messages were received by, if any
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Returns
+    /// Tuple of (IDs of messages whose results were applied, results that failed with errors)
+    pub async fn ack_messages(
+        &self,
+        namespace: &str,
+        queue: &str,
+        outcomes: Vec<Outcome>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<(Vec<u64>, Vec<(u64, Error)>), Error> {
+        self.service.ensure_available()?;
+
+        if outcomes.is_empty() || outcomes.len() > MAX_ACK_RESULTS {
+            return Err(Error::invalid_parameter(format!(
+                "results: must be between 1 and {MAX_ACK_RESULTS}"
+            )));
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .service
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        // Verify queue exists
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let group = match consumer_group {
+            Some(name) => Some(
+                self.service
+                    .get_consumer_group_id(queue_id, queue, name)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let mut success = Vec::new();
+        let mut failure = Vec::new();
+        let mut deleted = Vec::new();
+        let mut failed = BTreeMap::new();
+
+        for outcome in outcomes {
+            let message_id = outcome.message_id;
+            let payload = outcome.payload.as_ref().map(ToString::to_string);
+            if payload
+                .as_ref()
+                .is_some_and(|payload| payload.len() > MAX_ACK_PAYLOAD_LENGTH)
+            {
+                failure.push((
+                    message_id,
+                    Error::invalid_parameter(format!(
+                        "payload: must be at most {MAX_ACK_PAYLOAD_LENGTH} bytes"
+                    )),
+                ));
+                continue;
+            }
+
+            if !outcome.success {
+                failed.insert(message_id, outcome.payload);
+                continue;
+            }
+
+            let removed = match group {
+                Some(group) => {
+                    self.remove_group_message(queue_id, group, message_id, &mut tx)
+                        .await
+                }
+                None => self.remove_message(queue_id, message_id, &mut tx).await,
+            };
+            match removed {
+                Ok(true) => {
+                    // The message only has a history record if the queue keeps one.
+                    if let Some(payload) = payload {
+                        sqlx::query(
+                            "
+                            UPDATE messages_history SET result = $1
+                            WHERE message = $2 AND queue = $3
+                            ",
+                        )
+                        .bind(payload)
+                        .bind(message_id as i64)
+                        .bind(queue_id as i64)
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                    success.push(message_id);
+                    deleted.push(message_id);
+                }
+                Ok(false) => failure.push((
+                    message_id,
+                    Error::not_found(format!("{message_id} in queue {queue}")),
+                )),
+                Err(err) => failure.push((message_id, err)),
+            }
+        }
+
+        if !failed.is_empty() {
+            let ids = failed.keys().copied().collect::<BTreeSet<_>>();
+            let changed = self
+                .hide_in_flight(queue_id, group, &ids, Duration::ZERO, &mut tx)
+                .await?;
+
+            for (message_id, _, tries) in &changed {
+                // Payloads that are strings are recorded as they are, other values as JSON.
+                let reason = failed
+                    .remove(message_id)
+                    .flatten()
+                    .map(json_string)
+                    .unwrap_or_default();
+                self.record_failure(*message_id, &reason, consumer_group, *tries, &mut tx)
+                    .await?;
+            }
+
+            let changed = changed.iter().map(|(id, _, _)| *id).collect::<HashSet<_>>();
+            for message_id in ids {
+                if changed.contains(&message_id) {
+                    success.push(message_id);
+                } else {
+                    failure.push((
+                        message_id,
+                        Error::not_found(format!(
+                            "in-flight message {message_id} in queue {queue}"
+                        )),
+                    ));
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        for message in deleted {
+            self.service.events.publish(Event::MessageDeleted {
+                queue: queue_id,
+                message,
+            });
+        }
+
+        Ok((success, failure))
+    }
+
+    /// Hides in-flight messages of a queue, or of a consumer group, until `visibility_timeout`
+    /// from now.
+    ///
+    /// Only messages that are still hidden can be changed. Once a message's timeout has expired,
+    /// it may already have been received by another consumer.
+    ///
+    /// # Returns
+    /// ID, new visibility deadline and number of tries of every changed message
+    pub(super) async fn hide_in_flight(
+        &self,
+        queue_id: u64,
+        group: Option<u64>,
+        message_ids: &BTreeSet<u64>,
+        visibility_timeout: Duration,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<Vec<(u64, u64, u64)>, Error> {
+        let ids = serde_json::to_string(message_ids)?;
+        let now = self.service.now();
+
+        let changed = match group {
+            Some(group) => {
+                sqlx::query_as(
+                    "
+                    UPDATE consumer_group_deliveries
+                    SET visible_at = $4 + $3
+                    WHERE grp = $1
+                    AND message IN (SELECT value FROM json_each($2))
+                    AND deleted_at IS NULL
+                    AND delivered_at IS NOT NULL
+                    AND visible_at > $4
+                    RETURNING message, visible_at, tries
+                    ",
+                )
+                .bind(group as i64)
+                .bind(&ids)
+                .bind(visibility_timeout.as_secs() as i64)
+                .bind(now)
+                .fetch_all(&mut **tx)
+                .await?
+            }
+            None => {
+                sqlx::query_as(
+                    "
+                    UPDATE messages
+                    SET visible_at = $4 + $3
+                    WHERE queue = $1
+                    AND id IN (SELECT value FROM json_each($2))
+                    AND delivered_at IS NOT NULL
+                    AND visible_at > $4
+                    RETURNING id, visible_at, tries
+                    ",
+                )
+                .bind(queue_id as i64)
+                .bind(&ids)
+                .bind(visibility_timeout.as_secs() as i64)
+                .bind(now)
+                .fetch_all(&mut **tx)
+                .await?
+            }
+        };
+
+        Ok(changed)
+    }
+
+    /// Records a failure reported by a consumer, dropping the message's oldest failures beyond
+    /// [`MAX_RECORDED_FAILURES`].
+    pub(super) async fn record_failure(
+        &self,
+        message_id: u64,
+        reason: &str,
+        consumer_group: Option<&str>,
+        tries: u64,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "
+            INSERT INTO message_failures (message, consumer_group, reason, tries)
+            VALUES ($1, $2, $3, $4)
+            ",
+        )
+        .bind(message_id as i64)
+        .bind(consumer_group)
+        .bind(reason)
+        .bind(tries as i64)
+        .execute(&mut **tx)
+        .await?;
+
+        sqlx::query(
+            "
+            DELETE FROM message_failures
+            WHERE message = $1
+            AND id NOT IN (
+                SELECT id FROM message_failures WHERE message = $1 ORDER BY id DESC LIMIT $2
+            )
+            ",
+        )
+        .bind(message_id as i64)
+        .bind(MAX_RECORDED_FAILURES as i64)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes an acknowledged message, recording it in the message history if the queue keeps
+    /// one.
+    ///
+    /// # Returns
+    /// `false` if the message does not exist in the queue
+    pub(super) async fn remove_message(
+        &self,
+        queue_id: u64,
+        message_id: u64,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<bool, Error> {
+        sqlx::query(
+            "
+            INSERT INTO messages_history
+                (
+                    message, message_id, queue, body, compressed, sent_by, tries, delivered_at,
+                    trace_id, deleted_at
+                )
+            SELECT
+                m.id,
+                m.message_id,
+                m.queue,
+                CASE WHEN conf.history = 'full' THEN m.body END,
+                conf.history = 'full' AND m.compressed,
+                m.sent_by,
+                m.tries,
+                m.delivered_at,
+                m.trace_id,
+                $3
+            FROM messages m
+            JOIN queue_configurations conf ON conf.queue = m.queue
+            WHERE m.id = $1 AND m.queue = $2 AND conf.history != 'off'
+            ",
+        )
+        .bind(message_id as i64)
+        .bind(queue_id as i64)
+        .bind(self.service.now())
+        .execute(&mut **tx)
+        .await?;
+
+        self.record_trace_event(TraceEvent::Deleted, queue_id, Some(&[message_id]), tx)
+            .await?;
+
+        let res = sqlx::query(
+            "
+            DELETE FROM messages
+            WHERE id = $1 AND queue = $2
+            ",
+        )
+        .bind(message_id as i64)
+        .bind(queue_id as i64)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Lists the recorded history of deleted messages in a queue, most recently deleted first.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `limit` - Maximum number of records to return, capped at [`MAX_HISTORY_PAGE_SIZE`]
+    /// * `before` - Only return messages deleted before this Unix timestamp, for paging
+    pub async fn list_message_history(
+        &self,
+        namespace: &str,
+        queue: &str,
+        limit: u64,
+        before: Option<u64>,
+    ) -> Result<Vec<HistoryEntry>, Error> {
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                message_id, body, compressed, sent_by, tries, delivered_at, deleted_at, trace_id,
+                result
+            FROM messages_history
+            WHERE queue = $1 AND ($2 IS NULL OR deleted_at < $2)
+            ORDER BY deleted_at DESC, id DESC
+            LIMIT $3
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(before.map(|before| before as i64))
+        .bind(limit.min(MAX_HISTORY_PAGE_SIZE) as i64)
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Returns a random sample of the messages in a queue, without changing their delivery state.
+    ///
+    /// Attributes and JSON body fields named in the queue's `SampleMaskedAttributes` are masked.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `count` - Number of messages to sample, capped at [`MAX_SAMPLE_SIZE`]
+    pub async fn sample_messages(
+        &self,
+        namespace: &str,
+        queue: &str,
+        count: u64,
+    ) -> Result<Vec<MessageSample>, Error> {
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let masked = sample::masked_names(
+            self.service
+                .get_queue_text_attribute(queue_id, queue_attributes::SampleMaskedAttributes)
+                .await?
+                .as_deref(),
+        );
+
+        let mut conn = self.service.db().acquire().await?;
+
+        let messages = sqlx::query_as::<_, (u64, String, Vec<u8>, bool)>(
+            "
+            SELECT id, message_id, body, compressed
+            FROM messages
+            WHERE queue = $1
+            ORDER BY RANDOM()
+            LIMIT $2
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(count.min(MAX_SAMPLE_SIZE) as i64)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut samples = Vec::with_capacity(messages.len());
+        for (id, message_id, body, compressed) in messages {
+            let kv = sqlx::query_as::<_, (String, Vec<u8>)>(
+                "
+                SELECT k, v FROM kv_pairs WHERE message = $1
+                ",
+            )
+            .bind(id as i64)
+            .fetch_all(&mut *conn)
+            .await?;
+
+            let mut message_attributes = HashMap::with_capacity(kv.len());
+            for (k, v) in kv {
+                let value = if masked.contains(&k) {
+                    serde_json::Value::String(MASKED_VALUE.to_owned())
+                } else {
+                    serde_json::from_slice::<SqsMessageAttribute>(&v)?.to_json()?
+                };
+                message_attributes.insert(k, value);
+            }
+
+            samples.push(MessageSample {
+                message_id,
+                body: sample::mask_body(compression::decode_body(body, compressed)?, &masked),
+                message_attributes,
+            });
+        }
+
+        Ok(samples)
+    }
+
+    /// Runs a report over the messages in a queue, see [`crate::report`].
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `kind` - Report to run
+    /// * `attribute` - Attribute to report on, for [`ReportKind::TopAttributeValues`]
+    /// * `limit` - Maximum number of rows, capped at [`report::MAX_REPORT_ROWS`]
+    ///
+    /// # Errors
+    /// Returns [`Error::QueryTimedOut`] if the report takes longer than
+    /// [`report::REPORT_TIME_LIMIT`]
+    pub async fn queue_report(
+        &self,
+        namespace: &str,
+        queue: &str,
+        kind: ReportKind,
+        attribute: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<ReportRow>, Error> {
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let mut conn = self.service.db().acquire().await?;
+
+        // SQLite interrupts the report once the progress handler returns false. The handler is
+        // removed again before the connection goes back to the pool.
+        let deadline = std::time::Instant::now() + report::REPORT_TIME_LIMIT;
+        let timed_out = Arc::new(AtomicBool::new(false));
+        conn.lock_handle()
+            .await?
+            .set_progress_handler(report::REPORT_PROGRESS_INTERVAL, {
+                let timed_out = timed_out.clone();
+                move || {
+                    let expired = std::time::Instant::now() >= deadline;
+                    timed_out.store(expired, Ordering::Relaxed);
+                    !expired
+                }
+            });
+
+        let res = report::run(
+            &mut conn,
+            queue_id,
+            kind,
+            attribute,
+            limit,
+            self.service.now(),
+        )
+        .await;
+
+        conn.lock_handle().await?.remove_progress_handler();
+
+        match res {
+            Err(_) if timed_out.load(Ordering::Relaxed) => Err(Error::QueryTimedOut {
+                limit: report::REPORT_TIME_LIMIT,
+            }),
+            res => res,
+        }
+    }
+
+    /// Removes history records that are older than their queue's retention period.
+    ///
+    /// # Returns
+    /// The number of records removed
+    pub async fn prune_message_history(&self) -> Result<u64, Error> {
+        let res = sqlx::query(
+            "
+            DELETE FROM messages_history
+            WHERE deleted_at + (
+                SELECT history_retention
+                FROM queue_configurations
+                WHERE queue = messages_history.queue
+            ) <= $1
+            ",
+        )
+        .bind(self.service.now())
+        .execute(self.service.db())
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Records a lifecycle event for messages that have a trace id.
+    ///
+    /// # Arguments
+    /// * `event` - Event to record
+    /// * `queue_id` - Queue the messages are in after the event
+    /// * `messages` - IDs of the messages, or `None` for every message in the queue
+    /// * `conn` - Connection of the transaction making the change
+    pub(super) async fn record_trace_event(
+        &self,
+        event: TraceEvent,
+        queue_id: u64,
+        messages: Option<&[u64]>,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), Error> {
+        let ids = messages.map(serde_json::to_string).transpose()?;
+
+        sqlx::query(
+            "
+            INSERT INTO message_traces (trace_id, message, namespace, queue, event, receive_count, at)
+            SELECT
+                m.trace_id,
+                m.id,
+                n.name,
+                q.name,
+                $1,
+                CASE WHEN $1 = 'received' THEN m.tries END,
+                $4
+            FROM messages m
+            JOIN queues q ON q.id = m.queue
+            JOIN namespaces n ON n.id = q.ns
+            WHERE m.queue = $2
+            AND m.trace_id IS NOT NULL
+            AND ($3 IS NULL OR m.id IN (SELECT value FROM json_each($3)))
+            ",
+        )
+        .bind(event)
+        .bind(queue_id as i64)
+        .bind(ids)
+        .bind(self.service.now())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records that messages were received by a consumer group.
+    pub(super) async fn record_group_trace_event(
+        &self,
+        group: u64,
+        messages: &[u64],
+        conn: &mut SqliteConnection,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "
+            INSERT INTO message_traces
+                (trace_id, message, namespace, queue, event, receive_count, consumer_group, at)
+            SELECT m.trace_id, m.id, n.name, q.name, $1, d.tries, g.name, $4
+            FROM consumer_group_deliveries d
+            JOIN consumer_groups g ON g.id = d.grp
+            JOIN messages m ON m.id = d.message
+            JOIN queues q ON q.id = m.queue
+            JOIN namespaces n ON n.id = q.ns
+            WHERE d.grp = $2
+            AND d.message IN (SELECT value FROM json_each($3))
+            AND m.trace_id IS NOT NULL
+            ",
+        )
+        .bind(TraceEvent::Received)
+        .bind(group as i64)
+        .bind(serde_json::to_string(messages)?)
+        .bind(self.service.now())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gets the recorded lifecycle of the messages with a trace id.
+    ///
+    /// Only events in namespaces the user can access are returned.
+    ///
+    /// # Arguments
+    /// * `trace_id` - Trace id of the messages
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Returns
+    /// Up to [`MAX_TRACE_EVENTS`] events, oldest first
+    ///
+    /// # Errors
+    /// Returns [`Error::NotFound`] if there are no events the user can see
+    pub async fn get_message_trace(
+        &self,
+        trace_id: &str,
+        identity: Identity,
+    ) -> Result<Trace, Error> {
+        let email = identity.id()?;
+
+        let events = sqlx::query_as(
+            "
+            SELECT message, namespace, queue, event, receive_count, consumer_group, at
+            FROM message_traces
+            WHERE trace_id = $1
+            AND namespace IN (
+                SELECT n.name FROM namespaces n
+                JOIN user_permissions p ON p.namespace = n.id
+                JOIN users u ON p.user = u.id
+                WHERE u.email = $2
+            )
+            ORDER BY id
+            LIMIT $3
+            ",
+        )
+        .bind(trace_id)
+        .bind(email)
+        .bind(MAX_TRACE_EVENTS as i64)
+        .fetch_all(self.service.db())
+        .await?;
+
+        if events.is_empty() {
+            return Err(Error::not_found(format!("trace {trace_id}")));
+        }
+
+        Ok(Trace {
+            trace_id: trace_id.to_owned(),
+            events,
+        })
+    }
+
+    /// Removes trace events that are older than the configured trace retention.
+    ///
+    /// # Returns
+    /// The number of events removed
+    pub async fn prune_message_traces(&self) -> Result<u64, Error> {
+        let res = sqlx::query("DELETE FROM message_traces WHERE at + $1 <= $2")
+            .bind(self.service.config.trace_retention().as_secs() as i64)
+            .bind(self.service.now())
+            .execute(self.service.db())
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Marks a message as deleted by a consumer group, and removes it from the queue if every
+    /// group has now finished with it.
+    ///
+    /// # Returns
+    /// `false` if the message has not been delivered to the group
+    pub(super) async fn remove_group_message(
+        &self,
+        queue_id: u64,
+        group: u64,
+        message_id: u64,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query(
+            "
+            UPDATE consumer_group_deliveries
+            SET deleted_at = IFNULL(deleted_at, $3)
+            WHERE grp = $1 AND message = $2 AND delivered_at IS NOT NULL
+            ",
+        )
+        .bind(group as i64)
+        .bind(message_id as i64)
+        .bind(self.service.now())
+        .execute(&mut **tx)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        self.sweep_consumed_messages(queue_id, Some(&[message_id]), tx)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Removes the messages of a queue with consumer groups that every group has finished
+    /// with, by deleting them or using up the queue's retries on them.
+    ///
+    /// # Arguments
+    /// * `queue_id` - Queue to remove messages from
+    /// * `messages` - Messages to consider, or `None` for every message in the queue
+    /// * `tx` - Transaction to remove the messages in
+    pub(super) async fn sweep_consumed_messages(
+        &self,
+        queue_id: u64,
+        messages: Option<&[u64]>,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<(), Error> {
+        let ids = messages.map(serde_json::to_string).transpose()?;
+
+        let consumed: Vec<u64> = sqlx::query_scalar(
+            "
+            SELECT m.id
+            FROM messages m
+            JOIN queue_configurations conf ON conf.queue = m.queue
+            WHERE m.queue = $1
+            AND ($2 IS NULL OR m.id IN (SELECT value FROM json_each($2)))
+            AND EXISTS (SELECT 1 FROM consumer_groups WHERE queue = m.queue)
+            AND NOT EXISTS (
+                SELECT 1 FROM consumer_groups g
+                WHERE g.queue = m.queue
+                AND NOT EXISTS (
+                    SELECT 1 FROM consumer_group_deliveries d
+                    WHERE d.grp = g.id AND d.message = m.id
+                    AND (
+                        d.deleted_at IS NOT NULL
+                        OR (d.tries >= conf.max_retries AND d.visible_at <= $3)
+                    )
+                )
+            )
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(ids)
+        .bind(self.service.now())
+        .fetch_all(&mut **tx)
+        .await?;
+
+        for message in consumed {
+            self.remove_message(queue_id, message, tx).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the receipt handles of messages received from a queue to the messages' IDs. A
+    /// receipt handle is the message ID clients see, so row IDs never leave the server. Handles
+    /// that don't refer to a message in the queue resolve to `None`.
+    pub async fn resolve_receipt_handles(
+        &self,
+        namespace: &str,
+        queue: &str,
+        handles: &[String],
+    ) -> Result<Vec<Option<u64>>, Error> {
+        let ids: HashMap<String, u64> = sqlx::query_as(
+            "
+            SELECT m.message_id, m.id FROM messages m
+            JOIN queues q ON m.queue = q.id
+            JOIN namespaces n ON q.ns = n.id
+            WHERE n.name = $1 AND q.name = $2
+            AND m.message_id IN (SELECT value FROM json_each($3))
+            ",
+        )
+        .bind(namespace)
+        .bind(queue)
+        .bind(serde_json::to_string(handles)?)
+        .fetch_all(self.service.db())
+        .await?
+        .into_iter()
+        .collect();
+
+        Ok(handles
+            .iter()
+            .map(|handle| ids.get(handle).copied())
+            .collect())
+    }
+
+    /// Resolves a single receipt handle, see [`Self::resolve_receipt_handles`].
+    pub async fn resolve_receipt_handle(
+        &self,
+        namespace: &str,
+        queue: &str,
+        handle: &str,
+    ) -> Result<u64, Error> {
+        self.resolve_receipt_handles(namespace, queue, &[handle.to_owned()])
+            .await?
+            .pop()
+            .flatten()
+            .ok_or_else(|| Error::ReceiptHandleIsInvalid {
+                handle: handle.to_owned(),
+            })
+    }
+
+    /// Deletes a single message from a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `message_id` - ID of message to delete
+    /// * `consumer_group` - Consumer group the message was received by, if any
+    /// * `identity` - Identity of the authenticated user
+    pub async fn delete_message(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_id: u64,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.service.ensure_available()?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .service
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        // Verify queue exists
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let group = match consumer_group {
+            Some(name) => Some(
+                self.service
+                    .get_consumer_group_id(queue_id, queue, name)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        // Delete the message if it exists in this queue
+        let removed = match group {
+            Some(group) => {
+                self.remove_group_message(queue_id, group, message_id, &mut tx)
+                    .await?
+            }
+            None => self.remove_message(queue_id, message_id, &mut tx).await?,
+        };
+        if !removed {
+            return Err(Error::not_found(format!("{message_id} in queue {queue}")));
+        }
+
+        tx.commit().await?;
+
+        self.service.events.publish(Event::MessageDeleted {
+            queue: queue_id,
+            message: message_id,
+        });
+
+        Ok(())
+    }
+
+    /// Deletes all messages from a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Errors
+    /// * `Error::PurgeQueueInProgress` - If the queue was purged less than [`PURGE_COOLDOWN`] ago
+    pub async fn purge_queue(
+        &self,
+        namespace: &str,
+        queue: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.service.ensure_available()?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .service
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        // Verify queue exists
+        let queue_id = self
+            .service
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        // Checked and recorded in one statement, so that of two concurrent purges only one runs.
+        let now = self.service.now();
+        let cooldown = PURGE_COOLDOWN.as_secs() as i64;
+        let purged_at: Option<i64> = sqlx::query_scalar(
+            "
+            UPDATE queues SET purged_at = $2
+            WHERE id = $1 AND (purged_at IS NULL OR purged_at <= $2 - $3)
+            RETURNING purged_at
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(now)
+        .bind(cooldown)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if purged_at.is_none() {
+            let purged_at: i64 = sqlx::query_scalar("SELECT purged_at FROM queues WHERE id = $1")
+                .bind(queue_id as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+            return Err(Error::PurgeQueueInProgress {
+                queue: queue.to_owned(),
+                retry_after: Duration::from_secs((purged_at + cooldown - now).max(1) as u64),
+            });
+        }
+
+        self.record_trace_event(TraceEvent::Purged, queue_id, None, &mut tx)
+            .await?;
+
+        // Delete all messages from the queue
+        sqlx::query(
+            "
+            DELETE FROM messages
+            WHERE queue = $1
+            ",
+        )
+        .bind(queue_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Loads the attributes and failures of a message.
+async fn message_details(db: SqlitePool, message: Message) -> Result<MessageDetails, Error> {
+    let mut conn = db.acquire().await?;
+    // let mut kv_pairs = sqlx::query_as::<_, (String, Vec<u8>)>(
+    //     "
+    //     SELECT k, v FROM kv_pairs WHERE message = $1
+    // ",
+    // )
+    // .bind(message.id as i64)
+    // .fetch(&mut *conn);
+    //
+    // while let Some((k, v)) = kv_pairs.next().await.transpose()? {
+    //     message
+    //         .kv
+    //         .insert(k, bincode::deserialize(&v).map_err(Error::internal)?);
+    // }
+
+    let mut message_attributes = HashMap::new();
+    let mut kv = sqlx::query_as::<_, (String, Vec<u8>)>(
+        "
+        SELECT k, v FROM kv_pairs WHERE message = $1
+        ",
+    )
+    .bind(message.id as i64)
+    .fetch(&mut *conn);
+
+    while let Some((k, v)) = kv.next().await.transpose()? {
+        let attr: SqsMessageAttribute = match serde_json::from_slice(&v) {
+            Ok(attr) => attr,
+            Err(e) => {
+                tracing::warn!(
+                    attribute = k,
+                    message = message.id,
+                    "Failed to deserialize message attribute: {e}",
+                );
+
+                continue;
+            }
+        };
+        message_attributes.insert(k, attr.to_json().map_err(Error::internal)?);
+    }
+    drop(kv);
+
+    let failures = sqlx::query_as::<_, MessageFailure>(
+        "
+        SELECT reason, consumer_group, tries, reported_at
+        FROM message_failures
+        WHERE message = $1
+        ORDER BY id DESC
+        ",
+    )
+    .bind(message.id as i64)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let sqs_message = MessageDetails {
+        id: message.id,
+        message_id: message.message_id,
+        queue: message.queue,
+        status: message.status,
+        sent_by: message.sent_by,
+        delivered_at: message.delivered_at,
+        tries: message.tries,
+        body: message.body,
+        trace_id: message.trace_id,
+
+        message_attributes,
+        failures,
+    };
+
+    Ok(sqs_message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        history::HistoryMode,
+        kms::memory::InMemoryKeyManager,
+        selector::Selector,
+        service::{OrderingMode, QueueAttribute, QueueConfig},
+    };
+
+    #[tokio::test]
+    async fn test_message_trace_follows_dead_letter_move() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        let mut queues = Vec::new();
+        for name in ["in", "dlq"] {
+            service
+                .create_queue("t", name, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+            let id = service.get_queue_id("t", name, service.db()).await.unwrap();
+            queues.push(id.unwrap());
+        }
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some(queues[1]),
+            ..service.get_queue_configuration(queues[0]).await.unwrap()
+        };
+        service
+            .update_queue_configuration(queues[0], config)
+            .await
+            .unwrap();
+
+        service
+            .sqs_send(
+                queues[0],
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/in".parse().unwrap(),
+                    message_body: "hello".to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let options = ReceiveOptions::builder()
+            .visibility_timeout(Duration::ZERO)
+            .attribute_names(HashSet::from([TRACE_ID_ATTRIBUTE.to_owned()]))
+            .build();
+        let first = service
+            .sqs_recv("t", "in", options.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(service
+            .sqs_recv("t", "in", options.clone())
+            .await
+            .unwrap()
+            .is_none());
+        let second = service
+            .sqs_recv("t", "dlq", options)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let trace_id = &first.attributes[TRACE_ID_ATTRIBUTE];
+        assert_eq!(&second.attributes[TRACE_ID_ATTRIBUTE], trace_id);
+
+        service
+            .delete_message(
+                "t",
+                "dlq",
+                service
+                    .resolve_receipt_handle("t", "dlq", &second.receipt_handle)
+                    .await
+                    .unwrap(),
+                None,
+                root(),
+            )
+            .await
+            .unwrap();
+
+        let trace = service.get_message_trace(trace_id, root()).await.unwrap();
+        let events = trace
+            .events
+            .iter()
+            .map(|e| (e.queue.as_str(), e.event, e.receive_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                ("in", TraceEvent::Sent, None),
+                ("in", TraceEvent::Received, Some(1)),
+                ("dlq", TraceEvent::DeadLettered, None),
+                ("dlq", TraceEvent::Received, Some(1)),
+                ("dlq", TraceEvent::Deleted, None),
+            ]
+        );
+
+        assert!(matches!(
+            service
+                .get_message_trace(trace_id, Identity::mock("nobody@example.com".to_owned()))
+                .await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_receive_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "db_path": dir.path().join("nervemq.db"),
+            "integrity_check": "off",
+        }))
+        .unwrap();
+        let connect = || {
+            Service::connect_with()
+                .config(config.clone())
+                .kms_factory(crate::kms::sqlite::SqliteKeyManager::new)
+                .call()
+        };
+        let service = connect().await.unwrap();
+        let root_email = service.config().root_email().to_owned();
+        let root = || Identity::mock(root_email.clone());
+        service.create_namespace("t", root()).await.unwrap();
+        let mut queues = Vec::new();
+        for name in ["in", "dlq"] {
+            service
+                .create_queue("t", name, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+            let id = service.get_queue_id("t", name, service.db()).await.unwrap();
+            queues.push(id.unwrap());
+        }
+        let config = QueueConfig {
+            max_retries: 2,
+            dead_letter_queue: Some(queues[1]),
+            ..service.get_queue_configuration(queues[0]).await.unwrap()
+        };
+        service
+            .update_queue_configuration(queues[0], config)
+            .await
+            .unwrap();
+
+        let send = |service: &Service, queue: u64, body: &str| {
+            let request = SendMessageRequest {
+                queue_url: "http://localhost:8080/t/in".parse().unwrap(),
+                message_body: body.to_owned(),
+                delay_seconds: None,
+                message_attributes: HashMap::new(),
+                message_deduplication_id: None,
+                message_group_id: None,
+                md5_of_message_body: None,
+            };
+            let service = service.clone();
+            async move { service.sqs_send(queue, request).await.unwrap() }
+        };
+        // Received messages are requeued at once, and dead-lettered on their second timeout.
+        let recv = |service: &Service, queue: &'static str, max_messages: u64| {
+            let service = service.clone();
+            async move {
+                let options = ReceiveOptions::builder()
+                    .max_messages(max_messages)
+                    .visibility_timeout(Duration::ZERO)
+                    .build();
+                service
+                    .sqs_recv_batch("t", queue, options)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|message| message.body)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        for body in ["a", "b", "c"] {
+            send(&service, queues[0], body).await;
+        }
+        send(&service, queues[1], "x").await;
+
+        // The order is kept in the database.
+        service.db().close().await;
+        let service = connect().await.unwrap();
+
+        assert_eq!(recv(&service, "in", 1).await, ["a"]);
+        send(&service, queues[0], "d").await;
+        // A requeued message keeps its place ahead of newer ones.
+        assert_eq!(recv(&service, "in", 10).await, ["a", "b", "c", "d"]);
+        assert_eq!(recv(&service, "in", 10).await, ["b", "c", "d"]);
+        assert!(recv(&service, "in", 10).await.is_empty());
+        // Dead-lettered messages are ordered by when they were sent to their source queue.
+        assert_eq!(recv(&service, "dlq", 10).await, ["a", "b", "c", "x", "d"]);
+
+        let ordering_mode = || async {
+            service
+                .get_queue_attributes("t", "in", &[], root())
+                .await
+                .unwrap()
+                .ordering_mode
+        };
+        assert_eq!(ordering_mode().await, Some(OrderingMode::Insertion));
+        let fair =
+            serde_json::from_value(serde_json::json!({ "FairReceiveKey": "MessageGroupId" }));
+        service
+            .set_queue_attributes("t", "in", fair.unwrap(), root())
+            .await
+            .unwrap();
+        assert_eq!(ordering_mode().await, Some(OrderingMode::Fair));
+
+        let read_only = serde_json::from_value(serde_json::json!({ "OrderingMode": "Fair" }));
+        assert!(matches!(
+            service
+                .set_queue_attributes("t", "in", read_only.unwrap(), root())
+                .await,
+            Err(Error::InvalidAttributeName { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_consumer_groups_receive_every_message() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "fan", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "fan", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let send = |body: &str| {
+            service.sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/fan".parse().unwrap(),
+                    message_body: body.to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+        };
+        let recv = |group: Option<&str>| {
+            service.sqs_recv_batch(
+                "t",
+                "fan",
+                ReceiveOptions::builder()
+                    .max_messages(10)
+                    .maybe_consumer_group(group)
+                    .build(),
+            )
+        };
+        let count = || async {
+            sqlx::query_scalar::<_, u64>("SELECT COUNT(*) FROM messages WHERE queue = $1")
+                .bind(queue as i64)
+                .fetch_one(service.db())
+                .await
+                .unwrap()
+        };
+        let bodies = |messages: Vec<SqsMessage>| {
+            messages
+                .into_iter()
+                .map(|message| (message.receipt_handle, message.body))
+                .collect::<Vec<_>>()
+        };
+
+        // The first group takes over the backlog, later groups only get newer messages.
+        send("1").await.unwrap();
+        service
+            .create_consumer_group("t", "fan", "a")
+            .await
+            .unwrap();
+        send("2").await.unwrap();
+        service
+            .create_consumer_group("t", "fan", "b")
+            .await
+            .unwrap();
+        send("3").await.unwrap();
+
+        assert!(matches!(
+            recv(None).await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        assert!(matches!(recv(Some("c")).await, Err(Error::NotFound { .. })));
+
+        let a = bodies(recv(Some("a")).await.unwrap());
+        let b = bodies(recv(Some("b")).await.unwrap());
+        assert_eq!(
+            a.iter().map(|(_, body)| body.as_str()).collect::<Vec<_>>(),
+            ["1", "2", "3"]
+        );
+        assert_eq!(b, [a[2].clone()]);
+        assert!(recv(Some("a")).await.unwrap().is_empty());
+
+        let pending = service
+            .list_consumer_groups("t", "fan")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|group| (group.name, group.pending))
+            .collect::<Vec<_>>();
+        assert_eq!(pending, [("a".to_owned(), 3), ("b".to_owned(), 1)]);
+
+        // A message is only removed once every group has deleted it.
+        let ids = |messages: &[(String, String)]| {
+            let handles = messages
+                .iter()
+                .map(|(handle, _)| handle.clone())
+                .collect::<Vec<_>>();
+            let service = &service;
+            async move {
+                service
+                    .resolve_receipt_handles("t", "fan", &handles)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(Option::unwrap)
+                    .collect::<Vec<_>>()
+            }
+        };
+        service
+            .delete_message("t", "fan", ids(&b[..1]).await[0], Some("b"), root())
+            .await
+            .unwrap();
+        assert_eq!(count().await, 3);
+
+        let (deleted, failed) = service
+            .delete_message_batch("t", "fan", ids(&a).await, Some("a"), root())
+            .await
+            .unwrap();
+        assert_eq!(deleted.len(), 3);
+        assert!(failed.is_empty());
+        assert_eq!(count().await, 0);
+
+        // Without groups, the queue is back to competing consumers.
+        send("4").await.unwrap();
+        for group in ["a", "b"] {
+            service
+                .delete_consumer_group("t", "fan", group)
+                .await
+                .unwrap();
+        }
+        assert_eq!(recv(None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fair_receive() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+
+        // Messages are grouped by their group ID, or by the value of a message attribute.
+        for (name, key) in [("by-group", "MessageGroupId"), ("by-tenant", "tenant")] {
+            service
+                .create_queue("t", name, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+            let fair = serde_json::from_value(serde_json::json!({ "FairReceiveKey": key }));
+            service
+                .set_queue_attributes("t", name, fair.unwrap(), root())
+                .await
+                .unwrap();
+            let queue = service
+                .get_queue_id("t", name, service.db())
+                .await
+                .unwrap()
+                .unwrap();
+
+            let send = |group: &str, body: &str| {
+                let (message_group_id, message_attributes) = match key {
+                    "MessageGroupId" => (Some(group.to_owned()), HashMap::new()),
+                    _ => (
+                        None,
+                        HashMap::from([(
+                            key.to_owned(),
+                            SqsMessageAttribute::String {
+                                string_value: group.to_owned(),
+                            },
+                        )]),
+                    ),
+                };
+                let request = SendMessageRequest {
+                    queue_url: format!("http://localhost:8080/t/{name}").parse().unwrap(),
+                    message_body: body.to_owned(),
+                    delay_seconds: None,
+                    message_attributes,
+                    message_deduplication_id: None,
+                    message_group_id,
+                    md5_of_message_body: None,
+                };
+                let service = service.clone();
+                async move { service.sqs_send(queue, request).await.unwrap() }
+            };
+            let recv = || {
+                let service = service.clone();
+                async move {
+                    let options = ReceiveOptions::builder()
+                        .max_messages(1)
+                        .visibility_timeout(Duration::from_secs(60))
+                        .build();
+                    let messages = service.sqs_recv_batch("t", name, options).await.unwrap();
+                    let [message] = <[SqsMessage; 1]>::try_from(messages).unwrap();
+                    message.body
+                }
+            };
+
+            for body in ["b1", "b2", "b3", "b4"] {
+                send("busy", body).await;
+            }
+            send("quiet", "q1").await;
+
+            // The quiet group doesn't wait for the burst of the busy one to be received.
+            assert_eq!(recv().await, "b1", "{name}");
+            assert_eq!(recv().await, "q1", "{name}");
+            send("quiet", "q2").await;
+            assert_eq!(recv().await, "b2", "{name}");
+            assert_eq!(recv().await, "q2", "{name}");
+            assert_eq!(recv().await, "b3", "{name}");
+            assert_eq!(recv().await, "b4", "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_receives() {
+        use crate::clock::ManualClock;
+
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        let attributes = HashMap::from([(
+            queue_attributes::MaxConcurrentReceives.name().to_owned(),
+            "2".to_owned(),
+        )]);
+        for name in ["capped", "grouped"] {
+            service
+                .create_queue("t", name, attributes.clone(), HashMap::new(), root())
+                .await
+                .unwrap();
+        }
+        service
+            .create_consumer_group("t", "grouped", "g")
+            .await
+            .unwrap();
+
+        // The cap applies to the queue's consumers, and to each consumer group of a queue.
+        for (name, group) in [("capped", None), ("grouped", Some("g"))] {
+            let queue = service
+                .get_queue_id("t", name, service.db())
+                .await
+                .unwrap()
+                .unwrap();
+            for i in 0..5 {
+                service
+                    .sqs_send(
+                        queue,
+                        SendMessageRequest {
+                            queue_url: format!("http://localhost:8080/t/{name}").parse().unwrap(),
+                            message_body: i.to_string(),
+                            delay_seconds: None,
+                            message_attributes: HashMap::new(),
+                            message_deduplication_id: None,
+                            message_group_id: None,
+                            md5_of_message_body: None,
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+            let recv = || {
+                service.sqs_recv_batch(
+                    "t",
+                    name,
+                    ReceiveOptions::builder()
+                        .max_messages(10)
+                        .visibility_timeout(Duration::from_secs(30))
+                        .maybe_consumer_group(group)
+                        .build(),
+                )
+            };
+
+            let received = recv().await.unwrap();
+            assert_eq!(received.len(), 2, "{name}");
+            assert!(recv().await.unwrap().is_empty(), "{name}");
+
+            // Deleting a message makes room for another.
+            let id = service
+                .resolve_receipt_handle("t", name, &received[0].receipt_handle)
+                .await
+                .unwrap();
+            service
+                .delete_message("t", name, id, group, root())
+                .await
+                .unwrap();
+            assert_eq!(recv().await.unwrap().len(), 1, "{name}");
+            assert!(recv().await.unwrap().is_empty(), "{name}");
+
+            // So do messages whose visibility timeout expires.
+            clock.advance(Duration::from_secs(30));
+            assert_eq!(recv().await.unwrap().len(), 2, "{name}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_compressed_bodies_round_trip() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue(
+                "t",
+                "zip",
+                HashMap::from([("compression_threshold".to_owned(), "64".to_owned())]),
+                HashMap::new(),
+                root(),
+            )
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "zip", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        let config = QueueConfig {
+            history: HistoryMode::Full,
+            ..service.get_queue_configuration(queue).await.unwrap()
+        };
+        service
+            .update_queue_configuration(queue, config)
+            .await
+            .unwrap();
+
+        let large = "{\"event\": \"page_view\"}".repeat(20);
+        for body in ["small", large.as_str()] {
+            service
+                .sqs_send(
+                    queue,
+                    SendMessageRequest {
+                        queue_url: "http://localhost:8080/t/zip".parse().unwrap(),
+                        message_body: body.to_owned(),
+                        delay_seconds: None,
+                        message_attributes: HashMap::new(),
+                        message_deduplication_id: None,
+                        message_group_id: None,
+                        md5_of_message_body: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        let compressed = sqlx::query_scalar::<_, bool>(
+            "SELECT compressed FROM messages WHERE queue = $1 ORDER BY id",
+        )
+        .bind(queue as i64)
+        .fetch_all(service.db())
+        .await
+        .unwrap();
+        assert_eq!(compressed, [false, true]);
+
+        let listed = service.list_messages("t", "zip").await.unwrap();
+        assert!(listed.iter().any(|message| message.body == large));
+
+        let received = service
+            .sqs_recv_batch(
+                "t",
+                "zip",
+                ReceiveOptions::builder().max_messages(10).build(),
+            )
+            .await
+            .unwrap();
+        let bodies = received.iter().map(|m| m.body.as_str()).collect::<Vec<_>>();
+        assert_eq!(bodies, ["small", large.as_str()]);
+        assert_eq!(
+            received[1].md5_of_body,
+            hex::encode(md5::compute(&large).as_slice())
+        );
+
+        service
+            .delete_message(
+                "t",
+                "zip",
+                service
+                    .resolve_receipt_handle("t", "zip", &received[1].receipt_handle)
+                    .await
+                    .unwrap(),
+                None,
+                root(),
+            )
+            .await
+            .unwrap();
+        let history = service
+            .list_message_history("t", "zip", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(history[0].body.as_deref(), Some(large.as_str()));
+        assert_eq!(
+            history[0].message_id.as_deref(),
+            Some(received[1].message_id.as_str())
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_receipt_handles() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        for queue in ["q", "other"] {
+            service
+                .create_queue("t", queue, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+            let queue_id = service
+                .get_queue_id("t", queue, service.db())
+                .await
+                .unwrap()
+                .unwrap();
+            for body in ["first", "second"] {
+                service
+                    .sqs_send(
+                        queue_id,
+                        SendMessageRequest {
+                            queue_url: format!("http://localhost:8080/t/{queue}").parse().unwrap(),
+                            message_body: body.to_owned(),
+                            delay_seconds: None,
+                            message_attributes: HashMap::new(),
+                            message_deduplication_id: None,
+                            message_group_id: None,
+                            md5_of_message_body: None,
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+        let recv = |queue: &'static str| {
+            let service = service.clone();
+            async move {
+                service
+                    .sqs_recv_batch(
+                        "t",
+                        queue,
+                        ReceiveOptions::builder().max_messages(10).build(),
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+        let received = recv("q").await;
+        let other = recv("other").await;
+
+        let valid = service
+            .resolve_receipt_handle("t", "q", &received[0].receipt_handle)
+            .await
+            .unwrap();
+        service
+            .delete_message("t", "q", valid, None, root())
+            .await
+            .unwrap();
+
+        // The handle of a deleted message, and one of a message in another queue, don't resolve.
+        let handles = [
+            received[1].receipt_handle.clone(),
+            received[0].receipt_handle.clone(),
+            other[0].receipt_handle.clone(),
+            "junk".to_owned(),
+        ];
+        let resolved = service
+            .resolve_receipt_handles("t", "q", &handles)
+            .await
+            .unwrap();
+        assert!(resolved[0].is_some());
+        assert_ne!(resolved[0], Some(valid));
+        assert_eq!(resolved[1..], [None, None, None]);
+
+        for handle in &handles[1..] {
+            assert!(matches!(
+                service.resolve_receipt_handle("t", "q", handle).await,
+                Err(Error::ReceiptHandleIsInvalid { .. })
+            ));
+        }
+        assert!(service
+            .resolve_receipt_handle("t", "other", &other[0].receipt_handle)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_returns_attribute_digests_and_sequence_numbers() {
+        use crate::types::send_message_batch::SendMessageBatchRequestEntry;
+
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        service
+            .create_queue("t", "other", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+
+        let entry = |id: &str, group: Option<&str>| SendMessageBatchRequestEntry {
+            id: id.to_owned(),
+            message_body: "hello".to_owned(),
+            delay_seconds: None,
+            message_attributes: HashMap::from([
+                (
+                    "b".to_owned(),
+                    SqsMessageAttribute::String {
+                        string_value: "2".to_owned(),
+                    },
+                ),
+                (
+                    "a".to_owned(),
+                    SqsMessageAttribute::Number {
+                        string_value: "1".to_owned(),
+                    },
+                ),
+            ]),
+            message_deduplication_id: None,
+            message_group_id: group.map(ToOwned::to_owned),
+            md5_of_message_body: None,
+        };
+        let res = service
+            .sqs_send_batch(
+                "t",
+                "q",
+                SendMessageBatchRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    entries: vec![
+                        entry("0", Some("g")),
+                        entry("1", Some("g")),
+                        entry("2", None),
+                    ],
+                },
+            )
+            .await
+            .unwrap();
+        assert!(res.failed.is_empty());
+
+        let sequence_numbers = res
+            .successful
+            .iter()
+            .map(|entry| {
+                entry
+                    .sequence_number
+                    .as_ref()
+                    .map(|n| n.parse::<u128>().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert!(sequence_numbers[0] < sequence_numbers[1]);
+        assert_eq!(sequence_numbers[2], None);
+        // Sequence numbers come from the message IDs, not the rows.
+        assert_eq!(
+            sequence_numbers[0],
+            Some(
+                uuid::Uuid::parse_str(&res.successful[0].message_id)
+                    .unwrap()
+                    .as_u128()
+            )
+        );
+
+        // The digest covers the attributes in order of their names, as when receiving.
+        let mut expected = Vec::new();
+        for (k, v) in entry("", None)
+            .message_attributes
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        {
+            v.serialize_into(&k, &mut expected);
+        }
+        let expected = checksum::md5_hex(expected);
+
+        let received = service
+            .sqs_recv(
+                "t",
+                "q",
+                ReceiveOptions::builder()
+                    .message_attribute_names(HashSet::from(["a".to_owned(), "b".to_owned()]))
+                    .build(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.md5_of_message_attributes, expected);
+        // Messages are known by their UUID, not their row id.
+        assert_eq!(received.message_id, res.successful[0].message_id);
+        assert!(uuid::Uuid::parse_str(&received.message_id).is_ok());
+
+        // So are receipt handles, which resolve to the message within its queue only.
+        assert_eq!(received.receipt_handle, received.message_id);
+        let id: u64 = sqlx::query_scalar("SELECT id FROM messages WHERE message_id = $1")
+            .bind(&received.message_id)
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        let handles = [
+            received.receipt_handle.clone(),
+            id.to_string(),
+            "nope".to_owned(),
+        ];
+        assert_eq!(
+            service
+                .resolve_receipt_handles("t", "q", &handles)
+                .await
+                .unwrap(),
+            [Some(id), None, None]
+        );
+        assert!(matches!(
+            service
+                .resolve_receipt_handle("t", "other", &received.receipt_handle)
+                .await,
+            Err(Error::ReceiptHandleIsInvalid { .. })
+        ));
+        for entry in &res.successful {
+            assert_eq!(entry.md5_of_message_attributes, expected);
+            assert_eq!(entry.md5_of_message_body, checksum::md5_hex("hello"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_enforces_message_limits() {
+        use crate::types::send_message_batch::SendMessageBatchRequestEntry;
+
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue(
+                "t",
+                "q",
+                HashMap::from([("max_message_size".to_owned(), "16".to_owned())]),
+                HashMap::new(),
+                root(),
+            )
+            .await
+            .unwrap();
+
+        let entry = |id: &str, body: &str, attribute: &str| SendMessageBatchRequestEntry {
+            id: id.to_owned(),
+            message_body: body.to_owned(),
+            delay_seconds: None,
+            message_attributes: HashMap::from([(
+                attribute.to_owned(),
+                SqsMessageAttribute::String {
+                    string_value: "v".to_owned(),
+                },
+            )]),
+            message_deduplication_id: None,
+            message_group_id: None,
+            md5_of_message_body: None,
+        };
+        let res = service
+            .sqs_send_batch(
+                "t",
+                "q",
+                SendMessageBatchRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    entries: vec![
+                        entry("ok", "hi", "a"),
+                        entry("name", "hi", "AWS.a"),
+                        // The attribute's name, data type and value add 8 bytes to the body's 11.
+                        entry("size", "hello world", "a"),
+                    ],
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.successful.len(), 1);
+        assert_eq!(res.successful[0].id, "ok");
+        let codes = res
+            .failed
+            .iter()
+            .map(|entry| (entry.id.as_str(), entry.code.as_str(), entry.sender_fault))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                ("name", "InvalidAttributeName", true),
+                ("size", "InvalidParameterValue", true),
+            ]
+        );
+
+        // Ingesting checks every message too, and sends none of them if one is too large.
+        let queue = service
+            .get_queue_id("t", "q", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            service
+                .ingest_messages(queue, vec!["hi".to_owned().into(), "x".repeat(17).into()])
+                .await,
+            Err(Error::MessageTooLong { size: 17, max: 16 })
+        ));
+        let count: u64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE queue = $1")
+            .bind(queue as i64)
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        service
+            .ingest_messages(queue, vec!["x".repeat(16).into()])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recv_with_selector() {
+        use crate::types::send_message_batch::SendMessageBatchRequestEntry;
+
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+
+        let entry = |id: &str, kind: Option<&str>, amount: &str| SendMessageBatchRequestEntry {
+            id: id.to_owned(),
+            message_body: id.to_owned(),
+            delay_seconds: None,
+            message_attributes: kind
+                .map(|kind| {
+                    (
+                        "type".to_owned(),
+                        SqsMessageAttribute::String {
+                            string_value: kind.to_owned(),
+                        },
+                    )
+                })
+                .into_iter()
+                .chain([(
+                    "amount".to_owned(),
+                    SqsMessageAttribute::Number {
+                        string_value: amount.to_owned(),
+                    },
+                )])
+                .collect(),
+            message_deduplication_id: None,
+            message_group_id: None,
+            md5_of_message_body: None,
+        };
+        service
+            .sqs_send_batch(
+                "t",
+                "q",
+                SendMessageBatchRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    entries: vec![
+                        entry("0", Some("invoice"), "5"),
+                        entry("1", Some("refund"), "50"),
+                        entry("2", Some("invoice"), "100"),
+                        entry("3", None, "500"),
+                    ],
+                },
+            )
+            .await
+            .unwrap();
+
+        let recv = |selector: &str| {
+            let options = ReceiveOptions::builder()
+                .max_messages(10)
+                .selector(Selector::parse(selector).unwrap())
+                .build();
+            let service = service.clone();
+            async move {
+                service
+                    .sqs_recv_batch("t", "q", options)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|message| message.body)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Numbers are compared numerically, not as text.
+        assert_eq!(recv("type = 'invoice' AND amount >= 10").await, ["2"]);
+        // Messages without the attribute never match a comparison with it.
+        assert_eq!(recv("NOT type = 'invoice'").await, ["1"]);
+        assert_eq!(recv("type IS NULL OR amount < 10").await, ["0", "3"]);
+        assert!(recv("type = 'invoice'").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queue_reports() {
+        use crate::types::send_message_batch::SendMessageBatchRequestEntry;
+
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+
+        let entry = |id: &str, kind: &str, body: String| SendMessageBatchRequestEntry {
+            id: id.to_owned(),
+            message_body: body,
+            delay_seconds: None,
+            message_attributes: HashMap::from([(
+                "type".to_owned(),
+                SqsMessageAttribute::String {
+                    string_value: kind.to_owned(),
+                },
+            )]),
+            message_deduplication_id: None,
+            message_group_id: None,
+            md5_of_message_body: None,
+        };
+        service
+            .sqs_send_batch(
+                "t",
+                "q",
+                SendMessageBatchRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    entries: vec![
+                        entry("0", "invoice", "a".to_owned()),
+                        entry("1", "refund", "b".to_owned()),
+                        entry("2", "invoice", "c".repeat(2048)),
+                    ],
+                },
+            )
+            .await
+            .unwrap();
+
+        let report = |kind, attribute, limit| {
+            let service = service.clone();
+            async move {
+                service
+                    .queue_report("t", "q", kind, attribute, limit)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|row| (row.label, row.count))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            report(ReportKind::TopAttributeValues, Some("type"), 1).await,
+            [("invoice".to_owned(), 2)]
+        );
+
+        let sizes = report(ReportKind::SizeHistogram, None, 0).await;
+        assert_eq!(sizes.len(), 5);
+        assert_eq!((sizes[0].1, sizes[1].1), (2, 1));
+
+        let ages = report(ReportKind::AgeHistogram, None, 0).await;
+        assert_eq!(ages[0].1, 3);
+        assert_eq!(ages.iter().map(|(_, count)| count).sum::<u64>(), 3);
+
+        assert!(matches!(
+            service
+                .queue_report("t", "q", ReportKind::TopAttributeValues, None, 10)
+                .await,
+            Err(Error::MissingParameter { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sample_masks_and_leaves_messages_pending() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue(
+                "t",
+                "q",
+                HashMap::from([("sample_masked_attributes".to_owned(), "email".to_owned())]),
+                HashMap::new(),
+                root(),
+            )
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "q", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        for i in 0..5 {
+            service
+                .sqs_send(
+                    queue,
+                    SendMessageRequest {
+                        queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                        message_body: format!(r#"{{"n":{i},"email":"user{i}@example.com"}}"#),
+                        delay_seconds: None,
+                        message_attributes: HashMap::from([(
+                            "email".to_owned(),
+                            SqsMessageAttribute::String {
+                                string_value: "user@example.com".to_owned(),
+                            },
+                        )]),
+                        message_deduplication_id: None,
+                        message_group_id: None,
+                        md5_of_message_body: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        let samples = service.sample_messages("t", "q", 3).await.unwrap();
+        assert_eq!(samples.len(), 3);
+        for sample in &samples {
+            let body: serde_json::Value = serde_json::from_str(&sample.body).unwrap();
+            assert_eq!(body["email"], MASKED_VALUE);
+            assert_eq!(sample.message_attributes["email"], MASKED_VALUE);
+        }
+
+        // Sampled messages are still there to be received.
+        let received = service
+            .sqs_recv_batch("t", "q", ReceiveOptions::builder().max_messages(10).build())
+            .await
+            .unwrap();
+        assert_eq!(received.len(), 5);
+    }
+
+    #[actix_web::test]
+    async fn test_extend_visibility() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "work", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "work", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        for body in ["a", "b", "c"] {
+            service
+                .sqs_send(
+                    queue,
+                    SendMessageRequest {
+                        queue_url: "http://localhost:8080/t/work".parse().unwrap(),
+                        message_body: body.to_owned(),
+                        delay_seconds: None,
+                        message_attributes: HashMap::new(),
+                        message_deduplication_id: None,
+                        message_group_id: None,
+                        md5_of_message_body: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        let handles = service
+            .sqs_recv_batch(
+                "t",
+                "work",
+                ReceiveOptions::builder().max_messages(2).build(),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.receipt_handle)
+            .collect::<Vec<_>>();
+        let received = service
+            .resolve_receipt_handles("t", "work", &handles)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Option::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(received.len(), 2);
+        let visible_at = || async {
+            sqlx::query_scalar::<_, Option<u64>>(
+                "SELECT visible_at FROM messages WHERE queue = $1 ORDER BY id",
+            )
+            .bind(queue as i64)
+            .fetch_all(service.db())
+            .await
+            .unwrap()
+        };
+        let before = visible_at().await;
+
+        let extend = |ids: Vec<u64>, timeout: Duration| {
+            let (service, root) = (&service, root());
+            async move {
+                service
+                    .extend_visibility("t", "work", &ids, timeout, None, root)
+                    .await
+            }
+        };
+
+        let until = extend(received.clone(), Duration::from_secs(600))
+            .await
+            .unwrap();
+        let after = visible_at().await;
+        assert_eq!(after[0], Some(until));
+        assert_eq!(after[1], Some(until));
+        assert!(after[0] > before[0]);
+
+        // A message that wasn't received fails the whole batch.
+        let pending = (1..=3).find(|id| !received.contains(id)).unwrap();
+        let mut ids = received.clone();
+        ids.push(pending);
+        assert!(matches!(
+            extend(ids, Duration::from_secs(1200)).await,
+            Err(Error::NotFound { .. })
+        ));
+        assert_eq!(visible_at().await, after);
+
+        assert!(matches!(
+            extend(vec![], Duration::from_secs(60)).await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            extend(received, MAX_VISIBILITY_TIMEOUT + Duration::from_secs(1)).await,
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_redelivery_backoff() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "work", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "work", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let set_backoff = |backoff: &str| {
+            let attributes =
+                serde_json::from_value(serde_json::json!({ "RedeliveryBackoff": backoff }))
+                    .unwrap();
+            service.set_queue_attributes("t", "work", attributes, root())
+        };
+        assert!(matches!(
+            set_backoff("1m").await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        set_backoff("60,300").await.unwrap();
+
+        service
+            .sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/work".parse().unwrap(),
+                    message_body: "poison".to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let recv = || {
+            service.sqs_recv(
+                "t",
+                "work",
+                ReceiveOptions::builder()
+                    .visibility_timeout(Duration::ZERO)
+                    .build(),
+            )
+        };
+        // Moves the message's visibility timeout back by `secs`.
+        let age = |secs: i64| {
+            sqlx::query("UPDATE messages SET visible_at = visible_at - $1 WHERE queue = $2")
+                .bind(secs)
+                .bind(queue as i64)
+                .execute(service.db())
+        };
+
+        assert!(recv().await.unwrap().is_some());
+
+        // The first redelivery waits for the first delay.
+        assert!(recv().await.unwrap().is_none());
+        age(61).await.unwrap();
+        assert!(recv().await.unwrap().is_some());
+
+        // Later redeliveries wait for the later delays.
+        age(61).await.unwrap();
+        assert!(recv().await.unwrap().is_none());
+        age(240).await.unwrap();
+        assert!(recv().await.unwrap().is_some());
+
+        // Without delays, the message is redelivered as soon as its timeout expires.
+        set_backoff("").await.unwrap();
+        assert!(recv().await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_purge_cooldown() {
+        use crate::clock::ManualClock;
+
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        for queue in ["a", "b"] {
+            service
+                .create_queue("t", queue, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+        }
+
+        service.purge_queue("t", "a", root()).await.unwrap();
+
+        clock.advance(Duration::from_secs(20));
+        let err = service.purge_queue("t", "a", root()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PurgeQueueInProgress { ref queue, retry_after }
+                if queue == "a" && retry_after == Duration::from_secs(40)
+        ));
+        assert_eq!(err.code(), "PurgeQueueInProgress");
+
+        // Other queues can still be purged.
+        service.purge_queue("t", "b", root()).await.unwrap();
+
+        clock.advance(Duration::from_secs(40));
+        service.purge_queue("t", "a", root()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_visibility_follows_service_clock() {
+        use crate::clock::ManualClock;
+
+        // Far enough in the past that the database's own clock would see every deadline as
+        // expired.
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let clock = ManualClock::new(start);
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "work", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "work", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+
+        service
+            .sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/work".parse().unwrap(),
+                    message_body: "hello".to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let recv = || {
+            service.sqs_recv(
+                "t",
+                "work",
+                ReceiveOptions::builder()
+                    .visibility_timeout(Duration::from_secs(30))
+                    .build(),
+            )
+        };
+        let deadline = || {
+            sqlx::query_as::<_, (i64, i64)>("SELECT sent_at, visible_at FROM messages")
+                .fetch_one(service.db())
+        };
+
+        assert!(recv().await.unwrap().is_some());
+        assert_eq!(deadline().await.unwrap(), (1_000_000_000, 1_000_000_030));
+        assert!(recv().await.unwrap().is_none());
+
+        // The wall clock being set back doesn't move the deadline, which is absolute.
+        clock.rewind(Duration::from_secs(3600));
+        assert!(recv().await.unwrap().is_none());
+        assert_eq!(deadline().await.unwrap().1, 1_000_000_030);
+
+        clock.set(start + Duration::from_secs(29));
+        assert!(recv().await.unwrap().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(recv().await.unwrap().is_some());
+        assert_eq!(deadline().await.unwrap().1, 1_000_000_060);
+
+        // Nor does it jumping ahead, after which the message is simply due.
+        clock.advance(Duration::from_secs(86_400));
+        assert!(recv().await.unwrap().is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_failure_reasons() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "work", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "work", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        let config = QueueConfig {
+            max_retries: 2,
+            ..service.get_queue_configuration(queue).await.unwrap()
+        };
+        service
+            .update_queue_configuration(queue, config)
+            .await
+            .unwrap();
+
+        service
+            .sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/work".parse().unwrap(),
+                    message_body: "poison".to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let give_up = |message_id: u64, reason: String| {
+            let (service, root) = (&service, root());
+            async move {
+                service
+                    .change_message_visibility(
+                        "t",
+                        "work",
+                        message_id,
+                        Duration::ZERO,
+                        Some(&reason),
+                        None,
+                        root,
+                    )
+                    .await
+            }
+        };
+
+        for attempt in 1..=2 {
+            let message = service
+                .sqs_recv("t", "work", ReceiveOptions::builder().build())
+                .await
+                .unwrap()
+                .unwrap();
+            let id = service
+                .resolve_receipt_handle("t", "work", &message.receipt_handle)
+                .await
+                .unwrap();
+
+            assert!(matches!(
+                give_up(id, "x".repeat(MAX_FAILURE_REASON_LENGTH + 1)).await,
+                Err(Error::InvalidParameter { .. })
+            ));
+            give_up(id, format!("attempt {attempt} failed"))
+                .await
+                .unwrap();
+
+            // The message is visible again, so it can't be given up on twice.
+            assert!(matches!(
+                give_up(id, "again".to_owned()).await,
+                Err(Error::NotFound { .. })
+            ));
+        }
+
+        let messages = service.list_messages("t", "work").await.unwrap();
+        assert_eq!(messages[0].tries, 2);
+        let failures = messages[0]
+            .failures
+            .iter()
+            .map(|failure| (failure.reason.as_str(), failure.tries))
+            .collect::<Vec<_>>();
+        assert_eq!(failures, [("attempt 2 failed", 2), ("attempt 1 failed", 1)]);
+    }
+
+    #[actix_web::test]
+    async fn test_ack_messages() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "work", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "work", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        let config = QueueConfig {
+            history: HistoryMode::Metadata,
+            ..service.get_queue_configuration(queue).await.unwrap()
+        };
+        service
+            .update_queue_configuration(queue, config)
+            .await
+            .unwrap();
+
+        for body in ["a", "b", "c"] {
+            service
+                .sqs_send(
+                    queue,
+                    SendMessageRequest {
+                        queue_url: "http://localhost:8080/t/work".parse().unwrap(),
+                        message_body: body.to_owned(),
+                        delay_seconds: None,
+                        message_attributes: HashMap::new(),
+                        message_deduplication_id: None,
+                        message_group_id: None,
+                        md5_of_message_body: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        let handles = service
+            .sqs_recv_batch(
+                "t",
+                "work",
+                ReceiveOptions::builder().max_messages(3).build(),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.receipt_handle)
+            .collect::<Vec<_>>();
+        let ids = service
+            .resolve_receipt_handles("t", "work", &handles)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Option::unwrap)
+            .sorted()
+            .collect::<Vec<_>>();
+
+        let outcome = |message_id: u64, success: bool, payload: serde_json::Value| Outcome {
+            message_id,
+            success,
+            payload: Some(payload),
+        };
+        let (applied, failed) = service
+            .ack_messages(
+                "t",
+                "work",
+                vec![
+                    outcome(ids[0], true, serde_json::json!({ "rows": 3 })),
+                    outcome(ids[1], false, serde_json::json!("upstream timed out")),
+                    outcome(ids[2], false, serde_json::json!("x".repeat(2000))),
+                    outcome(999, true, serde_json::Value::Null),
+                ],
+                None,
+                root(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(applied, [ids[0], ids[1]]);
+        assert_eq!(
+            failed.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            [ids[2], 999]
+        );
+        assert!(matches!(failed[0].1, Error::InvalidParameter { .. }));
+        assert!(matches!(failed[1].1, Error::NotFound { .. }));
+
+        // The successful message is deleted, with its payload kept in the history.
+        let history = service
+            .list_message_history("t", "work", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].result, Some(serde_json::json!({ "rows": 3 })));
+
+        // The failed message can be received again, and its failure is recorded.
+        let messages = service.list_messages("t", "work").await.unwrap();
+        let failed = messages.iter().find(|m| m.id == ids[1]).unwrap();
+        assert_eq!(failed.failures[0].reason, "upstream timed out");
+        let redelivered = service
+            .sqs_recv("t", "work", ReceiveOptions::builder().build())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(redelivered.receipt_handle, failed.message_id);
+    }
+}
@@ -0,0 +1,2704 @@
+//! Core service implementation for NerveMQ message queue system.
+//!
+//! This module implements the main service layer that provides:
+//!
+//! - Queue management (create, delete, list, purge)
+//! - Message operations (send, receive, delete)
+//! - Namespace management (create, delete, list)
+//! - User management and authentication
+//! - Statistics and monitoring
+//!
+//! # Key Types
+//!
+//! - [`Service`] - The main service struct that handles all operations
+//! - [`queues::QueueService`] - Namespace and queue operations, via [`Service::queues`]
+//! - [`messages::MessageService`] - Message operations, via [`Service::messages`]
+//! - [`users::UserService`] - User, credential and permission operations, via [`Service::users`]
+//! - [`QueueAttributes`] - Configuration options for queues
+//! - [`QueueConfig`] - Internal queue configuration
+//! - [`MessageDetails`] - Detailed message information
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use nervemq::service::Service;
+//!
+//! async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//!     // Connect to the service
+//!     let service = Service::connect().await?;
+//!
+//!     // Create a namespace
+//!     service.create_namespace("my-namespace", identity).await?;
+//!
+//!     // Create a queue
+//!     service.create_queue(
+//!         "my-namespace",
+//!         "my-queue",
+//!         HashMap::new(),
+//!         HashMap::new(),
+//!         identity
+//!     ).await?;
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+//! # Database Schema
+//!
+//! The service uses SQLite with the following main tables:
+//!
+//! - `namespaces` - Namespace definitions
+//! - `queues` - Queue definitions
+//! - `messages` - Message storage
+//! - `users` - User accounts
+//! - `user_permissions` - Access control
+//! - `queue_configurations` - Queue settings
+//! - `queue_attributes` - Queue attributes
+//! - `queue_tags` - Queue metadata
+//! - `kv_pairs` - Message attributes
+//!
+//! # Architecture
+//!
+//! The service implements an AWS SQS-compatible message queue with:
+//!
+//! - Multi-tenant support via namespaces
+//! - Role-based access control
+//! - Dead letter queues
+//! - Message attributes
+//! - Configurable retry policies
+//! - Queue tags and attributes
+//!
+pub mod messages;
+pub mod queues;
+pub mod users;
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use actix_identity::Identity;
+use ipnet::IpNet;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use serde_email::Email;
+use sqlx::{
+    sqlite::{
+        SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqliteLockingMode,
+        SqlitePoolOptions,
+    },
+    Acquire, FromRow, Sqlite, SqliteConnection, SqlitePool,
+};
+
+use self::{messages::MessageService, queues::QueueService, users::UserService};
+#[cfg(feature = "oidc")]
+use crate::jwt::JwtVerifier;
+use crate::{
+    ack::Outcome,
+    api::{auth::Role, tokens::CreateTokenResponse},
+    audit::{AuditEntry, AuditQuery, NewAuditEntry, DEFAULT_AUDIT_PAGE_SIZE, MAX_AUDIT_PAGE_SIZE},
+    auth::{
+        credential::{ApiKey, API_KEY_PREFIX},
+        crypto::{generate_api_key, generate_token, sha256_hex, GeneratedKey},
+        replay::NonceCache,
+        session::SessionInfo,
+    },
+    caching::ValidatorRegistry,
+    clock::{Clock, SystemClock},
+    config::{Config, MEMORY_DB_PATH},
+    consumer_group::ConsumerGroup,
+    consumers::{Consumer, UnattendedQueue, CONSUMER_TTL},
+    error::Error,
+    events::{EventBus, ADMIN_EVENTS},
+    history::{HistoryEntry, HistoryMode},
+    ingest::IngestMessage,
+    integrity,
+    key_export::{ImportResponse, KeyExport},
+    kms::{memory::InMemoryKeyManager, KeyManager},
+    message::{MessageFailure, MessageStatus},
+    message_move::MessageMoveTask,
+    migrate::{MigrationLock, MIGRATOR},
+    namespace::{Namespace, NamespaceLimits, NamespaceStatistics},
+    notify::{self, Notifier},
+    outbox::{CreateOutboxSourceRequest, OutboxRow, OutboxSource},
+    overview::{HealthTracker, Overview, Totals},
+    provision,
+    push::{PushSubscription, PushSubscriptionRequest, PushTarget},
+    queue::{Queue, QueueStatistics},
+    rate_limit::{BucketRegistry, RateLimit, RateLimitTarget},
+    report::{ReportKind, ReportRow},
+    sample::MessageSample,
+    selector::Selector,
+    sqs::types::SqsMessage,
+    telemetry::{NoopSink, TelemetrySink},
+    tls::{CertificateMatch, ClientCertificateInfo},
+    token_usage::{TokenInfo, TokenUsage},
+    trace::{Trace, TraceEvent},
+    types::{
+        send_message::{SendMessageRequest, SendMessageResponse},
+        send_message_batch::{SendMessageBatchRequest, SendMessageBatchResponse},
+    },
+    user_import::{ImportUser, ImportUsersResponse},
+    webhooks::{CreateWebhookRequest, CreateWebhookResponse, Webhook},
+};
+
+/// Configuration for dead-letter queue redrive policy.
+///
+/// This defines how failed messages should be moved to a dead-letter queue
+/// after exceeding the maximum number of receive attempts.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedrivePolicy {
+    /// The field is named ARN, but for NerveMQ we use the format `namespace:queue`
+    dead_letter_target_arn: String,
+    max_receive_count: u64,
+}
+
+/// Maximum number of source queues a `byQueue` redrive allow policy may list.
+pub const MAX_REDRIVE_SOURCE_QUEUES: usize = 10;
+
+/// Which queues may use a queue as their dead-letter queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RedrivePermission {
+    /// Any queue may use this queue as its dead-letter queue
+    AllowAll,
+    /// No queue may use this queue as its dead-letter queue
+    DenyAll,
+    /// Only the queues in `source_queue_arns` may use this queue as their dead-letter queue
+    ByQueue,
+}
+
+/// Restricts which source queues may target a queue in their [`RedrivePolicy`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedriveAllowPolicy {
+    redrive_permission: RedrivePermission,
+    /// Queues in the format `namespace:queue`. Only allowed with `byQueue`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    source_queue_arns: Vec<String>,
+}
+
+impl RedriveAllowPolicy {
+    /// Parses and validates a policy set as a queue attribute.
+    pub fn parse(policy: &str) -> Result<Self, Error> {
+        let policy: Self = serde_json::from_str(policy)
+            .map_err(|e| Error::invalid_parameter(format!("RedriveAllowPolicy: {e}")))?;
+
+        match policy.redrive_permission {
+            RedrivePermission::ByQueue if policy.source_queue_arns.is_empty() => {
+                Err(Error::invalid_parameter(
+                    "RedriveAllowPolicy: byQueue requires at least one source queue",
+                ))
+            }
+            RedrivePermission::ByQueue
+                if policy.source_queue_arns.len() > MAX_REDRIVE_SOURCE_QUEUES =>
+            {
+                Err(Error::invalid_parameter(format!(
+                    "RedriveAllowPolicy: at most {MAX_REDRIVE_SOURCE_QUEUES} source queues are allowed"
+                )))
+            }
+            RedrivePermission::AllowAll | RedrivePermission::DenyAll
+                if !policy.source_queue_arns.is_empty() =>
+            {
+                Err(Error::invalid_parameter(
+                    "RedriveAllowPolicy: sourceQueueArns is only allowed with byQueue",
+                ))
+            }
+            _ => Ok(policy),
+        }
+    }
+
+    /// Checks whether a source queue may use the queue with this policy as its dead-letter queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace of the source queue
+    /// * `queue` - Name of the source queue
+    pub fn allows(&self, namespace: &str, queue: &str) -> bool {
+        match self.redrive_permission {
+            RedrivePermission::AllowAll => true,
+            RedrivePermission::DenyAll => false,
+            RedrivePermission::ByQueue => self
+                .source_queue_arns
+                .iter()
+                .any(|arn| arn.split_once(':') == Some((namespace, queue))),
+        }
+    }
+}
+
+/// Order in which a queue delivers its messages, the read-only `OrderingMode` queue attribute.
+///
+/// Either way, a message keeps its position when it is requeued, because its visibility timeout
+/// ran out or was changed to zero, when it is moved to a dead-letter queue or back by a message
+/// move task, and across restarts. Messages received together are returned oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderingMode {
+    /// Visible messages are delivered in the order they were sent, oldest first. Messages moved
+    /// from another queue are ordered by when they were sent to that queue.
+    Insertion,
+    /// Visible messages are taken round-robin from the groups of the queue's `FairReceiveKey`,
+    /// in the order they were sent within each group
+    Fair,
+}
+
+/// Name of the read-only [`OrderingMode`] queue attribute.
+pub const ORDERING_MODE_ATTRIBUTE: &str = "OrderingMode";
+
+/// Configurable attributes for a queue.
+///
+/// These attributes control the queue's behavior including:
+/// - Message delay
+/// - Message size limits
+/// - Message retention
+/// - Visibility timeout
+/// - Consumer concurrency and fairness
+/// - Compression of message bodies
+/// - Masking of message samples
+/// - Delays before failed messages are redelivered
+/// - Dead letter queue configuration
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QueueAttributes {
+    pub delay_seconds: Option<u64>,
+    pub max_message_size: Option<u64>,
+    pub message_retention_period: Option<u64>,
+    pub receive_message_wait_time_seconds: Option<u64>,
+    pub visibility_timeout: Option<u64>,
+    /// Maximum number of messages that may be in flight (received but not yet deleted) at once,
+    /// or to each consumer group of the queue. 0 removes the limit.
+    pub max_concurrent_receives: Option<u64>,
+    /// Round-robin receives across groups of messages instead of strictly oldest first. Either
+    /// `MessageGroupId`, or the name of the message attribute to group by. Empty disables it.
+    pub fair_receive_key: Option<String>,
+    /// Bodies of at least this many bytes are stored compressed. Unset disables compression.
+    pub compression_threshold: Option<u64>,
+    /// Comma-separated names of attributes and JSON body fields that are masked in message
+    /// samples.
+    pub sample_masked_attributes: Option<String>,
+    /// Comma-separated delays in seconds before a message is redelivered after its first,
+    /// second, ... delivery timed out. The last delay repeats. Empty disables the delays.
+    pub redelivery_backoff: Option<String>,
+    /// Read-only, see [`OrderingMode`]
+    pub ordering_mode: Option<OrderingMode>,
+
+    pub redrive_policy: Option<RedrivePolicy /* Must be JSON serialized to a string */>,
+    pub redrive_allow_policy:
+        Option<RedriveAllowPolicy /* Must be JSON serialized to a string */>,
+
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QueueAttributesSer {
+    pub delay_seconds: Option<u64>,
+    #[serde(alias = "MaximumMessageSize")]
+    pub max_message_size: Option<u64>,
+    pub message_retention_period: Option<u64>,
+    pub receive_message_wait_time_seconds: Option<u64>,
+    pub visibility_timeout: Option<u64>,
+    pub max_concurrent_receives: Option<u64>,
+    pub fair_receive_key: Option<String>,
+    pub compression_threshold: Option<u64>,
+    pub sample_masked_attributes: Option<String>,
+    pub redelivery_backoff: Option<String>,
+    pub ordering_mode: Option<OrderingMode>,
+
+    pub redrive_policy: Option<String /* Must be JSON serialized to a string */>,
+    pub redrive_allow_policy: Option<String /* Must be JSON serialized to a string */>,
+
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+impl QueueAttributesSer {
+    pub fn deser(self) -> Result<QueueAttributes, Error> {
+        Ok(QueueAttributes {
+            delay_seconds: self.delay_seconds,
+            max_message_size: self.max_message_size,
+            message_retention_period: self.message_retention_period,
+            receive_message_wait_time_seconds: self.receive_message_wait_time_seconds,
+            visibility_timeout: self.visibility_timeout,
+            max_concurrent_receives: self.max_concurrent_receives,
+            fair_receive_key: self.fair_receive_key,
+            compression_threshold: self.compression_threshold,
+            sample_masked_attributes: self.sample_masked_attributes,
+            redelivery_backoff: self.redelivery_backoff,
+            ordering_mode: self.ordering_mode,
+            redrive_policy: self
+                .redrive_policy
+                .map(|rp| serde_json::from_str(&rp))
+                .transpose()?,
+            redrive_allow_policy: self
+                .redrive_allow_policy
+                .map(|rap| serde_json::from_str(&rap))
+                .transpose()?,
+            other: self.other,
+        })
+    }
+}
+
+impl QueueAttributes {
+    pub fn ser(self) -> Result<QueueAttributesSer, Error> {
+        Ok(QueueAttributesSer {
+            delay_seconds: self.delay_seconds,
+            max_message_size: self.max_message_size,
+            message_retention_period: self.message_retention_period,
+            receive_message_wait_time_seconds: self.receive_message_wait_time_seconds,
+            visibility_timeout: self.visibility_timeout,
+            max_concurrent_receives: self.max_concurrent_receives,
+            fair_receive_key: self.fair_receive_key,
+            compression_threshold: self.compression_threshold,
+            sample_masked_attributes: self.sample_masked_attributes,
+            redelivery_backoff: self.redelivery_backoff,
+            ordering_mode: self.ordering_mode,
+            redrive_policy: self
+                .redrive_policy
+                .map(|rp| serde_json::to_string(&rp))
+                .transpose()?,
+            redrive_allow_policy: self
+                .redrive_allow_policy
+                .map(|rap| serde_json::to_string(&rap))
+                .transpose()?,
+            other: self.other,
+        })
+    }
+}
+
+/// Trait for type-safe queue attributes.
+///
+/// Used to define queue attribute names and types for extraction from
+/// the database.
+#[allow(unused)]
+pub trait QueueAttribute {
+    type Value;
+
+    /// Returns the name of the queue attribute's column in the database.
+    fn name(&self) -> &str;
+}
+
+#[allow(unused)]
+pub(crate) mod queue_attributes {
+    use super::QueueAttribute;
+
+    /// Represents the delay_seconds queue attribute.
+    pub struct DelaySeconds;
+
+    impl QueueAttribute for DelaySeconds {
+        type Value = u64;
+
+        fn name(&self) -> &str {
+            "delay_seconds"
+        }
+    }
+
+    /// Represents the max_message_size queue
+    pub struct MaxMessageSize;
+
+    impl QueueAttribute for MaxMessageSize {
+        type Value = u64;
+
+        fn name(&self) -> &str {
+            "max_message_size"
+        }
+    }
+
+    /// Represents the message_retention_period queue attribute.
+    pub struct MessageRetentionPeriod;
+
+    impl QueueAttribute for MessageRetentionPeriod {
+        type Value = u64;
+        fn name(&self) -> &str {
+            "message_retention_period"
+        }
+    }
+
+    /// Represents the receive_message_wait_time_seconds queue attribute.
+    pub struct ReceiveMessageWaitTimeSeconds;
+
+    impl QueueAttribute for ReceiveMessageWaitTimeSeconds {
+        type Value = u64;
+        fn name(&self) -> &str {
+            "receive_message_wait_time_seconds"
+        }
+    }
+
+    /// Represents the visibility_timeout queue.
+    pub struct VisibilityTimeout;
+
+    impl QueueAttribute for VisibilityTimeout {
+        type Value = u64;
+        fn name(&self) -> &str {
+            "visibility_timeout"
+        }
+    }
+
+    /// Represents the max_concurrent_receives queue attribute.
+    pub struct MaxConcurrentReceives;
+
+    impl QueueAttribute for MaxConcurrentReceives {
+        type Value = u64;
+        fn name(&self) -> &str {
+            "max_concurrent_receives"
+        }
+    }
+
+    /// Represents the fair_receive_key queue attribute.
+    pub struct FairReceiveKey;
+
+    impl QueueAttribute for FairReceiveKey {
+        type Value = String;
+        fn name(&self) -> &str {
+            "fair_receive_key"
+        }
+    }
+
+    /// Represents the compression_threshold queue attribute.
+    pub struct CompressionThreshold;
+
+    impl QueueAttribute for CompressionThreshold {
+        type Value = u64;
+        fn name(&self) -> &str {
+            "compression_threshold"
+        }
+    }
+
+    /// Represents the sample_masked_attributes queue attribute.
+    pub struct SampleMaskedAttributes;
+
+    impl QueueAttribute for SampleMaskedAttributes {
+        type Value = String;
+        fn name(&self) -> &str {
+            "sample_masked_attributes"
+        }
+    }
+
+    /// Represents the redelivery_backoff queue attribute.
+    pub struct RedeliveryBackoff;
+
+    impl QueueAttribute for RedeliveryBackoff {
+        type Value = String;
+        fn name(&self) -> &str {
+            "redelivery_backoff"
+        }
+    }
+
+    /// Represents the redrive_policy queue attribute.
+    pub struct RedrivePolicy;
+
+    impl QueueAttribute for RedrivePolicy {
+        type Value = String;
+
+        fn name(&self) -> &str {
+            "redrive_policy"
+        }
+    }
+
+    /// Represents the redrive_allow_policy queue attribute.
+    pub struct RedriveAllowPolicy;
+
+    impl QueueAttribute for RedriveAllowPolicy {
+        type Value = String;
+
+        fn name(&self) -> &str {
+            "redrive_allow_policy"
+        }
+    }
+
+    /// Represents an arbitrary stringly-typed queue attribute.
+    pub struct Other(String);
+
+    impl QueueAttribute for Other {
+        type Value = String;
+
+        fn name(&self) -> &str {
+            &self.0
+        }
+    }
+}
+
+/// Internal configuration for a queue stored in the database.
+///
+/// Contains:
+/// - Queue ID
+/// - Maximum retry attempts
+/// - Optional dead letter queue ID
+/// - Message history mode and retention (in seconds)
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct QueueConfig {
+    pub queue: u64,
+    pub max_retries: u64,
+    pub dead_letter_queue: Option<u64>,
+    pub history: HistoryMode,
+    pub history_retention: u64,
+}
+
+/// Represents the details of a message for display in the UI.
+/// Detailed information about a message for display in the UI.
+///
+/// Includes:
+/// - Message ID and queue
+/// - Delivery status and attempts
+/// - Message body and attributes
+/// - Timestamps
+#[derive(Debug, Serialize)]
+pub struct MessageDetails {
+    pub id: u64,
+    pub message_id: String,
+    pub queue: String,
+
+    pub delivered_at: Option<u64>,
+    pub sent_by: Option<u64>,
+    pub body: String,
+    pub tries: u64,
+    pub trace_id: Option<String>,
+
+    pub status: MessageStatus,
+
+    pub message_attributes: HashMap<String, serde_json::Value>,
+    /// Most recent failures reported by consumers, newest first
+    pub failures: Vec<MessageFailure>,
+}
+
+/// Default time a received message stays hidden from other consumers, if neither the request
+/// nor the queue's `visibility_timeout` attribute specify one.
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum visibility timeout of a received message (12 hours, as in SQS).
+pub const MAX_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Maximum number of messages whose visibility can be extended in one call to
+/// [`Service::extend_visibility`].
+pub const MAX_VISIBILITY_EXTENSION_BATCH: usize = 1000;
+
+/// How long a request waits for a free database connection before it is turned away with
+/// [`Error::Overloaded`].
+pub const DB_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a query waits for another connection to release its lock on the database before it
+/// fails with [`Error::Overloaded`].
+pub const DB_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a `MessageDeduplicationId` keeps a message from being sent again, as in SQS.
+pub const DEDUPLICATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long after a purge a queue can be purged again, as in SQS.
+pub const PURGE_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How long an invitation to set a password stays valid.
+pub const INVITATION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long a password reset token stays valid.
+pub const PASSWORD_RESET_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of password resets that can be requested for a user per
+/// [`PASSWORD_RESET_WINDOW`]. Further requests are ignored.
+pub const PASSWORD_RESET_LIMIT: u64 = 3;
+
+/// Window over which [`PASSWORD_RESET_LIMIT`] applies.
+pub const PASSWORD_RESET_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Name of the root admin's API token installed from [`Config::bootstrap_token`].
+pub const BOOTSTRAP_TOKEN_NAME: &str = "bootstrap";
+
+/// [`Config::bootstrap_token`] value that generates a bootstrap token on first run.
+pub const GENERATE_BOOTSTRAP_TOKEN: &str = "generate";
+
+/// Writes a secret to a file that only the current user can read.
+pub(crate) fn write_private_file(path: &str, contents: &str) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(Error::internal)
+}
+
+/// Builds the SQL expression for the fair receive group of message `m`, given the parameter that
+/// holds the queue's fair receive key. Messages without a value share the empty group.
+fn fair_group_key(key: &str) -> String {
+    format!(
+        "IFNULL(CASE
+            WHEN {key} = 'MessageGroupId' THEN m.group_id
+            ELSE (SELECT CAST(v AS TEXT) FROM kv_pairs WHERE message = m.id AND k = {key})
+        END, '')"
+    )
+}
+
+/// Builds the SQL expression for the delay, in seconds, before a message that has been delivered
+/// `tries` times can be received again, given the parameter that holds the queue's redelivery
+/// delays as a JSON array. Without delays, it is 0.
+fn redelivery_delay(tries: &str, delays: &str) -> String {
+    format!(
+        "IFNULL(json_extract(
+            {delays},
+            '$[' || (MAX(MIN({tries}, json_array_length({delays})), 1) - 1) || ']'
+        ), 0)"
+    )
+}
+
+/// Returns the condition (starting with `AND`) that restricts a receive to the messages matching
+/// its selector, and the parameters to bind to `param` for it. Receives without a selector get an
+/// empty condition.
+fn selector_condition(options: &ReceiveOptions, param: &str) -> (String, Option<String>) {
+    match &options.selector {
+        Some(selector) => {
+            let (condition, params) = selector.to_sql(param);
+            (format!("AND {condition}"), Some(params))
+        }
+        None => (String::new(), None),
+    }
+}
+
+/// How often an empty queue is polled again while a receive is waiting for messages.
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Options for receiving messages from a queue.
+///
+/// Options that are not set fall back to the queue's attributes, then to the SQS defaults.
+#[derive(Debug, Clone, bon::Builder)]
+pub struct ReceiveOptions {
+    /// Maximum number of messages to receive.
+    #[builder(default = 1)]
+    pub max_messages: u64,
+    /// How long received messages stay hidden before they can be received again.
+    pub visibility_timeout: Option<Duration>,
+    /// How long to wait for messages to arrive if the queue is empty.
+    pub wait_time: Option<Duration>,
+    /// System attributes to include with each message (`All` includes every attribute).
+    #[builder(default)]
+    pub attribute_names: HashSet<String>,
+    /// Names of the message attributes to include with each message. As in SQS, `All` and `.*`
+    /// include every attribute, and a name ending in `.*`, like `order.*`, every attribute
+    /// starting with the part before the `*`.
+    #[builder(default)]
+    pub message_attribute_names: HashSet<String>,
+    /// Consumer group to receive the messages for, see [`crate::consumer_group`].
+    #[builder(into)]
+    pub consumer_group: Option<String>,
+    /// Only receive the messages whose attributes match this selector, see [`crate::selector`].
+    pub selector: Option<Selector>,
+}
+
+impl ReceiveOptions {
+    /// Whether a system attribute is to be included with each message.
+    fn includes_attribute(&self, name: &str) -> bool {
+        self.attribute_names.contains("All") || self.attribute_names.contains(name)
+    }
+
+    /// Whether a message attribute is to be included with each message.
+    fn includes_message_attribute(&self, name: &str) -> bool {
+        self.message_attribute_names
+            .iter()
+            .any(|requested| match requested.as_str() {
+                "All" | ".*" => true,
+                requested => match requested.strip_suffix('*') {
+                    Some(prefix) if prefix.ends_with('.') => name.starts_with(prefix),
+                    _ => requested == name,
+                },
+            })
+    }
+}
+
+/// Main service struct that handles all queue operations.
+///
+/// The service manages:
+/// - Queue and message operations
+/// - User authentication and authorization
+/// - Database connections
+/// - Key management for encryption
+#[derive(Clone)]
+pub struct Service {
+    kms: Arc<dyn KeyManager>,
+    notifier: Arc<dyn Notifier>,
+    events: EventBus,
+    maintenance: Arc<AtomicBool>,
+    token_usage: TokenUsage,
+    nonces: NonceCache,
+    health: HealthTracker,
+    cache_validators: ValidatorRegistry,
+    rate_limit_buckets: BucketRegistry,
+    clock: Arc<dyn Clock>,
+    telemetry: Arc<dyn TelemetrySink>,
+    #[cfg(feature = "oidc")]
+    jwt: Option<JwtVerifier>,
+    /// SHA-256 of the SCIM bearer token, see [`crate::scim`]
+    scim_token: Option<String>,
+    db: SqlitePool,
+    config: Arc<crate::config::Config>,
+}
+
+// Every worker of the HTTP server and every background task shares one service, so its state
+// must be shared between threads as well, see [`crate::registry`].
+const _: () = {
+    const fn assert_shared<T: Send + Sync + 'static>() {}
+    assert_shared::<Service>();
+};
+
+#[bon::bon]
+impl Service {
+    /// Returns a reference to the underlying SQLite connection pool.
+    pub fn db(&self) -> &SqlitePool {
+        &self.db
+    }
+
+    /// Operations on namespaces and queues.
+    pub fn queues(&self) -> QueueService<'_> {
+        QueueService::new(self)
+    }
+
+    /// Operations on the messages of queues.
+    pub fn messages(&self) -> MessageService<'_> {
+        MessageService::new(self)
+    }
+
+    /// Operations on users, their credentials and their permissions.
+    pub fn users(&self) -> UserService<'_> {
+        UserService::new(self)
+    }
+
+    /// Creates a new Service instance with default configuration and in-memory key management.
+    ///
+    /// Mostly useful for tests and debugging.
+    #[allow(unused)]
+    pub async fn connect() -> Result<Self, Error> {
+        Self::connect_with()
+            .config(Config::default())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+    }
+
+    /// Returns a reference to the service configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the bus that service events are published on.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Returns the tracker of the server's health, see [`crate::overview`].
+    pub fn health(&self) -> &HealthTracker {
+        &self.health
+    }
+
+    /// Returns the validators of recent responses, see [`crate::caching`].
+    pub fn cache_validators(&self) -> &ValidatorRegistry {
+        &self.cache_validators
+    }
+
+    /// Returns the token buckets of rate limits kept in memory, see [`crate::rate_limit`].
+    pub fn rate_limit_buckets(&self) -> &BucketRegistry {
+        &self.rate_limit_buckets
+    }
+
+    /// Returns the nonces of recently signed requests, see [`crate::auth::replay`].
+    pub fn nonces(&self) -> &NonceCache {
+        &self.nonces
+    }
+
+    /// Returns the clock the service tells time with, see [`crate::clock`].
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Returns the current time of the service's clock, as a Unix timestamp in seconds.
+    pub(crate) fn now(&self) -> i64 {
+        self.clock.unix_now()
+    }
+
+    /// Returns the sink telemetry events are reported to, see [`crate::telemetry`].
+    pub fn telemetry(&self) -> &dyn TelemetrySink {
+        self.telemetry.as_ref()
+    }
+
+    /// Returns the verifier of bearer tokens, or `None` if they are disabled, see [`crate::jwt`].
+    #[cfg(feature = "oidc")]
+    pub fn jwt(&self) -> Option<&JwtVerifier> {
+        self.jwt.as_ref()
+    }
+
+    /// Checks a bearer token against the SCIM token, see [`crate::scim`].
+    ///
+    /// # Returns
+    /// `false` if the token is wrong, or the SCIM API is disabled
+    pub fn check_scim_token(&self, token: &str) -> bool {
+        self.scim_token
+            .as_deref()
+            .is_some_and(|expected| expected == sha256_hex(token.as_bytes()))
+    }
+
+    /// Returns whether the service is in maintenance mode.
+    pub fn maintenance_mode(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables maintenance mode.
+    ///
+    /// While in maintenance mode, messages can't be sent, received, deleted or purged, and the
+    /// background tasks that write to the database are paused, so that the database can be
+    /// safely backed up or migrated. The management API keeps working.
+    pub fn set_maintenance_mode(&self, enabled: bool) {
+        if self.maintenance.swap(enabled, Ordering::Relaxed) != enabled {
+            tracing::info!(target: "nervemq::audit", enabled, "Maintenance mode changed");
+        }
+    }
+
+    /// Fails with [`Error::Unavailable`] while the service is in maintenance mode.
+    fn ensure_available(&self) -> Result<(), Error> {
+        if self.maintenance_mode() {
+            return Err(Error::unavailable(
+                "server is in maintenance mode",
+                self.config().retry_after(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the options the service opens its database with.
+    ///
+    /// # Errors
+    /// Returns an error if a key is given but SQLCipher support is not compiled in, since the
+    /// database would silently be left unencrypted
+    pub(crate) fn connect_options(
+        db_path: &str,
+        db_key: Option<&SecretString>,
+    ) -> Result<SqliteConnectOptions, Error> {
+        if db_path == MEMORY_DB_PATH {
+            // Every `sqlite::memory:` URL names a distinct shared-cache database, which stays
+            // alive for as long as the pool keeps a connection to it open.
+            return Ok(
+                with_db_key(SqliteConnectOptions::from_str("sqlite::memory:")?, db_key)?
+                    .foreign_keys(true),
+            );
+        }
+
+        Ok(with_db_key(SqliteConnectOptions::new(), db_key)?
+            .filename(db_path)
+            .busy_timeout(DB_BUSY_TIMEOUT)
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .locking_mode(SqliteLockingMode::Normal)
+            .optimize_on_close(true, None)
+            .auto_vacuum(SqliteAutoVacuum::Full))
+    }
+
+    /// Creates a new Service instance with custom configuration and key management.
+    ///
+    /// # Arguments
+    /// * `config` - Custom service configuration
+    /// * `kms_factory` - Factory function to create a key management service
+    /// * `db_key` - Passphrase the database is encrypted with, overriding the configured one.
+    ///   Useful for keys derived through a [`KeyManager`] with [`crate::kms::database_key`].
+    /// * `clock` - Clock to tell time with, a [`SystemClock`] by default
+    /// * `telemetry` - Sink to report telemetry events to, a [`NoopSink`] by default
+    /// * `notifier` - Notifier to send invitations and password resets through, the one the
+    ///   configuration selects by default
+    #[builder]
+    pub async fn connect_with<K, F, R>(
+        config: Config,
+        kms_factory: F,
+        db_key: Option<SecretString>,
+        clock: Option<Arc<dyn Clock>>,
+        telemetry: Option<Arc<dyn TelemetrySink>>,
+        notifier: Option<Arc<dyn Notifier>>,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce(SqlitePool) -> R,
+        R: Future<Output = Result<K, Error>>,
+        K: KeyManager,
+    {
+        let db_key = match db_key {
+            Some(db_key) => Some(db_key),
+            None => config.db_key()?,
+        };
+
+        integrity::verify(&config, db_key.as_ref()).await?;
+
+        let options = Self::connect_options(config.db_path(), db_key.as_ref())?;
+
+        let pool = if config.is_in_memory() {
+            let pool = SqlitePoolOptions::new()
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(options)
+                .await?;
+            // Nobody else can see the database, so there is nothing to lock against.
+            MIGRATOR.run(&pool).await?;
+            pool
+        } else {
+            let pool = SqlitePoolOptions::new()
+                .acquire_timeout(DB_ACQUIRE_TIMEOUT)
+                .connect_with(options)
+                .await?;
+            let _lock = MigrationLock::acquire(config.db_path()).await?;
+            MIGRATOR.run(&pool).await?;
+            pool
+        };
+
+        #[cfg(not(feature = "oidc"))]
+        if config.jwt_jwks_url().is_some() {
+            return Err(Error::FeatureUnsupported {
+                setting: "A JWKS URL for bearer tokens",
+                feature: "oidc",
+            });
+        }
+
+        let kms = kms_factory(pool.clone()).await?;
+        let notifier = match notifier {
+            Some(notifier) => notifier,
+            None => Arc::from(notify::from_config(&config)?),
+        };
+
+        let svc = Self {
+            kms: Arc::new(kms),
+            notifier,
+            events: EventBus::new(),
+            maintenance: Arc::new(AtomicBool::new(config.maintenance_mode())),
+            token_usage: TokenUsage::default(),
+            nonces: NonceCache::default(),
+            health: HealthTracker::default(),
+            cache_validators: ValidatorRegistry::new(),
+            rate_limit_buckets: BucketRegistry::new(),
+            clock: clock.unwrap_or_else(|| Arc::new(SystemClock::new())),
+            telemetry: telemetry.unwrap_or_else(|| Arc::new(NoopSink)),
+            #[cfg(feature = "oidc")]
+            jwt: config.jwt_settings().map(JwtVerifier::new),
+            scim_token: config
+                .scim_token()?
+                .map(|token| sha256_hex(token.expose_secret().as_bytes())),
+            db: pool,
+            config: Arc::new(config),
+        };
+
+        match svc
+            .create_user(
+                Email::from_str(svc.config.root_email()).map_err(Error::internal)?,
+                svc.config().root_password().to_owned(),
+                Some(Role::Admin),
+                vec![],
+            )
+            .await
+        {
+            Ok(_) => {
+                tracing::info!("Root user created");
+            }
+            Err(e) => match e {
+                Error::Sqlx { source } => match source {
+                    sqlx::Error::Database(db_err) => match db_err.kind() {
+                        sqlx::error::ErrorKind::UniqueViolation => {
+                            tracing::info!("Root user already exists");
+                        }
+                        _ => tracing::warn!("{db_err}"),
+                    },
+                    other => tracing::warn!("{other}"),
+                },
+                other => tracing::warn!("{other}"),
+            },
+        };
+
+        svc.install_bootstrap_token().await?;
+
+        if let Some(path) = svc.config.provision_file() {
+            let provision = provision::Provision::load(Path::new(path))?;
+            provision::apply(&svc, &provision).await?;
+        }
+
+        Ok(svc)
+    }
+
+    /// Installs the root admin's bootstrap API token, if one is configured.
+    ///
+    /// A configured token replaces any earlier bootstrap token, so it can be rotated by
+    /// restarting with a new one. A generated token is only created if there is no bootstrap
+    /// token yet, and is emitted once, to the configured file or the logs.
+    async fn install_bootstrap_token(&self) -> Result<(), Error> {
+        let Some(token) = self.config.bootstrap_token() else {
+            return Ok(());
+        };
+
+        let root = self.config.root_email().to_owned();
+        let namespace = self.config.bootstrap_namespace().to_owned();
+
+        if self
+            .get_namespace_id(&namespace, self.db())
+            .await?
+            .is_none()
+        {
+            self.create_namespace(&namespace, Identity::mock(root.clone()))
+                .await?;
+        }
+
+        if token.expose_secret() != GENERATE_BOOTSTRAP_TOKEN {
+            let key = ApiKey::parse(token.expose_secret())?;
+            if self
+                .install_token(BOOTSTRAP_TOKEN_NAME, &namespace, &root, key)
+                .await?
+            {
+                tracing::info!(
+                    target: "nervemq::audit",
+                    namespace,
+                    "Installed bootstrap API token"
+                );
+            }
+            return Ok(());
+        }
+
+        if self
+            .find_token(&root, BOOTSTRAP_TOKEN_NAME)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let key = tokio::task::spawn_blocking(generate_api_key)
+            .await
+            .map_err(Error::internal)?
+            .map_err(Error::internal)?;
+
+        let created = self
+            .replace_token(BOOTSTRAP_TOKEN_NAME, &namespace, &root, key)
+            .await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace,
+            key_id = created.access_key,
+            "Generated bootstrap API token"
+        );
+
+        let token = format!(
+            "{API_KEY_PREFIX}_{}_{}",
+            created.access_key, created.secret_key
+        );
+
+        match self.config.bootstrap_token_file() {
+            Some(path) => {
+                write_private_file(path, &token)?;
+                tracing::info!(path, "Wrote generated bootstrap API token");
+            }
+            None => tracing::warn!(
+                token,
+                "Generated bootstrap API token. It won't be shown again, so store it now"
+            ),
+        }
+
+        Ok(())
+    }
+
+    pub fn kms(&self) -> &dyn KeyManager {
+        self.kms.as_ref()
+    }
+
+    /// Subscribes a URL to administrative events, see [`crate::webhooks`].
+    ///
+    /// # Arguments
+    /// * `request` - URL and event types to subscribe to
+    ///
+    /// # Returns
+    /// The webhook, with the secret its deliveries are signed with
+    pub async fn create_webhook(
+        &self,
+        request: CreateWebhookRequest,
+    ) -> Result<CreateWebhookResponse, Error> {
+        if !matches!(request.url.scheme(), "http" | "https") {
+            return Err(Error::invalid_parameter(
+                "url: must be an HTTP or HTTPS URL",
+            ));
+        }
+
+        if let Some(events) = &request.events {
+            if events.is_empty() {
+                return Err(Error::invalid_parameter(
+                    "events: must not be empty, use null for every event",
+                ));
+            }
+            if let Some(unknown) = events
+                .iter()
+                .find(|kind| !ADMIN_EVENTS.contains(&kind.as_str()))
+            {
+                return Err(Error::invalid_parameter(format!(
+                    "events: unknown event type {unknown}, expected one of {}",
+                    ADMIN_EVENTS.join(", ")
+                )));
+            }
+        }
+
+        let secret = generate_token::<24>(rand::thread_rng())?;
+
+        let webhook: Webhook = sqlx::query_as(
+            "
+            INSERT INTO admin_webhooks (url, secret, events, created_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            ",
+        )
+        .bind(request.url.as_str())
+        .bind(&secret)
+        .bind(request.events.map(sqlx::types::Json))
+        .bind(self.now())
+        .fetch_one(self.db())
+        .await?;
+
+        tracing::info!(target: "nervemq::audit", webhook = webhook.id, url = webhook.url, "Webhook created");
+
+        Ok(CreateWebhookResponse { webhook, secret })
+    }
+
+    /// Lists the subscriptions to administrative events.
+    pub async fn list_webhooks(&self) -> Result<Vec<Webhook>, Error> {
+        Ok(sqlx::query_as("SELECT * FROM admin_webhooks ORDER BY id")
+            .fetch_all(self.db())
+            .await?)
+    }
+
+    /// Deletes a subscription to administrative events. Deliveries already being retried still
+    /// run to completion.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the webhook
+    pub async fn delete_webhook(&self, id: u64) -> Result<(), Error> {
+        let deleted = sqlx::query("DELETE FROM admin_webhooks WHERE id = $1")
+            .bind(id as i64)
+            .execute(self.db())
+            .await?
+            .rows_affected();
+
+        if deleted == 0 {
+            return Err(Error::not_found(format!("webhook {id}")));
+        }
+
+        tracing::info!(target: "nervemq::audit", webhook = id, "Webhook deleted");
+
+        Ok(())
+    }
+
+    /// Gets the rate limit an admin set for a namespace or API key.
+    ///
+    /// # Returns
+    /// The limit, or `None` if the configured default applies
+    ///
+    /// # Errors
+    /// * `Error::NotFound` - If the namespace or API key doesn't exist
+    pub async fn rate_limit(
+        &self,
+        target: RateLimitTarget<'_>,
+    ) -> Result<Option<RateLimit>, Error> {
+        let mut db = self.db().acquire().await?;
+
+        let (namespace, api_key) = self.rate_limit_target_ids(target, &mut db).await?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT requests_per_second, burst FROM rate_limits
+            WHERE namespace = $1 OR api_key = $2
+            ",
+        )
+        .bind(namespace.map(|id| id as i64))
+        .bind(api_key.map(|id| id as i64))
+        .fetch_optional(&mut *db)
+        .await?)
+    }
+
+    /// Sets the rate limit of a namespace's API keys and users, or of a single API key, or with
+    /// `None`, goes back to the configured default.
+    ///
+    /// # Errors
+    /// * `Error::NotFound` - If the namespace or API key doesn't exist
+    /// * `Error::InvalidParameter` - If the limit wouldn't let any requests through
+    pub async fn set_rate_limit(
+        &self,
+        target: RateLimitTarget<'_>,
+        limit: Option<RateLimit>,
+    ) -> Result<(), Error> {
+        if let Some(limit) = &limit {
+            limit.validate()?;
+        }
+
+        let mut tx = self.db().begin().await?;
+
+        let (namespace, api_key) = self.rate_limit_target_ids(target, &mut tx).await?;
+        let (namespace, api_key) = (namespace.map(|id| id as i64), api_key.map(|id| id as i64));
+
+        sqlx::query("DELETE FROM rate_limits WHERE namespace = $1 OR api_key = $2")
+            .bind(namespace)
+            .bind(api_key)
+            .execute(&mut *tx)
+            .await?;
+
+        if let Some(limit) = &limit {
+            sqlx::query(
+                "
+                INSERT INTO rate_limits (namespace, api_key, requests_per_second, burst)
+                VALUES ($1, $2, $3, $4)
+                ",
+            )
+            .bind(namespace)
+            .bind(api_key)
+            .bind(limit.requests_per_second)
+            .bind(limit.burst as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            ?target,
+            requests_per_second = limit.map(|limit| limit.requests_per_second),
+            burst = limit.map(|limit| limit.burst),
+            "Rate limit set"
+        );
+
+        Ok(())
+    }
+
+    /// Gets the rate limit of requests made with an API key, or by a user scoped to a namespace.
+    ///
+    /// # Arguments
+    /// * `key_id` - ID of the API key the request was authenticated with, if any
+    /// * `namespace` - Namespace the request is authorized for, if any
+    ///
+    /// # Returns
+    /// The key's own limit, or else the namespace's, or else the configured default, or `None`
+    /// if requests aren't limited
+    pub async fn effective_rate_limit(
+        &self,
+        key_id: Option<&str>,
+        namespace: Option<&str>,
+    ) -> Result<Option<RateLimit>, Error> {
+        let limit = sqlx::query_as(
+            "
+            SELECT requests_per_second, burst FROM rate_limits
+            WHERE api_key = (SELECT id FROM api_keys WHERE key_id = $1)
+                OR namespace = (SELECT id FROM namespaces WHERE name = $2)
+            ORDER BY api_key IS NULL
+            LIMIT 1
+            ",
+        )
+        .bind(key_id)
+        .bind(namespace)
+        .fetch_optional(self.db())
+        .await?;
+
+        Ok(limit.or_else(|| self.config().rate_limit()))
+    }
+
+    /// Resolves the target of a rate limit to the ID of its namespace or API key.
+    async fn rate_limit_target_ids(
+        &self,
+        target: RateLimitTarget<'_>,
+        conn: &mut SqliteConnection,
+    ) -> Result<(Option<u64>, Option<u64>), Error> {
+        match target {
+            RateLimitTarget::Namespace(name) => {
+                let id = self
+                    .get_namespace_id(name, &mut *conn)
+                    .await?
+                    .ok_or_else(|| Error::namespace_not_found(name))?;
+                Ok((Some(id), None))
+            }
+            RateLimitTarget::ApiKey(key_id) => {
+                let id = sqlx::query_scalar("SELECT id FROM api_keys WHERE key_id = $1")
+                    .bind(key_id)
+                    .fetch_optional(&mut *conn)
+                    .await?
+                    .ok_or_else(|| Error::not_found(format!("API key {key_id}")))?;
+                Ok((None, Some(id)))
+            }
+        }
+    }
+
+    /// Records a request in the audit log, see [`crate::audit`].
+    pub async fn record_audit_entry(&self, entry: NewAuditEntry) -> Result<(), Error> {
+        sqlx::query(
+            "
+            INSERT INTO audit_log (at, actor, api_key, namespace, action, resource, status)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+        )
+        .bind(self.now())
+        .bind(entry.actor)
+        .bind(entry.api_key)
+        .bind(entry.namespace)
+        .bind(entry.action)
+        .bind(entry.resource)
+        .bind(entry.status as i64)
+        .execute(self.db())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Searches the audit log, newest entries first.
+    ///
+    /// Every given filter has to match. At most [`MAX_AUDIT_PAGE_SIZE`] entries are returned, the
+    /// next page starts before the ID of the last one.
+    pub async fn list_audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT id, at, actor, api_key, namespace, action, resource, status
+            FROM audit_log
+            WHERE ($1 IS NULL OR actor = $1)
+            AND ($2 IS NULL OR api_key = $2)
+            AND ($3 IS NULL OR namespace = $3)
+            AND ($4 IS NULL OR action = $4)
+            AND ($5 IS NULL OR at >= $5)
+            AND ($6 IS NULL OR at < $6)
+            AND ($7 IS NULL OR id < $7)
+            ORDER BY id DESC
+            LIMIT $8
+            ",
+        )
+        .bind(query.actor.as_deref())
+        .bind(query.api_key.as_deref())
+        .bind(query.namespace.as_deref())
+        .bind(query.action.as_deref())
+        .bind(query.since.map(|since| since as i64))
+        .bind(query.until.map(|until| until as i64))
+        .bind(query.before.map(|before| before as i64))
+        .bind(
+            query
+                .limit
+                .unwrap_or(DEFAULT_AUDIT_PAGE_SIZE)
+                .min(MAX_AUDIT_PAGE_SIZE) as i64,
+        )
+        .fetch_all(self.db())
+        .await?)
+    }
+
+    /// Removes audit log entries that are older than the configured audit retention.
+    ///
+    /// # Returns
+    /// The number of entries removed
+    pub async fn prune_audit_log(&self) -> Result<u64, Error> {
+        let res = sqlx::query("DELETE FROM audit_log WHERE at + $1 <= $2")
+            .bind(self.config.audit_retention().as_secs() as i64)
+            .bind(self.now())
+            .execute(self.db())
+            .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Summarizes the namespaces, queues and messages a user has access to, and for admins, the
+    /// health of the server.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn overview(&self, identity: Identity) -> Result<Overview, Error> {
+        let email = identity.id()?;
+        let mut db = self.db().acquire().await?;
+
+        let role: Role = sqlx::query_scalar("SELECT role FROM users WHERE email = $1")
+            .bind(&email)
+            .fetch_optional(&mut *db)
+            .await?
+            .ok_or(Error::Unauthorized)?;
+
+        let totals: Totals = sqlx::query_as(
+            "
+            WITH
+                visible AS (
+                    SELECT p.namespace AS ns FROM user_permissions p
+                    JOIN users u ON u.id = p.user
+                    WHERE u.email = $1
+                ),
+                vq AS (
+                    SELECT q.id, conf.max_retries FROM queues q
+                    JOIN queue_configurations conf ON conf.queue = q.id
+                    WHERE q.ns IN (SELECT ns FROM visible)
+                ),
+                dlq AS (
+                    SELECT dead_letter_queue AS id FROM queue_configurations
+                    WHERE dead_letter_queue IS NOT NULL
+                )
+            SELECT
+                (SELECT COUNT(*) FROM visible) AS namespaces,
+                (SELECT COUNT(*) FROM vq) AS queues,
+                COUNT(m.id) AS messages,
+                COUNT(CASE WHEN m.delivered_at IS NULL AND m.tries < vq.max_retries THEN 1 END) AS pending,
+                COUNT(CASE WHEN m.delivered_at IS NULL AND m.tries >= vq.max_retries THEN 1 END) AS failed,
+                (SELECT COUNT(*) FROM vq WHERE id IN (SELECT id FROM dlq)) AS dead_letter_queues,
+                COUNT(CASE WHEN m.queue IN (SELECT id FROM dlq) THEN 1 END) AS dead_lettered
+            FROM vq
+            LEFT JOIN messages m ON m.queue = vq.id
+            ",
+        )
+        .bind(&email)
+        .fetch_one(&mut *db)
+        .await?;
+
+        let live_consumers: u64 = sqlx::query_scalar(
+            "
+            SELECT COUNT(*) FROM consumers c
+            JOIN queues q ON q.id = c.queue
+            JOIN user_permissions p ON p.namespace = q.ns
+            JOIN users u ON u.id = p.user
+            WHERE u.email = $1 AND c.last_heartbeat_at > $3 - $2
+            ",
+        )
+        .bind(&email)
+        .bind(CONSUMER_TTL.as_secs() as i64)
+        .bind(self.now())
+        .fetch_one(&mut *db)
+        .await?;
+
+        Ok(Overview {
+            totals,
+            live_consumers,
+            unattended_queues: self.unattended_queues(Some(&email)).await?,
+            maintenance: self.maintenance_mode(),
+            health: (role == Role::Admin).then(|| self.health.health()),
+        })
+    }
+}
+
+/// The operations of [`QueueService`], for callers that use the service as a whole.
+impl Service {
+    /// See [`QueueService::get_queue_id`].
+    pub async fn get_queue_id(
+        &self,
+        namespace: &str,
+        name: &str,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<Option<u64>, Error> {
+        self.queues().get_queue_id(namespace, name, exec).await
+    }
+
+    /// See [`QueueService::get_namespace_id`].
+    pub async fn get_namespace_id<'a>(
+        &self,
+        name: &str,
+        ex: impl Acquire<'a, Database = Sqlite>,
+    ) -> Result<Option<u64>, Error> {
+        self.queues().get_namespace_id(name, ex).await
+    }
+
+    /// See [`QueueService::list_namespaces`].
+    pub async fn list_namespaces(&self, identity: Identity) -> Result<Vec<Namespace>, Error> {
+        self.queues().list_namespaces(identity).await
+    }
+
+    /// See [`QueueService::create_namespace`].
+    pub async fn create_namespace(&self, name: &str, identity: Identity) -> Result<u64, Error> {
+        self.queues().create_namespace(name, identity).await
+    }
+
+    /// See [`QueueService::delete_namespace`].
+    pub async fn delete_namespace(&self, name: &str, identity: Identity) -> Result<(), Error> {
+        self.queues().delete_namespace(name, identity).await
+    }
+
+    /// See [`QueueService::create_queue`].
+    pub async fn create_queue(
+        &self,
+        namespace: &str,
+        name: &str,
+        attributes: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.queues()
+            .create_queue(namespace, name, attributes, tags, identity)
+            .await
+    }
+
+    /// See [`QueueService::set_queue_attributes`].
+    pub async fn set_queue_attributes(
+        &self,
+        ns: &str,
+        queue: &str,
+        attributes: QueueAttributesSer,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.queues()
+            .set_queue_attributes(ns, queue, attributes, identity)
+            .await
+    }
+
+    /// See [`QueueService::get_queue_attributes`].
+    pub async fn get_queue_attributes(
+        &self,
+        ns: &str,
+        queue: &str,
+        names: &[String],
+        identity: Identity,
+    ) -> Result<QueueAttributesSer, Error> {
+        self.queues()
+            .get_queue_attributes(ns, queue, names, identity)
+            .await
+    }
+
+    /// See [`QueueService::tag_queue`].
+    pub async fn tag_queue(
+        &self,
+        ns: &str,
+        queue: &str,
+        tags: HashMap<String, String>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.queues().tag_queue(ns, queue, tags, identity).await
+    }
+
+    /// See [`QueueService::untag_queue`].
+    pub async fn untag_queue(
+        &self,
+        ns: &str,
+        queue: &str,
+        tags: Vec<String>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.queues().untag_queue(ns, queue, tags, identity).await
+    }
+
+    /// See [`QueueService::get_queue_tags`].
+    pub async fn get_queue_tags(
+        &self,
+        ns: &str,
+        queue: &str,
+        identity: Identity,
+    ) -> Result<HashMap<String, String>, Error> {
+        self.queues().get_queue_tags(ns, queue, identity).await
+    }
+
+    /// See [`QueueService::delete_queue`].
+    pub async fn delete_queue(
+        &self,
+        namespace: &str,
+        name: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.queues().delete_queue(namespace, name, identity).await
+    }
+
+    /// See [`QueueService::list_queues`].
+    pub async fn list_queues(
+        &self,
+        namespace: Option<&str>,
+        identity: Identity,
+    ) -> Result<Vec<Queue>, Error> {
+        self.queues().list_queues(namespace, identity).await
+    }
+
+    /// See [`QueueService::list_dead_letter_source_queues`].
+    pub async fn list_dead_letter_source_queues(
+        &self,
+        ns: &str,
+        queue: &str,
+        identity: Identity,
+    ) -> Result<Vec<Queue>, Error> {
+        self.queues()
+            .list_dead_letter_source_queues(ns, queue, identity)
+            .await
+    }
+
+    /// See [`QueueService::list_queues_for_namespace`].
+    pub async fn list_queues_for_namespace(&self, namespace: &str) -> Result<Vec<Queue>, Error> {
+        self.queues().list_queues_for_namespace(namespace).await
+    }
+
+    /// See [`QueueService::list_all_queues`].
+    pub async fn list_all_queues(&self, identity: Identity) -> Result<Vec<Queue>, Error> {
+        self.queues().list_all_queues(identity).await
+    }
+
+    /// See [`QueueService::set_push_subscription`].
+    pub async fn set_push_subscription(
+        &self,
+        namespace: &str,
+        queue: &str,
+        request: PushSubscriptionRequest,
+    ) -> Result<PushSubscription, Error> {
+        self.queues()
+            .set_push_subscription(namespace, queue, request)
+            .await
+    }
+
+    /// See [`QueueService::get_push_subscription`].
+    pub async fn get_push_subscription(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<PushSubscription, Error> {
+        self.queues().get_push_subscription(namespace, queue).await
+    }
+
+    /// See [`QueueService::delete_push_subscription`].
+    pub async fn delete_push_subscription(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<(), Error> {
+        self.queues()
+            .delete_push_subscription(namespace, queue)
+            .await
+    }
+
+    /// See [`QueueService::namespace_limits`].
+    pub async fn namespace_limits(&self, namespace: &str) -> Result<NamespaceLimits, Error> {
+        self.queues().namespace_limits(namespace).await
+    }
+
+    /// See [`QueueService::set_namespace_limits`].
+    pub async fn set_namespace_limits(
+        &self,
+        namespace: &str,
+        limits: NamespaceLimits,
+    ) -> Result<(), Error> {
+        self.queues().set_namespace_limits(namespace, limits).await
+    }
+
+    /// See [`QueueService::get_namespace_limits`].
+    async fn get_namespace_limits<'a>(
+        &self,
+        ns_id: u64,
+        exec: impl Acquire<'a, Database = Sqlite>,
+    ) -> Result<NamespaceLimits, Error> {
+        self.queues().get_namespace_limits(ns_id, exec).await
+    }
+
+    /// See [`QueueService::get_queue_attribute`].
+    async fn get_queue_attribute<A>(
+        &self,
+        queue_id: u64,
+        attribute: A,
+    ) -> Result<Option<u64>, Error>
+    where
+        A: QueueAttribute<Value = u64>,
+    {
+        self.queues().get_queue_attribute(queue_id, attribute).await
+    }
+
+    /// See [`QueueService::get_queue_text_attribute`].
+    async fn get_queue_text_attribute<A>(
+        &self,
+        queue_id: u64,
+        attribute: A,
+    ) -> Result<Option<String>, Error>
+    where
+        A: QueueAttribute<Value = String>,
+    {
+        self.queues()
+            .get_queue_text_attribute(queue_id, attribute)
+            .await
+    }
+
+    /// See [`QueueService::get_queue_configuration`].
+    pub async fn get_queue_configuration(&self, queue: u64) -> Result<QueueConfig, Error> {
+        self.queues().get_queue_configuration(queue).await
+    }
+
+    /// See [`QueueService::update_queue_configuration`].
+    pub async fn update_queue_configuration(
+        &self,
+        queue: u64,
+        new_config: QueueConfig,
+    ) -> Result<(), Error> {
+        self.queues()
+            .update_queue_configuration(queue, new_config)
+            .await
+    }
+
+    /// See [`QueueService::queue_statistics`].
+    pub async fn queue_statistics(
+        &self,
+        identity: Identity,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<QueueStatistics, Error> {
+        self.queues()
+            .queue_statistics(identity, namespace, queue)
+            .await
+    }
+
+    /// See [`QueueService::global_queue_statistics`].
+    pub async fn global_queue_statistics(
+        &self,
+        identity: Identity,
+    ) -> Result<HashMap<String, QueueStatistics>, Error> {
+        self.queues().global_queue_statistics(identity).await
+    }
+
+    /// See [`QueueService::list_consumer_groups`].
+    pub async fn list_consumer_groups(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<Vec<ConsumerGroup>, Error> {
+        self.queues().list_consumer_groups(namespace, queue).await
+    }
+
+    /// See [`QueueService::create_consumer_group`].
+    pub async fn create_consumer_group(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        self.queues()
+            .create_consumer_group(namespace, queue, name)
+            .await
+    }
+
+    /// See [`QueueService::delete_consumer_group`].
+    pub async fn delete_consumer_group(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        self.queues()
+            .delete_consumer_group(namespace, queue, name)
+            .await
+    }
+
+    /// See [`QueueService::get_consumer_group_id`].
+    async fn get_consumer_group_id(
+        &self,
+        queue_id: u64,
+        queue: &str,
+        name: &str,
+    ) -> Result<u64, Error> {
+        self.queues()
+            .get_consumer_group_id(queue_id, queue, name)
+            .await
+    }
+
+    /// See [`QueueService::has_consumer_groups`].
+    async fn has_consumer_groups(&self, queue_id: u64) -> Result<bool, Error> {
+        self.queues().has_consumer_groups(queue_id).await
+    }
+
+    /// See [`QueueService::heartbeat_consumer`].
+    pub async fn heartbeat_consumer(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+        identity: Identity,
+    ) -> Result<Consumer, Error> {
+        self.queues()
+            .heartbeat_consumer(namespace, queue, name, identity)
+            .await
+    }
+
+    /// See [`QueueService::deregister_consumer`].
+    pub async fn deregister_consumer(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.queues()
+            .deregister_consumer(namespace, queue, name, identity)
+            .await
+    }
+
+    /// See [`QueueService::list_consumers`].
+    pub async fn list_consumers(&self, identity: Identity) -> Result<Vec<Consumer>, Error> {
+        self.queues().list_consumers(identity).await
+    }
+
+    /// See [`QueueService::unattended_queues`].
+    pub async fn unattended_queues(
+        &self,
+        email: Option<&str>,
+    ) -> Result<Vec<UnattendedQueue>, Error> {
+        self.queues().unattended_queues(email).await
+    }
+
+    /// See [`QueueService::list_namespace_statistics`].
+    pub async fn list_namespace_statistics(
+        &self,
+        identity: Identity,
+    ) -> Result<Vec<NamespaceStatistics>, Error> {
+        self.queues().list_namespace_statistics(identity).await
+    }
+}
+
+/// The operations of [`MessageService`], for callers that use the service as a whole.
+impl Service {
+    /// See [`MessageService::start_message_move_task`].
+    pub async fn start_message_move_task(
+        &self,
+        ns: &str,
+        queue: &str,
+        destination: Option<(&str, &str)>,
+        max_per_second: Option<u64>,
+        identity: Identity,
+    ) -> Result<String, Error> {
+        self.messages()
+            .start_message_move_task(ns, queue, destination, max_per_second, identity)
+            .await
+    }
+
+    /// See [`MessageService::cancel_message_move_task`].
+    pub async fn cancel_message_move_task(
+        &self,
+        ns: &str,
+        handle: &str,
+        identity: Identity,
+    ) -> Result<u64, Error> {
+        self.messages()
+            .cancel_message_move_task(ns, handle, identity)
+            .await
+    }
+
+    /// See [`MessageService::list_message_move_tasks`].
+    pub async fn list_message_move_tasks(
+        &self,
+        ns: &str,
+        queue: &str,
+        limit: u64,
+        identity: Identity,
+    ) -> Result<Vec<MessageMoveTask>, Error> {
+        self.messages()
+            .list_message_move_tasks(ns, queue, limit, identity)
+            .await
+    }
+
+    /// See [`MessageService::run_message_move_tasks`].
+    pub async fn run_message_move_tasks(&self) -> Result<u64, Error> {
+        self.messages().run_message_move_tasks().await
+    }
+
+    /// See [`MessageService::list_push_targets`].
+    pub async fn list_push_targets(&self) -> Result<Vec<PushTarget>, Error> {
+        self.messages().list_push_targets().await
+    }
+
+    /// See [`MessageService::complete_push`].
+    pub async fn complete_push(&self, queue_id: u64, message_id: u64) -> Result<(), Error> {
+        self.messages().complete_push(queue_id, message_id).await
+    }
+
+    /// See [`MessageService::fail_push`].
+    pub async fn fail_push(
+        &self,
+        queue_id: u64,
+        message_id: u64,
+        backoff: Duration,
+        reason: &str,
+    ) -> Result<(), Error> {
+        self.messages()
+            .fail_push(queue_id, message_id, backoff, reason)
+            .await
+    }
+
+    /// See [`MessageService::sqs_send`].
+    pub async fn sqs_send(
+        &self,
+        queue: u64,
+        req: SendMessageRequest,
+    ) -> Result<SendMessageResponse, Error> {
+        self.messages().sqs_send(queue, req).await
+    }
+
+    #[allow(unused)]
+    /// See [`MessageService::sqs_send_batch`].
+    pub async fn sqs_send_batch(
+        &self,
+        namespace_name: &str,
+        queue_name: &str,
+        req: SendMessageBatchRequest,
+    ) -> Result<SendMessageBatchResponse, Error> {
+        self.messages()
+            .sqs_send_batch(namespace_name, queue_name, req)
+            .await
+    }
+
+    /// See [`MessageService::ingest_messages`].
+    pub async fn ingest_messages(
+        &self,
+        queue: u64,
+        messages: Vec<IngestMessage>,
+    ) -> Result<Vec<String>, Error> {
+        self.messages().ingest_messages(queue, messages).await
+    }
+
+    /// See [`MessageService::create_outbox_source`].
+    pub async fn create_outbox_source(
+        &self,
+        request: CreateOutboxSourceRequest,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.messages()
+            .create_outbox_source(request, identity)
+            .await
+    }
+
+    /// See [`MessageService::list_outbox_sources`].
+    pub async fn list_outbox_sources(&self) -> Result<Vec<OutboxSource>, Error> {
+        self.messages().list_outbox_sources().await
+    }
+
+    /// See [`MessageService::delete_outbox_source`].
+    pub async fn delete_outbox_source(&self, name: &str) -> Result<(), Error> {
+        self.messages().delete_outbox_source(name).await
+    }
+
+    /// See [`MessageService::ingest_outbox_rows`].
+    pub async fn ingest_outbox_rows(
+        &self,
+        source: &OutboxSource,
+        rows: Vec<OutboxRow>,
+    ) -> Result<bool, Error> {
+        self.messages().ingest_outbox_rows(source, rows).await
+    }
+
+    #[allow(unused)]
+    /// See [`MessageService::sqs_recv`].
+    pub async fn sqs_recv(
+        &self,
+        namespace: &str,
+        queue: &str,
+        options: ReceiveOptions,
+    ) -> Result<Option<SqsMessage>, Error> {
+        self.messages().sqs_recv(namespace, queue, options).await
+    }
+
+    /// See [`MessageService::sqs_recv_batch`].
+    pub async fn sqs_recv_batch(
+        &self,
+        namespace: &str,
+        queue: &str,
+        options: ReceiveOptions,
+    ) -> Result<Vec<SqsMessage>, Error> {
+        self.messages()
+            .sqs_recv_batch(namespace, queue, options)
+            .await
+    }
+
+    /// See [`MessageService::visibility_timeout`].
+    pub async fn visibility_timeout(&self, queue_id: u64) -> Result<Duration, Error> {
+        self.messages().visibility_timeout(queue_id).await
+    }
+
+    /// See [`MessageService::list_messages`].
+    pub async fn list_messages(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<Vec<MessageDetails>, Error> {
+        self.messages().list_messages(namespace, queue).await
+    }
+
+    /// See [`MessageService::get_message_details`].
+    pub async fn get_message_details(
+        &self,
+        queue_id: u64,
+        message_id: u64,
+    ) -> Result<Option<MessageDetails>, Error> {
+        self.messages()
+            .get_message_details(queue_id, message_id)
+            .await
+    }
+
+    /// See [`MessageService::delete_message_batch`].
+    pub async fn delete_message_batch(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_ids: Vec<u64>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<
+        (
+            Vec<u64>,          // Successfully deleted message IDs
+            Vec<(u64, Error)>, // Failed message IDs
+        ),
+        Error,
+    > {
+        self.messages()
+            .delete_message_batch(namespace, queue, message_ids, consumer_group, identity)
+            .await
+    }
+
+    /// See [`MessageService::extend_visibility`].
+    pub async fn extend_visibility(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_ids: &[u64],
+        visibility_timeout: Duration,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<u64, Error> {
+        self.messages()
+            .extend_visibility(
+                namespace,
+                queue,
+                message_ids,
+                visibility_timeout,
+                consumer_group,
+                identity,
+            )
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// See [`MessageService::change_message_visibility`].
+    pub async fn change_message_visibility(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_id: u64,
+        visibility_timeout: Duration,
+        failure_reason: Option<&str>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.messages()
+            .change_message_visibility(
+                namespace,
+                queue,
+                message_id,
+                visibility_timeout,
+                failure_reason,
+                consumer_group,
+                identity,
+            )
+            .await
+    }
+
+    /// See [`MessageService::ack_messages`].
+    pub async fn ack_messages(
+        &self,
+        namespace: &str,
+        queue: &str,
+        outcomes: Vec<Outcome>,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<(Vec<u64>, Vec<(u64, Error)>), Error> {
+        self.messages()
+            .ack_messages(namespace, queue, outcomes, consumer_group, identity)
+            .await
+    }
+
+    /// See [`MessageService::list_message_history`].
+    pub async fn list_message_history(
+        &self,
+        namespace: &str,
+        queue: &str,
+        limit: u64,
+        before: Option<u64>,
+    ) -> Result<Vec<HistoryEntry>, Error> {
+        self.messages()
+            .list_message_history(namespace, queue, limit, before)
+            .await
+    }
+
+    /// See [`MessageService::sample_messages`].
+    pub async fn sample_messages(
+        &self,
+        namespace: &str,
+        queue: &str,
+        count: u64,
+    ) -> Result<Vec<MessageSample>, Error> {
+        self.messages()
+            .sample_messages(namespace, queue, count)
+            .await
+    }
+
+    /// See [`MessageService::queue_report`].
+    pub async fn queue_report(
+        &self,
+        namespace: &str,
+        queue: &str,
+        kind: ReportKind,
+        attribute: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<ReportRow>, Error> {
+        self.messages()
+            .queue_report(namespace, queue, kind, attribute, limit)
+            .await
+    }
+
+    /// See [`MessageService::prune_message_history`].
+    pub async fn prune_message_history(&self) -> Result<u64, Error> {
+        self.messages().prune_message_history().await
+    }
+
+    /// See [`MessageService::record_trace_event`].
+    async fn record_trace_event(
+        &self,
+        event: TraceEvent,
+        queue_id: u64,
+        messages: Option<&[u64]>,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), Error> {
+        self.messages()
+            .record_trace_event(event, queue_id, messages, conn)
+            .await
+    }
+
+    /// See [`MessageService::get_message_trace`].
+    pub async fn get_message_trace(
+        &self,
+        trace_id: &str,
+        identity: Identity,
+    ) -> Result<Trace, Error> {
+        self.messages().get_message_trace(trace_id, identity).await
+    }
+
+    /// See [`MessageService::prune_message_traces`].
+    pub async fn prune_message_traces(&self) -> Result<u64, Error> {
+        self.messages().prune_message_traces().await
+    }
+
+    /// See [`MessageService::sweep_consumed_messages`].
+    async fn sweep_consumed_messages(
+        &self,
+        queue_id: u64,
+        messages: Option<&[u64]>,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<(), Error> {
+        self.messages()
+            .sweep_consumed_messages(queue_id, messages, tx)
+            .await
+    }
+
+    /// See [`MessageService::resolve_receipt_handles`].
+    pub async fn resolve_receipt_handles(
+        &self,
+        namespace: &str,
+        queue: &str,
+        handles: &[String],
+    ) -> Result<Vec<Option<u64>>, Error> {
+        self.messages()
+            .resolve_receipt_handles(namespace, queue, handles)
+            .await
+    }
+
+    /// See [`MessageService::resolve_receipt_handle`].
+    pub async fn resolve_receipt_handle(
+        &self,
+        namespace: &str,
+        queue: &str,
+        handle: &str,
+    ) -> Result<u64, Error> {
+        self.messages()
+            .resolve_receipt_handle(namespace, queue, handle)
+            .await
+    }
+
+    /// See [`MessageService::delete_message`].
+    pub async fn delete_message(
+        &self,
+        namespace: &str,
+        queue: &str,
+        message_id: u64,
+        consumer_group: Option<&str>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.messages()
+            .delete_message(namespace, queue, message_id, consumer_group, identity)
+            .await
+    }
+
+    /// See [`MessageService::purge_queue`].
+    pub async fn purge_queue(
+        &self,
+        namespace: &str,
+        queue: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.messages()
+            .purge_queue(namespace, queue, identity)
+            .await
+    }
+}
+
+/// The operations of [`UserService`], for callers that use the service as a whole.
+impl Service {
+    /// See [`UserService::delete_user`].
+    pub async fn delete_user(&self, email: Email) -> Result<(), Error> {
+        self.users().delete_user(email).await
+    }
+
+    /// See [`UserService::set_user_role`].
+    pub async fn set_user_role(&self, email: &str, role: Role) -> Result<(), Error> {
+        self.users().set_user_role(email, role).await
+    }
+
+    /// See [`UserService::set_user_active`].
+    pub async fn set_user_active(&self, email: &str, active: bool) -> Result<(), Error> {
+        self.users().set_user_active(email, active).await
+    }
+
+    /// See [`UserService::revoke_stale_tokens`].
+    pub async fn revoke_stale_tokens(
+        &self,
+        email: &str,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<u64, Error> {
+        self.users().revoke_stale_tokens(email, exec).await
+    }
+
+    /// See [`UserService::check_user_role`].
+    pub async fn check_user_role(&self, identity: Identity, role: Role) -> Result<(), Error> {
+        self.users().check_user_role(identity, role).await
+    }
+
+    /// See [`UserService::check_user_access`].
+    pub async fn check_user_access<'a>(
+        &self,
+        identity: &Identity,
+        ns: u64,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<(u64, bool), Error> {
+        self.users().check_user_access(identity, ns, exec).await
+    }
+
+    /// See [`UserService::namespace_access_denied`].
+    pub fn namespace_access_denied(&self, namespace: &str) -> Error {
+        self.users().namespace_access_denied(namespace)
+    }
+
+    /// See [`UserService::get_key_id`].
+    pub async fn get_key_id(&self, user_email: &str) -> Result<String, Error> {
+        self.users().get_key_id(user_email).await
+    }
+
+    /// See [`UserService::create_token`].
+    pub async fn create_token(
+        &self,
+        name: String,
+        namespace: String,
+        identity: Identity,
+    ) -> Result<CreateTokenResponse, Error> {
+        self.users().create_token(name, namespace, identity).await
+    }
+
+    /// See [`UserService::install_token`].
+    pub async fn install_token(
+        &self,
+        name: &str,
+        namespace: &str,
+        email: &str,
+        key: ApiKey,
+    ) -> Result<bool, Error> {
+        self.users()
+            .install_token(name, namespace, email, key)
+            .await
+    }
+
+    /// See [`UserService::find_token`].
+    async fn find_token(&self, email: &str, name: &str) -> Result<Option<(String, String)>, Error> {
+        self.users().find_token(email, name).await
+    }
+
+    /// See [`UserService::replace_token`].
+    async fn replace_token(
+        &self,
+        name: &str,
+        namespace: &str,
+        email: &str,
+        key: GeneratedKey,
+    ) -> Result<CreateTokenResponse, Error> {
+        self.users()
+            .replace_token(name, namespace, email, key)
+            .await
+    }
+
+    /// See [`UserService::list_tokens`].
+    pub async fn list_tokens(&self, identity: Identity) -> Result<Vec<TokenInfo>, Error> {
+        self.users().list_tokens(identity).await
+    }
+
+    /// See [`UserService::token_namespace`].
+    pub async fn token_namespace(&self, owner: &str, name: &str) -> Result<Option<String>, Error> {
+        self.users().token_namespace(owner, name).await
+    }
+
+    /// See [`UserService::set_token_allowed_networks`].
+    pub async fn set_token_allowed_networks(
+        &self,
+        owner: &str,
+        name: &str,
+        networks: Option<Vec<IpNet>>,
+    ) -> Result<(), Error> {
+        self.users()
+            .set_token_allowed_networks(owner, name, networks)
+            .await
+    }
+
+    /// See [`UserService::check_token_network`].
+    pub async fn check_token_network(&self, key_id: &str, ip: Option<IpAddr>) -> Result<(), Error> {
+        self.users().check_token_network(key_id, ip).await
+    }
+
+    /// See [`UserService::export_api_keys`].
+    pub async fn export_api_keys(&self, public_key: &RsaPublicKey) -> Result<KeyExport, Error> {
+        self.users().export_api_keys(public_key).await
+    }
+
+    /// See [`UserService::import_api_keys`].
+    pub async fn import_api_keys(
+        &self,
+        export: KeyExport,
+        private_key: RsaPrivateKey,
+    ) -> Result<ImportResponse, Error> {
+        self.users().import_api_keys(export, private_key).await
+    }
+
+    /// See [`UserService::create_client_certificate`].
+    pub async fn create_client_certificate(
+        &self,
+        name: &str,
+        namespace: &str,
+        certificate: CertificateMatch,
+        identity: Identity,
+    ) -> Result<ClientCertificateInfo, Error> {
+        self.users()
+            .create_client_certificate(name, namespace, certificate, identity)
+            .await
+    }
+
+    /// See [`UserService::list_client_certificates`].
+    pub async fn list_client_certificates(
+        &self,
+        identity: Identity,
+    ) -> Result<Vec<ClientCertificateInfo>, Error> {
+        self.users().list_client_certificates(identity).await
+    }
+
+    /// See [`UserService::client_certificate_namespace`].
+    pub async fn client_certificate_namespace(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> Result<Option<String>, Error> {
+        self.users().client_certificate_namespace(owner, name).await
+    }
+
+    /// See [`UserService::delete_client_certificate`].
+    pub async fn delete_client_certificate(
+        &self,
+        name: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.users().delete_client_certificate(name, identity).await
+    }
+
+    /// See [`UserService::record_token_use`].
+    pub fn record_token_use(&self, key_id: &str) {
+        self.users().record_token_use(key_id)
+    }
+
+    /// See [`UserService::flush_token_usage`].
+    pub async fn flush_token_usage(&self) -> Result<usize, Error> {
+        self.users().flush_token_usage().await
+    }
+
+    /// See [`UserService::create_user`].
+    pub async fn create_user(
+        &self,
+        email: Email,
+        password: String,
+        role: Option<Role>,
+        namespaces: Vec<String>,
+    ) -> Result<(), Error> {
+        self.users()
+            .create_user(email, password, role, namespaces)
+            .await
+    }
+
+    /// See [`UserService::invite_user`].
+    pub async fn invite_user(
+        &self,
+        email: Email,
+        role: Option<Role>,
+        namespaces: Vec<String>,
+        namespace_quota: Option<u64>,
+    ) -> Result<(), Error> {
+        self.users()
+            .invite_user(email, role, namespaces, namespace_quota)
+            .await
+    }
+
+    /// See [`UserService::import_users`].
+    pub async fn import_users(&self, users: Vec<ImportUser>) -> Result<ImportUsersResponse, Error> {
+        self.users().import_users(users).await
+    }
+
+    /// See [`UserService::accept_invitation`].
+    pub async fn accept_invitation(&self, token: &str, password: String) -> Result<(), Error> {
+        self.users().accept_invitation(token, password).await
+    }
+
+    /// See [`UserService::set_namespace_quota`].
+    pub async fn set_namespace_quota(&self, email: &str, quota: Option<u64>) -> Result<(), Error> {
+        self.users().set_namespace_quota(email, quota).await
+    }
+
+    /// See [`UserService::request_password_reset`].
+    pub async fn request_password_reset(&self, email: Email) -> Result<(), Error> {
+        self.users().request_password_reset(email).await
+    }
+
+    /// See [`UserService::reset_password`].
+    pub async fn reset_password(&self, token: &str, password: String) -> Result<(), Error> {
+        self.users().reset_password(token, password).await
+    }
+
+    /// See [`UserService::list_sessions`].
+    pub async fn list_sessions(&self, email: &str) -> Result<Vec<SessionInfo>, Error> {
+        self.users().list_sessions(email).await
+    }
+
+    /// See [`UserService::delete_session`].
+    pub async fn delete_session(&self, email: &str, id: u64) -> Result<(), Error> {
+        self.users().delete_session(email, id).await
+    }
+
+    /// See [`UserService::delete_user_sessions`].
+    pub async fn delete_user_sessions(&self, email: &str) -> Result<u64, Error> {
+        self.users().delete_user_sessions(email).await
+    }
+}
+
+/// Converts a decoded attribute value back into the string it was stored as.
+/// Sets the passphrase SQLCipher decrypts the database with, if there is one.
+pub(crate) fn with_db_key(
+    options: SqliteConnectOptions,
+    db_key: Option<&SecretString>,
+) -> Result<SqliteConnectOptions, Error> {
+    let Some(db_key) = db_key else {
+        return Ok(options);
+    };
+
+    if !cfg!(feature = "sqlcipher") {
+        return Err(Error::EncryptionUnsupported);
+    }
+
+    // The pragma value is inserted into the statement as is, so it has to be quoted here.
+    let passphrase = db_key.expose_secret().replace('\'', "''");
+    Ok(options.pragma("key", format!("'{passphrase}'")))
+}
+
+fn read_only_attribute(name: &str) -> Error {
+    Error::InvalidAttributeName {
+        name: name.to_owned(),
+        reason: "the attribute is read-only".to_owned(),
+    }
+}
+
+/// Returns the key an attribute given by its SQS name, like `MaximumMessageSize`, is stored under.
+/// Other names are stored as they are.
+fn attribute_key(name: &str) -> &str {
+    match name {
+        "DelaySeconds" => queue_attributes::DelaySeconds.name(),
+        "MaximumMessageSize" => queue_attributes::MaxMessageSize.name(),
+        "MessageRetentionPeriod" => queue_attributes::MessageRetentionPeriod.name(),
+        "ReceiveMessageWaitTimeSeconds" => queue_attributes::ReceiveMessageWaitTimeSeconds.name(),
+        "VisibilityTimeout" => queue_attributes::VisibilityTimeout.name(),
+        "RedrivePolicy" => queue_attributes::RedrivePolicy.name(),
+        "RedriveAllowPolicy" => queue_attributes::RedriveAllowPolicy.name(),
+        name => name,
+    }
+}
+
+fn json_string(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redrive_allow_policy_parse() {
+        for valid in [
+            r#"{"redrivePermission":"allowAll"}"#,
+            r#"{"redrivePermission":"denyAll"}"#,
+            r#"{"redrivePermission":"byQueue","sourceQueueArns":["ns:a","ns:b"]}"#,
+        ] {
+            assert!(RedriveAllowPolicy::parse(valid).is_ok(), "{valid}");
+        }
+
+        let too_many = serde_json::json!({
+            "redrivePermission": "byQueue",
+            "sourceQueueArns": (0..=MAX_REDRIVE_SOURCE_QUEUES)
+                .map(|i| format!("ns:{i}"))
+                .collect::<Vec<_>>(),
+        })
+        .to_string();
+
+        for invalid in [
+            r#"{"redrivePermission":"byQueue"}"#,
+            r#"{"redrivePermission":"allowAll","sourceQueueArns":["ns:a"]}"#,
+            r#"{"redrivePermission":"sometimes"}"#,
+            too_many.as_str(),
+        ] {
+            assert!(RedriveAllowPolicy::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_redrive_allow_policy_allows() {
+        let policy = RedriveAllowPolicy::parse(
+            r#"{"redrivePermission":"byQueue","sourceQueueArns":["ns:a"]}"#,
+        )
+        .unwrap();
+        assert!(policy.allows("ns", "a"));
+        assert!(!policy.allows("ns", "b"));
+        assert!(!policy.allows("other", "a"));
+
+        let policy = RedriveAllowPolicy::parse(r#"{"redrivePermission":"denyAll"}"#).unwrap();
+        assert!(!policy.allows("ns", "a"));
+
+        let policy = RedriveAllowPolicy::parse(r#"{"redrivePermission":"allowAll"}"#).unwrap();
+        assert!(policy.allows("ns", "a"));
+    }
+
+    #[test]
+    fn test_receive_attribute_names() {
+        let options = |names: &[&str]| {
+            let names = names.iter().map(|name| name.to_string()).collect();
+            ReceiveOptions::builder()
+                .attribute_names(HashSet::clone(&names))
+                .message_attribute_names(names)
+                .build()
+        };
+
+        for all in ["All", ".*"] {
+            assert!(options(&[all]).includes_message_attribute("order.id"));
+        }
+        assert!(options(&["All"]).includes_attribute("ApproximateReceiveCount"));
+        assert!(!options(&[".*"]).includes_attribute("ApproximateReceiveCount"));
+
+        let options = options(&["order.*", "kind"]);
+        assert!(options.includes_message_attribute("order.id"));
+        assert!(options.includes_message_attribute("kind"));
+        assert!(!options.includes_message_attribute("order"));
+        assert!(!options.includes_message_attribute("orders.id"));
+        assert!(!options.includes_message_attribute("kind.x"));
+        assert!(options.includes_attribute("kind"));
+        assert!(!options.includes_attribute("order.id"));
+    }
+
+    #[tokio::test]
+    async fn test_overview() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        let mut queues = Vec::new();
+        for name in ["in", "dlq"] {
+            service
+                .create_queue("t", name, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+            let id = service.get_queue_id("t", name, service.db()).await.unwrap();
+            queues.push(id.unwrap());
+        }
+        let config = QueueConfig {
+            max_retries: 1,
+            dead_letter_queue: Some(queues[1]),
+            ..service.get_queue_configuration(queues[0]).await.unwrap()
+        };
+        service
+            .update_queue_configuration(queues[0], config)
+            .await
+            .unwrap();
+
+        for body in ["a", "b"] {
+            service
+                .sqs_send(
+                    queues[0],
+                    SendMessageRequest {
+                        queue_url: "http://localhost:8080/t/in".parse().unwrap(),
+                        message_body: body.to_owned(),
+                        delay_seconds: None,
+                        message_attributes: HashMap::new(),
+                        message_deduplication_id: None,
+                        message_group_id: None,
+                        md5_of_message_body: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+
+        // Receiving a message twice moves it to the dead-letter queue.
+        let options = ReceiveOptions::builder()
+            .visibility_timeout(Duration::ZERO)
+            .build();
+        service
+            .sqs_recv("t", "in", options.clone())
+            .await
+            .unwrap()
+            .unwrap();
+        service.sqs_recv("t", "in", options).await.unwrap().unwrap();
+
+        service
+            .health()
+            .record_task("history", Duration::from_secs(60), None);
+
+        let overview = service.overview(root()).await.unwrap();
+        assert_eq!(
+            overview.totals,
+            Totals {
+                namespaces: 1,
+                queues: 2,
+                messages: 2,
+                pending: 1,
+                failed: 0,
+                dead_letter_queues: 1,
+                dead_lettered: 1,
+            }
+        );
+        let health = overview.health.unwrap();
+        assert_eq!(health.tasks.len(), 1);
+        assert!(health.tasks[0].healthy);
+
+        // Users only see their own namespaces, and not the server's health.
+        service
+            .create_user(
+                Email::from_str("user@example.com").unwrap(),
+                "password".to_owned(),
+                None,
+                vec![],
+            )
+            .await
+            .unwrap();
+        let overview = service
+            .overview(Identity::mock("user@example.com".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(overview.totals, Totals::default());
+        assert!(overview.health.is_none());
+    }
+}
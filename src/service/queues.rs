@@ -0,0 +1,2235 @@
+//! Namespaces and queues: creating, listing and deleting them, and their attributes, tags, limits,
+//! consumer groups, consumers and statistics.
+
+use std::collections::{HashMap, HashSet};
+
+use actix_identity::Identity;
+use sqlx::{Acquire, Sqlite};
+use tokio_stream::StreamExt as _;
+
+use crate::{
+    api::auth::{Role, User},
+    consumer_group::ConsumerGroup,
+    consumers::{self, Consumer, UnattendedQueue, CONSUMER_TTL},
+    error::Error,
+    events::Event,
+    logging,
+    namespace::{Namespace, NamespaceLimits, NamespaceStatistics},
+    push::{self, PushSubscription, PushSubscriptionRequest},
+    queue::{Queue, QueueStatistics},
+    redelivery,
+    trace::TraceEvent,
+};
+
+use super::{
+    attribute_key, json_string, queue_attributes, read_only_attribute, OrderingMode,
+    QueueAttribute, QueueAttributesSer, QueueConfig, RedriveAllowPolicy, RedrivePolicy, Service,
+    MAX_VISIBILITY_TIMEOUT, ORDERING_MODE_ATTRIBUTE,
+};
+
+/// Operations on namespaces and queues, see [`Service::queues`].
+#[derive(Clone)]
+pub struct QueueService<'a> {
+    service: &'a Service,
+}
+
+impl QueueService<'_> {
+    pub(super) fn new(service: &Service) -> QueueService<'_> {
+        QueueService { service }
+    }
+
+    /// Gets the internal ID for a queue given its namespace and name.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `name` - Name of the queue
+    /// * `exec` - Database executor to use
+    pub async fn get_queue_id(
+        &self,
+        namespace: &str,
+        name: &str,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<Option<u64>, Error> {
+        logging::record_queue(namespace, name);
+
+        Ok(sqlx::query_scalar(
+            "
+            SELECT q.id FROM queues q
+            JOIN namespaces n ON q.ns = n.id
+            WHERE n.name = $1 AND q.name = $2
+            ",
+        )
+        .bind(namespace)
+        .bind(name)
+        .fetch_optional(&mut *exec.acquire().await?)
+        .await?)
+    }
+
+    /// Gets the internal ID for a namespace given its name.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the namespace
+    /// * `ex` - Database executor to use
+    pub async fn get_namespace_id<'a>(
+        &self,
+        name: &str,
+        ex: impl Acquire<'a, Database = Sqlite>,
+    ) -> Result<Option<u64>, Error> {
+        logging::record_namespace(name);
+
+        Ok(sqlx::query_scalar(
+            "
+            SELECT id FROM namespaces WHERE name = $1
+            ",
+        )
+        .bind(name)
+        .fetch_optional(&mut *ex.acquire().await?)
+        .await?)
+    }
+
+    /// Lists all namespaces accessible to the authenticated user.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_namespaces(&self, identity: Identity) -> Result<Vec<Namespace>, Error> {
+        let email = identity.id()?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT ns.id, ns.name, nu.email as created_by FROM namespaces ns
+            JOIN user_permissions p ON p.namespace = ns.id
+            JOIN users u ON p.user = u.id
+            JOIN users nu ON ns.created_by = nu.id
+            WHERE u.email = $1
+        ",
+        )
+        .bind(email)
+        .fetch_all(&mut *self.service.db.acquire().await?)
+        .await?)
+    }
+
+    /// Creates a new namespace. Admins can create any number of namespaces, other users only up
+    /// to their namespace quota (see [`Service::set_namespace_quota`]).
+    ///
+    /// # Arguments
+    /// * `name` - Name of the namespace to create
+    /// * `identity` - Identity of the authenticated user
+    ///
+    /// # Errors
+    /// * `Error::Unauthorized` - If the user isn't an admin and has no namespace quota
+    /// * `Error::QuotaExceeded` - If the user has already created as many namespaces as their
+    ///   quota allows
+    pub async fn create_namespace(&self, name: &str, identity: Identity) -> Result<u64, Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let user_email = identity.id()?;
+
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE email = $1")
+            .bind(&user_email)
+            .fetch_optional(&mut *tx.acquire().await?)
+            .await?
+            .ok_or_else(|| Error::Unauthorized)?;
+
+        if user.role != Role::Admin {
+            let quota: u64 = sqlx::query_scalar::<_, Option<u64>>(
+                "SELECT namespace_quota FROM users WHERE id = $1",
+            )
+            .bind(user.id as i64)
+            .fetch_one(&mut *tx)
+            .await?
+            .ok_or(Error::Unauthorized)?;
+
+            let created: u64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM namespaces WHERE created_by = $1")
+                    .bind(user.id as i64)
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+            if created >= quota {
+                return Err(Error::QuotaExceeded {
+                    resource: "namespaces".to_owned(),
+                    limit: quota,
+                });
+            }
+        }
+
+        let ns_id: Option<u64> = sqlx::query_scalar(
+            "
+            INSERT INTO namespaces(name, created_by) VALUES ($1, $2)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id
+            ",
+        )
+        .bind(name)
+        .bind(user.id as i64)
+        .fetch_optional(&mut *tx.as_mut().acquire().await?)
+        .await?;
+
+        let Some(ns_id) = ns_id else {
+            return Err(Error::invalid_parameter(format!(
+                "namespace {name} already exists"
+            )));
+        };
+
+        sqlx::query(
+            "
+            INSERT INTO user_permissions (user, namespace, can_delete_ns)
+            VALUES ($1, $2, true)
+        ",
+        )
+        .bind(user.id as i64)
+        .bind(ns_id as i64)
+        .execute(&mut *tx.as_mut().acquire().await?)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user.id)
+    }
+
+    /// Deletes a namespace and all its queues. User must have delete permission.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the namespace to delete
+    /// * `identity` - Identity of the authenticated user
+    pub async fn delete_namespace(&self, name: &str, identity: Identity) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let namespace = self
+            .get_namespace_id(name, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(name))?;
+
+        let (_user_id, can_delete) = self
+            .service
+            .check_user_access(&identity, namespace, &mut tx)
+            .await?;
+
+        if !can_delete {
+            return Err(Error::Unauthorized);
+        }
+
+        sqlx::query(
+            "
+            DELETE FROM namespaces WHERE name = $1
+        ",
+        )
+        .bind(name)
+        .execute(&mut *tx)
+        .await
+        .map(|_| ())?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Creates a new queue in a namespace.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace to create the queue in
+    /// * `name` - Name of the queue
+    /// * `attributes` - Queue configuration attributes
+    /// * `tags` - Metadata tags for the queue
+    /// * `identity` - Identity of the authenticated user
+    pub async fn create_queue(
+        &self,
+        namespace: &str,
+        name: &str,
+        attributes: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let namespace_name = namespace;
+        let namespace = self
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        let (user_id, _) = self
+            .service
+            .check_user_access(&identity, namespace, &mut tx)
+            .await?;
+
+        let limits = self.get_namespace_limits(namespace, &mut tx).await?;
+        if let Some(max_queues) = limits.max_queues {
+            let queues: u64 = sqlx::query_scalar("SELECT COUNT(*) FROM queues WHERE ns = $1")
+                .bind(namespace as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+            if queues >= max_queues {
+                return Err(Error::OverLimit {
+                    resource: format!("queues in namespace {namespace_name}"),
+                    limit: max_queues,
+                });
+            }
+        }
+
+        let queue_id: Option<u64> = sqlx::query_scalar(
+            "
+            INSERT INTO queues (ns, name, created_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (ns, name) DO NOTHING
+            RETURNING id
+        ",
+        )
+        .bind(namespace as i64)
+        .bind(name)
+        .bind(user_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(queue_id) = queue_id else {
+            return Err(Error::invalid_parameter(format!(
+                "queue {name} already exists in namespace {namespace_name}"
+            )));
+        };
+
+        sqlx::query(
+            "
+            INSERT INTO queue_configurations (queue, max_retries)
+            VALUES ($1, $2)
+        ",
+        )
+        .bind(queue_id as i64)
+        .bind(self.service.config.default_max_retries() as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        for (k, v) in attributes.into_iter() {
+            let k = attribute_key(&k).to_owned();
+            if k == ORDERING_MODE_ATTRIBUTE {
+                return Err(read_only_attribute(&k));
+            }
+            if k == queue_attributes::RedeliveryBackoff.name() {
+                redelivery::parse_delays(&v)?;
+            }
+
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, $2, $3)
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(k)
+            .bind(v)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for (k, v) in tags.into_iter() {
+            sqlx::query(
+                "
+                INSERT INTO queue_tags (queue, k, v)
+                VALUES ($1, $2, $3)
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(k)
+            .bind(v)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        self.service.events.publish(Event::QueueCreated {
+            queue: queue_id,
+            namespace: namespace_name.to_owned(),
+            name: name.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    /// Updates the attributes of an existing queue.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the queue
+    /// * `queue` - Name of the queue
+    /// * `attributes` - New queue attributes
+    /// * `identity` - Identity of the authenticated user
+    pub async fn set_queue_attributes(
+        &self,
+        ns: &str,
+        queue: &str,
+        attributes: QueueAttributesSer,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let ns_id = self
+            .get_namespace_id(ns, &mut *tx)
+            .await?
+            .ok_or(Error::namespace_not_found(ns))?;
+
+        self.service
+            .check_user_access(&identity, ns_id, &mut *tx)
+            .await?;
+
+        let queue_id = self
+            .get_queue_id(ns, queue, &mut *tx)
+            .await?
+            .ok_or(Error::queue_not_found(queue, ns))?;
+
+        if attributes.ordering_mode.is_some() {
+            return Err(read_only_attribute(ORDERING_MODE_ATTRIBUTE));
+        }
+
+        if let Some(delay_seconds) = attributes.delay_seconds {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'delay_seconds', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(delay_seconds as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(max_message_size) = attributes.max_message_size {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'max_message_size', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(max_message_size as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(message_retention_period) = attributes.message_retention_period {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'message_retention_period', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(message_retention_period as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(receive_message_wait_time_seconds) =
+            attributes.receive_message_wait_time_seconds
+        {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'receive_message_wait_time_seconds', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(receive_message_wait_time_seconds as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(visibility_timeout) = attributes.visibility_timeout {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'visibility_timeout', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(visibility_timeout as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(max_concurrent_receives) = attributes.max_concurrent_receives {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'max_concurrent_receives', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(max_concurrent_receives as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(fair_receive_key) = attributes.fair_receive_key {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'fair_receive_key', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(fair_receive_key)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(compression_threshold) = attributes.compression_threshold {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'compression_threshold', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(compression_threshold as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(sample_masked_attributes) = attributes.sample_masked_attributes {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'sample_masked_attributes', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(sample_masked_attributes)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(redelivery_backoff) = attributes.redelivery_backoff {
+            redelivery::parse_delays(&redelivery_backoff)?;
+
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'redelivery_backoff', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(redelivery_backoff)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(redrive_allow_policy) = attributes.redrive_allow_policy {
+            if !redrive_allow_policy.is_empty() {
+                RedriveAllowPolicy::parse(&redrive_allow_policy)?;
+            }
+
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'redrive_allow_policy', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(redrive_allow_policy)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(redrive_policy) = attributes.redrive_policy {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, 'redrive_policy', $2)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(&redrive_policy)
+            .execute(&mut *tx)
+            .await?;
+
+            self.apply_redrive_policy(ns, queue, queue_id, &redrive_policy, &mut tx)
+                .await?;
+        }
+
+        for (k, v) in attributes.other.into_iter() {
+            sqlx::query(
+                "
+                INSERT INTO queue_attributes (queue, k, v)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $3
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(k)
+            .bind(v)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Points a queue's configuration at the dead-letter queue named by its redrive policy.
+    ///
+    /// An empty policy removes the dead-letter queue and restores the default retry limit.
+    ///
+    /// # Errors
+    /// * `Error::InvalidParameter` - If the target's redrive allow policy does not permit the queue
+    pub(super) async fn apply_redrive_policy(
+        &self,
+        ns: &str,
+        queue: &str,
+        queue_id: u64,
+        redrive_policy: &str,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<(), Error> {
+        if redrive_policy.is_empty() {
+            sqlx::query(
+                "
+                UPDATE queue_configurations
+                SET dead_letter_queue = NULL, max_retries = $1
+                WHERE queue = $2
+                ",
+            )
+            .bind(self.service.config.default_max_retries() as i64)
+            .bind(queue_id as i64)
+            .execute(&mut **tx)
+            .await?;
+
+            return Ok(());
+        }
+
+        let policy: RedrivePolicy = serde_json::from_str(redrive_policy)
+            .map_err(|e| Error::invalid_parameter(format!("RedrivePolicy: {e}")))?;
+
+        // A target without a namespace refers to a queue in the same namespace.
+        let (dlq_ns, dlq_name) = policy
+            .dead_letter_target_arn
+            .split_once(':')
+            .unwrap_or((ns, &policy.dead_letter_target_arn));
+
+        let dlq_id = self
+            .get_queue_id(dlq_ns, dlq_name, &mut **tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(dlq_name, dlq_ns))?;
+
+        if dlq_id == queue_id {
+            return Err(Error::invalid_parameter(
+                "RedrivePolicy: a queue can't be its own dead-letter queue",
+            ));
+        }
+
+        let allow_policy: Option<String> = sqlx::query_scalar(
+            "
+            SELECT v FROM queue_attributes WHERE queue = $1 AND k = 'redrive_allow_policy'
+            ",
+        )
+        .bind(dlq_id as i64)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        if let Some(allow_policy) = allow_policy.filter(|policy| !policy.is_empty()) {
+            if !RedriveAllowPolicy::parse(&allow_policy)?.allows(ns, queue) {
+                return Err(Error::invalid_parameter(format!(
+                    "RedrivePolicy: {dlq_ns}:{dlq_name} does not allow {ns}:{queue} to use it as a dead-letter queue"
+                )));
+            }
+        }
+
+        sqlx::query(
+            "
+            UPDATE queue_configurations
+            SET dead_letter_queue = $1, max_retries = $2
+            WHERE queue = $3
+            ",
+        )
+        .bind(dlq_id as i64)
+        .bind(policy.max_receive_count as i64)
+        .bind(queue_id as i64)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gets the current attributes of a queue.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the queue
+    /// * `queue` - Name of the queue
+    /// * `names` - Names of attributes to retrieve
+    /// * `identity` - Identity of the authenticated user
+    pub async fn get_queue_attributes(
+        &self,
+        ns: &str,
+        queue: &str,
+        names: &[String],
+        identity: Identity,
+    ) -> Result<QueueAttributesSer, Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        let ns_id = self
+            .get_namespace_id(ns, &mut *db)
+            .await?
+            .ok_or(Error::namespace_not_found(ns))?;
+
+        self.service
+            .check_user_access(&identity, ns_id, &mut *db)
+            .await?;
+
+        let queue_id = self
+            .get_queue_id(ns, queue, &mut *db)
+            .await?
+            .ok_or(Error::queue_not_found(queue, ns))?;
+
+        let set = names.iter().collect::<HashSet<_>>();
+
+        let mut res = sqlx::query_as::<_, (String, String)>(
+            "
+            SELECT k, CAST(v AS TEXT) FROM queue_attributes WHERE queue = $1
+            ",
+        )
+        .bind(queue_id as i64)
+        .fetch(&mut *db);
+
+        let mut attributes = QueueAttributesSer {
+            delay_seconds: None,
+            max_message_size: None,
+            message_retention_period: None,
+            receive_message_wait_time_seconds: None,
+            visibility_timeout: None,
+            max_concurrent_receives: None,
+            fair_receive_key: None,
+            compression_threshold: None,
+            sample_masked_attributes: None,
+            redelivery_backoff: None,
+            ordering_mode: Some(OrderingMode::Insertion),
+            redrive_policy: None,
+            redrive_allow_policy: None,
+            other: Default::default(),
+        };
+        while let Some((k, v)) = res.next().await.transpose()? {
+            // Values are stored as text, which is JSON for everything but free-form strings.
+            let v = serde_json::from_str(&v).unwrap_or(serde_json::Value::String(v));
+            match &*k {
+                "delay_seconds" => attributes.delay_seconds = Some(serde_json::from_value(v)?),
+                "max_message_size" => {
+                    attributes.max_message_size = Some(serde_json::from_value(v)?)
+                }
+                "message_retention_period" => {
+                    attributes.message_retention_period = Some(serde_json::from_value(v)?)
+                }
+                "receive_message_wait_time_seconds" => {
+                    attributes.receive_message_wait_time_seconds = Some(serde_json::from_value(v)?)
+                }
+                "visibility_timeout" => {
+                    attributes.visibility_timeout = Some(serde_json::from_value(v)?)
+                }
+                "max_concurrent_receives" => {
+                    attributes.max_concurrent_receives = Some(serde_json::from_value(v)?)
+                }
+                "fair_receive_key" => {
+                    let key = json_string(v);
+                    if !key.is_empty() {
+                        attributes.ordering_mode = Some(OrderingMode::Fair);
+                    }
+                    attributes.fair_receive_key = Some(key);
+                }
+                "compression_threshold" => {
+                    attributes.compression_threshold = Some(serde_json::from_value(v)?)
+                }
+                "sample_masked_attributes" => {
+                    attributes.sample_masked_attributes = Some(json_string(v))
+                }
+                "redelivery_backoff" => attributes.redelivery_backoff = Some(json_string(v)),
+                // Policies are stored as JSON text, which is decoded into an object here.
+                "redrive_policy" => attributes.redrive_policy = Some(json_string(v)),
+                "redrive_allow_policy" => attributes.redrive_allow_policy = Some(json_string(v)),
+                _ => {
+                    if set.contains(&k) {
+                        attributes.other.insert(k, v);
+                    }
+                }
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// Adds or updates tags on a queue.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the queue
+    /// * `queue` - Name of the queue
+    /// * `tags` - Tags to set
+    /// * `identity` - Identity of the authenticated user
+    pub async fn tag_queue(
+        &self,
+        ns: &str,
+        queue: &str,
+        tags: HashMap<String, String>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        let mut db = self.service.db().acquire().await?;
+        let ns_id = self
+            .get_namespace_id(ns, &mut *db)
+            .await?
+            .ok_or(Error::namespace_not_found(ns))?;
+
+        self.service
+            .check_user_access(&identity, ns_id, &mut *db)
+            .await?;
+
+        let queue_id = self
+            .get_queue_id(ns, queue, &mut *db)
+            .await?
+            .ok_or(Error::queue_not_found(queue, ns))?;
+
+        for (k, v) in tags.into_iter() {
+            sqlx::query(
+                "
+                INSERT INTO queue_tags (queue, k, v)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (queue, k) DO UPDATE SET v = $3
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(k)
+            .bind(v)
+            .execute(&mut *db)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes tags from a queue.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the queue
+    /// * `queue` - Name of the queue
+    /// * `tags` - Tags to remove
+    /// * `identity` - Identity of the authenticated user
+    pub async fn untag_queue(
+        &self,
+        ns: &str,
+        queue: &str,
+        tags: Vec<String>,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        let mut db = self.service.db().acquire().await?;
+        let ns_id = self
+            .get_namespace_id(ns, &mut *db)
+            .await?
+            .ok_or(Error::namespace_not_found(ns))?;
+
+        self.service
+            .check_user_access(&identity, ns_id, &mut *db)
+            .await?;
+
+        let queue_id = self
+            .get_queue_id(ns, queue, &mut *db)
+            .await?
+            .ok_or(Error::queue_not_found(queue, ns))?;
+
+        for tag in tags {
+            sqlx::query(
+                "
+                DELETE FROM queue_tags WHERE queue = $1 AND k = $2
+                ",
+            )
+            .bind(queue_id as i64)
+            .bind(tag)
+            .execute(&mut *db)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets all tags for a queue.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace containing the queue
+    /// * `queue` - Name of the queue
+    /// * `identity` - Identity of the authenticated user
+    pub async fn get_queue_tags(
+        &self,
+        ns: &str,
+        queue: &str,
+        identity: Identity,
+    ) -> Result<HashMap<String, String>, Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        let ns_id = self
+            .get_namespace_id(ns, &mut *db)
+            .await?
+            .ok_or(Error::namespace_not_found(ns))?;
+
+        self.service
+            .check_user_access(&identity, ns_id, &mut *db)
+            .await?;
+
+        let queue_id = self
+            .get_queue_id(ns, queue, &mut *db)
+            .await?
+            .ok_or(Error::queue_not_found(queue, ns))?;
+
+        let res = sqlx::query_as(
+            "
+            SELECT k, v FROM queue_tags WHERE queue = $1
+            ",
+        )
+        .bind(queue_id as i64)
+        .fetch_all(&mut *db)
+        .await?;
+
+        Ok(res.into_iter().collect())
+    }
+
+    /// Deletes a queue and all its messages.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `name` - Name of the queue
+    /// * `identity` - Identity of the authenticated user
+    pub async fn delete_queue(
+        &self,
+        namespace: &str,
+        name: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let namespace_id = self
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        let id = self
+            .get_queue_id(namespace, name, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(name, namespace))?;
+
+        self.service
+            .record_trace_event(TraceEvent::QueueDeleted, id, None, &mut tx)
+            .await?;
+
+        sqlx::query("DELETE FROM queues WHERE id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        self.service.events.publish(Event::QueueDeleted {
+            queue: id,
+            namespace: namespace.to_owned(),
+            name: name.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    /// Lists queues, optionally filtered by namespace.
+    ///
+    /// # Arguments
+    /// * `namespace` - Optional namespace to filter by
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_queues(
+        &self,
+        namespace: Option<&str>,
+        identity: Identity,
+    ) -> Result<Vec<Queue>, Error> {
+        let mut conn = self.service.db().acquire().await?;
+
+        if let Some(namespace) = namespace {
+            let namespace_id = self
+                .get_namespace_id(namespace, &mut *conn)
+                .await?
+                .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+            self.service
+                .check_user_access(&identity, namespace_id, &mut *conn)
+                .await?;
+        }
+
+        // Queue::list(conn.acquire().await?, namespace, identity).await
+
+        match namespace {
+            Some(ns) => self.list_queues_for_namespace(ns).await,
+            None => self.list_all_queues(identity).await,
+        }
+    }
+
+    /// Lists the queues of a namespace whose redrive policy targets a queue, in order of their
+    /// IDs.
+    ///
+    /// # Arguments
+    /// * `ns` - Namespace of the dead-letter queue, and of the source queues listed
+    /// * `queue` - Name of the dead-letter queue
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_dead_letter_source_queues(
+        &self,
+        ns: &str,
+        queue: &str,
+        identity: Identity,
+    ) -> Result<Vec<Queue>, Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        let ns_id = self
+            .get_namespace_id(ns, &mut *db)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(ns))?;
+
+        self.service
+            .check_user_access(&identity, ns_id, &mut *db)
+            .await?;
+
+        let dlq_id = self
+            .get_queue_id(ns, queue, &mut *db)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, ns))?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT q.id, q.name, n.name AS ns, COALESCE(u.email, '') AS created_by FROM queues q
+            JOIN queue_configurations c ON c.queue = q.id
+            JOIN namespaces n ON q.ns = n.id
+            LEFT JOIN users u ON q.created_by = u.id
+            WHERE c.dead_letter_queue = $1 AND q.ns = $2
+            ORDER BY q.id
+            ",
+        )
+        .bind(dlq_id as i64)
+        .bind(ns_id as i64)
+        .fetch_all(&mut *db)
+        .await?)
+    }
+
+    /// Lists all queues in a specific namespace.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace to list queues from
+    pub async fn list_queues_for_namespace(&self, namespace: &str) -> Result<Vec<Queue>, Error> {
+        let mut db = self.service.db().acquire().await?;
+        let mut stream = sqlx::query_as(
+            "
+            SELECT q.id, q.name, n.name as ns, COALESCE(u.email, '') as created_by FROM queues q
+            JOIN namespaces n ON q.ns = n.id
+            LEFT JOIN users u on q.created_by = u.id
+            WHERE n.name = $1",
+        )
+        .bind(namespace)
+        .fetch(&mut *db);
+
+        let mut queues = Vec::new();
+
+        while let Some(res) = stream.next().await.transpose()? {
+            queues.push(res);
+        }
+
+        Ok(queues)
+    }
+
+    /// Lists all queues accessible to the authenticated user.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_all_queues(&self, identity: Identity) -> Result<Vec<Queue>, Error> {
+        let email = identity.id()?;
+
+        let queues = sqlx::query_as(
+            "
+            SELECT q.id, q.name, COALESCE(qu.email, '') as created_by, n.name as ns FROM queues q
+            JOIN user_permissions p ON p.namespace = q.ns
+            JOIN namespaces n ON n.id = q.ns
+            JOIN users u ON u.id = p.user
+            LEFT JOIN users qu ON q.created_by = qu.id
+            WHERE u.email = $1
+            ",
+        )
+        .bind(email)
+        .fetch_all(&mut *self.service.db().acquire().await?)
+        .await?;
+
+        Ok(queues)
+    }
+
+    /// Sets the webhook the messages of a queue are pushed to, replacing the queue's previous
+    /// one, see [`crate::push`].
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `request` - URL, authorization header, concurrency, batching and backoff of the
+    ///   subscription
+    pub async fn set_push_subscription(
+        &self,
+        namespace: &str,
+        queue: &str,
+        request: PushSubscriptionRequest,
+    ) -> Result<PushSubscription, Error> {
+        push::validate_url(&request.url)?;
+
+        let concurrency = request
+            .concurrency
+            .unwrap_or(push::DEFAULT_PUSH_CONCURRENCY);
+        if !(1..=push::MAX_PUSH_CONCURRENCY).contains(&concurrency) {
+            return Err(Error::invalid_parameter(format!(
+                "concurrency: must be between 1 and {}",
+                push::MAX_PUSH_CONCURRENCY
+            )));
+        }
+
+        let batch_size = request.batch_size.unwrap_or(1);
+        if !(1..=push::MAX_PUSH_BATCH_SIZE).contains(&batch_size) {
+            return Err(Error::invalid_parameter(format!(
+                "batchSize: must be between 1 and {}",
+                push::MAX_PUSH_BATCH_SIZE
+            )));
+        }
+        let batch_window = request.batch_window.unwrap_or(0);
+        if batch_window > push::MAX_PUSH_BATCH_WINDOW.as_millis() as u64 {
+            return Err(Error::invalid_parameter(format!(
+                "batchWindow: must be at most {} milliseconds",
+                push::MAX_PUSH_BATCH_WINDOW.as_millis()
+            )));
+        }
+
+        let initial_backoff = request
+            .initial_backoff
+            .unwrap_or(push::DEFAULT_INITIAL_BACKOFF);
+        let max_backoff = request
+            .max_backoff
+            .unwrap_or(push::DEFAULT_MAX_BACKOFF.max(initial_backoff));
+        if max_backoff > MAX_VISIBILITY_TIMEOUT.as_secs() {
+            return Err(Error::invalid_parameter(format!(
+                "maxBackoff: must be at most {} seconds",
+                MAX_VISIBILITY_TIMEOUT.as_secs()
+            )));
+        }
+        if initial_backoff > max_backoff {
+            return Err(Error::invalid_parameter(
+                "initialBackoff: must be at most maxBackoff",
+            ));
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        let queue_id = self
+            .get_queue_id(namespace, queue, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        if self.has_consumer_groups(queue_id).await? {
+            return Err(Error::invalid_parameter(format!(
+                "queue {queue} has consumer groups, which can't be pushed to"
+            )));
+        }
+
+        let subscription: PushSubscription = sqlx::query_as(
+            "
+            INSERT INTO push_subscriptions
+                (queue, url, auth_header, concurrency, batch_size, batch_window, initial_backoff,
+                 max_backoff, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (queue) DO UPDATE SET
+                url = excluded.url,
+                auth_header = excluded.auth_header,
+                concurrency = excluded.concurrency,
+                batch_size = excluded.batch_size,
+                batch_window = excluded.batch_window,
+                initial_backoff = excluded.initial_backoff,
+                max_backoff = excluded.max_backoff,
+                created_at = excluded.created_at
+            RETURNING *
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(request.url.as_str())
+        .bind(request.auth_header)
+        .bind(concurrency as i64)
+        .bind(batch_size as i64)
+        .bind(batch_window as i64)
+        .bind(initial_backoff as i64)
+        .bind(max_backoff as i64)
+        .bind(self.service.now())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace,
+            queue,
+            url = subscription.url,
+            "Push subscription set"
+        );
+
+        Ok(subscription)
+    }
+
+    /// Returns the push subscription of a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    pub async fn get_push_subscription(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<PushSubscription, Error> {
+        let queue_id = self
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        sqlx::query_as("SELECT * FROM push_subscriptions WHERE queue = $1")
+            .bind(queue_id as i64)
+            .fetch_optional(self.service.db())
+            .await?
+            .ok_or_else(|| Error::not_found(format!("push subscription of queue {queue}")))
+    }
+
+    /// Deletes the push subscription of a queue. Messages already being pushed are still
+    /// delivered.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    pub async fn delete_push_subscription(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<(), Error> {
+        let queue_id = self
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let deleted = sqlx::query("DELETE FROM push_subscriptions WHERE queue = $1")
+            .bind(queue_id as i64)
+            .execute(self.service.db())
+            .await?
+            .rows_affected();
+
+        if deleted == 0 {
+            return Err(Error::not_found(format!(
+                "push subscription of queue {queue}"
+            )));
+        }
+
+        tracing::info!(target: "nervemq::audit", namespace, queue, "Push subscription deleted");
+
+        Ok(())
+    }
+
+    /// Gets the limits on the resources of a namespace.
+    ///
+    /// # Arguments
+    /// * `namespace` - Name of the namespace
+    ///
+    /// # Errors
+    /// * `Error::NotFound` - If the namespace doesn't exist
+    pub async fn namespace_limits(&self, namespace: &str) -> Result<NamespaceLimits, Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        let ns_id = self
+            .get_namespace_id(namespace, &mut *db)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.get_namespace_limits(ns_id, &mut *db).await
+    }
+
+    /// Sets the limits on the resources of a namespace. Lowering a limit below the current usage
+    /// doesn't remove anything, but nothing more can be added until usage drops below it.
+    ///
+    /// # Arguments
+    /// * `namespace` - Name of the namespace
+    /// * `limits` - New limits, replacing the current ones
+    ///
+    /// # Errors
+    /// * `Error::NotFound` - If the namespace doesn't exist
+    pub async fn set_namespace_limits(
+        &self,
+        namespace: &str,
+        limits: NamespaceLimits,
+    ) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let ns_id = self
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        sqlx::query(
+            "
+            INSERT INTO namespace_limits
+                (namespace, max_queues, max_storage_bytes, max_messages_per_queue)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (namespace) DO UPDATE SET
+                max_queues = excluded.max_queues,
+                max_storage_bytes = excluded.max_storage_bytes,
+                max_messages_per_queue = excluded.max_messages_per_queue
+            ",
+        )
+        .bind(ns_id as i64)
+        .bind(limits.max_queues.map(|limit| limit as i64))
+        .bind(limits.max_storage_bytes.map(|limit| limit as i64))
+        .bind(limits.max_messages_per_queue.map(|limit| limit as i64))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace,
+            max_queues = limits.max_queues,
+            max_storage_bytes = limits.max_storage_bytes,
+            max_messages_per_queue = limits.max_messages_per_queue,
+            "Namespace limits set"
+        );
+
+        Ok(())
+    }
+
+    pub(super) async fn get_namespace_limits<'a>(
+        &self,
+        ns_id: u64,
+        exec: impl Acquire<'a, Database = Sqlite>,
+    ) -> Result<NamespaceLimits, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT max_queues, max_storage_bytes, max_messages_per_queue
+            FROM namespace_limits WHERE namespace = $1
+            ",
+        )
+        .bind(ns_id as i64)
+        .fetch_optional(&mut *exec.acquire().await?)
+        .await?
+        .unwrap_or_default())
+    }
+
+    /// Reads a numeric attribute of a queue, if it has been set.
+    pub(super) async fn get_queue_attribute<A>(
+        &self,
+        queue_id: u64,
+        attribute: A,
+    ) -> Result<Option<u64>, Error>
+    where
+        A: QueueAttribute<Value = u64>,
+    {
+        let value: Option<Option<i64>> = sqlx::query_scalar(
+            "
+            SELECT CAST(v AS INTEGER) FROM queue_attributes WHERE queue = $1 AND k = $2
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(attribute.name())
+        .fetch_optional(self.service.db())
+        .await?;
+
+        Ok(value.flatten().map(|v| v as u64))
+    }
+
+    /// Reads a text attribute of a queue, if it has been set.
+    pub(super) async fn get_queue_text_attribute<A>(
+        &self,
+        queue_id: u64,
+        attribute: A,
+    ) -> Result<Option<String>, Error>
+    where
+        A: QueueAttribute<Value = String>,
+    {
+        Ok(sqlx::query_scalar(
+            "
+            SELECT CAST(v AS TEXT) FROM queue_attributes WHERE queue = $1 AND k = $2
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(attribute.name())
+        .fetch_optional(self.service.db())
+        .await?)
+    }
+
+    /// Gets the configuration for a queue.
+    ///
+    /// # Arguments
+    /// * `queue` - Queue ID
+    pub async fn get_queue_configuration(&self, queue: u64) -> Result<QueueConfig, Error> {
+        let mut db = self.service.db().acquire().await?;
+        Ok(sqlx::query_as(
+            "
+            SELECT queue, max_retries, dead_letter_queue, history, history_retention
+            FROM queue_configurations
+            WHERE queue = $1
+            ",
+        )
+        .bind(queue as i64)
+        .fetch_one(&mut *db)
+        .await?)
+    }
+
+    /// Updates the configuration for a queue.
+    ///
+    /// # Arguments
+    /// * `queue` - Queue ID
+    /// * `new_config` - New configuration settings
+    pub async fn update_queue_configuration(
+        &self,
+        queue: u64,
+        new_config: QueueConfig,
+    ) -> Result<(), Error> {
+        let mut db = self.service.db().acquire().await?;
+
+        sqlx::query(
+            "
+            UPDATE queue_configurations
+            SET max_retries = $1, dead_letter_queue = $2, history = $3, history_retention = $4
+            WHERE queue = $5
+            ",
+        )
+        .bind(new_config.max_retries as i64)
+        .bind(new_config.dead_letter_queue.map(|id| id as i64))
+        .bind(new_config.history)
+        .bind(new_config.history_retention as i64)
+        .bind(queue as i64)
+        .execute(&mut *db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gets statistics for a specific queue.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    pub async fn queue_statistics(
+        &self,
+        identity: Identity,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<QueueStatistics, Error> {
+        let mut db = self.service.db().acquire().await?;
+        let email = identity.id()?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                q.id,
+                q.name,
+                COALESCE(qu.email, '') as created_by,
+                n.name as ns,
+                COUNT(m.id) AS message_count,
+                IFNULL(AVG(LENGTH(m.body)), 0.0) as avg_size_bytes,
+                COUNT(CASE WHEN m.delivered_at IS NULL AND m.tries < conf.max_retries THEN 1 END) as pending,
+                COUNT(CASE WHEN m.delivered_at IS NOT NULL THEN 1 END) as delivered,
+                COUNT(CASE WHEN m.delivered_at IS NULL AND m.tries >= conf.max_retries THEN 1 END) as failed,
+                COUNT(CASE WHEN m.delivered_at IS NOT NULL AND m.visible_at > $4 THEN 1 END) as in_flight,
+                EXISTS (
+                    SELECT 1 FROM queue_configurations dl WHERE dl.dead_letter_queue = q.id
+                ) as dead_letter_queue
+            FROM queues q
+            JOIN queue_configurations conf ON q.id = conf.queue
+            LEFT JOIN messages m ON q.id = m.queue
+            JOIN user_permissions p ON p.namespace = q.ns
+            JOIN namespaces n ON n.id = q.ns
+            JOIN users u ON u.id = p.user
+            LEFT JOIN users qu ON q.created_by = qu.id
+            WHERE u.email = $1 AND n.name = $2 AND q.name = $3
+        ",
+        )
+        .bind(email)
+        .bind(namespace)
+        .bind(queue)
+        .bind(self.service.now())
+        .fetch_one(&mut *db)
+        .await?)
+    }
+
+    /// Gets statistics for all queues accessible to the user.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn global_queue_statistics(
+        &self,
+        identity: Identity,
+    ) -> Result<HashMap<String, QueueStatistics>, Error> {
+        let mut db = self.service.db().acquire().await?;
+        let email = identity.id()?;
+
+        let res = sqlx::query_as(
+            "
+            SELECT
+                q.id,
+                q.name,
+                COALESCE(qu.email, '') as created_by,
+                n.name as ns,
+                COUNT(m.id) AS message_count,
+                IFNULL(AVG(LENGTH(m.body)), 0.0) as avg_size_bytes,
+                COUNT(CASE WHEN m.delivered_at IS NULL AND m.tries < conf.max_retries THEN 1 END) as pending,
+                COUNT(CASE WHEN m.delivered_at IS NOT NULL  THEN 1 END) as delivered,
+                COUNT(CASE WHEN m.delivered_at IS NULL AND m.tries >= conf.max_retries THEN 1 END) as failed,
+                COUNT(CASE WHEN m.delivered_at IS NOT NULL AND m.visible_at > $2 THEN 1 END) as in_flight,
+                EXISTS (
+                    SELECT 1 FROM queue_configurations dl WHERE dl.dead_letter_queue = q.id
+                ) as dead_letter_queue
+            FROM queues q
+            JOIN queue_configurations conf ON q.id = conf.queue
+            LEFT JOIN messages m ON q.id = m.queue
+            JOIN user_permissions p ON p.namespace = q.ns
+            JOIN namespaces n ON n.id = q.ns
+            JOIN users u ON u.id = p.user
+            LEFT JOIN users qu ON q.created_by = qu.id
+            WHERE u.email = $1
+            GROUP BY q.id, q.name
+        ",
+        )
+        .bind(email)
+        .bind(self.service.now())
+        .fetch_all(&mut *db)
+        .await?
+        .into_iter()
+        .map(|row: QueueStatistics| (row.queue.name.clone(), row))
+        .collect::<HashMap<_, _>>();
+
+        Ok(res)
+    }
+
+    /// Lists the consumer groups of a queue.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    pub async fn list_consumer_groups(
+        &self,
+        namespace: &str,
+        queue: &str,
+    ) -> Result<Vec<ConsumerGroup>, Error> {
+        let queue_id = self
+            .get_queue_id(namespace, queue, self.service.db())
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                g.name,
+                g.created_at,
+                (
+                    SELECT COUNT(*) FROM messages m
+                    WHERE m.queue = g.queue
+                    AND NOT EXISTS (
+                        SELECT 1 FROM consumer_group_deliveries d
+                        WHERE d.grp = g.id AND d.message = m.id AND d.deleted_at IS NOT NULL
+                    )
+                ) AS pending
+            FROM consumer_groups g
+            WHERE g.queue = $1
+            ORDER BY g.name
+            ",
+        )
+        .bind(queue_id as i64)
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Creates a consumer group, switching the queue to fan-out consumption if it is its first.
+    ///
+    /// The first group of a queue receives the messages already in it. Later groups only
+    /// receive the messages sent after they were created.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `name` - Name of the group
+    pub async fn create_consumer_group(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        if name.is_empty() {
+            return Err(Error::missing_parameter("consumer group name"));
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        let queue_id = self
+            .get_queue_id(namespace, queue, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let pushed: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM push_subscriptions WHERE queue = $1)")
+                .bind(queue_id as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+        if pushed {
+            return Err(Error::invalid_parameter(format!(
+                "queue {queue} has a push subscription, which consumer groups can't share"
+            )));
+        }
+
+        let group: Option<u64> = sqlx::query_scalar(
+            "
+            INSERT INTO consumer_groups (queue, name)
+            VALUES ($1, $2)
+            ON CONFLICT (queue, name) DO NOTHING
+            RETURNING id
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(name)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(group) = group else {
+            return Err(Error::invalid_parameter(format!(
+                "consumer group {name} of queue {queue} already exists"
+            )));
+        };
+
+        // Messages already in the queue when a group joins are finished with as far as the new
+        // group is concerned, unless it is the first group and takes over the backlog.
+        sqlx::query(
+            "
+            INSERT INTO consumer_group_deliveries (grp, message, deleted_at)
+            SELECT $1, m.id, $3
+            FROM messages m
+            WHERE m.queue = $2
+            AND EXISTS (SELECT 1 FROM consumer_groups WHERE queue = $2 AND id != $1)
+            ",
+        )
+        .bind(group as i64)
+        .bind(queue_id as i64)
+        .bind(self.service.now())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace,
+            queue,
+            consumer_group = name,
+            "Consumer group created"
+        );
+
+        Ok(())
+    }
+
+    /// Deletes a consumer group and its delivery state.
+    ///
+    /// Messages that the remaining groups have all finished with are removed. If it was the last
+    /// group, the queue returns to competing consumption.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `name` - Name of the group
+    pub async fn delete_consumer_group(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let queue_id = self
+            .get_queue_id(namespace, queue, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let res = sqlx::query("DELETE FROM consumer_groups WHERE queue = $1 AND name = $2")
+            .bind(queue_id as i64)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::not_found(format!(
+                "consumer group {name} of queue {queue}"
+            )));
+        }
+
+        self.service
+            .sweep_consumed_messages(queue_id, None, &mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            namespace,
+            queue,
+            consumer_group = name,
+            "Consumer group deleted"
+        );
+
+        Ok(())
+    }
+
+    /// Looks up a consumer group of a queue.
+    pub(super) async fn get_consumer_group_id(
+        &self,
+        queue_id: u64,
+        queue: &str,
+        name: &str,
+    ) -> Result<u64, Error> {
+        sqlx::query_scalar("SELECT id FROM consumer_groups WHERE queue = $1 AND name = $2")
+            .bind(queue_id as i64)
+            .bind(name)
+            .fetch_optional(self.service.db())
+            .await?
+            .ok_or_else(|| Error::not_found(format!("consumer group {name} of queue {queue}")))
+    }
+
+    /// Checks whether a queue has switched to fan-out consumption.
+    pub(super) async fn has_consumer_groups(&self, queue_id: u64) -> Result<bool, Error> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM consumer_groups WHERE queue = $1)")
+                .bind(queue_id as i64)
+                .fetch_one(self.service.db())
+                .await?,
+        )
+    }
+
+    /// Records a heartbeat of a consumer of a queue, registering the consumer if it is new.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `name` - Name of the consumer
+    /// * `identity` - Identity of the authenticated user
+    pub async fn heartbeat_consumer(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+        identity: Identity,
+    ) -> Result<Consumer, Error> {
+        self.service.ensure_available()?;
+        consumers::validate_name(name)?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        // Verify queue exists
+        let queue_id = self
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let (registered_at, last_heartbeat_at): (u64, u64) = sqlx::query_as(
+            "
+            INSERT INTO consumers (queue, name, registered_at, last_heartbeat_at)
+            VALUES ($1, $2, $3, $3)
+            ON CONFLICT (queue, name) DO UPDATE SET last_heartbeat_at = excluded.last_heartbeat_at
+            RETURNING registered_at, last_heartbeat_at
+            ",
+        )
+        .bind(queue_id as i64)
+        .bind(name)
+        .bind(self.service.now())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Consumer {
+            name: name.to_owned(),
+            namespace: namespace.to_owned(),
+            queue: queue.to_owned(),
+            registered_at,
+            last_heartbeat_at,
+            alive: true,
+        })
+    }
+
+    /// Removes a consumer from a queue, e.g. when it shuts down.
+    ///
+    /// # Arguments
+    /// * `namespace` - Namespace containing the queue
+    /// * `queue` - Queue name
+    /// * `name` - Name of the consumer
+    /// * `identity` - Identity of the authenticated user
+    pub async fn deregister_consumer(
+        &self,
+        namespace: &str,
+        queue: &str,
+        name: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        self.service.ensure_available()?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        // Verify namespace exists and user has access
+        let namespace_id = self
+            .get_namespace_id(namespace, &mut tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.service
+            .check_user_access(&identity, namespace_id, &mut tx)
+            .await?;
+
+        // Verify queue exists
+        let queue_id = self
+            .get_queue_id(namespace, queue, &mut tx)
+            .await?
+            .ok_or_else(|| Error::queue_not_found(queue, namespace))?;
+
+        let res = sqlx::query("DELETE FROM consumers WHERE queue = $1 AND name = $2")
+            .bind(queue_id as i64)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::not_found(format!(
+                "consumer {name} of queue {queue}"
+            )));
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Lists the consumers registered with the queues a user has access to.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_consumers(&self, identity: Identity) -> Result<Vec<Consumer>, Error> {
+        let email = identity.id()?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                c.name,
+                n.name AS namespace,
+                q.name AS queue,
+                c.registered_at,
+                c.last_heartbeat_at,
+                c.last_heartbeat_at > $3 - $2 AS alive
+            FROM consumers c
+            JOIN queues q ON q.id = c.queue
+            JOIN namespaces n ON n.id = q.ns
+            JOIN user_permissions p ON p.namespace = q.ns
+            JOIN users u ON u.id = p.user
+            WHERE u.email = $1
+            ORDER BY n.name, q.name, c.name
+            ",
+        )
+        .bind(email)
+        .bind(CONSUMER_TTL.as_secs() as i64)
+        .bind(self.service.now())
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Finds the queues that have registered consumers, none of which is alive, and messages
+    /// that can be received.
+    ///
+    /// # Arguments
+    /// * `email` - Only consider the queues this user has access to, or every queue if `None`
+    pub async fn unattended_queues(
+        &self,
+        email: Option<&str>,
+    ) -> Result<Vec<UnattendedQueue>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                q.id,
+                n.name AS namespace,
+                q.name AS queue,
+                COUNT(m.id) AS depth
+            FROM queues q
+            JOIN namespaces n ON n.id = q.ns
+            JOIN queue_configurations conf ON conf.queue = q.id
+            JOIN messages m ON m.queue = q.id
+            WHERE EXISTS (SELECT 1 FROM consumers WHERE queue = q.id)
+            AND NOT EXISTS (
+                SELECT 1 FROM consumers
+                WHERE queue = q.id AND last_heartbeat_at > $3 - $2
+            )
+            AND ($1 IS NULL OR q.ns IN (
+                SELECT p.namespace FROM user_permissions p
+                JOIN users u ON u.id = p.user
+                WHERE u.email = $1
+            ))
+            AND m.tries < conf.max_retries
+            AND (m.delivered_at IS NULL OR m.visible_at <= $3)
+            GROUP BY q.id
+            ORDER BY n.name, q.name
+            ",
+        )
+        .bind(email)
+        .bind(CONSUMER_TTL.as_secs() as i64)
+        .bind(self.service.now())
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Gets statistics for all namespaces accessible to the user.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_namespace_statistics(
+        &self,
+        identity: Identity,
+    ) -> Result<Vec<NamespaceStatistics>, Error> {
+        let email = identity.id()?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT
+                ns.*,
+                nu.email as created_by,
+                COUNT(q.id) as queue_count
+            FROM namespaces ns
+            JOIN user_permissions p ON p.namespace = ns.id
+            JOIN users u ON p.user = u.id
+            JOIN users nu ON ns.created_by = nu.id
+            LEFT JOIN queues q ON q.ns = ns.id
+            WHERE u.email = $1
+            GROUP BY ns.id, nu.email
+        ",
+        )
+        .bind(email)
+        .fetch_all(&mut *self.service.db().acquire().await?)
+        .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_email::Email;
+
+    use super::*;
+    use crate::{
+        config::Config, kms::memory::InMemoryKeyManager, types::send_message::SendMessageRequest,
+    };
+
+    #[tokio::test]
+    async fn test_names_are_unique_and_outlive_their_creator() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let lead = || Identity::mock("lead@example.com".to_owned());
+
+        service
+            .create_user(
+                Email::from_str("lead@example.com").unwrap(),
+                "password".to_owned(),
+                None,
+                vec![],
+            )
+            .await
+            .unwrap();
+        service
+            .set_namespace_quota("lead@example.com", Some(1))
+            .await
+            .unwrap();
+        service.create_namespace("a", lead()).await.unwrap();
+        service
+            .create_queue("a", "q", HashMap::new(), HashMap::new(), lead())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            service.create_namespace("a", root()).await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            service
+                .create_queue("a", "q", HashMap::new(), HashMap::new(), lead())
+                .await,
+            Err(Error::InvalidParameter { .. })
+        ));
+
+        // The namespace and its queue stay when the user who created them is deleted, and the
+        // namespace passes to an admin.
+        service
+            .create_user(
+                Email::from_str("member@example.com").unwrap(),
+                "password".to_owned(),
+                None,
+                vec!["a".to_owned()],
+            )
+            .await
+            .unwrap();
+        service
+            .delete_user(Email::from_str("lead@example.com").unwrap())
+            .await
+            .unwrap();
+
+        let member = || Identity::mock("member@example.com".to_owned());
+        let namespaces = service.list_namespaces(member()).await.unwrap();
+        assert_eq!(namespaces.len(), 1);
+        assert_eq!(namespaces[0].created_by, service.config().root_email());
+        let queues = service.list_all_queues(member()).await.unwrap();
+        assert_eq!(queues.len(), 1);
+        assert_eq!(queues[0].name, "q");
+    }
+
+    #[tokio::test]
+    async fn test_namespace_limits() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let create_queue = |name: &'static str| {
+            service.create_queue("t", name, HashMap::new(), HashMap::new(), root())
+        };
+        let send = |queue: u64, body: &str| {
+            service.sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/q".parse().unwrap(),
+                    message_body: body.to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+        };
+
+        service.create_namespace("t", root()).await.unwrap();
+        assert_eq!(
+            service.namespace_limits("t").await.unwrap(),
+            NamespaceLimits::default()
+        );
+        service
+            .set_namespace_limits(
+                "t",
+                NamespaceLimits {
+                    max_queues: Some(2),
+                    max_storage_bytes: Some(10),
+                    max_messages_per_queue: Some(2),
+                },
+            )
+            .await
+            .unwrap();
+
+        create_queue("a").await.unwrap();
+        create_queue("b").await.unwrap();
+        assert!(matches!(
+            create_queue("c").await,
+            Err(Error::OverLimit { limit: 2, .. })
+        ));
+
+        let a = service
+            .get_queue_id("t", "a", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        let b = service
+            .get_queue_id("t", "b", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        send(a, "1").await.unwrap();
+        send(a, "2").await.unwrap();
+        assert!(matches!(
+            send(a, "3").await,
+            Err(Error::OverLimit { limit: 2, .. })
+        ));
+        service
+            .ingest_messages(b, vec!["34".to_owned().into()])
+            .await
+            .unwrap();
+        // Ingestion is all or nothing.
+        assert!(matches!(
+            service
+                .ingest_messages(b, vec!["5".to_owned().into(), "6".to_owned().into()])
+                .await,
+            Err(Error::OverLimit { limit: 2, .. })
+        ));
+
+        // Storage counts across queues: 4 of 10 bytes are used.
+        assert!(matches!(
+            send(b, "1234567").await,
+            Err(Error::OverLimit { limit: 10, .. })
+        ));
+
+        // Deleted messages no longer count.
+        sqlx::query(
+            "DELETE FROM messages WHERE id = (SELECT MIN(id) FROM messages WHERE queue = $1)",
+        )
+        .bind(a as i64)
+        .execute(service.db())
+        .await
+        .unwrap();
+        send(a, "3").await.unwrap();
+        send(b, "123456").await.unwrap();
+        let usage: Vec<(u64, u64, u64, u64)> = sqlx::query_as(
+            "
+            SELECT
+                u.message_count,
+                (SELECT COUNT(*) FROM messages m WHERE m.queue = u.queue),
+                u.message_bytes,
+                (SELECT COALESCE(SUM(LENGTH(m.body)), 0) FROM messages m WHERE m.queue = u.queue)
+            FROM queue_usage u
+            ",
+        )
+        .fetch_all(service.db())
+        .await
+        .unwrap();
+        for (count, actual_count, bytes, actual_bytes) in usage {
+            assert_eq!((count, bytes), (actual_count, actual_bytes));
+        }
+        assert_eq!(
+            Error::OverLimit {
+                resource: "queues".to_owned(),
+                limit: 1
+            }
+            .code(),
+            "OverLimit"
+        );
+
+        service
+            .set_namespace_limits("t", NamespaceLimits::default())
+            .await
+            .unwrap();
+        send(a, "3").await.unwrap();
+        create_queue("c").await.unwrap();
+
+        assert!(matches!(
+            service.namespace_limits("nope").await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_consumer_liveness() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "work", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+        let queue = service
+            .get_queue_id("t", "work", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        service
+            .sqs_send(
+                queue,
+                SendMessageRequest {
+                    queue_url: "http://localhost:8080/t/work".parse().unwrap(),
+                    message_body: "a".to_owned(),
+                    delay_seconds: None,
+                    message_attributes: HashMap::new(),
+                    message_deduplication_id: None,
+                    message_group_id: None,
+                    md5_of_message_body: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // Queues no consumer registered with are not watched.
+        assert!(service.unattended_queues(None).await.unwrap().is_empty());
+
+        assert!(matches!(
+            service
+                .heartbeat_consumer("t", "work", "bad name", root())
+                .await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        let consumer = service
+            .heartbeat_consumer("t", "work", "worker-1", root())
+            .await
+            .unwrap();
+        assert!(consumer.alive);
+
+        let consumers = service.list_consumers(root()).await.unwrap();
+        assert_eq!(consumers.len(), 1);
+        assert_eq!(consumers[0].name, "worker-1");
+        assert!(consumers[0].alive);
+        assert!(service.unattended_queues(None).await.unwrap().is_empty());
+        assert_eq!(service.overview(root()).await.unwrap().live_consumers, 1);
+
+        // Once its only consumer stops sending heartbeats, the queue is unattended.
+        sqlx::query("UPDATE consumers SET last_heartbeat_at = last_heartbeat_at - $1")
+            .bind(CONSUMER_TTL.as_secs() as i64 + 1)
+            .execute(service.db())
+            .await
+            .unwrap();
+        assert!(!service.list_consumers(root()).await.unwrap()[0].alive);
+        let unattended = service.unattended_queues(None).await.unwrap();
+        assert_eq!(unattended.len(), 1);
+        assert_eq!(unattended[0].queue, "work");
+        assert_eq!(unattended[0].depth, 1);
+        let overview = service.overview(root()).await.unwrap();
+        assert_eq!(overview.live_consumers, 0);
+        assert_eq!(overview.unattended_queues, unattended);
+
+        // A heartbeat brings the consumer back.
+        service
+            .heartbeat_consumer("t", "work", "worker-1", root())
+            .await
+            .unwrap();
+        assert!(service.unattended_queues(None).await.unwrap().is_empty());
+
+        service
+            .deregister_consumer("t", "work", "worker-1", root())
+            .await
+            .unwrap();
+        assert!(service.list_consumers(root()).await.unwrap().is_empty());
+        assert!(matches!(
+            service
+                .deregister_consumer("t", "work", "worker-1", root())
+                .await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+}
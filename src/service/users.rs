@@ -0,0 +1,2494 @@
+//! Users and what they authenticate with: accounts, invitations, password resets, sessions, API
+//! keys and client certificates, and their access to namespaces.
+
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_identity::Identity;
+use ipnet::IpNet;
+use itertools::Itertools;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use secrecy::{ExposeSecret, SecretString};
+use serde_email::Email;
+use sqlx::{Acquire, Sqlite};
+use url::Url;
+
+use crate::{
+    api::{
+        auth::{Permission, Role, User},
+        tokens::CreateTokenResponse,
+    },
+    auth::{
+        credential::ApiKey,
+        crypto::{generate_api_key, generate_token, hash_secret, sha256_hex, GeneratedKey},
+        session::SessionInfo,
+    },
+    error::Error,
+    events::Event,
+    key_export::{self, ExportedKey, ImportFailure, ImportResponse, KeyExport},
+    notify::Notification,
+    proxy,
+    tls::{CertificateMatch, ClientCertificateInfo, MAX_CERTIFICATE_NAME_LENGTH},
+    token_usage::TokenInfo,
+    user_import::{ImportRow, ImportStatus, ImportUser, ImportUsersResponse, MAX_IMPORT_USERS},
+};
+
+use super::{
+    Service, INVITATION_TTL, PASSWORD_RESET_LIMIT, PASSWORD_RESET_TTL, PASSWORD_RESET_WINDOW,
+};
+
+/// Message the `users_namespace_handover` trigger aborts a deletion with when the user owns
+/// namespaces and no other admin is left to take them.
+const NO_NAMESPACE_HEIR: &str = "no admin to hand namespaces over to";
+
+/// Operations on users, their credentials and their permissions, see [`Service::users`].
+#[derive(Clone)]
+pub struct UserService<'a> {
+    service: &'a Service,
+}
+
+impl UserService<'_> {
+    pub(super) fn new(service: &Service) -> UserService<'_> {
+        UserService { service }
+    }
+
+    /// Returns the link to a page of the dashboard, below the path the service is served under.
+    fn dashboard_link(&self, page: &str) -> Url {
+        let mut link = self.service.config.host();
+        if let Ok(mut segments) = link.path_segments_mut() {
+            segments.pop_if_empty().push(page);
+        }
+        link
+    }
+
+    /// Deletes a user account and their associated encryption key.
+    ///
+    /// The namespaces the user created are handed to the oldest remaining admin, so the last
+    /// admin can't be deleted while they own namespaces.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user to delete
+    ///
+    /// # Errors
+    /// * `Error::NotFound` - If the user doesn't exist
+    /// * `Error::InvalidRequest` - If the user owns namespaces and no other admin is left
+    pub async fn delete_user(&self, email: Email) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let key_id: String = sqlx::query_scalar(
+            "
+            DELETE FROM users
+            WHERE email = $1
+            RETURNING kms_key_id
+            ",
+        )
+        .bind(email.as_str())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            // Raised by the `users_namespace_handover` trigger.
+            sqlx::Error::Database(e) if e.message() == NO_NAMESPACE_HEIR => {
+                Error::invalid_request(format!(
+                    "user {} owns namespaces and there is no other admin to hand them to",
+                    email.as_str()
+                ))
+            }
+            e => e.into(),
+        })?
+        .ok_or_else(|| Error::not_found(format!("user {}", email.as_str())))?;
+
+        tx.commit().await?;
+
+        // Only deleted once the user is gone, so that a failed commit can't leave a user whose
+        // API keys can't be decrypted. A key left behind is removed by `fsck::spawn`.
+        if let Err(e) = self.service.kms.delete_key(&key_id).await {
+            tracing::warn!(key_id, "Failed to delete the key of a deleted user: {e}");
+        }
+
+        self.service.events.publish(Event::UserDeleted {
+            email: email.to_string(),
+        });
+
+        Ok(())
+    }
+
+    /// Changes the role of a user.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    /// * `role` - New role of the user
+    pub async fn set_user_role(&self, email: &str, role: Role) -> Result<(), Error> {
+        let updated = sqlx::query("UPDATE users SET role = $1 WHERE email = $2")
+            .bind(&role)
+            .bind(email)
+            .execute(self.service.db())
+            .await?
+            .rows_affected();
+
+        if updated == 0 {
+            return Err(Error::not_found(format!("user {email}")));
+        }
+
+        self.service.events.publish(Event::UserRoleChanged {
+            email: email.to_owned(),
+            role,
+        });
+
+        Ok(())
+    }
+
+    /// Deactivates or reactivates a user. Deactivated users keep their permissions, but can't log
+    /// in or use the API, and their sessions and API keys are revoked.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    /// * `active` - Whether the user should be active
+    pub async fn set_user_active(&self, email: &str, active: bool) -> Result<(), Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let updated = sqlx::query(
+            "
+            UPDATE users
+            SET deactivated_at = CASE WHEN $1 THEN NULL ELSE $3 END
+            WHERE email = $2 AND (deactivated_at IS NULL) != $1
+            ",
+        )
+        .bind(active)
+        .bind(email)
+        .bind(self.service.now())
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if updated == 0 {
+            let exists: bool =
+                sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)")
+                    .bind(email)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if !exists {
+                return Err(Error::not_found(format!("user {email}")));
+            }
+            return Ok(());
+        }
+
+        self.revoke_stale_tokens(email, &mut *tx).await?;
+        tx.commit().await?;
+
+        if active {
+            tracing::info!(target: "nervemq::audit", email, "User reactivated");
+        } else {
+            tracing::warn!(target: "nervemq::audit", email, "User deactivated");
+            self.delete_user_sessions(email).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Revokes the API keys a user may no longer use: those for namespaces they lost access to,
+    /// or all of them if they are deactivated. Must be called whenever a user loses access, in the
+    /// same transaction.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    /// * `exec` - Database executor to use
+    ///
+    /// # Returns
+    /// The number of revoked API keys
+    pub async fn revoke_stale_tokens(
+        &self,
+        email: &str,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<u64, Error> {
+        let mut db = exec.acquire().await?;
+
+        let revoked: Vec<(String, String, String)> = sqlx::query_as(
+            "
+            DELETE FROM api_keys
+            WHERE user = (SELECT id FROM users WHERE email = $1)
+            AND (
+                (SELECT deactivated_at FROM users WHERE email = $1) IS NOT NULL
+                OR ns NOT IN (SELECT namespace FROM user_permissions WHERE user = api_keys.user)
+            )
+            RETURNING key_id, name, (SELECT name FROM namespaces WHERE id = api_keys.ns)
+            ",
+        )
+        .bind(email)
+        .fetch_all(&mut *db)
+        .await?;
+
+        for (key_id, name, namespace) in &revoked {
+            tracing::warn!(
+                target: "nervemq::audit",
+                email,
+                namespace,
+                key_id,
+                name,
+                "API key revoked after loss of access"
+            );
+        }
+
+        Ok(revoked.len() as u64)
+    }
+
+    /// Verifies that a user is active and has at least the specified role level.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the user to check
+    /// * `role` - Minimum required role level
+    pub async fn check_user_role(&self, identity: Identity, role: Role) -> Result<(), Error> {
+        let email = identity.id()?;
+        let user: User =
+            sqlx::query_as("SELECT * FROM users WHERE email = $1 AND deactivated_at IS NULL")
+                .bind(email)
+                .fetch_optional(&mut *self.service.db.acquire().await?)
+                .await?
+                .ok_or(Error::Unauthorized)?;
+        if user.role < role {
+            return Err(Error::Unauthorized);
+        }
+
+        Ok(())
+    }
+
+    /// Checks if a user has access to a namespace and returns their permissions.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the user to check
+    /// * `ns` - ID of the namespace
+    /// * `exec` - Database executor to use
+    ///
+    /// # Returns
+    /// Tuple of (user_id, can_delete_ns)
+    pub async fn check_user_access<'a>(
+        &self,
+        identity: &Identity,
+        ns: u64,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<(u64, bool), Error> {
+        let email = identity.id()?;
+        let mut db = exec.acquire().await?;
+
+        let res: Option<Permission> = sqlx::query_as(
+            "
+            SELECT p.* FROM user_permissions p
+            JOIN users u ON p.user = u.id
+            WHERE u.email = $1 AND p.namespace = $2
+        ",
+        )
+        .bind(email)
+        .bind(ns as i64)
+        .fetch_optional(&mut *db)
+        .await?;
+
+        match res {
+            Some(permission) => Ok((permission.user, permission.can_delete_ns)),
+            None if self.service.config.hide_existence() => {
+                let name: String = sqlx::query_scalar("SELECT name FROM namespaces WHERE id = $1")
+                    .bind(ns as i64)
+                    .fetch_one(&mut *db)
+                    .await?;
+
+                Err(Error::namespace_not_found(name))
+            }
+            None => Err(Error::Unauthorized),
+        }
+    }
+
+    /// Creates the error for a request to a namespace the caller may not access. With
+    /// `hide_existence` configured, it is the same error as for a namespace that doesn't exist, so
+    /// that callers can't find out which namespaces exist.
+    ///
+    /// # Arguments
+    /// * `namespace` - Name of the namespace
+    pub fn namespace_access_denied(&self, namespace: &str) -> Error {
+        if self.service.config.hide_existence() {
+            Error::namespace_not_found(namespace)
+        } else {
+            Error::Unauthorized
+        }
+    }
+
+    /// Gets the KMS key ID associated with a user.
+    ///
+    /// # Arguments
+    /// * `user_email` - Email of the user
+    pub async fn get_key_id(&self, user_email: &str) -> Result<String, Error> {
+        let key_id = sqlx::query_scalar(
+            "
+            SELECT kms_key_id FROM users
+            WHERE email = $1
+            ",
+        )
+        .bind(user_email)
+        .fetch_one(self.service.db())
+        .await?;
+
+        Ok(key_id)
+    }
+
+    /// Creates an API token for accessing a namespace.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the token
+    /// * `namespace` - Namespace to grant access to
+    /// * `identity` - Identity of the authenticated user
+    pub async fn create_token(
+        &self,
+        name: String,
+        namespace: String,
+        identity: Identity,
+    ) -> Result<CreateTokenResponse, Error> {
+        let key = tokio::task::spawn_blocking(generate_api_key)
+            .await
+            .map_err(Error::internal)?
+            .map_err(Error::internal)?;
+
+        self.insert_token(name, namespace, identity, key).await
+    }
+
+    /// Installs an API token with a known secret, replacing the user's token of the same name.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the token
+    /// * `namespace` - Namespace to grant access to
+    /// * `email` - Email address of the user the token belongs to
+    /// * `key` - The token
+    ///
+    /// # Returns
+    /// `false` if the user already had the token for the namespace, and nothing changed
+    pub async fn install_token(
+        &self,
+        name: &str,
+        namespace: &str,
+        email: &str,
+        key: ApiKey,
+    ) -> Result<bool, Error> {
+        let ApiKey {
+            short_token,
+            long_token,
+        } = key;
+
+        if let Some((key_id, ns)) = self.find_token(email, name).await? {
+            if key_id == short_token && ns == namespace {
+                return Ok(false);
+            }
+        }
+
+        let long_token = long_token.expose_secret().to_owned();
+        let secret = long_token.clone();
+        let long_token_hash = tokio::task::spawn_blocking(move || hash_secret(secret))
+            .await
+            .map_err(Error::internal)??;
+
+        let key = GeneratedKey {
+            short_token,
+            long_token,
+            long_token_hash,
+        };
+        self.replace_token(name, namespace, email, key).await?;
+
+        Ok(true)
+    }
+
+    /// Finds a user's API token by name.
+    ///
+    /// # Returns
+    /// The token's key ID and namespace, or `None` if the user has no token of that name
+    pub(super) async fn find_token(
+        &self,
+        email: &str,
+        name: &str,
+    ) -> Result<Option<(String, String)>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT k.key_id, ns.name FROM api_keys k
+            JOIN users u ON u.id = k.user
+            JOIN namespaces ns ON ns.id = k.ns
+            WHERE u.email = $1 AND k.name = $2
+            ",
+        )
+        .bind(email)
+        .bind(name)
+        .fetch_optional(self.service.db())
+        .await?)
+    }
+
+    /// Stores an API token, replacing the user's token of the same name.
+    pub(super) async fn replace_token(
+        &self,
+        name: &str,
+        namespace: &str,
+        email: &str,
+        key: GeneratedKey,
+    ) -> Result<CreateTokenResponse, Error> {
+        sqlx::query(
+            "
+            DELETE FROM api_keys
+            WHERE name = $1 AND user = (SELECT id FROM users WHERE email = $2)
+            ",
+        )
+        .bind(name)
+        .bind(email)
+        .execute(self.service.db())
+        .await?;
+
+        self.insert_token(
+            name.to_owned(),
+            namespace.to_owned(),
+            Identity::mock(email.to_owned()),
+            key,
+        )
+        .await
+    }
+
+    /// Stores an API token for accessing a namespace.
+    pub(super) async fn insert_token(
+        &self,
+        name: String,
+        namespace: String,
+        identity: Identity,
+        key: GeneratedKey,
+    ) -> Result<CreateTokenResponse, Error> {
+        let GeneratedKey {
+            short_token,
+            long_token,
+            long_token_hash,
+        } = key;
+
+        // Encrypt before starting the transaction, so that it doesn't keep a read snapshot open
+        // across the key manager's queries. Otherwise the insert can fail with SQLITE_BUSY when
+        // a key manager backed by the same database had to open a new connection meanwhile.
+        let key_id = self.get_key_id(&identity.id()?).await?;
+
+        let encrypted_key = self
+            .service
+            .kms
+            .encrypt(&key_id, long_token.as_bytes().to_vec())
+            .await?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        let namespace_id = self
+            .service
+            .get_namespace_id(&namespace, &mut *tx)
+            .await
+            .map_err(Error::internal)?
+            .ok_or_else(|| Error::namespace_not_found(&namespace))?;
+
+        self.check_user_access(&identity, namespace_id, &mut *tx)
+            .await?;
+
+        sqlx::query(
+            "
+            INSERT INTO api_keys (name, user, key_id, hashed_key, encrypted_key, ns, created_at)
+            VALUES ($1, (SELECT id FROM users WHERE email = $2), $3, $4, $5, $6, $7)
+            ",
+        )
+        .bind(&name)
+        .bind(identity.id()?)
+        .bind(&short_token)
+        .bind(long_token_hash.to_string())
+        .bind(encrypted_key)
+        .bind(namespace_id as i64)
+        .bind(self.service.now())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::internal)?;
+
+        tx.commit().await?;
+
+        // Return the plain API key (should be securely sent/stored by the user).
+        Ok(CreateTokenResponse {
+            name,
+            namespace,
+            access_key: short_token,
+            secret_key: long_token,
+        })
+    }
+
+    /// Lists the API keys of a user, with their usage.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_tokens(&self, identity: Identity) -> Result<Vec<TokenInfo>, Error> {
+        let email = identity.id()?;
+
+        Ok(sqlx::query_as(
+            "
+            SELECT k.name, ns.name AS namespace, k.created_at, k.last_used_at, k.request_count,
+                k.allowed_networks
+            FROM users u
+            JOIN api_keys k ON u.id = k.user
+            JOIN namespaces ns ON k.ns = ns.id
+            WHERE u.email = $1
+            ORDER BY k.name
+            ",
+        )
+        .bind(&email)
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Returns the namespace of a user's API key, or `None` if the user has no key of that name.
+    ///
+    /// # Arguments
+    /// * `owner` - Email address of the user owning the token
+    /// * `name` - Name of the token
+    pub async fn token_namespace(&self, owner: &str, name: &str) -> Result<Option<String>, Error> {
+        Ok(sqlx::query_scalar(
+            "
+            SELECT ns.name FROM api_keys k
+            JOIN users u ON u.id = k.user
+            JOIN namespaces ns ON ns.id = k.ns
+            WHERE u.email = $1 AND k.name = $2
+            ",
+        )
+        .bind(owner)
+        .bind(name)
+        .fetch_optional(self.service.db())
+        .await?)
+    }
+
+    /// Restricts the networks an API key can be used from, or with `None`, lets it be used from
+    /// anywhere.
+    ///
+    /// # Arguments
+    /// * `owner` - Email address of the user owning the token
+    /// * `name` - Name of the token
+    /// * `networks` - Networks requests authenticated by the token must come from
+    pub async fn set_token_allowed_networks(
+        &self,
+        owner: &str,
+        name: &str,
+        networks: Option<Vec<IpNet>>,
+    ) -> Result<(), Error> {
+        if networks.as_ref().is_some_and(Vec::is_empty) {
+            return Err(Error::invalid_parameter(
+                "allowedNetworks: must not be empty, use null to allow any network",
+            ));
+        }
+
+        let updated = sqlx::query(
+            "
+            UPDATE api_keys SET allowed_networks = $1
+            WHERE name = $2 AND user IN (SELECT id FROM users WHERE email = $3)
+            ",
+        )
+        .bind(networks.map(sqlx::types::Json))
+        .bind(name)
+        .bind(owner)
+        .execute(self.service.db())
+        .await?
+        .rows_affected();
+
+        if updated == 0 {
+            return Err(Error::not_found(format!("api key {name}")));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a request authenticated by an API key comes from one of the key's allowed
+    /// networks, if it has any. Rejections are recorded in the audit log.
+    ///
+    /// # Arguments
+    /// * `key_id` - ID (access key) of the API key
+    /// * `ip` - Address of the client, if known
+    ///
+    /// # Errors
+    /// * `Error::Unauthorized` - If the key has allowed networks and the client isn't in them
+    pub async fn check_token_network(&self, key_id: &str, ip: Option<IpAddr>) -> Result<(), Error> {
+        let networks: Option<sqlx::types::Json<Vec<IpNet>>> =
+            sqlx::query_scalar("SELECT allowed_networks FROM api_keys WHERE key_id = $1")
+                .bind(key_id)
+                .fetch_optional(self.service.db())
+                .await?
+                .flatten();
+
+        let Some(networks) = networks else {
+            return Ok(());
+        };
+
+        if ip.is_some_and(|ip| proxy::in_networks(&ip, &networks)) {
+            return Ok(());
+        }
+
+        tracing::warn!(
+            target: "nervemq::audit",
+            key_id,
+            ip = ip.map(|ip| ip.to_string()),
+            "API key used from outside its allowed networks"
+        );
+
+        Err(Error::Unauthorized)
+    }
+
+    /// Exports every API key, with its secret encrypted to a public key, see [`crate::key_export`].
+    ///
+    /// # Arguments
+    /// * `public_key` - Key to encrypt the secrets to
+    pub async fn export_api_keys(&self, public_key: &RsaPublicKey) -> Result<KeyExport, Error> {
+        type Row = (
+            String,
+            String,
+            String,
+            String,
+            Vec<u8>,
+            String,
+            Option<sqlx::types::Json<Vec<IpNet>>>,
+        );
+
+        let rows: Vec<Row> = sqlx::query_as(
+            "
+            SELECT u.email, ns.name, k.name, k.key_id, k.encrypted_key, u.kms_key_id,
+                k.allowed_networks
+            FROM api_keys k
+            JOIN users u ON u.id = k.user
+            JOIN namespaces ns ON ns.id = k.ns
+            ORDER BY u.email, k.name
+            ",
+        )
+        .fetch_all(self.service.db())
+        .await?;
+
+        let mut keys = Vec::with_capacity(rows.len());
+        for (owner, namespace, name, access_key, encrypted_key, kms_key_id, networks) in rows {
+            let secret = self.service.kms.decrypt(&kms_key_id, encrypted_key).await?;
+
+            keys.push(ExportedKey {
+                encrypted_secret: key_export::wrap(public_key, &secret)?,
+                owner,
+                namespace,
+                name,
+                access_key,
+                allowed_networks: networks.map(|networks| networks.0),
+            });
+        }
+
+        tracing::warn!(target: "nervemq::audit", keys = keys.len(), "API keys exported");
+
+        Ok(KeyExport {
+            version: key_export::EXPORT_VERSION,
+            algorithm: key_export::EXPORT_ALGORITHM.to_owned(),
+            exported_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            keys,
+        })
+    }
+
+    /// Imports API keys exported with [`Service::export_api_keys`], replacing the owners' keys
+    /// of the same names. Keys that can't be imported, e.g. because their owner or namespace
+    /// doesn't exist, are skipped and reported.
+    ///
+    /// # Arguments
+    /// * `export` - The exported keys
+    /// * `private_key` - Key the secrets were encrypted to
+    pub async fn import_api_keys(
+        &self,
+        export: KeyExport,
+        private_key: RsaPrivateKey,
+    ) -> Result<ImportResponse, Error> {
+        if export.version != key_export::EXPORT_VERSION
+            || export.algorithm != key_export::EXPORT_ALGORITHM
+        {
+            return Err(Error::invalid_parameter(format!(
+                "export: unsupported version {} or algorithm {}",
+                export.version, export.algorithm
+            )));
+        }
+
+        // Decrypting is slow enough to block the runtime for a noticeable time with many keys.
+        let keys = tokio::task::spawn_blocking(move || {
+            export
+                .keys
+                .into_iter()
+                .map(|key| {
+                    let secret = key_export::unwrap(&private_key, &key.encrypted_secret);
+                    (key, secret)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(Error::internal)?;
+
+        let mut response = ImportResponse::default();
+        for (key, secret) in keys {
+            match self.import_api_key(&key, secret).await {
+                Ok(true) => response.imported += 1,
+                Ok(false) => response.unchanged += 1,
+                Err(e) => response.failed.push(ImportFailure {
+                    owner: key.owner,
+                    name: key.name,
+                    code: e.code().to_owned(),
+                    message: e.to_string(),
+                }),
+            }
+        }
+
+        tracing::warn!(
+            target: "nervemq::audit",
+            imported = response.imported,
+            unchanged = response.unchanged,
+            failed = response.failed.len(),
+            "API keys imported"
+        );
+
+        Ok(response)
+    }
+
+    /// Imports one exported API key.
+    ///
+    /// # Returns
+    /// `false` if the owner already had the key, and nothing changed
+    pub(super) async fn import_api_key(
+        &self,
+        key: &ExportedKey,
+        secret: Result<String, Error>,
+    ) -> Result<bool, Error> {
+        let secret = secret?;
+
+        let owner_exists: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)")
+                .bind(&key.owner)
+                .fetch_one(self.service.db())
+                .await?;
+        if !owner_exists {
+            return Err(Error::UserNotFound {
+                email: key.owner.clone(),
+            });
+        }
+
+        // Access keys are unique, and `install_token` would replace another key of the owner's
+        // only to fail inserting this one.
+        let holder: Option<(String, String)> = sqlx::query_as(
+            "
+            SELECT u.email, k.name FROM api_keys k
+            JOIN users u ON u.id = k.user
+            WHERE k.key_id = $1
+            ",
+        )
+        .bind(&key.access_key)
+        .fetch_optional(self.service.db())
+        .await?;
+        if holder.is_some_and(|holder| holder != (key.owner.clone(), key.name.clone())) {
+            return Err(Error::invalid_parameter(format!(
+                "accessKey: {} is in use by another API key",
+                key.access_key
+            )));
+        }
+
+        let changed = self
+            .install_token(
+                &key.name,
+                &key.namespace,
+                &key.owner,
+                ApiKey::new(key.access_key.clone(), SecretString::from(secret)),
+            )
+            .await?;
+
+        self.set_token_allowed_networks(&key.owner, &key.name, key.allowed_networks.clone())
+            .await?;
+
+        Ok(changed)
+    }
+
+    /// Maps client certificates to a namespace, so that requests on connections with such a
+    /// certificate are authenticated as the user, see [`crate::tls`].
+    ///
+    /// # Arguments
+    /// * `name` - Name of the mapping
+    /// * `namespace` - Namespace to grant access to
+    /// * `certificate` - How the certificates are recognized
+    /// * `identity` - Identity of the authenticated user
+    pub async fn create_client_certificate(
+        &self,
+        name: &str,
+        namespace: &str,
+        certificate: CertificateMatch,
+        identity: Identity,
+    ) -> Result<ClientCertificateInfo, Error> {
+        if name.is_empty() || name.len() > MAX_CERTIFICATE_NAME_LENGTH {
+            return Err(Error::invalid_parameter(format!(
+                "name: must be 1 to {MAX_CERTIFICATE_NAME_LENGTH} characters"
+            )));
+        }
+
+        let (subject_name, fingerprint) = match certificate {
+            CertificateMatch::SubjectName(subject_name) => (Some(subject_name), None),
+            CertificateMatch::Fingerprint(fingerprint) => (None, Some(fingerprint)),
+        };
+
+        let mut tx = self.service.db().begin().await?;
+
+        let namespace_id = self
+            .service
+            .get_namespace_id(namespace, &mut *tx)
+            .await?
+            .ok_or_else(|| Error::namespace_not_found(namespace))?;
+
+        self.check_user_access(&identity, namespace_id, &mut *tx)
+            .await?;
+
+        let created_at: Option<u64> = sqlx::query_scalar(
+            "
+            INSERT INTO client_certificates (user, ns, name, subject_name, fingerprint)
+            VALUES ((SELECT id FROM users WHERE email = $1), $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+            RETURNING created_at
+            ",
+        )
+        .bind(identity.id()?)
+        .bind(namespace_id as i64)
+        .bind(name)
+        .bind(&subject_name)
+        .bind(&fingerprint)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(created_at) = created_at else {
+            return Err(Error::invalid_parameter(format!(
+                "client certificate {name} already exists, or the certificate is already mapped"
+            )));
+        };
+
+        tx.commit().await?;
+
+        Ok(ClientCertificateInfo {
+            name: name.to_owned(),
+            namespace: namespace.to_owned(),
+            subject_name,
+            fingerprint,
+            created_at,
+        })
+    }
+
+    /// Lists the client certificates a user has mapped.
+    ///
+    /// # Arguments
+    /// * `identity` - Identity of the authenticated user
+    pub async fn list_client_certificates(
+        &self,
+        identity: Identity,
+    ) -> Result<Vec<ClientCertificateInfo>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT c.name, ns.name AS namespace, c.subject_name, c.fingerprint, c.created_at
+            FROM client_certificates c
+            JOIN users u ON u.id = c.user
+            JOIN namespaces ns ON ns.id = c.ns
+            WHERE u.email = $1
+            ORDER BY c.name
+            ",
+        )
+        .bind(identity.id()?)
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Returns the namespace of a user's client certificate mapping, or `None` if the user has no
+    /// mapping of that name.
+    ///
+    /// # Arguments
+    /// * `owner` - Email address of the user owning the mapping
+    /// * `name` - Name of the mapping
+    pub async fn client_certificate_namespace(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> Result<Option<String>, Error> {
+        Ok(sqlx::query_scalar(
+            "
+            SELECT ns.name FROM client_certificates c
+            JOIN users u ON u.id = c.user
+            JOIN namespaces ns ON ns.id = c.ns
+            WHERE u.email = $1 AND c.name = $2
+            ",
+        )
+        .bind(owner)
+        .bind(name)
+        .fetch_optional(self.service.db())
+        .await?)
+    }
+
+    /// Removes a client certificate mapping of a user.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the mapping
+    /// * `identity` - Identity of the authenticated user
+    pub async fn delete_client_certificate(
+        &self,
+        name: &str,
+        identity: Identity,
+    ) -> Result<(), Error> {
+        let deleted = sqlx::query(
+            "
+            DELETE FROM client_certificates
+            WHERE name = $1 AND user IN (SELECT id FROM users WHERE email = $2)
+            ",
+        )
+        .bind(name)
+        .bind(identity.id()?)
+        .execute(self.service.db())
+        .await?
+        .rows_affected();
+
+        if deleted == 0 {
+            return Err(Error::not_found(format!("client certificate {name}")));
+        }
+
+        Ok(())
+    }
+
+    /// Records a request authenticated by an API key. Usage is written to the database later,
+    /// by [`Service::flush_token_usage`].
+    ///
+    /// # Arguments
+    /// * `key_id` - ID (access key) of the API key
+    pub fn record_token_use(&self, key_id: &str) {
+        self.service.token_usage.record(key_id);
+    }
+
+    /// Writes the API key usage recorded since the last call to the database.
+    ///
+    /// # Returns
+    /// The number of API keys whose usage was written
+    pub async fn flush_token_usage(&self) -> Result<usize, Error> {
+        let usage = self.service.token_usage.take();
+        if usage.is_empty() {
+            return Ok(0);
+        }
+
+        let write = async {
+            let mut tx = self.service.db().begin().await?;
+            for (key_id, usage) in &usage {
+                sqlx::query(
+                    "
+                    UPDATE api_keys
+                    SET request_count = request_count + $1,
+                        last_used_at = max(coalesce(last_used_at, 0), $2)
+                    WHERE key_id = $3
+                    ",
+                )
+                .bind(usage.requests as i64)
+                .bind(usage.last_used_at as i64)
+                .bind(key_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+
+            Ok::<_, Error>(usage.len())
+        };
+
+        match write.await {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                self.service.token_usage.restore(usage);
+                Err(e)
+            }
+        }
+    }
+
+    /// Creates a new user account.
+    ///
+    /// # Arguments
+    /// * `email` - User's email address
+    /// * `password` - User's password
+    /// * `role` - Optional role to assign
+    /// * `namespaces` - Namespaces to grant access to
+    pub async fn create_user(
+        &self,
+        email: Email,
+        password: String,
+        role: Option<Role>,
+        namespaces: Vec<String>,
+    ) -> Result<(), Error> {
+        let hashed_password = tokio::task::spawn_blocking(move || hash_secret(password))
+            .await
+            .map_err(Error::internal)??;
+
+        let key_id = self.service.kms.create_key().await?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        let role = role.unwrap_or_default();
+        self.insert_user(
+            &email,
+            hashed_password.as_str(),
+            Some(role.clone()),
+            namespaces,
+            key_id,
+            &mut tx,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        self.service.events.publish(Event::UserCreated {
+            email: email.to_string(),
+            role,
+        });
+
+        Ok(())
+    }
+
+    /// Inserts a user and their namespace permissions, returning the new user's ID.
+    ///
+    /// The user's KMS key must be created before `exec` starts writing, since a key manager
+    /// backed by the same database can't write while the transaction is open.
+    pub(super) async fn insert_user(
+        &self,
+        email: &Email,
+        hashed_password: &str,
+        role: Option<Role>,
+        namespaces: Vec<String>,
+        key_id: String,
+        exec: impl Acquire<'_, Database = Sqlite>,
+    ) -> Result<u64, Error> {
+        let mut tx = exec.begin().await?;
+
+        let user_id: u64 = sqlx::query_scalar(
+            "
+            INSERT INTO users (email, hashed_pass, role, kms_key_id)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+        ",
+        )
+        .bind(email.as_str())
+        .bind(hashed_password)
+        .bind(role.unwrap_or(Role::User))
+        .bind(key_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for namespace in namespaces {
+            sqlx::query(
+                "
+                INSERT INTO user_permissions (user, namespace, can_delete_ns)
+                VALUES ($1, (SELECT id FROM namespaces WHERE name = $2), false)
+            ",
+            )
+            .bind(user_id as i64)
+            .bind(namespace)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(user_id)
+    }
+
+    /// Creates a user without a password and emails them an invitation to set one.
+    ///
+    /// The invited user cannot log in until they accept the invitation with
+    /// [`Service::accept_invitation`].
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user to invite
+    /// * `role` - Optional role to assign
+    /// * `namespaces` - Namespaces to grant access to
+    /// * `namespace_quota` - Number of namespaces the user may create themselves, if any
+    pub async fn invite_user(
+        &self,
+        email: Email,
+        role: Option<Role>,
+        namespaces: Vec<String>,
+        namespace_quota: Option<u64>,
+    ) -> Result<(), Error> {
+        let token = generate_token::<24>(rand::thread_rng())?;
+        let key_id = self.service.kms.create_key().await?;
+
+        let mut tx = self.service.db().begin().await?;
+
+        let role = role.unwrap_or_default();
+        let user_id = self
+            .insert_user(
+                &email,
+                "",
+                Some(role.clone()),
+                namespaces,
+                key_id.clone(),
+                &mut tx,
+            )
+            .await?;
+
+        sqlx::query("UPDATE users SET namespace_quota = $1 WHERE id = $2")
+            .bind(namespace_quota.map(|quota| quota as i64))
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "
+            INSERT INTO user_invitations (user, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            ",
+        )
+        .bind(user_id as i64)
+        .bind(sha256_hex(token.as_bytes()))
+        .bind(self.service.now() + INVITATION_TTL.as_secs() as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        // Sent once the invitation is committed, so that the database isn't locked while waiting
+        // on the mail server, as with password resets.
+        let mut link = self.dashboard_link("invite");
+        link.query_pairs_mut().append_pair("token", &token);
+
+        let sent = self
+            .service
+            .notifier
+            .send(Notification {
+                to: email.to_string(),
+                subject: "You have been invited to NerveMQ".to_owned(),
+                body: format!(
+                    "You have been invited to NerveMQ as {email}.\n\n\
+                    Set your password within {days} days to activate your account:\n\n{link}\n",
+                    days = INVITATION_TTL.as_secs() / (24 * 60 * 60),
+                ),
+            })
+            .await;
+
+        if let Err(e) = sent {
+            self.withdraw_invitation(user_id, &key_id).await;
+            return Err(e);
+        }
+
+        self.service.events.publish(Event::UserCreated {
+            email: email.to_string(),
+            role,
+        });
+
+        Ok(())
+    }
+
+    /// Deletes a user whose invitation couldn't be sent, so that inviting them again isn't
+    /// refused because they already exist. A user who has already accepted is left alone.
+    async fn withdraw_invitation(&self, user_id: u64, key_id: &str) {
+        let result = sqlx::query("DELETE FROM users WHERE id = $1 AND hashed_pass = ''")
+            .bind(user_id as i64)
+            .execute(self.service.db())
+            .await;
+
+        match result {
+            Ok(res) if res.rows_affected() > 0 => {
+                if let Err(e) = self.service.kms.delete_key(key_id).await {
+                    tracing::warn!(key_id, "Failed to delete the key of an uninvited user: {e}");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(user_id, "Failed to withdraw an unsent invitation: {e}"),
+        }
+    }
+
+    /// Creates many users at once, see [`crate::user_import`]. Either every user is created, or
+    /// none is.
+    ///
+    /// # Arguments
+    /// * `users` - Users to create
+    ///
+    /// # Returns
+    /// The result of every user, in the order they were given
+    pub async fn import_users(&self, users: Vec<ImportUser>) -> Result<ImportUsersResponse, Error> {
+        if users.is_empty() || users.len() > MAX_IMPORT_USERS {
+            return Err(Error::invalid_parameter(format!(
+                "users: must be 1 to {MAX_IMPORT_USERS} users"
+            )));
+        }
+
+        // Created up front, since a key manager backed by the same database can't write while
+        // the transaction is open.
+        let mut key_ids = Vec::with_capacity(users.len());
+        for _ in &users {
+            key_ids.push(self.service.kms.create_key().await?);
+        }
+
+        let mut tx = self.service.db().begin().await?;
+
+        let mut emails = HashSet::new();
+        let mut results = Vec::with_capacity(users.len());
+        for (user, key_id) in users.iter().zip(key_ids) {
+            let result = self.import_user(user, key_id, &mut emails, &mut tx).await;
+            results.push(result);
+        }
+
+        let failed = results.iter().any(Result::is_err);
+        if failed {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        let rows: Vec<_> = users
+            .iter()
+            .zip(results)
+            .enumerate()
+            .map(|(index, (user, result))| {
+                let (status, error) = match result {
+                    Ok(()) if failed => (ImportStatus::Skipped, None),
+                    Ok(()) => (ImportStatus::Created, None),
+                    Err(e) => (ImportStatus::Failed, Some(e)),
+                };
+                ImportRow {
+                    row: index + 1,
+                    email: user.email.clone(),
+                    status,
+                    code: error.as_ref().map(|e| e.code().to_owned()),
+                    message: error.map(|e| e.to_string()),
+                }
+            })
+            .collect();
+
+        if failed {
+            return Ok(ImportUsersResponse { created: 0, rows });
+        }
+
+        tracing::info!(target: "nervemq::audit", users = users.len(), "Users imported");
+        for user in users {
+            self.service.events.publish(Event::UserCreated {
+                email: user.email,
+                role: user.role,
+            });
+        }
+
+        Ok(ImportUsersResponse {
+            created: rows.len(),
+            rows,
+        })
+    }
+
+    /// Validates and inserts one user of an import.
+    ///
+    /// # Arguments
+    /// * `emails` - Emails of the users imported so far, to detect duplicates
+    pub(super) async fn import_user(
+        &self,
+        user: &ImportUser,
+        key_id: String,
+        emails: &mut HashSet<String>,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<(), Error> {
+        let email = Email::from_str(&user.email)
+            .map_err(|e| Error::invalid_parameter(format!("email: {e}")))?;
+
+        if !emails.insert(email.to_string()) {
+            return Err(Error::invalid_parameter(format!(
+                "email: {email} is imported more than once"
+            )));
+        }
+
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE email = $1)")
+                .bind(email.as_str())
+                .fetch_one(&mut **tx)
+                .await?;
+        if exists {
+            return Err(Error::invalid_parameter(format!(
+                "email: user {email} already exists"
+            )));
+        }
+
+        let namespaces: Vec<String> = user.namespaces.iter().unique().cloned().collect();
+        for namespace in &namespaces {
+            if self
+                .service
+                .get_namespace_id(namespace, &mut **tx)
+                .await?
+                .is_none()
+            {
+                return Err(Error::namespace_not_found(namespace));
+            }
+        }
+
+        self.insert_user(
+            &email,
+            "",
+            Some(user.role.clone()),
+            namespaces,
+            key_id,
+            &mut **tx,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets the password of an invited user and consumes their invitation.
+    ///
+    /// # Arguments
+    /// * `token` - Invitation token from the invitation email
+    /// * `password` - New password for the user
+    ///
+    /// # Errors
+    /// * `Error::Unauthorized` - If the token is unknown, already used, or expired
+    pub async fn accept_invitation(&self, token: &str, password: String) -> Result<(), Error> {
+        // Consumed before the password is hashed, so that invalid tokens cost no hashing.
+        let user_id: u64 = sqlx::query_scalar(
+            "
+            DELETE FROM user_invitations
+            WHERE token_hash = $1 AND expires_at > $2
+            RETURNING user
+            ",
+        )
+        .bind(sha256_hex(token.as_bytes()))
+        .bind(self.service.now())
+        .fetch_optional(self.service.db())
+        .await?
+        .ok_or(Error::Unauthorized)?;
+
+        let hashed_password = tokio::task::spawn_blocking(move || hash_secret(password))
+            .await
+            .map_err(Error::internal)??;
+
+        let mut tx = self.service.db().begin().await?;
+
+        sqlx::query("UPDATE users SET hashed_pass = $1 WHERE id = $2")
+            .bind(hashed_password.to_string())
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        // Any other outstanding invitations for this user are no longer needed.
+        sqlx::query("DELETE FROM user_invitations WHERE user = $1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Lets a user create up to `quota` namespaces themselves, or with `None`, takes that right
+    /// away. Namespaces the user has already created are kept either way.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    /// * `quota` - Number of namespaces the user may create, including ones they already have
+    pub async fn set_namespace_quota(&self, email: &str, quota: Option<u64>) -> Result<(), Error> {
+        let updated = sqlx::query("UPDATE users SET namespace_quota = $1 WHERE email = $2")
+            .bind(quota.map(|quota| quota as i64))
+            .bind(email)
+            .execute(self.service.db())
+            .await?
+            .rows_affected();
+
+        if updated == 0 {
+            return Err(Error::not_found(format!("user {email}")));
+        }
+
+        Ok(())
+    }
+
+    /// Emails a password reset link to a user.
+    ///
+    /// To avoid revealing which email addresses have accounts, this returns at once, and the same
+    /// way, for every address. The reset is issued and emailed in the background, where nothing
+    /// is sent if the user does not exist or has requested too many resets recently, and
+    /// failures are only logged.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    pub async fn request_password_reset(&self, email: Email) -> Result<(), Error> {
+        let service = self.service.clone();
+        tokio::spawn(async move {
+            let result = match service.users().issue_password_reset(&email).await {
+                Ok(Some(notification)) => service.notifier.send(notification).await,
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                tracing::warn!(%email, "Failed to send password reset: {e}");
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Stores a password reset token for a user, unless the user does not exist or has requested
+    /// too many resets recently.
+    ///
+    /// # Returns
+    /// The email with the reset link, to be sent now that the token is committed
+    async fn issue_password_reset(&self, email: &Email) -> Result<Option<Notification>, Error> {
+        let mut tx = self.service.db().begin().await?;
+
+        let Some(user_id): Option<u64> =
+            sqlx::query_scalar("SELECT id FROM users WHERE email = $1")
+                .bind(email.as_str())
+                .fetch_optional(&mut *tx)
+                .await?
+        else {
+            tracing::info!(target: "nervemq::audit", %email, "Password reset requested for unknown user");
+            return Ok(None);
+        };
+
+        let recent: u64 = sqlx::query_scalar(
+            "
+            SELECT COUNT(*) FROM password_resets
+            WHERE user = $1 AND created_at > $2
+            ",
+        )
+        .bind(user_id as i64)
+        .bind(self.service.now() - PASSWORD_RESET_WINDOW.as_secs() as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if recent >= PASSWORD_RESET_LIMIT {
+            tracing::warn!(target: "nervemq::audit", %email, "Password reset rate limit exceeded");
+            return Ok(None);
+        }
+
+        let token = generate_token::<24>(rand::thread_rng())?;
+
+        sqlx::query(
+            "
+            INSERT INTO password_resets (user, token_hash, created_at, expires_at)
+            VALUES ($1, $2, $3, $3 + $4)
+            ",
+        )
+        .bind(user_id as i64)
+        .bind(sha256_hex(token.as_bytes()))
+        .bind(self.service.now())
+        .bind(PASSWORD_RESET_TTL.as_secs() as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(target: "nervemq::audit", %email, "Password reset requested");
+
+        let mut link = self.dashboard_link("reset-password");
+        link.query_pairs_mut().append_pair("token", &token);
+
+        Ok(Some(Notification {
+            to: email.to_string(),
+            subject: "Reset your NerveMQ password".to_owned(),
+            body: format!(
+                "A password reset was requested for your NerveMQ account ({email}).\n\n\
+                Use this link within {minutes} minutes to choose a new password:\n\n{link}\n\n\
+                If you did not request a reset, you can ignore this email.\n",
+                minutes = PASSWORD_RESET_TTL.as_secs() / 60,
+            ),
+        }))
+    }
+
+    /// Sets a user's password using a password reset token, and revokes all of their sessions.
+    ///
+    /// The token is consumed, along with any other outstanding reset tokens for the user.
+    ///
+    /// # Arguments
+    /// * `token` - Reset token from the password reset email
+    /// * `password` - New password for the user
+    ///
+    /// # Errors
+    /// * `Error::Unauthorized` - If the token is unknown, already used, or expired
+    pub async fn reset_password(&self, token: &str, password: String) -> Result<(), Error> {
+        // Consumed before the password is hashed, so that invalid tokens cost no hashing.
+        let Some(user_id): Option<u64> = sqlx::query_scalar(
+            "
+            DELETE FROM password_resets
+            WHERE token_hash = $1 AND expires_at > $2
+            RETURNING user
+            ",
+        )
+        .bind(sha256_hex(token.as_bytes()))
+        .bind(self.service.now())
+        .fetch_optional(self.service.db())
+        .await?
+        else {
+            tracing::warn!(target: "nervemq::audit", "Password reset attempted with invalid token");
+            return Err(Error::Unauthorized);
+        };
+
+        let hashed_password = tokio::task::spawn_blocking(move || hash_secret(password))
+            .await
+            .map_err(Error::internal)??;
+
+        let mut tx = self.service.db().begin().await?;
+
+        let email: String =
+            sqlx::query_scalar("UPDATE users SET hashed_pass = $1 WHERE id = $2 RETURNING email")
+                .bind(hashed_password.to_string())
+                .bind(user_id as i64)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        sqlx::query("DELETE FROM password_resets WHERE user = $1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(target: "nervemq::audit", email, "Password reset completed");
+
+        // Whoever knew the old password must not stay logged in.
+        self.delete_user_sessions(&email).await?;
+
+        Ok(())
+    }
+
+    /// Lists a user's active sessions, most recently used first.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    pub async fn list_sessions(&self, email: &str) -> Result<Vec<SessionInfo>, Error> {
+        Ok(sqlx::query_as(
+            "
+            SELECT id, created_at, last_seen, last_seen + ttl AS expires_at, user_agent, ip
+            FROM sessions
+            WHERE email = $1 AND last_seen + ttl > $2
+            ORDER BY last_seen DESC
+            ",
+        )
+        .bind(email)
+        .bind(self.service.now())
+        .fetch_all(self.service.db())
+        .await?)
+    }
+
+    /// Revokes one of a user's sessions.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user the session must belong to
+    /// * `id` - ID of the session
+    pub async fn delete_session(&self, email: &str, id: u64) -> Result<(), Error> {
+        let res = sqlx::query("DELETE FROM sessions WHERE id = $1 AND email = $2")
+            .bind(id as i64)
+            .bind(email)
+            .execute(self.service.db())
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::not_found(format!("session {id}")));
+        }
+
+        tracing::info!(target: "nervemq::audit", email, session = id, "Session revoked");
+
+        Ok(())
+    }
+
+    /// Revokes all of a user's sessions, logging them out everywhere.
+    ///
+    /// # Arguments
+    /// * `email` - Email address of the user
+    ///
+    /// # Returns
+    /// The number of sessions that were revoked
+    pub async fn delete_user_sessions(&self, email: &str) -> Result<u64, Error> {
+        let res = sqlx::query("DELETE FROM sessions WHERE email = $1")
+            .bind(email)
+            .execute(self.service.db())
+            .await?;
+
+        tracing::info!(
+            target: "nervemq::audit",
+            email,
+            count = res.rows_affected(),
+            "All sessions revoked"
+        );
+
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+    use argon2::password_hash::PasswordHashString;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        auth::crypto::verify_secret,
+        clock::ManualClock,
+        config::{Config, MEMORY_DB_PATH},
+        kms::memory::InMemoryKeyManager,
+        notify::Notifier,
+    };
+
+    /// Notifier that hands every notification to the test.
+    struct Mailbox(mpsc::UnboundedSender<Notification>);
+
+    impl Notifier for Mailbox {
+        fn send(
+            &self,
+            notification: Notification,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + '_>> {
+            self.0.send(notification).unwrap();
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// A notifier whose mail server is down.
+    struct Unreachable;
+
+    impl Notifier for Unreachable {
+        fn send(
+            &self,
+            _notification: Notification,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + '_>> {
+            Box::pin(async { Err(Error::internal(eyre::eyre!("connection refused"))) })
+        }
+    }
+
+    /// Waits for the next notification and returns the link in it.
+    async fn next_link(mailbox: &mut mpsc::UnboundedReceiver<Notification>) -> Url {
+        let notification = tokio::time::timeout(Duration::from_secs(5), mailbox.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let link = notification
+            .body
+            .split_whitespace()
+            .find(|word| word.starts_with("http"))
+            .unwrap();
+        Url::parse(link).unwrap()
+    }
+
+    /// Waits for the next notification and returns the token of the link in it.
+    async fn next_token(mailbox: &mut mpsc::UnboundedReceiver<Notification>) -> String {
+        let link = next_link(mailbox).await;
+        let (_, token) = link.query_pairs().find(|(k, _)| k == "token").unwrap();
+        token.into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_import_users() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("orders", root()).await.unwrap();
+
+        let user = |email: &str, role: Role, namespaces: &[&str]| ImportUser {
+            email: email.to_owned(),
+            role,
+            namespaces: namespaces.iter().map(|ns| ns.to_string()).collect(),
+        };
+        let statuses = |response: &ImportUsersResponse| {
+            response
+                .rows
+                .iter()
+                .map(|row| row.status)
+                .collect::<Vec<_>>()
+        };
+
+        // Nothing is created if any row is invalid.
+        let response = service
+            .import_users(vec![
+                user("alice@example.com", Role::Admin, &["orders"]),
+                user("bob@example.com", Role::User, &["missing"]),
+                user("alice@example.com", Role::User, &[]),
+                user("not an email", Role::User, &[]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(response.created, 0);
+        assert_eq!(
+            statuses(&response),
+            [
+                ImportStatus::Skipped,
+                ImportStatus::Failed,
+                ImportStatus::Failed,
+                ImportStatus::Failed
+            ]
+        );
+        assert_eq!(response.rows[1].code.as_deref(), Some("NotFound"));
+        assert_eq!(response.rows[3].row, 4);
+        let users: u64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        assert_eq!(users, 1);
+
+        let response = service
+            .import_users(vec![
+                user("alice@example.com", Role::Admin, &["orders", "orders"]),
+                user("bob@example.com", Role::User, &[]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(response.created, 2);
+        assert_eq!(
+            statuses(&response),
+            [ImportStatus::Created, ImportStatus::Created]
+        );
+        let role: Role = sqlx::query_scalar("SELECT role FROM users WHERE email = $1")
+            .bind("alice@example.com")
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        assert_eq!(role, Role::Admin);
+        let alice = Identity::mock("alice@example.com".to_owned());
+        service.list_queues(Some("orders"), alice).await.unwrap();
+
+        let response = service
+            .import_users(vec![user("bob@example.com", Role::User, &[])])
+            .await
+            .unwrap();
+        assert_eq!(statuses(&response), [ImportStatus::Failed]);
+
+        assert!(matches!(
+            service.import_users(vec![]).await,
+            Err(Error::InvalidParameter { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_namespace_quota() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let lead = || Identity::mock("lead@example.com".to_owned());
+
+        service
+            .create_user(
+                Email::from_str("lead@example.com").unwrap(),
+                "password".to_owned(),
+                None,
+                vec![],
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            service.create_namespace("a", lead()).await,
+            Err(Error::Unauthorized)
+        ));
+
+        service
+            .set_namespace_quota("lead@example.com", Some(1))
+            .await
+            .unwrap();
+        service.create_namespace("a", lead()).await.unwrap();
+        assert!(matches!(
+            service.create_namespace("b", lead()).await,
+            Err(Error::QuotaExceeded { limit: 1, .. })
+        ));
+
+        // The creator can manage the namespace, and it is visible to them.
+        let namespaces = service.list_namespaces(lead()).await.unwrap();
+        assert_eq!(namespaces.len(), 1);
+        service.delete_namespace("a", lead()).await.unwrap();
+        service.create_namespace("b", lead()).await.unwrap();
+
+        assert!(matches!(
+            service
+                .set_namespace_quota("nobody@example.com", None)
+                .await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_namespace_handover() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = service.config().root_email().to_owned();
+        let owner = || async {
+            sqlx::query_scalar::<_, String>(
+                "
+                SELECT u.email FROM namespaces ns
+                JOIN users u ON u.id = ns.created_by
+                WHERE ns.name = 'a'
+                ",
+            )
+            .fetch_one(service.db())
+            .await
+            .unwrap()
+        };
+
+        service
+            .create_namespace("a", Identity::mock(root.clone()))
+            .await
+            .unwrap();
+
+        // The last admin can't leave their namespaces to nobody.
+        let result = service.delete_user(Email::from_str(&root).unwrap()).await;
+        assert!(
+            matches!(result, Err(Error::InvalidRequest { .. })),
+            "{result:?}"
+        );
+        assert_eq!(owner().await, root);
+
+        service
+            .create_user(
+                Email::from_str("second@example.com").unwrap(),
+                "password".to_owned(),
+                Some(Role::Admin),
+                vec![],
+            )
+            .await
+            .unwrap();
+        service
+            .delete_user(Email::from_str(&root).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(owner().await, "second@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_token_usage() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        let token = service
+            .create_token("ci".to_owned(), "t".to_owned(), root())
+            .await
+            .unwrap();
+
+        let listed = |tokens: Vec<TokenInfo>| tokens.into_iter().find(|t| t.name == "ci").unwrap();
+
+        let info = listed(service.list_tokens(root()).await.unwrap());
+        assert!(info.created_at.is_some());
+        assert_eq!(info.last_used_at, None);
+        assert_eq!(info.request_count, 0);
+
+        for _ in 0..3 {
+            service.record_token_use(&token.access_key);
+        }
+        // Usage isn't visible until it is written.
+        assert_eq!(
+            listed(service.list_tokens(root()).await.unwrap()).request_count,
+            0
+        );
+
+        assert_eq!(service.flush_token_usage().await.unwrap(), 1);
+        assert_eq!(service.flush_token_usage().await.unwrap(), 0);
+
+        let info = listed(service.list_tokens(root()).await.unwrap());
+        assert!(info.last_used_at.is_some());
+        assert_eq!(info.request_count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_stale_tokens() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let user = || Identity::mock("user@example.com".to_owned());
+
+        for namespace in ["orders", "billing"] {
+            service.create_namespace(namespace, root()).await.unwrap();
+        }
+        service
+            .create_user(
+                Email::from_str("user@example.com").unwrap(),
+                "password".to_owned(),
+                None,
+                vec!["orders".to_owned(), "billing".to_owned()],
+            )
+            .await
+            .unwrap();
+        for namespace in ["orders", "billing"] {
+            service
+                .create_token(namespace.to_owned(), namespace.to_owned(), user())
+                .await
+                .unwrap();
+        }
+        service
+            .create_token("billing".to_owned(), "billing".to_owned(), root())
+            .await
+            .unwrap();
+
+        // Nothing is revoked while the user can still access everything.
+        assert_eq!(
+            service
+                .revoke_stale_tokens("user@example.com", service.db())
+                .await
+                .unwrap(),
+            0
+        );
+
+        sqlx::query(
+            "
+            DELETE FROM user_permissions
+            WHERE user = (SELECT id FROM users WHERE email = 'user@example.com')
+            AND namespace = (SELECT id FROM namespaces WHERE name = 'billing')
+            ",
+        )
+        .execute(service.db())
+        .await
+        .unwrap();
+        assert_eq!(
+            service
+                .revoke_stale_tokens("user@example.com", service.db())
+                .await
+                .unwrap(),
+            1
+        );
+        let tokens = service.list_tokens(user()).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].namespace, "orders");
+
+        service
+            .set_user_active("user@example.com", false)
+            .await
+            .unwrap();
+        assert!(service.list_tokens(user()).await.unwrap().is_empty());
+
+        // Other users' keys aren't affected.
+        assert_eq!(service.list_tokens(root()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_token_allowed_networks() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let email = service.config().root_email().to_owned();
+
+        service.create_namespace("t", root()).await.unwrap();
+        let token = service
+            .create_token("ci".to_owned(), "t".to_owned(), root())
+            .await
+            .unwrap();
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+        // Keys without a list can be used from anywhere.
+        service
+            .check_token_network(&token.access_key, None)
+            .await
+            .unwrap();
+
+        service
+            .set_token_allowed_networks(
+                &email,
+                "ci",
+                Some(vec![
+                    "10.0.0.0/8".parse().unwrap(),
+                    "::1/128".parse().unwrap(),
+                ]),
+            )
+            .await
+            .unwrap();
+
+        for allowed in ["10.1.2.3", "::ffff:10.1.2.3", "::1"] {
+            service
+                .check_token_network(&token.access_key, ip(allowed))
+                .await
+                .unwrap();
+        }
+        for denied in [ip("192.168.0.1"), None] {
+            assert!(matches!(
+                service.check_token_network(&token.access_key, denied).await,
+                Err(Error::Unauthorized)
+            ));
+        }
+
+        assert!(matches!(
+            service
+                .set_token_allowed_networks(&email, "ci", Some(vec![]))
+                .await,
+            Err(Error::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            service
+                .set_token_allowed_networks("nobody@example.com", "ci", None)
+                .await,
+            Err(Error::NotFound { .. })
+        ));
+
+        service
+            .set_token_allowed_networks(&email, "ci", None)
+            .await
+            .unwrap();
+        service
+            .check_token_network(&token.access_key, ip("192.168.0.1"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hide_existence() {
+        for hide_existence in [false, true] {
+            let config: Config = serde_json::from_value(serde_json::json!({
+                "db_path": MEMORY_DB_PATH,
+                "integrity_check": "off",
+                "hide_existence": hide_existence,
+            }))
+            .unwrap();
+            let service = Service::connect_with()
+                .config(config)
+                .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+                .call()
+                .await
+                .unwrap();
+            let root = || Identity::mock(service.config().root_email().to_owned());
+            let user = || Identity::mock("user@example.com".to_owned());
+
+            service
+                .create_user(
+                    Email::from_str("user@example.com").unwrap(),
+                    "password".to_owned(),
+                    None,
+                    vec![],
+                )
+                .await
+                .unwrap();
+            service.create_namespace("t", root()).await.unwrap();
+            service
+                .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+
+            let missing = service.list_queues(Some("missing"), user()).await;
+            assert!(matches!(missing, Err(Error::NotFound { .. })));
+
+            let denied = [
+                service.list_queues(Some("t"), user()).await.map(|_| ()),
+                service.delete_queue("t", "q", user()).await,
+                service.delete_namespace("t", user()).await,
+                Err(service.namespace_access_denied("t")),
+            ];
+            for result in denied {
+                match result {
+                    Err(Error::NotFound { resource }) if hide_existence => {
+                        assert_eq!(resource, "namespace t");
+                    }
+                    Err(Error::Unauthorized) if !hide_existence => {}
+                    other => panic!("unexpected result {other:?}"),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_key_export() {
+        use crate::auth::protocols::nervemq::authenticate_api_key;
+
+        let connect = || async {
+            Service::connect_with()
+                .config(Config::in_memory())
+                .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+                .call()
+                .await
+                .unwrap()
+        };
+        let source = connect().await;
+        let target = connect().await;
+        let email = source.config().root_email().to_owned();
+        let root = || Identity::mock(email.clone());
+
+        source.create_namespace("t", root()).await.unwrap();
+        source.create_namespace("other", root()).await.unwrap();
+        target.create_namespace("t", root()).await.unwrap();
+
+        let token = source
+            .create_token("ci".to_owned(), "t".to_owned(), root())
+            .await
+            .unwrap();
+        source
+            .create_token("other".to_owned(), "other".to_owned(), root())
+            .await
+            .unwrap();
+        source
+            .set_token_allowed_networks(&email, "ci", Some(vec!["10.0.0.0/8".parse().unwrap()]))
+            .await
+            .unwrap();
+
+        let public_key =
+            key_export::parse_public_key(include_str!("../../tests/fixtures/export.pub.pem"))
+                .unwrap();
+        let private_key = || {
+            key_export::parse_private_key(include_str!("../../tests/fixtures/export.pem")).unwrap()
+        };
+
+        let export = source.export_api_keys(&public_key).await.unwrap();
+        assert_eq!(export.keys.len(), 2);
+        assert!(!serde_json::to_string(&export)
+            .unwrap()
+            .contains(&token.secret_key));
+
+        // The namespace of the second key doesn't exist on the target.
+        let response = target
+            .import_api_keys(export.clone(), private_key())
+            .await
+            .unwrap();
+        assert_eq!(response.imported, 1);
+        assert_eq!(response.unchanged, 0);
+        assert_eq!(response.failed.len(), 1);
+        assert_eq!(response.failed[0].name, "other");
+        assert_eq!(response.failed[0].code, "NotFound");
+
+        let (user, namespace) = authenticate_api_key(
+            target.db(),
+            ApiKey::new(
+                token.access_key.clone(),
+                SecretString::from(token.secret_key.clone()),
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.email, email);
+        assert_eq!(namespace.0, "t");
+        assert!(matches!(
+            target.check_token_network(&token.access_key, None).await,
+            Err(Error::Unauthorized)
+        ));
+
+        target.create_namespace("other", root()).await.unwrap();
+        let response = target.import_api_keys(export, private_key()).await.unwrap();
+        assert_eq!(response.imported, 1);
+        assert_eq!(response.unchanged, 1);
+        assert!(response.failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_client_certificates() {
+        use crate::{
+            auth::protocols::certificate::authenticate_certificate, tls::ClientCertificate,
+        };
+
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("orders", root()).await.unwrap();
+        service.create_namespace("billing", root()).await.unwrap();
+
+        let der =
+            rustls_pemfile::certs(&mut include_str!("../../tests/fixtures/client.crt").as_bytes())
+                .unwrap()
+                .remove(0);
+        let cert = ClientCertificate(der);
+
+        assert!(matches!(
+            authenticate_certificate(service.db(), &cert).await,
+            Err(Error::IdentityNotFound { .. })
+        ));
+
+        let by_name = CertificateMatch::SubjectName("orders.internal".to_owned());
+        service
+            .create_client_certificate("orders", "orders", by_name.clone(), root())
+            .await
+            .unwrap();
+        let (user, namespace) = authenticate_certificate(service.db(), &cert).await.unwrap();
+        assert_eq!(user.email, service.config().root_email());
+        assert_eq!(namespace.0, "orders");
+
+        // A subject name can only be mapped once.
+        assert!(matches!(
+            service
+                .create_client_certificate("again", "billing", by_name, root())
+                .await,
+            Err(Error::InvalidParameter { .. })
+        ));
+
+        // Fingerprints take precedence over subject names.
+        service
+            .create_client_certificate(
+                "pinned",
+                "billing",
+                CertificateMatch::Fingerprint(cert.fingerprint()),
+                root(),
+            )
+            .await
+            .unwrap();
+        let (_, namespace) = authenticate_certificate(service.db(), &cert).await.unwrap();
+        assert_eq!(namespace.0, "billing");
+
+        let names = service
+            .list_client_certificates(root())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|cert| cert.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["orders", "pinned"]);
+
+        service
+            .delete_client_certificate("pinned", root())
+            .await
+            .unwrap();
+        service
+            .delete_client_certificate("orders", root())
+            .await
+            .unwrap();
+        assert!(matches!(
+            service.delete_client_certificate("orders", root()).await,
+            Err(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            authenticate_certificate(service.db(), &cert).await,
+            Err(Error::IdentityNotFound { .. })
+        ));
+    }
+
+    /// Returns whether a user's password is `password`.
+    async fn has_password(service: &Service, email: &str, password: &str) -> bool {
+        let hash: String = sqlx::query_scalar("SELECT hashed_pass FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        // Invited users have no password until they accept.
+        let Ok(hash) = PasswordHashString::new(&hash) else {
+            return false;
+        };
+        verify_secret(password.to_owned().into(), hash).is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_password_reset() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let (mailbox, mut inbox) = mpsc::unbounded_channel();
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .notifier(Arc::new(Mailbox(mailbox)))
+            .call()
+            .await
+            .unwrap();
+        let email = || Email::from_str("user@example.com").unwrap();
+        service
+            .create_user(email(), "old password".to_owned(), None, vec![])
+            .await
+            .unwrap();
+        sqlx::query(
+            "
+            INSERT INTO sessions (session_key, ttl, email, created_at, last_seen)
+            VALUES ('key', 3600, $1, $2, $2)
+            ",
+        )
+        .bind(email().as_str())
+        .bind(service.now())
+        .execute(service.db())
+        .await
+        .unwrap();
+        assert_eq!(
+            service.list_sessions(email().as_str()).await.unwrap().len(),
+            1
+        );
+        let password_is = |password| has_password(&service, "user@example.com", password);
+
+        // Unknown emails get the same answer, but nothing is issued for them.
+        let unknown = Email::from_str("nobody@example.com").unwrap();
+        service
+            .request_password_reset(unknown.clone())
+            .await
+            .unwrap();
+        let issued = service
+            .users()
+            .issue_password_reset(&unknown)
+            .await
+            .unwrap();
+        assert!(issued.is_none());
+
+        let mut tokens = Vec::new();
+        for _ in 0..PASSWORD_RESET_LIMIT {
+            service.request_password_reset(email()).await.unwrap();
+            tokens.push(next_token(&mut inbox).await);
+        }
+        // Further requests are ignored until the window has passed.
+        let issued = service
+            .users()
+            .issue_password_reset(&email())
+            .await
+            .unwrap();
+        assert!(issued.is_none());
+
+        let result = service
+            .reset_password("junk", "new password".to_owned())
+            .await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+        assert!(password_is("old password").await);
+
+        service
+            .reset_password(&tokens[0], "new password".to_owned())
+            .await
+            .unwrap();
+        assert!(password_is("new password").await);
+        assert!(service
+            .list_sessions(email().as_str())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Tokens are single use, and a reset consumes the user's other tokens too.
+        for token in &tokens {
+            let result = service
+                .reset_password(token, "other password".to_owned())
+                .await;
+            assert!(matches!(result, Err(Error::Unauthorized)));
+        }
+        assert!(password_is("new password").await);
+
+        clock.advance(PASSWORD_RESET_WINDOW);
+        service.request_password_reset(email()).await.unwrap();
+        let token = next_token(&mut inbox).await;
+        clock.advance(PASSWORD_RESET_TTL + Duration::from_secs(1));
+        let result = service
+            .reset_password(&token, "late password".to_owned())
+            .await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+        assert!(password_is("new password").await);
+    }
+
+    #[tokio::test]
+    async fn test_invitations() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+        let (mailbox, mut inbox) = mpsc::unbounded_channel();
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .clock(Arc::new(clock.clone()))
+            .notifier(Arc::new(Mailbox(mailbox)))
+            .call()
+            .await
+            .unwrap();
+        let invite = |email: &'static str| {
+            let service = service.clone();
+            async move {
+                service
+                    .invite_user(Email::from_str(email).unwrap(), None, vec![], None)
+                    .await
+                    .unwrap();
+            }
+        };
+
+        invite("user@example.com").await;
+        let token = next_token(&mut inbox).await;
+        assert!(!has_password(&service, "user@example.com", "").await);
+
+        let result = service
+            .accept_invitation("junk", "password".to_owned())
+            .await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+
+        service
+            .accept_invitation(&token, "password".to_owned())
+            .await
+            .unwrap();
+        assert!(has_password(&service, "user@example.com", "password").await);
+
+        // Invitations are single use.
+        let result = service.accept_invitation(&token, "other".to_owned()).await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+        assert!(has_password(&service, "user@example.com", "password").await);
+
+        // Deleting a user withdraws their invitation.
+        invite("deleted@example.com").await;
+        let token = next_token(&mut inbox).await;
+        service
+            .delete_user(Email::from_str("deleted@example.com").unwrap())
+            .await
+            .unwrap();
+        let result = service
+            .accept_invitation(&token, "password".to_owned())
+            .await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+
+        invite("late@example.com").await;
+        let token = next_token(&mut inbox).await;
+        clock.advance(INVITATION_TTL + Duration::from_secs(1));
+        let result = service
+            .accept_invitation(&token, "password".to_owned())
+            .await;
+        assert!(matches!(result, Err(Error::Unauthorized)));
+        assert!(!has_password(&service, "late@example.com", "password").await);
+    }
+
+    #[tokio::test]
+    async fn test_unsent_invitation_is_withdrawn() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .notifier(Arc::new(Unreachable))
+            .call()
+            .await
+            .unwrap();
+        let email = || Email::from_str("user@example.com").unwrap();
+
+        let result = service.invite_user(email(), None, vec![], None).await;
+        assert!(matches!(result, Err(Error::InternalServerError { .. })));
+
+        let users: u64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE email = $1")
+            .bind(email().as_str())
+            .fetch_one(service.db())
+            .await
+            .unwrap();
+        assert_eq!(users, 0);
+
+        // Trying again gets as far as sending, rather than finding the user already exists.
+        let result = service.invite_user(email(), None, vec![], None).await;
+        assert!(matches!(result, Err(Error::InternalServerError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_links_keep_host_path() {
+        for host in [
+            "https://example.com/nervemq/",
+            "https://example.com/nervemq",
+        ] {
+            let config: Config = serde_json::from_value(serde_json::json!({
+                "db_path": MEMORY_DB_PATH,
+                "integrity_check": "off",
+                "host": host,
+            }))
+            .unwrap();
+            let (mailbox, mut inbox) = mpsc::unbounded_channel();
+            let service = Service::connect_with()
+                .config(config)
+                .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+                .notifier(Arc::new(Mailbox(mailbox)))
+                .call()
+                .await
+                .unwrap();
+            let email = || Email::from_str("user@example.com").unwrap();
+
+            service
+                .invite_user(email(), None, vec![], None)
+                .await
+                .unwrap();
+            let link = next_link(&mut inbox).await;
+            assert_eq!(link.path(), "/nervemq/invite", "{host}");
+
+            service.request_password_reset(email()).await.unwrap();
+            let link = next_link(&mut inbox).await;
+            assert_eq!(link.path(), "/nervemq/reset-password", "{host}");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Validation shared by the SQS batch operations.
+//!
+//! SQS rejects a whole batch request, before processing any entries, if it is empty, has more
+//! than [`MAX_BATCH_ENTRIES`] entries, or reuses an entry id. Entry ids are client-chosen and
+//! only used to correlate results with requests, so they must be unique within a batch.
+
+use std::collections::HashSet;
+
+use crate::error::Error;
+
+/// The maximum number of entries allowed in a single batch request.
+pub const MAX_BATCH_ENTRIES: usize = 10;
+
+/// Validates the entry ids of a batch request.
+///
+/// # Errors
+/// * `Error::EmptyBatchRequest` - If there are no entries
+/// * `Error::TooManyEntriesInBatchRequest` - If there are more than [`MAX_BATCH_ENTRIES`] entries
+/// * `Error::BatchEntryIdsNotDistinct` - If any entry id is used more than once
+pub fn validate_entry_ids<'a>(ids: impl ExactSizeIterator<Item = &'a str>) -> Result<(), Error> {
+    let count = ids.len();
+
+    if count == 0 {
+        return Err(Error::EmptyBatchRequest);
+    }
+
+    if count > MAX_BATCH_ENTRIES {
+        return Err(Error::TooManyEntriesInBatchRequest {
+            count,
+            max: MAX_BATCH_ENTRIES,
+        });
+    }
+
+    let mut seen = HashSet::with_capacity(count);
+    for id in ids {
+        if !seen.insert(id) {
+            return Err(Error::BatchEntryIdsNotDistinct { id: id.to_owned() });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_batch() {
+        let ids = ["a", "b", "c"];
+        assert!(validate_entry_ids(ids.into_iter()).is_ok());
+
+        let ids = (0..MAX_BATCH_ENTRIES)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        assert!(validate_entry_ids(ids.iter().map(String::as_str)).is_ok());
+    }
+
+    #[test]
+    fn test_empty_batch() {
+        let ids: [&str; 0] = [];
+        assert!(matches!(
+            validate_entry_ids(ids.into_iter()),
+            Err(Error::EmptyBatchRequest)
+        ));
+    }
+
+    #[test]
+    fn test_too_many_entries() {
+        let ids = (0..=MAX_BATCH_ENTRIES)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            validate_entry_ids(ids.iter().map(String::as_str)),
+            Err(Error::TooManyEntriesInBatchRequest { count: 11, max: 10 })
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let ids = ["a", "b", "a"];
+        assert!(matches!(
+            validate_entry_ids(ids.into_iter()),
+            Err(Error::BatchEntryIdsNotDistinct { id }) if id == "a"
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Reading the JSON body of SQS requests.
+//!
+//! Bodies are read chunk by chunk as they arrive, whether the client sends them with a
+//! `Content-Length` or chunked, and reading stops as soon as a body grows past
+//! [`Config::max_request_body`](crate::config::Config::max_request_body), so that no request
+//! makes the server buffer more than that. A body whose `Content-Length` announces more is
+//! rejected before any of it is read. Both fail with `PayloadTooLarge`.
+//!
+//! A body that isn't JSON fails with `InvalidRequest`, saying where it stops being JSON. A
+//! request that doesn't have the shape its method expects fails with `MissingParameter` if a
+//! field is missing, and with `InvalidRequest` otherwise.
+
+use actix_web::{
+    error::PayloadError,
+    http::header::{self, HeaderMap},
+};
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt as _};
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+
+use crate::error::Error;
+
+/// Reads a request body of at most `limit` bytes.
+///
+/// # Errors
+/// * `Error::PayloadTooLarge` - If the body is, or is announced to be, larger than `limit`
+pub async fn read(
+    headers: &HeaderMap,
+    payload: impl Stream<Item = Result<Bytes, PayloadError>>,
+    limit: usize,
+) -> Result<Bytes, Error> {
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > limit) {
+        return Err(Error::PayloadTooLarge);
+    }
+
+    let mut payload = std::pin::pin!(payload);
+    let mut body = BytesMut::with_capacity(content_length.unwrap_or_default());
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| match e {
+            PayloadError::Overflow => Error::PayloadTooLarge,
+            e => Error::invalid_request(format!("unreadable body: {e}")),
+        })?;
+        if body.len() + chunk.len() > limit {
+            return Err(Error::PayloadTooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
+
+/// Parses a request body as JSON.
+///
+/// # Errors
+/// * `Error::MissingParameter` - If the body is empty
+/// * `Error::InvalidRequest` - If the body isn't JSON
+pub fn parse(body: &[u8]) -> Result<serde_json::Value, Error> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Err(Error::missing_parameter("missing request body"));
+    }
+
+    serde_json::from_slice(body).map_err(request_error)
+}
+
+/// Decodes the JSON request of a method.
+///
+/// # Errors
+/// * `Error::MissingParameter` - If a field of the request is missing
+/// * `Error::InvalidRequest` - If a field has the wrong type or value
+pub fn decode<T: DeserializeOwned>(request: serde_json::Value) -> Result<T, Error> {
+    serde_json::from_value(request).map_err(request_error)
+}
+
+/// Maps an error reading a request to the error reported to the client.
+fn request_error(e: serde_json::Error) -> Error {
+    match e.classify() {
+        Category::Syntax | Category::Eof => Error::invalid_request(format!("malformed JSON: {e}")),
+        // Requests decoded from a value have no position, only the message to go on.
+        Category::Data if e.to_string().starts_with("missing field") => {
+            Error::missing_parameter(e.to_string())
+        }
+        Category::Data => Error::invalid_request(e.to_string()),
+        Category::Io => Error::internal(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::HeaderValue;
+    use futures_util::stream;
+
+    use super::*;
+    use crate::sqs::types::delete_message::DeleteMessageRequest;
+
+    fn chunks(chunks: &[&'static str]) -> impl Stream<Item = Result<Bytes, PayloadError>> {
+        stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        let headers = HeaderMap::new();
+        let body = read(&headers, chunks(&["{\"Queue", "Url\": ", "\"a\"}"]), 17)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"{\"QueueUrl\": \"a\"}");
+
+        assert!(matches!(
+            read(&headers, chunks(&["{\"Queue", "Url\": ", "\"ab\"}"]), 17).await,
+            Err(Error::PayloadTooLarge)
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from_static("18"));
+        assert!(matches!(
+            read(&headers, chunks(&[]), 17).await,
+            Err(Error::PayloadTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(parse(b" \n"), Err(Error::MissingParameter { .. })));
+        assert!(matches!(
+            parse(b"{\"QueueUrl\": "),
+            Err(Error::InvalidRequest { .. })
+        ));
+        let error = parse(b"{\"QueueUrl\" \"a\"}").unwrap_err();
+        assert!(error.to_string().contains("column 13"), "{error}");
+
+        let request = parse(b"{\"QueueUrl\": \"http://localhost/sqs/test/a\"}").unwrap();
+        assert!(matches!(
+            decode::<DeleteMessageRequest>(request),
+            Err(Error::MissingParameter { .. })
+        ));
+        let request = parse(b"{\"QueueUrl\": 1, \"ReceiptHandle\": \"b\"}").unwrap();
+        assert!(matches!(
+            decode::<DeleteMessageRequest>(request),
+            Err(Error::InvalidRequest { .. })
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+//! Checksum verification for sends.
+//!
+//! SQS clients check the `MD5OfMessageBody` returned by the server against what they sent. In
+//! the other direction, NerveMQ lets clients protect what they send:
+//!
+//! - A `Content-MD5` header (RFC 1864, the base64-encoded MD5 digest of the request body) is
+//!   checked against the whole request before it is decoded.
+//! - `MD5OfMessageBody` on a `SendMessage` request or `SendMessageBatch` entry (the hex-encoded
+//!   MD5 digest, as in responses) is checked against the message body before it is stored.
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use base64::Engine as _;
+
+use crate::error::Error;
+
+/// The `Content-MD5` request header.
+pub const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+/// Returns the hex-encoded MD5 digest of `data`, as used in SQS requests and responses.
+pub fn md5_hex(data: impl AsRef<[u8]>) -> String {
+    hex::encode(md5::compute(data).as_slice())
+}
+
+/// Checks a request body against its `Content-MD5` header.
+///
+/// # Errors
+/// Returns [`Error::InvalidHeader`] if the header is not a base64-encoded MD5 digest, and
+/// [`Error::ChecksumMismatch`] if it doesn't match the body
+pub fn verify_content_md5(header: &HeaderValue, body: &[u8]) -> Result<(), Error> {
+    let expected = base64::prelude::BASE64_STANDARD
+        .decode(header.as_bytes())
+        .map_err(|_| Error::InvalidHeader {
+            header: CONTENT_MD5.to_string(),
+        })?;
+
+    if expected != md5::compute(body).as_slice() {
+        return Err(Error::ChecksumMismatch {
+            field: "Content-MD5".to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks a message body against the `MD5OfMessageBody` the client sent with it, if any.
+///
+/// # Errors
+/// Returns [`Error::ChecksumMismatch`] if the digest doesn't match the body
+pub fn verify_message_body(expected: Option<&str>, body: &str) -> Result<(), Error> {
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&md5_hex(body)) => {
+            Err(Error::ChecksumMismatch {
+                field: "MD5OfMessageBody".to_owned(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_content_md5() {
+        let body = br#"{"QueueUrl": "http://localhost:8080/sqs/ns/q"}"#;
+        let header = base64::prelude::BASE64_STANDARD.encode(md5::compute(body).as_slice());
+
+        let header = HeaderValue::from_str(&header).unwrap();
+        assert!(verify_content_md5(&header, body).is_ok());
+        assert!(matches!(
+            verify_content_md5(&header, b"{}"),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_content_md5(&HeaderValue::from_static("not base64!"), body),
+            Err(Error::InvalidHeader { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_message_body() {
+        let digest = md5_hex("hello");
+
+        assert!(verify_message_body(None, "hello").is_ok());
+        assert!(verify_message_body(Some(&digest), "hello").is_ok());
+        assert!(verify_message_body(Some(&digest.to_uppercase()), "hello").is_ok());
+        assert!(verify_message_body(Some(&digest), "hello!").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Errors as SQS reports them.
+//!
+//! The AWS SDKs don't read the `code` of NerveMQ's error bodies, but the error type SQS would
+//! have answered with, see [`Error::sqs_code`], and decide from it whether to retry. JSON
+//! requests get it as `__type`, along with the usual error body, and as the
+//! `x-amzn-query-error` header that SDKs of the query protocol era map their error codes from.
+//! Query protocol requests get it in their XML error response, see [`super::query`].
+
+use actix_web::{
+    body::BoxBody,
+    http::header::{HeaderName, HeaderValue},
+    HttpResponse, ResponseError as _,
+};
+use serde::Serialize;
+
+use crate::error::{Error, ErrorBody};
+
+/// Header carrying the query protocol code and fault of an error in JSON responses.
+pub const QUERY_ERROR: HeaderName = HeaderName::from_static("x-amzn-query-error");
+
+/// Prefix of the `__type` of SQS errors.
+const TYPE_PREFIX: &str = "com.amazonaws.sqs#";
+
+/// The JSON body of SQS error responses.
+#[derive(Debug, Serialize)]
+pub struct SqsErrorBody {
+    #[serde(rename = "__type")]
+    pub error_type: String,
+    #[serde(flatten)]
+    pub body: ErrorBody,
+}
+
+/// Returns the code the query protocol uses for an error, which for some errors differs from
+/// the one of the JSON protocol.
+pub fn query_code(error: &Error) -> &'static str {
+    match error.sqs_code() {
+        "QueueDoesNotExist" => "AWS.SimpleQueueService.NonExistentQueue",
+        "UnsupportedOperation" => "AWS.SimpleQueueService.UnsupportedOperation",
+        "TooManyEntriesInBatchRequest" => "AWS.SimpleQueueService.TooManyEntriesInBatchRequest",
+        "BatchEntryIdsNotDistinct" => "AWS.SimpleQueueService.BatchEntryIdsNotDistinct",
+        "EmptyBatchRequest" => "AWS.SimpleQueueService.EmptyBatchRequest",
+        "BatchRequestTooLong" => "AWS.SimpleQueueService.BatchRequestTooLong",
+        "PurgeQueueInProgress" => "AWS.SimpleQueueService.PurgeQueueInProgress",
+        "AccessDeniedException" => "AccessDenied",
+        code => code,
+    }
+}
+
+/// Returns whether an error is the fault of the client, `Sender`, or the server, `Receiver`.
+pub fn fault(error: &Error) -> &'static str {
+    match error.is_sender_fault() {
+        true => "Sender",
+        false => "Receiver",
+    }
+}
+
+/// Returns the value of the [`QUERY_ERROR`] header for an error.
+pub fn query_error(error: &Error) -> HeaderValue {
+    HeaderValue::from_str(&format!("{};{}", query_code(error), fault(error)))
+        .expect("error codes are valid header values")
+}
+
+/// Returns the JSON body of SQS for an error.
+pub fn json_body(error: &Error) -> BoxBody {
+    let body = SqsErrorBody {
+        error_type: format!("{TYPE_PREFIX}{}", error.sqs_code()),
+        body: error.body(),
+    };
+    BoxBody::new(serde_json::to_vec(&body).expect("error bodies serialize"))
+}
+
+/// Renders an error as the JSON error response of SQS.
+pub fn json_response(error: &Error) -> HttpResponse {
+    let mut res = error.error_response();
+    res.headers_mut().insert(QUERY_ERROR, query_error(error));
+    res.set_body(json_body(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_response() {
+        let res = json_response(&Error::queue_not_found("orders", "test"));
+        assert_eq!(res.status(), 404);
+        assert_eq!(
+            res.headers().get(QUERY_ERROR).unwrap(),
+            "AWS.SimpleQueueService.NonExistentQueue;Sender"
+        );
+
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["__type"], "com.amazonaws.sqs#QueueDoesNotExist");
+        assert_eq!(
+            body["message"],
+            "Resource not found: queue orders in namespace test"
+        );
+        // The native error body is still there, for clients that read it.
+        assert_eq!(body["code"], "NotFound");
+    }
+
+    #[test]
+    fn test_codes() {
+        for (error, code, query) in [
+            (
+                Error::ReceiptHandleIsInvalid {
+                    handle: "x".to_owned(),
+                },
+                "ReceiptHandleIsInvalid",
+                "ReceiptHandleIsInvalid",
+            ),
+            (
+                Error::OverLimit {
+                    resource: "in-flight messages".to_owned(),
+                    limit: 120_000,
+                },
+                "OverLimit",
+                "OverLimit",
+            ),
+            (
+                Error::Throttled {
+                    retry_after: std::time::Duration::from_secs(1),
+                },
+                "RequestThrottled",
+                "RequestThrottled",
+            ),
+            (
+                Error::invalid_parameter("DelaySeconds"),
+                "InvalidParameterValue",
+                "InvalidParameterValue",
+            ),
+            (
+                Error::EmptyBatchRequest,
+                "EmptyBatchRequest",
+                "AWS.SimpleQueueService.EmptyBatchRequest",
+            ),
+            (Error::opaque(), "InternalFailure", "InternalFailure"),
+        ] {
+            assert_eq!(error.sqs_code(), code, "{error}");
+            assert_eq!(query_code(&error), query, "{error}");
+        }
+        assert_eq!(fault(&Error::opaque()), "Receiver");
+    }
+}
@@ -0,0 +1,242 @@
+//! Limits on the messages sent through SQS.
+//!
+//! As in SQS, a message has at most [`MAX_MESSAGE_ATTRIBUTES`] attributes, whose names are at
+//! most [`MAX_ATTRIBUTE_NAME_LENGTH`] characters of `A-Z a-z 0-9 _ - .`, and its body and
+//! attributes together (each attribute counting its name, data type and value) are at most the
+//! queue's `MaximumMessageSize`, [`MAX_MESSAGE_SIZE`] by default. The messages of a batch are at
+//! most [`MAX_BATCH_SIZE`] together, whatever the queue's limit. A `MessageDeduplicationId` is
+//! at most [`MAX_DEDUPLICATION_ID_LENGTH`] printable ASCII characters.
+
+use std::collections::HashMap;
+
+use crate::{error::Error, sqs::types::SqsMessageAttribute};
+
+/// The maximum number of attributes of a message.
+pub const MAX_MESSAGE_ATTRIBUTES: usize = 10;
+
+/// The maximum length of an attribute name.
+pub const MAX_ATTRIBUTE_NAME_LENGTH: usize = 256;
+
+/// The maximum size of a message, in bytes, for queues that don't set a `MaximumMessageSize`.
+pub const MAX_MESSAGE_SIZE: u64 = 256 * 1024;
+
+/// The maximum size of all messages of a batch together, in bytes.
+pub const MAX_BATCH_SIZE: u64 = 256 * 1024;
+
+/// The maximum length of a `MessageDeduplicationId`.
+pub const MAX_DEDUPLICATION_ID_LENGTH: usize = 128;
+
+/// Prefixes of attribute names reserved by AWS, compared case-insensitively.
+const RESERVED_PREFIXES: [&str; 2] = ["aws.", "amazon."];
+
+/// Validates the body and attributes of a message.
+///
+/// # Arguments
+/// * `body` - Body of the message
+/// * `attributes` - Attributes of the message
+/// * `max_size` - Maximum size of the message, in bytes
+///
+/// # Errors
+/// * `Error::TooManyMessageAttributes` - If there are more than [`MAX_MESSAGE_ATTRIBUTES`]
+/// * `Error::InvalidAttributeName` - If an attribute name isn't valid
+/// * `Error::MessageTooLong` - If the body and attributes together exceed `max_size`
+pub fn validate_message(
+    body: &str,
+    attributes: &HashMap<String, SqsMessageAttribute>,
+    max_size: u64,
+) -> Result<(), Error> {
+    if attributes.len() > MAX_MESSAGE_ATTRIBUTES {
+        return Err(Error::TooManyMessageAttributes {
+            count: attributes.len(),
+            max: MAX_MESSAGE_ATTRIBUTES,
+        });
+    }
+
+    for name in attributes.keys() {
+        validate_attribute_name(name)?;
+    }
+
+    let size = message_size(body, attributes);
+    if size > max_size {
+        return Err(Error::MessageTooLong {
+            size,
+            max: max_size,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the size of a message as SQS counts it: its body, and the name, data type and value of
+/// each of its attributes.
+pub fn message_size(body: &str, attributes: &HashMap<String, SqsMessageAttribute>) -> u64 {
+    let attributes = attributes.iter().map(|(name, value)| {
+        let len = match value {
+            SqsMessageAttribute::String { string_value }
+            | SqsMessageAttribute::Number { string_value } => string_value.len(),
+            SqsMessageAttribute::Binary { binary_value } => binary_value.len(),
+        };
+        name.len() + value.data_type().len() + len
+    });
+
+    (body.len() + attributes.sum::<usize>()) as u64
+}
+
+/// Validates the size of the messages of a batch together.
+///
+/// # Errors
+/// Returns `Error::BatchRequestTooLong` if the messages exceed [`MAX_BATCH_SIZE`]
+pub fn validate_batch_size(sizes: impl IntoIterator<Item = u64>) -> Result<(), Error> {
+    let size = sizes.into_iter().sum();
+    if size > MAX_BATCH_SIZE {
+        return Err(Error::BatchRequestTooLong {
+            size,
+            max: MAX_BATCH_SIZE,
+        });
+    }
+
+    Ok(())
+}
+
+/// Validates the name of a message attribute.
+///
+/// # Errors
+/// Returns `Error::InvalidAttributeName` with the reason if the name is invalid
+pub fn validate_attribute_name(name: &str) -> Result<(), Error> {
+    let invalid = |reason: &str| {
+        Err(Error::InvalidAttributeName {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        })
+    };
+
+    if name.is_empty() {
+        return invalid("it is empty");
+    }
+    if name.len() > MAX_ATTRIBUTE_NAME_LENGTH {
+        return invalid("it is longer than 256 characters");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return invalid("it may only contain A-Z, a-z, 0-9, underscores, hyphens and periods");
+    }
+    if name.starts_with('.') || name.ends_with('.') || name.contains("..") {
+        return invalid("it may not start or end with a period, or contain consecutive periods");
+    }
+    let lowercase = name.to_ascii_lowercase();
+    if RESERVED_PREFIXES
+        .iter()
+        .any(|prefix| lowercase.starts_with(prefix))
+    {
+        return invalid("names starting with AWS. or Amazon. are reserved");
+    }
+
+    Ok(())
+}
+
+/// Validates a `MessageDeduplicationId`.
+///
+/// # Errors
+/// Returns `Error::InvalidParameter` if the ID is empty, too long, or not printable ASCII
+pub fn validate_deduplication_id(id: &str) -> Result<(), Error> {
+    if id.is_empty() || id.len() > MAX_DEDUPLICATION_ID_LENGTH {
+        return Err(Error::invalid_parameter(format!(
+            "MessageDeduplicationId must be 1 to {MAX_DEDUPLICATION_ID_LENGTH} characters long"
+        )));
+    }
+    if !id.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(Error::invalid_parameter(
+            "MessageDeduplicationId may only contain printable ASCII characters without spaces",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> SqsMessageAttribute {
+        SqsMessageAttribute::String {
+            string_value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_attribute_names() {
+        for name in ["a", "order-id", "Order_ID.v2", &"a".repeat(256)] {
+            assert!(validate_attribute_name(name).is_ok(), "{name}");
+        }
+
+        for name in [
+            "",
+            &"a".repeat(257),
+            "order id",
+            "émoji",
+            ".hidden",
+            "trailing.",
+            "a..b",
+            "AWS.TraceHeader",
+            "amazon.thing",
+        ] {
+            assert!(
+                matches!(
+                    validate_attribute_name(name),
+                    Err(Error::InvalidAttributeName { .. })
+                ),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_attribute_count() {
+        let attributes = (0..MAX_MESSAGE_ATTRIBUTES)
+            .map(|i| (format!("a{i}"), string("v")))
+            .collect::<HashMap<_, _>>();
+        assert!(validate_message("body", &attributes, MAX_MESSAGE_SIZE).is_ok());
+
+        let mut attributes = attributes;
+        attributes.insert("one-too-many".to_owned(), string("v"));
+        assert!(matches!(
+            validate_message("body", &attributes, MAX_MESSAGE_SIZE),
+            Err(Error::TooManyMessageAttributes { count: 11, max: 10 })
+        ));
+    }
+
+    #[test]
+    fn test_message_size() {
+        // 4 bytes of body, and 4 + 6 + 5 bytes of name, data type and value.
+        let attributes = HashMap::from([("name".to_owned(), string("value"))]);
+        assert!(validate_message("body", &attributes, 19).is_ok());
+        assert!(matches!(
+            validate_message("body", &attributes, 18),
+            Err(Error::MessageTooLong { size: 19, max: 18 })
+        ));
+
+        let body = "x".repeat(MAX_MESSAGE_SIZE as usize + 1);
+        assert!(validate_message(&body, &HashMap::new(), MAX_MESSAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_batch_size() {
+        assert!(validate_batch_size([MAX_BATCH_SIZE / 2, MAX_BATCH_SIZE / 2]).is_ok());
+        assert!(matches!(
+            validate_batch_size([MAX_BATCH_SIZE / 2, MAX_BATCH_SIZE / 2 + 1]),
+            Err(Error::BatchRequestTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn test_deduplication_ids() {
+        for id in ["a", "order-42:retry!", &"a".repeat(128)] {
+            assert!(validate_deduplication_id(id).is_ok(), "{id}");
+        }
+        for id in ["", &"a".repeat(129), "order 42", "émoji"] {
+            assert!(validate_deduplication_id(id).is_err(), "{id}");
+        }
+    }
+}
@@ -19,9 +19,9 @@ use std::str::FromStr;
 
 use actix_web::{FromRequest, HttpMessage};
 use pom::utf8::{end, seq, sym};
-use strum::EnumString;
+use strum::{AsRefStr, EnumString, VariantNames};
 
-use crate::{error::Error, utils::to_pom_error};
+use crate::{error::Error, utils::to_pom_error, version::VERSION_PATH};
 
 /// Standard prefix for all SQS API method names.
 ///
@@ -30,11 +30,11 @@ use crate::{error::Error, utils::to_pom_error};
 pub const SQS_METHOD_PREFIX: &str = "AmazonSQS";
 
 /// Represents an SQS API method.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr, EnumString, VariantNames)]
 pub enum Method {
     // AddPermission,                // TODO: Implement
-    // CancelMessageMoveTask,        // TODO: Implement
-    // ChangeMessageVisibility,      // TODO: Implement
+    CancelMessageMoveTask,
+    ChangeMessageVisibility,
     // ChangeMessageVisibilityBatch, // TODO: Implement
     CreateQueue,
     DeleteMessage,
@@ -42,8 +42,8 @@ pub enum Method {
     DeleteQueue,
     GetQueueAttributes,
     GetQueueUrl,
-    // ListDeadLetterSourceQueues,   // TODO: Implement
-    // ListMessageMoveTasks,         // TODO: Implement
+    ListDeadLetterSourceQueues,
+    ListMessageMoveTasks,
     ListQueues,
     ListQueueTags,
     PurgeQueue,
@@ -52,7 +52,7 @@ pub enum Method {
     SendMessage,
     SendMessageBatch,
     SetQueueAttributes,
-    // StartMessageMoveTask,         // TODO: Implement
+    StartMessageMoveTask,
     TagQueue,
     UntagQueue,
 }
@@ -74,11 +74,25 @@ impl Method {
         let parser = seq(SQS_METHOD_PREFIX) * sym('.') * method - end();
 
         parser.parse_str(input).map_err(|e| Error::InvalidMethod {
-            message: format!("{e}"),
+            message: format!("{e}, see GET {VERSION_PATH} for the supported operations"),
         })
     }
 }
 
+impl FromRequest for Method {
+    type Error = Error;
+
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        std::future::ready(req.extensions().get::<Method>().cloned().ok_or_else(|| {
+            Error::MissingHeader {
+                header: "X-Amz-Target".to_owned(),
+            }
+        }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,7 +104,19 @@ mod tests {
             ("AmazonSQS.SendMessageBatch", Method::SendMessageBatch),
             ("AmazonSQS.ReceiveMessage", Method::ReceiveMessage),
             ("AmazonSQS.DeleteMessage", Method::DeleteMessage),
+            (
+                "AmazonSQS.ChangeMessageVisibility",
+                Method::ChangeMessageVisibility,
+            ),
             ("AmazonSQS.ListQueues", Method::ListQueues),
+            (
+                "AmazonSQS.ListDeadLetterSourceQueues",
+                Method::ListDeadLetterSourceQueues,
+            ),
+            (
+                "AmazonSQS.StartMessageMoveTask",
+                Method::StartMessageMoveTask,
+            ),
             ("AmazonSQS.GetQueueUrl", Method::GetQueueUrl),
             ("AmazonSQS.CreateQueue", Method::CreateQueue),
             ("AmazonSQS.GetQueueAttributes", Method::GetQueueAttributes),
@@ -140,17 +166,3 @@ mod tests {
         }
     }
 }
-
-impl FromRequest for Method {
-    type Error = Error;
-
-    type Future = std::future::Ready<Result<Self, Self::Error>>;
-
-    fn from_request(req: &actix_web::HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
-        std::future::ready(req.extensions().get::<Method>().cloned().ok_or_else(|| {
-            Error::MissingHeader {
-                header: "X-Amz-Target".to_owned(),
-            }
-        }))
-    }
-}
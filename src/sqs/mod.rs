@@ -1,63 +1,86 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use actix_identity::Identity;
-use actix_web::{post, web::Data, Responder, Scope};
-use futures_util::TryStreamExt as _;
+use actix_web::{web::Data, HttpMessage, HttpRequest, Responder, Scope};
 use method::Method;
-use tokio_serde::{formats::SymmetricalJson, SymmetricallyFramed};
-use tokio_stream::StreamExt;
-use tokio_util::{
-    codec::{BytesCodec, FramedRead},
-    io::StreamReader,
-};
 use tracing::instrument;
 use types::{
+    cancel_message_move_task::{CancelMessageMoveTaskRequest, CancelMessageMoveTaskResponse},
+    change_message_visibility::{ChangeMessageVisibilityRequest, ChangeMessageVisibilityResponse},
     create_queue::{CreateQueueRequest, CreateQueueResponse},
     delete_message::{DeleteMessageRequest, DeleteMessageResponse},
+    delete_message_batch::{
+        DeleteMessageBatchRequest, DeleteMessageBatchResponse, DeleteMessageBatchResultError,
+        DeleteMessageBatchResultSuccess,
+    },
     delete_queue::{DeleteQueueRequest, DeleteQueueResponse},
     get_queue_attributes::{GetQueueAttributesRequest, GetQueueAttributesResponse},
     get_queue_url::{GetQueueUrlRequest, GetQueueUrlResponse},
+    list_dead_letter_source_queues::{
+        ListDeadLetterSourceQueuesRequest, ListDeadLetterSourceQueuesResponse,
+    },
+    list_message_move_tasks::{
+        ListMessageMoveTasksRequest, ListMessageMoveTasksResponse, ListMessageMoveTasksResultEntry,
+    },
     list_queues::{ListQueuesRequest, ListQueuesResponse},
     purge_queue::{PurgeQueueRequest, PurgeQueueResponse},
     receive_message::{ReceiveMessageRequest, ReceiveMessageResponse},
     send_message::SendMessageRequest,
     send_message_batch::SendMessageBatchRequest,
     set_queue_attributes::{SetQueueAttributesRequest, SetQueueAttributesResponse},
+    start_message_move_task::{StartMessageMoveTaskRequest, StartMessageMoveTaskResponse},
     SqsResponse,
 };
-use url::Url;
 
-use crate::{auth::credential::AuthorizedNamespace, error::Error};
+use crate::{
+    audit::AuditResource,
+    auth::credential::AuthorizedNamespace,
+    error::Error,
+    message_move::{MoveTaskStatus, MAX_LISTED_TASKS},
+    selector::Selector,
+    service::ReceiveOptions,
+};
+
+pub use queue_url::{BaseUrl, QueueUrl};
+
+use body::decode;
 
+pub mod batch;
+pub mod body;
+pub mod checksum;
+pub mod error;
+pub mod limits;
 pub mod method;
+pub mod query;
+pub mod queue_url;
 pub mod service;
 pub mod types;
 
-fn queue_url(mut host: Url, queue_name: &str, namespace_name: &str) -> Result<url::Url, Error> {
-    host.path_segments_mut()
-        .map_err(|_| Error::InternalServerError { source: None })?
-        .push("sqs")
-        .push(namespace_name)
-        .push(queue_name);
-    Ok(host)
-}
+/// Maximum number of messages returned by a single ReceiveMessage call.
+const MAX_RECEIVE_MESSAGES: u64 = 10;
+
+/// Maximum visibility timeout accepted by ReceiveMessage (12 hours).
+const MAX_VISIBILITY_TIMEOUT_SECONDS: u64 = crate::service::MAX_VISIBILITY_TIMEOUT.as_secs();
+
+/// Maximum number of queues returned by a single ListDeadLetterSourceQueues call.
+const MAX_DEAD_LETTER_SOURCE_QUEUES: u64 = 1000;
+
+/// Maximum long-polling wait time accepted by ReceiveMessage.
+const MAX_WAIT_TIME_SECONDS: u64 = 20;
 
 #[instrument(skip(service, identity))]
 async fn send_message(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: SendMessageRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -69,7 +92,7 @@ async fn send_message(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
     let queue_id = service
@@ -87,18 +110,11 @@ async fn send_message_batch(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: SendMessageBatchRequest,
 ) -> Result<SqsResponse, Error> {
-    let queue_url = request.queue_url.clone();
-
-    let mut path = queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -110,9 +126,11 @@ async fn send_message_batch(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
+    batch::validate_entry_ids(request.entries.iter().map(|entry| entry.id.as_str()))?;
+
     let res = service
         .sqs_send_batch(namespace_name, queue_name, request)
         .await?;
@@ -125,17 +143,11 @@ async fn receive_message(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: ReceiveMessageRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -147,16 +159,52 @@ async fn receive_message(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
+    }
+
+    let max_messages = request.max_number_of_messages.unwrap_or(1);
+    if !(1..=MAX_RECEIVE_MESSAGES).contains(&max_messages) {
+        return Err(Error::invalid_parameter(format!(
+            "MaxNumberOfMessages: must be between 1 and {MAX_RECEIVE_MESSAGES}"
+        )));
+    }
+
+    if request
+        .visibility_timeout
+        .is_some_and(|timeout| timeout > MAX_VISIBILITY_TIMEOUT_SECONDS)
+    {
+        return Err(Error::invalid_parameter(format!(
+            "VisibilityTimeout: must be between 0 and {MAX_VISIBILITY_TIMEOUT_SECONDS}"
+        )));
     }
 
+    if request
+        .wait_time_seconds
+        .is_some_and(|wait_time| wait_time > MAX_WAIT_TIME_SECONDS)
+    {
+        return Err(Error::invalid_parameter(format!(
+            "WaitTimeSeconds: must be between 0 and {MAX_WAIT_TIME_SECONDS}"
+        )));
+    }
+
+    let selector = request
+        .message_selector
+        .as_deref()
+        .map(Selector::parse)
+        .transpose()?;
+
+    let options = ReceiveOptions::builder()
+        .max_messages(max_messages)
+        .maybe_visibility_timeout(request.visibility_timeout.map(Duration::from_secs))
+        .maybe_wait_time(request.wait_time_seconds.map(Duration::from_secs))
+        .attribute_names(HashSet::from_iter(request.attribute_names))
+        .message_attribute_names(HashSet::from_iter(request.message_attribute_names))
+        .maybe_consumer_group(queue_url.consumer_group())
+        .maybe_selector(selector)
+        .build();
+
     let messages = service
-        .sqs_recv_batch(
-            namespace_name,
-            queue_name,
-            request.max_number_of_messages.unwrap_or(1) as u64,
-            HashSet::from_iter(request.attribute_names.into_iter()),
-        )
+        .sqs_recv_batch(namespace_name, queue_name, options)
         .await?;
 
     Ok(SqsResponse::ReceiveMessage(ReceiveMessageResponse {
@@ -169,17 +217,11 @@ async fn delete_message(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: DeleteMessageRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -191,93 +233,156 @@ async fn delete_message(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
-    let message_id = request
-        .receipt_handle
-        .parse::<u64>()
-        .map_err(|e| Error::invalid_parameter(format!("ReceiptHandle: {e}")))?;
+    let message_id = service
+        .resolve_receipt_handle(namespace_name, queue_name, &request.receipt_handle)
+        .await?;
 
     service
-        .delete_message(namespace_name, queue_name, message_id, identity)
+        .delete_message(
+            namespace_name,
+            queue_name,
+            message_id,
+            queue_url.consumer_group(),
+            identity,
+        )
         .await?;
 
     Ok(SqsResponse::DeleteMessage(DeleteMessageResponse {}))
 }
 
-// // FIXME: Finish implementing this
-//
-// async fn delete_message_batch(
-//     service: Data<crate::service::Service>,
-//     identity: Identity,
-//     namespace: AuthorizedNamespace,
-//     mut stream: Stream<DeleteMessageBatchRequest>,
-// ) -> Result<DeleteMessageBatchResponse, Error> {
-//     let request = stream
-//         .next()
-//         .await
-//         .transpose()
-//         .map_err(|e| Error::internal(e))?
-//         .ok_or_else(|| Error::missing_parameter("missing request body"))?;
-//
-//     let mut path = request
-//         .queue_url
-//         .path_segments()
-//         .ok_or_else(|| Error::missing_parameter("queue name"))?;
-//
-//     let (queue_name, namespace_name) = path
-//         .next_back()
-//         .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-//         .ok_or_else(|| Error::missing_parameter("namespace name"))?;
-//
-//     let ns_id = service
-//         .get_namespace_id(namespace_name, service.db())
-//         .await?
-//         .ok_or_else(|| Error::namespace_not_found(namespace_name))?;
-//
-//     service
-//         .check_user_access(&identity, ns_id, service.db())
-//         .await?;
-//
-//     if namespace_name != namespace.0 {
-//         return Err(Error::Unauthorized);
-//     }
-//
-//     let message_id = request
-//         .receipt_handle
-//         .parse::<u64>()
-//         .map_err(|e| Error::invalid_parameter(format!("ReceiptHandle: {e}")))?;
-//
-//     let (successful, failed) = service
-//         .delete_message_batch(namespace_name, queue_name, message_id, identity)
-//         .await
-//         .map(|(successful, failed)| {
-//             (
-//                 successful
-//                     .into_iter()
-//                     .map(|id| DeleteMessageBatchResultSuccess { id: id.to_string() })
-//                     .collect(),
-//                 failed
-//                     .into_iter()
-//                     .map(|(id, err)| DeleteMessageBatchResultError {
-//                         id: id.to_string(),
-//                         code: "InternalError".to_string(),
-//                         message: err.to_string(),
-//                         sender_fault: true,
-//                     })
-//                     .collect(),
-//             )
-//         })?;
-//
-//     Ok(DeleteMessageBatchResponse { failed, successful })
-// }
+#[instrument(skip(service, identity))]
+async fn change_message_visibility(
+    service: Data<crate::service::Service>,
+    identity: Identity,
+    namespace: AuthorizedNamespace,
+    base: BaseUrl,
+    request: ChangeMessageVisibilityRequest,
+) -> Result<SqsResponse, Error> {
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
+
+    if namespace_name != namespace.0 {
+        return Err(service.namespace_access_denied(namespace_name));
+    }
+
+    let message_id = service
+        .resolve_receipt_handle(namespace_name, queue_name, &request.receipt_handle)
+        .await?;
+
+    service
+        .change_message_visibility(
+            namespace_name,
+            queue_name,
+            message_id,
+            Duration::from_secs(request.visibility_timeout),
+            request.failure_reason.as_deref(),
+            queue_url.consumer_group(),
+            identity,
+        )
+        .await?;
+
+    Ok(SqsResponse::ChangeMessageVisibility(
+        ChangeMessageVisibilityResponse {},
+    ))
+}
+
+#[instrument(skip(service, identity))]
+async fn delete_message_batch(
+    service: Data<crate::service::Service>,
+    identity: Identity,
+    namespace: AuthorizedNamespace,
+    base: BaseUrl,
+    request: DeleteMessageBatchRequest,
+) -> Result<SqsResponse, Error> {
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
+
+    let ns_id = service
+        .get_namespace_id(namespace_name, service.db())
+        .await?
+        .ok_or_else(|| Error::namespace_not_found(namespace_name))?;
+
+    service
+        .check_user_access(&identity, ns_id, service.db())
+        .await?;
+
+    if namespace_name != namespace.0 {
+        return Err(service.namespace_access_denied(namespace_name));
+    }
+
+    batch::validate_entry_ids(request.entries.iter().map(|entry| entry.id.as_str()))?;
+
+    let mut failed = Vec::new();
+    let mut successful = Vec::new();
+
+    // Several entries may carry the same receipt handle, so track every entry id per message.
+    let mut entries: HashMap<u64, Vec<String>> = HashMap::new();
+    let handles = request
+        .entries
+        .iter()
+        .map(|entry| entry.receipt_handle.clone())
+        .collect::<Vec<_>>();
+    let message_ids = service
+        .resolve_receipt_handles(namespace_name, queue_name, &handles)
+        .await?;
+    for (entry, message_id) in request.entries.into_iter().zip(message_ids) {
+        match message_id {
+            Some(message_id) => entries.entry(message_id).or_default().push(entry.id),
+            None => failed.push(DeleteMessageBatchResultError {
+                id: entry.id,
+                code: "ReceiptHandleIsInvalid".to_string(),
+                message: format!("ReceiptHandle: {}", entry.receipt_handle),
+                sender_fault: true,
+            }),
+        }
+    }
+
+    let (deleted, errors) = service
+        .delete_message_batch(
+            namespace_name,
+            queue_name,
+            entries.keys().copied().collect(),
+            queue_url.consumer_group(),
+            identity,
+        )
+        .await?;
+
+    for message_id in deleted {
+        for id in entries.remove(&message_id).unwrap_or_default() {
+            successful.push(DeleteMessageBatchResultSuccess { id });
+        }
+    }
+
+    for (message_id, err) in errors {
+        let (code, sender_fault) = match &err {
+            Error::NotFound { .. } => ("ReceiptHandleIsInvalid", true),
+            other => (other.sqs_code(), other.is_sender_fault()),
+        };
+
+        for id in entries.remove(&message_id).unwrap_or_default() {
+            failed.push(DeleteMessageBatchResultError {
+                id,
+                code: code.to_string(),
+                message: err.to_string(),
+                sender_fault,
+            });
+        }
+    }
+
+    Ok(SqsResponse::DeleteMessageBatch(
+        DeleteMessageBatchResponse { failed, successful },
+    ))
+}
 
 #[instrument(skip(service, identity))]
 async fn list_queues(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: ListQueuesRequest,
 ) -> Result<SqsResponse, Error> {
     let namespace_id = service
@@ -304,11 +409,7 @@ async fn list_queues(
     let mut urls = Vec::new();
 
     for queue in queues {
-        urls.push(queue_url(
-            service.config().host(),
-            &queue.name,
-            &namespace.0,
-        )?);
+        urls.push(QueueUrl::new(base.as_url(), &namespace.0, queue.name)?.into());
     }
 
     Ok(SqsResponse::ListQueues(ListQueuesResponse {
@@ -316,11 +417,179 @@ async fn list_queues(
     }))
 }
 
+/// Lists the queues that move their failed messages to a queue. Only source queues in the
+/// caller's namespace are listed, as with ListQueues.
+///
+/// Pages are continued after the queue whose ID is in the `NextToken`.
+#[instrument(skip(service, identity))]
+async fn list_dead_letter_source_queues(
+    service: Data<crate::service::Service>,
+    identity: Identity,
+    namespace: AuthorizedNamespace,
+    base: BaseUrl,
+    request: ListDeadLetterSourceQueuesRequest,
+) -> Result<SqsResponse, Error> {
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
+
+    if namespace_name != namespace.0 {
+        return Err(service.namespace_access_denied(namespace_name));
+    }
+
+    if let Some(max_results) = request.max_results {
+        if !(1..=MAX_DEAD_LETTER_SOURCE_QUEUES).contains(&max_results) {
+            return Err(Error::invalid_parameter(format!(
+                "MaxResults: must be between 1 and {MAX_DEAD_LETTER_SOURCE_QUEUES}"
+            )));
+        }
+    }
+    let after = request
+        .next_token
+        .as_deref()
+        .map(str::parse::<u64>)
+        .transpose()
+        .map_err(|_| Error::invalid_parameter("NextToken: invalid token"))?;
+
+    let mut queues = service
+        .list_dead_letter_source_queues(namespace_name, queue_name, identity)
+        .await?;
+    queues.retain(|queue| after.is_none_or(|after| queue.id > after));
+
+    let mut next_token = None;
+    if let Some(max_results) = request.max_results {
+        if queues.len() as u64 > max_results {
+            queues.truncate(max_results as usize);
+            next_token = queues.last().map(|queue| queue.id.to_string());
+        }
+    }
+
+    let mut urls = Vec::new();
+
+    for queue in queues {
+        urls.push(QueueUrl::new(base.as_url(), &queue.ns, queue.name)?.into());
+    }
+
+    Ok(SqsResponse::ListDeadLetterSourceQueues(
+        ListDeadLetterSourceQueuesResponse {
+            queue_urls: urls,
+            next_token,
+        },
+    ))
+}
+
+/// Splits an ARN, which NerveMQ writes as `namespace:queue`, into its namespace and queue. An ARN
+/// without a namespace refers to a queue in the caller's namespace.
+fn parse_arn<'a>(arn: &'a str, namespace: &'a str) -> (&'a str, &'a str) {
+    arn.split_once(':').unwrap_or((namespace, arn))
+}
+
+/// Starts moving the messages of a dead-letter queue in the caller's namespace, to a queue in the
+/// same namespace or back to their source queues.
+#[instrument(skip(service, identity))]
+async fn start_message_move_task(
+    service: Data<crate::service::Service>,
+    identity: Identity,
+    namespace: AuthorizedNamespace,
+    request: StartMessageMoveTaskRequest,
+) -> Result<SqsResponse, Error> {
+    let (source_ns, source) = parse_arn(&request.source_arn, &namespace.0);
+    if source_ns != namespace.0 {
+        return Err(service.namespace_access_denied(source_ns));
+    }
+
+    let destination = request
+        .destination_arn
+        .as_deref()
+        .map(|arn| parse_arn(arn, &namespace.0));
+    if let Some((destination_ns, _)) = destination {
+        if destination_ns != namespace.0 {
+            return Err(service.namespace_access_denied(destination_ns));
+        }
+    }
+
+    let task_handle = service
+        .start_message_move_task(
+            source_ns,
+            source,
+            destination,
+            request.max_number_of_messages_per_second,
+            identity,
+        )
+        .await?;
+
+    Ok(SqsResponse::StartMessageMoveTask(
+        StartMessageMoveTaskResponse { task_handle },
+    ))
+}
+
+#[instrument(skip(service, identity))]
+async fn cancel_message_move_task(
+    service: Data<crate::service::Service>,
+    identity: Identity,
+    namespace: AuthorizedNamespace,
+    request: CancelMessageMoveTaskRequest,
+) -> Result<SqsResponse, Error> {
+    let moved = service
+        .cancel_message_move_task(&namespace.0, &request.task_handle, identity)
+        .await?;
+
+    Ok(SqsResponse::CancelMessageMoveTask(
+        CancelMessageMoveTaskResponse {
+            approximate_number_of_messages_moved: moved,
+        },
+    ))
+}
+
+#[instrument(skip(service, identity))]
+async fn list_message_move_tasks(
+    service: Data<crate::service::Service>,
+    identity: Identity,
+    namespace: AuthorizedNamespace,
+    request: ListMessageMoveTasksRequest,
+) -> Result<SqsResponse, Error> {
+    let (source_ns, source) = parse_arn(&request.source_arn, &namespace.0);
+    if source_ns != namespace.0 {
+        return Err(service.namespace_access_denied(source_ns));
+    }
+
+    let max_results = request.max_results.unwrap_or(1);
+    if !(1..=MAX_LISTED_TASKS).contains(&max_results) {
+        return Err(Error::invalid_parameter(format!(
+            "MaxResults: must be between 1 and {MAX_LISTED_TASKS}"
+        )));
+    }
+
+    let results = service
+        .list_message_move_tasks(source_ns, source, max_results, identity)
+        .await?
+        .into_iter()
+        .map(|task| ListMessageMoveTasksResultEntry {
+            task_handle: (task.status == MoveTaskStatus::Running).then_some(task.handle),
+            status: task.status,
+            source_arn: format!("{}:{}", task.source_namespace, task.source),
+            destination_arn: task
+                .destination_namespace
+                .zip(task.destination)
+                .map(|(ns, queue)| format!("{ns}:{queue}")),
+            max_number_of_messages_per_second: task.max_per_second,
+            approximate_number_of_messages_moved: task.moved,
+            approximate_number_of_messages_to_move: task.to_move,
+            failure_reason: task.failure_reason,
+            started_timestamp: task.started_at * 1000,
+        })
+        .collect();
+
+    Ok(SqsResponse::ListMessageMoveTasks(
+        ListMessageMoveTasksResponse { results },
+    ))
+}
+
 #[instrument(skip(service, identity))]
 async fn get_queue_url(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: GetQueueUrlRequest,
 ) -> Result<SqsResponse, Error> {
     let namespace_id = service
@@ -337,10 +606,10 @@ async fn get_queue_url(
         .await?
         .ok_or_else(|| Error::queue_not_found(&request.queue_name, &namespace.0))?;
 
-    let url = queue_url(service.config().host(), &request.queue_name, &namespace.0)?;
+    let url = QueueUrl::new(base.as_url(), &namespace.0, request.queue_name)?;
 
     Ok(SqsResponse::GetQueueUrl(GetQueueUrlResponse {
-        queue_url: url,
+        queue_url: url.into(),
     }))
 }
 
@@ -349,6 +618,7 @@ async fn create_queue(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: CreateQueueRequest,
 ) -> Result<SqsResponse, Error> {
     let namespace_id = service
@@ -370,10 +640,10 @@ async fn create_queue(
         )
         .await?;
 
-    let url = queue_url(service.config().host(), &request.queue_name, &namespace.0)?;
+    let url = QueueUrl::new(base.as_url(), &namespace.0, request.queue_name)?;
 
     Ok(SqsResponse::CreateQueue(CreateQueueResponse {
-        queue_url: url,
+        queue_url: url.into(),
     }))
 }
 
@@ -382,17 +652,11 @@ async fn set_queue_attributes(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: SetQueueAttributesRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -404,7 +668,7 @@ async fn set_queue_attributes(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
     service
@@ -421,17 +685,11 @@ async fn get_queue_attributes(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: GetQueueAttributesRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -443,7 +701,7 @@ async fn get_queue_attributes(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
     let attributes = service
@@ -464,18 +722,12 @@ async fn get_queue_attributes(
 async fn purge_queue(
     service: Data<crate::service::Service>,
     identity: Identity,
-    _namespace: AuthorizedNamespace,
+    namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: PurgeQueueRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -486,30 +738,29 @@ async fn purge_queue(
         .check_user_access(&identity, ns_id, service.db())
         .await?;
 
-    let success = service
+    if namespace_name != namespace.0 {
+        return Err(service.namespace_access_denied(namespace_name));
+    }
+
+    service
         .purge_queue(namespace_name, queue_name, identity)
-        .await
-        .is_ok();
+        .await?;
 
-    Ok(SqsResponse::PurgeQueue(PurgeQueueResponse { success }))
+    Ok(SqsResponse::PurgeQueue(PurgeQueueResponse {
+        success: true,
+    }))
 }
 
 #[instrument(skip(service, identity))]
 async fn delete_queue(
     service: Data<crate::service::Service>,
     identity: Identity,
-    _namespace: AuthorizedNamespace,
+    namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: DeleteQueueRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -520,6 +771,10 @@ async fn delete_queue(
         .check_user_access(&identity, ns_id, service.db())
         .await?;
 
+    if namespace_name != namespace.0 {
+        return Err(service.namespace_access_denied(namespace_name));
+    }
+
     service
         .delete_queue(namespace_name, queue_name, identity)
         .await?;
@@ -532,17 +787,11 @@ async fn list_queue_tags(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: types::list_queue_tags::ListQueueTagsRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     let ns_id = service
         .get_namespace_id(namespace_name, service.db())
@@ -554,7 +803,7 @@ async fn list_queue_tags(
         .await?;
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
     let tags = service
@@ -571,20 +820,14 @@ async fn tag_queue(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: types::tag_queue::TagQueueRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
     service
@@ -599,20 +842,14 @@ async fn untag_queue(
     service: Data<crate::service::Service>,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
     request: types::untag_queue::UntagQueueRequest,
 ) -> Result<SqsResponse, Error> {
-    let mut path = request
-        .queue_url
-        .path_segments()
-        .ok_or_else(|| Error::missing_parameter("queue name"))?;
-
-    let (queue_name, namespace_name) = path
-        .next_back()
-        .and_then(|queue_name| path.next_back().map(|ns_name| (queue_name, ns_name)))
-        .ok_or_else(|| Error::missing_parameter("namespace name"))?;
+    let queue_url = base.parse(&request.queue_url)?;
+    let (namespace_name, queue_name) = (queue_url.namespace(), queue_url.queue());
 
     if namespace_name != namespace.0 {
-        return Err(Error::Unauthorized);
+        return Err(service.namespace_access_denied(namespace_name));
     }
 
     service
@@ -624,224 +861,112 @@ async fn untag_queue(
     ))
 }
 
-#[post("")]
 pub async fn sqs_service(
     service: Data<crate::service::Service>,
     method: Method,
+    req: HttpRequest,
     payload: actix_web::web::Payload,
-    // payload: actix_web::web::Bytes,
     identity: Identity,
     namespace: AuthorizedNamespace,
+    base: BaseUrl,
 ) -> Result<impl Responder, Error> {
-    let stream = StreamReader::new(payload.map_err(Box::new(move |e| {
-        std::io::Error::new(std::io::ErrorKind::Other, e)
-    }) as Box<dyn FnMut(_) -> _>));
+    let body = body::read(req.headers(), payload, service.config().max_request_body()).await?;
+
+    // A request with a Content-MD5 header is checked before it is decoded.
+    if let Some(content_md5) = req.headers().get(checksum::CONTENT_MD5) {
+        checksum::verify_content_md5(content_md5, &body)?;
+    }
 
-    let stream = FramedRead::new(stream, BytesCodec::new());
+    let request = body::parse(&body)?;
+
+    if let Some(resource) = audit_resource(&request) {
+        req.extensions_mut().insert(resource);
+    }
 
     let res = match method {
-        Method::DeleteMessageBatch => todo!(),
-        Method::SetQueueAttributes => {
-            set_queue_attributes(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+        Method::DeleteMessageBatch => {
+            delete_message_batch(service, identity, namespace, base, decode(request)?).await?
         }
-        Method::TagQueue => {
-            tag_queue(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+        Method::SetQueueAttributes => {
+            set_queue_attributes(service, identity, namespace, base, decode(request)?).await?
         }
+        Method::TagQueue => tag_queue(service, identity, namespace, base, decode(request)?).await?,
         Method::UntagQueue => {
-            untag_queue(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            untag_queue(service, identity, namespace, base, decode(request)?).await?
         }
         Method::ListQueueTags => {
-            list_queue_tags(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            list_queue_tags(service, identity, namespace, base, decode(request)?).await?
         }
         Method::DeleteQueue => {
-            delete_queue(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            delete_queue(service, identity, namespace, base, decode(request)?).await?
         }
         Method::SendMessage => {
-            send_message(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            send_message(service, identity, namespace, base, decode(request)?).await?
         }
         Method::SendMessageBatch => {
-            send_message_batch(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            send_message_batch(service, identity, namespace, base, decode(request)?).await?
         }
         Method::ReceiveMessage => {
-            receive_message(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            receive_message(service, identity, namespace, base, decode(request)?).await?
         }
         Method::DeleteMessage => {
-            delete_message(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            delete_message(service, identity, namespace, base, decode(request)?).await?
+        }
+        Method::ChangeMessageVisibility => {
+            change_message_visibility(service, identity, namespace, base, decode(request)?).await?
+        }
+        Method::ListDeadLetterSourceQueues => {
+            list_dead_letter_source_queues(service, identity, namespace, base, decode(request)?)
+                .await?
+        }
+        Method::StartMessageMoveTask => {
+            start_message_move_task(service, identity, namespace, decode(request)?).await?
+        }
+        Method::CancelMessageMoveTask => {
+            cancel_message_move_task(service, identity, namespace, decode(request)?).await?
+        }
+        Method::ListMessageMoveTasks => {
+            list_message_move_tasks(service, identity, namespace, decode(request)?).await?
         }
         Method::ListQueues => {
-            list_queues(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            list_queues(service, identity, namespace, base, decode(request)?).await?
         }
         Method::GetQueueUrl => {
-            get_queue_url(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            get_queue_url(service, identity, namespace, base, decode(request)?).await?
         }
         Method::CreateQueue => {
-            create_queue(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            create_queue(service, identity, namespace, base, decode(request)?).await?
         }
         Method::GetQueueAttributes => {
-            get_queue_attributes(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            get_queue_attributes(service, identity, namespace, base, decode(request)?).await?
         }
         Method::PurgeQueue => {
-            purge_queue(
-                service,
-                identity,
-                namespace,
-                SymmetricallyFramed::new(stream, SymmetricalJson::default())
-                    .next()
-                    .await
-                    .transpose()
-                    .map_err(|e| Error::internal(e))?
-                    .ok_or_else(|| Error::missing_parameter("missing request body"))?,
-            )
-            .await?
+            purge_queue(service, identity, namespace, base, decode(request)?).await?
         }
     };
 
     Ok(actix_web::web::Json(res))
 }
 
+/// Names what a request acts on in the audit log: its queue, or its message move task.
+fn audit_resource(request: &serde_json::Value) -> Option<AuditResource> {
+    if let Some(queue_url) = request.get("QueueUrl").and_then(|url| url.as_str()) {
+        let path = url::Url::parse(queue_url)
+            .map(|url| url.path().to_owned())
+            .unwrap_or_else(|_| queue_url.to_owned());
+        return Some(AuditResource(path));
+    }
+
+    ["QueueName", "SourceArn", "TaskHandle"]
+        .into_iter()
+        .find_map(|field| request.get(field).and_then(|value| value.as_str()))
+        .map(|resource| AuditResource(resource.to_owned()))
+}
+
 pub fn service() -> Scope {
-    actix_web::web::scope("/sqs").service(sqs_service)
+    // Requests go to the SQS endpoint, except that query protocol clients may send them to the URL
+    // of the queue.
+    actix_web::web::scope("/sqs")
+        .route("", actix_web::web::post().to(sqs_service))
+        .route("/{queue_path:.*}", actix_web::web::post().to(sqs_service))
 }
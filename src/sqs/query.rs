@@ -0,0 +1,518 @@
+//! The AWS query protocol, which older SDKs and tools speak instead of JSON.
+//!
+//! Clients like boto2, and the AWS CLI from before SQS supported JSON, don't send an
+//! `X-Amz-Target` header but a form, `Action=SendMessage&QueueUrl=...&MessageBody=...`, either to
+//! the SQS endpoint or to the URL of the queue, and expect XML back. [`SqsApi`] tells these
+//! requests apart by their content type, turns the form into the JSON request the handlers take
+//! with [`parse`], and their JSON response, or error, into XML with [`render`] and
+//! [`render_error`].
+//!
+//! Lists are flattened with 1-based indices, as in `AttributeName.1=All`, and maps are lists of
+//! entries, as in `Attribute.1.Name=DelaySeconds&Attribute.1.Value=5`.
+//!
+//! [`SqsApi`]: super::service::SqsApi
+
+use std::{borrow::Cow, collections::BTreeMap, fmt::Write as _, str::FromStr};
+
+use base64::Engine as _;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{Error, ErrorBody},
+    version::VERSION_PATH,
+};
+
+use super::method::Method;
+
+/// Content type of query protocol requests.
+pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Content type of query protocol responses.
+pub const XML_CONTENT_TYPE: &str = "text/xml";
+
+/// XML namespace of SQS responses.
+const XML_NAMESPACE: &str = "http://queue.amazonaws.com/doc/2012-11-05/";
+
+/// Parameters sent as strings that the JSON requests take as numbers.
+const NUMBER_PARAMETERS: [&str; 6] = [
+    "DelaySeconds",
+    "MaxNumberOfMessages",
+    "MaxNumberOfMessagesPerSecond",
+    "MaxResults",
+    "VisibilityTimeout",
+    "WaitTimeSeconds",
+];
+
+/// Queue attributes that SetQueueAttributes, unlike CreateQueue, takes as numbers.
+const NUMBER_QUEUE_ATTRIBUTES: [&str; 7] = [
+    "CompressionThreshold",
+    "DelaySeconds",
+    "MaxConcurrentReceives",
+    "MaximumMessageSize",
+    "MessageRetentionPeriod",
+    "ReceiveMessageWaitTimeSeconds",
+    "VisibilityTimeout",
+];
+
+/// A flattened list or map of the query protocol.
+struct Collection {
+    /// Name of each element, in requests and responses
+    element: &'static str,
+    /// Name of the field of the JSON request or response
+    field: &'static str,
+    /// Names of the key and value of map entries, or `None` for lists
+    entry: Option<(&'static str, &'static str)>,
+}
+
+const COLLECTIONS: [Collection; 10] = [
+    Collection {
+        element: "Attribute",
+        field: "Attributes",
+        entry: Some(("Name", "Value")),
+    },
+    Collection {
+        element: "MessageAttribute",
+        field: "MessageAttributes",
+        entry: Some(("Name", "Value")),
+    },
+    Collection {
+        element: "Tag",
+        field: "Tags",
+        entry: Some(("Key", "Value")),
+    },
+    Collection {
+        element: "AttributeName",
+        field: "AttributeNames",
+        entry: None,
+    },
+    Collection {
+        element: "MessageAttributeName",
+        field: "MessageAttributeNames",
+        entry: None,
+    },
+    Collection {
+        element: "MessageSystemAttributeName",
+        field: "MessageSystemAttributeNames",
+        entry: None,
+    },
+    Collection {
+        element: "TagKey",
+        field: "TagKeys",
+        entry: None,
+    },
+    Collection {
+        element: "SendMessageBatchRequestEntry",
+        field: "Entries",
+        entry: None,
+    },
+    Collection {
+        element: "DeleteMessageBatchRequestEntry",
+        field: "Entries",
+        entry: None,
+    },
+    Collection {
+        element: "ChangeMessageVisibilityBatchRequestEntry",
+        field: "Entries",
+        entry: None,
+    },
+];
+
+/// A parameter, or the parameters below it, by the parts of their names.
+#[derive(Debug, Default)]
+struct Node {
+    value: Option<String>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, name: &str, value: String) {
+        let node = name.split('.').fold(self, |node, part| {
+            node.children.entry(part.to_owned()).or_default()
+        });
+        node.value = Some(value);
+    }
+}
+
+/// Parses the form body of a query protocol request.
+///
+/// # Returns
+/// The method named by its `Action` parameter, and the other parameters as the JSON request of
+/// the method
+///
+/// # Errors
+/// * `Error::MissingParameter` - If there is no `Action`
+/// * `Error::InvalidMethod` - If the action isn't supported
+/// * `Error::InvalidParameter` - If a list or number parameter is malformed
+pub fn parse(body: &[u8]) -> Result<(Method, Value), Error> {
+    let mut action = None;
+    let mut params = Node::default();
+    for (name, value) in url::form_urlencoded::parse(body) {
+        match name.as_ref() {
+            "Action" => action = Some(value.into_owned()),
+            "Version" => {}
+            _ => params.insert(&name, value.into_owned()),
+        }
+    }
+
+    let action = action.ok_or_else(|| Error::missing_parameter("Action"))?;
+    let method = Method::from_str(&action).map_err(|_| Error::InvalidMethod {
+        message: format!(
+            "unsupported action {action}, see GET {VERSION_PATH} for the supported operations"
+        ),
+    })?;
+
+    Ok((method, Value::Object(object_to_json(&params, method)?)))
+}
+
+fn object_to_json(node: &Node, method: Method) -> Result<Map<String, Value>, Error> {
+    let mut object = Map::new();
+    for (name, child) in &node.children {
+        match COLLECTIONS.iter().find(|c| c.element == name) {
+            Some(collection) => {
+                let value = collection_to_json(collection, child, method)?;
+                object.insert(collection.field.to_owned(), value);
+            }
+            None => {
+                object.insert(name.clone(), to_json(name, child, method)?);
+            }
+        }
+    }
+
+    Ok(object)
+}
+
+fn to_json(name: &str, node: &Node, method: Method) -> Result<Value, Error> {
+    match &node.value {
+        Some(value) if node.children.is_empty() => scalar_to_json(name, value),
+        _ => object_to_json(node, method).map(Value::Object),
+    }
+}
+
+fn scalar_to_json(name: &str, value: &str) -> Result<Value, Error> {
+    if NUMBER_PARAMETERS.contains(&name) {
+        return number_to_json(name, value);
+    }
+    if name == "BinaryValue" {
+        let bytes = base64::prelude::BASE64_STANDARD
+            .decode(value)
+            .map_err(|_| Error::invalid_parameter("BinaryValue must be base64-encoded"))?;
+        return Ok(Value::from(bytes));
+    }
+
+    Ok(Value::String(value.to_owned()))
+}
+
+fn number_to_json(name: &str, value: &str) -> Result<Value, Error> {
+    value
+        .parse::<u64>()
+        .map(Value::from)
+        .map_err(|_| Error::invalid_parameter(format!("{name} must be a number")))
+}
+
+fn collection_to_json(
+    collection: &Collection,
+    node: &Node,
+    method: Method,
+) -> Result<Value, Error> {
+    let mut elements = node
+        .children
+        .iter()
+        .map(|(index, element)| match index.parse::<u32>() {
+            Ok(index) if index > 0 => Ok((index, element)),
+            _ => Err(Error::invalid_parameter(format!(
+                "{}.{index}: list indices must be positive numbers",
+                collection.element
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    elements.sort_by_key(|(index, _)| *index);
+
+    let Some((key, value)) = collection.entry else {
+        return elements
+            .into_iter()
+            .map(|(_, element)| to_json(collection.element, element, method))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array);
+    };
+
+    let mut map = Map::new();
+    for (index, element) in elements {
+        let missing =
+            |part: &str| Error::missing_parameter(format!("{}.{index}.{part}", collection.element));
+        let name = element
+            .children
+            .get(key)
+            .and_then(|node| node.value.clone())
+            .ok_or_else(|| missing(key))?;
+        let value = element.children.get(value).ok_or_else(|| missing(value))?;
+
+        let value = match &value.value {
+            Some(number)
+                if method == Method::SetQueueAttributes
+                    && NUMBER_QUEUE_ATTRIBUTES.contains(&name.as_str()) =>
+            {
+                number_to_json(&name, number)?
+            }
+            _ => to_json(collection.element, value, method)?,
+        };
+        map.insert(name, value);
+    }
+
+    Ok(Value::Object(map))
+}
+
+/// Renders the JSON response of a method as the XML response of the query protocol.
+pub fn render(method: Method, response: &Value, request_id: Option<&str>) -> String {
+    let action = method.as_ref();
+    let mut xml = format!(r#"<?xml version="1.0"?><{action}Response xmlns="{XML_NAMESPACE}">"#);
+
+    if let Some(result) = response.as_object().filter(|result| !result.is_empty()) {
+        let _ = write!(xml, "<{action}Result>");
+        render_fields(&mut xml, action, result);
+        let _ = write!(xml, "</{action}Result>");
+    }
+
+    xml.push_str("<ResponseMetadata>");
+    render_request_id(&mut xml, request_id);
+    let _ = write!(xml, "</ResponseMetadata></{action}Response>");
+
+    xml
+}
+
+/// Renders an error as the XML error response of the query protocol.
+pub fn render_error(error: &Error) -> String {
+    let ErrorBody {
+        message,
+        request_id,
+        ..
+    } = error.body();
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?><ErrorResponse><Error><Type>{}</Type><Code>{}</Code>\
+         <Message>{}</Message></Error>",
+        super::error::fault(error),
+        super::error::query_code(error),
+        escape(&message)
+    );
+    render_request_id(&mut xml, request_id.as_deref());
+    xml.push_str("</ErrorResponse>");
+
+    xml
+}
+
+fn render_request_id(xml: &mut String, request_id: Option<&str>) {
+    if let Some(request_id) = request_id {
+        let _ = write!(xml, "<RequestId>{}</RequestId>", escape(request_id));
+    }
+}
+
+fn render_fields(xml: &mut String, action: &str, fields: &Map<String, Value>) {
+    for (name, value) in fields {
+        render_field(xml, action, name, value);
+    }
+}
+
+fn render_field(xml: &mut String, action: &str, name: &str, value: &Value) {
+    match value {
+        Value::Null => {}
+        // Binary values are lists of bytes in JSON, and base64-encoded in XML.
+        Value::Array(bytes) if name == "BinaryValue" => {
+            let bytes = bytes
+                .iter()
+                .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                .collect::<Vec<_>>();
+            let value = base64::prelude::BASE64_STANDARD.encode(bytes);
+            let _ = write!(xml, "<{name}>{value}</{name}>");
+        }
+        Value::Array(elements) => {
+            // Lists are flattened into their elements, named after the list.
+            let element = match name {
+                "Messages" => Cow::Borrowed("Message"),
+                "QueueUrls" | "queueUrls" => Cow::Borrowed("QueueUrl"),
+                "Successful" | "Results" => Cow::Owned(format!("{action}ResultEntry")),
+                "Failed" => Cow::Borrowed("BatchResultErrorEntry"),
+                _ => Cow::Borrowed(name),
+            };
+            for value in elements {
+                render_field(xml, action, &element, value);
+            }
+        }
+        Value::Object(fields) => {
+            let map = COLLECTIONS
+                .iter()
+                .find(|c| c.field == name)
+                .and_then(|c| c.entry.map(|entry| (c.element, entry)));
+            match map {
+                Some((element, (key, value))) => {
+                    for (name, field) in fields {
+                        let _ = write!(xml, "<{element}><{key}>{}</{key}>", escape(name));
+                        render_field(xml, action, value, field);
+                        let _ = write!(xml, "</{element}>");
+                    }
+                }
+                None => {
+                    let _ = write!(xml, "<{name}>");
+                    render_fields(xml, action, fields);
+                    let _ = write!(xml, "</{name}>");
+                }
+            }
+        }
+        Value::String(value) => {
+            let _ = write!(xml, "<{name}>{}</{name}>", escape(value));
+        }
+        Value::Number(_) | Value::Bool(_) => {
+            let _ = write!(xml, "<{name}>{value}</{name}>");
+        }
+    }
+}
+
+fn escape(value: &str) -> Cow<'_, str> {
+    if !value.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len() + 16);
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let body = "Action=SendMessageBatch&Version=2012-11-05&QueueUrl=http%3A%2F%2Fq\
+            &SendMessageBatchRequestEntry.2.Id=b&SendMessageBatchRequestEntry.2.MessageBody=two\
+            &SendMessageBatchRequestEntry.1.Id=a&SendMessageBatchRequestEntry.1.MessageBody=one\
+            &SendMessageBatchRequestEntry.1.DelaySeconds=5\
+            &SendMessageBatchRequestEntry.1.MessageAttribute.1.Name=kind\
+            &SendMessageBatchRequestEntry.1.MessageAttribute.1.Value.DataType=String\
+            &SendMessageBatchRequestEntry.1.MessageAttribute.1.Value.StringValue=x\
+            &SendMessageBatchRequestEntry.1.MessageAttribute.2.Name=raw\
+            &SendMessageBatchRequestEntry.1.MessageAttribute.2.Value.DataType=Binary\
+            &SendMessageBatchRequestEntry.1.MessageAttribute.2.Value.BinaryValue=AQI%3D";
+        let (method, request) = parse(body.as_bytes()).unwrap();
+
+        assert_eq!(method, Method::SendMessageBatch);
+        assert_eq!(
+            request,
+            json!({
+                "QueueUrl": "http://q",
+                "Entries": [
+                    {
+                        "Id": "a",
+                        "MessageBody": "one",
+                        "DelaySeconds": 5,
+                        "MessageAttributes": {
+                            "kind": { "DataType": "String", "StringValue": "x" },
+                            "raw": { "DataType": "Binary", "BinaryValue": [1, 2] },
+                        },
+                    },
+                    { "Id": "b", "MessageBody": "two" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let body = "Attribute.1.Name=DelaySeconds&Attribute.1.Value=5\
+            &Attribute.2.Name=RedrivePolicy&Attribute.2.Value=%7B%7D&Tag.1.Key=team&Tag.1.Value=a";
+
+        let (_, request) = parse(format!("Action=CreateQueue&{body}").as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "Attributes": { "DelaySeconds": "5", "RedrivePolicy": "{}" },
+                "Tags": { "team": "a" },
+            })
+        );
+
+        let (_, request) = parse(format!("Action=SetQueueAttributes&{body}").as_bytes()).unwrap();
+        assert_eq!(request["Attributes"]["DelaySeconds"], json!(5));
+
+        let (_, request) =
+            parse(b"Action=ReceiveMessage&AttributeName.10=b&AttributeName.2=a").unwrap();
+        assert_eq!(request, json!({ "AttributeNames": ["a", "b"] }));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(
+            parse(b"QueueName=q"),
+            Err(Error::MissingParameter { .. })
+        ));
+        assert!(matches!(
+            parse(b"Action=AddPermission"),
+            Err(Error::InvalidMethod { .. })
+        ));
+        for body in [
+            "Action=ReceiveMessage&WaitTimeSeconds=soon",
+            "Action=ReceiveMessage&AttributeName.0=All",
+            "Action=ReceiveMessage&AttributeName.first=All",
+            "Action=CreateQueue&Attribute.1.Value=5",
+        ] {
+            assert!(parse(body.as_bytes()).is_err(), "{body}");
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let response = json!({
+            "Successful": [{ "Id": "a", "MessageId": "1" }],
+            "Failed": [{ "Id": "b", "SenderFault": true, "Code": "Oops", "Message": null }],
+        });
+        assert_eq!(
+            render(Method::SendMessageBatch, &response, Some("req")),
+            "<?xml version=\"1.0\"?><SendMessageBatchResponse \
+             xmlns=\"http://queue.amazonaws.com/doc/2012-11-05/\"><SendMessageBatchResult>\
+             <BatchResultErrorEntry><Code>Oops</Code><Id>b</Id><SenderFault>true</SenderFault>\
+             </BatchResultErrorEntry><SendMessageBatchResultEntry><Id>a</Id>\
+             <MessageId>1</MessageId></SendMessageBatchResultEntry></SendMessageBatchResult>\
+             <ResponseMetadata><RequestId>req</RequestId></ResponseMetadata>\
+             </SendMessageBatchResponse>"
+        );
+
+        let response = json!({
+            "Messages": [{
+                "Body": "<a & b>",
+                "Attributes": { "SentTimestamp": "1" },
+                "MessageAttributes": { "raw": { "DataType": "Binary", "BinaryValue": [1, 2] } },
+            }],
+        });
+        let xml = render(Method::ReceiveMessage, &response, None);
+        assert!(xml.contains(
+            "<Message><Attribute><Name>SentTimestamp</Name><Value>1</Value></Attribute>\
+             <Body>&lt;a &amp; b&gt;</Body><MessageAttribute><Name>raw</Name><Value>\
+             <BinaryValue>AQI=</BinaryValue><DataType>Binary</DataType></Value>\
+             </MessageAttribute></Message>"
+        ));
+
+        // Results with no fields are left out.
+        let xml = render(Method::DeleteQueue, &json!({}), Some("req"));
+        assert!(!xml.contains("DeleteQueueResult"));
+    }
+
+    #[test]
+    fn test_render_error() {
+        let xml = render_error(&Error::missing_parameter("Action"));
+        assert!(xml.starts_with(
+            "<?xml version=\"1.0\"?><ErrorResponse><Error><Type>Sender</Type>\
+             <Code>MissingParameter</Code>"
+        ));
+        assert!(render_error(&Error::opaque()).contains("<Type>Receiver</Type>"));
+    }
+}
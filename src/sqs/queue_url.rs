@@ -0,0 +1,475 @@
+//! Queue URL parsing and generation.
+//!
+//! SQS identifies queues by URL rather than by name. NerveMQ queue URLs have the form
+//! `{host}/sqs/{namespace}/{queue}`, where `host` is the configured server URL.
+//!
+//! [`QueueUrl`] is the single place where these URLs are built and taken apart, so that every
+//! handler applies the same validation rules:
+//!
+//! - The URL must point at this server (host and port must match the configured host)
+//! - The path must end in exactly `{namespace}/{queue}`, optionally preceded by `sqs`
+//! - Neither the namespace nor the queue segment may be empty
+//!
+//! A queue URL may also select a [consumer group](crate::consumer_group) with the
+//! `ConsumerGroup` query parameter.
+
+use actix_web::{web::Data, FromRequest, HttpRequest};
+use url::Url;
+
+use crate::{
+    consumer_group::CONSUMER_GROUP_PARAMETER, error::Error, proxy::ClientInfo, service::Service,
+};
+
+/// Path segment that prefixes all SQS queue paths.
+pub const SQS_PATH_SEGMENT: &str = "sqs";
+
+/// A validated queue URL, split into its namespace and queue components.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueUrl {
+    url: Url,
+    namespace: String,
+    queue: String,
+    consumer_group: Option<String>,
+}
+
+impl QueueUrl {
+    /// Builds the canonical URL for a queue, relative to the given host.
+    pub fn new(
+        host: &Url,
+        namespace: impl Into<String>,
+        queue: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let namespace = namespace.into();
+        let queue = queue.into();
+
+        if namespace.is_empty() {
+            return Err(Error::missing_parameter("namespace name"));
+        }
+
+        if queue.is_empty() {
+            return Err(Error::missing_parameter("queue name"));
+        }
+
+        let mut url = host.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::opaque())?
+            .pop_if_empty()
+            .push(SQS_PATH_SEGMENT)
+            .push(&namespace)
+            .push(&queue);
+
+        Ok(Self {
+            url,
+            namespace,
+            queue,
+            consumer_group: None,
+        })
+    }
+
+    /// Parses and validates a queue URL provided by a client.
+    ///
+    /// # Arguments
+    /// * `url` - The queue URL from the request
+    /// * `host` - The configured server host, which the URL must point at
+    ///
+    /// # Errors
+    /// * `Error::InvalidParameter` - If the URL points at a different host, or has an unexpected path
+    /// * `Error::MissingParameter` - If the namespace or queue segment is missing
+    /// * `Error::InvalidParameter` - If the consumer group parameter is empty
+    pub fn parse(url: &Url, host: &Url) -> Result<Self, Error> {
+        if !same_origin(url, host) {
+            return Err(Error::invalid_parameter(format!(
+                "QueueUrl: {url} does not belong to this server"
+            )));
+        }
+
+        let segments = url
+            .path_segments()
+            .ok_or_else(|| Error::invalid_parameter(format!("QueueUrl: {url} has no path")))?
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+
+        // The configured host may itself include a path prefix (e.g. when served behind a proxy
+        // under `/nervemq`), which must be present before the queue path.
+        let prefix = host
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let Some(rest) = segments.strip_prefix(prefix.as_slice()) else {
+            return Err(Error::invalid_parameter(format!(
+                "QueueUrl: {url} does not belong to this server"
+            )));
+        };
+
+        let rest = match rest {
+            [SQS_PATH_SEGMENT, rest @ ..] => rest,
+            rest => rest,
+        };
+
+        let (namespace, queue) = match rest {
+            [namespace, queue] => (*namespace, *queue),
+            [] => return Err(Error::missing_parameter("namespace name")),
+            [_] => return Err(Error::missing_parameter("queue name")),
+            _ => {
+                return Err(Error::invalid_parameter(format!(
+                    "QueueUrl: unexpected path {}",
+                    url.path()
+                )))
+            }
+        };
+
+        let namespace = urlencoding::decode(namespace)
+            .map_err(|e| Error::invalid_parameter(format!("QueueUrl: {e}")))?
+            .into_owned();
+
+        let queue = urlencoding::decode(queue)
+            .map_err(|e| Error::invalid_parameter(format!("QueueUrl: {e}")))?
+            .into_owned();
+
+        let consumer_group = match url
+            .query_pairs()
+            .find(|(key, _)| key == CONSUMER_GROUP_PARAMETER)
+        {
+            Some((_, group)) if group.is_empty() => {
+                return Err(Error::invalid_parameter(format!(
+                    "QueueUrl: {CONSUMER_GROUP_PARAMETER} must not be empty"
+                )))
+            }
+            Some((_, group)) => Some(group.into_owned()),
+            None => None,
+        };
+
+        Ok(Self {
+            url: url.clone(),
+            namespace,
+            queue,
+            consumer_group,
+        })
+    }
+
+    /// The namespace the queue belongs to.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The name of the queue.
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// The consumer group the URL selects, if any.
+    pub fn consumer_group(&self) -> Option<&str> {
+        self.consumer_group.as_deref()
+    }
+
+    /// The full URL of the queue.
+    pub fn as_url(&self) -> &Url {
+        &self.url
+    }
+}
+
+impl From<QueueUrl> for Url {
+    fn from(value: QueueUrl) -> Self {
+        value.url
+    }
+}
+
+/// The base URL that queue URLs returned to the current client are built on.
+///
+/// This is normally the configured host. If the server is also reachable at one of the
+/// configured allowed hosts and the client used it, that host is used instead, so clients get
+/// back URLs they can actually reach. In both cases the scheme is replaced by the one the client
+/// used if the request came through a trusted TLS-terminating proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseUrl {
+    url: Url,
+    /// Every base a client-provided queue URL may be relative to
+    accepted: Vec<Url>,
+}
+
+impl BaseUrl {
+    /// Derives the base URL for a client.
+    ///
+    /// # Arguments
+    /// * `host` - The configured server host
+    /// * `allowed_hosts` - Additional `host[:port]` authorities the server is reachable at
+    /// * `client` - The client, as resolved from the request
+    pub fn new(host: Url, allowed_hosts: &[String], client: &ClientInfo) -> Self {
+        let accepted = std::iter::once(host.clone())
+            .chain(allowed_hosts.iter().filter_map(|allowed| {
+                let replaced = with_authority(&host, allowed);
+                if replaced.is_none() {
+                    tracing::warn!(host = allowed, "Ignoring invalid allowed host");
+                }
+                replaced
+            }))
+            .collect::<Vec<_>>();
+
+        let mut url = client
+            .host
+            .as_deref()
+            .and_then(|requested| with_authority(&host, requested))
+            .and_then(|requested| {
+                accepted
+                    .iter()
+                    .find(|candidate| same_origin(candidate, &requested))
+            })
+            .cloned()
+            .unwrap_or(host);
+
+        if let Some(proto) = &client.proto {
+            // Only fails when switching between special and non-special schemes, in which
+            // case the configured scheme is kept.
+            url.set_scheme(proto).ok();
+        }
+
+        Self { url, accepted }
+    }
+
+    /// The base URL new queue URLs should be built on.
+    pub fn as_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Parses a client-provided queue URL, which may point at any of the accepted hosts.
+    ///
+    /// # Errors
+    /// See [`QueueUrl::parse`]. If the URL matches none of the accepted hosts, the error for the
+    /// configured host is returned.
+    pub fn parse(&self, url: &Url) -> Result<QueueUrl, Error> {
+        let (host, alternatives) = self
+            .accepted
+            .split_first()
+            .expect("configured host is always accepted");
+
+        alternatives
+            .iter()
+            .filter(|alternative| same_origin(url, alternative))
+            .find_map(|alternative| QueueUrl::parse(url, alternative).ok())
+            .map_or_else(|| QueueUrl::parse(url, host), Ok)
+    }
+}
+
+impl FromRequest for BaseUrl {
+    type Error = Error;
+
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let res = req
+            .app_data::<Data<Service>>()
+            .map(|service| {
+                Self::new(
+                    service.config().host(),
+                    service.config().allowed_hosts(),
+                    &ClientInfo::from_http_request(req),
+                )
+            })
+            .ok_or_else(Error::opaque);
+
+        std::future::ready(res)
+    }
+}
+
+/// Replaces the host and port of a URL with a `host[:port]` authority.
+fn with_authority(url: &Url, authority: &str) -> Option<Url> {
+    let parsed = Url::parse(&format!("{}://{authority}", url.scheme())).ok()?;
+
+    if parsed.path() != "/" || !parsed.username().is_empty() {
+        return None;
+    }
+
+    let mut url = url.clone();
+    url.set_host(parsed.host_str()).ok()?;
+    url.set_port(parsed.port()).ok()?;
+
+    Some(url)
+}
+
+/// Checks that two URLs refer to the same server.
+///
+/// The scheme is deliberately ignored, since clients may reach the server through a
+/// TLS-terminating proxy while the configured host uses plain HTTP. For the same reason, two URLs
+/// without an explicit port match even if their schemes' default ports differ.
+fn same_origin(a: &Url, b: &Url) -> bool {
+    let hosts_match = match (a.host_str(), b.host_str()) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => false,
+    };
+
+    hosts_match && (a.port() == b.port() || a.port_or_known_default() == b.port_or_known_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Url {
+        Url::parse("http://localhost:8080").unwrap()
+    }
+
+    #[test]
+    fn test_new_queue_url() {
+        let url = QueueUrl::new(&host(), "ns", "queue").unwrap();
+        assert_eq!(url.as_url().as_str(), "http://localhost:8080/sqs/ns/queue");
+        assert_eq!(url.namespace(), "ns");
+        assert_eq!(url.queue(), "queue");
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let url = QueueUrl::new(&host(), "my ns", "my-queue").unwrap();
+        let parsed = QueueUrl::parse(url.as_url(), &host()).unwrap();
+        assert_eq!(parsed.namespace(), "my ns");
+        assert_eq!(parsed.queue(), "my-queue");
+    }
+
+    #[test]
+    fn test_parse_valid() {
+        let test_cases = vec![
+            ("http://localhost:8080/sqs/ns/queue", "ns", "queue"),
+            ("http://localhost:8080/ns/queue", "ns", "queue"),
+            ("http://localhost:8080/sqs/ns/queue/", "ns", "queue"),
+            ("https://LOCALHOST:8080/sqs/ns/queue", "ns", "queue"),
+        ];
+
+        for (input, namespace, queue) in test_cases {
+            let url = Url::parse(input).unwrap();
+            let parsed = QueueUrl::parse(&url, &host())
+                .unwrap_or_else(|e| panic!("Failed to parse valid url {input}: {e}"));
+            assert_eq!(
+                parsed.namespace(),
+                namespace,
+                "Namespace mismatch for {input}"
+            );
+            assert_eq!(parsed.queue(), queue, "Queue mismatch for {input}");
+        }
+    }
+
+    #[test]
+    fn test_parse_with_host_prefix() {
+        let host = Url::parse("http://example.com/nervemq").unwrap();
+
+        let url = Url::parse("http://example.com/nervemq/sqs/ns/queue").unwrap();
+        let parsed = QueueUrl::parse(&url, &host).unwrap();
+        assert_eq!(parsed.namespace(), "ns");
+        assert_eq!(parsed.queue(), "queue");
+
+        let url = Url::parse("http://example.com/sqs/ns/queue").unwrap();
+        assert!(QueueUrl::parse(&url, &host).is_err());
+    }
+
+    #[test]
+    fn test_parse_consumer_group() {
+        let url = Url::parse("http://localhost:8080/sqs/ns/queue?ConsumerGroup=a%20b").unwrap();
+        let parsed = QueueUrl::parse(&url, &host()).unwrap();
+        assert_eq!(parsed.queue(), "queue");
+        assert_eq!(parsed.consumer_group(), Some("a b"));
+
+        let url = Url::parse("http://localhost:8080/sqs/ns/queue").unwrap();
+        assert_eq!(
+            QueueUrl::parse(&url, &host()).unwrap().consumer_group(),
+            None
+        );
+
+        let url = Url::parse("http://localhost:8080/sqs/ns/queue?ConsumerGroup=").unwrap();
+        assert!(QueueUrl::parse(&url, &host()).is_err());
+    }
+
+    fn client(host: Option<&str>, proto: Option<&str>) -> ClientInfo {
+        ClientInfo {
+            ip: None,
+            proto: proto.map(str::to_owned),
+            host: host.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_base_url_forwarded_proto() {
+        let base = BaseUrl::new(host(), &[], &client(None, Some("https")));
+        let url = QueueUrl::new(base.as_url(), "ns", "queue").unwrap();
+        assert_eq!(url.as_url().as_str(), "https://localhost:8080/sqs/ns/queue");
+
+        let base = BaseUrl::new(host(), &[], &ClientInfo::default());
+        assert_eq!(base.as_url(), &host());
+    }
+
+    #[test]
+    fn test_base_url_forwarded_proto_default_port() {
+        let host = Url::parse("http://mq.example.com").unwrap();
+        let base = BaseUrl::new(host.clone(), &[], &client(None, Some("https")));
+        let url = QueueUrl::new(base.as_url(), "ns", "queue").unwrap();
+        assert_eq!(url.as_url().as_str(), "https://mq.example.com/sqs/ns/queue");
+
+        // The URL handed out over HTTPS is accepted when the client sends it back.
+        let parsed = base.parse(url.as_url()).unwrap();
+        assert_eq!(parsed.namespace(), "ns");
+        assert_eq!(parsed.queue(), "queue");
+
+        // An explicit port still has to match.
+        let url = Url::parse("https://mq.example.com:8443/sqs/ns/queue").unwrap();
+        assert!(base.parse(&url).is_err());
+
+        // So does the configured one, even if a proxy switched the scheme.
+        let host = Url::parse("http://mq.example.com:443").unwrap();
+        let base = BaseUrl::new(host, &[], &client(None, Some("https")));
+        let url = QueueUrl::new(base.as_url(), "ns", "queue").unwrap();
+        assert!(base.parse(url.as_url()).is_ok());
+    }
+
+    #[test]
+    fn test_base_url_from_allowed_host() {
+        let allowed = vec!["mq.internal".to_owned(), "10.0.0.5:9000".to_owned()];
+
+        let base = BaseUrl::new(host(), &allowed, &client(Some("MQ.internal"), None));
+        assert_eq!(base.as_url().as_str(), "http://mq.internal/");
+
+        let base = BaseUrl::new(host(), &allowed, &client(Some("10.0.0.5:9000"), None));
+        assert_eq!(base.as_url().as_str(), "http://10.0.0.5:9000/");
+
+        // Hosts that aren't allowed fall back to the configured host.
+        let base = BaseUrl::new(host(), &allowed, &client(Some("evil.example.com"), None));
+        assert_eq!(base.as_url(), &host());
+
+        let base = BaseUrl::new(host(), &allowed, &client(Some("10.0.0.5:9001"), None));
+        assert_eq!(base.as_url(), &host());
+    }
+
+    #[test]
+    fn test_base_url_parse() {
+        let allowed = vec!["mq.internal".to_owned()];
+        let base = BaseUrl::new(host(), &allowed, &ClientInfo::default());
+
+        for input in [
+            "http://localhost:8080/sqs/ns/queue",
+            "http://mq.internal/sqs/ns/queue",
+        ] {
+            let parsed = base.parse(&Url::parse(input).unwrap()).unwrap();
+            assert_eq!(parsed.namespace(), "ns", "{input}");
+            assert_eq!(parsed.queue(), "queue", "{input}");
+        }
+
+        let url = Url::parse("http://evil.example.com/sqs/ns/queue").unwrap();
+        assert!(base.parse(&url).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let invalid_inputs = vec![
+            "http://example.com:8080/sqs/ns/queue", // Wrong host
+            "http://localhost:9090/sqs/ns/queue",   // Wrong port
+            "http://localhost:8080/sqs/ns",         // Missing queue
+            "http://localhost:8080/sqs",            // Missing namespace and queue
+            "http://localhost:8080/a/b/c/d",        // Too many segments
+        ];
+
+        for input in invalid_inputs {
+            let url = Url::parse(input).unwrap();
+            assert!(
+                QueueUrl::parse(&url, &host()).is_err(),
+                "Expected error for invalid url: {input}"
+            );
+        }
+    }
+}
@@ -1,24 +1,29 @@
-use std::rc::Rc;
+use std::{pin::Pin, rc::Rc};
 
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    http::header::HeaderName,
-    HttpMessage,
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::header::{self, HeaderName, HeaderValue},
+    web::Bytes,
+    FromRequest as _, HttpMessage, HttpResponse, ResponseError as _,
 };
 
 use crate::error::Error;
 
-use super::method::Method;
+use super::{body, checksum, error, method::Method, query, BaseUrl};
 
+/// Middleware that resolves the SQS method of a request, from its `X-Amz-Target` header or, for
+/// query protocol requests, its `Action` parameter, see [`query`].
 pub struct SqsApi;
 
 impl<S, B> Transform<S, ServiceRequest> for SqsApi
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
 
     type Error = actix_web::Error;
 
@@ -43,30 +48,176 @@ impl<S, B> Service<ServiceRequest> for SqsApiMiddleware<S>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = actix_web::Error;
-    type Future =
-        std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
 
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = Rc::clone(&self.service);
         Box::pin(async move {
+            if is_query_request(&req) {
+                return call_query(service, req).await;
+            }
+
             let method = req
                 .headers()
                 .get(HeaderName::from_static("x-amz-target"))
                 .ok_or_else(|| Error::InvalidHeader {
                     header: "X-Amz-Target".to_owned(),
                 })
-                .and_then(|header| header.to_str().map_err(|e| Error::internal(e)))
-                .and_then(Method::parse)?;
+                .and_then(|header| header.to_str().map_err(Error::internal))
+                .and_then(Method::parse);
+
+            let method = match method {
+                Ok(method) => method,
+                Err(e) => {
+                    let (query_error, body) = (error::query_error(&e), error::json_body(&e));
+                    let mut res = HttpResponse::from_error(e).set_body(body);
+                    res.headers_mut().insert(error::QUERY_ERROR, query_error);
+                    return Ok(req.into_response(res).map_into_right_body());
+                }
+            };
 
             req.extensions_mut().insert(method);
 
-            service.call(req).await
+            let res = service.call(req).await.map_err(json_error)?;
+
+            // The error stays attached to the response, for the middleware further out.
+            let Some(error) = res.response().error().and_then(|e| e.as_error::<Error>()) else {
+                return Ok(res.map_into_left_body());
+            };
+            let (query_error, body) = (error::query_error(error), error::json_body(error));
+
+            Ok(res
+                .map_body(|head, _| {
+                    head.headers.insert(error::QUERY_ERROR, query_error);
+                    body
+                })
+                .map_into_right_body())
         })
     }
 }
+
+/// Passes on errors of the middleware further in with their SQS JSON response.
+fn json_error(e: actix_web::Error) -> actix_web::Error {
+    match e.as_error::<Error>() {
+        Some(error) => {
+            InternalError::from_response(e.to_string(), error::json_response(error)).into()
+        }
+        None => e,
+    }
+}
+
+/// Whether a request is made with the query protocol rather than JSON.
+fn is_query_request(req: &ServiceRequest) -> bool {
+    !req.headers().contains_key("x-amz-target") && req.content_type() == query::FORM_CONTENT_TYPE
+}
+
+/// Handles a query protocol request, by passing it on as JSON and rendering the response, or
+/// error, as XML.
+async fn call_query<S, B>(
+    service: Rc<S>,
+    mut req: ServiceRequest,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let method = match to_json_request(&mut req).await {
+        Ok(method) => method,
+        Err(e) => return Ok(req.into_response(error_response(&e)).map_into_right_body()),
+    };
+
+    let res = match service.call(req).await {
+        Ok(res) => res,
+        // Errors of the middleware further in come without the request, and are passed on with
+        // their XML response.
+        Err(e) => match e.as_error::<Error>() {
+            Some(error) => {
+                return Err(
+                    InternalError::from_response(e.to_string(), error_response(error)).into(),
+                )
+            }
+            None => return Err(e),
+        },
+    };
+    if let Some(error) = res.response().error().and_then(|e| e.as_error::<Error>()) {
+        let error = error_response(error);
+        return Ok(res.into_response(error).map_into_right_body());
+    }
+    if !res.status().is_success() {
+        return Ok(res.map_into_left_body());
+    }
+
+    let (http_req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = actix_web::body::to_bytes(body)
+        .await
+        .map_err(|e| Error::internal(eyre::eyre!("{}", e.into())))?;
+    let response = serde_json::from_slice(&body).map_err(Error::internal)?;
+    let request_id = crate::request_id::RequestId::current();
+    let body = query::render(method, &response, request_id.as_ref().map(|id| id.as_str()));
+
+    Ok(ServiceResponse::new(http_req, xml(res.set_body(BoxBody::new(body)))).map_into_right_body())
+}
+
+/// Replaces the form body of a query protocol request with its JSON request, and inserts its
+/// method.
+///
+/// Query protocol clients may send requests to the URL of the queue instead of the SQS endpoint,
+/// and leave its `QueueUrl` out, which is then taken from the request path.
+async fn to_json_request(req: &mut ServiceRequest) -> Result<Method, Error> {
+    let limit = req
+        .app_data::<actix_web::web::Data<crate::service::Service>>()
+        .expect("Service not found. This is a bug.")
+        .config()
+        .max_request_body();
+    let payload = req.take_payload();
+    let body = body::read(req.headers(), payload, limit).await?;
+
+    // The checksum is of the form, not of the JSON the handler gets.
+    if let Some(content_md5) = req.headers_mut().remove(checksum::CONTENT_MD5).next() {
+        checksum::verify_content_md5(&content_md5, &body)?;
+    }
+
+    let (method, mut request) = query::parse(&body)?;
+
+    let to_queue = !req.match_info().unprocessed().trim_matches('/').is_empty();
+    if let Some(request) = request
+        .as_object_mut()
+        .filter(|request| to_queue && !request.contains_key("QueueUrl"))
+    {
+        let mut url = BaseUrl::extract(req.request()).await?.as_url().clone();
+        url.set_path(&format!(
+            "{}{}",
+            url.path().trim_end_matches('/'),
+            req.path()
+        ));
+        request.insert("QueueUrl".to_owned(), url.to_string().into());
+    }
+
+    let body = Bytes::from(serde_json::to_vec(&request).map_err(Error::internal)?);
+    req.set_payload(Payload::from(body));
+    req.extensions_mut().insert(method);
+
+    Ok(method)
+}
+
+/// Renders an error as the XML error response of the query protocol.
+fn error_response(error: &Error) -> HttpResponse {
+    xml(error
+        .error_response()
+        .set_body(BoxBody::new(query::render_error(error))))
+}
+
+fn xml(mut res: HttpResponse) -> HttpResponse {
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(query::XML_CONTENT_TYPE),
+    );
+    res
+}
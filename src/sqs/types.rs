@@ -43,16 +43,22 @@ pub mod send_message {
         pub queue_url: Url,
         pub message_body: String,
         pub delay_seconds: Option<u64>,
+        #[serde(default)]
         pub message_attributes: HashMap<String, SqsMessageAttribute>,
         pub message_deduplication_id: Option<String>,
         pub message_group_id: Option<String>,
+
+        /// Hex-encoded MD5 digest of the message body. NerveMQ extension: if set, the message is
+        /// rejected unless it matches.
+        #[serde(rename = "MD5OfMessageBody")]
+        pub md5_of_message_body: Option<String>,
     }
 
     #[derive(Debug, serde::Serialize)]
     #[serde(rename_all = "PascalCase")]
     /// Response for the SendMessage operation.
     pub struct SendMessageResponse {
-        pub message_id: u64,
+        pub message_id: String,
 
         #[serde(rename = "MD5OfMessageBody")]
         pub md5_of_message_body: String,
@@ -60,7 +66,10 @@ pub mod send_message {
         #[serde(rename = "MD5OfMessageAttributes")]
         pub md5_of_message_attributes: String,
         // pub md5_of_message_system_attributes: String,
-        // pub sequence_number: Option<String>,
+        /// Position of the message within its message group, for messages sent with a
+        /// `MessageGroupId`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sequence_number: Option<String>,
     }
 }
 
@@ -135,6 +144,122 @@ pub mod list_queues {
     }
 }
 
+/// Types for the ListDeadLetterSourceQueues API operation.
+///
+/// Lists the queues whose redrive policy targets a queue, that is, the queues that move their
+/// failed messages to it.
+pub mod list_dead_letter_source_queues {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Request for the ListDeadLetterSourceQueues operation.
+    pub struct ListDeadLetterSourceQueuesRequest {
+        pub queue_url: Url,
+        /// Maximum number of queues to return, from 1 to 1000. Results are only paginated if set.
+        pub max_results: Option<u64>,
+        pub next_token: Option<String>,
+    }
+
+    #[derive(Debug, serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Response for the ListDeadLetterSourceQueues operation.
+    pub struct ListDeadLetterSourceQueuesResponse {
+        /// Unlike other responses, SQS names this field in camel case.
+        #[serde(rename = "queueUrls")]
+        pub queue_urls: Vec<Url>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub next_token: Option<String>,
+    }
+}
+
+/// Types for the StartMessageMoveTask API operation.
+///
+/// Starts moving the messages of a dead-letter queue to another queue, or back to the queues they
+/// were dead-lettered from.
+pub mod start_message_move_task {
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Request for the StartMessageMoveTask operation.
+    pub struct StartMessageMoveTaskRequest {
+        /// The dead-letter queue, as `namespace:queue`
+        pub source_arn: String,
+        /// The queue to move the messages to, as `namespace:queue`, or none to move each back to
+        /// its source queue
+        pub destination_arn: Option<String>,
+        pub max_number_of_messages_per_second: Option<u64>,
+    }
+
+    #[derive(Debug, serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Response for the StartMessageMoveTask operation.
+    pub struct StartMessageMoveTaskResponse {
+        pub task_handle: String,
+    }
+}
+
+/// Types for the CancelMessageMoveTask API operation.
+///
+/// Stops a running message move task.
+pub mod cancel_message_move_task {
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Request for the CancelMessageMoveTask operation.
+    pub struct CancelMessageMoveTaskRequest {
+        pub task_handle: String,
+    }
+
+    #[derive(Debug, serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Response for the CancelMessageMoveTask operation.
+    pub struct CancelMessageMoveTaskResponse {
+        pub approximate_number_of_messages_moved: u64,
+    }
+}
+
+/// Types for the ListMessageMoveTasks API operation.
+///
+/// Lists the most recent message move tasks of a dead-letter queue.
+pub mod list_message_move_tasks {
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Request for the ListMessageMoveTasks operation.
+    pub struct ListMessageMoveTasksRequest {
+        /// The dead-letter queue, as `namespace:queue`
+        pub source_arn: String,
+        /// Maximum number of tasks to return, from 1 to 10. Defaults to 1.
+        pub max_results: Option<u64>,
+    }
+
+    #[derive(Debug, serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Response for the ListMessageMoveTasks operation.
+    pub struct ListMessageMoveTasksResponse {
+        pub results: Vec<ListMessageMoveTasksResultEntry>,
+    }
+
+    #[derive(Debug, serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// A task listed by the ListMessageMoveTasks operation.
+    pub struct ListMessageMoveTasksResultEntry {
+        /// Only set while the task is running
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub task_handle: Option<String>,
+        pub status: crate::message_move::MoveTaskStatus,
+        pub source_arn: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub destination_arn: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub max_number_of_messages_per_second: Option<u64>,
+        pub approximate_number_of_messages_moved: u64,
+        pub approximate_number_of_messages_to_move: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub failure_reason: Option<String>,
+        /// Start of the task, in milliseconds since the Unix epoch
+        pub started_timestamp: u64,
+    }
+}
+
 /// Types for the DeleteMessage API operation.
 ///
 /// Deletes a specific message from a queue using its receipt handle.
@@ -156,6 +281,31 @@ pub mod delete_message {
     pub struct DeleteMessageResponse {}
 }
 
+/// Types for the ChangeMessageVisibility API operation.
+///
+/// Changes how long a received message stays hidden, using its receipt handle. A timeout of 0
+/// makes the message receivable again right away, which consumers use to give up on it.
+pub mod change_message_visibility {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Request for the ChangeMessageVisibility operation.
+    pub struct ChangeMessageVisibilityRequest {
+        pub queue_url: Url,
+        pub receipt_handle: String,
+        pub visibility_timeout: u64,
+        /// NerveMQ extension: why the consumer gave up on the message, kept with the message
+        #[serde(default)]
+        pub failure_reason: Option<String>,
+    }
+
+    #[derive(Debug, serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    /// Empty response for the ChangeMessageVisibility operation.
+    pub struct ChangeMessageVisibilityResponse {}
+}
+
 /// Types for the DeleteQueue API operation.
 ///
 /// Permanently deletes a queue and all its messages. This operation
@@ -255,6 +405,10 @@ pub mod receive_message {
         pub visibility_timeout: Option<u64>,
         pub wait_time_seconds: Option<u64>,
         pub receive_request_attempt_id: Option<String>,
+
+        /// NerveMQ extension: only receive the messages matching this selector, see
+        /// [`crate::selector`]
+        pub message_selector: Option<String>,
     }
 
     #[derive(Debug, serde::Serialize)]
@@ -295,9 +449,15 @@ pub mod send_message_batch {
         pub id: String,
         pub message_body: String,
         pub delay_seconds: Option<u64>,
+        #[serde(default)]
         pub message_attributes: HashMap<String, SqsMessageAttribute>,
         pub message_deduplication_id: Option<String>,
         pub message_group_id: Option<String>,
+
+        /// Hex-encoded MD5 digest of the message body. NerveMQ extension: if set, the entry fails
+        /// unless it matches.
+        #[serde(rename = "MD5OfMessageBody")]
+        pub md5_of_message_body: Option<String>,
     }
 
     #[derive(Debug, serde::Serialize)]
@@ -305,14 +465,19 @@ pub mod send_message_batch {
     /// Successful result entry for a batch message send operation.
     ///
     /// Contains the ID of the successfully sent message along with
-    /// its message ID and MD5 hash for verification.
+    /// its message ID and MD5 hashes for verification.
     pub struct SendMessageBatchResultEntry {
         pub id: String,
         pub message_id: String,
         #[serde(rename = "MD5OfMessageBody")]
         pub md5_of_message_body: String,
-        // pub md5_of_message_attributes: String,
+        #[serde(rename = "MD5OfMessageAttributes")]
+        pub md5_of_message_attributes: String,
         // pub md5_of_message_system_attributes: String,
+        /// Position of the message within its message group, for messages sent with a
+        /// `MessageGroupId`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sequence_number: Option<String>,
     }
 
     #[derive(Debug, serde::Serialize)]
@@ -523,6 +688,24 @@ impl SqsMessageAttribute {
         }
     }
 
+    /// Converts the attribute to a plain JSON value, as shown in the admin API. Binary values are
+    /// base64-encoded.
+    pub fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        use base64::Engine as _;
+
+        Ok(match self {
+            SqsMessageAttribute::String { string_value } => {
+                serde_json::Value::String(string_value.clone())
+            }
+            SqsMessageAttribute::Number { string_value } => {
+                serde_json::Value::Number(string_value.parse()?)
+            }
+            SqsMessageAttribute::Binary { binary_value } => {
+                serde_json::Value::String(base64::prelude::BASE64_STANDARD.encode(binary_value))
+            }
+        })
+    }
+
     /// Serializes the attributes in the expected binary format for SQS attributes.
     ///
     /// [key length (4 bytes)][key bytes][type (1 byte)][value length (4 bytes)][value bytes]
@@ -603,7 +786,7 @@ fn test_sqs_message_attribute() {
 #[serde(rename_all = "PascalCase")]
 pub struct SqsMessage {
     pub message_id: String,
-    // pub receipt_handle: String,
+    pub receipt_handle: String,
     #[serde(rename = "MD5OfBody")]
     pub md5_of_body: String,
     pub body: String,
@@ -631,7 +814,12 @@ pub enum SqsResponse {
     GetQueueUrl(get_queue_url::GetQueueUrlResponse),
     CreateQueue(create_queue::CreateQueueResponse),
     ListQueues(list_queues::ListQueuesResponse),
+    ListDeadLetterSourceQueues(list_dead_letter_source_queues::ListDeadLetterSourceQueuesResponse),
+    StartMessageMoveTask(start_message_move_task::StartMessageMoveTaskResponse),
+    CancelMessageMoveTask(cancel_message_move_task::CancelMessageMoveTaskResponse),
+    ListMessageMoveTasks(list_message_move_tasks::ListMessageMoveTasksResponse),
     DeleteMessage(delete_message::DeleteMessageResponse),
+    ChangeMessageVisibility(change_message_visibility::ChangeMessageVisibilityResponse),
     PurgeQueue(purge_queue::PurgeQueueResponse),
     DeleteQueue(delete_queue::DeleteQueueResponse),
     GetQueueAttributes(get_queue_attributes::GetQueueAttributesResponse),
@@ -0,0 +1,193 @@
+//! Public status page.
+//!
+//! `GET /status` tells whoever asks whether the server is up, without authentication: its version,
+//! how long it has been running, and its health in aggregate. Nothing about namespaces, queues or
+//! users is included, so that it can be shown to everyone who depends on the server. Browsers get
+//! a small HTML page, everything else JSON.
+//!
+//! The response may be cached for [`STATUS_MAX_AGE`], so that pollers and proxies can't make the
+//! server check itself more often than that.
+
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    http::{
+        header::{self, Accept, CacheControl, CacheDirective, ContentType, Header},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+
+use crate::{overview::Health, service::Service};
+
+/// How long clients and proxies may cache the status.
+pub const STATUS_MAX_AGE: Duration = Duration::from_secs(10);
+
+/// Share of server errors over the recent window above which the server is degraded.
+pub const DEGRADED_SERVER_ERROR_RATE: f64 = 0.05;
+
+/// Overall state of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::AsRefStr)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum State {
+    /// Everything works
+    Operational,
+    /// Requests are served, but some fail or background tasks are failing
+    Degraded,
+    /// Message traffic is rejected on purpose, see [`Service::set_maintenance_mode`]
+    Maintenance,
+    /// The database can't be reached, so nothing works
+    Down,
+}
+
+/// Status of the server, without tenant data.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub state: State,
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    /// Whether the database answers
+    pub database: bool,
+    /// Share of responses with a 5xx status over the recent window, between 0 and 1
+    pub server_error_rate: f64,
+    /// Number of background tasks that are failing or stopped running
+    pub unhealthy_tasks: u64,
+}
+
+impl Status {
+    /// Summarizes the health of a server.
+    ///
+    /// # Arguments
+    /// * `health` - Health of the server
+    /// * `uptime` - How long the server has been running
+    /// * `database` - Whether the database answers
+    /// * `maintenance` - Whether the server is in maintenance mode
+    pub fn new(health: &Health, uptime: Duration, database: bool, maintenance: bool) -> Self {
+        let unhealthy_tasks = health.tasks.iter().filter(|task| !task.healthy).count() as u64;
+        let server_error_rate = health.requests.server_error_rate;
+
+        let state = if !database {
+            State::Down
+        } else if maintenance {
+            State::Maintenance
+        } else if unhealthy_tasks > 0 || server_error_rate > DEGRADED_SERVER_ERROR_RATE {
+            State::Degraded
+        } else {
+            State::Operational
+        };
+
+        Self {
+            state,
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: uptime.as_secs(),
+            database,
+            server_error_rate,
+            unhealthy_tasks,
+        }
+    }
+
+    /// Renders the status as a page for browsers.
+    fn render_html(&self) -> String {
+        let uptime = Duration::from_secs(self.uptime_seconds);
+        let (days, hours, minutes) = (
+            uptime.as_secs() / 86400,
+            uptime.as_secs() % 86400 / 3600,
+            uptime.as_secs() % 3600 / 60,
+        );
+
+        format!(
+            "<!DOCTYPE html>\n\
+             <html><head><meta charset=\"utf-8\"><title>NerveMQ status</title></head><body>\n\
+             <h1>NerveMQ is {state}</h1>\n\
+             <p>Version {version}, up for {days}d {hours}h {minutes}m.</p>\n\
+             </body></html>\n",
+            state = self.state.as_ref(),
+            version = self.version,
+        )
+    }
+}
+
+#[get("/status")]
+pub async fn get_status(req: HttpRequest, service: web::Data<Service>) -> HttpResponse {
+    let database = sqlx::query("SELECT 1").execute(service.db()).await.is_ok();
+    let status = Status::new(
+        &service.health().health(),
+        service.health().uptime(),
+        database,
+        service.maintenance_mode(),
+    );
+
+    let mut res = HttpResponse::build(match status.state {
+        State::Down => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    });
+    res.insert_header(CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(STATUS_MAX_AGE.as_secs() as u32),
+    ]))
+    .insert_header((header::VARY, "Accept"));
+
+    let wants_html = Accept::parse(&req).is_ok_and(|accept| {
+        accept
+            .ranked()
+            .first()
+            .is_some_and(|mime| mime.essence_str() == "text/html")
+    });
+    if wants_html {
+        res.insert_header(ContentType::html())
+            .body(status.render_html())
+    } else {
+        res.json(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overview::{RequestRates, TaskHealth};
+
+    #[test]
+    fn test_status_state() {
+        let mut health = Health {
+            requests: RequestRates {
+                requests: 100,
+                server_errors: 1,
+                server_error_rate: 0.01,
+                ..Default::default()
+            },
+            tasks: vec![TaskHealth {
+                name: "trace",
+                last_run_at: 0,
+                last_error: None,
+                healthy: true,
+            }],
+        };
+        let uptime = Duration::from_secs(90);
+
+        let status = Status::new(&health, uptime, true, false);
+        assert_eq!(status.state, State::Operational);
+        assert_eq!(status.uptime_seconds, 90);
+        assert_eq!(
+            Status::new(&health, uptime, true, true).state,
+            State::Maintenance
+        );
+        assert_eq!(Status::new(&health, uptime, false, true).state, State::Down);
+
+        health.tasks[0].healthy = false;
+        let status = Status::new(&health, uptime, true, false);
+        assert_eq!(status.state, State::Degraded);
+        assert_eq!(status.unhealthy_tasks, 1);
+
+        health.tasks.clear();
+        health.requests.server_error_rate = 0.5;
+        assert_eq!(
+            Status::new(&health, uptime, true, false).state,
+            State::Degraded
+        );
+        assert!(status.render_html().contains("NerveMQ is degraded"));
+    }
+}
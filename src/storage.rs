@@ -0,0 +1,247 @@
+//! Storage usage and reclamation.
+//!
+//! `GET /admin/storage` reports how much disk the database takes up, and how much of it belongs
+//! to the messages and history of each queue. Every
+//! [`Config::vacuum_interval`](crate::config::Config::vacuum_interval), the write-ahead log is
+//! checkpointed and truncated, and free pages are returned to the file system if the database
+//! uses incremental auto-vacuum. `POST /admin/storage/vacuum` does the same on demand, or with
+//! `?full=true` rebuilds the whole database, which also reclaims the free pages of databases
+//! created without auto-vacuum, but blocks writes while it runs.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{config::MEMORY_DB_PATH, error::Error, service::Service, tasks};
+
+/// Disk usage of the database.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageReport {
+    /// Size of the database file in bytes
+    pub db_size: u64,
+    /// Size of the write-ahead log in bytes
+    pub wal_size: u64,
+    pub page_size: u64,
+    pub page_count: u64,
+    /// Pages that are allocated but unused, which vacuuming returns to the file system
+    pub free_pages: u64,
+    pub auto_vacuum: AutoVacuum,
+    /// Usage of every queue, largest first
+    pub queues: Vec<QueueStorage>,
+}
+
+/// SQLite's `auto_vacuum` mode of the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoVacuum {
+    /// Free pages are kept until a full vacuum
+    None,
+    /// Free pages are reclaimed on every commit
+    Full,
+    /// Free pages are reclaimed by the scheduled vacuum
+    Incremental,
+}
+
+/// Disk usage of a queue. Sizes are of the data as stored, so compressed bodies count with their
+/// compressed size, and don't include SQLite's own overhead.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStorage {
+    pub namespace: String,
+    pub queue: String,
+    pub messages: u64,
+    /// Bytes of message bodies and attributes
+    pub message_bytes: u64,
+    pub history_entries: u64,
+    /// Bytes of message bodies kept in the history
+    pub history_bytes: u64,
+}
+
+/// Query parameters of `POST /admin/storage/vacuum`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VacuumQuery {
+    /// Rebuild the whole database instead of reclaiming incrementally
+    #[serde(default)]
+    pub full: bool,
+}
+
+/// Reports the disk usage of the database.
+pub async fn report(service: &Service) -> Result<StorageReport, Error> {
+    let mut conn = service.db().acquire().await?;
+
+    let page_size: u64 = sqlx::query_scalar("PRAGMA page_size")
+        .fetch_one(&mut *conn)
+        .await?;
+    let page_count: u64 = sqlx::query_scalar("PRAGMA page_count")
+        .fetch_one(&mut *conn)
+        .await?;
+    let free_pages: u64 = sqlx::query_scalar("PRAGMA freelist_count")
+        .fetch_one(&mut *conn)
+        .await?;
+    let auto_vacuum = match sqlx::query_scalar::<_, i64>("PRAGMA auto_vacuum")
+        .fetch_one(&mut *conn)
+        .await?
+    {
+        1 => AutoVacuum::Full,
+        2 => AutoVacuum::Incremental,
+        _ => AutoVacuum::None,
+    };
+
+    let queues = sqlx::query_as(
+        "
+        SELECT n.name AS namespace, q.name AS queue,
+            (SELECT COUNT(*) FROM messages m WHERE m.queue = q.id) AS messages,
+            (SELECT COALESCE(SUM(length(m.body)), 0) FROM messages m WHERE m.queue = q.id)
+                + (
+                    SELECT COALESCE(SUM(length(kv.k) + length(kv.v)), 0) FROM kv_pairs kv
+                    JOIN messages m ON kv.message = m.id
+                    WHERE m.queue = q.id
+                ) AS message_bytes,
+            (SELECT COUNT(*) FROM messages_history h WHERE h.queue = q.id) AS history_entries,
+            (
+                SELECT COALESCE(SUM(length(h.body)), 0) FROM messages_history h
+                WHERE h.queue = q.id
+            ) AS history_bytes
+        FROM queues q
+        JOIN namespaces n ON q.ns = n.id
+        ORDER BY message_bytes + history_bytes DESC, n.name, q.name
+        ",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let db_path = service.config().db_path();
+    let (db_size, wal_size) = if db_path == MEMORY_DB_PATH {
+        (page_size * page_count, 0)
+    } else {
+        (
+            file_size(Path::new(db_path)),
+            file_size(Path::new(&format!("{db_path}-wal"))),
+        )
+    };
+
+    Ok(StorageReport {
+        db_size,
+        wal_size,
+        page_size,
+        page_count,
+        free_pages,
+        auto_vacuum,
+        queues,
+    })
+}
+
+/// Returns the size of a file, or 0 if it doesn't exist.
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+/// Checkpoints and truncates the write-ahead log, and reclaims free pages.
+///
+/// # Arguments
+/// * `full` - Rebuild the database with `VACUUM` instead of reclaiming incrementally
+pub async fn vacuum(service: &Service, full: bool) -> Result<(), Error> {
+    let mut conn = service.db().acquire().await?;
+
+    let statement = if full {
+        "VACUUM"
+    } else {
+        // Only reclaims anything in incremental auto-vacuum mode.
+        "PRAGMA incremental_vacuum"
+    };
+    sqlx::query(statement).execute(&mut *conn).await?;
+
+    let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(&mut *conn)
+        .await?;
+    if busy != 0 {
+        tracing::debug!("Write-ahead log is in use, checkpoint was incomplete");
+    }
+
+    if full {
+        tracing::info!(target: "nervemq::audit", "Database vacuumed");
+    }
+
+    Ok(())
+}
+
+/// Reclaims storage every configured interval until the process exits, if it is enabled.
+pub fn spawn(service: &Service) {
+    let Some(period) = service.config().vacuum_interval() else {
+        return;
+    };
+
+    // There is nothing to reclaim right after startup, when the write-ahead log is busy with
+    // every other background task's first run.
+    let start = tokio::time::Instant::now() + period;
+    tasks::spawn_periodic_at(service, "storage", start, period, |service| async move {
+        vacuum(&service, false).await
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_identity::Identity;
+
+    use super::*;
+    use crate::{config::Config, kms::memory::InMemoryKeyManager};
+
+    #[tokio::test]
+    async fn test_report() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+
+        service.create_namespace("t", root()).await.unwrap();
+        for name in ["small", "large"] {
+            service
+                .create_queue("t", name, HashMap::new(), HashMap::new(), root())
+                .await
+                .unwrap();
+        }
+        let large = service
+            .get_queue_id("t", "large", service.db())
+            .await
+            .unwrap()
+            .unwrap();
+        let message: i64 =
+            sqlx::query_scalar("INSERT INTO messages (queue, body) VALUES ($1, $2) RETURNING id")
+                .bind(large as i64)
+                .bind(vec![0u8; 1000])
+                .fetch_one(service.db())
+                .await
+                .unwrap();
+        sqlx::query("INSERT INTO kv_pairs (message, k, v) VALUES ($1, 'key', 'value')")
+            .bind(message)
+            .execute(service.db())
+            .await
+            .unwrap();
+
+        let usage = report(&service).await.unwrap();
+        assert_eq!(usage.db_size, usage.page_size * usage.page_count);
+        assert_eq!(usage.queues.len(), 2);
+        assert_eq!(usage.queues[0].queue, "large");
+        assert_eq!(usage.queues[0].messages, 1);
+        assert_eq!(usage.queues[0].message_bytes, 1008);
+        assert_eq!(usage.queues[1].message_bytes, 0);
+
+        sqlx::query("DELETE FROM messages")
+            .execute(service.db())
+            .await
+            .unwrap();
+        vacuum(&service, true).await.unwrap();
+        let usage = report(&service).await.unwrap();
+        assert_eq!(usage.free_pages, 0);
+        assert_eq!(usage.queues[0].message_bytes, 0);
+    }
+}
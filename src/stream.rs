@@ -0,0 +1,186 @@
+//! Server-sent event streams of queue messages.
+//!
+//! `GET /queue/{namespace}/{queue}/stream` keeps the response open and sends messages as
+//! `message` events as soon as they are available, for dashboards that show them in real time.
+//!
+//! In the default `receive` mode, messages are received as ReceiveMessage receives them: they are
+//! hidden for the visibility timeout and count as a try, and the stream's client has to delete
+//! them (with DeleteMessage or `POST /api/ack`) using the receipt handle of the event, just like
+//! any other consumer. Each event is the message as ReceiveMessage returns it. Messages that were
+//! received while the client disconnected become visible again once their timeout expires.
+//!
+//! In `tail` mode, messages are only watched: every message sent to the queue after the stream
+//! was opened is sent as `GET /queue/{namespace}/{queue}/messages` lists it, and is left for the
+//! queue's consumers. A message that is consumed before it could be looked up is skipped. If the
+//! stream falls too far behind the [event bus](crate::events), a `lagged` event says how many
+//! messages it missed.
+//!
+//! A comment is sent every [`KEEP_ALIVE_INTERVAL`] while there are no messages, so that proxies
+//! don't close idle streams.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+    error::Error,
+    events::Event,
+    service::{ReceiveOptions, Service},
+};
+
+/// How long a stream may stay silent before a comment is sent to keep it open.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Maximum number of messages received at once in `receive` mode, as allowed by ReceiveMessage.
+const MAX_STREAM_BATCH: u64 = 10;
+
+/// How a stream reads the messages of a queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamMode {
+    /// Messages are received, with the same visibility semantics as ReceiveMessage
+    #[default]
+    Receive,
+    /// New messages are watched without being received
+    Tail,
+}
+
+/// Query parameters of a stream.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamQuery {
+    #[serde(default)]
+    pub mode: StreamMode,
+    /// Visibility timeout of received messages in seconds, in `receive` mode
+    pub visibility_timeout: Option<u64>,
+    /// Consumer group to receive the messages for, in `receive` mode
+    pub consumer_group: Option<String>,
+}
+
+/// Formats a server-sent event.
+fn event(kind: &str, id: Option<&str>, data: &impl Serialize) -> Result<String, Error> {
+    let mut event = String::new();
+    if let Some(id) = id {
+        event.push_str(&format!("id: {id}\n"));
+    }
+    event.push_str(&format!(
+        "event: {kind}\ndata: {}\n\n",
+        serde_json::to_string(data)?
+    ));
+
+    Ok(event)
+}
+
+/// Formats an error event.
+fn error_event(error: &Error) -> Bytes {
+    let data = serde_json::json!({ "message": error.to_string() });
+    Bytes::from(format!("event: error\ndata: {data}\n\n"))
+}
+
+fn keep_alive() -> Bytes {
+    Bytes::from_static(b": keep-alive\n\n")
+}
+
+/// Receives the messages of a queue until the client disconnects or receiving fails.
+///
+/// # Arguments
+/// * `service` - Service to receive from
+/// * `namespace` - Namespace containing the queue
+/// * `queue` - Queue name
+/// * `query` - Visibility timeout and consumer group of the stream
+pub fn receive(
+    service: Service,
+    namespace: String,
+    queue: String,
+    query: StreamQuery,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    let options = ReceiveOptions::builder()
+        .max_messages(MAX_STREAM_BATCH)
+        .maybe_visibility_timeout(query.visibility_timeout.map(Duration::from_secs))
+        .wait_time(KEEP_ALIVE_INTERVAL)
+        .attribute_names(["All".to_owned()].into())
+        .message_attribute_names(["All".to_owned()].into())
+        .maybe_consumer_group(query.consumer_group)
+        .build();
+
+    stream::unfold(Some(options), move |options| {
+        let service = service.clone();
+        let (namespace, queue) = (namespace.clone(), queue.clone());
+        async move {
+            let options = options?;
+            let chunk = match service
+                .sqs_recv_batch(&namespace, &queue, options.clone())
+                .await
+                .and_then(|messages| {
+                    messages
+                        .iter()
+                        .map(|message| event("message", Some(&message.message_id), message))
+                        .collect::<Result<String, _>>()
+                }) {
+                Ok(events) if events.is_empty() => keep_alive(),
+                Ok(events) => Bytes::from(events),
+                Err(e) => return Some((Ok(error_event(&e)), None)),
+            };
+
+            Some((Ok(chunk), Some(options)))
+        }
+    })
+}
+
+/// Watches for the messages sent to a queue until the client disconnects or the service shuts
+/// down.
+///
+/// # Arguments
+/// * `service` - Service to watch
+/// * `queue_id` - ID of the queue
+pub fn tail(service: Service, queue_id: u64) -> impl Stream<Item = Result<Bytes, Error>> {
+    let events = service.events().subscribe();
+
+    stream::unfold((service, events), move |(service, mut events)| async move {
+        let chunk = loop {
+            let message = match tokio::time::timeout(KEEP_ALIVE_INTERVAL, events.recv()).await {
+                Err(_) => break keep_alive(),
+                Ok(Ok(Event::MessageSent { queue, message })) if queue == queue_id => message,
+                Ok(Ok(_)) => continue,
+                Ok(Err(broadcast::error::RecvError::Lagged(missed))) => {
+                    match event("lagged", None, &serde_json::json!({ "missed": missed })) {
+                        Ok(event) => break Bytes::from(event),
+                        Err(e) => break error_event(&e),
+                    }
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+            };
+
+            match service.get_message_details(queue_id, message).await {
+                Ok(Some(details)) => match event("message", Some(&details.message_id), &details) {
+                    Ok(event) => break Bytes::from(event),
+                    Err(e) => break error_event(&e),
+                },
+                Ok(None) => continue,
+                Err(e) => break error_event(&e),
+            }
+        };
+
+        Some((Ok(chunk), (service, events)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        assert_eq!(
+            event("message", Some("a1"), &serde_json::json!({ "Body": "hi" })).unwrap(),
+            "id: a1\nevent: message\ndata: {\"Body\":\"hi\"}\n\n"
+        );
+        assert_eq!(
+            event("lagged", None, &serde_json::json!({ "missed": 3 })).unwrap(),
+            "event: lagged\ndata: {\"missed\":3}\n\n"
+        );
+    }
+}
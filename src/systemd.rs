@@ -0,0 +1,170 @@
+//! Integration with systemd.
+//!
+//! When started by a socket unit, the server serves on the sockets systemd passes it instead of
+//! binding its own: sockets named `https` (with `FileDescriptorName=https`) by the HTTPS listener,
+//! and all others by the plain HTTP one. Under a `Type=notify` service, systemd is told once the
+//! server is ready to accept requests and once it has shut down, and if the service has a
+//! `WatchdogSec=`, the server keeps the watchdog fed for as long as its runtime is responsive.
+//!
+//! Outside of systemd, and on platforms other than Unix, none of this does anything.
+
+use std::{net::TcpListener, time::Duration};
+
+/// Name of the sockets the HTTPS listener serves on.
+pub const HTTPS_SOCKET_NAME: &str = "https";
+
+/// Sockets passed by systemd socket activation.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    /// Sockets to serve plain HTTP on
+    pub http: Vec<TcpListener>,
+    /// Sockets to serve HTTPS on, named [`HTTPS_SOCKET_NAME`]
+    pub https: Vec<TcpListener>,
+}
+
+/// Takes the sockets systemd passed to the process, and removes the variables describing them
+/// from the environment, so child processes don't take them as well.
+///
+/// # Returns
+/// The sockets, or none if the process wasn't socket-activated
+pub fn listeners() -> std::io::Result<Listeners> {
+    let count = match (std::env::var("LISTEN_PID"), std::env::var("LISTEN_FDS")) {
+        // The sockets may have been meant for a parent process that didn't unset the variables.
+        (Ok(pid), Ok(count)) if pid.parse() == Ok(std::process::id()) => count
+            .parse::<i32>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+        _ => 0,
+    };
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+
+    let mut names = names.split(':');
+    let mut listeners = Listeners::default();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let listener = from_fd(fd)?;
+        if names.next() == Some(HTTPS_SOCKET_NAME) {
+            listeners.https.push(listener);
+        } else {
+            listeners.http.push(listener);
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// First file descriptor systemd passes sockets as.
+const LISTEN_FDS_START: i32 = 3;
+
+#[cfg(unix)]
+fn from_fd(fd: i32) -> std::io::Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: systemd passes the process `LISTEN_FDS` open sockets starting at
+    // `LISTEN_FDS_START`, which nothing else owns since the variables are only read once.
+    Ok(unsafe { TcpListener::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn from_fd(_fd: i32) -> std::io::Result<TcpListener> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "socket activation is only supported on Unix",
+    ))
+}
+
+/// Sends a state change, like `READY=1`, to systemd.
+///
+/// # Returns
+/// `false` if the service isn't supervised by systemd
+pub fn notify(state: &str) -> std::io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    send(&path, state)?;
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Gets how often systemd expects the watchdog to be fed.
+///
+/// # Returns
+/// Half of the watchdog timeout, as systemd recommends, or `None` if the watchdog is disabled
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec) / 2)
+}
+
+/// Feeds the systemd watchdog until the process exits, if it is enabled.
+pub async fn watchdog() {
+    let Some(period) = watchdog_interval() else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = notify("WATCHDOG=1") {
+            tracing::warn!("Failed to notify the systemd watchdog: {e}");
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        let dir = std::env::temp_dir().join(format!("nervemq-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Periodic background tasks.
+//!
+//! Every task runs on an interval, under a name its runs are reported by in the server's health,
+//! see [`crate::overview`]. Names are those of the modules the tasks live in. While the server is
+//! in maintenance mode, runs are skipped but still reported, so that the tasks don't look stuck.
+//! A run that fails is logged and reported, and the task carries on at its next tick.
+
+use std::{future::Future, time::Duration};
+
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::{error::Error, service::Service};
+
+/// Runs a task every `period`, starting now, until the process exits.
+///
+/// # Arguments
+/// * `service` - Service the task works on
+/// * `name` - Name the task is reported by
+/// * `period` - How often the task runs
+/// * `task` - Does one run of the task
+pub fn spawn_periodic<F, Fut>(service: &Service, name: &'static str, period: Duration, task: F)
+where
+    F: FnMut(Service) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), Error>> + Send,
+{
+    spawn_periodic_at(service, name, Instant::now(), period, task);
+}
+
+/// Runs a task every `period`, starting at `start`, until the process exits.
+///
+/// See [`spawn_periodic`].
+pub fn spawn_periodic_at<F, Fut>(
+    service: &Service,
+    name: &'static str,
+    start: Instant,
+    period: Duration,
+    mut task: F,
+) where
+    F: FnMut(Service) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), Error>> + Send,
+{
+    let service = service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(start, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            if service.maintenance_mode() {
+                service.health().record_task(name, period, None);
+                continue;
+            }
+
+            let error = match task(service.clone()).await {
+                Ok(()) => None,
+                Err(e) => {
+                    tracing::error!(task = name, "Background task failed: {e}");
+                    Some(e.to_string())
+                }
+            };
+            service.health().record_task(name, period, error);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{config::Config, kms::memory::InMemoryKeyManager};
+
+    #[tokio::test]
+    async fn test_spawn_periodic() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let runs = Arc::new(AtomicU64::new(0));
+
+        let task_runs = runs.clone();
+        spawn_periodic(&service, "test", Duration::from_millis(10), move |_| {
+            let runs = task_runs.clone();
+            async move {
+                // Every other run fails.
+                match runs.fetch_add(1, Ordering::SeqCst) % 2 {
+                    0 => Err(Error::not_found("something")),
+                    _ => Ok(()),
+                }
+            }
+        });
+
+        let last_error = || {
+            let tasks = service.health().health().tasks;
+            let task = tasks.iter().find(|task| task.name == "test")?;
+            Some(task.last_error.clone())
+        };
+        let last_error = &last_error;
+        let wait_for = move |error: Option<String>| async move {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while last_error() != Some(error.clone()) {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .unwrap();
+        };
+        wait_for(Some("Resource not found: something".to_owned())).await;
+        wait_for(None).await;
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+    }
+}
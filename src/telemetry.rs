@@ -0,0 +1,112 @@
+//! Structured telemetry for applications embedding NerveMQ.
+//!
+//! An embedder that wants to feed its own metrics or tracing pipeline implements
+//! [`TelemetrySink`] and passes it to [`crate::run`]. The service then reports a
+//! [`TelemetryEvent`] for every request it handles, every message it accepts and every error it
+//! returns, so that nothing has to be scraped from the logs.
+//!
+//! Sinks are called inline, on the worker handling the request, so they must return quickly.
+//! Sinks that do I/O should hand events off to a channel or a background task of their own.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+    web::Data,
+    ResponseError,
+};
+
+use crate::{error::Error, service::Service};
+
+/// Something that happened in the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TelemetryEvent {
+    /// An HTTP request was answered.
+    RequestHandled {
+        method: Method,
+        /// Route the request matched, like `/queue/{namespace}`, or its path if it matched none
+        route: String,
+        status: u16,
+        duration: Duration,
+    },
+    /// A message was committed to a queue.
+    MessageSent { queue: u64, message: u64 },
+    /// A request failed with an error.
+    ErrorOccurred {
+        /// Machine-readable code of the error, see [`Error::code`]
+        code: &'static str,
+        status: u16,
+        /// Message of the error. Unlike in the response, internal errors keep their details.
+        message: String,
+    },
+}
+
+/// Receives the [`TelemetryEvent`]s of a service.
+pub trait TelemetrySink: Send + Sync + 'static {
+    /// Records an event. Called inline, so it must not block.
+    fn record(&self, event: &TelemetryEvent);
+}
+
+/// Sink that drops every event, used when the embedder doesn't register one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl TelemetrySink for NoopSink {
+    fn record(&self, _event: &TelemetryEvent) {}
+}
+
+impl<S: TelemetrySink + ?Sized> TelemetrySink for Arc<S> {
+    fn record(&self, event: &TelemetryEvent) {
+        (**self).record(event)
+    }
+}
+
+/// Middleware that reports every request, and the error it failed with if any, to the service's
+/// [`TelemetrySink`].
+pub async fn record_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let service = req.app_data::<Data<Service>>().cloned();
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+    let start = Instant::now();
+
+    let res = next.call(req).await;
+
+    if let Some(service) = service {
+        // Requests are only routed once they have passed the middleware.
+        let (status, error, route) = match &res {
+            Ok(res) => (
+                res.status(),
+                res.response().error(),
+                res.request().match_pattern(),
+            ),
+            Err(e) => (e.as_response_error().status_code(), Some(e), None),
+        };
+
+        if let Some(e) = error.and_then(|e| e.as_error::<Error>()) {
+            service.telemetry().record(&TelemetryEvent::ErrorOccurred {
+                code: e.code(),
+                status: e.status_code().as_u16(),
+                message: e.to_string(),
+            });
+        }
+
+        service.telemetry().record(&TelemetryEvent::RequestHandled {
+            method,
+            route: route.unwrap_or(path),
+            status: status.as_u16(),
+            duration: start.elapsed(),
+        });
+    }
+
+    res
+}
@@ -0,0 +1,275 @@
+//! In-memory NerveMQ servers for end-to-end tests.
+//!
+//! [`TestServer`] runs the full HTTP application on a random local port, backed by an in-memory
+//! database and key manager, so applications can test against a real NerveMQ without temporary
+//! directories or configuration. Every server is independent and starts out empty, apart from
+//! the namespaces, users and API tokens it is seeded with.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), nervemq::error::Error> {
+//! use nervemq::testing::{Role, TestServer, TestUser};
+//!
+//! let server = TestServer::builder()
+//!     .users(vec![TestUser::builder()
+//!         .email("app@example.com")
+//!         .role(Role::User)
+//!         .namespaces(vec!["test".to_owned()])
+//!         .build()])
+//!     .start()
+//!     .await?;
+//!
+//! let token = server.token("app@example.com", "test").unwrap();
+//! let response = server
+//!     .http()
+//!     .get("/queue/test")
+//!     .insert_header(("Authorization", token.authorization()))
+//!     .send()
+//!     .await
+//!     .unwrap();
+//! assert!(response.status().is_success());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use actix_identity::Identity;
+use actix_web::web::Data;
+use serde_email::Email;
+
+use crate::{
+    auth::{
+        credential::API_KEY_PREFIX,
+        crypto::generate_token,
+        protocols::nervemq::{sign_v2, string_to_sign_v2, DATE_HEADER, NONCE_HEADER},
+        session::SqliteSessionStore,
+    },
+    config::Config,
+    error::Error,
+    kms::memory::InMemoryKeyManager,
+    service::Service,
+    telemetry::TelemetrySink,
+    Components,
+};
+
+pub use crate::api::auth::Role;
+
+/// Namespace every test server is seeded with unless told otherwise.
+pub const NAMESPACE: &str = "test";
+
+/// Password given to seeded users that don't specify one.
+pub const PASSWORD: &str = "password";
+
+/// A user to seed a test server with.
+#[derive(Debug, Clone, bon::Builder)]
+#[builder(on(String, into))]
+pub struct TestUser {
+    pub email: String,
+    #[builder(default = PASSWORD.to_owned())]
+    pub password: String,
+    #[builder(default)]
+    pub role: Role,
+    /// Namespaces the user can access, and gets an API token for
+    #[builder(default)]
+    pub namespaces: Vec<String>,
+}
+
+/// An API token created for a seeded user.
+#[derive(Debug, Clone)]
+pub struct TestToken {
+    pub email: String,
+    pub namespace: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl TestToken {
+    /// Formats the token as an `Authorization` header value.
+    pub fn authorization(&self) -> String {
+        format!(
+            "NerveMqApiV1 {API_KEY_PREFIX}_{}_{}",
+            self.access_key, self.secret_key
+        )
+    }
+
+    /// Signs a request with the `NerveMqApiV2` scheme, with a random nonce.
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method of the request
+    /// * `path_and_query` - Path of the request, with its query string if it has one
+    /// * `body` - Body of the request
+    /// * `date` - Unix time to sign the request at
+    ///
+    /// # Returns
+    /// The `Authorization`, date and nonce headers to send
+    pub fn sign_v2(
+        &self,
+        method: &str,
+        path_and_query: &str,
+        body: &[u8],
+        date: i64,
+    ) -> [(&'static str, String); 3] {
+        let (path, query) = path_and_query
+            .split_once('?')
+            .unwrap_or((path_and_query, ""));
+        let date = date.to_string();
+        let nonce = generate_token::<16>(rand::thread_rng()).expect("nonce");
+
+        let string_to_sign = string_to_sign_v2(method, path, query, &date, &nonce, body);
+        let signature = sign_v2(self.secret_key.as_bytes(), &string_to_sign).expect("signature");
+
+        [
+            (
+                "Authorization",
+                format!(
+                    "NerveMqApiV2 Credential={}, Signature={signature}",
+                    self.access_key
+                ),
+            ),
+            (DATE_HEADER, date),
+            (NONCE_HEADER, nonce),
+        ]
+    }
+}
+
+/// A NerveMQ server backed by an in-memory database, stopped when dropped.
+pub struct TestServer {
+    server: actix_test::TestServer,
+    service: Service,
+    tokens: Vec<TestToken>,
+}
+
+#[bon::bon]
+impl TestServer {
+    /// Starts a server and seeds it.
+    ///
+    /// The root admin gets a token for every namespace, and every other user a token for each
+    /// namespace they can access.
+    ///
+    /// # Arguments
+    /// * `namespaces` - Namespaces to create. Defaults to [`NAMESPACE`].
+    /// * `users` - Users to create in addition to the root admin
+    /// * `config` - Configuration of the server. Defaults to [`Config::in_memory`]. Unlike a
+    ///   real server, a test server runs a single worker unless
+    ///   [`Config::workers`] says otherwise.
+    /// * `components` - Parts of the API to serve. Defaults to all of them.
+    /// * `telemetry` - Sink to report telemetry events to, see [`crate::telemetry`]
+    ///
+    /// # Errors
+    /// Returns an error if seeding fails, for example if a user names a namespace that isn't
+    /// created
+    #[builder(finish_fn = start)]
+    pub async fn new(
+        #[builder(default = vec![NAMESPACE.to_owned()])] namespaces: Vec<String>,
+        #[builder(default)] users: Vec<TestUser>,
+        #[builder(default = Config::in_memory())] config: Config,
+        #[builder(default)] components: Components,
+        telemetry: Option<Arc<dyn TelemetrySink>>,
+    ) -> Result<Self, Error> {
+        let service = Service::connect_with()
+            .config(config)
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .maybe_telemetry(telemetry)
+            .call()
+            .await?;
+
+        crate::spawn_tasks(&service);
+
+        let root = service.config().root_email().to_owned();
+        for namespace in &namespaces {
+            service
+                .create_namespace(namespace, Identity::mock(root.clone()))
+                .await?;
+        }
+
+        let mut seeded = vec![(root, namespaces)];
+
+        for user in users {
+            service
+                .create_user(
+                    Email::from_str(&user.email)
+                        .map_err(|e| Error::invalid_parameter(e.to_string()))?,
+                    user.password,
+                    Some(user.role),
+                    user.namespaces.clone(),
+                )
+                .await?;
+            seeded.push((user.email, user.namespaces));
+        }
+
+        let mut tokens = Vec::new();
+        for (email, namespaces) in seeded {
+            for namespace in namespaces {
+                let token = service
+                    .create_token(
+                        format!("{email} ({namespace})"),
+                        namespace,
+                        Identity::mock(email.clone()),
+                    )
+                    .await?;
+                tokens.push(TestToken {
+                    email: email.clone(),
+                    namespace: token.namespace,
+                    access_key: token.access_key,
+                    secret_key: token.secret_key,
+                });
+            }
+        }
+
+        let session_store = SqliteSessionStore::new(service.db().clone());
+        let secret_key = crate::auth::signing_key::load(&service).await?;
+        let data = Data::new(service.clone());
+
+        let workers = service.config().workers().unwrap_or(1);
+        let server = actix_test::start_with(actix_test::config().workers(workers), move || {
+            crate::app(
+                data.clone(),
+                session_store.clone(),
+                secret_key.clone(),
+                components,
+            )
+        });
+
+        Ok(Self {
+            server,
+            service,
+            tokens,
+        })
+    }
+
+    /// Gets the absolute URL of a path on the server.
+    pub fn url(&self, path: &str) -> String {
+        self.server.url(path)
+    }
+
+    /// Gets the underlying HTTP test server, whose request methods take paths on the server.
+    pub fn http(&self) -> &actix_test::TestServer {
+        &self.server
+    }
+
+    /// Gets the email and password of the root admin.
+    pub fn admin(&self) -> (&str, &str) {
+        let config = self.service.config();
+        (config.root_email(), config.root_password())
+    }
+
+    /// Gets the token seeded for a user and namespace.
+    ///
+    /// # Returns
+    /// The token, or `None` if the user can't access the namespace or wasn't seeded
+    pub fn token(&self, email: &str, namespace: &str) -> Option<&TestToken> {
+        self.tokens
+            .iter()
+            .find(|token| token.email == email && token.namespace == namespace)
+    }
+
+    /// Gets the token seeded for the root admin and a namespace.
+    pub fn admin_token(&self, namespace: &str) -> Option<&TestToken> {
+        self.token(self.service.config().root_email(), namespace)
+    }
+
+    /// Gets every seeded token.
+    pub fn tokens(&self) -> &[TestToken] {
+        &self.tokens
+    }
+}
@@ -0,0 +1,297 @@
+//! HTTPS listener and client certificate authentication.
+//!
+//! When `NERVEMQ_TLS_CERT_FILE` and `NERVEMQ_TLS_KEY_FILE` are set, the server also serves the API
+//! over HTTPS on `NERVEMQ_TLS_BIND`. With `NERVEMQ_TLS_CLIENT_CA_FILE`, clients are additionally
+//! asked for a certificate issued by one of the CAs in that file (mutual TLS). Presenting one is
+//! optional, so browsers and API key clients can keep using the listener.
+//!
+//! Users map client certificates to one of their namespaces with `POST /certificates`, either by a
+//! subject alternative name the certificate must be valid for (so that renewed certificates keep
+//! working), or by the SHA-256 fingerprint of one particular certificate. A request without an
+//! `Authorization` header on a connection with a mapped certificate is authenticated as the user
+//! who mapped it, scoped to the namespace, like with an API key of theirs.
+
+use std::{fs::File, io::BufReader, net::SocketAddr, sync::Arc, time::Duration};
+
+use actix_http::{Extensions, HttpService, Protocol};
+use actix_server::Server;
+use actix_service::{fn_service, map_config, ServiceFactoryExt};
+use actix_web::{cookie::Key, dev::AppConfig, rt::net::TcpStream, web::Data};
+use rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, NoClientAuth},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use serde::Serialize;
+use sqlx::FromRow;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use crate::{
+    auth::{crypto::sha256_hex, session::SqliteSessionStore},
+    config::Config,
+    error::Error,
+    service::Service,
+    Components,
+};
+
+/// How long clients have to complete the TLS handshake.
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum length of the name of a certificate mapping.
+pub const MAX_CERTIFICATE_NAME_LENGTH: usize = 128;
+
+/// How a client certificate is recognized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateMatch {
+    /// Any certificate that is valid for this DNS name or IP address
+    SubjectName(String),
+    /// The certificate with this SHA-256 fingerprint, as lowercase hex
+    Fingerprint(String),
+}
+
+impl CertificateMatch {
+    /// Creates a match from exactly one of a subject name and a fingerprint. Fingerprints may be
+    /// in upper case and separated by colons, like `openssl x509 -fingerprint -sha256` prints them.
+    pub fn new(subject_name: Option<String>, fingerprint: Option<String>) -> Result<Self, Error> {
+        match (subject_name, fingerprint) {
+            (Some(name), None) => {
+                if webpki::SubjectNameRef::try_from_ascii_str(&name).is_err() {
+                    return Err(Error::invalid_parameter(format!(
+                        "subjectName: {name} is not a DNS name or IP address"
+                    )));
+                }
+                Ok(Self::SubjectName(name.to_ascii_lowercase()))
+            }
+            (None, Some(fingerprint)) => {
+                let normalized = fingerprint.replace(':', "").to_ascii_lowercase();
+                if normalized.len() != 64 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(Error::invalid_parameter(format!(
+                        "fingerprint: {fingerprint} is not a SHA-256 fingerprint"
+                    )));
+                }
+                Ok(Self::Fingerprint(normalized))
+            }
+            _ => Err(Error::invalid_parameter(
+                "exactly one of subjectName and fingerprint must be set",
+            )),
+        }
+    }
+}
+
+/// A client certificate mapping, as listed to its owner.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCertificateInfo {
+    pub name: String,
+    pub namespace: String,
+    pub subject_name: Option<String>,
+    pub fingerprint: Option<String>,
+    /// Unix timestamp of the creation
+    pub created_at: u64,
+}
+
+/// The verified certificate a client presented during the TLS handshake, in DER form.
+///
+/// Included in the connection data of requests on connections that have one.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate(pub Vec<u8>);
+
+impl ClientCertificate {
+    /// Computes the SHA-256 fingerprint of the certificate, as lowercase hex.
+    pub fn fingerprint(&self) -> String {
+        sha256_hex(&self.0)
+    }
+
+    /// Checks whether the certificate is valid for a DNS name or IP address.
+    pub fn is_valid_for(&self, subject_name: &str) -> bool {
+        let Ok(cert) = webpki::EndEntityCert::try_from(self.0.as_slice()) else {
+            return false;
+        };
+        let Ok(subject_name) = webpki::SubjectNameRef::try_from_ascii_str(subject_name) else {
+            return false;
+        };
+
+        cert.verify_is_valid_for_subject_name(subject_name).is_ok()
+    }
+}
+
+/// Builds the TLS configuration of the HTTPS listener.
+///
+/// # Returns
+/// The configuration, or `None` if HTTPS is disabled
+pub fn server_config(config: &Config) -> eyre::Result<Option<ServerConfig>> {
+    let Some((cert_file, key_file)) = config.tls_files() else {
+        return Ok(None);
+    };
+
+    let certs = read_certificates(cert_file)?;
+    let key = read_private_key(key_file)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match config.tls_client_ca_file() {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certificates(ca_file)? {
+                roots.add(&cert)?;
+            }
+            builder.with_client_cert_verifier(
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+            )
+        }
+        None => builder.with_client_cert_verifier(NoClientAuth::boxed()),
+    };
+
+    Ok(Some(builder.with_single_cert(certs, key)?))
+}
+
+fn read_certificates(path: &str) -> eyre::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        eyre::bail!("{path} contains no certificates");
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_private_key(path: &str) -> eyre::Result<PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    eyre::bail!("{path} contains no private key")
+}
+
+/// Records the client certificate of a connection, if it presented one.
+fn on_connect(stream: &TlsStream<TcpStream>, extensions: &mut Extensions) {
+    if let Some(cert) = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+    {
+        extensions.insert(ClientCertificate(cert.0.clone()));
+    }
+}
+
+/// Builds the HTTPS server, serving the same application as the plain HTTP one.
+///
+/// # Arguments
+/// * `addr` - Address to bind, unless `listeners` has sockets to serve on
+/// * `listeners` - Sockets passed by systemd, see [`crate::systemd`]
+pub(crate) fn server(
+    addr: SocketAddr,
+    listeners: Vec<std::net::TcpListener>,
+    tls_config: ServerConfig,
+    data: Data<Service>,
+    session_store: SqliteSessionStore,
+    secret_key: Key,
+    components: Components,
+) -> std::io::Result<Server> {
+    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+    let mut server = Server::build();
+    if let Some(workers) = data.config().workers() {
+        server = server.workers(workers);
+    }
+
+    let factory = move || {
+        let acceptor = acceptor.clone();
+        let app = crate::app(
+            data.clone(),
+            session_store.clone(),
+            secret_key.clone(),
+            components,
+        );
+
+        fn_service(move |stream: TcpStream| {
+            let acceptor = acceptor.clone();
+            async move {
+                let peer_addr = stream.peer_addr().ok();
+                let stream = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| actix_http::error::DispatchError::SlowRequestTimeout)?
+                    .map_err(actix_http::error::DispatchError::Io)?;
+
+                Ok((stream, Protocol::Http1, peer_addr))
+            }
+        })
+        .and_then(
+            HttpService::build()
+                .on_connect_ext(on_connect)
+                .finish(map_config(app, |_| AppConfig::default())),
+        )
+    };
+
+    if listeners.is_empty() {
+        server = server.bind("nervemq-tls", addr, factory.clone())?;
+    }
+    for listener in listeners {
+        server = server.listen("nervemq-tls", listener, factory.clone())?;
+    }
+
+    Ok(server.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Client certificate for `orders.internal` and `*.workers.internal` (but not its common name,
+    // `orders`), issued by a throwaway CA.
+    const CERT: &str = include_str!("../tests/fixtures/client.crt");
+
+    fn client_certificate() -> ClientCertificate {
+        let der = rustls_pemfile::certs(&mut CERT.as_bytes())
+            .unwrap()
+            .remove(0);
+        ClientCertificate(der)
+    }
+
+    #[test]
+    fn test_is_valid_for() {
+        let cert = client_certificate();
+        assert!(cert.is_valid_for("orders.internal"));
+        assert!(cert.is_valid_for("a.workers.internal"));
+        assert!(!cert.is_valid_for("billing.internal"));
+        assert!(!cert.is_valid_for("orders"));
+    }
+
+    #[test]
+    fn test_certificate_match() {
+        let fingerprint = client_certificate().fingerprint();
+        let colons = fingerprint
+            .to_ascii_uppercase()
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(
+            CertificateMatch::new(None, Some(colons)).unwrap(),
+            CertificateMatch::Fingerprint(fingerprint)
+        );
+        assert_eq!(
+            CertificateMatch::new(Some("Orders.Internal".to_owned()), None).unwrap(),
+            CertificateMatch::SubjectName("orders.internal".to_owned())
+        );
+
+        for (subject_name, fingerprint) in [
+            (None, None),
+            (Some("orders.internal"), Some("00")),
+            (Some("not a name"), None),
+            (None, Some("abc")),
+        ] {
+            assert!(matches!(
+                CertificateMatch::new(
+                    subject_name.map(str::to_owned),
+                    fingerprint.map(str::to_owned)
+                ),
+                Err(Error::InvalidParameter { .. })
+            ));
+        }
+    }
+}
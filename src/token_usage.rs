@@ -0,0 +1,120 @@
+//! API key usage.
+//!
+//! Every API key records when it was last used and how many requests it has authenticated, so users
+//! can find keys that are no longer needed. Writing to the database on every request would put a
+//! write on the hot path of every authenticated request, so usage is collected in memory and
+//! written in batches every [flush interval](crate::config::TaskIntervals::usage_flush). Usage that
+//! hasn't been written yet is lost if the process exits.
+
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ipnet::IpNet;
+use serde::Serialize;
+use sqlx::{types::Json, FromRow};
+
+use crate::{registry::Registry, service::Service, tasks};
+
+/// An API key, as listed to its owner.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenInfo {
+    pub name: String,
+    pub namespace: String,
+    /// Unix timestamp of the creation, unknown for keys created before it was recorded
+    pub created_at: Option<u64>,
+    /// Unix timestamp of the last authenticated request
+    pub last_used_at: Option<u64>,
+    /// Number of requests the key has authenticated
+    pub request_count: u64,
+    /// Networks the key can be used from, or `None` if it can be used from anywhere
+    pub allowed_networks: Option<Json<Vec<IpNet>>>,
+}
+
+/// Usage of an API key that hasn't been written yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub requests: u64,
+    /// Unix timestamp of the last request
+    pub last_used_at: u64,
+}
+
+impl Usage {
+    fn merge(&mut self, other: Usage) {
+        self.requests += other.requests;
+        self.last_used_at = self.last_used_at.max(other.last_used_at);
+    }
+}
+
+/// Usage collected since it was last written, by key ID.
+#[derive(Debug, Clone, Default)]
+pub struct TokenUsage {
+    pending: Registry<String, Usage>,
+}
+
+impl TokenUsage {
+    /// Records a request authenticated by the key with the given ID.
+    pub fn record(&self, key_id: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let usage = Usage {
+            requests: 1,
+            last_used_at: now,
+        };
+
+        self.pending.update(key_id, |pending| pending.merge(usage));
+    }
+
+    /// Takes the usage collected so far.
+    pub fn take(&self) -> HashMap<String, Usage> {
+        self.pending.take()
+    }
+
+    /// Puts back usage that couldn't be written, to be written with the next batch.
+    pub fn restore(&self, usage: HashMap<String, Usage>) {
+        for (key_id, usage) in usage {
+            self.pending
+                .update(key_id.as_str(), |pending| pending.merge(usage));
+        }
+    }
+}
+
+/// Writes collected usage every flush interval until the process exits.
+pub fn spawn(service: &Service) {
+    let period = service.config().tasks().usage_flush;
+    tasks::spawn_periodic(service, "token_usage", period, |service| async move {
+        let count = service.flush_token_usage().await?;
+        if count > 0 {
+            tracing::debug!(count, "Wrote API key usage");
+        }
+        Ok(())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_restore() {
+        let usage = TokenUsage::default();
+        usage.record("a");
+        usage.record("a");
+        usage.record("b");
+
+        let taken = usage.take();
+        assert_eq!(taken["a"].requests, 2);
+        assert_eq!(taken["b"].requests, 1);
+        assert!(usage.take().is_empty());
+
+        usage.record("a");
+        usage.restore(taken);
+        let taken = usage.take();
+        assert_eq!(taken["a"].requests, 3);
+        assert_eq!(taken["b"].requests, 1);
+    }
+}
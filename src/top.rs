@@ -0,0 +1,179 @@
+//! Live queue statistics in a terminal, for `nervemq top`.
+//!
+//! Operators who can't reach the web dashboard can still watch a server from a shell: the
+//! statistics of every queue are fetched from the management API at an interval, and shown as a
+//! table of queue depths, how fast they change, in-flight messages and failed messages. Dead-letter
+//! queues also show how many messages they gained since `top` started, as a growing dead-letter
+//! queue is usually the first sign of a broken consumer.
+
+use std::{collections::HashMap, fmt::Write as _, time::Duration};
+
+use crate::queue::QueueStatistics;
+
+/// Clears the terminal and moves the cursor to its top left corner.
+pub const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+/// Counts of a queue in the previous refresh, and when `top` started.
+#[derive(Debug, Clone, Copy)]
+struct Counts {
+    pending: u64,
+    message_count: u64,
+}
+
+/// A row of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// `<namespace>/<queue>`
+    pub queue: String,
+    /// Number of messages waiting to be received
+    pub depth: u64,
+    /// Change of the depth per second since the previous refresh, `None` on the first one
+    pub depth_rate: Option<f64>,
+    pub in_flight: u64,
+    pub failed: u64,
+    /// Number of messages gained since `top` started, `None` if the queue isn't a dead-letter
+    /// queue
+    pub dead_letter_growth: Option<i64>,
+}
+
+/// Turns successive statistics into rows, keeping what rates and growth are computed from.
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    initial: HashMap<u64, Counts>,
+    previous: HashMap<u64, Counts>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Computes the rows of a refresh, sorted by namespace and queue.
+    ///
+    /// # Arguments
+    /// * `statistics` - Statistics of every queue
+    /// * `elapsed` - Time since the previous refresh
+    pub fn refresh(&mut self, statistics: Vec<QueueStatistics>, elapsed: Duration) -> Vec<Row> {
+        let seconds = elapsed.as_secs_f64();
+
+        let mut rows = Vec::with_capacity(statistics.len());
+        let mut previous = HashMap::with_capacity(statistics.len());
+        for stats in statistics {
+            let counts = Counts {
+                pending: stats.pending,
+                message_count: stats.message_count,
+            };
+            let initial = *self.initial.entry(stats.queue.id).or_insert(counts);
+
+            rows.push(Row {
+                queue: format!("{}/{}", stats.queue.ns, stats.queue.name),
+                depth: stats.pending,
+                depth_rate: self
+                    .previous
+                    .get(&stats.queue.id)
+                    .filter(|_| seconds > 0.0)
+                    .map(|last| (stats.pending as f64 - last.pending as f64) / seconds),
+                in_flight: stats.in_flight,
+                failed: stats.failed,
+                dead_letter_growth: stats
+                    .dead_letter_queue
+                    .then(|| stats.message_count as i64 - initial.message_count as i64),
+            });
+            previous.insert(stats.queue.id, counts);
+        }
+        self.previous = previous;
+
+        rows.sort_by(|a, b| a.queue.cmp(&b.queue));
+        rows
+    }
+}
+
+/// Renders rows as a table with a header line.
+pub fn render(rows: &[Row], title: &str) -> String {
+    let width = rows
+        .iter()
+        .map(|row| row.queue.len())
+        .chain(["QUEUE".len()])
+        .max()
+        .unwrap_or_default();
+
+    let mut table = format!("{title}\n\n");
+    let _ = writeln!(
+        table,
+        "{:<width$}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}",
+        "QUEUE", "DEPTH", "DEPTH/S", "IN FLIGHT", "FAILED", "DLQ +"
+    );
+    for row in rows {
+        let rate = row
+            .depth_rate
+            .map_or_else(|| "-".to_owned(), |rate| format!("{rate:+.1}"));
+        let growth = row
+            .dead_letter_growth
+            .map_or_else(|| "-".to_owned(), |growth| format!("{growth:+}"));
+        let _ = writeln!(
+            table,
+            "{:<width$}  {:>9}  {:>9}  {:>9}  {:>9}  {:>9}",
+            row.queue, row.depth, rate, row.in_flight, row.failed, growth
+        );
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::Queue;
+
+    fn stats(id: u64, name: &str, pending: u64, dead_letter_queue: bool) -> QueueStatistics {
+        QueueStatistics {
+            queue: Queue {
+                id,
+                ns: "default".to_owned(),
+                name: name.to_owned(),
+                created_by: "admin@example.com".to_owned(),
+            },
+            message_count: pending + 1,
+            avg_size_bytes: 0.0,
+            pending,
+            delivered: 1,
+            failed: 0,
+            in_flight: 1,
+            dead_letter_queue,
+        }
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut dashboard = Dashboard::new();
+
+        let rows = dashboard.refresh(
+            vec![stats(2, "orders", 10, false), stats(1, "dlq", 0, true)],
+            Duration::ZERO,
+        );
+        assert_eq!(rows[0].queue, "default/dlq");
+        assert_eq!(rows[0].depth_rate, None);
+        assert_eq!(rows[0].dead_letter_growth, Some(0));
+        assert_eq!(rows[1].dead_letter_growth, None);
+
+        let rows = dashboard.refresh(
+            vec![stats(2, "orders", 4, false), stats(1, "dlq", 3, true)],
+            Duration::from_secs(2),
+        );
+        assert_eq!(rows[0].depth_rate, Some(1.5));
+        assert_eq!(rows[0].dead_letter_growth, Some(3));
+        assert_eq!(rows[1].depth_rate, Some(-3.0));
+
+        // Growth is counted from the start, rates from the previous refresh.
+        let rows = dashboard.refresh(vec![stats(1, "dlq", 5, true)], Duration::from_secs(1));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].depth_rate, Some(2.0));
+        assert_eq!(rows[0].dead_letter_growth, Some(5));
+
+        let table = render(&rows, "title");
+        assert!(table.starts_with("title\n\n"), "{table}");
+        assert!(table.contains("default/dlq"), "{table}");
+        assert!(table.contains("+2.0"), "{table}");
+        assert!(table.contains("+5"), "{table}");
+    }
+}
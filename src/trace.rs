@@ -0,0 +1,85 @@
+//! Message tracing.
+//!
+//! Every message is assigned a trace id when it is sent. The id stays with the message for its
+//! whole lifetime, including when it is moved to a dead-letter queue, and is recorded in the
+//! message history once it is deleted. Each step of the message's lifecycle is recorded in the
+//! `message_traces` table, so that a lost message can be followed from queue to queue with
+//! [`Service::get_message_trace`].
+//!
+//! Trace events are kept for the configured
+//! [`trace_retention`](crate::config::Config::trace_retention) period, independently of the
+//! queues they refer to, so traces of deleted queues remain available until then.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{service::Service, tasks};
+
+/// Maximum number of events returned for a single trace.
+pub const MAX_TRACE_EVENTS: u64 = 1000;
+
+/// Name of the message attribute the trace id is returned as when receiving messages.
+pub const TRACE_ID_ATTRIBUTE: &str = "NerveMqTraceId";
+
+/// A step in the lifecycle of a traced message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum TraceEvent {
+    /// The message was sent to the queue
+    Sent,
+    /// The message was delivered to a consumer
+    Received,
+    /// The message used up its retries and was moved to this dead-letter queue
+    DeadLettered,
+    /// The message was deleted (acknowledged) by a consumer
+    Deleted,
+    /// The message was removed by purging the queue
+    Purged,
+    /// The message was removed by deleting the queue
+    QueueDeleted,
+}
+
+/// A recorded step in the lifecycle of a traced message.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    /// ID the message had in the queue
+    pub message: u64,
+    /// Namespace of the queue the message was in after the event
+    pub namespace: String,
+    /// Queue the message was in after the event
+    pub queue: String,
+    pub event: TraceEvent,
+    /// Number of times the message had been received, for [`TraceEvent::Received`]
+    pub receive_count: Option<u64>,
+    /// Consumer group the event happened in, if any
+    pub consumer_group: Option<String>,
+    /// Unix timestamp of the event
+    pub at: u64,
+}
+
+/// The recorded lifecycle of every message sharing a trace id, oldest event first.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    pub trace_id: String,
+    pub events: Vec<TraceEntry>,
+}
+
+/// Generates a new trace id, formatted like a W3C trace context trace id.
+pub fn new_trace_id() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// Prunes expired trace events every prune interval until the process exits.
+pub fn spawn(service: &Service) {
+    let period = service.config().tasks().prune;
+    tasks::spawn_periodic(service, "trace", period, |service| async move {
+        let count = service.prune_message_traces().await?;
+        if count > 0 {
+            tracing::debug!(count, "Pruned message traces");
+        }
+        Ok(())
+    });
+}
@@ -0,0 +1,310 @@
+//! Bulk user import.
+//!
+//! Whole teams can be onboarded at once with `POST /admin/users:import` or
+//! `nervemq users import <file>`. The users are either:
+//!
+//! - JSON (`application/json`, or files ending in `.json`): an array of [`ImportUser`] objects,
+//!   for example `[{"email": "alice@example.com", "role": "admin", "namespaces": ["orders"]}]`
+//! - CSV (`text/csv`, or any other file): a header row naming the columns `email`, `role`
+//!   (optional, `user` or `admin`) and `namespaces` (optional, separated by `;`), followed by one
+//!   row per user. Fields can be quoted as usual.
+//!
+//! ```text
+//! email,role,namespaces
+//! alice@example.com,admin,orders;billing
+//! bob@example.com,,orders
+//! ```
+//!
+//! Imports are all or nothing: the users are only created if every row is valid, and the result
+//! of every row is reported either way. Imported users have no password, and can't log in until
+//! they set one by resetting it.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{api::auth::Role, config::Config, error::Error, kms::KeyManager, service::Service};
+
+/// Maximum number of users per import.
+pub const MAX_IMPORT_USERS: usize = 1000;
+
+/// Content type of JSON imports.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Content type of CSV imports.
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+
+/// Separator of the namespaces in the `namespaces` column of CSV imports.
+pub const NAMESPACE_SEPARATOR: char = ';';
+
+/// A user to import.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportUser {
+    pub email: String,
+    #[serde(default)]
+    pub role: Role,
+    /// Namespaces the user can access
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+}
+
+/// Result of an import.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportUsersResponse {
+    /// Number of users that were created, either all or none
+    pub created: usize,
+    pub rows: Vec<ImportRow>,
+}
+
+/// Result of one row of an import.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRow {
+    /// Position of the user in the import, starting at 1
+    pub row: usize,
+    pub email: String,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportStatus {
+    /// The user was created
+    Created,
+    /// The row is invalid
+    Failed,
+    /// The row is valid, but nothing was created because other rows are invalid
+    Skipped,
+}
+
+/// Parses the users of an import according to its content type.
+///
+/// # Errors
+/// Returns [`Error::InvalidParameter`] if the content type is not supported, or the body is
+/// malformed
+pub fn parse(content_type: &str, data: &[u8]) -> Result<Vec<ImportUser>, Error> {
+    match content_type {
+        JSON_CONTENT_TYPE | "" => serde_json::from_slice(data)
+            .map_err(|e| Error::invalid_parameter(format!("request body: {e}"))),
+        CSV_CONTENT_TYPE => parse_csv(data),
+        other => Err(Error::invalid_parameter(format!(
+            "Content-Type: expected {JSON_CONTENT_TYPE} or {CSV_CONTENT_TYPE}, got {other:?}"
+        ))),
+    }
+}
+
+/// Parses CSV users, see the module documentation for the columns.
+fn parse_csv(data: &[u8]) -> Result<Vec<ImportUser>, Error> {
+    let data = std::str::from_utf8(data)
+        .map_err(|e| Error::invalid_parameter(format!("request body: not valid UTF-8: {e}")))?;
+
+    let mut records = csv_records(data)?.into_iter();
+    let Some((_, header)) = records.next() else {
+        return Ok(Vec::new());
+    };
+
+    let mut columns = HashMap::new();
+    for (index, name) in header.iter().enumerate() {
+        let name = name.trim().to_ascii_lowercase();
+        if !matches!(name.as_str(), "email" | "role" | "namespaces") {
+            return Err(Error::invalid_parameter(format!(
+                "line 1: unknown column {name:?}, expected email, role and namespaces"
+            )));
+        }
+        columns.insert(name, index);
+    }
+    let Some(&email) = columns.get("email") else {
+        return Err(Error::invalid_parameter("line 1: missing column email"));
+    };
+
+    records
+        .map(|(line, record)| {
+            let field = |column: &str| {
+                columns
+                    .get(column)
+                    .and_then(|index| record.get(*index))
+                    .map(|field| field.trim())
+                    .filter(|field| !field.is_empty())
+            };
+
+            let role = match field("role") {
+                Some(role) => {
+                    serde_json::from_value(serde_json::Value::from(role)).map_err(|_| {
+                        Error::invalid_parameter(format!(
+                            "line {line}: role must be user or admin, got {role:?}"
+                        ))
+                    })?
+                }
+                None => Role::default(),
+            };
+            let namespaces = field("namespaces")
+                .map(|namespaces| {
+                    namespaces
+                        .split(NAMESPACE_SEPARATOR)
+                        .map(str::trim)
+                        .filter(|namespace| !namespace.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Ok(ImportUser {
+                email: record
+                    .get(email)
+                    .map(|email| email.trim())
+                    .unwrap_or("")
+                    .to_owned(),
+                role,
+                namespaces,
+            })
+        })
+        .collect()
+}
+
+/// Splits CSV into records of fields, with the line each record starts on. Fields may be quoted
+/// with `"`, and quotes in quoted fields are escaped by doubling them. Blank lines are skipped.
+fn csv_records(data: &str) -> Result<Vec<(usize, Vec<String>)>, Error> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                field.push(c);
+                line += 1;
+            }
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                let fields = std::mem::take(&mut record);
+                if fields.iter().any(|field| !field.is_empty()) {
+                    records.push((start, fields));
+                }
+                line += 1;
+                start = line;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(Error::invalid_parameter(format!(
+            "line {start}: unterminated quoted field"
+        )));
+    }
+
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push((start, record));
+    }
+
+    Ok(records)
+}
+
+/// Imports the users in a file into the configured database.
+///
+/// # Arguments
+/// * `config` - Service configuration
+/// * `kms_factory` - Factory function to create a key management service
+/// * `path` - File with the users, as JSON if it ends in `.json` and as CSV otherwise
+///
+/// # Returns
+/// The result of the import
+#[bon::builder(finish_fn = start)]
+pub async fn import_file<K, F, R>(
+    config: Config,
+    kms_factory: K,
+    path: &Path,
+) -> Result<ImportUsersResponse, Error>
+where
+    K: FnOnce(SqlitePool) -> F,
+    F: std::future::Future<Output = Result<R, Error>>,
+    R: KeyManager,
+{
+    let data = std::fs::read(path).map_err(Error::internal)?;
+    let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => JSON_CONTENT_TYPE,
+        _ => CSV_CONTENT_TYPE,
+    };
+    let users = parse(content_type, &data)?;
+
+    let service = Service::connect_with()
+        .config(config)
+        .kms_factory(kms_factory)
+        .call()
+        .await?;
+
+    service.import_users(users).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let data = "Email,namespaces,role\r\n\
+            alice@example.com,orders;billing,admin\r\n\
+            \r\n\
+            \"bob@example.com\",\"orders; \"\"quoted\"\"\n\",\n\
+            carol@example.com";
+
+        let users = parse(CSV_CONTENT_TYPE, data.as_bytes()).unwrap();
+        assert_eq!(users.len(), 3);
+        assert_eq!(users[0].email, "alice@example.com");
+        assert_eq!(users[0].role, Role::Admin);
+        assert_eq!(users[0].namespaces, ["orders", "billing"]);
+        assert_eq!(users[1].role, Role::User);
+        assert_eq!(users[1].namespaces, ["orders", "\"quoted\""]);
+        assert_eq!(users[2].email, "carol@example.com");
+        assert!(users[2].namespaces.is_empty());
+
+        for (data, line) in [
+            ("email,name\n", "line 1"),
+            ("role\nadmin\n", "line 1"),
+            (
+                "email,role\na@example.com,user\n\nb@example.com,root\n",
+                "line 4",
+            ),
+            ("email\n\"a@example.com\n", "line 2"),
+        ] {
+            let err = parse(CSV_CONTENT_TYPE, data.as_bytes()).unwrap_err();
+            assert!(err.to_string().contains(line), "{data:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let users = parse(
+            JSON_CONTENT_TYPE,
+            br#"[{"email": "alice@example.com", "role": "admin", "namespaces": ["orders"]}]"#,
+        )
+        .unwrap();
+        assert_eq!(users[0].role, Role::Admin);
+
+        assert!(parse(JSON_CONTENT_TYPE, br#"[{"mail": "alice@example.com"}]"#).is_err());
+        assert!(parse("text/plain", b"").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Version and build information.
+//!
+//! `GET /api/version` tells clients which server they are talking to: its version, the commit it
+//! was built from, the optional features it was compiled with, and the SQS operations it
+//! implements. Client tooling can check for an operation there instead of calling it and
+//! getting an error; requests for operations NerveMQ doesn't implement fail with `InvalidMethod`,
+//! whose message points at the endpoint.
+
+use serde::Serialize;
+use strum::VariantNames;
+
+use crate::sqs::method::Method;
+
+/// Path of the version endpoint.
+pub const VERSION_PATH: &str = "/api/version";
+
+/// What a server is and what it supports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub version: &'static str,
+    /// Commit the server was built from, if it was built from a git checkout
+    pub git_sha: Option<&'static str>,
+    /// Cargo features the server was compiled with
+    pub features: Vec<&'static str>,
+    /// Names of the implemented SQS operations, like `SendMessage`
+    pub sqs_operations: &'static [&'static str],
+}
+
+impl VersionInfo {
+    /// Returns the information about this build.
+    pub fn current() -> Self {
+        let features = [
+            ("asm", cfg!(feature = "asm")),
+            ("aws-kms", cfg!(feature = "aws-kms")),
+            ("dashboard", cfg!(feature = "dashboard")),
+            ("oidc", cfg!(feature = "oidc")),
+            ("postgres", cfg!(feature = "postgres")),
+            ("smtp", cfg!(feature = "smtp")),
+            ("sqlcipher", cfg!(feature = "sqlcipher")),
+            ("testing", cfg!(feature = "testing")),
+        ];
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: option_env!("NERVEMQ_GIT_SHA"),
+            features: features
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+            sqs_operations: Method::VARIANTS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current() {
+        let info = VersionInfo::current();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(info.sqs_operations.contains(&"SendMessage"));
+        assert!(info.sqs_operations.contains(&"StartMessageMoveTask"));
+        assert!(!info.sqs_operations.contains(&"AddPermission"));
+        assert_eq!(info.features.contains(&"oidc"), cfg!(feature = "oidc"));
+    }
+}
@@ -0,0 +1,361 @@
+//! Webhooks for administrative events.
+//!
+//! External systems (a CMDB, a chat bot) can subscribe to administrative changes, such as queues
+//! being created or deleted and users being created, deleted or changing roles, and to alerts
+//! about queues left without live consumers (see [`crate::consumers`]). Each event is POSTed as
+//! JSON to every subscribed URL, independently of message traffic.
+//!
+//! Deliveries are signed, so receivers can check that they come from NerveMQ: the
+//! `X-NerveMQ-Signature` header holds `sha256=` followed by the hex HMAC-SHA256, keyed with the
+//! subscription's secret, of the `X-NerveMQ-Timestamp` header, a `.`, and the body. Receivers
+//! should reject old timestamps to prevent replays.
+//!
+//! Failed deliveries (errors and non-2xx responses) are retried with exponential backoff, up to
+//! [`WEBHOOK_MAX_ATTEMPTS`] times. Retries are kept in memory, so deliveries still pending when
+//! the process exits are lost.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use hyper::{client::HttpConnector, Body, Client, Request};
+use hyper_rustls::HttpsConnector;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{types::Json, FromRow};
+use tokio::sync::broadcast;
+
+use crate::{events::Event, service::Service};
+
+/// Maximum number of attempts to deliver an event to a webhook.
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry of a delivery. Every further retry waits twice as long.
+pub const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a webhook may take to respond before the attempt fails.
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header with the signature of a delivery.
+pub const SIGNATURE_HEADER: &str = "x-nervemq-signature";
+
+/// Header with the Unix timestamp a delivery was signed at.
+pub const TIMESTAMP_HEADER: &str = "x-nervemq-timestamp";
+
+/// Header with the type of the delivered event.
+pub const EVENT_HEADER: &str = "x-nervemq-event";
+
+/// Header with the ID of a delivery, the same for all of its attempts.
+pub const DELIVERY_HEADER: &str = "x-nervemq-delivery";
+
+/// A subscription to administrative events.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub id: u64,
+    pub url: String,
+    /// Key deliveries are signed with. Only returned when the webhook is created.
+    #[serde(skip)]
+    pub secret: String,
+    /// Event types to deliver, or `None` for every administrative event
+    pub events: Option<Json<Vec<String>>>,
+    /// Unix timestamp of the creation
+    pub created_at: u64,
+}
+
+impl Webhook {
+    /// Returns whether the webhook subscribes to an event.
+    pub fn wants(&self, event: &Event) -> bool {
+        event.is_admin()
+            && self
+                .events
+                .as_ref()
+                .is_none_or(|events| events.iter().any(|kind| kind == event.kind()))
+    }
+}
+
+/// Request to subscribe to administrative events.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: url::Url,
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+}
+
+/// A newly created webhook, with the secret its deliveries are signed with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+/// The body of a delivery.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery {
+    /// ID of the delivery, also sent in [`DELIVERY_HEADER`]
+    pub id: String,
+    /// Unix timestamp of the event
+    pub occurred_at: u64,
+    pub event: Event,
+}
+
+/// Signs a delivery body.
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub(crate) type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Builds the client webhooks are called with, for HTTP and HTTPS URLs.
+pub(crate) fn http_client() -> HttpClient {
+    Client::builder().build(
+        hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build(),
+    )
+}
+
+/// Delivers administrative events to the subscribed webhooks until the bus is closed.
+pub async fn run(service: Service, mut events: broadcast::Receiver<Event>) {
+    let client = http_client();
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) if event.is_admin() => event,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!(missed, "Webhooks fell behind the event bus");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let webhooks = match service.list_webhooks().await {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                tracing::error!("Failed to list webhooks: {e}");
+                continue;
+            }
+        };
+
+        let delivery = Delivery {
+            id: uuid::Uuid::now_v7().to_string(),
+            occurred_at: now(),
+            event,
+        };
+
+        for webhook in webhooks.into_iter().filter(|w| w.wants(&delivery.event)) {
+            tokio::spawn(deliver(client.clone(), webhook, delivery.clone()));
+        }
+    }
+}
+
+/// Delivers an event to a webhook, retrying until it succeeds or runs out of attempts.
+async fn deliver(client: HttpClient, webhook: Webhook, delivery: Delivery) {
+    let body = match serde_json::to_vec(&delivery) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to serialize webhook delivery: {e}");
+            return;
+        }
+    };
+
+    let mut delay = WEBHOOK_RETRY_DELAY;
+    for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+        match attempt_delivery(&client, &webhook, &delivery, &body).await {
+            Ok(()) => return,
+            Err(e) => tracing::warn!(
+                webhook = webhook.id,
+                delivery = delivery.id,
+                attempt,
+                "Webhook delivery failed: {e}"
+            ),
+        }
+
+        if attempt < WEBHOOK_MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    tracing::error!(
+        webhook = webhook.id,
+        delivery = delivery.id,
+        "Giving up on webhook delivery after {WEBHOOK_MAX_ATTEMPTS} attempts"
+    );
+}
+
+async fn attempt_delivery(
+    client: &HttpClient,
+    webhook: &Webhook,
+    delivery: &Delivery,
+    body: &[u8],
+) -> eyre::Result<()> {
+    let timestamp = now();
+
+    let request = Request::post(&webhook.url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, delivery.event.kind())
+        .header(DELIVERY_HEADER, &delivery.id)
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, timestamp, body))
+        .body(Body::from(body.to_vec()))?;
+
+    let response = tokio::time::timeout(WEBHOOK_TIMEOUT, client.request(request)).await??;
+
+    if !response.status().is_success() {
+        eyre::bail!("webhook responded with {}", response.status());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_identity::Identity;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{config::Config, error::Error, kms::memory::InMemoryKeyManager};
+
+    /// Accepts one HTTP request, answers it with `200 OK`, and returns its head and body.
+    async fn receive(listener: &tokio::net::TcpListener) -> (String, Vec<u8>) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+
+        loop {
+            let read = socket.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..read]);
+
+            let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&data[..end]).to_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            if data.len() >= end + 4 + length {
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+                return (head, data[end + 4..end + 4 + length].to_vec());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivery() {
+        let service = Service::connect_with()
+            .config(Config::in_memory())
+            .kms_factory(|_| async move { Ok(InMemoryKeyManager::new()) })
+            .call()
+            .await
+            .unwrap();
+        let root = || Identity::mock(service.config().root_email().to_owned());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        assert!(matches!(
+            service
+                .create_webhook(CreateWebhookRequest {
+                    url: "http://localhost/".parse().unwrap(),
+                    events: Some(vec!["messageSent".to_owned()]),
+                })
+                .await,
+            Err(Error::InvalidParameter { .. })
+        ));
+
+        let webhook = service
+            .create_webhook(CreateWebhookRequest {
+                url: format!("http://{}/hook", listener.local_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+                events: Some(vec!["queueCreated".to_owned()]),
+            })
+            .await
+            .unwrap();
+
+        tokio::spawn(run(service.clone(), service.events().subscribe()));
+
+        service.create_namespace("t", root()).await.unwrap();
+        service
+            .create_queue("t", "q", HashMap::new(), HashMap::new(), root())
+            .await
+            .unwrap();
+
+        let (head, body) = receive(&listener).await;
+        assert!(head.starts_with("post /hook "));
+        assert!(head.contains("x-nervemq-event: queuecreated"));
+
+        let timestamp: u64 = head
+            .lines()
+            .find_map(|line| line.strip_prefix("x-nervemq-timestamp: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let signature = sign(&webhook.secret, timestamp, &body);
+        assert!(head.contains(&format!("x-nervemq-signature: {signature}")));
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["event"]["type"], "queueCreated");
+        assert_eq!(body["event"]["name"], "q");
+
+        service.delete_webhook(webhook.webhook.id).await.unwrap();
+        assert!(service.list_webhooks().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sign() {
+        // Computed with `printf '1700000000.{}' | openssl dgst -sha256 -hmac secret`.
+        assert_eq!(
+            sign("secret", 1_700_000_000, b"{}"),
+            "sha256=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+
+    #[test]
+    fn test_wants() {
+        let webhook = |events: Option<Vec<&str>>| Webhook {
+            id: 1,
+            url: "http://localhost".to_owned(),
+            secret: String::new(),
+            events: events.map(|events| Json(events.into_iter().map(str::to_owned).collect())),
+            created_at: 0,
+        };
+        let deleted = Event::UserDeleted {
+            email: "a@example.com".to_owned(),
+        };
+
+        assert!(webhook(None).wants(&deleted));
+        assert!(webhook(Some(vec!["userDeleted"])).wants(&deleted));
+        assert!(!webhook(Some(vec!["queueCreated"])).wants(&deleted));
+        assert!(!webhook(None).wants(&Event::MessageSent {
+            queue: 1,
+            message: 1
+        }));
+    }
+}
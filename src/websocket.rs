@@ -0,0 +1,473 @@
+//! WebSocket consumers.
+//!
+//! `GET /queue/{namespace}/{queue}/ws` upgrades the connection to a WebSocket over which a
+//! consumer is sent the messages of a queue as soon as they are sent, instead of polling for them
+//! with ReceiveMessage. Messages are received like ReceiveMessage receives them: they are hidden
+//! for the visibility timeout and count as a try.
+//!
+//! Every frame is a JSON text frame with a `type`. The server sends each message as
+//! `{"type": "message", "message": {...}}`, with the message as ReceiveMessage returns it, and
+//! reports failures as `{"type": "error", "code": "...", "message": "..."}`, with the
+//! `receiptHandle` the failure is about, if any. The consumer settles each message with
+//!
+//! - `{"type": "ack", "receiptHandle": "..."}`, which deletes it like DeleteMessage, or
+//! - `{"type": "nack", "receiptHandle": "..."}`, which makes it receivable again right away, like
+//!   ChangeMessageVisibility with a timeout of 0, or after `visibilityTimeout` seconds if set. A
+//!   `reason` is recorded as the failure reason of the delivery.
+//!
+//! At most `prefetch` messages are unsettled at a time, and the ones that are still unsettled
+//! when the connection closes become receivable again right away. A message that isn't settled
+//! within its visibility timeout stops counting against `prefetch`, and is delivered again like
+//! it would be to any other consumer. If receiving fails, for example because the server entered
+//! maintenance mode, the error is sent and the connection closed.
+
+use std::{collections::HashMap, convert::Infallible, time::Duration};
+
+use actix_http::ws::{self, CloseCode, CloseReason, Frame};
+use actix_identity::Identity;
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::Instant,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    error::Error,
+    events::Event,
+    service::{ReceiveOptions, Service},
+    sqs::types::SqsMessage,
+    stream::KEEP_ALIVE_INTERVAL,
+};
+
+/// Number of unsettled messages a consumer is sent if it doesn't ask for another number.
+pub const DEFAULT_PREFETCH: u64 = 10;
+
+/// Maximum number of unsettled messages a consumer can ask for.
+pub const MAX_PREFETCH: u64 = 100;
+
+/// Maximum number of messages received at once, as allowed by ReceiveMessage.
+const MAX_BATCH: u64 = 10;
+
+/// How often the queue is checked for messages that became receivable without being sent, like
+/// delayed messages and ones whose visibility timeout expired.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Query parameters of a WebSocket consumer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumeQuery {
+    /// Visibility timeout of received messages in seconds
+    pub visibility_timeout: Option<u64>,
+    /// Consumer group to receive the messages for
+    pub consumer_group: Option<String>,
+    /// Maximum number of unsettled messages, [`DEFAULT_PREFETCH`] by default
+    pub prefetch: Option<u64>,
+}
+
+/// A frame sent by the consumer.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientFrame {
+    /// The message was processed and is deleted
+    #[serde(rename_all = "camelCase")]
+    Ack { receipt_handle: String },
+    /// The message wasn't processed and is made receivable again
+    #[serde(rename_all = "camelCase")]
+    Nack {
+        receipt_handle: String,
+        /// Seconds before the message can be received again, 0 by default
+        #[serde(default)]
+        visibility_timeout: Option<u64>,
+        /// Why processing failed
+        #[serde(default)]
+        reason: Option<String>,
+    },
+}
+
+/// A frame sent to the consumer.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerFrame<'a> {
+    Message {
+        message: &'a SqsMessage,
+    },
+    #[serde(rename_all = "camelCase")]
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        receipt_handle: Option<&'a str>,
+        code: &'a str,
+        message: String,
+    },
+}
+
+/// The connection was closed, or is closing.
+struct Closed;
+
+/// State of a consumer's connection.
+struct Session {
+    service: Service,
+    namespace: String,
+    queue: String,
+    queue_id: u64,
+    consumer_group: Option<String>,
+    /// Email of the user the consumer authenticated as
+    user: String,
+    options: ReceiveOptions,
+    visibility_timeout: Duration,
+    prefetch: usize,
+    /// When each unsettled message becomes visible again, by receipt handle
+    in_flight: HashMap<String, Instant>,
+    codec: ws::Codec,
+    tx: mpsc::Sender<Bytes>,
+}
+
+/// Upgrades a request to a WebSocket and serves a consumer over it until the connection closes.
+///
+/// # Arguments
+/// * `service` - Service to receive from
+/// * `req` - The upgrade request
+/// * `payload` - Frames sent by the consumer
+/// * `namespace` - Namespace containing the queue
+/// * `queue` - Queue name
+/// * `query` - Visibility timeout, consumer group and prefetch of the consumer
+/// * `identity` - Identity of the authenticated user
+pub async fn consume(
+    service: Service,
+    req: &actix_web::HttpRequest,
+    payload: actix_web::web::Payload,
+    namespace: String,
+    queue: String,
+    query: ConsumeQuery,
+    identity: &Identity,
+) -> Result<actix_web::HttpResponse, Error> {
+    let prefetch = query.prefetch.unwrap_or(DEFAULT_PREFETCH);
+    if !(1..=MAX_PREFETCH).contains(&prefetch) {
+        return Err(Error::invalid_parameter(format!(
+            "prefetch: must be between 1 and {MAX_PREFETCH}"
+        )));
+    }
+
+    let queue_id = service
+        .get_queue_id(&namespace, &queue, service.db())
+        .await?
+        .ok_or_else(|| Error::queue_not_found(&queue, &namespace))?;
+
+    let visibility_timeout = match query.visibility_timeout {
+        Some(timeout) => Duration::from_secs(timeout),
+        None => service.visibility_timeout(queue_id).await?,
+    };
+
+    let mut response =
+        ws::handshake(req.head()).map_err(|e| Error::invalid_parameter(e.to_string()))?;
+
+    let (tx, rx) = mpsc::channel(16);
+    let session = Session {
+        options: ReceiveOptions::builder()
+            .visibility_timeout(visibility_timeout)
+            .wait_time(Duration::ZERO)
+            .attribute_names(["All".to_owned()].into())
+            .message_attribute_names(["All".to_owned()].into())
+            .maybe_consumer_group(query.consumer_group.clone())
+            .build(),
+        service,
+        namespace,
+        queue,
+        queue_id,
+        consumer_group: query.consumer_group,
+        user: identity.id()?,
+        visibility_timeout,
+        prefetch: prefetch as usize,
+        in_flight: HashMap::new(),
+        codec: ws::Codec::new(),
+        tx,
+    };
+    actix_web::rt::spawn(session.run(payload));
+
+    let body = actix_http::body::BodyStream::new(
+        tokio_stream::wrappers::ReceiverStream::new(rx).map(Ok::<_, Infallible>),
+    );
+    Ok(response
+        .message_body(actix_http::body::BoxBody::new(body))
+        .map_err(|e| Error::internal(eyre::eyre!("{e}")))?
+        .into())
+}
+
+impl Session {
+    async fn run(mut self, mut payload: actix_web::web::Payload) {
+        let mut events = self.service.events().subscribe();
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        let mut ping =
+            tokio::time::interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+        let mut buf = BytesMut::new();
+        let mut receive = true;
+
+        loop {
+            if receive {
+                receive = false;
+                if self.receive().await.is_err() {
+                    break;
+                }
+            }
+
+            tokio::select! {
+                chunk = payload.next() => {
+                    let Some(Ok(chunk)) = chunk else {
+                        break;
+                    };
+                    buf.extend_from_slice(&chunk);
+
+                    match self.handle_frames(&mut buf).await {
+                        Ok(settled) => receive = settled,
+                        Err(Closed) => break,
+                    }
+                }
+                event = events.recv() => match event {
+                    Ok(Event::MessageSent { queue, .. }) => receive = queue == self.queue_id,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) => receive = true,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = poll.tick() => receive = true,
+                _ = ping.tick() => {
+                    if self.send(ws::Message::Ping(Bytes::new())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.release().await;
+    }
+
+    /// Handles the complete frames in `buf`.
+    ///
+    /// # Returns
+    /// Whether a message was settled, so that another one can be received
+    async fn handle_frames(&mut self, buf: &mut BytesMut) -> Result<bool, Closed> {
+        let mut settled = false;
+
+        loop {
+            let frame = match self.codec.decode(buf) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(settled),
+                Err(e) => {
+                    tracing::debug!("Invalid WebSocket frame: {e}");
+                    self.close(CloseCode::Protocol).await;
+                    return Err(Closed);
+                }
+            };
+
+            match frame {
+                Frame::Text(text) => {
+                    let result = match serde_json::from_slice(&text) {
+                        Ok(frame) => self.settle(frame).await,
+                        Err(e) => self.send_error(None, &Error::from(e)).await,
+                    };
+                    result?;
+                    settled = true;
+                }
+                Frame::Ping(data) => self.send(ws::Message::Pong(data)).await?,
+                Frame::Pong(_) => {}
+                Frame::Close(_) => {
+                    self.close(CloseCode::Normal).await;
+                    return Err(Closed);
+                }
+                Frame::Binary(_) | Frame::Continuation(_) => {
+                    self.close(CloseCode::Unsupported).await;
+                    return Err(Closed);
+                }
+            }
+        }
+    }
+
+    /// Acks or nacks a message.
+    async fn settle(&mut self, frame: ClientFrame) -> Result<(), Closed> {
+        let receipt_handle = match &frame {
+            ClientFrame::Ack { receipt_handle } | ClientFrame::Nack { receipt_handle, .. } => {
+                receipt_handle.clone()
+            }
+        };
+        let message_id = match self
+            .service
+            .resolve_receipt_handle(&self.namespace, &self.queue, &receipt_handle)
+            .await
+        {
+            Ok(message_id) => message_id,
+            Err(e) => return self.send_error(Some(&receipt_handle), &e).await,
+        };
+
+        let identity = Identity::mock(self.user.clone());
+        let consumer_group = self.consumer_group.as_deref();
+        let result = match frame {
+            ClientFrame::Ack { .. } => {
+                self.service
+                    .delete_message(
+                        &self.namespace,
+                        &self.queue,
+                        message_id,
+                        consumer_group,
+                        identity,
+                    )
+                    .await
+            }
+            ClientFrame::Nack {
+                visibility_timeout,
+                reason,
+                ..
+            } => {
+                self.service
+                    .change_message_visibility(
+                        &self.namespace,
+                        &self.queue,
+                        message_id,
+                        Duration::from_secs(visibility_timeout.unwrap_or(0)),
+                        reason.as_deref(),
+                        consumer_group,
+                        identity,
+                    )
+                    .await
+            }
+        };
+
+        // A message that can't be settled anymore was either settled already or isn't in flight.
+        self.in_flight.remove(&receipt_handle);
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => self.send_error(Some(&receipt_handle), &e).await,
+        }
+    }
+
+    /// Receives messages until `prefetch` of them are unsettled or the queue has no more.
+    async fn receive(&mut self) -> Result<(), Closed> {
+        let now = Instant::now();
+        self.in_flight.retain(|_, visible_at| *visible_at > now);
+
+        while self.in_flight.len() < self.prefetch {
+            let options = ReceiveOptions {
+                max_messages: ((self.prefetch - self.in_flight.len()) as u64).min(MAX_BATCH),
+                ..self.options.clone()
+            };
+            let messages = match self
+                .service
+                .sqs_recv_batch(&self.namespace, &self.queue, options)
+                .await
+            {
+                Ok(messages) => messages,
+                Err(e) => {
+                    self.send_error(None, &e).await?;
+                    self.close(CloseCode::Error).await;
+                    return Err(Closed);
+                }
+            };
+            if messages.is_empty() {
+                break;
+            }
+
+            let visible_at = Instant::now() + self.visibility_timeout;
+            for message in &messages {
+                self.in_flight
+                    .insert(message.receipt_handle.clone(), visible_at);
+                self.send_json(&ServerFrame::Message { message }).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the messages that are still unsettled receivable again.
+    async fn release(&mut self) {
+        let handles = std::mem::take(&mut self.in_flight)
+            .into_keys()
+            .collect::<Vec<_>>();
+        let message_ids = match self
+            .service
+            .resolve_receipt_handles(&self.namespace, &self.queue, &handles)
+            .await
+        {
+            Ok(message_ids) => message_ids,
+            Err(e) => {
+                tracing::debug!("Failed to release unsettled messages: {e}");
+                return;
+            }
+        };
+        for message_id in message_ids.into_iter().flatten() {
+            if let Err(e) = self
+                .service
+                .change_message_visibility(
+                    &self.namespace,
+                    &self.queue,
+                    message_id,
+                    Duration::ZERO,
+                    None,
+                    self.consumer_group.as_deref(),
+                    Identity::mock(self.user.clone()),
+                )
+                .await
+            {
+                tracing::debug!(message_id, "Failed to release unsettled message: {e}");
+            }
+        }
+    }
+
+    async fn send_error(&self, receipt_handle: Option<&str>, error: &Error) -> Result<(), Closed> {
+        self.send_json(&ServerFrame::Error {
+            receipt_handle,
+            code: error.code(),
+            message: error.to_string(),
+        })
+        .await
+    }
+
+    async fn send_json(&self, frame: &ServerFrame<'_>) -> Result<(), Closed> {
+        let text = serde_json::to_string(frame).map_err(|_| Closed)?;
+        self.send(ws::Message::Text(text.into())).await
+    }
+
+    /// Sends a close frame. The connection is closed afterwards either way.
+    async fn close(&self, code: CloseCode) {
+        let _ = self
+            .send(ws::Message::Close(Some(CloseReason::from(code))))
+            .await;
+    }
+
+    async fn send(&self, message: ws::Message) -> Result<(), Closed> {
+        let mut buf = BytesMut::new();
+        self.codec
+            .clone()
+            .encode(message, &mut buf)
+            .map_err(|_| Closed)?;
+        self.tx.send(buf.freeze()).await.map_err(|_| Closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_frame() {
+        assert_eq!(
+            serde_json::from_str::<ClientFrame>(r#"{"type": "ack", "receiptHandle": "1"}"#)
+                .unwrap(),
+            ClientFrame::Ack {
+                receipt_handle: "1".to_owned()
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<ClientFrame>(
+                r#"{"type": "nack", "receiptHandle": "2", "reason": "timeout"}"#
+            )
+            .unwrap(),
+            ClientFrame::Nack {
+                receipt_handle: "2".to_owned(),
+                visibility_timeout: None,
+                reason: Some("timeout".to_owned()),
+            }
+        );
+        assert!(serde_json::from_str::<ClientFrame>(r#"{"type": "drop"}"#).is_err());
+    }
+}
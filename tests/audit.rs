@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use nervemq::testing::{Role, TestServer, TestUser, NAMESPACE};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn test_mutating_requests_are_audited() {
+    let server = TestServer::builder()
+        .users(vec![TestUser::builder()
+            .email("app@example.com")
+            .role(Role::User)
+            .namespaces(vec![NAMESPACE.to_owned()])
+            .build()])
+        .start()
+        .await
+        .unwrap();
+    let admin_token = server.admin_token(NAMESPACE).unwrap().clone();
+    let admin = admin_token.authorization();
+    let (admin_email, _) = server.admin();
+
+    let sqs = |target: &str, body: Value| {
+        let request = server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", admin.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            .timeout(Duration::from_secs(60));
+        async move {
+            let mut response = request.send_json(&body).await.unwrap();
+            let status = response.status().as_u16();
+            let body: Value = response.json().await.unwrap();
+            (status, body)
+        }
+    };
+    let audit = |query: &str| {
+        let request = server
+            .http()
+            .get(format!("/admin/audit{query}"))
+            .insert_header(("Authorization", admin.clone()));
+        async move {
+            let mut response = request.send().await.unwrap();
+            assert!(response.status().is_success(), "{}", response.status());
+            response.json::<Vec<Value>>().await.unwrap()
+        }
+    };
+
+    let (status, body) = sqs("CreateQueue", json!({ "QueueName": "orders" })).await;
+    assert_eq!(status, 200, "{body}");
+    let queue_url = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    // Message traffic and reads aren't recorded.
+    let (status, body) = sqs(
+        "SendMessage",
+        json!({ "QueueUrl": queue_url, "MessageBody": "hello" }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+    let (status, body) = sqs(
+        "GetQueueAttributes",
+        json!({ "QueueUrl": queue_url, "AttributeNames": ["All"] }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (status, body) = sqs("PurgeQueue", json!({ "QueueUrl": queue_url })).await;
+    assert_eq!(status, 200, "{body}");
+
+    let response = server
+        .http()
+        .post("/admin/users/app@example.com/permissions")
+        .insert_header(("Authorization", admin.clone()))
+        .send_json(&json!([NAMESPACE]))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Unauthenticated requests aren't recorded.
+    let response = server
+        .http()
+        .delete("/admin/users/app@example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let entries = audit("").await;
+    let actions = entries
+        .iter()
+        .map(|entry| entry["action"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        actions,
+        [
+            "POST /admin/users/{email}/permissions",
+            "PurgeQueue",
+            "CreateQueue"
+        ]
+    );
+
+    let purge = &entries[1];
+    assert_eq!(purge["actor"], admin_email);
+    assert_eq!(purge["apiKey"], admin_token.access_key);
+    assert_eq!(purge["namespace"], NAMESPACE);
+    assert_eq!(purge["resource"], format!("/sqs/{NAMESPACE}/orders"));
+    assert_eq!(purge["status"], 200);
+    assert_eq!(entries[2]["resource"], "orders");
+    assert_eq!(
+        entries[0]["resource"],
+        "/admin/users/app@example.com/permissions"
+    );
+
+    // Filters and pages.
+    let entries = audit("?action=PurgeQueue").await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(audit("?actor=app@example.com").await.len(), 0);
+
+    let page = audit("?limit=2").await;
+    assert_eq!(page.len(), 2);
+    let rest = audit(&format!("?limit=2&before={}", page[1]["id"])).await;
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0]["action"], "CreateQueue");
+
+    let at = rest[0]["at"].as_u64().unwrap();
+    assert_eq!(audit(&format!("?since={}", at + 3600)).await.len(), 0);
+    assert_eq!(audit(&format!("?until={}", at + 3600)).await.len(), 3);
+
+    // The audit log is admin-only.
+    let response = server
+        .http()
+        .get("/admin/audit")
+        .insert_header((
+            "Authorization",
+            server
+                .token("app@example.com", NAMESPACE)
+                .unwrap()
+                .authorization(),
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
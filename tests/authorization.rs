@@ -0,0 +1,465 @@
+//! The status code of every SQS method and management endpoint, for each kind of caller.
+//!
+//! Every caller works on resources of its own, named after it, so that what one caller changes
+//! doesn't affect the status codes of the next. A change to the authorization model shows up as
+//! a changed row here.
+
+use std::{collections::HashMap, time::Duration};
+
+use nervemq::testing::{Role, TestServer, TestUser, NAMESPACE};
+use serde_json::{json, Value};
+
+/// Namespace callers other than the admin and the member get a token for.
+const OTHER: &str = "other";
+
+const MEMBER: &str = "member@example.com";
+const OUTSIDER: &str = "outsider@example.com";
+const SCOPED: &str = "scoped@example.com";
+
+/// Kinds of callers, in the order of the expected status codes.
+const CALLERS: [&str; 5] = ["admin", "member", "outsider", "scoped", "anonymous"];
+
+struct Case {
+    method: &'static str,
+    path: &'static str,
+    /// SQS method of the request, sent to `/sqs`, or `None` for a management endpoint
+    target: Option<&'static str>,
+    body: Option<&'static str>,
+    content_type: &'static str,
+    /// Status codes for the admin, a member of the namespace, a user of another namespace, a
+    /// member using a token for another namespace, and an anonymous caller
+    expected: [u16; 5],
+}
+
+const fn sqs(target: &'static str, body: &'static str, expected: [u16; 5]) -> Case {
+    Case {
+        method: "POST",
+        path: "/sqs",
+        target: Some(target),
+        body: Some(body),
+        content_type: "application/x-amz-json-1.0",
+        expected,
+    }
+}
+
+const fn api(
+    method: &'static str,
+    path: &'static str,
+    body: Option<&'static str>,
+    expected: [u16; 5],
+) -> Case {
+    Case {
+        method,
+        path,
+        target: None,
+        body,
+        content_type: "application/json",
+        expected,
+    }
+}
+
+/// Cases run in order for each caller, so the ones deleting resources come last.
+///
+/// A token only works on the namespace it is for, on management endpoints as with SQS, so the
+/// scoped caller gets no further than the outsider with the namespace's resources.
+/// The message stream and websocket endpoints are left out, as they stay open.
+const CASES: &[Case] = &[
+    sqs("ListQueues", "{}", [200, 200, 200, 200, 401]),
+    sqs(
+        "GetQueueUrl",
+        r#"{"QueueName": "{q}"}"#,
+        [200, 200, 404, 404, 401],
+    ),
+    sqs(
+        "CreateQueue",
+        r#"{"QueueName": "new-{p}"}"#,
+        [200, 200, 200, 200, 401],
+    ),
+    sqs(
+        "GetQueueAttributes",
+        r#"{"QueueUrl": "{url}", "AttributeNames": ["All"]}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "SetQueueAttributes",
+        r#"{"QueueUrl": "{url}", "Attributes": {"VisibilityTimeout": 60}}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "TagQueue",
+        r#"{"QueueUrl": "{url}", "Tags": {"team": "a"}}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "ListQueueTags",
+        r#"{"QueueUrl": "{url}"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "UntagQueue",
+        r#"{"QueueUrl": "{url}", "TagKeys": ["team"]}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "SendMessage",
+        r#"{"QueueUrl": "{url}", "MessageBody": "hi"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "SendMessageBatch",
+        r#"{"QueueUrl": "{url}", "Entries": [{"Id": "a", "MessageBody": "hi"}]}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "ReceiveMessage",
+        r#"{"QueueUrl": "{url}", "VisibilityTimeout": 300}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "DeleteMessage",
+        r#"{"QueueUrl": "{url}", "ReceiptHandle": "{h0}"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "ChangeMessageVisibility",
+        r#"{"QueueUrl": "{url}", "ReceiptHandle": "{h1}", "VisibilityTimeout": 0}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "DeleteMessageBatch",
+        r#"{"QueueUrl": "{url}", "Entries": [{"Id": "a", "ReceiptHandle": "{h2}"}]}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "ListDeadLetterSourceQueues",
+        r#"{"QueueUrl": "{url}"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "StartMessageMoveTask",
+        r#"{"SourceArn": "{arn}"}"#,
+        [400, 400, 401, 401, 401],
+    ),
+    sqs(
+        "ListMessageMoveTasks",
+        r#"{"SourceArn": "{arn}"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "CancelMessageMoveTask",
+        r#"{"TaskHandle": "nope"}"#,
+        [404, 404, 404, 404, 401],
+    ),
+    Case {
+        method: "POST",
+        path: "/ingest/{ns}/{q}",
+        target: None,
+        body: Some(r#"{"body": "hi"}"#),
+        content_type: "application/x-ndjson",
+        expected: [200, 200, 401, 401, 401],
+    },
+    api("GET", "/api/version", None, [200, 200, 200, 200, 401]),
+    api("GET", "/api/overview", None, [200, 200, 200, 200, 401]),
+    api("GET", "/api/consumers", None, [200, 200, 200, 200, 401]),
+    api(
+        "PUT",
+        "/api/consumers/{ns}/{q}/worker",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "DELETE",
+        "/api/consumers/{ns}/{q}/worker",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "POST",
+        "/api/ack",
+        Some(r#"{"namespace": "{ns}", "queue": "{q}", "results": []}"#),
+        [200, 200, 401, 401, 401],
+    ),
+    api("GET", "/stats/queue", None, [200, 200, 200, 200, 401]),
+    api("GET", "/stats/ns", None, [200, 200, 200, 200, 401]),
+    api("GET", "/queue", None, [200, 200, 200, 200, 401]),
+    api("GET", "/queue/{ns}", None, [200, 200, 401, 401, 401]),
+    api(
+        "POST",
+        "/queue/{ns}/created-{p}",
+        Some(r#"{"attributes": {}, "tags": {}}"#),
+        [200, 200, 401, 401, 401],
+    ),
+    api("GET", "/queue/{ns}/{q}", None, [200, 200, 401, 401, 401]),
+    api(
+        "GET",
+        "/queue/{ns}/{q}/messages",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/queue/{ns}/{q}/config",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "POST",
+        "/queue/{ns}/{q}/config",
+        Some(r#"{"max_retries": 5}"#),
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/queue/{ns}/{q}/history",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/queue/{ns}/{q}/sample",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/queue/{ns}/{q}/groups",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/queue/{ns}/{q}/push",
+        None,
+        [404, 404, 401, 401, 401],
+    ),
+    api(
+        "POST",
+        "/queue/{ns}/{q}/push",
+        Some(r#"{"url": "http://169.254.169.254/latest/meta-data/"}"#),
+        [403, 401, 401, 401, 401],
+    ),
+    api(
+        "DELETE",
+        "/queue/{ns}/{q}/push",
+        None,
+        [404, 404, 401, 401, 401],
+    ),
+    api("GET", "/ns", None, [200, 200, 200, 200, 401]),
+    api("POST", "/ns/ns-{p}", None, [200, 401, 401, 401, 401]),
+    api("DELETE", "/ns/ns-{p}", None, [200, 404, 404, 404, 401]),
+    api("GET", "/tokens", None, [200, 200, 200, 200, 401]),
+    api(
+        "POST",
+        "/tokens",
+        Some(r#"{"name": "new-{p}", "namespace": "{ns}"}"#),
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "DELETE",
+        "/tokens",
+        Some(r#"{"name": "new-{p}"}"#),
+        [200, 200, 404, 404, 401],
+    ),
+    api("GET", "/certificates", None, [200, 200, 200, 200, 401]),
+    api("GET", "/trace/nope", None, [404, 404, 404, 404, 401]),
+    api("GET", "/admin/users", None, [200, 401, 401, 401, 401]),
+    api(
+        "GET",
+        "/admin/users/{member}/role",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/admin/users/{member}/permissions",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "PUT",
+        "/admin/users/{member}/permissions",
+        Some(r#"["{ns}"]"#),
+        [200, 401, 401, 401, 401],
+    ),
+    api(
+        "GET",
+        "/admin/namespaces/{ns}/limits",
+        None,
+        [200, 401, 401, 401, 401],
+    ),
+    api("GET", "/admin/maintenance", None, [200, 401, 401, 401, 401]),
+    api("GET", "/admin/log-level", None, [404, 401, 401, 401, 401]),
+    api("GET", "/admin/storage", None, [200, 401, 401, 401, 401]),
+    api("GET", "/admin/webhooks", None, [200, 401, 401, 401, 401]),
+    api("GET", "/admin/audit", None, [200, 401, 401, 401, 401]),
+    api("GET", "/outbox", None, [200, 401, 401, 401, 401]),
+    sqs(
+        "PurgeQueue",
+        r#"{"QueueUrl": "{url}"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+    api(
+        "DELETE",
+        "/queue/{ns}/created-{p}",
+        None,
+        [200, 200, 401, 401, 401],
+    ),
+    sqs(
+        "DeleteQueue",
+        r#"{"QueueUrl": "{url}"}"#,
+        [200, 200, 401, 401, 401],
+    ),
+];
+
+#[actix_web::test]
+async fn test_authorization_matrix() {
+    let user = |email: &str, namespaces: &[&str]| {
+        TestUser::builder()
+            .email(email)
+            .role(Role::User)
+            .namespaces(namespaces.iter().map(|ns| ns.to_string()).collect())
+            .build()
+    };
+    let server = TestServer::builder()
+        .namespaces(vec![NAMESPACE.to_owned(), OTHER.to_owned()])
+        .users(vec![
+            user(MEMBER, &[NAMESPACE]),
+            user(OUTSIDER, &[OTHER]),
+            user(SCOPED, &[NAMESPACE, OTHER]),
+        ])
+        .start()
+        .await
+        .unwrap();
+
+    let authorization = HashMap::from([
+        (
+            "admin",
+            server.admin_token(NAMESPACE).unwrap().authorization(),
+        ),
+        (
+            "member",
+            server.token(MEMBER, NAMESPACE).unwrap().authorization(),
+        ),
+        (
+            "outsider",
+            server.token(OUTSIDER, OTHER).unwrap().authorization(),
+        ),
+        (
+            "scoped",
+            server.token(SCOPED, OTHER).unwrap().authorization(),
+        ),
+    ]);
+    let admin = authorization["admin"].clone();
+
+    let send = |case: &Case, path: String, body: Option<String>, auth: Option<&String>| {
+        let mut request = server
+            .http()
+            .request(case.method.parse().unwrap(), server.url(&path))
+            .content_type(case.content_type)
+            // Every request hashes its API key secret, which takes a while on small machines.
+            .timeout(Duration::from_secs(60));
+        if let Some(target) = case.target {
+            request = request.insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")));
+        }
+        if let Some(auth) = auth {
+            request = request.insert_header(("Authorization", auth.clone()));
+        }
+        async move {
+            let mut response = request.send_body(body.unwrap_or_default()).await.unwrap();
+            let body: Value = response.json().await.unwrap_or(Value::Null);
+            (response.status().as_u16(), body)
+        }
+    };
+    let sqs_as_admin = |target: &'static str, request: Value| {
+        let case = sqs(target, "", [200; 5]);
+        let admin = admin.clone();
+        async move {
+            let (status, body) = send(
+                &case,
+                case.path.to_owned(),
+                Some(request.to_string()),
+                Some(&admin),
+            )
+            .await;
+            assert_eq!(status, 200, "{target}: {body}");
+            body
+        }
+    };
+
+    // Each caller gets a queue with received messages to work on.
+    let mut substitutions = HashMap::new();
+    for caller in CALLERS {
+        let queue = format!("q-{caller}");
+        let body = sqs_as_admin("CreateQueue", json!({ "QueueName": queue })).await;
+        let url = body["QueueUrl"].as_str().unwrap().to_owned();
+
+        let mut handles = Vec::new();
+        while handles.len() < 3 {
+            sqs_as_admin(
+                "SendMessage",
+                json!({ "QueueUrl": url, "MessageBody": "hi" }),
+            )
+            .await;
+            let body = sqs_as_admin(
+                "ReceiveMessage",
+                json!({ "QueueUrl": url, "VisibilityTimeout": 300 }),
+            )
+            .await;
+            for message in body["Messages"].as_array().unwrap() {
+                handles.push(message["ReceiptHandle"].as_str().unwrap().to_owned());
+            }
+        }
+
+        let arn = format!("{NAMESPACE}:{queue}");
+        substitutions.insert(
+            caller,
+            [
+                ("{ns}", NAMESPACE.to_owned()),
+                ("{q}", queue),
+                ("{p}", caller.to_owned()),
+                ("{url}", url),
+                ("{arn}", arn),
+                ("{member}", MEMBER.to_owned()),
+                ("{h0}", handles[0].clone()),
+                ("{h1}", handles[1].clone()),
+                ("{h2}", handles[2].clone()),
+            ],
+        );
+    }
+
+    let mut observed = vec![[0u16; 5]; CASES.len()];
+    for (i, caller) in CALLERS.into_iter().enumerate() {
+        let substitute = |template: &str| {
+            substitutions[caller]
+                .iter()
+                .fold(template.to_owned(), |s, (from, to)| s.replace(from, to))
+        };
+        for (j, case) in CASES.iter().enumerate() {
+            let (status, _) = send(
+                case,
+                substitute(case.path),
+                case.body.map(substitute),
+                authorization.get(caller),
+            )
+            .await;
+            observed[j][i] = status;
+        }
+    }
+
+    let mismatches: Vec<_> = CASES
+        .iter()
+        .zip(&observed)
+        .filter(|(case, statuses)| case.expected != **statuses)
+        .map(|(case, statuses)| {
+            format!(
+                "{} {} {}: expected {:?}, got {:?}",
+                case.method,
+                case.path,
+                case.target.unwrap_or_default(),
+                case.expected,
+                statuses
+            )
+        })
+        .collect();
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::json;
+
+#[actix_web::test]
+async fn test_conditional_get() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let get = |path: &str| {
+        server
+            .http()
+            .get(path)
+            .insert_header(("Authorization", token.clone()))
+            .timeout(Duration::from_secs(60))
+    };
+
+    let response = get("/queue").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let etag = response.headers().get("ETag").unwrap().clone();
+    let last_modified = response.headers().get("Last-Modified").unwrap().clone();
+    assert_eq!(
+        response.headers().get("Cache-Control").unwrap(),
+        "no-cache, private"
+    );
+
+    let mut response = get("/queue")
+        .insert_header(("If-None-Match", etag.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers().get("ETag").unwrap(), etag);
+    assert!(response.body().await.unwrap().is_empty());
+
+    let response = get("/queue")
+        .insert_header(("If-Modified-Since", last_modified.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    let response = server
+        .http()
+        .post(format!("/queue/{NAMESPACE}/orders"))
+        .insert_header(("Authorization", token.clone()))
+        .timeout(Duration::from_secs(60))
+        .send_json(&json!({ "attributes": {}, "tags": {} }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let response = get("/queue")
+        .insert_header(("If-None-Match", etag.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_ne!(response.headers().get("ETag").unwrap(), etag);
+
+    // Other endpoints aren't cached.
+    let response = get(&format!("/queue/{NAMESPACE}/orders/config"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().get("ETag").is_none());
+}
@@ -0,0 +1,80 @@
+use std::{collections::HashMap, time::Duration};
+
+use nervemq::{
+    client::{Client, ReceiveOptions},
+    testing::{TestServer, NAMESPACE},
+    types::SqsMessageAttribute,
+};
+use serde_json::json;
+
+#[actix_web::test]
+async fn test_client() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap();
+    let authorization = token.authorization();
+
+    let response = server
+        .http()
+        .post("/sqs")
+        .insert_header(("Authorization", authorization.clone()))
+        .insert_header(("X-Amz-Target", "AmazonSQS.CreateQueue"))
+        .timeout(Duration::from_secs(60))
+        .send_json(&json!({ "QueueName": "cli" }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let host = url::Url::parse(&server.url("/")).unwrap();
+    let token = authorization.trim_start_matches("NerveMqApiV1 ");
+    let client = Client::new(&host, token).unwrap();
+    let queue_url = client.queue_url("cli").await.unwrap();
+    assert!(queue_url.path().ends_with("/cli"));
+
+    for priority in ["1", "9"] {
+        let attributes = HashMap::from([(
+            "priority".to_owned(),
+            SqsMessageAttribute::Number {
+                string_value: priority.to_owned(),
+            },
+        )]);
+        client
+            .send_message(&queue_url, &format!("p{priority}"), &attributes, None)
+            .await
+            .unwrap();
+    }
+
+    let options = ReceiveOptions {
+        message_selector: Some("priority > 5".to_owned()),
+        message_attribute_names: vec!["priority".to_owned()],
+        ..Default::default()
+    };
+    let messages = client.receive_messages(&queue_url, &options).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].body, "p9");
+    assert!(messages[0].message_attributes.contains_key("priority"));
+
+    client
+        .delete_message(&queue_url, &messages[0].receipt_handle)
+        .await
+        .unwrap();
+    let options = ReceiveOptions {
+        max_number_of_messages: Some(10),
+        ..Default::default()
+    };
+    let messages = client.receive_messages(&queue_url, &options).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].body, "p1");
+
+    // The received message is in flight until its visibility timeout runs out.
+    let statistics = client.queue_statistics().await.unwrap();
+    let stats = statistics.iter().find(|s| s.queue.name == "cli").unwrap();
+    assert_eq!(stats.pending, 0);
+    assert_eq!(stats.in_flight, 1);
+    assert!(!stats.dead_letter_queue);
+
+    let error = client.queue_url("missing").await.unwrap_err();
+    assert!(error.to_string().contains("GetQueueUrl"), "{error}");
+
+    let client = Client::new(&host, "nervemq_bad_token").unwrap();
+    assert!(client.queue_url("cli").await.is_err());
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn test_list_dead_letter_source_queues() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            .timeout(Duration::from_secs(60))
+            .send_json(&body)
+    };
+
+    let mut urls = Vec::new();
+    for name in ["dlq", "orders", "payments", "unrelated"] {
+        let mut response = sqs("CreateQueue", json!({ "QueueName": name }))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body: Value = response.json().await.unwrap();
+        urls.push(body["QueueUrl"].as_str().unwrap().to_owned());
+    }
+
+    for source in &urls[1..3] {
+        let policy = json!({ "deadLetterTargetArn": "dlq", "maxReceiveCount": 3 }).to_string();
+        let response = sqs(
+            "SetQueueAttributes",
+            json!({ "QueueUrl": source, "Attributes": { "RedrivePolicy": policy } }),
+        )
+        .await
+        .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    let mut response = sqs("ListDeadLetterSourceQueues", json!({ "QueueUrl": urls[0] }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["queueUrls"], json!(urls[1..3]));
+    assert!(body.get("NextToken").is_none());
+
+    let mut response = sqs(
+        "ListDeadLetterSourceQueues",
+        json!({ "QueueUrl": urls[0], "MaxResults": 1 }),
+    )
+    .await
+    .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["queueUrls"], json!([urls[1]]));
+    let next_token = body["NextToken"].as_str().unwrap().to_owned();
+
+    let mut response = sqs(
+        "ListDeadLetterSourceQueues",
+        json!({ "QueueUrl": urls[0], "MaxResults": 1, "NextToken": next_token }),
+    )
+    .await
+    .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["queueUrls"], json!([urls[2]]));
+    assert!(body.get("NextToken").is_none());
+
+    let response = sqs(
+        "ListDeadLetterSourceQueues",
+        json!({ "QueueUrl": urls[0], "MaxResults": 0 }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_message_move_tasks() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            .timeout(Duration::from_secs(60))
+            .send_json(&body)
+    };
+    let call = |target: &'static str, body: Value| {
+        let request = sqs(target, body);
+        async move {
+            let mut response = request.await.unwrap();
+            assert!(response.status().is_success(), "{target}");
+            response.json::<Value>().await.unwrap()
+        }
+    };
+
+    let mut urls = Vec::new();
+    for name in ["dlq", "orders"] {
+        let body = call("CreateQueue", json!({ "QueueName": name })).await;
+        urls.push(body["QueueUrl"].as_str().unwrap().to_owned());
+    }
+    let (dlq, orders) = (&urls[0], &urls[1]);
+
+    // Not a dead-letter queue yet.
+    let response = sqs("StartMessageMoveTask", json!({ "SourceArn": "dlq" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let policy = json!({ "deadLetterTargetArn": "dlq", "maxReceiveCount": 1 }).to_string();
+    call(
+        "SetQueueAttributes",
+        json!({ "QueueUrl": orders, "Attributes": { "RedrivePolicy": policy } }),
+    )
+    .await;
+
+    for i in 0..3 {
+        call(
+            "SendMessage",
+            json!({
+                "QueueUrl": orders,
+                "MessageBody": format!("order {i}"),
+                "MessageAttributes": {},
+            }),
+        )
+        .await;
+    }
+    let receive = |url: &str| {
+        call(
+            "ReceiveMessage",
+            json!({ "QueueUrl": url, "MaxNumberOfMessages": 10, "VisibilityTimeout": 0 }),
+        )
+    };
+    let received = |body: Value| body["Messages"].as_array().map_or(0, Vec::len);
+
+    // The messages use up their only try, and are dead-lettered by the next receive.
+    assert_eq!(received(receive(orders).await), 3);
+    assert_eq!(received(receive(orders).await), 0);
+
+    let body = call(
+        "StartMessageMoveTask",
+        json!({ "SourceArn": "dlq", "MaxNumberOfMessagesPerSecond": 2 }),
+    )
+    .await;
+    let handle = body["TaskHandle"].as_str().unwrap().to_owned();
+
+    let response = sqs("StartMessageMoveTask", json!({ "SourceArn": "dlq" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let mut task = Value::Null;
+    for _ in 0..30 {
+        let body = call("ListMessageMoveTasks", json!({ "SourceArn": "dlq" })).await;
+        task = body["Results"][0].clone();
+        if task["Status"] != "RUNNING" {
+            break;
+        }
+        assert_eq!(task["TaskHandle"], handle);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(task["Status"], "COMPLETED");
+    assert_eq!(task["SourceArn"], format!("{NAMESPACE}:dlq"));
+    assert_eq!(task["ApproximateNumberOfMessagesMoved"], 3);
+    assert_eq!(task["ApproximateNumberOfMessagesToMove"], 3);
+    assert!(task.get("TaskHandle").is_none());
+
+    // The messages are back in their source queue, with their retries restored.
+    assert_eq!(received(receive(dlq).await), 0);
+    assert_eq!(received(receive(orders).await), 3);
+
+    let response = sqs("CancelMessageMoveTask", json!({ "TaskHandle": handle }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let response = sqs("CancelMessageMoveTask", json!({ "TaskHandle": "unknown" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn test_retried_batch_is_not_sent_twice() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        let request = server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            .timeout(Duration::from_secs(60));
+        async move {
+            let mut response = request.send_json(&body).await.unwrap();
+            let status = response.status().as_u16();
+            let body: Value = response.json().await.unwrap();
+            (status, body)
+        }
+    };
+
+    let (status, body) = sqs("CreateQueue", json!({ "QueueName": "orders" })).await;
+    assert_eq!(status, 200, "{body}");
+    let queue_url = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    let batch = json!({
+        "QueueUrl": queue_url,
+        "Entries": [
+            { "Id": "a", "MessageBody": "a", "MessageDeduplicationId": "order-1" },
+            { "Id": "b", "MessageBody": "b", "MessageDeduplicationId": "order-2" },
+            { "Id": "c", "MessageBody": "c" },
+        ],
+    });
+    let message_ids = |body: &Value| {
+        body["Successful"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["MessageId"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    let (status, body) = sqs("SendMessageBatch", batch.clone()).await;
+    assert_eq!(status, 200, "{body}");
+    let first = message_ids(&body);
+
+    // As if the response had been lost and the client retried.
+    let (status, body) = sqs("SendMessageBatch", batch).await;
+    assert_eq!(status, 200, "{body}");
+    let retried = message_ids(&body);
+    assert_eq!(retried[..2], first[..2]);
+    assert_ne!(retried[2], first[2]);
+
+    let (status, body) = sqs(
+        "SendMessage",
+        json!({
+            "QueueUrl": queue_url,
+            "MessageBody": "a",
+            "MessageDeduplicationId": "order-1",
+        }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["MessageId"], first[0]);
+
+    let (status, body) = sqs(
+        "ReceiveMessage",
+        json!({ "QueueUrl": queue_url, "MaxNumberOfMessages": 10 }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+    let bodies = body["Messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["Body"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(bodies, ["a", "b", "c", "c"]);
+
+    let (status, body) = sqs(
+        "SendMessage",
+        json!({
+            "QueueUrl": queue_url,
+            "MessageBody": "d",
+            "MessageDeduplicationId": "not allowed",
+        }),
+    )
+    .await;
+    assert_eq!(status, 400, "{body}");
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use nervemq::{
+    config::{Config, MEMORY_DB_PATH},
+    testing::{TestServer, NAMESPACE},
+};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn test_message_history() {
+    let config: Config = serde_json::from_value(json!({
+        "db_path": MEMORY_DB_PATH,
+        "integrity_check": "off",
+        "cookie_secure": false,
+        "prune_interval": 1,
+    }))
+    .unwrap();
+    let server = TestServer::builder().config(config).start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            .timeout(Duration::from_secs(60))
+            .send_json(&body)
+    };
+    let set_config = |config: Value| {
+        server
+            .http()
+            .post(format!("/queue/{NAMESPACE}/kept/config"))
+            .insert_header(("Authorization", token.clone()))
+            .send_json(&config)
+    };
+    let history = || async {
+        let mut response = server
+            .http()
+            .get(format!("/queue/{NAMESPACE}/kept/history"))
+            .insert_header(("Authorization", token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+        response.json::<Vec<Value>>().await.unwrap()
+    };
+
+    let mut response = sqs("CreateQueue", json!({ "QueueName": "kept" }))
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    let url = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    let response = set_config(json!({ "max_retries": 10, "history": "full" }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+
+    sqs(
+        "SendMessage",
+        json!({ "QueueUrl": url, "MessageBody": "hello" }),
+    )
+    .await
+    .unwrap();
+    let mut response = sqs("ReceiveMessage", json!({ "QueueUrl": url }))
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    let message = &body["Messages"][0];
+
+    // Nothing is recorded until the message is deleted.
+    assert!(history().await.is_empty());
+
+    let response = sqs(
+        "DeleteMessage",
+        json!({ "QueueUrl": url, "ReceiptHandle": message["ReceiptHandle"] }),
+    )
+    .await
+    .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+
+    let entries = history().await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["messageId"], message["MessageId"]);
+    assert_eq!(entries[0]["body"], "hello");
+    assert_eq!(entries[0]["tries"], 1);
+
+    // The record outlives a prune run while it is within the retention period.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(history().await.len(), 1);
+
+    // Once it is past it, the next run removes it.
+    let response = set_config(json!({ "max_retries": 10, "history_retention": 0 }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !history().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,90 @@
+use nervemq::testing::{TestServer, NAMESPACE};
+
+#[actix_web::test]
+async fn test_ingest_ndjson_and_binary() {
+    let server = TestServer::builder()
+        .namespaces(vec![NAMESPACE.to_owned(), "other".to_owned()])
+        .start()
+        .await
+        .unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let response = server
+        .http()
+        .post(format!("/queue/{NAMESPACE}/events"))
+        .insert_header(("Authorization", token.clone()))
+        .send_json(&serde_json::json!({ "attributes": {}, "tags": {} }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let ndjson = (0..1200)
+        .map(|i| format!("{{\"body\": \"message {i}\"}}\n"))
+        .collect::<String>();
+    let mut response = server
+        .http()
+        .post(format!("/ingest/{NAMESPACE}/events"))
+        .insert_header(("Authorization", token.clone()))
+        .content_type("application/x-ndjson")
+        .send_body(ndjson)
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let ids = body["messageIds"].as_array().unwrap();
+    assert_eq!(ids.len(), 1200);
+    let ids = ids
+        .iter()
+        .filter_map(|id| id.as_str())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(ids.len(), 1200);
+
+    let mut frames = Vec::new();
+    for body in ["one", "two"] {
+        frames.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frames.extend_from_slice(body.as_bytes());
+    }
+    let mut response = server
+        .http()
+        .post(format!("/ingest/{NAMESPACE}/events"))
+        .insert_header(("Authorization", token.clone()))
+        .content_type("application/octet-stream")
+        .send_body(frames.clone())
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["messageIds"].as_array().unwrap().len(), 2);
+
+    // A malformed request sends nothing.
+    let response = server
+        .http()
+        .post(format!("/ingest/{NAMESPACE}/events"))
+        .insert_header(("Authorization", token.clone()))
+        .content_type("application/octet-stream")
+        .send_body(frames[..frames.len() - 1].to_vec())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let mut response = server
+        .http()
+        .get(format!("/queue/{NAMESPACE}/events"))
+        .insert_header(("Authorization", token.clone()))
+        .send()
+        .await
+        .unwrap();
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(stats["pending"], 1202);
+
+    // Tokens can only ingest into their own namespace.
+    let response = server
+        .http()
+        .post("/ingest/other/events")
+        .insert_header(("Authorization", token))
+        .content_type("application/x-ndjson")
+        .send_body("{\"body\": \"x\"}")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
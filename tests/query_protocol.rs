@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use nervemq::testing::{TestServer, NAMESPACE};
+
+/// Pulls the text of the first `<tag>` element out of an XML response.
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(&xml[start..end])
+}
+
+#[actix_web::test]
+async fn test_query_protocol() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |path: &str, form: &[(&str, &str)]| {
+        let request = server
+            .http()
+            .post(path)
+            .insert_header(("Authorization", token.clone()))
+            .timeout(Duration::from_secs(60));
+        let form = form
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        async move {
+            let mut response = request.send_form(&form).await.unwrap();
+            let body = response.body().await.unwrap();
+            let content_type = response.headers().get("content-type").cloned();
+            assert_eq!(
+                content_type.as_ref().and_then(|v| v.to_str().ok()),
+                Some("text/xml"),
+                "{} {body:?}",
+                response.status()
+            );
+            (
+                response.status().as_u16(),
+                String::from_utf8(body.to_vec()).unwrap(),
+            )
+        }
+    };
+
+    let (status, xml) = sqs(
+        "/sqs",
+        &[
+            ("Action", "CreateQueue"),
+            ("Version", "2012-11-05"),
+            ("QueueName", "legacy"),
+            ("Attribute.1.Name", "VisibilityTimeout"),
+            ("Attribute.1.Value", "60"),
+        ],
+    )
+    .await;
+    assert_eq!(status, 200, "{xml}");
+    assert!(xml.contains("<CreateQueueResponse"), "{xml}");
+    assert!(element(&xml, "RequestId").is_some(), "{xml}");
+    let queue_url = element(&xml, "QueueUrl").unwrap().to_owned();
+    let queue_path = url::Url::parse(&queue_url).unwrap().path().to_owned();
+
+    // Requests to the queue's URL don't need a QueueUrl.
+    let (status, xml) = sqs(
+        &queue_path,
+        &[
+            ("Action", "SendMessage"),
+            ("MessageBody", "<hello> & goodbye"),
+            ("MessageAttribute.1.Name", "kind"),
+            ("MessageAttribute.1.Value.DataType", "String"),
+            ("MessageAttribute.1.Value.StringValue", "greeting"),
+        ],
+    )
+    .await;
+    assert_eq!(status, 200, "{xml}");
+    let message_id = element(&xml, "MessageId").unwrap().to_owned();
+
+    let (status, xml) = sqs(
+        "/sqs",
+        &[
+            ("Action", "ReceiveMessage"),
+            ("QueueUrl", &queue_url),
+            ("MaxNumberOfMessages", "10"),
+            ("MessageAttributeName.1", "kind"),
+        ],
+    )
+    .await;
+    assert_eq!(status, 200, "{xml}");
+    let message = element(&xml, "Message").unwrap();
+    assert_eq!(element(message, "MessageId"), Some(message_id.as_str()));
+    assert_eq!(
+        element(message, "Body"),
+        Some("&lt;hello&gt; &amp; goodbye")
+    );
+    let attribute = element(message, "MessageAttribute").unwrap();
+    assert_eq!(element(attribute, "Name"), Some("kind"));
+    assert_eq!(element(attribute, "StringValue"), Some("greeting"));
+    let receipt_handle = element(message, "ReceiptHandle").unwrap().to_owned();
+
+    let (status, xml) = sqs(
+        &queue_path,
+        &[
+            ("Action", "DeleteMessageBatch"),
+            ("DeleteMessageBatchRequestEntry.1.Id", "a"),
+            (
+                "DeleteMessageBatchRequestEntry.1.ReceiptHandle",
+                &receipt_handle,
+            ),
+        ],
+    )
+    .await;
+    assert_eq!(status, 200, "{xml}");
+    let entry = element(&xml, "DeleteMessageBatchResultEntry").unwrap();
+    assert_eq!(element(entry, "Id"), Some("a"));
+
+    // Errors are XML too.
+    let (status, xml) = sqs(
+        "/sqs",
+        &[("Action", "GetQueueUrl"), ("QueueName", "missing")],
+    )
+    .await;
+    assert_eq!(status, 404, "{xml}");
+    assert!(xml.contains("<ErrorResponse>"), "{xml}");
+    assert_eq!(element(&xml, "Type"), Some("Sender"));
+    assert_eq!(
+        element(&xml, "Code"),
+        Some("AWS.SimpleQueueService.NonExistentQueue")
+    );
+
+    let (status, xml) = sqs("/sqs", &[("Action", "AddPermission")]).await;
+    assert_eq!(status, 400, "{xml}");
+    assert_eq!(
+        element(&xml, "Code"),
+        Some("AWS.SimpleQueueService.UnsupportedOperation")
+    );
+    assert!(
+        element(&xml, "Message").unwrap().contains("/api/version"),
+        "{xml}"
+    );
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::json;
+
+#[actix_web::test]
+async fn test_rate_limit() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap();
+    let authorization = token.authorization();
+
+    let response = server
+        .http()
+        .post(format!("/queue/{NAMESPACE}/events"))
+        .insert_header(("Authorization", authorization.clone()))
+        .timeout(Duration::from_secs(60))
+        .send_json(&json!({ "attributes": {}, "tags": {} }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let response = server
+        .http()
+        .put(format!("/admin/api-keys/{}/rate-limit", token.access_key))
+        .insert_header(("Authorization", authorization.clone()))
+        .timeout(Duration::from_secs(60))
+        .send_json(&json!({ "rateLimit": { "requestsPerSecond": 0.01, "burst": 2 } }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let ingest = || {
+        server
+            .http()
+            .post(format!("/ingest/{NAMESPACE}/events"))
+            .insert_header(("Authorization", authorization.clone()))
+            .content_type("application/x-ndjson")
+            .timeout(Duration::from_secs(60))
+            .send_body("{\"body\": \"hello\"}\n")
+    };
+
+    for _ in 0..2 {
+        assert!(ingest().await.unwrap().status().is_success());
+    }
+
+    let mut response = ingest().await.unwrap();
+    assert_eq!(response.status(), 429);
+    let retry_after: u64 = response
+        .headers()
+        .get("Retry-After")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 60 && retry_after <= 100);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "ThrottlingException");
+
+    // The management API isn't limited.
+    let response = server
+        .http()
+        .get(format!("/admin/api-keys/{}/rate-limit", token.access_key))
+        .insert_header(("Authorization", authorization.clone()))
+        .timeout(Duration::from_secs(60))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
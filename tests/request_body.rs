@@ -0,0 +1,86 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use nervemq::{
+    config::{Config, MEMORY_DB_PATH},
+    testing::{TestServer, NAMESPACE},
+};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn test_request_body() {
+    let config: Config = serde_json::from_value(json!({
+        "db_path": MEMORY_DB_PATH,
+        "integrity_check": "off",
+        "cookie_secure": false,
+        "max_request_body": 256,
+    }))
+    .unwrap();
+    let server = TestServer::builder().config(config).start().await.unwrap();
+    let admin_token = server.admin_token(NAMESPACE).unwrap();
+    let token = admin_token.authorization();
+
+    let sqs = |target: &str| {
+        server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            .content_type("application/x-amz-json-1.0")
+            .timeout(Duration::from_secs(60))
+    };
+
+    // Chunks are put together, wherever they split the JSON.
+    let chunks = ["{\"Queue", "Name\": \"ch", "unked\"}"]
+        .map(|chunk| Ok::<_, actix_web::Error>(Bytes::from_static(chunk.as_bytes())));
+    let response = sqs("CreateQueue")
+        .send_stream(futures_util::stream::iter(chunks))
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+
+    let body = json!({ "QueueName": "a".repeat(300) }).to_string();
+    let response = sqs("CreateQueue").send_body(body.clone()).await.unwrap();
+    assert_eq!(response.status(), 413);
+
+    let chunks = body
+        .into_bytes()
+        .chunks(64)
+        .map(|chunk| Ok::<_, actix_web::Error>(Bytes::copy_from_slice(chunk)))
+        .collect::<Vec<_>>();
+    let response = sqs("CreateQueue")
+        .send_stream(futures_util::stream::iter(chunks))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 413);
+
+    for (body, code) in [
+        ("{\"QueueName\": ", "InvalidRequest"),
+        ("{\"QueueName\": 1}", "InvalidRequest"),
+        ("{}", "MissingParameter"),
+        ("", "MissingParameter"),
+    ] {
+        let mut response = sqs("CreateQueue").send_body(body).await.unwrap();
+        assert_eq!(response.status(), 400, "{body}");
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(error["code"], code, "{body}: {error}");
+    }
+
+    // Signed requests are read before they are authenticated, with the same limit, wherever they
+    // go.
+    let body = json!({ "name": "a".repeat(300) }).to_string().into_bytes();
+    let path = format!("/queue/{NAMESPACE}/signed");
+    let mut request = server.http().post(&path).content_type("application/json");
+    for header in admin_token.sign_v2("POST", &path, &body, unix_now()) {
+        request = request.insert_header(header);
+    }
+    let response = request.send_body(body).await.unwrap();
+    assert_eq!(response.status(), 413);
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
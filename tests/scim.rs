@@ -0,0 +1,318 @@
+use nervemq::{
+    config::{Config, MEMORY_DB_PATH},
+    testing::{Role, TestServer, TestUser, NAMESPACE, PASSWORD},
+};
+use serde_json::{json, Value};
+
+const SCIM_TOKEN: &str = "scim-secret";
+
+async fn start() -> TestServer {
+    let config: Config = serde_json::from_value(json!({
+        "db_path": MEMORY_DB_PATH,
+        "integrity_check": "off",
+        "cookie_secure": false,
+        "scim_token": SCIM_TOKEN,
+    }))
+    .unwrap();
+
+    TestServer::builder()
+        .config(config)
+        .users(vec![TestUser::builder()
+            .email("app@example.com")
+            .role(Role::User)
+            .namespaces(vec![NAMESPACE.to_owned()])
+            .build()])
+        .start()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_scim_users() {
+    let server = start().await;
+    let scim = || format!("Bearer {SCIM_TOKEN}");
+    let app = server
+        .token("app@example.com", NAMESPACE)
+        .unwrap()
+        .authorization();
+
+    for authorization in [None, Some("Bearer wrong".to_owned()), Some(app.clone())] {
+        let mut request = server.http().get("/scim/v2/Users");
+        if let Some(authorization) = authorization {
+            request = request.insert_header(("Authorization", authorization));
+        }
+        let mut response = request.send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(
+            body["schemas"][0],
+            "urn:ietf:params:scim:api:messages:2.0:Error"
+        );
+    }
+
+    let mut response = server
+        .http()
+        .post("/scim/v2/Users")
+        .insert_header(("Authorization", scim()))
+        .insert_header(("Content-Type", "application/scim+json"))
+        .send_body(
+            json!({
+                "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+                "userName": "alice@example.com",
+                "name": { "givenName": "Alice" },
+                "roles": [{ "value": "admin" }],
+            })
+            .to_string(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let alice: Value = response.json().await.unwrap();
+    assert_eq!(alice["active"], true);
+    assert_eq!(alice["roles"][0]["value"], "admin");
+
+    let response = server
+        .http()
+        .post("/scim/v2/Users")
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({ "userName": "Alice@example.com" }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+
+    let mut response = server
+        .http()
+        .get("/scim/v2/Users?filter=userName%20eq%20%22app@example.com%22")
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let list: Value = response.json().await.unwrap();
+    assert_eq!(list["totalResults"], 1);
+    let id = list["Resources"][0]["id"].as_str().unwrap().to_owned();
+
+    // Deactivated users can't log in until they are reactivated, and lose their API keys.
+    let deactivate = json!({
+        "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+        "Operations": [{ "op": "Replace", "path": "active", "value": "False" }],
+    });
+    let mut response = server
+        .http()
+        .patch(format!("/scim/v2/Users/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send_json(&deactivate)
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let user: Value = response.json().await.unwrap();
+    assert_eq!(user["active"], false);
+
+    let response = server
+        .http()
+        .get("/ns")
+        .insert_header(("Authorization", app.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let login = json!({ "email": "app@example.com", "password": PASSWORD });
+    let response = server
+        .http()
+        .post("/auth/login")
+        .send_json(&login)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = server
+        .http()
+        .patch(format!("/scim/v2/Users/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({
+            "Operations": [{ "op": "replace", "value": { "active": true } }],
+        }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let response = server
+        .http()
+        .get("/ns")
+        .insert_header(("Authorization", app.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    // Deleting a user only deactivates them.
+    let response = server
+        .http()
+        .delete(format!("/scim/v2/Users/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 204);
+
+    let mut response = server
+        .http()
+        .get(format!("/scim/v2/Users/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    let user: Value = response.json().await.unwrap();
+    assert_eq!(user["active"], false);
+
+    // The root user can't be locked out.
+    let (admin, _) = server.admin();
+    let mut response = server
+        .http()
+        .get(format!(
+            "/scim/v2/Users?filter=userName%20eq%20%22{admin}%22"
+        ))
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    let list: Value = response.json().await.unwrap();
+    let root = list["Resources"][0]["id"].as_str().unwrap().to_owned();
+
+    let mut response = server
+        .http()
+        .patch(format!("/scim/v2/Users/{root}"))
+        .insert_header(("Authorization", scim()))
+        .send_json(&deactivate)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["scimType"], "mutability");
+}
+
+#[actix_web::test]
+async fn test_scim_groups() {
+    let server = start().await;
+    let scim = || format!("Bearer {SCIM_TOKEN}");
+
+    let mut response = server
+        .http()
+        .get("/scim/v2/Users?filter=userName%20eq%20%22app@example.com%22")
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    let list: Value = response.json().await.unwrap();
+    let app = list["Resources"][0]["id"].as_str().unwrap().to_owned();
+
+    let mut response = server
+        .http()
+        .post("/scim/v2/Groups")
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({ "displayName": "orders", "members": [{ "value": app }] }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let group: Value = response.json().await.unwrap();
+    assert_eq!(
+        group["members"],
+        json!([{ "value": app, "display": "app@example.com" }])
+    );
+    let id = group["id"].as_str().unwrap().to_owned();
+
+    let response = server
+        .http()
+        .post("/scim/v2/Groups")
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({ "displayName": NAMESPACE }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 409);
+
+    let mut response = server
+        .http()
+        .get("/scim/v2/Groups?filter=displayName%20eq%20%22orders%22")
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    let list: Value = response.json().await.unwrap();
+    assert_eq!(list["totalResults"], 1);
+    assert_eq!(list["Resources"][0]["id"], id.as_str());
+
+    let mut response = server
+        .http()
+        .patch(format!("/scim/v2/Groups/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({
+            "Operations": [{ "op": "remove", "path": format!("members[value eq \"{app}\"]") }],
+        }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let group: Value = response.json().await.unwrap();
+    assert_eq!(group["members"], json!([]));
+
+    let mut response = server
+        .http()
+        .patch(format!("/scim/v2/Groups/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({
+            "Operations": [{ "op": "add", "path": "members", "value": [{ "value": "999" }] }],
+        }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["scimType"], "invalidValue");
+
+    let response = server
+        .http()
+        .patch(format!("/scim/v2/Groups/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send_json(&json!({
+            "Operations": [{ "op": "add", "path": "members", "value": [{ "value": app }] }],
+        }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let mut response = server
+        .http()
+        .get("/admin/users/app@example.com/permissions")
+        .insert_header((
+            "Authorization",
+            server
+                .token("app@example.com", NAMESPACE)
+                .unwrap()
+                .authorization(),
+        ))
+        .send()
+        .await
+        .unwrap();
+    let permissions: Value = response.json().await.unwrap();
+    assert_eq!(permissions, json!([NAMESPACE, "orders"]));
+
+    // Deleting a group revokes access, but keeps the namespace.
+    let response = server
+        .http()
+        .delete(format!("/scim/v2/Groups/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 204);
+
+    let mut response = server
+        .http()
+        .get(format!("/scim/v2/Groups/{id}"))
+        .insert_header(("Authorization", scim()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let group: Value = response.json().await.unwrap();
+    assert_eq!(group["members"], json!([]));
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::json;
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[actix_web::test]
+async fn test_signed_requests() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap();
+
+    let send = |headers: [(&'static str, String); 3], body: Vec<u8>| {
+        let mut request = server
+            .http()
+            .post("/sqs?signed=1")
+            .insert_header(("X-Amz-Target", "AmazonSQS.CreateQueue"))
+            .content_type("application/x-amz-json-1.0")
+            .timeout(Duration::from_secs(60));
+        for header in headers {
+            request = request.insert_header(header);
+        }
+        request.send_body(body)
+    };
+
+    let body = serde_json::to_vec(&json!({ "QueueName": "signed" })).unwrap();
+    let headers = token.sign_v2("POST", "/sqs?signed=1", &body, unix_now());
+
+    let response = send(headers.clone(), body.clone()).await.unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+
+    // The same request can't be sent twice.
+    let response = send(headers, body.clone()).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    // Nor can a request signed too long ago, or for another body or path.
+    let headers = token.sign_v2("POST", "/sqs?signed=1", &body, unix_now() - 600);
+    let response = send(headers, body.clone()).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let headers = token.sign_v2("POST", "/sqs?signed=1", b"{}", unix_now());
+    let response = send(headers, body.clone()).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let headers = token.sign_v2("POST", "/sqs?signed=2", &body, unix_now());
+    let response = send(headers, body.clone()).await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    // Requests that are signed again go through.
+    let body = serde_json::to_vec(&json!({ "QueueName": "signed-again" })).unwrap();
+    let headers = token.sign_v2("POST", "/sqs?signed=1", &body, unix_now());
+    let response = send(headers, body).await.unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+}
@@ -0,0 +1,146 @@
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn test_sqs_error_types() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        let request = server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")));
+        async move {
+            let mut response = request.send_json(&body).await.unwrap();
+            let query_error = response
+                .headers()
+                .get("x-amzn-query-error")
+                .map(|value| value.to_str().unwrap().to_owned());
+            let body: Value = response.json().await.unwrap();
+            (response.status().as_u16(), query_error, body)
+        }
+    };
+
+    let (status, _, body) = sqs("CreateQueue", json!({ "QueueName": "orders" })).await;
+    assert_eq!(status, 200, "{body}");
+    let queue_url = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    let (status, query_error, body) = sqs(
+        "SendMessage",
+        json!({ "QueueUrl": queue_url.replace("orders", "missing"), "MessageBody": "hello" }),
+    )
+    .await;
+    assert_eq!(status, 404, "{body}");
+    assert_eq!(body["__type"], "com.amazonaws.sqs#QueueDoesNotExist");
+    assert!(
+        body["message"].as_str().unwrap().contains("missing"),
+        "{body}"
+    );
+    assert_eq!(
+        query_error.as_deref(),
+        Some("AWS.SimpleQueueService.NonExistentQueue;Sender")
+    );
+    // Clients of the native API still find the code they know.
+    assert_eq!(body["code"], "NotFound");
+
+    let (status, query_error, body) = sqs(
+        "DeleteMessage",
+        json!({ "QueueUrl": queue_url, "ReceiptHandle": "not-a-handle" }),
+    )
+    .await;
+    assert_eq!(status, 400, "{body}");
+    assert_eq!(body["__type"], "com.amazonaws.sqs#ReceiptHandleIsInvalid");
+    assert_eq!(
+        query_error.as_deref(),
+        Some("ReceiptHandleIsInvalid;Sender")
+    );
+
+    let (status, query_error, body) = sqs(
+        "SendMessageBatch",
+        json!({ "QueueUrl": queue_url, "Entries": [] }),
+    )
+    .await;
+    assert_eq!(status, 400, "{body}");
+    assert_eq!(body["__type"], "com.amazonaws.sqs#EmptyBatchRequest");
+    assert_eq!(
+        query_error.as_deref(),
+        Some("AWS.SimpleQueueService.EmptyBatchRequest;Sender")
+    );
+
+    let (status, _, body) = sqs("AddPermission", json!({ "QueueUrl": queue_url })).await;
+    assert_eq!(status, 400, "{body}");
+    assert_eq!(body["__type"], "com.amazonaws.sqs#UnsupportedOperation");
+}
+
+#[actix_web::test]
+async fn test_message_size_limits() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        let request = server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")));
+        async move {
+            let mut response = request.send_json(&body).await.unwrap();
+            let body: Value = response.json().limit(1 << 20).await.unwrap();
+            (response.status().as_u16(), body)
+        }
+    };
+
+    let (status, body) = sqs(
+        "CreateQueue",
+        json!({ "QueueName": "small", "Attributes": { "MaximumMessageSize": "1024" } }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+    let small = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    let (status, body) = sqs(
+        "SendMessage",
+        json!({ "QueueUrl": small, "MessageBody": "x".repeat(1025) }),
+    )
+    .await;
+    assert_eq!(status, 400, "{body}");
+    assert_eq!(body["__type"], "com.amazonaws.sqs#InvalidParameterValue");
+
+    // Within a batch, only the entries that are too long fail.
+    let (status, body) = sqs(
+        "SendMessageBatch",
+        json!({
+            "QueueUrl": small,
+            "Entries": [
+                { "Id": "fits", "MessageBody": "x".repeat(1024) },
+                { "Id": "long", "MessageBody": "x".repeat(1025) },
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["Successful"][0]["Id"], "fits");
+    assert_eq!(body["Failed"][0]["Id"], "long");
+    assert_eq!(body["Failed"][0]["Code"], "InvalidParameterValue");
+
+    let (status, body) = sqs("CreateQueue", json!({ "QueueName": "large" })).await;
+    assert_eq!(status, 200, "{body}");
+    let large = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    // Each message is within the queue's limit, but not the two together.
+    let (status, body) = sqs(
+        "SendMessageBatch",
+        json!({
+            "QueueUrl": large,
+            "Entries": [
+                { "Id": "a", "MessageBody": "x".repeat(140 * 1024) },
+                { "Id": "b", "MessageBody": "x".repeat(140 * 1024) },
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, 400, "{body}");
+    assert_eq!(body["__type"], "com.amazonaws.sqs#BatchRequestTooLong");
+}
@@ -0,0 +1,31 @@
+use nervemq::testing::TestServer;
+use serde_json::Value;
+
+#[actix_web::test]
+async fn test_status_is_public() {
+    let server = TestServer::builder().start().await.unwrap();
+
+    let mut response = server.http().get("/status").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers().get("cache-control").unwrap(),
+        "public, max-age=10"
+    );
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["state"], "operational", "{body}");
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["database"], true);
+    assert!(body.get("namespaces").is_none(), "{body}");
+
+    let mut response = server
+        .http()
+        .get("/status")
+        .insert_header(("Accept", "text/html,application/xhtml+xml,*/*;q=0.8"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body = response.body().await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("NerveMQ is operational"), "{body}");
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use nervemq::testing::{TestServer, NAMESPACE};
+
+#[actix_web::test]
+async fn test_stream_messages() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let response = server
+        .http()
+        .post(format!("/queue/{NAMESPACE}/events"))
+        .insert_header(("Authorization", token.clone()))
+        .send_json(&serde_json::json!({ "attributes": {}, "tags": {} }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let send = |body: &'static str| {
+        server
+            .http()
+            .post(format!("/ingest/{NAMESPACE}/events"))
+            .insert_header(("Authorization", token.clone()))
+            .content_type("application/x-ndjson")
+            .send_body(format!("{{\"body\": \"{body}\"}}\n"))
+    };
+    let stream = |mode: &str| {
+        server
+            .http()
+            .get(format!("/queue/{NAMESPACE}/events/stream?mode={mode}"))
+            .insert_header(("Authorization", token.clone()))
+            .timeout(Duration::from_secs(60))
+            .send()
+    };
+
+    let mut response = stream("tail").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+    assert!(send("hello").await.unwrap().status().is_success());
+
+    let chunk = response.next().await.unwrap().unwrap();
+    let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(chunk.contains("event: message\n"), "{chunk}");
+    assert!(chunk.contains("\"body\":\"hello\""), "{chunk}");
+    drop(response);
+
+    // Tailing leaves the message in the queue.
+    let mut response = server
+        .http()
+        .get(format!("/queue/{NAMESPACE}/events/messages"))
+        .insert_header(("Authorization", token.clone()))
+        .send()
+        .await
+        .unwrap();
+    let messages: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(messages[0]["tries"], 0);
+
+    // Receiving hides the message, like ReceiveMessage.
+    let mut response = stream("receive").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let chunk = response.next().await.unwrap().unwrap();
+    let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(chunk.contains("event: message\n"), "{chunk}");
+    assert!(chunk.contains("\"Body\":\"hello\""), "{chunk}");
+    assert!(chunk.contains("\"ReceiptHandle\""), "{chunk}");
+    drop(response);
+
+    let response = server
+        .http()
+        .get(format!("/queue/{NAMESPACE}/events/stream?mode=peek"))
+        .insert_header(("Authorization", token.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+
+use nervemq::{
+    telemetry::{TelemetryEvent, TelemetrySink},
+    testing::{TestServer, NAMESPACE},
+};
+use serde_json::{json, Value};
+
+#[derive(Default)]
+struct Recorder(Mutex<Vec<TelemetryEvent>>);
+
+impl TelemetrySink for Recorder {
+    fn record(&self, event: &TelemetryEvent) {
+        self.0.lock().unwrap().push(event.clone());
+    }
+}
+
+#[actix_web::test]
+async fn test_telemetry_events() {
+    let recorder = Arc::new(Recorder::default());
+    let server = TestServer::builder()
+        .telemetry(recorder.clone())
+        .start()
+        .await
+        .unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str, body: Value| {
+        let request = server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")));
+        async move {
+            let mut response = request.send_json(&body).await.unwrap();
+            let status = response.status().as_u16();
+            let body: Value = response.json().await.unwrap();
+            (status, body)
+        }
+    };
+
+    let (status, body) = sqs("CreateQueue", json!({ "QueueName": "orders" })).await;
+    assert_eq!(status, 200, "{body}");
+    let queue_url = body["QueueUrl"].as_str().unwrap().to_owned();
+
+    let (status, body) = sqs(
+        "SendMessage",
+        json!({ "QueueUrl": queue_url, "MessageBody": "hello" }),
+    )
+    .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (status, body) = sqs(
+        "SendMessage",
+        json!({ "QueueUrl": queue_url.replace("orders", "missing"), "MessageBody": "hello" }),
+    )
+    .await;
+    assert_eq!(status, 404, "{body}");
+
+    let events = recorder.0.lock().unwrap().clone();
+
+    let sent = events
+        .iter()
+        .filter(|event| matches!(event, TelemetryEvent::MessageSent { .. }))
+        .count();
+    assert_eq!(sent, 1, "{events:?}");
+
+    let handled = events
+        .iter()
+        .filter_map(|event| match event {
+            TelemetryEvent::RequestHandled { route, status, .. } => Some((route.as_str(), *status)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        handled,
+        [("/sqs", 200), ("/sqs", 200), ("/sqs", 404)],
+        "{events:?}"
+    );
+
+    let errors = events
+        .iter()
+        .filter_map(|event| match event {
+            TelemetryEvent::ErrorOccurred { code, status, .. } => Some((*code, *status)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(errors, [("NotFound", 404)], "{events:?}");
+}
@@ -0,0 +1,177 @@
+use nervemq::{
+    testing::{Role, TestServer, TestUser, NAMESPACE},
+    Components,
+};
+
+#[actix_web::test]
+async fn test_seeded_tokens_authenticate() {
+    let server = TestServer::builder()
+        .namespaces(vec![NAMESPACE.to_owned(), "other".to_owned()])
+        .users(vec![TestUser::builder()
+            .email("app@example.com")
+            .role(Role::User)
+            .namespaces(vec![NAMESPACE.to_owned()])
+            .build()])
+        .start()
+        .await
+        .unwrap();
+
+    assert_eq!(server.tokens().len(), 3);
+    assert!(server.admin_token("other").is_some());
+    assert!(server.token("app@example.com", "other").is_none());
+
+    let token = server.token("app@example.com", NAMESPACE).unwrap();
+
+    let response = server
+        .http()
+        .post(format!("/queue/{NAMESPACE}/orders"))
+        .insert_header(("Authorization", token.authorization()))
+        .send_json(&serde_json::json!({ "attributes": {}, "tags": {} }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let mut response = server
+        .http()
+        .get(format!("/queue/{NAMESPACE}"))
+        .insert_header(("Authorization", token.authorization()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["queues"][0]["name"], "orders");
+
+    let response = server.http().get("/queue").send().await.unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[actix_web::test]
+async fn test_admin_login() {
+    let server = TestServer::builder().start().await.unwrap();
+    let (email, password) = server.admin();
+
+    let response = server
+        .http()
+        .post("/auth/login")
+        .send_json(&serde_json::json!({ "email": email, "password": password }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert!(response.headers().contains_key("set-cookie"));
+}
+
+#[actix_web::test]
+async fn test_request_ids() {
+    let server = TestServer::builder().start().await.unwrap();
+
+    // Errors from the authentication middleware get an ID too.
+    let mut response = server.http().get("/queue").send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    let id = response.headers().get("x-request-id").unwrap().clone();
+    assert!(uuid::Uuid::parse_str(id.to_str().unwrap()).is_ok());
+
+    // Error bodies carry the ID as well.
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "Unauthorized");
+    assert_eq!(body["request_id"], id.to_str().unwrap());
+
+    let response = server
+        .http()
+        .get("/queue")
+        .insert_header(("X-Request-Id", "support-ticket-42"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get("x-request-id").unwrap(),
+        "support-ticket-42"
+    );
+
+    let token = server.admin_token(NAMESPACE).unwrap();
+    let response = server
+        .http()
+        .post("/sqs")
+        .insert_header(("Authorization", token.authorization()))
+        .insert_header(("X-Amz-Target", "AmazonSQS.ListQueues"))
+        .insert_header(("X-Request-Id", "sqs-1"))
+        .send_json(&serde_json::json!({}))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(response.headers().get("x-amzn-requestid").unwrap(), "sqs-1");
+}
+
+#[actix_web::test]
+async fn test_components() {
+    let data_plane = TestServer::builder()
+        .components(
+            Components::builder()
+                .management(false)
+                .dashboard_auth(false)
+                .metrics(false)
+                .build(),
+        )
+        .start()
+        .await
+        .unwrap();
+    let token = data_plane.admin_token(NAMESPACE).unwrap().authorization();
+
+    let response = data_plane
+        .http()
+        .post("/sqs")
+        .insert_header(("Authorization", token.clone()))
+        .insert_header(("X-Amz-Target", "AmazonSQS.CreateQueue"))
+        .send_json(&serde_json::json!({ "QueueName": "orders" }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    for path in ["/queue", "/stats", "/api/overview", "/admin/users"] {
+        let response = data_plane
+            .http()
+            .get(path)
+            .insert_header(("Authorization", token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404, "{path}");
+    }
+
+    let (email, password) = data_plane.admin();
+    let response = data_plane
+        .http()
+        .post("/auth/login")
+        .send_json(&serde_json::json!({ "email": email, "password": password }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let admin_plane = TestServer::builder()
+        .components(Components::builder().sqs(false).build())
+        .start()
+        .await
+        .unwrap();
+    let token = admin_plane.admin_token(NAMESPACE).unwrap().authorization();
+
+    let response = admin_plane
+        .http()
+        .post("/sqs")
+        .insert_header(("Authorization", token.clone()))
+        .insert_header(("X-Amz-Target", "AmazonSQS.ListQueues"))
+        .send_json(&serde_json::json!({}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    for path in ["/queue", "/api/overview"] {
+        let response = admin_plane
+            .http()
+            .get(path)
+            .insert_header(("Authorization", token.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{path}");
+    }
+}
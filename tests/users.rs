@@ -0,0 +1,79 @@
+use nervemq::testing::{Role, TestServer, TestUser, NAMESPACE};
+
+#[actix_web::test]
+async fn test_users_read_own_details() {
+    let server = TestServer::builder()
+        .users(vec![TestUser::builder()
+            .email("app@example.com")
+            .role(Role::User)
+            .namespaces(vec![NAMESPACE.to_owned()])
+            .build()])
+        .start()
+        .await
+        .unwrap();
+    let user = server
+        .token("app@example.com", NAMESPACE)
+        .unwrap()
+        .authorization();
+    let admin = server.admin_token(NAMESPACE).unwrap().authorization();
+    let (admin_email, _) = server.admin();
+
+    let mut response = server
+        .http()
+        .get("/admin/users/app@example.com/permissions")
+        .insert_header(("Authorization", user.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, serde_json::json!([NAMESPACE]));
+
+    for path in ["role", "namespace-quota"] {
+        let response = server
+            .http()
+            .get(format!("/admin/users/app@example.com/{path}"))
+            .insert_header(("Authorization", user.clone()))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{path}");
+    }
+
+    // Other users' details and everything else stay admin-only.
+    let response = server
+        .http()
+        .get(format!("/admin/users/{admin_email}/role"))
+        .insert_header(("Authorization", user.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = server
+        .http()
+        .get("/admin/users")
+        .insert_header(("Authorization", user.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = server
+        .http()
+        .put("/admin/users/app@example.com/permissions")
+        .insert_header(("Authorization", user))
+        .send_json(&serde_json::json!(["other"]))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = server
+        .http()
+        .get("/admin/users/app@example.com/role")
+        .insert_header(("Authorization", admin))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+}
@@ -0,0 +1,30 @@
+use nervemq::testing::{TestServer, NAMESPACE};
+use serde_json::Value;
+
+#[actix_web::test]
+async fn test_version() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let response = server.http().get("/api/version").send().await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let mut response = server
+        .http()
+        .get("/api/version")
+        .insert_header(("Authorization", token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["features"]
+        .as_array()
+        .unwrap()
+        .contains(&Value::from("testing")));
+    let operations = body["sqsOperations"].as_array().unwrap();
+    assert!(operations.contains(&Value::from("ReceiveMessage")));
+    assert!(!operations.contains(&Value::from("AddPermission")));
+}
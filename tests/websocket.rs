@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use actix_http::ws;
+use futures_util::{SinkExt, Stream, StreamExt};
+use nervemq::testing::{TestServer, NAMESPACE};
+
+/// Reads the next JSON frame, skipping pings.
+async fn next_json(
+    socket: &mut (impl Stream<Item = Result<ws::Frame, ws::ProtocolError>> + Unpin),
+) -> serde_json::Value {
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(10), socket.next())
+            .await
+            .expect("frame")
+            .unwrap()
+            .unwrap();
+        match frame {
+            ws::Frame::Text(text) => return serde_json::from_slice(&text).unwrap(),
+            ws::Frame::Ping(_) => continue,
+            frame => panic!("unexpected frame: {frame:?}"),
+        }
+    }
+}
+
+fn text(value: serde_json::Value) -> ws::Message {
+    ws::Message::Text(value.to_string().into())
+}
+
+#[actix_web::test]
+async fn test_websocket_consumer() {
+    let server = TestServer::builder().start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let response = server
+        .http()
+        .post(format!("/queue/{NAMESPACE}/jobs"))
+        .insert_header(("Authorization", token.clone()))
+        .send_json(&serde_json::json!({ "attributes": {}, "tags": {} }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let response = server
+        .http()
+        .get(format!("/queue/{NAMESPACE}/jobs/ws?prefetch=1000"))
+        .insert_header(("Authorization", token.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let (response, mut socket) = actix_test::Client::new()
+        .ws(server.url(&format!("/queue/{NAMESPACE}/jobs/ws?prefetch=1")))
+        .set_header("Authorization", token.clone())
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 101);
+
+    let response = server
+        .http()
+        .post(format!("/ingest/{NAMESPACE}/jobs"))
+        .insert_header(("Authorization", token.clone()))
+        .content_type("application/x-ndjson")
+        .send_body("{\"body\": \"one\"}\n{\"body\": \"two\"}\n")
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Only one message is sent at a time, and a nacked one is received again right away.
+    let frame = next_json(&mut socket).await;
+    assert_eq!(frame["type"], "message", "{frame}");
+    assert_eq!(frame["message"]["Body"], "one", "{frame}");
+    let handle = frame["message"]["ReceiptHandle"].clone();
+    socket
+        .send(text(serde_json::json!({
+            "type": "nack",
+            "receiptHandle": handle,
+            "reason": "busy",
+        })))
+        .await
+        .unwrap();
+
+    let frame = next_json(&mut socket).await;
+    assert_eq!(frame["message"]["Body"], "one", "{frame}");
+    assert_eq!(
+        frame["message"]["Attributes"]["ApproximateReceiveCount"], "2",
+        "{frame}"
+    );
+    let handle = frame["message"]["ReceiptHandle"].clone();
+    socket
+        .send(text(
+            serde_json::json!({ "type": "ack", "receiptHandle": handle }),
+        ))
+        .await
+        .unwrap();
+
+    let frame = next_json(&mut socket).await;
+    assert_eq!(frame["message"]["Body"], "two", "{frame}");
+    let handle = frame["message"]["ReceiptHandle"].clone();
+    socket
+        .send(text(
+            serde_json::json!({ "type": "ack", "receiptHandle": handle }),
+        ))
+        .await
+        .unwrap();
+
+    socket
+        .send(text(
+            serde_json::json!({ "type": "ack", "receiptHandle": "nope" }),
+        ))
+        .await
+        .unwrap();
+    let frame = next_json(&mut socket).await;
+    assert_eq!(frame["type"], "error", "{frame}");
+    assert_eq!(frame["receiptHandle"], "nope", "{frame}");
+
+    // Frames are handled in order, so both acks were applied by now.
+    let mut response = server
+        .http()
+        .get(format!("/queue/{NAMESPACE}/jobs/messages"))
+        .insert_header(("Authorization", token.clone()))
+        .send()
+        .await
+        .unwrap();
+    let messages: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(messages, serde_json::json!([]));
+
+    socket.send(ws::Message::Close(None)).await.unwrap();
+    let frame = tokio::time::timeout(Duration::from_secs(10), socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(matches!(frame, ws::Frame::Close(_)), "{frame:?}");
+}
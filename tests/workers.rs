@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use nervemq::{
+    config::{Config, MEMORY_DB_PATH},
+    testing::{TestServer, NAMESPACE},
+};
+use serde_json::{json, Value};
+
+const WORKERS: usize = 4;
+
+#[actix_web::test]
+async fn test_state_is_shared_by_workers() {
+    let config: Config = serde_json::from_value(json!({
+        "db_path": MEMORY_DB_PATH,
+        "integrity_check": "off",
+        "cookie_secure": false,
+        "workers": WORKERS,
+    }))
+    .unwrap();
+    let server = TestServer::builder().config(config).start().await.unwrap();
+    let token = server.admin_token(NAMESPACE).unwrap().authorization();
+
+    let sqs = |target: &str| {
+        server
+            .http()
+            .post("/sqs")
+            .insert_header(("Authorization", token.clone()))
+            .insert_header(("X-Amz-Target", format!("AmazonSQS.{target}")))
+            // Every request hashes its API key secret, which takes a while on small machines.
+            .timeout(Duration::from_secs(60))
+    };
+
+    let mut response = sqs("CreateQueue")
+        .send_json(&json!({ "QueueName": "orders" }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let queue_url: Value = response.json().await.unwrap();
+    let queue_url = queue_url["QueueUrl"].as_str().unwrap().to_owned();
+
+    // Concurrent requests open a connection each, and connections are spread over the workers.
+    let send_all = || {
+        futures_util::future::join_all((0..2 * WORKERS).map(|i| {
+            sqs("SendMessage").send_json(&json!({
+                "QueueUrl": queue_url,
+                "MessageBody": format!("message {i}"),
+                "MessageAttributes": {},
+            }))
+        }))
+    };
+
+    for response in send_all().await {
+        assert!(response.unwrap().status().is_success());
+    }
+
+    let mut response = server
+        .http()
+        .get("/stats/queue")
+        .insert_header(("Authorization", token.clone()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let stats: Value = response.json().await.unwrap();
+    assert_eq!(stats["orders"]["message_count"], 2 * WORKERS);
+
+    // Maintenance mode, switched on through one worker, applies to all of them.
+    let response = server
+        .http()
+        .put("/admin/maintenance")
+        .insert_header(("Authorization", token.clone()))
+        .send_json(&json!({ "enabled": true }))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    for response in send_all().await {
+        assert_eq!(response.unwrap().status(), 503);
+    }
+}